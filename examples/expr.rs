@@ -0,0 +1,151 @@
+use virmin::asm::Assembler;
+use virmin::isa::stack;
+use virmin::machine::State;
+use virmin::program::DecodedProgram;
+
+// =====================================================
+// Compiler
+// =====================================================
+
+/// A tiny compiler of arithmetic expressions (over integers, `+`,
+/// `-`, `*`, unary `-`, brackets and the built-in function `sq(e)`)
+/// into stack bytecode, using recursive descent.  Calls to `sq()` are
+/// compiled into calls to a subroutine appended to the program.
+struct Compiler<'a> {
+    tokens: Vec<&'a str>,
+    index: usize,
+    code: Vec<String>
+}
+
+impl<'a> Compiler<'a> {
+    fn new(text: &'a str) -> Self {
+	let mut tokens = Vec::new();
+	let mut start = None;
+	for (i,c) in text.char_indices() {
+	    if c.is_ascii_alphanumeric() {
+		start = start.or(Some(i));
+		continue;
+	    }
+	    if let Some(s) = start.take() {
+		tokens.push(&text[s..i]);
+	    }
+	    if !c.is_whitespace() {
+		tokens.push(&text[i..i+c.len_utf8()]);
+	    }
+	}
+	if let Some(s) = start {
+	    tokens.push(&text[s..]);
+	}
+	Compiler{tokens,index:0,code:Vec::new()}
+    }
+
+    fn compile(mut self) -> Result<String,String> {
+	self.expr()?;
+	if let Some(t) = self.peek() {
+	    return Err(format!("unexpected \"{}\"",t));
+	}
+	self.emit("halt");
+	// Subroutine moves return pc out of the way
+	self.code.push("square: swap".to_string());
+	self.emit("dup");
+	self.emit("mul");
+	self.emit("swap");
+	self.emit("ret");
+	Ok(self.code.join("\n"))
+    }
+
+    fn expr(&mut self) -> Result<(),String> {
+	self.term()?;
+	while let Some(op) = self.peek().filter(|t| *t == "+" || *t == "-") {
+	    self.index += 1;
+	    self.term()?;
+	    self.emit(if op == "+" { "add" } else { "sub" });
+	}
+	Ok(())
+    }
+
+    fn term(&mut self) -> Result<(),String> {
+	self.factor()?;
+	while self.peek() == Some("*") {
+	    self.index += 1;
+	    self.factor()?;
+	    self.emit("mul");
+	}
+	Ok(())
+    }
+
+    fn factor(&mut self) -> Result<(),String> {
+	let token = self.next()?;
+	match token {
+	    "-" => {
+		self.factor()?;
+		self.emit("neg");
+	    }
+	    "(" => {
+		self.expr()?;
+		self.expect(")")?;
+	    }
+	    "sq" => {
+		self.expect("(")?;
+		self.expr()?;
+		self.expect(")")?;
+		self.emit("call square");
+	    }
+	    _ => {
+		let value : i32 = token.parse().map_err(|_| format!("unexpected \"{}\"",token))?;
+		self.emit(&format!("push {}",value));
+	    }
+	}
+	Ok(())
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+	self.tokens.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str,String> {
+	let token = self.peek().ok_or("unexpected end of expression")?;
+	self.index += 1;
+	Ok(token)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(),String> {
+	match self.next()? {
+	    t if t == token => Ok(()),
+	    t => Err(format!("expected \"{}\", found \"{}\"",token,t))
+	}
+    }
+
+    fn emit(&mut self, line: &str) {
+	self.code.push(format!("        {}",line));
+    }
+}
+
+// =====================================================
+// Main
+// =====================================================
+
+/// Compile an arithmetic expression (given on the command line) into
+/// stack bytecode, then assemble and run it.  For example:
+///
+/// ```text
+/// cargo run --example expr --features stack -- "(1 + 2) * 3 - sq(4)"
+/// ```
+fn main() {
+    let text = std::env::args().nth(1).unwrap_or("(1 + 2) * 3 - sq(4)".to_string());
+    let source = match Compiler::new(&text).compile() {
+	Ok(source) => source,
+	Err(e) => { eprintln!("error: {}",e); std::process::exit(1); }
+    };
+    println!("{}\n",source);
+    let isa = stack::isa();
+    let program = Assembler::new(&isa).assemble(&source).expect("invalid bytecode");
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [0u8;1024];
+    let mut state = State::new(0,&mut bytes);
+    stack::reset(&mut state);
+    match stack::run(&mut state,&decoded,10000) {
+	Ok(steps) => println!("{} = {:?} ({} steps)",text,stack::stack(&state),steps),
+	Err(e) => { eprintln!("error: {}",e); std::process::exit(1); }
+    }
+}
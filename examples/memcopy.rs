@@ -0,0 +1,51 @@
+use std::time::Instant;
+use virmin::machine::{Memory,MicroCode,State,Width::QuadWord};
+
+/// Width (in bytes) of each line of the framebuffer.
+const WIDTH : usize = 320;
+
+/// Number of lines in the framebuffer.
+const HEIGHT : usize = 200;
+
+/// Number of times the framebuffer is scrolled in each run.
+const SCROLLS : usize = 2_000;
+
+/// Measure the throughput of bulk memory operations, by repeatedly
+/// scrolling a (byte per pixel) framebuffer up by one line and
+/// clearing the last line.  This compares `Memory::copy()` and
+/// `Memory::fill()` against executing one microcode per eight bytes
+/// (i.e. as a guest would without bulk operations).  This should be
+/// run in release mode (i.e. `cargo run --release --example memcopy`).
+fn main() {
+    let microcode = measure(|bytes| {
+	let mut state = State::new(0,bytes);
+	for i in (0..WIDTH * (HEIGHT - 1)).step_by(8) {
+	    state.execute(MicroCode::Copy(i,i + WIDTH,QuadWord));
+	}
+	for i in (WIDTH * (HEIGHT - 1)..WIDTH * HEIGHT).step_by(8) {
+	    state.execute(MicroCode::Load(i,0,QuadWord));
+	}
+    });
+    let bulk = measure(|bytes| {
+	let mut memory = Memory::new(bytes);
+	memory.copy(0,WIDTH,WIDTH * (HEIGHT - 1));
+	memory.fill(WIDTH * (HEIGHT - 1),WIDTH,0);
+    });
+    println!("{:.2}GB/s (microcode)",microcode);
+    println!("{:.2}GB/s (bulk)",bulk);
+}
+
+/// Determine the throughput (in GB/s) of a given scroll function,
+/// taking the best of several runs.
+fn measure<F:Fn(&mut [u8])>(scroll: F) -> f64 {
+    let mut best = f64::MAX;
+    for _ in 0..5 {
+	let mut bytes : Vec<u8> = (0..WIDTH * HEIGHT).map(|i| i as u8).collect();
+	let start = Instant::now();
+	for _ in 0..SCROLLS {
+	    scroll(&mut bytes);
+	}
+	best = best.min(start.elapsed().as_secs_f64());
+    }
+    (WIDTH * HEIGHT * SCROLLS) as f64 / best / 1e9
+}
@@ -0,0 +1,81 @@
+use std::time::Instant;
+use virmin::compile::CompiledSet;
+use virmin::coverage::Coverage;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::{Add,Copy,Goto,If};
+use virmin::insn::Operand::Var;
+use virmin::machine::{State,Width::Byte};
+use virmin::program::DecodedProgram;
+
+/// Number of times the loop is executed in each run.
+const LOOPS : usize = 20_000;
+
+/// Number of instructions executed by each loop (i.e. 256 iterations
+/// of four instructions).
+const LENGTH : usize = 1024;
+
+/// Measure the time taken by `State::step()`, `CompiledSet::step()`
+/// and `State::run_block()` to execute a simple loop, which counts a
+/// byte down to zero before restarting.  The loop is then measured
+/// again after quickening every instruction and, separately, after
+/// fusing its most frequent pairs of instructions.  This should be
+/// run in release mode (i.e. `cargo run --release --example
+/// throughput`).
+fn main() {
+    let fmt = Format::builder().width_bytes(2).opcode_bits(2).immediate("a",7).immediate("b",7).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut image = Vec::new();
+    for (mnemonic,operands) in [("add",[0,1]),("add",[2,3]),("mov",[4,2]),("jnz",[0,0])] {
+	image.extend(isa.encode(mnemonic,&operands).unwrap());
+    }
+    let mut program = DecodedProgram::new(&isa,&image);
+    let compiled = CompiledSet::new(&isa);
+    let threaded = measure(|state| state.step(&program).unwrap(),program.len());
+    let closures = measure(|state| compiled.step(state,&program).unwrap(),program.len());
+    println!("{:.1}ns per instruction (threaded)",threaded);
+    println!("{:.1}ns per instruction (compiled)",closures);
+    let blocks = measure(|state| { state.run_block(&program,usize::MAX).unwrap(); },program.len());
+    println!("{:.1}ns per instruction (blocks)",blocks);
+    // Quicken every instruction (as happens to hot instructions when
+    // using Tiered)
+    let mut quickened = DecodedProgram::new(&isa,&image);
+    for pc in 0..quickened.len() {
+	quickened.quicken(pc);
+    }
+    let quick = measure(|state| state.step(&quickened).unwrap(),program.len());
+    println!("{:.1}ns per instruction (quickened)",quick);
+    // Fuse the two most frequent pairs of instructions
+    let mut coverage = Coverage::new(&isa);
+    let mut bytes = [0,0xFF,0,1,0];
+    let mut state = State::new(0,&mut bytes);
+    while state.pc < program.len() {
+	coverage.step(&mut state,&program).unwrap();
+    }
+    let pairs : Vec<_> = coverage.pairs().iter().take(2).map(|(p,_)| *p).collect();
+    program.fuse(&pairs);
+    let fused = measure(|state| state.step(&program).unwrap(),program.len());
+    println!("{:.1}ns per instruction (fused)",fused);
+}
+
+/// Determine the time (in ns) taken per instruction by a given step
+/// function, taking the best of several runs.
+fn measure<F:Fn(&mut State)>(step: F, length: usize) -> f64 {
+    let mut best = f64::MAX;
+    for _ in 0..5 {
+	let mut bytes = [0,0xFF,0,1,0];
+	let mut state = State::new(0,&mut bytes);
+	let start = Instant::now();
+	for _ in 0..LOOPS {
+	    state.pc = 0;
+	    while state.pc < length {
+		step(&mut state);
+	    }
+	}
+	best = best.min(start.elapsed().as_secs_f64());
+    }
+    best * 1e9 / (LOOPS * LENGTH) as f64
+}
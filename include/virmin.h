@@ -0,0 +1,61 @@
+/*
+ * C interface to the virmin interpreter (see src/capi.rs).  Build the
+ * library with:
+ *
+ *   cargo rustc --lib --release --features capi --crate-type staticlib
+ */
+#ifndef VIRMIN_H
+#define VIRMIN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes returned by virmin_machine_run() and others. */
+#define VIRMIN_OK 0
+#define VIRMIN_HALTED 1
+#define VIRMIN_HOOK 2
+#define VIRMIN_ERROR (-1)
+
+/* Special registers (see virmin_machine_read_register()). */
+#define VIRMIN_PC SIZE_MAX
+#define VIRMIN_SP (SIZE_MAX - 1)
+#define VIRMIN_FLAGS (SIZE_MAX - 2)
+#define VIRMIN_LR (SIZE_MAX - 3)
+
+typedef struct virmin_isa virmin_isa;
+typedef struct virmin_machine virmin_machine;
+
+/* Invoked before the instruction at a hooked pc executes.  Returning
+ * non-zero stops execution with VIRMIN_HOOK. */
+typedef int (*virmin_hook)(virmin_machine *machine, size_t pc, void *user);
+
+const char *virmin_last_error(void);
+
+virmin_isa *virmin_isa_load(const char *path);
+virmin_isa *virmin_isa_from_toml(const char *text);
+virmin_isa *virmin_isa_from_json(const char *text);
+void virmin_isa_free(virmin_isa *isa);
+
+virmin_machine *virmin_machine_new(const virmin_isa *isa, size_t memory);
+void virmin_machine_free(virmin_machine *machine);
+int virmin_machine_load_image(virmin_machine *machine, const uint8_t *image, size_t length);
+int virmin_machine_run(virmin_machine *machine, size_t steps, size_t *executed);
+size_t virmin_machine_pc(const virmin_machine *machine);
+void virmin_machine_set_pc(virmin_machine *machine, size_t pc);
+int virmin_machine_read_register(virmin_machine *machine, size_t reg, uint64_t *value);
+int virmin_machine_write_register(virmin_machine *machine, size_t reg, uint64_t value);
+int virmin_machine_read(const virmin_machine *machine, size_t address, uint8_t *buffer, size_t length);
+int virmin_machine_write(virmin_machine *machine, size_t address, const uint8_t *buffer, size_t length);
+void virmin_machine_add_hook(virmin_machine *machine, size_t pc, virmin_hook hook, void *user);
+bool virmin_machine_remove_hook(virmin_machine *machine, size_t pc);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
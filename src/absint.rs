@@ -0,0 +1,647 @@
+use std::collections::{BTreeMap,BTreeSet};
+use std::fmt;
+use crate::machine::{AluOp,MicroCode,Width,LR,PC,TEMPS};
+use crate::program::DecodedProgram;
+
+/// Number of times the entry state of an instruction is joined before
+/// widening is applied, which ensures the analysis terminates.
+pub const WIDEN : usize = 3;
+
+/// Maximum number of targets followed for a branch whose target is
+/// not constant (e.g. a jump through a register).
+pub const TARGETS : u64 = 256;
+
+// =====================================================
+// Domains
+// =====================================================
+
+/// An abstract domain, where each element describes a set of
+/// (unsigned) values.  Elements are only ever constructed for values
+/// which fit within a given width, as determined by the location
+/// holding them.
+pub trait Domain : Clone + fmt::Debug + PartialEq {
+    /// Any value of a given width.
+    fn top(width: Width) -> Self;
+    /// Exactly a given value.
+    fn constant(value: u64) -> Self;
+    /// The smallest element describing every value described by this
+    /// or another.
+    fn join(&self, other: &Self) -> Self;
+    /// As for `join()`, but ensuring any ascending chain of elements
+    /// is finite.  Domains of finite height need not override this.
+    fn widen(&self, other: &Self) -> Self {
+	self.join(other)
+    }
+    /// Addition (with wrap around) at a given width.
+    fn add(&self, other: &Self, width: Width) -> Self;
+    /// Apply a given operation at a given width.  By default, the
+    /// result is only known when both are exactly one value.
+    fn apply(&self, op: AluOp, other: &Self, width: Width) -> Self {
+	match (self.bounds(),other.bounds()) {
+	    (Some((a,b)),Some((c,d))) if a == b && c == d => Self::constant(op.apply(a,c,width)),
+	    _ => Self::top(width)
+	}
+    }
+    /// Retain only the lowest bits of each value, as happens when
+    /// writing a narrower location.
+    fn truncate(&self, width: Width) -> Self;
+    /// Get the smallest and largest values described (if known).
+    fn bounds(&self) -> Option<(u64,u64)>;
+    /// Restrict to either the value zero or to nonzero values,
+    /// producing `None` if no values remain.
+    fn assume(&self, zero: bool) -> Option<Self>;
+}
+
+/// The interval domain, describing every value between two bounds
+/// (inclusive).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Interval {
+    pub lo: u64,
+    pub hi: u64
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	if self.lo == self.hi {
+	    write!(f,"{}",self.lo)
+	} else {
+	    write!(f,"[{}..{}]",self.lo,self.hi)
+	}
+    }
+}
+
+impl Domain for Interval {
+    fn top(width: Width) -> Self {
+	Interval{lo:0,hi:width.mask()}
+    }
+    fn constant(value: u64) -> Self {
+	Interval{lo:value,hi:value}
+    }
+    fn join(&self, other: &Self) -> Self {
+	Interval{lo:self.lo.min(other.lo),hi:self.hi.max(other.hi)}
+    }
+    fn widen(&self, other: &Self) -> Self {
+	let lo = if other.lo < self.lo { 0 } else { self.lo };
+	let hi = if other.hi > self.hi { u64::MAX } else { self.hi };
+	Interval{lo,hi}
+    }
+    fn add(&self, other: &Self, width: Width) -> Self {
+	let (a,b) = (self.truncate(width),other.truncate(width));
+	match a.hi.checked_add(b.hi) {
+	    Some(hi) if hi <= width.mask() => Interval{lo:a.lo+b.lo,hi},
+	    _ => Interval::top(width)
+	}
+    }
+    fn truncate(&self, width: Width) -> Self {
+	if self.hi <= width.mask() { *self } else { Interval::top(width) }
+    }
+    fn bounds(&self) -> Option<(u64,u64)> {
+	Some((self.lo,self.hi))
+    }
+    fn assume(&self, zero: bool) -> Option<Self> {
+	match (zero,self.lo,self.hi) {
+	    (true,0,_) => Some(Interval::constant(0)),
+	    (true,..) => None,
+	    (false,0,0) => None,
+	    (false,0,hi) => Some(Interval{lo:1,hi}),
+	    (false,..) => Some(*self)
+	}
+    }
+}
+
+/// The constant propagation domain, describing either exactly one
+/// value or any value.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Constant {
+    Value(u64),
+    Any
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Constant::Value(v) => write!(f,"{}",v),
+	    Constant::Any => write!(f,"?")
+	}
+    }
+}
+
+impl Domain for Constant {
+    fn top(_: Width) -> Self {
+	Constant::Any
+    }
+    fn constant(value: u64) -> Self {
+	Constant::Value(value)
+    }
+    fn join(&self, other: &Self) -> Self {
+	if self == other { *self } else { Constant::Any }
+    }
+    fn add(&self, other: &Self, width: Width) -> Self {
+	match (self,other) {
+	    (Constant::Value(a),Constant::Value(b)) => Constant::Value(a.wrapping_add(*b) & width.mask()),
+	    _ => Constant::Any
+	}
+    }
+    fn truncate(&self, width: Width) -> Self {
+	match self {
+	    Constant::Value(v) => Constant::Value(v & width.mask()),
+	    Constant::Any => Constant::Any
+	}
+    }
+    fn bounds(&self) -> Option<(u64,u64)> {
+	match self {
+	    Constant::Value(v) => Some((*v,*v)),
+	    Constant::Any => None
+	}
+    }
+    fn assume(&self, zero: bool) -> Option<Self> {
+	match self {
+	    Constant::Value(v) if (*v == 0) == zero => Some(*self),
+	    Constant::Value(_) => None,
+	    Constant::Any if zero => Some(Constant::Value(0)),
+	    Constant::Any => Some(Constant::Any)
+	}
+    }
+}
+
+// =====================================================
+// Abstract State
+// =====================================================
+
+/// Describes the possible states of a machine on reaching a given
+/// instruction.  Memory is held as cells (i.e. an address and width)
+/// which have been written, whilst bytes not covered by any cell hold
+/// their initial value.  Scratch registers are always zero between
+/// instructions, hence need not be held.
+#[derive(Clone,Debug,PartialEq)]
+pub struct AbstractState<D:Domain> {
+    memory: BTreeMap<usize,(Width,D)>,
+    registers: BTreeMap<usize,D>,
+    /// Indicates whether memory and registers not yet written are
+    /// initially zero (rather than unknown).
+    zeroed: bool
+}
+
+impl<D:Domain> AbstractState<D> {
+    fn new(zeroed: bool) -> Self {
+	AbstractState{memory:BTreeMap::new(),registers:BTreeMap::new(),zeroed}
+    }
+    /// Get the values which a given memory location (may) hold.
+    pub fn read(&self, address: usize, width: Width) -> D {
+	match self.memory.get(&address) {
+	    Some((w,v)) if *w == width => v.clone(),
+	    _ if self.zeroed && self.overlapping(address,width).is_empty() => D::constant(0),
+	    _ => D::top(width)
+	}
+    }
+    /// Get the values which a given register (of a given width) may
+    /// hold.
+    pub fn register(&self, register: usize, width: Width) -> D {
+	match self.registers.get(&register) {
+	    Some(v) => v.clone(),
+	    None if self.zeroed || (LR - TEMPS..LR).contains(&register) => D::constant(0),
+	    None => D::top(width)
+	}
+    }
+    fn write(&mut self, address: usize, width: Width, value: D) {
+	// Bytes of overlapping cells not overwritten become unknown
+	for x in self.overlapping(address,width) {
+	    let (w,_) = self.memory.remove(&x).unwrap();
+	    for b in (x..x+w.bytes()).filter(|b| !(address..address+width.bytes()).contains(b)) {
+		self.memory.insert(b,(Width::Byte,D::top(Width::Byte)));
+	    }
+	}
+	self.memory.insert(address,(width,value.truncate(width)));
+    }
+    fn set_register(&mut self, register: usize, value: D) {
+	self.registers.insert(register,value);
+    }
+    /// Forget everything known about memory, as happens when writing
+    /// an unknown location.  Registers not yet written also become
+    /// unknown (rather than zero), since they are not distinguished.
+    fn forget(&mut self) {
+	self.zeroed = false;
+	self.memory.clear();
+    }
+    /// Determine the cells overlapping a given location.
+    fn overlapping(&self, address: usize, width: Width) -> Vec<usize> {
+	let end = address + width.bytes();
+	self.memory.range(address.saturating_sub(7)..end)
+	    .filter(|(x,(w,_))| *x + w.bytes() > address)
+	    .map(|(x,_)| *x).collect()
+    }
+    fn merge(&self, other: &Self, widen: bool, width: Width) -> Self {
+	let op = |a: &D, b: &D| if widen { a.widen(b) } else { a.join(b) };
+	let mut r = AbstractState::new(self.zeroed && other.zeroed);
+	for (x,(w,v)) in &self.memory {
+	    r.memory.insert(*x,(*w,op(v,&other.read(*x,*w)).truncate(*w)));
+	}
+	for (x,(w,v)) in &other.memory {
+	    if r.memory.get(x).is_none_or(|(rw,_)| rw != w) {
+		r.write(*x,*w,op(&self.read(*x,*w),v));
+	    }
+	}
+	let registers : BTreeSet<usize> = self.registers.keys().chain(other.registers.keys()).copied().collect();
+	for reg in registers {
+	    let w = if reg >= LR - TEMPS { Width::QuadWord } else { width };
+	    r.registers.insert(reg,op(&self.register(reg,w),&other.register(reg,w)).truncate(w));
+	}
+	r
+    }
+}
+
+// =====================================================
+// Findings
+// =====================================================
+
+/// Identifies a (possible) problem found by the analysis.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Finding {
+    /// A reachable instruction accesses memory out of bounds, hence
+    /// always faults.
+    OutOfBounds{pc: usize, address: usize, width: Width},
+    /// A reachable instruction accesses a register which does not
+    /// exist, hence always faults.
+    InvalidRegister{pc: usize, register: usize},
+    /// A reachable instruction could not be decoded.
+    Decode{pc: usize},
+    /// A conditional skip within a reachable instruction is either
+    /// always taken (`zero`) or never taken.
+    Constant{pc: usize, index: usize, zero: bool},
+    /// A reachable instruction branches to a target which could not be
+    /// determined, so its successors are not known.
+    UnknownTarget{pc: usize},
+    /// An instruction which is never reached.
+    Unreachable{pc: usize}
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Finding::OutOfBounds{pc,address,width} => {
+		write!(f,"pc {:#x}: mem{}[{}] is out of bounds",pc,8*width.bytes(),address)
+	    }
+	    Finding::InvalidRegister{pc,register} => write!(f,"pc {:#x}: register {} does not exist",pc,register),
+	    Finding::Decode{pc} => write!(f,"pc {:#x}: invalid instruction",pc),
+	    Finding::Constant{pc,index,zero:true} => write!(f,"pc {:#x}: skip #{} is always taken",pc,index),
+	    Finding::Constant{pc,index,zero:false} => write!(f,"pc {:#x}: skip #{} is never taken",pc,index),
+	    Finding::UnknownTarget{pc} => write!(f,"pc {:#x}: branch target is unknown",pc),
+	    Finding::Unreachable{pc} => write!(f,"pc {:#x}: unreachable",pc)
+	}
+    }
+}
+
+// =====================================================
+// Analysis
+// =====================================================
+
+/// Where control goes after an instruction.
+#[derive(Clone,Debug,PartialEq)]
+enum Next {
+    Fallthrough,
+    Targets(Vec<usize>),
+    Unknown
+}
+
+/// Statically analyses a decoded program by abstractly interpreting
+/// its microcode over a given domain (e.g. `Interval` or `Constant`),
+/// starting from pc zero.  This determines the values each location
+/// may hold on reaching each instruction, which can prove properties
+/// (e.g. that no write exceeds a given address) or warn of problems
+/// (e.g. accesses out of bounds) before the program is executed.
+/// For example:
+///
+/// ```text
+/// let results = Analysis::<Interval>::new(&program,4096).registers(8,Width::Word).zeroed().run();
+/// for finding in &results.findings {
+///     println!("{}",finding);
+/// }
+/// assert!(results.max_write().is_none_or(|x| x < 0x100));
+/// ```
+///
+/// Instructions are assumed to have any feature they require
+/// enabled.
+pub struct Analysis<'a,D:Domain> {
+    program: &'a DecodedProgram<'a>,
+    /// Size (in bytes) of memory.
+    memory: usize,
+    /// Number and width of general purpose registers.
+    registers: (usize,Width),
+    zeroed: bool,
+    domain: std::marker::PhantomData<D>
+}
+
+impl<'a,D:Domain> Analysis<'a,D> {
+    /// Construct an analysis for a given program, executing on a
+    /// machine with a given amount of memory (in bytes).
+    pub fn new(program: &'a DecodedProgram<'a>, memory: usize) -> Self {
+	Analysis{program,memory,registers:(0,Width::QuadWord),zeroed:false,domain:std::marker::PhantomData}
+    }
+    /// Set the number and width of general purpose registers (default
+    /// is none).
+    pub fn registers(mut self, count: usize, width: Width) -> Self {
+	self.registers = (count,width);
+	self
+    }
+    /// Assume memory and registers are initially zero (as for a newly
+    /// constructed machine), rather than unknown.
+    pub fn zeroed(mut self) -> Self {
+	self.zeroed = true;
+	self
+    }
+
+    /// Run the analysis to a fixed point.
+    pub fn run(&self) -> Results<D> {
+	let n = self.program.len();
+	let mut states : Vec<Option<AbstractState<D>>> = vec![None;n];
+	let mut visits = vec![0;n];
+	let mut worklist = BTreeSet::new();
+	if n > 0 {
+	    states[0] = Some(AbstractState::new(self.zeroed));
+	    worklist.insert(0);
+	}
+	while let Some(pc) = worklist.pop_first() {
+	    let state = states[pc].clone().unwrap();
+	    for (next,s) in self.transfer(pc,&state,&mut Vec::new()) {
+		let targets = match next {
+		    Next::Fallthrough => vec![pc + 1],
+		    Next::Targets(ts) => ts,
+		    Next::Unknown => vec![]
+		};
+		for t in targets.into_iter().filter(|t| *t < n) {
+		    let merged = match &states[t] {
+			None => s.clone(),
+			Some(old) => {
+			    visits[t] += 1;
+			    old.merge(&s,visits[t] > WIDEN,self.registers.1)
+			}
+		    };
+		    if states[t].as_ref() != Some(&merged) {
+			states[t] = Some(merged);
+			worklist.insert(t);
+		    }
+		}
+	    }
+	}
+	// Report findings against the final states
+	let mut findings = Vec::new();
+	let mut writes = None;
+	for (pc,state) in states.iter().enumerate() {
+	    match state {
+		Some(s) => {
+		    self.transfer(pc,s,&mut findings);
+		    if let Ok(e) = self.program.get(pc) {
+			for code in e.microcode.iter().map(|t| t.code()) {
+			    if let MicroCode::Add(x,_,w)|MicroCode::Alu(_,x,_,w)|MicroCode::Copy(x,_,w)|MicroCode::Load(x,_,w)|MicroCode::RegStore(x,_,w) = code {
+				writes = writes.max(Some(x + w.bytes() - 1));
+			    }
+			}
+		    }
+		}
+		None => findings.push(Finding::Unreachable{pc})
+	    }
+	}
+	Results{states,findings,writes,width:self.registers.1}
+    }
+
+    /// Abstractly execute the instruction at a given pc from a given
+    /// state, producing each possible successor along with the state
+    /// on reaching it.  Findings for the instruction are recorded.
+    fn transfer(&self, pc: usize, state: &AbstractState<D>, findings: &mut Vec<Finding>) -> Vec<(Next,AbstractState<D>)> {
+	let Ok(entry) = self.program.get(pc) else {
+	    findings.push(Finding::Decode{pc});
+	    return vec![];
+	};
+	let microcode : Vec<MicroCode> = entry.microcode.iter().map(|t| t.code()).collect();
+	if !self.check(pc,&microcode,findings) {
+	    return vec![];
+	}
+	// The paths reaching each microcode
+	let mut paths : Vec<Vec<(Next,AbstractState<D>)>> = vec![Vec::new();microcode.len()+1];
+	paths[0].push((Next::Fallthrough,state.clone()));
+	for (i,code) in microcode.iter().enumerate() {
+	    let mut outcomes = (false,false);
+	    for (next,mut s) in std::mem::take(&mut paths[i]) {
+		match self.execute(pc,*code,&mut s) {
+		    Some((value,n)) => {
+			if let Some(z) = value.assume(true) {
+			    outcomes.0 = true;
+			    let mut t = s.clone();
+			    self.refine(*code,&mut t,z);
+			    join(&mut paths[(i+1+n).min(microcode.len())],next.clone(),t,self.registers.1);
+			}
+			if let Some(nz) = value.assume(false) {
+			    outcomes.1 = true;
+			    self.refine(*code,&mut s,nz);
+			    join(&mut paths[i+1],next,s,self.registers.1);
+			}
+		    }
+		    None if code.is_branch() => {
+			let next = self.target(pc,*code,&s);
+			join(&mut paths[i+1],next,s,self.registers.1);
+		    }
+		    None => {
+			let skip = if let MicroCode::Skip(n) = code { *n } else { 0 };
+			join(&mut paths[(i+1+skip).min(microcode.len())],next,s,self.registers.1);
+		    }
+		}
+	    }
+	    if let (MicroCode::SkipIfZero(..)|MicroCode::RegSkipIfZero(..),(a,b)) = (code,outcomes) {
+		if a != b { findings.push(Finding::Constant{pc,index:i,zero:a}); }
+	    }
+	}
+	let mut exits = paths.pop().unwrap();
+	for (next,s) in &mut exits {
+	    s.registers.retain(|r,_| !(LR - TEMPS..LR).contains(r));
+	    if *next == Next::Unknown { findings.push(Finding::UnknownTarget{pc}); }
+	}
+	exits
+    }
+
+    /// Check the given microcode only accesses memory and registers
+    /// which exist (as for `State::try_step()`), since otherwise the
+    /// instruction always faults.
+    fn check(&self, pc: usize, microcode: &[MicroCode], findings: &mut Vec<Finding>) -> bool {
+	let mut ok = true;
+	for code in microcode {
+	    for (address,width) in code.locations().into_iter().flatten() {
+		if address.checked_add(width.bytes()).is_none_or(|end| end > self.memory) {
+		    findings.push(Finding::OutOfBounds{pc,address,width});
+		    ok = false;
+		}
+	    }
+	    for register in code.registers().into_iter().flatten() {
+		if register >= self.registers.0 && register < LR - TEMPS {
+		    findings.push(Finding::InvalidRegister{pc,register});
+		    ok = false;
+		}
+	    }
+	}
+	ok
+    }
+
+    /// Abstractly execute a given microcode on a given state.  For a
+    /// conditional skip, the value tested and the number of microcode
+    /// skipped (if it is zero) are returned.
+    fn execute(&self, pc: usize, code: MicroCode, s: &mut AbstractState<D>) -> Option<(D,usize)> {
+	match code {
+	    MicroCode::Add(x,y,w) => {
+		let v = s.read(x,w).add(&s.read(y,w),w);
+		s.write(x,w,v);
+	    }
+	    MicroCode::Alu(op,x,y,w) => {
+		let v = s.read(x,w).apply(op,&s.read(y,w),w);
+		s.write(x,w,v);
+	    }
+	    MicroCode::Copy(x,y,w) => {
+		let v = s.read(y,w);
+		s.write(x,w,v);
+	    }
+	    MicroCode::Load(x,i,w) => s.write(x,w,D::constant(i & w.mask())),
+	    MicroCode::RegAdd(r,t) => {
+		let w = self.width(r);
+		let v = self.reg(pc,r,s).add(&self.reg(pc,t,s),Width::QuadWord).truncate(w);
+		self.set(r,v,s);
+	    }
+	    MicroCode::RegAlu(op,r,t) => {
+		let v = self.reg(pc,r,s).apply(op,&self.reg(pc,t,s),self.registers.1).truncate(self.width(r));
+		self.set(r,v,s);
+	    }
+	    MicroCode::RegCopy(r,t) => {
+		let v = self.reg(pc,t,s).truncate(self.width(r));
+		self.set(r,v,s);
+	    }
+	    MicroCode::RegFetch(r,x,w) => {
+		let v = s.read(x,w).truncate(self.width(r));
+		self.set(r,v,s);
+	    }
+	    MicroCode::RegFetchIndirect(r,t,w) => {
+		let v = self.indirect(pc,t,w,s).map_or(D::top(w),|x| s.read(x,w)).truncate(self.width(r));
+		self.set(r,v,s);
+	    }
+	    MicroCode::RegLoad(r,i) => self.set(r,D::constant(i & self.width(r).mask()),s),
+	    MicroCode::RegStore(x,r,w) => {
+		let v = self.reg(pc,r,s).truncate(w);
+		s.write(x,w,v);
+	    }
+	    MicroCode::RegStoreIndirect(t,r,w) => {
+		let v = self.reg(pc,r,s).truncate(w);
+		match self.indirect(pc,t,w,s) {
+		    Some(x) => s.write(x,w,v),
+		    None => s.forget()
+		}
+	    }
+	    MicroCode::RegSkipIfZero(r,n) => return Some((self.reg(pc,r,s),n)),
+	    MicroCode::SkipIfZero(x,w,n) => return Some((s.read(x,w),n)),
+	    MicroCode::Goto(_)|MicroCode::Jump(_)|MicroCode::Skip(_) => {}
+	}
+	None
+    }
+
+    /// Restrict the location tested by a conditional skip, given the
+    /// value it was found to hold.
+    fn refine(&self, code: MicroCode, s: &mut AbstractState<D>, value: D) {
+	match code {
+	    MicroCode::SkipIfZero(x,w,_) => s.write(x,w,value),
+	    MicroCode::RegSkipIfZero(r,_) if r != PC => self.set(r,value,s),
+	    _ => {}
+	}
+    }
+
+    /// Determine the target of a given branching microcode.
+    fn target(&self, pc: usize, code: MicroCode, s: &AbstractState<D>) -> Next {
+	let value = match code {
+	    MicroCode::Goto(i) => return Next::Targets(vec![i]),
+	    MicroCode::Jump(i) => return Next::Targets(vec![pc.wrapping_add_signed(i)]),
+	    // The target is the value written to the pc
+	    MicroCode::RegAdd(_,t) => D::constant(pc as u64).add(&self.reg(pc,t,s),Width::QuadWord),
+	    MicroCode::RegAlu(op,_,t) => D::constant(pc as u64).apply(op,&self.reg(pc,t,s),self.registers.1),
+	    MicroCode::RegCopy(_,t) => self.reg(pc,t,s),
+	    MicroCode::RegFetch(_,x,w) => s.read(x,w),
+	    MicroCode::RegFetchIndirect(_,t,w) => self.indirect(pc,t,w,s).map_or(D::top(w),|x| s.read(x,w)),
+	    MicroCode::RegLoad(_,i) => D::constant(i),
+	    _ => unreachable!()
+	};
+	match value.bounds() {
+	    Some((lo,hi)) if hi - lo < TARGETS => Next::Targets((lo..=hi).map(|t| t as usize).collect()),
+	    _ => Next::Unknown
+	}
+    }
+
+    /// Get the values a register (may) hold, where the pc is always
+    /// that of the instruction.
+    fn reg(&self, pc: usize, r: usize, s: &AbstractState<D>) -> D {
+	if r == PC { D::constant(pc as u64) } else { s.register(r,self.width(r)) }
+    }
+
+    /// Determine the location accessed indirectly via a given register
+    /// (wrapping around memory), if it is known and lies within
+    /// memory.
+    fn indirect(&self, pc: usize, r: usize, w: Width, s: &AbstractState<D>) -> Option<usize> {
+	let (lo,hi) = self.reg(pc,r,s).bounds()?;
+	let x = (self.memory > 0 && lo == hi).then(|| (lo % self.memory as u64) as usize)?;
+	(x + w.bytes() <= self.memory).then_some(x)
+    }
+
+    fn set(&self, r: usize, value: D, s: &mut AbstractState<D>) {
+	// Writes to the pc are handled as branches
+	if r != PC { s.set_register(r,value); }
+    }
+
+    /// Get the width of a given register, where special (and scratch)
+    /// registers are 64 bits.
+    fn width(&self, r: usize) -> Width {
+	if r >= LR - TEMPS { Width::QuadWord } else { self.registers.1 }
+    }
+}
+
+/// Add a path to those reaching a given microcode, joining it with
+/// any which proceed to the same successor.
+fn join<D:Domain>(paths: &mut Vec<(Next,AbstractState<D>)>, next: Next, state: AbstractState<D>, width: Width) {
+    match paths.iter_mut().find(|(n,_)| *n == next) {
+	Some((_,s)) => { *s = s.merge(&state,false,width); }
+	None => paths.push((next,state))
+    }
+}
+
+// =====================================================
+// Results
+// =====================================================
+
+/// The results of an analysis, giving the abstract state on reaching
+/// each instruction (if it is reachable), and any findings.
+pub struct Results<D:Domain> {
+    states: Vec<Option<AbstractState<D>>>,
+    pub findings: Vec<Finding>,
+    /// Highest address written by any reachable instruction.
+    writes: Option<usize>,
+    width: Width
+}
+
+impl<D:Domain> Results<D> {
+    /// Check whether the instruction at a given pc is reachable.
+    pub fn is_reachable(&self, pc: usize) -> bool {
+	matches!(self.states.get(pc),Some(Some(_)))
+    }
+    /// Get the abstract state on reaching the instruction at a given
+    /// pc (if it is reachable).
+    pub fn state(&self, pc: usize) -> Option<&AbstractState<D>> {
+	self.states.get(pc)?.as_ref()
+    }
+    /// Get the values a given memory location (may) hold on reaching
+    /// the instruction at a given pc (if it is reachable).
+    pub fn memory(&self, pc: usize, address: usize, width: Width) -> Option<D> {
+	Some(self.state(pc)?.read(address,width))
+    }
+    /// Get the values a given general purpose register (may) hold on
+    /// reaching the instruction at a given pc (if it is reachable).
+    pub fn register(&self, pc: usize, register: usize) -> Option<D> {
+	Some(self.state(pc)?.register(register,self.width))
+    }
+    /// Get the highest address which any reachable instruction may
+    /// write (if any).  Thus, for example, a program can be shown never to
+    /// write beyond a given address.
+    pub fn max_write(&self) -> Option<usize> {
+	self.writes
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+use crate::insn::{AbstractMicroCode,FieldKind,Instruction,InstructionSet,Operand,Predicate};
+
+// =====================================================
+// Warnings
+// =====================================================
+
+/// Identifies a suspicious microcode instruction within the semantics
+/// of a machine instruction.  Each warning identifies the offending
+/// microcode by its index within the sequence.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Warning {
+    /// A location is written and then overwritten by a later
+    /// microcode, without being read in between.
+    DeadWrite{mnemonic: String, index: usize, overwritten_by: usize},
+    /// A fixed location is read, but no instruction ever writes it
+    /// (either at that fixed location, or through an operand).
+    UnwrittenRead{mnemonic: String, index: usize, address: usize},
+    /// A microcode follows an unconditional branch (`Goto` or
+    /// `Jump`).  Since the branch determines the next instruction,
+    /// this is considered unreachable (i.e. it should be placed
+    /// before the branch).
+    Unreachable{mnemonic: String, index: usize}
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Warning::DeadWrite{mnemonic,index,overwritten_by} => {
+		write!(f,"{}: write at #{} overwritten at #{} without being read",mnemonic,index,overwritten_by)
+	    }
+	    Warning::UnwrittenRead{mnemonic,index,address} => {
+		write!(f,"{}: read at #{} of location {} which is never written",mnemonic,index,address)
+	    }
+	    Warning::Unreachable{mnemonic,index} => write!(f,"{}: microcode #{} is unreachable",mnemonic,index)
+	}
+    }
+}
+
+// =====================================================
+// Analysis
+// =====================================================
+
+/// Analyze the semantics of every instruction in a given set,
+/// reporting dead writes, unreachable microcode and reads of fixed
+/// locations which no instruction writes.  Since operands are not
+/// known statically, this is conservative: locations given by
+/// operands are assumed to alias any other location (hence reading
+/// them prevents a dead write), and to cover any location their field
+/// can encode (hence writing them counts as writing such locations).
+pub fn analyze(isa: &InstructionSet) -> Vec<Warning> {
+    // Determine all ranges of locations which can be written
+    let mut written : Vec<(usize,usize)> = Vec::new();
+    for insn in isa {
+	for (x,w) in insn.semantic().iter().flat_map(|c| c.nested()).filter_map(write) {
+	    let range = match x {
+		x if x.is_constant() => {
+		    let c = x.as_usize(&[]);
+		    (c,c.saturating_add(w))
+		}
+		Operand::Var(v) => {
+		    let field = &insn.format().operands()[*v];
+		    let bits = field.bits().value() as u32;
+		    match field.kind() {
+			FieldKind::SignedImmediate => (0,usize::MAX),
+			_ if bits >= usize::BITS => (0,usize::MAX),
+			_ => (0,(1usize << bits).saturating_add(w - 1))
+		    }
+		}
+		// Compound expressions may write anywhere
+		_ => (0,usize::MAX)
+	    };
+	    written.push(range);
+	}
+    }
+    let mut warnings = Vec::new();
+    for insn in isa {
+	warnings.extend(analyze_instruction(insn));
+	for (i,code) in insn.semantic().iter().enumerate() {
+	    for (y,w) in code.nested().into_iter().flat_map(reads).filter(|(y,_)| y.is_constant()) {
+		let c = y.as_usize(&[]);
+		if !written.iter().any(|(s,e)| c < *e && c.saturating_add(w) > *s) {
+		    warnings.push(Warning::UnwrittenRead{mnemonic:insn.mnemonic().to_string(),index:i,address:c});
+		}
+	    }
+	}
+    }
+    warnings
+}
+
+/// Analyze the semantics of a single instruction, reporting dead
+/// writes and unreachable microcode.
+pub fn analyze_instruction(insn: &Instruction) -> Vec<Warning> {
+    let mnemonic = insn.mnemonic();
+    let mut warnings = Vec::new();
+    // Writes not (yet) read, as (index,location,bytes)
+    let mut pending : Vec<(usize,&Operand,usize)> = Vec::new();
+    let mut branched = false;
+    for (i,code) in insn.semantic().iter().enumerate() {
+	if branched {
+	    warnings.push(Warning::Unreachable{mnemonic:mnemonic.to_string(),index:i});
+	}
+	for (y,w) in reads(code) {
+	    pending.retain(|(_,x,v)| !aliases(y,w,x,*v));
+	}
+	if let AbstractMicroCode::If(..) = code {
+	    // Conservatively, nested microcode reads everything and
+	    // writes nothing (since it may not execute).
+	    pending.clear();
+	}
+	if let Some((x,w)) = write(code) {
+	    pending.retain(|(j,p,v)| {
+		let covered = covers(x,w,p,*v);
+		if covered {
+		    warnings.push(Warning::DeadWrite{mnemonic:mnemonic.to_string(),index:*j,overwritten_by:i});
+		}
+		!covered
+	    });
+	    pending.push((i,x,w));
+	}
+	branched |= matches!(code,AbstractMicroCode::Goto(_)|AbstractMicroCode::Jump(_));
+    }
+    // Report in order of occurrence
+    warnings.sort_by_key(|w| match w {
+	Warning::DeadWrite{index,..}|Warning::Unreachable{index,..}|Warning::UnwrittenRead{index,..} => *index
+    });
+    warnings
+}
+
+/// Determine the locations (and number of bytes) read by a
+/// microcode.
+fn reads(code: &AbstractMicroCode) -> Vec<(&Operand,usize)> {
+    match code {
+	AbstractMicroCode::Add(x,y,w)|AbstractMicroCode::Alu(_,x,y,w) => vec![(x,w.bytes()),(y,w.bytes())],
+	AbstractMicroCode::Copy(_,y,w)|AbstractMicroCode::RegFetch(_,y,w) => vec![(y,w.bytes())],
+	AbstractMicroCode::If(p,_,_) => tested(p).into_iter().collect(),
+	_ => Vec::new()
+    }
+}
+
+/// Determine the location (and number of bytes) tested by a
+/// predicate.
+fn tested(p: &Predicate) -> Option<(&Operand,usize)> {
+    match p {
+	Predicate::Zero(x,w) => Some((x,w.bytes())),
+	Predicate::Not(p) => tested(p),
+	_ => None
+    }
+}
+
+/// Determine the location (and number of bytes) written by a
+/// microcode.
+fn write(code: &AbstractMicroCode) -> Option<(&Operand,usize)> {
+    match code {
+	AbstractMicroCode::Add(x,_,w)|AbstractMicroCode::Alu(_,x,_,w)|AbstractMicroCode::Copy(x,_,w)|AbstractMicroCode::Load(x,_,w)
+	    |AbstractMicroCode::RegStore(x,_,w) => Some((x,w.bytes())),
+	_ => None
+    }
+}
+
+/// Check whether two locations may overlap.
+fn aliases(x: &Operand, n: usize, y: &Operand, m: usize) -> bool {
+    match (constant(x),constant(y)) {
+	(Some(a),Some(b)) => a < b.saturating_add(m) && b < a.saturating_add(n),
+	_ => true
+    }
+}
+
+/// Check whether one location definitely covers another.  Locations
+/// given by the same operand expression are assumed to be the same.
+fn covers(x: &Operand, n: usize, y: &Operand, m: usize) -> bool {
+    match (constant(x),constant(y)) {
+	(Some(a),Some(b)) => a <= b && b.saturating_add(m) <= a.saturating_add(n),
+	(None,None) => x == y && m <= n,
+	_ => false
+    }
+}
+
+/// Evaluate an operand expression which does not depend on any
+/// operands.
+fn constant(x: &Operand) -> Option<usize> {
+    x.is_constant().then(|| x.as_usize(&[]))
+}
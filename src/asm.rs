@@ -0,0 +1,810 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::insn::{EncodeError,FieldKind,InstructionSet,Operand};
+use crate::machine::MachineProfile;
+use crate::program::{Location,Program,Relocation,Symbol,SymbolKind};
+
+// =====================================================
+// Errors
+// =====================================================
+
+/// Identifies a problem encountered when assembling a given line of
+/// source.
+#[derive(Clone,Debug,PartialEq)]
+pub struct AsmError {
+    /// Line number (starting from 1) where the error arose.
+    pub line: usize,
+    /// Describes what went wrong.
+    pub kind: AsmErrorKind
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum AsmErrorKind {
+    /// No instruction exists with the given mnemonic.
+    UnknownMnemonic(String),
+    /// The wrong number of operands was given.
+    WrongArity{expected: usize, actual: usize},
+    /// An operand could not be parsed.
+    InvalidOperand(String),
+    /// A register was expected, but something else was given.
+    ExpectedRegister(String),
+    /// An immediate was expected, but a register was given.
+    ExpectedImmediate(String),
+    /// A label (or constant) was used but never defined.
+    UnknownLabel(String),
+    /// A label (or constant) was defined more than once.
+    DuplicateLabel(String),
+    /// An unknown assembler directive was used.
+    UnknownDirective(String),
+    /// A macro definition was not terminated by `.endm`.
+    UnterminatedMacro(String),
+    /// A macro was (directly or indirectly) invoked from within its
+    /// own expansion.
+    MacroRecursion(String),
+    /// An expression refers to an external symbol in a way which
+    /// cannot be described by a relocation (e.g. `2 * ext`).
+    InvalidRelocation(String),
+    /// The value of a label (which for pc-relative operands is the
+    /// offset from the current instruction) does not fit into its
+    /// field.
+    OutOfRange{label: String, value: isize},
+    /// The instruction could not be encoded.
+    Encode(EncodeError)
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"line {}: ",self.line)?;
+	match &self.kind {
+	    AsmErrorKind::UnknownMnemonic(m) => write!(f,"unknown mnemonic \"{}\"",m),
+	    AsmErrorKind::WrongArity{expected,actual} => write!(f,"expected {} operand(s), found {}",expected,actual),
+	    AsmErrorKind::InvalidOperand(o) => write!(f,"invalid operand \"{}\"",o),
+	    AsmErrorKind::ExpectedRegister(o) => write!(f,"expected register, found \"{}\"",o),
+	    AsmErrorKind::ExpectedImmediate(o) => write!(f,"expected immediate, found \"{}\"",o),
+	    AsmErrorKind::UnknownLabel(l) => write!(f,"unknown label \"{}\"",l),
+	    AsmErrorKind::DuplicateLabel(l) => write!(f,"duplicate label \"{}\"",l),
+	    AsmErrorKind::UnknownDirective(d) => write!(f,"unknown directive \"{}\"",d),
+	    AsmErrorKind::UnterminatedMacro(m) => write!(f,"macro \"{}\" missing .endm",m),
+	    AsmErrorKind::MacroRecursion(m) => write!(f,"recursive invocation of macro \"{}\"",m),
+	    AsmErrorKind::InvalidRelocation(e) => write!(f,"cannot relocate \"{}\"",e),
+	    AsmErrorKind::OutOfRange{label,value} => write!(f,"label \"{}\" out of range ({})",label,value),
+	    AsmErrorKind::Encode(e) => write!(f,"cannot encode instruction ({})",e)
+	}
+    }
+}
+
+impl std::error::Error for AsmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self.kind {
+	    AsmErrorKind::Encode(e) => Some(e),
+	    _ => None
+	}
+    }
+}
+
+// =====================================================
+// Assembler
+// =====================================================
+
+/// Responsible for turning a textual assembly program into an encoded
+/// program for a given instruction set.  The syntax is line-based,
+/// where each line may contain a label, an instruction and a comment:
+///
+/// ```text
+/// loop:   mov r1, r2    ; copy r2 into r1
+///         ldi r3, 0x10
+/// ```
+///
+/// Registers are written as `r<n>`.  Immediates are constant
+/// expressions over numbers (in decimal, hexadecimal `0x` or binary
+/// `0b`), labels and constants using the usual arithmetic and bitwise
+/// operators (e.g. `base + 4*n`).  Labels evaluate to the pc of the
+/// instruction they label.  However, for operands used as pc-relative
+/// offsets (see `Instruction::is_relative()`), an expression
+/// referring to a label instead evaluates to its offset from the
+/// current instruction.  Labels can be referenced before they are
+/// defined, since they are resolved in a second pass.  Pseudo
+/// instructions are expanded before labels are resolved, such that
+/// each pseudo instruction occupies as many pc values as the length
+/// of its expansion.
+///
+/// Constants are defined using `.equ name, expr` and parameterised
+/// macros using `.macro`, where parameters are referred to within the
+/// body using a backslash:
+///
+/// ```text
+///         .equ BASE, 0x40
+///         .macro clear reg
+///         ldi \reg, 0
+///         .endm
+///         clear r1
+///         ldi r2, BASE + 4
+/// ```
+///
+/// Symbols defined in other programs are declared using `.extern`,
+/// and can be used in operands of the form `sym` or `sym + k`.  Such
+/// operands are encoded as zero, and a corresponding relocation is
+/// recorded in the assembled program.  Likewise, symbols which other
+/// programs may refer to are marked using `.global`.  All labels and
+/// constants are recorded in the program's symbol table.  Absolute
+/// references to labels (i.e. of the form `label + k`) are also
+/// recorded as relocations, allowing the program to be moved when
+/// linked.
+pub struct Assembler<'a> {
+    isa: &'a InstructionSet<'a>,
+    /// Name of the source file, as recorded in the location of each
+    /// instruction.
+    file: String,
+    /// Names (and aliases) of registers, in addition to `r0`, `r1`,
+    /// etc.
+    registers: BTreeMap<String,usize>
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	Assembler{isa,file:String::new(),registers:BTreeMap::new()}
+    }
+    /// Set the name of the source file being assembled (default is
+    /// empty).
+    pub fn file(mut self, file: &str) -> Self {
+	self.file = file.to_string();
+	self
+    }
+    /// Accept the register names (and aliases) declared by a given
+    /// machine profile.
+    pub fn profile(mut self, profile: &MachineProfile) -> Self {
+	for (i,name) in profile.names.iter().enumerate() {
+	    self.registers.insert(name.clone(),i);
+	}
+	for (alias,i) in &profile.aliases {
+	    self.registers.insert(alias.clone(),*i);
+	}
+	self
+    }
+    /// Assemble a given source program.
+    pub fn assemble(&self, source: &str) -> Result<Program<'a>,AsmError> {
+	self.build(source).map(|(program,_)| program)
+    }
+    /// Assemble a given source program, additionally producing a
+    /// listing which relates each source line to the instructions it
+    /// produced.
+    pub fn assemble_with_listing(&self, source: &str) -> Result<(Program<'a>,Listing),AsmError> {
+	let (program,lines) = self.build(source)?;
+	let mut rows = Vec::new();
+	let mut pc = 0;
+	for (i,text) in source.lines().enumerate() {
+	    let mut first = true;
+	    while pc < lines.len() && lines[pc] == i + 1 {
+		let offset = program.offset(pc);
+		let end = if pc + 1 < program.len() { program.offset(pc+1) } else { program.bytes().len() };
+		let source = if first { text.to_string() } else { String::new() };
+		rows.push(ListingLine{line:i+1,source,pc:Some(pc),address:Some(offset),bytes:program.bytes()[offset..end].to_vec()});
+		first = false;
+		pc += 1;
+	    }
+	    if first {
+		rows.push(ListingLine{line:i+1,source:text.to_string(),pc:None,address:None,bytes:Vec::new()});
+	    }
+	}
+	let symbols = program.symbols().to_vec();
+	Ok((program,Listing{lines:rows,symbols}))
+    }
+
+    /// Assemble a given source program, returning the line number from
+    /// which each instruction originated.
+    fn build(&self, source: &str) -> Result<(Program<'a>,Vec<usize>),AsmError> {
+	let mut lines = parse(source)?;
+	// Expand pseudo instructions
+	for l in &mut lines {
+	    l.insns = self.expand(&l.insns).map_err(|kind| AsmError{line:l.line,kind})?;
+	}
+	// Pass 1: determine the pc of every label
+	let mut labels = BTreeMap::new();
+	let mut pc = 0;
+	for l in &lines {
+	    for label in &l.labels {
+		if labels.insert(label.clone(),pc).is_some() {
+		    return Err(AsmError{line:l.line,kind:AsmErrorKind::DuplicateLabel(label.clone())});
+		}
+	    }
+	    pc += l.insns.len();
+	}
+	// Determine external symbols
+	let mut symbols = Symbols{labels,constants:BTreeMap::new(),externs:Vec::new()};
+	for l in &lines {
+	    for name in &l.externs {
+		if symbols.labels.contains_key(name) || symbols.externs.contains(name) {
+		    return Err(AsmError{line:l.line,kind:AsmErrorKind::DuplicateLabel(name.clone())});
+		}
+		symbols.externs.push(name.clone());
+	    }
+	}
+	// Evaluate constants (in order of definition)
+	let mut order = Vec::new();
+	for l in &lines {
+	    if let Some((name,expr)) = &l.equ {
+		let v = evaluate(expr,&symbols).map_err(|kind| AsmError{line:l.line,kind})?;
+		if v.external.is_some() {
+		    return Err(AsmError{line:l.line,kind:AsmErrorKind::InvalidRelocation(expr.clone())});
+		}
+		if symbols.labels.contains_key(name) || symbols.externs.contains(name) || symbols.constants.insert(name.clone(),v.value).is_some() {
+		    return Err(AsmError{line:l.line,kind:AsmErrorKind::DuplicateLabel(name.clone())});
+		}
+		order.push(name);
+	    }
+	}
+	// Pass 2: encode every instruction
+	let mut program = Program::new(self.isa);
+	let mut origins = Vec::new();
+	for l in &lines {
+	    for (mnemonic,operands) in &l.insns {
+		let pc = program.len();
+		origins.push(l.line);
+		let (operands,relocs) = self.operands(pc,mnemonic,operands,&symbols).map_err(|kind| AsmError{line:l.line,kind})?;
+		program.push(mnemonic,&operands).map_err(|e| AsmError{line:l.line,kind:AsmErrorKind::Encode(e)})?;
+		program.locate(pc,Location{file:self.file.clone(),line:l.line});
+		for r in relocs {
+		    program.relocate(r);
+		}
+	    }
+	}
+	// Construct symbol table
+	let mut globals = Vec::new();
+	for l in &lines {
+	    for name in &l.globals {
+		if !symbols.labels.contains_key(name) && !symbols.constants.contains_key(name) {
+		    return Err(AsmError{line:l.line,kind:AsmErrorKind::UnknownLabel(name.clone())});
+		}
+		globals.push(name);
+	    }
+	}
+	for l in &lines {
+	    for name in &l.labels {
+		let value = symbols.labels[name];
+		program.define(Symbol{name:name.clone(),value,kind:SymbolKind::Label,global:globals.contains(&name)});
+	    }
+	}
+	for name in order {
+	    let value = symbols.constants[name];
+	    program.define(Symbol{name:name.clone(),value,kind:SymbolKind::Constant,global:globals.contains(&name)});
+	}
+	Ok((program,origins))
+    }
+
+    /// Expand any pseudo instructions in a given sequence of
+    /// instructions.  Operands of a pseudo instruction are substituted
+    /// (as text) into its expansion, such that labels are resolved
+    /// with respect to the real instruction they end up in.
+    fn expand(&self, insns: &[(String,Vec<String>)]) -> Result<Vec<(String,Vec<String>)>,AsmErrorKind> {
+	let mut expanded = Vec::new();
+	for (mnemonic,operands) in insns {
+	    let pseudo = self.isa.pseudos().iter().find(|p| p.mnemonic() == mnemonic);
+	    let is_real = self.isa.get(mnemonic).is_some();
+	    match pseudo {
+		Some(p) if !is_real => {
+		    if p.arity() != operands.len() {
+			return Err(AsmErrorKind::WrongArity{expected:p.arity(),actual:operands.len()});
+		    }
+		    for (m,ops) in p.expansion() {
+			let insn = self.isa.get(m).unwrap();
+			let fields = insn.format().operands();
+			let ops = ops.iter().zip(fields).map(|(o,f)| match (o,f.kind()) {
+			    (Operand::Var(v),_) => operands[*v].clone(),
+			    (o,FieldKind::Register) if o.is_constant() => format!("r{}",o.as_usize(&[])),
+			    (o,_) => substitute(o,operands)
+			}).collect();
+			expanded.push((m.to_string(),ops));
+		    }
+		}
+		_ => {
+		    expanded.push((mnemonic.clone(),operands.clone()));
+		}
+	    }
+	}
+	Ok(expanded)
+    }
+    /// Translate the operands of a given instruction (at a given pc)
+    /// into their values, checking they are appropriate for their
+    /// fields.  Operands referring to external symbols are encoded as
+    /// zero, and the necessary relocations returned.
+    fn operands(&self, pc: usize, mnemonic: &str, operands: &[String], symbols: &Symbols) -> Result<(Vec<usize>,Vec<Relocation>),AsmErrorKind> {
+	let insn = match self.isa.get(mnemonic) {
+	    Some(i) => i,
+	    None => { return Err(AsmErrorKind::UnknownMnemonic(mnemonic.to_string())); }
+	};
+	let fields = insn.format().operands();
+	if fields.len() != operands.len() {
+	    return Err(AsmErrorKind::WrongArity{expected:fields.len(),actual:operands.len()});
+	}
+	let mut values = Vec::new();
+	let mut relocs = Vec::new();
+	for (i,(field,operand)) in fields.iter().zip(operands).enumerate() {
+	    let register = parse_register(operand).or_else(|| self.registers.get(operand).copied());
+	    let value = match (field.kind(),register) {
+		(FieldKind::Register,Some(r)) => r,
+		(FieldKind::Register,None) => {
+		    return Err(AsmErrorKind::ExpectedRegister(operand.clone()));
+		}
+		(_,Some(_)) => {
+		    return Err(AsmErrorKind::ExpectedImmediate(operand.clone()));
+		}
+		(_,None) => {
+		    let Value{value,label,external,relocatable} = evaluate(operand,symbols)?;
+		    if let Some((symbol,addend)) = external {
+			let relative = insn.is_relative(i);
+			relocs.push(Relocation{pc,operand:i,symbol:Some(symbol),addend,relative});
+			0
+		    } else if !label {
+			value
+		    } else {
+			let value = if insn.is_relative(i) {
+			    value.wrapping_sub(pc)
+			} else {
+			    if relocatable {
+				relocs.push(Relocation{pc,operand:i,symbol:None,addend:value as isize,relative:false});
+			    }
+			    value
+			};
+			if !field.fits(value) {
+			    let value = value as isize;
+			    return Err(AsmErrorKind::OutOfRange{label:operand.clone(),value});
+			}
+			value
+		    }
+		}
+	    };
+	    values.push(value);
+	}
+	Ok((values,relocs))
+    }
+}
+
+// =====================================================
+// Listing
+// =====================================================
+
+/// A single line of an assembly listing.  Source lines producing
+/// several instructions (e.g. macros or pseudo instructions) are
+/// followed by additional lines, one for each further instruction,
+/// which have no source.
+#[derive(Clone,Debug,PartialEq)]
+pub struct ListingLine {
+    /// Line number (starting from 1) in the source.
+    pub line: usize,
+    /// Source text of this line.
+    pub source: String,
+    /// The pc of the instruction on this line (if any).
+    pub pc: Option<usize>,
+    /// Address of the first byte of the instruction on this line (if
+    /// any).
+    pub address: Option<usize>,
+    /// Encoded bytes of the instruction on this line.
+    pub bytes: Vec<u8>
+}
+
+/// An assembly listing, consisting of every source line alongside the
+/// instructions it produced, followed by a symbol table.  When
+/// displayed, this looks like the following:
+///
+/// ```text
+///    1 0000  45 00  start: ldi r1, 1
+///    2 0002  02     nop
+///
+/// start = 0 (label, global)
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+    pub symbols: Vec<Symbol>
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let bytes : Vec<String> = self.lines.iter().map(|l| {
+	    let bs : Vec<String> = l.bytes.iter().map(|b| format!("{:02x}",b)).collect();
+	    bs.join(" ")
+	}).collect();
+	let width = bytes.iter().map(|b| b.len()).max().unwrap_or(0);
+	for (l,b) in self.lines.iter().zip(&bytes) {
+	    let address = match l.address {
+		Some(a) => format!("{:04x}",a),
+		None => "    ".to_string()
+	    };
+	    let line = format!("{:>4} {}  {:<width$}  {}",l.line,address,b,l.source,width=width);
+	    writeln!(f,"{}",line.trim_end())?;
+	}
+	if !self.symbols.is_empty() {
+	    writeln!(f)?;
+	}
+	for s in &self.symbols {
+	    let kind = match s.kind {
+		SymbolKind::Label => "label",
+		SymbolKind::Constant => "constant"
+	    };
+	    let scope = if s.global { ", global" } else { "" };
+	    writeln!(f,"{} = {} ({}{})",s.name,s.value,kind,scope)?;
+	}
+	Ok(())
+    }
+}
+
+// =====================================================
+// Parsing
+// =====================================================
+
+/// The maximum depth to which macros can be expanded.  This prevents
+/// infinite expansion of recursive macros.
+const MAX_MACRO_DEPTH : usize = 32;
+
+/// Represents a single (non-empty) line of source.
+struct SourceLine {
+    /// Line number (starting from 1).
+    line: usize,
+    /// Labels defined on this line.
+    labels: Vec<String>,
+    /// Instructions (mnemonic and operands) on this line.  Initially,
+    /// there is at most one but, once pseudo instructions are
+    /// expanded, there may be several.
+    insns: Vec<(String,Vec<String>)>,
+    /// Constant (name and expression) defined on this line, if any.
+    equ: Option<(String,String)>,
+    /// External symbols declared on this line.
+    externs: Vec<String>,
+    /// Global symbols declared on this line.
+    globals: Vec<String>
+}
+
+/// A macro definition, consisting of named parameters and a body of
+/// source lines.
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>
+}
+
+fn parse(source: &str) -> Result<Vec<SourceLine>,AsmError> {
+    let raw : Vec<&str> = source.lines().map(strip_comment).collect();
+    let mut macros = BTreeMap::new();
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+	let line = i + 1;
+	if let Some(rest) = directive(raw[i],".macro") {
+	    let mut words = rest.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty());
+	    let name = match words.next() {
+		Some(n) => n.to_string(),
+		None => { return Err(AsmError{line,kind:AsmErrorKind::InvalidOperand(rest.to_string())}); }
+	    };
+	    let params = words.map(|w| w.to_string()).collect();
+	    let mut body = Vec::new();
+	    i += 1;
+	    while i < raw.len() && directive(raw[i],".endm").is_none() {
+		body.push(raw[i].to_string());
+		i += 1;
+	    }
+	    if i == raw.len() {
+		return Err(AsmError{line,kind:AsmErrorKind::UnterminatedMacro(name)});
+	    }
+	    macros.insert(name,Macro{params,body});
+	} else {
+	    parse_line(line,raw[i],&macros,0,&mut lines)?;
+	}
+	i += 1;
+    }
+    Ok(lines)
+}
+
+fn parse_line(line: usize, text: &str, macros: &BTreeMap<String,Macro>, depth: usize, lines: &mut Vec<SourceLine>) -> Result<(),AsmError> {
+    let mut text = text.trim();
+    // Extract labels
+    let mut labels = Vec::new();
+    while let Some(j) = text.find(':') {
+	let label = text[..j].trim();
+	if !is_identifier(label) { break; }
+	labels.push(label.to_string());
+	text = text[j+1..].trim();
+    }
+    let mut insns = Vec::new();
+    let mut equ = None;
+    let mut externs = Vec::new();
+    let mut globals = Vec::new();
+    if !text.is_empty() {
+	let (mnemonic,rest) = match text.find(char::is_whitespace) {
+	    Some(j) => (&text[..j],text[j..].trim()),
+	    None => (text,"")
+	};
+	let operands : Vec<String> = if rest.is_empty() {
+	    Vec::new()
+	} else {
+	    rest.split(',').map(|o| o.trim().to_string()).collect()
+	};
+	if mnemonic == ".equ" {
+	    if operands.len() != 2 || !is_identifier(&operands[0]) {
+		return Err(AsmError{line,kind:AsmErrorKind::InvalidOperand(rest.to_string())});
+	    }
+	    equ = Some((operands[0].clone(),operands[1].clone()));
+	} else if mnemonic == ".extern" || mnemonic == ".global" {
+	    if operands.is_empty() || !operands.iter().all(|o| is_identifier(o)) {
+		return Err(AsmError{line,kind:AsmErrorKind::InvalidOperand(rest.to_string())});
+	    }
+	    if mnemonic == ".extern" { externs = operands; } else { globals = operands; }
+	} else if let Some(m) = macros.get(mnemonic) {
+	    if depth >= MAX_MACRO_DEPTH {
+		return Err(AsmError{line,kind:AsmErrorKind::MacroRecursion(mnemonic.to_string())});
+	    } else if m.params.len() != operands.len() {
+		return Err(AsmError{line,kind:AsmErrorKind::WrongArity{expected:m.params.len(),actual:operands.len()}});
+	    }
+	    if !labels.is_empty() {
+		lines.push(SourceLine{line,labels,insns:Vec::new(),equ:None,externs:Vec::new(),globals:Vec::new()});
+	    }
+	    // Substitute longest parameters first, so that one parameter
+	    // being a prefix of another is not a problem.
+	    let mut args : Vec<(&String,&String)> = m.params.iter().zip(&operands).collect();
+	    args.sort_by_key(|(p,_)| std::cmp::Reverse(p.len()));
+	    for body in &m.body {
+		let mut body = body.clone();
+		for (p,a) in &args {
+		    body = body.replace(&format!("\\{}",p),a);
+		}
+		parse_line(line,&body,macros,depth+1,lines)?;
+	    }
+	    return Ok(());
+	} else if mnemonic.starts_with('.') {
+	    return Err(AsmError{line,kind:AsmErrorKind::UnknownDirective(mnemonic.to_string())});
+	} else {
+	    insns.push((mnemonic.to_string(),operands));
+	}
+    }
+    if !labels.is_empty() || !insns.is_empty() || equ.is_some() || !externs.is_empty() || !globals.is_empty() {
+	lines.push(SourceLine{line,labels,insns,equ,externs,globals});
+    }
+    Ok(())
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find([';','#']) {
+	Some(j) => &text[..j],
+	None => text
+    }
+}
+
+/// Check whether a given line is a given directive and, if so,
+/// return the remainder of the line.
+fn directive<'b>(text: &'b str, name: &str) -> Option<&'b str> {
+    let text = text.trim();
+    match text.strip_prefix(name) {
+	Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => Some(rest.trim()),
+	_ => None
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+	Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+	    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+	}
+	_ => false
+    }
+}
+
+fn parse_register(s: &str) -> Option<usize> {
+    match s.strip_prefix('r') {
+	Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => n.parse().ok(),
+	_ => None
+    }
+}
+
+// =====================================================
+// Expressions
+// =====================================================
+
+/// The symbols available when evaluating an expression.
+struct Symbols {
+    /// Maps labels to the pc of the instruction they label.
+    labels: BTreeMap<String,usize>,
+    /// Maps constants to their values.
+    constants: BTreeMap<String,usize>,
+    /// Symbols defined elsewhere.
+    externs: Vec<String>
+}
+
+/// The result of evaluating an expression.
+struct Value {
+    value: usize,
+    /// Indicates whether a label was referenced.
+    label: bool,
+    /// The external symbol referenced (if any), along with the
+    /// constant offset from it.
+    external: Option<(String,isize)>,
+    /// Indicates whether the value has the form `label + k` and,
+    /// hence, moves with the program.
+    relocatable: bool
+}
+
+#[derive(Clone,PartialEq)]
+enum Token {
+    Number(usize),
+    Identifier(String),
+    Operator(&'static str)
+}
+
+/// Substitute the (textual) operands of a pseudo instruction into an
+/// operand expression from its expansion, giving an expression which
+/// can be evaluated once labels are resolved.
+fn substitute(operand: &Operand, operands: &[String]) -> String {
+    let (l,op,r) = match operand {
+	Operand::Const(c) => { return format!("({})",*c as isize); }
+	Operand::Var(v) => { return format!("({})",operands[*v]); }
+	Operand::Pc|Operand::Sp|Operand::Flags|Operand::Lr|Operand::Temp(_) => { return format!("({})",operand.as_usize(&[]) as isize); }
+	Operand::Add(l,r) => (l,"+",r),
+	Operand::Mul(l,r) => (l,"*",r),
+	Operand::Shl(l,r) => (l,"<<",r),
+	Operand::RegSlot(r,w) => { return format!("({} * {})",substitute(r,operands),w.bytes()); }
+	// Fields hold the offset of a pc-relative expression
+	Operand::PcRel(o) => { return substitute(o,operands); }
+	Operand::SExt(_,0) => { return "(0)".to_string(); }
+	Operand::SExt(o,n) => {
+	    // Shifting right is arithmetic on signed machine words
+	    let shift = isize::BITS.saturating_sub(*n as u32);
+	    return format!("(({} << {}) >> {})",substitute(o,operands),shift,shift);
+	}
+	Operand::Bits(o,hi,lo) => {
+	    // Masking discards any bits shifted in from the sign
+	    let n = (*hi as u32 + 1).saturating_sub(*lo as u32);
+	    let mask = usize::MAX.checked_shr(usize::BITS.saturating_sub(n)).unwrap_or(0) as isize;
+	    return format!("(({} >> {}) & {})",substitute(o,operands),lo,mask);
+	}
+    };
+    format!("({} {} {})",substitute(l,operands),op,substitute(r,operands))
+}
+
+/// Evaluate a constant expression.  Arithmetic is performed on signed
+/// machine words, with overflow wrapping around.  An expression can
+/// refer to at most one external symbol and, furthermore, must have
+/// the form `sym + k` (for some constant `k`).  This is checked by
+/// evaluating it with the symbol bound to both `0` and `1`.
+/// Likewise, evaluating it with all labels shifted by one determines
+/// whether it moves with the program.
+fn evaluate(text: &str, symbols: &Symbols) -> Result<Value,AsmErrorKind> {
+    let invalid = || AsmErrorKind::InvalidOperand(text.to_string());
+    let tokens = tokenize(text).ok_or_else(invalid)?;
+    let mut results = Vec::new();
+    for (binding,shift) in [(0,0),(1,0),(0,1)] {
+	let mut eval = Evaluator{tokens:tokens.clone(),index:0,symbols,label:false,external:None,binding,shift};
+	let value = eval.binary(0)?;
+	if eval.index != eval.tokens.len() {
+	    return Err(invalid());
+	}
+	match value {
+	    Some(v) => results.push((v,eval.label,eval.external)),
+	    None => { return Err(invalid()); }
+	}
+    }
+    let (v0,label,external) = results.remove(0);
+    let relocatable = results[1].0.wrapping_sub(v0) == 1;
+    match external {
+	None => Ok(Value{value:v0 as usize,label,external:None,relocatable}),
+	Some(sym) if !label && results[0].0.wrapping_sub(v0) == 1 => {
+	    Ok(Value{value:0,label,external:Some((sym,v0)),relocatable:false})
+	}
+	Some(_) => Err(AsmErrorKind::InvalidRelocation(text.to_string()))
+    }
+}
+
+fn tokenize(text: &str) -> Option<Vec<Token>> {
+    const OPERATORS : [&str;14] = ["<<",">>","+","-","*","/","%","&","|","^","~","(",")",","];
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+	let c = rest.chars().next().unwrap();
+	let n = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+	if c.is_ascii_digit() {
+	    let word = &rest[..n];
+	    let value = if let Some(h) = word.strip_prefix("0x") {
+		usize::from_str_radix(h,16).ok()?
+	    } else if let Some(b) = word.strip_prefix("0b") {
+		usize::from_str_radix(b,2).ok()?
+	    } else {
+		word.parse().ok()?
+	    };
+	    tokens.push(Token::Number(value));
+	    rest = &rest[n..];
+	} else if n > 0 {
+	    tokens.push(Token::Identifier(rest[..n].to_string()));
+	    rest = &rest[n..];
+	} else {
+	    let op = OPERATORS.iter().find(|op| rest.starts_with(*op))?;
+	    tokens.push(Token::Operator(op));
+	    rest = &rest[op.len()..];
+	}
+	rest = rest.trim_start();
+    }
+    Some(tokens)
+}
+
+struct Evaluator<'b> {
+    tokens: Vec<Token>,
+    index: usize,
+    symbols: &'b Symbols,
+    /// Records whether a label was referenced.
+    label: bool,
+    /// Records which external symbol was referenced (if any).
+    external: Option<String>,
+    /// The value given to external symbols.
+    binding: isize,
+    /// The amount by which all labels are shifted.
+    shift: isize
+}
+
+impl Evaluator<'_> {
+    /// Parse and evaluate a binary expression whose operators have at
+    /// least a given precedence.  Returns `None` for a syntax error.
+    fn binary(&mut self, precedence: usize) -> Result<Option<isize>,AsmErrorKind> {
+	let mut lhs = match self.unary()? {
+	    Some(v) => v,
+	    None => { return Ok(None); }
+	};
+	while let Some(Token::Operator(op)) = self.tokens.get(self.index) {
+	    let p = match *op {
+		"|" => 0, "^" => 1, "&" => 2, "<<" | ">>" => 3, "+" | "-" => 4, "*" | "/" | "%" => 5,
+		_ => { break; }
+	    };
+	    if p < precedence { break; }
+	    let op = *op;
+	    self.index += 1;
+	    let rhs = match self.binary(p+1)? {
+		Some(v) => v,
+		None => { return Ok(None); }
+	    };
+	    lhs = match op {
+		"|" => lhs | rhs,
+		"^" => lhs ^ rhs,
+		"&" => lhs & rhs,
+		"<<" => lhs.wrapping_shl(rhs as u32),
+		">>" => lhs.wrapping_shr(rhs as u32),
+		"+" => lhs.wrapping_add(rhs),
+		"-" => lhs.wrapping_sub(rhs),
+		"*" => lhs.wrapping_mul(rhs),
+		"/" if rhs != 0 => lhs.wrapping_div(rhs),
+		"%" if rhs != 0 => lhs.wrapping_rem(rhs),
+		_ => { return Ok(None); }
+	    };
+	}
+	Ok(Some(lhs))
+    }
+
+    fn unary(&mut self) -> Result<Option<isize>,AsmErrorKind> {
+	let token = self.tokens.get(self.index).cloned();
+	self.index += 1;
+	match token {
+	    Some(Token::Number(v)) => Ok(Some(v as isize)),
+	    Some(Token::Identifier(name)) => {
+		if let Some(v) = self.symbols.constants.get(&name) {
+		    Ok(Some(*v as isize))
+		} else if let Some(v) = self.symbols.labels.get(&name) {
+		    self.label = true;
+		    Ok(Some((*v as isize).wrapping_add(self.shift)))
+		} else if self.symbols.externs.contains(&name) {
+		    if self.external.as_ref().is_some_and(|e| *e != name) {
+			return Err(AsmErrorKind::InvalidRelocation(name));
+		    }
+		    self.external = Some(name);
+		    Ok(Some(self.binding))
+		} else {
+		    Err(AsmErrorKind::UnknownLabel(name))
+		}
+	    }
+	    Some(Token::Operator("-")) => Ok(self.unary()?.map(|v| v.wrapping_neg())),
+	    Some(Token::Operator("~")) => Ok(self.unary()?.map(|v| !v)),
+	    Some(Token::Operator("(")) => {
+		let v = self.binary(0)?;
+		if self.tokens.get(self.index) != Some(&Token::Operator(")")) {
+		    return Ok(None);
+		}
+		self.index += 1;
+		Ok(v)
+	    }
+	    _ => Ok(None)
+	}
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::time::{Duration,Instant};
+use crate::insn::{EncodeError,InstructionSet};
+use crate::machine::{MachineError,State};
+use crate::program::{DecodedProgram,Program};
+
+// =====================================================
+// Primitives
+// =====================================================
+
+/// Identifies the instructions of a given instruction set from which
+/// the standard workloads are constructed.  Each takes two operands
+/// which, except for the target of a branch, are byte addresses in
+/// memory (all below eight):
+///
+/// * `add a, b` performs `M[a] := M[a] + M[b]`.
+/// * `mov a, b` performs `M[a] := M[b]`.
+/// * `jnz a, t` branches to pc `t` if `M[a] != 0`.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Primitives<'a> {
+    pub add: &'a str,
+    pub mov: &'a str,
+    pub jnz: &'a str
+}
+
+// =====================================================
+// Workload
+// =====================================================
+
+/// A small program, along with the initial contents of memory, which
+/// exercises a particular aspect of an interpreter.  Workloads loop
+/// forever (restarting whenever the pc leaves the program), and are
+/// run for a given number of instructions.  For example:
+///
+/// ```text
+/// let primitives = Primitives{add:"add",mov:"mov",jnz:"jnz"};
+/// for workload in Workload::standard(&isa,&primitives)? {
+///     println!("{}",workload.run(&isa,1_000_000)?);
+/// }
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct Workload {
+    /// A human-readable name for this workload.
+    pub name: String,
+    /// The encoded program.
+    pub program: Vec<u8>,
+    /// The initial contents of memory.
+    pub memory: Vec<u8>
+}
+
+impl Workload {
+    /// A tight arithmetic loop, which adds into an accumulator whilst
+    /// counting down from 255.
+    pub fn arithmetic(isa: &InstructionSet, primitives: &Primitives) -> Result<Self,EncodeError> {
+	let mut program = Program::new(isa);
+	program.push(primitives.add,&[2,3])?;
+	program.push(primitives.add,&[0,1])?;
+	program.push(primitives.jnz,&[0,0])?;
+	Ok(Workload::new("arithmetic",program.bytes(),&[0,0xFF,0,1,0,0,0,0]))
+    }
+    /// A memory copy loop, which rotates four bytes of memory whilst
+    /// counting down from 255.
+    pub fn copy(isa: &InstructionSet, primitives: &Primitives) -> Result<Self,EncodeError> {
+	let mut program = Program::new(isa);
+	program.push(primitives.mov,&[2,4])?;
+	program.push(primitives.mov,&[4,5])?;
+	program.push(primitives.mov,&[5,6])?;
+	program.push(primitives.mov,&[6,7])?;
+	program.push(primitives.mov,&[7,2])?;
+	program.push(primitives.add,&[0,1])?;
+	program.push(primitives.jnz,&[0,0])?;
+	Ok(Workload::new("copy",program.bytes(),&[0,0xFF,0,0,1,2,3,4]))
+    }
+    /// A branch-heavy loop, where most instructions are taken branches
+    /// which jump around the program.
+    pub fn branches(isa: &InstructionSet, primitives: &Primitives) -> Result<Self,EncodeError> {
+	let mut program = Program::new(isa);
+	// Executes pcs 0,3,2,1,5,6
+	program.push(primitives.jnz,&[2,3])?;
+	program.push(primitives.jnz,&[2,5])?;
+	program.push(primitives.jnz,&[2,1])?;
+	program.push(primitives.jnz,&[2,2])?;
+	program.push(primitives.add,&[3,3])?;
+	program.push(primitives.add,&[0,1])?;
+	program.push(primitives.jnz,&[0,0])?;
+	Ok(Workload::new("branches",program.bytes(),&[0,0xFF,1,0,0,0,0,0]))
+    }
+    /// Construct all the standard workloads.
+    pub fn standard(isa: &InstructionSet, primitives: &Primitives) -> Result<Vec<Self>,EncodeError> {
+	Ok(vec![Workload::arithmetic(isa,primitives)?,
+		Workload::copy(isa,primitives)?,
+		Workload::branches(isa,primitives)?])
+    }
+    /// Run this workload (using `State::step()`) for a given number of
+    /// instructions, measuring the time taken.
+    pub fn run(&self, isa: &InstructionSet, instructions: usize) -> Result<Measurement,MachineError> {
+	let program = DecodedProgram::new(isa,&self.program);
+	let mut memory = self.memory.clone();
+	let mut state = State::new(0,&mut memory);
+	let start = Instant::now();
+	for _ in 0..instructions {
+	    if state.pc >= program.len() {
+		state.pc = 0;
+	    }
+	    state.step(&program)?;
+	}
+	let elapsed = start.elapsed();
+	Ok(Measurement{name:self.name.clone(),instructions,elapsed})
+    }
+
+    fn new(name: &str, program: &[u8], memory: &[u8]) -> Self {
+	Workload{name:name.to_string(),program:program.to_vec(),memory:memory.to_vec()}
+    }
+}
+
+// =====================================================
+// Measurement
+// =====================================================
+
+/// The result of running a workload.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Measurement {
+    /// The name of the workload.
+    pub name: String,
+    /// The number of instructions executed.
+    pub instructions: usize,
+    /// The time taken.
+    pub elapsed: Duration
+}
+
+impl Measurement {
+    /// Determine the number of instructions executed per second.
+    pub fn per_second(&self) -> f64 {
+	self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"{}: {:.1}M instructions/s",self.name,self.per_second() / 1e6)
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::io::{self,BufRead,Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::ExitCode;
+use virmin::asm::Assembler;
+use virmin::debug::Debugger;
+use virmin::disasm::Disassembler;
+use virmin::gdb::GdbStub;
+use virmin::insn::InstructionSet;
+use virmin::machine::State;
+use virmin::program::DecodedProgram;
+use virmin::spec;
+
+const USAGE : &str = "\
+usage: virmin asm FILE --isa SPEC [-o OUT]
+       virmin disasm FILE --isa SPEC [--hex]
+       virmin run FILE --isa SPEC [--memory BYTES] [--steps N] [--trace] [--dump]
+       virmin debug FILE --isa SPEC [--memory BYTES] [--tui | --gdb PORT]";
+
+/// Default size (in bytes) of the machine's memory.
+const MEMORY : usize = 256;
+
+// =====================================================
+// Arguments
+// =====================================================
+
+/// The command line arguments following the subcommand, comprising a
+/// single input file and any options.
+struct Args {
+    file: String,
+    options: Vec<(String,Option<String>)>
+}
+
+impl Args {
+    /// Parse the arguments following a subcommand, where options
+    /// which take a value are identified by their name.
+    fn parse(args: &[String], valued: &[&str]) -> Result<Self,String> {
+	let mut file = None;
+	let mut options = Vec::new();
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+	    if valued.contains(&arg.as_str()) {
+		let value = iter.next().ok_or(format!("missing value for {}",arg))?;
+		options.push((arg.clone(),Some(value.clone())));
+	    } else if arg.starts_with('-') {
+		options.push((arg.clone(),None));
+	    } else if file.replace(arg.clone()).is_some() {
+		return Err(format!("unexpected argument {}",arg));
+	    }
+	}
+	let file = file.ok_or("missing input file")?;
+	Ok(Args{file,options})
+    }
+    /// Check whether a given flag was given.
+    fn flag(&self, name: &str) -> bool {
+	self.options.iter().any(|(n,_)| n == name)
+    }
+    /// Get the value of a given option (if given).
+    fn value(&self, name: &str) -> Option<&str> {
+	self.options.iter().rev().find(|(n,_)| n == name).and_then(|(_,v)| v.as_deref())
+    }
+    /// Get the value of a given numeric option, or a default.
+    fn number(&self, name: &str, default: usize) -> Result<usize,String> {
+	match self.value(name) {
+	    Some(v) => v.parse().map_err(|_| format!("invalid value for {} ({})",name,v)),
+	    None => Ok(default)
+	}
+    }
+    /// Check that only the given options were given.
+    fn check(&self, allowed: &[&str]) -> Result<(),String> {
+	match self.options.iter().find(|(n,_)| !allowed.contains(&n.as_str())) {
+	    Some((n,_)) => Err(format!("unknown option {}",n)),
+	    None => Ok(())
+	}
+    }
+    /// Load the instruction set given by the `--isa` option.
+    fn isa(&self) -> Result<InstructionSet<'static>,String> {
+	let path = self.value("--isa").ok_or("missing --isa SPEC")?;
+	spec::load(path).map_err(|e| format!("{}: {}",path,e))
+    }
+}
+
+// =====================================================
+// Subcommands
+// =====================================================
+
+/// Assemble a source file into a binary image.
+fn asm(args: &Args) -> Result<(),String> {
+    args.check(&["--isa","-o"])?;
+    let isa = args.isa()?;
+    let source = read_to_string(&args.file)?;
+    let program = Assembler::new(&isa).file(&args.file).assemble(&source).map_err(|e| e.to_string())?;
+    let out = match args.value("-o") {
+	Some(out) => out.to_string(),
+	None => Path::new(&args.file).with_extension("bin").to_string_lossy().into_owned()
+    };
+    fs::write(&out,program.bytes()).map_err(|e| format!("{}: {}",out,e))
+}
+
+/// Disassemble a binary image.
+fn disasm(args: &Args) -> Result<(),String> {
+    args.check(&["--isa","--hex"])?;
+    let isa = args.isa()?;
+    let image = read(&args.file)?;
+    print!("{}",Disassembler::new(&isa).hex(args.flag("--hex")).listing(&image,0));
+    Ok(())
+}
+
+/// Execute a binary image from pc zero until the pc leaves the
+/// program (or the given number of steps have executed).
+fn run(args: &Args) -> Result<(),String> {
+    args.check(&["--isa","--memory","--steps","--trace","--dump"])?;
+    let isa = args.isa()?;
+    let image = read(&args.file)?;
+    let memory = args.number("--memory",MEMORY)?;
+    let steps = args.number("--steps",usize::MAX)?;
+    let program = DecodedProgram::new(&isa,&image);
+    let disasm = Disassembler::new(&isa).pseudos(false);
+    let mut bytes = vec![0u8;memory];
+    let mut state = State::new(0,&mut bytes);
+    let mut count = 0;
+    while state.pc < program.len() && count < steps {
+	if args.flag("--trace") {
+	    let entry = program.get(state.pc).map_err(|e| format!("pc {}: {}",state.pc,e))?;
+	    println!("{:04x}: {}",entry.offset,disasm.render(entry.insn,entry.operands));
+	}
+	state.step(&program).map_err(|e| e.to_string())?;
+	count += 1;
+    }
+    println!("halted at pc {} after {} steps",state.pc,count);
+    if args.flag("--dump") {
+	for (i,row) in state.data.bytes().chunks(16).enumerate() {
+	    let row : Vec<String> = row.iter().map(|b| format!("{:02x}",b)).collect();
+	    println!("{:04x}: {}",i * 16,row.join(" "));
+	}
+    }
+    Ok(())
+}
+
+/// Debug a binary image interactively, reading commands from stdin
+/// until it is closed (or `quit` is given).  Alternatively, a
+/// terminal user interface is used if requested, or a GDB session is
+/// served on a given port.
+fn debug(args: &Args) -> Result<(),String> {
+    args.check(&["--isa","--memory","--tui","--gdb"])?;
+    let isa = args.isa()?;
+    let image = read(&args.file)?;
+    let memory = args.number("--memory",MEMORY)?;
+    let program = DecodedProgram::new(&isa,&image);
+    let mut debugger = Debugger::new(&program);
+    let mut bytes = vec![0u8;memory];
+    let mut state = State::new(0,&mut bytes);
+    if let Some(port) = args.value("--gdb") {
+	let listener = TcpListener::bind(format!("127.0.0.1:{}",port)).map_err(|e| e.to_string())?;
+	eprintln!("waiting for gdb on port {}",port);
+	let (stream,_) = listener.accept().map_err(|e| e.to_string())?;
+	return GdbStub::new(&program).serve(stream,&mut state).map_err(|e| e.to_string());
+    } else if args.flag("--tui") {
+	#[cfg(feature="tui")]
+	return virmin::tui::Tui::new(debugger).run(&mut state).map_err(|e| e.to_string());
+	#[cfg(not(feature="tui"))]
+	return Err("--tui requires the tui feature".to_string());
+    }
+    println!("{}",debugger.render(0));
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+	print!("(virmin) ");
+	io::stdout().flush().map_err(|e| e.to_string())?;
+	let Some(line) = lines.next() else { break; };
+	let line = line.map_err(|e| e.to_string())?;
+	if matches!(line.trim(),"q"|"quit") {
+	    break;
+	}
+	match debugger.command(&mut state,&line) {
+	    Ok(out) if out.is_empty() => {}
+	    Ok(out) => println!("{}",out),
+	    Err(e) => println!("error: {}",e)
+	}
+    }
+    Ok(())
+}
+
+fn read(file: &str) -> Result<Vec<u8>,String> {
+    fs::read(file).map_err(|e| format!("{}: {}",file,e))
+}
+
+fn read_to_string(file: &str) -> Result<String,String> {
+    fs::read_to_string(file).map_err(|e| format!("{}: {}",file,e))
+}
+
+// =====================================================
+// Main
+// =====================================================
+
+fn main() -> ExitCode {
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|s| s.as_str()) {
+	Some("asm") => Args::parse(&args[1..],&["--isa","-o"]).and_then(|a| asm(&a)),
+	Some("disasm") => Args::parse(&args[1..],&["--isa"]).and_then(|a| disasm(&a)),
+	Some("run") => Args::parse(&args[1..],&["--isa","--memory","--steps"]).and_then(|a| run(&a)),
+	Some("debug") => Args::parse(&args[1..],&["--isa","--memory","--gdb"]).and_then(|a| debug(&a)),
+	_ => Err(USAGE.to_string())
+    };
+    match result {
+	Ok(()) => ExitCode::SUCCESS,
+	Err(e) => {
+	    eprintln!("virmin: {}",e);
+	    ExitCode::FAILURE
+	}
+    }
+}
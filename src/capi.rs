@@ -0,0 +1,346 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{c_char,c_int,c_void,CStr,CString};
+use std::ptr;
+use crate::insn::InstructionSet;
+use crate::machine::{MachineError,OwnedState};
+use crate::program::DecodedProgram;
+use crate::spec;
+
+/// Execution completed the given number of steps.
+pub const VIRMIN_OK : c_int = 0;
+/// Execution stopped because the pc left the program.
+pub const VIRMIN_HALTED : c_int = 1;
+/// Execution stopped because a hook asked it to.
+pub const VIRMIN_HOOK : c_int = 2;
+/// An error occurred (see `virmin_last_error()`).
+pub const VIRMIN_ERROR : c_int = -1;
+
+/// A host callback invoked before the instruction at a given pc
+/// executes.  The machine may be inspected and modified (e.g. to
+/// emulate a system call), and returning non-zero stops execution.
+pub type Hook = extern "C" fn(machine: *mut Machine, pc: usize, user: *mut c_void) -> c_int;
+
+thread_local! {
+    /// The most recent error reported on this thread.
+    static LAST_ERROR : RefCell<CString> = RefCell::new(CString::default());
+}
+
+// =====================================================
+// C Interface
+// =====================================================
+
+/// A machine as seen from C, which owns its memory and executes a
+/// loaded image.  This is part of a stable C interface for embedding
+/// the interpreter in other hosts (e.g. C/C++ simulators), as
+/// declared in `include/virmin.h`.  The library is built with, for
+/// example, `cargo rustc --lib --release --features capi --crate-type
+/// staticlib`.  Functions which can fail return `NULL` or
+/// `VIRMIN_ERROR`, after which `virmin_last_error()` describes the
+/// failure.
+pub struct Machine {
+    isa: &'static InstructionSet<'static>,
+    program: DecodedProgram<'static>,
+    state: OwnedState,
+    /// Host callbacks indexed by the pc they are attached to.
+    hooks: BTreeMap<usize,(Hook,*mut c_void)>
+}
+
+/// Get a description of the last error which occurred on this
+/// thread.  The string remains valid until the next error occurs.
+#[no_mangle]
+pub extern "C" fn virmin_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+/// Load an instruction set from a TOML or JSON specification file.
+///
+/// # Safety
+///
+/// The path must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_isa_load(path: *const c_char) -> *mut InstructionSet<'static> {
+    match string(path) {
+	Some(path) => isa(spec::load(path).map_err(|e| format!("{}: {}",path,e))),
+	None => isa(Err("invalid path".to_string()))
+    }
+}
+
+/// Construct an instruction set from a TOML specification.
+///
+/// # Safety
+///
+/// The text must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_isa_from_toml(text: *const c_char) -> *mut InstructionSet<'static> {
+    match string(text) {
+	Some(text) => isa(spec::from_toml(text).map_err(|e| e.to_string())),
+	None => isa(Err("invalid specification".to_string()))
+    }
+}
+
+/// Construct an instruction set from a JSON specification.
+///
+/// # Safety
+///
+/// The text must be a valid nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_isa_from_json(text: *const c_char) -> *mut InstructionSet<'static> {
+    match string(text) {
+	Some(text) => isa(spec::from_json(text).map_err(|e| e.to_string())),
+	None => isa(Err("invalid specification".to_string()))
+    }
+}
+
+/// Free an instruction set.
+///
+/// # Safety
+///
+/// The instruction set must have been returned from one of the
+/// functions above (or be `NULL`), and no machine using it may remain.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_isa_free(isa: *mut InstructionSet<'static>) {
+    if !isa.is_null() {
+	drop(Box::from_raw(isa));
+    }
+}
+
+/// Create a machine for a given instruction set with a given amount
+/// of memory (in bytes), all initially zero.  Initially, the machine
+/// holds an empty image.
+///
+/// # Safety
+///
+/// The instruction set must be valid, and outlive the machine.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_new(isa: *const InstructionSet<'static>, memory: usize) -> *mut Machine {
+    let Some(isa) = isa.as_ref() else {
+	error("invalid instruction set".to_string());
+	return ptr::null_mut();
+    };
+    let program = DecodedProgram::new(isa,&[]);
+    Box::into_raw(Box::new(Machine{isa,program,state:OwnedState::new(memory),hooks:BTreeMap::new()}))
+}
+
+/// Free a machine.
+///
+/// # Safety
+///
+/// The machine must have been returned from `virmin_machine_new()`
+/// (or be `NULL`).
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_free(machine: *mut Machine) {
+    if !machine.is_null() {
+	drop(Box::from_raw(machine));
+    }
+}
+
+/// Load a binary image into a machine, which is decoded straight away
+/// and resets the pc to zero.
+///
+/// # Safety
+///
+/// The machine must be valid, and the image must hold the given
+/// number of bytes.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_load_image(machine: *mut Machine, image: *const u8, length: usize) -> c_int {
+    let Some(m) = machine.as_mut() else { return VIRMIN_ERROR; };
+    let image = if length == 0 { &[] } else { std::slice::from_raw_parts(image,length) };
+    m.program = DecodedProgram::new(m.isa,image);
+    m.state.pc = 0;
+    VIRMIN_OK
+}
+
+/// Execute at most a given number of instructions, writing the number
+/// executed (if requested).  Execution stops early if the pc leaves
+/// the program, or a hook asks to stop.
+///
+/// # Safety
+///
+/// The machine must be valid, and `executed` must be valid or `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_run(machine: *mut Machine, steps: usize, executed: *mut usize) -> c_int {
+    if machine.is_null() { return VIRMIN_ERROR; }
+    let mut count = 0;
+    let status = loop {
+	let m = &mut *machine;
+	if count >= steps {
+	    break VIRMIN_OK;
+	} else if m.state.pc >= m.program.len() {
+	    break VIRMIN_HALTED;
+	}
+	// Hooks are called without holding a reference to the machine
+	let pc = m.state.pc;
+	if let Some(&(hook,user)) = m.hooks.get(&pc) {
+	    if hook(machine,pc,user) != 0 {
+		break VIRMIN_HOOK;
+	    }
+	}
+	// Execute until the next hooked pc (or the limit)
+	let m = &mut *machine;
+	let (program,hooks) = (&m.program,&m.hooks);
+	let result = m.state.with_state(|s| {
+	    while count < steps && s.pc < program.len() {
+		s.try_step(program)?;
+		count += 1;
+		if hooks.contains_key(&s.pc) { break; }
+	    }
+	    Ok::<(),MachineError>(())
+	});
+	if let Err(e) = result {
+	    error(e.to_string());
+	    break VIRMIN_ERROR;
+	}
+    };
+    if let Some(executed) = executed.as_mut() {
+	*executed = count;
+    }
+    status
+}
+
+/// Get the pc of a machine.
+///
+/// # Safety
+///
+/// The machine must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_pc(machine: *const Machine) -> usize {
+    (*machine).state.pc
+}
+
+/// Set the pc of a machine.
+///
+/// # Safety
+///
+/// The machine must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_set_pc(machine: *mut Machine, pc: usize) {
+    (*machine).state.pc = pc;
+}
+
+/// Read a given register (see `State::read_register()`), writing its
+/// value.
+///
+/// # Safety
+///
+/// The machine and value must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_read_register(machine: *mut Machine, register: usize, value: *mut u64) -> c_int {
+    let m = &mut *machine;
+    if !m.state.is_register(register) {
+	error(format!("invalid register {}",register));
+	return VIRMIN_ERROR;
+    }
+    *value = m.state.with_state(|s| s.read_register(register));
+    VIRMIN_OK
+}
+
+/// Write a given register (see `State::write_register()`).
+///
+/// # Safety
+///
+/// The machine must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_write_register(machine: *mut Machine, register: usize, value: u64) -> c_int {
+    let m = &mut *machine;
+    if !m.state.is_register(register) {
+	error(format!("invalid register {}",register));
+	return VIRMIN_ERROR;
+    }
+    m.state.with_state(|s| s.write_register(register,value));
+    VIRMIN_OK
+}
+
+/// Read some bytes of a machine's memory into a given buffer.
+///
+/// # Safety
+///
+/// The machine must be valid, and the buffer must hold the given
+/// number of bytes.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_read(machine: *const Machine, address: usize, buffer: *mut u8, length: usize) -> c_int {
+    let m = &*machine;
+    match memory(&m.state.memory,address,length) {
+	Some(range) => {
+	    ptr::copy_nonoverlapping(m.state.memory[range].as_ptr(),buffer,length);
+	    VIRMIN_OK
+	}
+	None => VIRMIN_ERROR
+    }
+}
+
+/// Write some bytes from a given buffer into a machine's memory.
+///
+/// # Safety
+///
+/// The machine must be valid, and the buffer must hold the given
+/// number of bytes.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_write(machine: *mut Machine, address: usize, buffer: *const u8, length: usize) -> c_int {
+    let m = &mut *machine;
+    match memory(&m.state.memory,address,length) {
+	Some(range) => {
+	    ptr::copy_nonoverlapping(buffer,m.state.memory[range].as_mut_ptr(),length);
+	    VIRMIN_OK
+	}
+	None => VIRMIN_ERROR
+    }
+}
+
+/// Attach a hook to a given pc, replacing any already attached.  The
+/// user pointer is passed to the hook unchanged.
+///
+/// # Safety
+///
+/// The machine must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_add_hook(machine: *mut Machine, pc: usize, hook: Hook, user: *mut c_void) {
+    (*machine).hooks.insert(pc,(hook,user));
+}
+
+/// Remove the hook attached to a given pc, returning whether there
+/// was one.
+///
+/// # Safety
+///
+/// The machine must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn virmin_machine_remove_hook(machine: *mut Machine, pc: usize) -> bool {
+    (*machine).hooks.remove(&pc).is_some()
+}
+
+/// Record an error for `virmin_last_error()`.
+fn error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+/// Convert a given C string, returning nothing if it is `NULL` or not
+/// valid UTF8.
+unsafe fn string<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() { return None; }
+    CStr::from_ptr(text).to_str().ok()
+}
+
+/// Box a constructed instruction set, or record the error.
+fn isa(result: Result<InstructionSet<'static>,String>) -> *mut InstructionSet<'static> {
+    match result {
+	Ok(isa) => Box::into_raw(Box::new(isa)),
+	Err(e) => {
+	    error(e);
+	    ptr::null_mut()
+	}
+    }
+}
+
+/// Determine the range of memory accessed, recording an error if it
+/// is out of bounds.
+fn memory(memory: &[u8], address: usize, length: usize) -> Option<std::ops::Range<usize>> {
+    match address.checked_add(length) {
+	Some(end) if end <= memory.len() => Some(address..end),
+	_ => {
+	    error(format!("memory {:#x}..{:#x} out of bounds",address,address.saturating_add(length)));
+	    None
+	}
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt::Write;
+use crate::disasm::Disassembler;
+use crate::machine::{MicroCode,LR};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Flow
+// =====================================================
+
+/// Describes where control may go after a single instruction, as
+/// determined from its microcode alone.
+#[derive(Clone,Debug,Default,PartialEq)]
+pub struct Flow {
+    /// Control may continue with the following instruction.
+    pub fallthrough: bool,
+    /// The (constant) targets of any branches.
+    pub targets: Vec<usize>,
+    /// The instruction writes the link register as it branches (i.e.
+    /// it is a subroutine call).
+    pub call: bool,
+    /// The instruction may branch to the link register (i.e. return
+    /// from a subroutine).
+    pub returns: bool,
+    /// The instruction may branch to a target computed from data,
+    /// which is not known statically.
+    pub indirect: bool
+}
+
+impl Flow {
+    /// Determine the flow of the instruction at a given pc with given
+    /// microcode.  Every path through the microcode (i.e. taking or
+    /// not taking each skip) is considered.
+    pub fn of(pc: usize, microcode: &[MicroCode]) -> Self {
+	let n = microcode.len();
+	let mut flow = Flow::default();
+	// Whether each microcode is reached without / after a branch
+	let mut reached = vec![[false;2];n+1];
+	reached[0][0] = true;
+	let mut links = false;
+	for (i,code) in microcode.iter().enumerate() {
+	    for branched in 0..2 {
+		if !reached[i][branched] { continue; }
+		match *code {
+		    MicroCode::SkipIfZero(_,_,k)|MicroCode::RegSkipIfZero(_,k) => {
+			reached[i+1][branched] = true;
+			reached[(i+1+k).min(n)][branched] = true;
+		    }
+		    MicroCode::Skip(k) => { reached[(i+1+k).min(n)][branched] = true; }
+		    c if c.is_branch() => {
+			match c {
+			    MicroCode::Goto(t) => flow.targets.push(t),
+			    MicroCode::Jump(k) => flow.targets.push(pc.wrapping_add_signed(k)),
+			    MicroCode::RegLoad(_,t) => flow.targets.push(t as usize),
+			    MicroCode::RegCopy(_,LR) => { flow.returns = true; }
+			    _ => { flow.indirect = true; }
+			}
+			reached[i+1][1] = true;
+		    }
+		    c => {
+			if let MicroCode::RegAdd(LR,_)|MicroCode::RegAlu(_,LR,_)|MicroCode::RegCopy(LR,_)|MicroCode::RegFetch(LR,..)|MicroCode::RegLoad(LR,_) = c {
+			    links = true;
+			}
+			reached[i+1][branched] = true;
+		    }
+		}
+	    }
+	}
+	flow.fallthrough = reached[n][0];
+	flow.call = links && (!flow.targets.is_empty() || flow.indirect);
+	flow.targets.sort_unstable();
+	flow.targets.dedup();
+	flow
+    }
+}
+
+// =====================================================
+// Control-Flow Graph
+// =====================================================
+
+/// Identifies the kind of an edge between blocks.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum EdgeKind {
+    /// Control continues with the following instruction.
+    Fallthrough,
+    /// Control is transferred by a (possibly conditional) branch.
+    Branch,
+    /// Control is transferred to a subroutine, which returns to the
+    /// following instruction (see `Fallthrough`).
+    Call
+}
+
+/// An edge from one block to another or, when `to` is `None`, out of
+/// the program (i.e. to a pc beyond its end).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: Option<usize>,
+    pub kind: EdgeKind
+}
+
+/// A maximal sequence of instructions which is only entered at its
+/// first instruction and only left after its last.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Block {
+    /// The pcs of the instructions in this block.
+    pub pcs: std::ops::Range<usize>,
+    /// The block ends with a return from a subroutine.
+    pub returns: bool,
+    /// The block ends with a branch whose target is not known.
+    pub indirect: bool,
+    /// The block ends with an instruction which could not be decoded.
+    pub invalid: bool
+}
+
+/// The control-flow graph of a decoded program, covering every
+/// instruction (whether reachable or not).  This is constructed from
+/// the microcode of each instruction, such that branches with
+/// constant targets give edges, whilst those computed from data (other
+/// than returns) are marked as indirect.  For example:
+///
+/// ```text
+/// let cfg = Cfg::new(&program);
+/// for edge in cfg.successors(cfg.block_of(pc).unwrap()) {
+///     ...
+/// }
+/// std::fs::write("cfg.dot",cfg.to_dot(&program))?;
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    /// Every edge, ordered by the block they leave.
+    pub edges: Vec<Edge>
+}
+
+impl Cfg {
+    /// Construct the control-flow graph of a given program.
+    pub fn new(program: &DecodedProgram) -> Self {
+	let n = program.len();
+	let flows : Vec<Option<Flow>> = (0..n).map(|pc| {
+	    let entry = program.get(pc).ok()?;
+	    let microcode : Vec<MicroCode> = entry.microcode.iter().map(|t| t.code()).collect();
+	    Some(Flow::of(pc,&microcode))
+	}).collect();
+	// Determine the first instruction of each block
+	let mut leaders = vec![false;n+1];
+	leaders[0] = true;
+	leaders[n] = true;
+	for (pc,flow) in flows.iter().enumerate() {
+	    match flow {
+		Some(f) if f.fallthrough && f.targets.is_empty() && !f.returns && !f.indirect => {}
+		Some(f) => {
+		    leaders[pc+1] = true;
+		    for t in f.targets.iter().filter(|t| **t < n) { leaders[*t] = true; }
+		}
+		None => { leaders[pc+1] = true; }
+	    }
+	}
+	let starts : Vec<usize> = (0..n).filter(|pc| leaders[*pc]).collect();
+	let mut blocks = Vec::new();
+	let mut edges = Vec::new();
+	let block_of = |pc: usize| if pc < n { Some(starts.partition_point(|s| *s <= pc) - 1) } else { None };
+	for (b,start) in starts.iter().enumerate() {
+	    let end = starts.get(b+1).copied().unwrap_or(n);
+	    let last = &flows[end-1];
+	    let Some(flow) = last else {
+		blocks.push(Block{pcs:*start..end,returns:false,indirect:false,invalid:true});
+		continue;
+	    };
+	    let kind = if flow.call { EdgeKind::Call } else { EdgeKind::Branch };
+	    for t in &flow.targets {
+		edges.push(Edge{from:b,to:block_of(*t),kind});
+	    }
+	    if flow.fallthrough || flow.call {
+		edges.push(Edge{from:b,to:block_of(end),kind:EdgeKind::Fallthrough});
+	    }
+	    blocks.push(Block{pcs:*start..end,returns:flow.returns,indirect:flow.indirect,invalid:false});
+	}
+	Cfg{blocks,edges}
+    }
+    /// Determine the block containing a given pc (if any).
+    pub fn block_of(&self, pc: usize) -> Option<usize> {
+	let b = self.blocks.partition_point(|b| b.pcs.start <= pc).checked_sub(1)?;
+	self.blocks[b].pcs.contains(&pc).then_some(b)
+    }
+    /// Get the edges leaving a given block.
+    pub fn successors(&self, block: usize) -> impl Iterator<Item=&Edge> {
+	self.edges.iter().filter(move |e| e.from == block)
+    }
+    /// Get the edges entering a given block.
+    pub fn predecessors(&self, block: usize) -> impl Iterator<Item=&Edge> {
+	self.edges.iter().filter(move |e| e.to == Some(block))
+    }
+    /// Determine which blocks are reachable from the first.
+    pub fn reachable(&self) -> Vec<bool> {
+	let mut reached = vec![false;self.blocks.len()];
+	let mut worklist = if self.blocks.is_empty() { vec![] } else { vec![0] };
+	while let Some(b) = worklist.pop() {
+	    if reached[b] { continue; }
+	    reached[b] = true;
+	    worklist.extend(self.successors(b).filter_map(|e| e.to));
+	}
+	reached
+    }
+    /// Render this graph in Graphviz DOT format, where each block
+    /// shows its instructions (disassembled from a given program).
+    pub fn to_dot(&self, program: &DecodedProgram) -> String {
+	let disasm = Disassembler::new(program.isa());
+	let mut out = String::new();
+	out.push_str("digraph cfg {\n  node [shape=box,fontname=monospace];\n");
+	for (b,block) in self.blocks.iter().enumerate() {
+	    let mut label = String::new();
+	    for pc in block.pcs.clone() {
+		match program.get(pc) {
+		    Ok(e) => write!(label,"{:04x}: {}\\l",e.offset,escape(&disasm.render(e.insn,e.operands))).unwrap(),
+		    Err(_) => label.push_str("(invalid)\\l")
+		}
+	    }
+	    if block.returns { label.push_str("(return)\\l"); }
+	    if block.indirect { label.push_str("(indirect)\\l"); }
+	    writeln!(out,"  b{} [label=\"{}\"];",b,label).unwrap();
+	}
+	if self.edges.iter().any(|e| e.to.is_none()) {
+	    out.push_str("  exit [shape=oval];\n");
+	}
+	for e in &self.edges {
+	    let to = e.to.map(|b| format!("b{}",b)).unwrap_or("exit".to_string());
+	    match e.kind {
+		EdgeKind::Fallthrough => writeln!(out,"  b{} -> {};",e.from,to),
+		EdgeKind::Branch => writeln!(out,"  b{} -> {} [color=blue];",e.from,to),
+		EdgeKind::Call => writeln!(out,"  b{} -> {} [style=dashed,label=\"call\"];",e.from,to)
+	    }.unwrap();
+	}
+	out.push_str("}\n");
+	out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\',"\\\\").replace('"',"\\\"")
+}
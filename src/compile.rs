@@ -0,0 +1,237 @@
+use crate::insn::{AbstractMicroCode,DecodeError,Instruction,InstructionSet,Operand,Predicate,sign_extend};
+use crate::domain::Bits;
+use crate::machine::{self,MicroCode,State,Threaded,Width};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Compiled Instructions
+// =====================================================
+
+/// An instruction compiled into a closure which, given its operands,
+/// executes it in a given state (i.e. as for `State::execute_all()`
+/// on its concrete microcode).
+pub type Compiled = Box<dyn Fn(&mut State,&[usize])>;
+
+/// An operand expression compiled into a closure over the pc and
+/// operands.
+type Expr = Box<dyn Fn(usize,&[usize]) -> usize>;
+
+/// A predicate compiled into a closure over the state, the pc and
+/// operands.
+type Test = Box<dyn Fn(&State,usize,&[usize]) -> bool>;
+
+/// A microcode instruction compiled into a closure over the state,
+/// the pc of the enclosing instruction, its operands and the pc
+/// which will follow it (which is updated by any branch).
+type Code = Box<dyn Fn(&mut State,usize,&[usize],&mut usize)>;
+
+/// Compile the semantics of a given instruction into a closure.  Thus,
+/// operand expressions are translated into closures (with constant
+/// subexpressions folded), and the handler for each microcode is
+/// selected, once rather than every time the instruction executes.
+pub fn compile(insn: &Instruction) -> Compiled {
+    let codes = block(insn.semantic());
+    Box::new(move |state,operands| {
+	let pc = state.pc;
+	let mut next = pc + 1;
+	for c in &codes {
+	    c(state,pc,operands,&mut next);
+	}
+	state.pc = next;
+	state.clear_temps();
+    })
+}
+
+/// The instructions of an instruction set compiled (once) into
+/// closures, which can then execute a decoded program.  Since operand
+/// expressions are evaluated on each execution, this is slower than
+/// `State::step()` (which uses the microcode prepared when a program
+/// is decoded), taking around twice as long per instruction in the
+/// `throughput` example.  However, it requires only the operands of
+/// each instruction.  For example:
+///
+/// ```text
+/// let compiled = CompiledSet::new(&isa);
+/// while state.pc < program.len() {
+///     compiled.step(&mut state,&program)?;
+/// }
+/// ```
+pub struct CompiledSet<'a> {
+    isa: &'a InstructionSet<'a>,
+    /// Compiled instructions, indexed as for the instruction set.
+    insns: Vec<Compiled>
+}
+
+impl<'a> CompiledSet<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	CompiledSet{isa,insns:isa.iter().map(compile).collect()}
+    }
+    /// Get the instruction set which was compiled.
+    pub fn isa(&self) -> &'a InstructionSet<'a> {
+	self.isa
+    }
+    /// Get the compiled instruction with a given index.
+    pub fn get(&self, index: usize) -> &Compiled {
+	&self.insns[index]
+    }
+    /// Fetch, decode and execute the instruction identified by the
+    /// current pc in a given program (as for `State::step()`).
+    pub fn step(&self, state: &mut State, program: &DecodedProgram) -> Result<(),DecodeError> {
+	let entry = program.get(state.pc)?;
+	state.check_feature(self.isa.instruction(entry.insn))?;
+	self.insns[entry.insn](state,entry.operands);
+	Ok(())
+    }
+}
+
+// =====================================================
+// Microcode
+// =====================================================
+
+fn block(codes: &[AbstractMicroCode]) -> Vec<Code> {
+    codes.iter().map(microcode).collect()
+}
+
+fn microcode(code: &AbstractMicroCode) -> Code {
+    let (x,y) = match code {
+	AbstractMicroCode::Add(x,y,_)|AbstractMicroCode::Alu(_,x,y,_)|AbstractMicroCode::Copy(x,y,_)|AbstractMicroCode::Load(x,y,_)
+	    |AbstractMicroCode::RegAdd(x,y)|AbstractMicroCode::RegAlu(_,x,y)|AbstractMicroCode::RegCopy(x,y)|AbstractMicroCode::RegFetch(x,y,_)
+	    |AbstractMicroCode::RegFetchIndirect(x,y,_)|AbstractMicroCode::RegLoad(x,y)|AbstractMicroCode::RegStore(x,y,_)
+	    |AbstractMicroCode::RegStoreIndirect(x,y,_) => (operand(x),operand(y)),
+	AbstractMicroCode::Goto(x)|AbstractMicroCode::Jump(x) => (operand(x),operand(&Operand::Const(0))),
+	AbstractMicroCode::If(p,t,e) => {
+	    let (p,t,e) = (predicate(p),block(t),block(e));
+	    return Box::new(move |state,pc,operands,next| {
+		let branch = if p(state,pc,operands) { &t } else { &e };
+		for c in branch {
+		    c(state,pc,operands,next);
+		}
+	    });
+	}
+    };
+    // Select the handler once, using a representative microcode.
+    let handler = Threaded::new(concrete(code,0,0)).handler();
+    let code = code.clone();
+    Box::new(move |state,pc,operands,next| {
+	let code = concrete(&code,x(pc,operands),y(pc,operands));
+	state.pc = pc;
+	handler(state,code);
+	if code.is_branch() {
+	    *next = state.pc;
+	}
+    })
+}
+
+/// Construct the concrete microcode corresponding to a given abstract
+/// microcode (other than `If`), whose operands evaluated to `x` and
+/// `y`.
+fn concrete(code: &AbstractMicroCode, x: usize, y: usize) -> MicroCode {
+    match code {
+	AbstractMicroCode::Add(_,_,w) => MicroCode::Add(x,y,*w),
+	AbstractMicroCode::Alu(op,_,_,w) => MicroCode::Alu(*op,x,y,*w),
+	AbstractMicroCode::Copy(_,_,w) => MicroCode::Copy(x,y,*w),
+	AbstractMicroCode::Goto(_) => MicroCode::Goto(x),
+	AbstractMicroCode::Jump(_) => MicroCode::Jump(x as isize),
+	AbstractMicroCode::Load(_,_,w) => MicroCode::Load(x,y as u64,*w),
+	AbstractMicroCode::RegAdd(..) => MicroCode::RegAdd(x,y),
+	AbstractMicroCode::RegAlu(op,..) => MicroCode::RegAlu(*op,x,y),
+	AbstractMicroCode::RegCopy(..) => MicroCode::RegCopy(x,y),
+	AbstractMicroCode::RegFetch(_,_,w) => MicroCode::RegFetch(x,y,*w),
+	AbstractMicroCode::RegFetchIndirect(_,_,w) => MicroCode::RegFetchIndirect(x,y,*w),
+	AbstractMicroCode::RegLoad(..) => MicroCode::RegLoad(x,y as u64),
+	AbstractMicroCode::RegStore(_,_,w) => MicroCode::RegStore(x,y,*w),
+	AbstractMicroCode::RegStoreIndirect(_,_,w) => MicroCode::RegStoreIndirect(x,y,*w),
+	AbstractMicroCode::If(..) => unreachable!()
+    }
+}
+
+// =====================================================
+// Predicates
+// =====================================================
+
+fn predicate(p: &Predicate) -> Test {
+    match p {
+	Predicate::Eq(x,y) => {
+	    let (x,y) = (operand(x),operand(y));
+	    Box::new(move |_,pc,ops| x(pc,ops) == y(pc,ops))
+	}
+	Predicate::Ne(x,y) => {
+	    let (x,y) = (operand(x),operand(y));
+	    Box::new(move |_,pc,ops| x(pc,ops) != y(pc,ops))
+	}
+	Predicate::Lt(x,y) => {
+	    let (x,y) = (operand(x),operand(y));
+	    Box::new(move |_,pc,ops| x(pc,ops) < y(pc,ops))
+	}
+	Predicate::Zero(x,w) => {
+	    let (x,w) : (Expr,Width) = (operand(x),*w);
+	    Box::new(move |state,pc,ops| machine::read(&state.data,x(pc,ops),w) == 0)
+	}
+	Predicate::RegZero(r) => {
+	    let r = operand(r);
+	    Box::new(move |state,pc,ops| state.read_register(r(pc,ops)) == 0)
+	}
+	Predicate::Not(p) => {
+	    let p = predicate(p);
+	    Box::new(move |state,pc,ops| !p(state,pc,ops))
+	}
+    }
+}
+
+// =====================================================
+// Operands
+// =====================================================
+
+/// Compile an operand expression, following `Operand::evaluate()`.
+fn operand(o: &Operand) -> Expr {
+    if o.is_constant() {
+	let c = o.as_usize(&[]);
+	return Box::new(move |_,_| c);
+    }
+    match o {
+	Operand::Var(v) => {
+	    let v = *v;
+	    Box::new(move |_,ops| ops[v])
+	}
+	Operand::Add(l,r) => {
+	    let (l,r) = (operand(l),operand(r));
+	    Box::new(move |pc,ops| l(pc,ops).wrapping_add(r(pc,ops)))
+	}
+	Operand::Mul(l,r) => {
+	    let (l,r) = (operand(l),operand(r));
+	    Box::new(move |pc,ops| l(pc,ops).wrapping_mul(r(pc,ops)))
+	}
+	Operand::Shl(l,r) => {
+	    let (l,r) = (operand(l),operand(r));
+	    Box::new(move |pc,ops| {
+		let r = u32::try_from(r(pc,ops)).unwrap_or(u32::MAX);
+		l(pc,ops).checked_shl(r).unwrap_or(0)
+	    })
+	}
+	Operand::RegSlot(r,w) => {
+	    let (r,n) = (operand(r),w.bytes());
+	    Box::new(move |pc,ops| r(pc,ops).wrapping_mul(n))
+	}
+	Operand::PcRel(o) => {
+	    let o = operand(o);
+	    Box::new(move |pc,ops| pc.wrapping_add(o(pc,ops)))
+	}
+	Operand::SExt(_,0) => {
+	    Box::new(|_,_| 0)
+	}
+	Operand::SExt(o,n) => {
+	    let (o,n) = (operand(o),Bits::from(*n));
+	    Box::new(move |pc,ops| sign_extend(o(pc,ops),n))
+	}
+	Operand::Bits(o,hi,lo) if hi >= lo => {
+	    let (o,n,lo) = (operand(o),(hi - lo) as u32 + 1,*lo as u32);
+	    let mask = usize::MAX.checked_shr(usize::BITS.saturating_sub(n)).unwrap_or(0);
+	    Box::new(move |pc,ops| o(pc,ops).checked_shr(lo).unwrap_or(0) & mask)
+	}
+	Operand::Bits(..) => {
+	    Box::new(|_,_| 0)
+	}
+	// Remaining operands are always constant
+	_ => unreachable!()
+    }
+}
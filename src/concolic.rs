@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap,BTreeSet,HashSet,VecDeque};
+use std::fmt;
+use std::ops::Range;
+use crate::machine::{self,MachineError,MicroCode,RegisterFile,State,Width,LR,TEMPS};
+use crate::program::DecodedProgram;
+
+/// Maximum number of instructions executed for each input (by
+/// default).
+pub const FUEL : usize = 10_000;
+
+/// Maximum number of candidate inputs tried when attempting to flip a
+/// given branch (by default).
+pub const ATTEMPTS : usize = 1024;
+
+// =====================================================
+// Branches
+// =====================================================
+
+/// Identifies the outcome of a conditional skip within the microcode
+/// of the instruction at a given pc.  The outcome is whether the
+/// tested value was zero (i.e. whether the skip was taken).
+#[derive(Clone,Copy,Debug,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Outcome {
+    pub pc: usize,
+    /// Index of the microcode within the instruction.
+    pub index: usize,
+    pub taken: bool
+}
+
+impl Outcome {
+    /// Get the opposite outcome of the same branch.
+    pub fn flip(&self) -> Outcome {
+	Outcome{taken:!self.taken,..*self}
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let taken = if self.taken { "taken" } else { "not taken" };
+	write!(f,"{:#x}#{} {}",self.pc,self.index,taken)
+    }
+}
+
+/// A branch executed whose outcome depended on the input, along with
+/// the input bytes (i.e. offsets within the input) it depended upon.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Branch {
+    pub outcome: Outcome,
+    pub inputs: Vec<usize>
+}
+
+/// The result of executing a program on a given input.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Execution {
+    /// Branches executed which depended on the input, in order.
+    pub branches: Vec<Branch>,
+    /// Number of instructions executed.
+    pub steps: usize,
+    /// The fault which ended execution (if any).
+    pub fault: Option<MachineError>
+}
+
+// =====================================================
+// Taint
+// =====================================================
+
+/// Records, for each byte of memory and each register, the input
+/// bytes on which its value (may) depend.  A value computed from
+/// others (e.g. by `Add` or `Alu`) is taken to depend on the union of
+/// its sources, which over-approximates some operations (e.g. `x ^ x`
+/// depends on nothing).
+struct Taint {
+    memory: Vec<BTreeSet<usize>>,
+    registers: BTreeMap<usize,BTreeSet<usize>>
+}
+
+impl Taint {
+    fn new(memory: usize, input: &Range<usize>) -> Self {
+	let mut taint = Taint{memory:vec![BTreeSet::new();memory],registers:BTreeMap::new()};
+	for address in input.clone() {
+	    taint.memory[address].insert(address - input.start);
+	}
+	taint
+    }
+    /// Get the input bytes on which a location (of a given width)
+    /// depends.
+    fn location(&self, x: usize, w: Width) -> BTreeSet<usize> {
+	self.memory[x..x+w.bytes()].iter().flatten().copied().collect()
+    }
+    fn register(&self, r: usize) -> BTreeSet<usize> {
+	self.registers.get(&r).cloned().unwrap_or_default()
+    }
+    fn set_location(&mut self, x: usize, w: Width, inputs: &BTreeSet<usize>) {
+	for byte in &mut self.memory[x..x+w.bytes()] {
+	    byte.clone_from(inputs);
+	}
+    }
+    fn set_register(&mut self, r: usize, inputs: BTreeSet<usize>) {
+	if inputs.is_empty() {
+	    self.registers.remove(&r);
+	} else {
+	    self.registers.insert(r,inputs);
+	}
+    }
+    /// Propagate the dependencies of a given microcode (which is about
+    /// to execute on a given state), returning the branch it
+    /// represents if its outcome depends on the input.
+    fn apply(&mut self, state: &State, pc: usize, index: usize, code: MicroCode) -> Option<Branch> {
+	let branch = |inputs: BTreeSet<usize>, taken: bool| {
+	    (!inputs.is_empty()).then(|| Branch{outcome:Outcome{pc,index,taken},inputs:inputs.into_iter().collect()})
+	};
+	match code {
+	    MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w) => {
+		let mut inputs = self.location(x,w);
+		inputs.extend(self.location(y,w));
+		self.set_location(x,w,&inputs);
+	    }
+	    MicroCode::Copy(x,y,w) => {
+		let bytes = self.memory[y..y+w.bytes()].to_vec();
+		self.memory[x..x+w.bytes()].clone_from_slice(&bytes);
+	    }
+	    MicroCode::Load(x,_,w) => self.set_location(x,w,&BTreeSet::new()),
+	    MicroCode::RegAdd(r,s)|MicroCode::RegAlu(_,r,s) => {
+		let mut inputs = self.register(r);
+		inputs.extend(self.register(s));
+		self.set_register(r,inputs);
+	    }
+	    MicroCode::RegCopy(r,s) => self.set_register(r,self.register(s)),
+	    MicroCode::RegFetch(r,x,w) => self.set_register(r,self.location(x,w)),
+	    MicroCode::RegFetchIndirect(r,s,w) => {
+		// The value also depends on the address it was read from
+		let mut inputs = self.register(s);
+		for a in indirect(state,s,w) { inputs.extend(self.memory[a].iter().copied()); }
+		self.set_register(r,inputs);
+	    }
+	    MicroCode::RegLoad(r,_) => self.set_register(r,BTreeSet::new()),
+	    MicroCode::RegStore(x,r,w) => self.set_location(x,w,&self.register(r)),
+	    MicroCode::RegStoreIndirect(s,r,w) => {
+		let inputs = self.register(r);
+		for a in indirect(state,s,w) { self.memory[a].clone_from(&inputs); }
+	    }
+	    MicroCode::RegSkipIfZero(r,_) => {
+		return branch(self.register(r),state.read_register(r) == 0);
+	    }
+	    MicroCode::SkipIfZero(x,w,_) => {
+		return branch(self.location(x,w),machine::read(&state.data,x,w) == 0);
+	    }
+	    MicroCode::Goto(_)|MicroCode::Jump(_)|MicroCode::Skip(_) => {}
+	}
+	None
+    }
+    /// Clear the scratch registers, as happens after each machine
+    /// instruction.
+    fn clear_temps(&mut self) {
+	self.registers.retain(|r,_| !(LR - TEMPS..LR).contains(r));
+    }
+}
+
+/// Determine the addresses of the bytes accessed indirectly via a
+/// given register (wrapping around memory, as for
+/// `Memory::read_wrapping()`).
+fn indirect(state: &State, r: usize, w: Width) -> Vec<usize> {
+    let n = state.data.len();
+    let address = state.read_register(r) as usize;
+    if n == 0 { Vec::new() } else { (0..w.bytes()).map(|i| (address % n + i) % n).collect() }
+}
+
+// =====================================================
+// Paths
+// =====================================================
+
+/// An input which covered at least one branch outcome not covered by
+/// an earlier input, along with the outcomes of every input-dependent
+/// branch along its path.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Path {
+    pub input: Vec<u8>,
+    pub outcomes: Vec<Outcome>,
+    pub fault: Option<MachineError>
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let bytes : Vec<String> = self.input.iter().map(|b| format!("{:02x}",b)).collect();
+	write!(f,"input [{}] ({} branches)",bytes.join(" "),self.outcomes.len())?;
+	if let Some(e) = &self.fault {
+	    write!(f," faults: {}",e)?;
+	}
+	Ok(())
+    }
+}
+
+/// The result of exploring a program.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Exploration {
+    /// The paths discovered, in the order they were found.
+    pub paths: Vec<Path>,
+    /// Every branch outcome covered.
+    pub covered: BTreeSet<Outcome>,
+    /// Branch outcomes which were attempted, but for which no input
+    /// was found (e.g. because they are infeasible).
+    pub unsolved: BTreeSet<Outcome>
+}
+
+impl fmt::Display for Exploration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for (i,p) in self.paths.iter().enumerate() {
+	    writeln!(f,"path {}: {}",i,p)?;
+	}
+	for o in &self.unsolved {
+	    writeln!(f,"unsolved: {}",o)?;
+	}
+	writeln!(f,"{} paths, {} outcomes covered",self.paths.len(),self.covered.len())
+    }
+}
+
+// =====================================================
+// Explorer
+// =====================================================
+
+/// Generates inputs for a program which cover new branches, by
+/// combining concrete execution with tracking of the input bytes on
+/// which each value depends (i.e. concolic execution).  The input is
+/// a region of memory, initially given by a seed.  Each input is
+/// executed concretely, recording the input-dependent branches along
+/// its path.  For each branch outcome not yet covered, new inputs are
+/// derived from the input by varying only the bytes on which that
+/// branch depended, until one is found which covers it.  No path
+/// condition is solved.  Instead, those bytes are first cleared
+/// together and, otherwise, mutated one byte at a time.  Thus, an
+/// outcome requiring several bytes to change at once (e.g. two input
+/// words being equal) may not be covered, in which case it is
+/// reported as unsolved.  For example:
+///
+/// ```text
+/// let concolic = Concolic::new(&program,0x100..0x108).memory(0x200).registers(8,Width::Word);
+/// let exploration = concolic.explore(&[0;8]);
+/// for path in &exploration.paths {
+///     println!("{}",path);
+/// }
+/// ```
+pub struct Concolic<'a> {
+    program: &'a DecodedProgram<'a>,
+    /// Region of memory holding the input.
+    input: Range<usize>,
+    /// Size (in bytes) of memory.
+    memory: usize,
+    /// Number and width of general purpose registers.
+    registers: (usize,Width),
+    /// Maximum number of instructions executed for each input.
+    fuel: usize,
+    /// Maximum number of candidate inputs tried for each branch.
+    attempts: usize,
+    /// Maximum number of paths to discover.
+    paths: usize
+}
+
+impl<'a> Concolic<'a> {
+    /// Construct an explorer for a given program, whose input is held
+    /// in a given region of memory.
+    pub fn new(program: &'a DecodedProgram<'a>, input: Range<usize>) -> Self {
+	let memory = input.end;
+	Concolic{program,input,memory,registers:(0,Width::QuadWord),fuel:FUEL,attempts:ATTEMPTS,paths:usize::MAX}
+    }
+    /// Set the size (in bytes) of memory (default is the end of the
+    /// input).  Memory always includes the input and, hence, a
+    /// smaller size is increased to the end of the input.
+    pub fn memory(mut self, bytes: usize) -> Self {
+	self.memory = bytes.max(self.input.end);
+	self
+    }
+    /// Set the number and width of general purpose registers (default
+    /// is none).
+    pub fn registers(mut self, count: usize, width: Width) -> Self {
+	self.registers = (count,width);
+	self
+    }
+    /// Set the maximum number of instructions executed for each input.
+    pub fn fuel(mut self, fuel: usize) -> Self {
+	self.fuel = fuel;
+	self
+    }
+    /// Set the maximum number of candidate inputs tried when
+    /// attempting to flip a branch.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+	self.attempts = attempts;
+	self
+    }
+    /// Set the maximum number of paths to discover.
+    pub fn paths(mut self, paths: usize) -> Self {
+	self.paths = paths;
+	self
+    }
+
+    /// Explore the program starting from a given seed input (which is
+    /// padded with zeros, or truncated, to the size of the input).
+    pub fn explore(&self, seed: &[u8]) -> Exploration {
+	let mut seed = seed.to_vec();
+	seed.resize(self.input.len(),0);
+	let mut covered = BTreeSet::new();
+	let mut attempted = BTreeSet::new();
+	let mut paths = Vec::new();
+	let mut queue = VecDeque::from([seed]);
+	while let Some(input) = queue.pop_front() {
+	    if paths.len() >= self.paths { break; }
+	    let execution = self.execute(&input);
+	    let outcomes : Vec<Outcome> = execution.branches.iter().map(|b| b.outcome).collect();
+	    let mut new = paths.is_empty();
+	    for o in &outcomes {
+		new |= covered.insert(*o);
+	    }
+	    if !new { continue; }
+	    for b in &execution.branches {
+		let target = b.outcome.flip();
+		if !covered.contains(&target) && attempted.insert(target) {
+		    if let Some(input) = self.solve(&input,&b.inputs,target) {
+			queue.push_back(input);
+		    }
+		}
+	    }
+	    paths.push(Path{input,outcomes,fault:execution.fault});
+	}
+	let unsolved = attempted.difference(&covered).copied().collect();
+	Exploration{paths,covered,unsolved}
+    }
+
+    /// Execute the program on a given input, recording the branches
+    /// which depended on it.
+    pub fn execute(&self, input: &[u8]) -> Execution {
+	let mut bytes = vec![0;self.memory];
+	let n = input.len().min(self.input.len());
+	bytes[self.input.start..self.input.start+n].copy_from_slice(&input[..n]);
+	let mut taint = Taint::new(self.memory,&self.input);
+	let (count,width) = self.registers;
+	let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(count,width));
+	let mut branches = Vec::new();
+	let mut steps = 0;
+	let mut fault = None;
+	while steps < self.fuel && state.pc < self.program.len() {
+	    let pc = state.pc;
+	    let microcode = match state.fetch(self.program).map_err(|error| MachineError::Decode{pc,error}) {
+		Ok(m) => m,
+		Err(e) => { fault = Some(e); break; }
+	    };
+	    if let Err(e) = state.check_accesses(microcode) {
+		fault = Some(e);
+		break;
+	    }
+	    state.execute_observed(microcode,|s,index,code| branches.extend(taint.apply(s,pc,index,code)));
+	    taint.clear_temps();
+	    steps += 1;
+	}
+	Execution{branches,steps,fault}
+    }
+
+    /// Find an input which covers a given branch outcome by varying
+    /// the bytes of a given input on which that branch depends.
+    /// First, those bytes are all cleared (since branches compare
+    /// against zero) and, otherwise, every value of each byte is
+    /// tried in turn (leaving the others unchanged).
+    fn solve(&self, input: &[u8], inputs: &[usize], target: Outcome) -> Option<Vec<u8>> {
+	let mut tried = HashSet::new();
+	let mut cleared = input.to_vec();
+	for i in inputs { cleared[*i] = 0; }
+	let varied = inputs.iter().flat_map(|i| (0..=255u8).map(move |v| {
+	    let mut candidate = input.to_vec();
+	    candidate[*i] = v;
+	    candidate
+	}));
+	std::iter::once(cleared).chain(varied)
+	    .filter(|c| c != input && tried.insert(c.clone()))
+	    .take(self.attempts)
+	    .find(|c| self.execute(c).branches.iter().any(|b| b.outcome == target))
+    }
+}
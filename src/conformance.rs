@@ -0,0 +1,468 @@
+use std::fmt;
+use std::path::Path;
+use crate::asm::{AsmError,Assembler};
+use crate::insn::InstructionSet;
+use crate::machine::{RegisterFile,State,Width};
+use crate::program::DecodedProgram;
+
+/// Amount of memory (in bytes) given to a case which does not specify
+/// it.
+pub const MEMORY : usize = 256;
+/// Maximum number of instructions executed by a case which does not
+/// specify it.
+pub const FUEL : usize = 10_000;
+
+// =====================================================
+// Errors
+// =====================================================
+
+/// Identifies a problem encountered when loading (or assembling) a
+/// conformance case, as opposed to a case which fails.
+#[derive(Clone,Debug,PartialEq)]
+pub enum ConformanceError {
+    /// A file or directory could not be read.
+    Io{path: String, message: String},
+    /// A line of a state file could not be parsed.
+    Parse{file: String, line: usize, message: String},
+    /// The program of a case could not be assembled.
+    Asm{file: String, error: AsmError}
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    ConformanceError::Io{path,message} => write!(f,"{}: {}",path,message),
+	    ConformanceError::Parse{file,line,message} => write!(f,"{}:{}: {}",file,line,message),
+	    ConformanceError::Asm{file,error} => write!(f,"{}: {}",file,error)
+	}
+    }
+}
+
+impl std::error::Error for ConformanceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    ConformanceError::Asm{error,..} => Some(error),
+	    _ => None
+	}
+    }
+}
+
+// =====================================================
+// Locations
+// =====================================================
+
+/// Identifies part of the state of a machine which can be set before
+/// a case executes, or checked afterwards.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Location {
+    Pc,
+    Sp,
+    Lr,
+    Flags,
+    /// A general purpose register (e.g. `r3`).
+    Register(usize),
+    /// A little endian value of a given width in memory (e.g.
+    /// `mem32[0x10]`).
+    Memory(usize,Width)
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Location::Pc => write!(f,"pc"),
+	    Location::Sp => write!(f,"sp"),
+	    Location::Lr => write!(f,"lr"),
+	    Location::Flags => write!(f,"flags"),
+	    Location::Register(r) => write!(f,"r{}",r),
+	    Location::Memory(a,Width::Byte) => write!(f,"mem[{:#x}]",a),
+	    Location::Memory(a,w) => write!(f,"mem{}[{:#x}]",w.bytes() * 8,a)
+	}
+    }
+}
+
+impl Location {
+    /// Read this location from a given machine, or `None` if it does
+    /// not exist.
+    fn read(&self, state: &State) -> Option<u64> {
+	match self {
+	    Location::Pc => Some(state.pc as u64),
+	    Location::Sp => Some(state.sp as u64),
+	    Location::Lr => Some(state.lr as u64),
+	    Location::Flags => Some(state.flags),
+	    Location::Register(r) if *r < state.registers.len() => Some(state.registers.read(*r)),
+	    Location::Memory(a,w) if a.checked_add(w.bytes()).is_some_and(|end| end <= state.data.len()) => {
+		let mut bytes = [0u8;8];
+		state.data.read_bytes(*a,&mut bytes[..w.bytes()]);
+		Some(u64::from_le_bytes(bytes))
+	    }
+	    _ => None
+	}
+    }
+    /// Write a given value to this location of a given machine,
+    /// returning false if it does not exist.
+    fn write(&self, state: &mut State, value: u64) -> bool {
+	match self {
+	    Location::Pc => state.pc = value as usize,
+	    Location::Sp => state.sp = value as usize,
+	    Location::Lr => state.lr = value as usize,
+	    Location::Flags => state.flags = value,
+	    Location::Register(r) if *r < state.registers.len() => state.registers.write(*r,value),
+	    Location::Memory(a,w) if self.read(state).is_some() => {
+		state.data.write_bytes(*a,&value.to_le_bytes()[..w.bytes()])
+	    }
+	    _ => { return false; }
+	}
+	true
+    }
+}
+
+/// A value held in a given location.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Assignment {
+    pub location: Location,
+    pub value: u64
+}
+
+// =====================================================
+// Cases
+// =====================================================
+
+/// A single conformance case, consisting of an assembly program along
+/// with the state of the machine before and after executing it.  On
+/// disk, a case named `NAME` consists of the files `NAME.asm`,
+/// `NAME.expected` and (optionally) `NAME.init`.  The latter two hold
+/// one setting or assignment per line (with `#` starting a comment).
+/// For example, `NAME.init` might be:
+///
+/// ```text
+/// memory = 64       # bytes of memory (default 256)
+/// registers = 4     # general purpose registers (default none)
+/// width = 32        # width of each register in bits (default 64)
+/// fuel = 1000       # maximum instructions to execute (default 10000)
+/// r1 = 5
+/// mem[0x10] = 1 2 3
+/// ```
+///
+/// Whilst `NAME.expected` might be:
+///
+/// ```text
+/// steps = 12        # instructions executed (optional)
+/// pc = 4
+/// r1 = 0
+/// mem16[0x10] = 0x0201
+/// ```
+///
+/// Only the locations given in `NAME.expected` are checked.  A line
+/// `fault` indicates the program is expected to raise a fault (e.g.
+/// an invalid memory access).
+#[derive(Clone,Debug,PartialEq)]
+pub struct Case {
+    pub name: String,
+    /// The assembly source of the program.
+    pub source: String,
+    pub memory: usize,
+    pub registers: usize,
+    pub width: Width,
+    pub fuel: usize,
+    /// Assignments made before execution.
+    pub initial: Vec<Assignment>,
+    /// Assignments expected to hold after execution.
+    pub expected: Vec<Assignment>,
+    /// The number of instructions expected to execute (if given).
+    pub steps: Option<usize>,
+    /// Whether execution is expected to raise a fault.
+    pub fault: bool
+}
+
+impl Case {
+    /// Construct a case from the contents of its files.
+    pub fn parse(name: &str, source: &str, initial: &str, expected: &str) -> Result<Case,ConformanceError> {
+	let mut case = Case{name:name.to_string(),source:source.to_string(),memory:MEMORY,registers:0,
+			    width:Width::QuadWord,fuel:FUEL,initial:Vec::new(),expected:Vec::new(),steps:None,fault:false};
+	let init = format!("{}.init",name);
+	for (line,item) in parse_lines(&init,initial)? {
+	    let error = |message: &str| ConformanceError::Parse{file:init.clone(),line,message:message.to_string()};
+	    match item {
+		Item::Setting(key,value) => match key.as_str() {
+		    "memory" => case.memory = value as usize,
+		    "registers" => case.registers = value as usize,
+		    "fuel" => case.fuel = value as usize,
+		    "width" => {
+			case.width = match value {
+			    8 => Width::Byte,
+			    16 => Width::Word,
+			    32 => Width::DoubleWord,
+			    64 => Width::QuadWord,
+			    _ => { return Err(error("width must be 8, 16, 32 or 64")); }
+			}
+		    }
+		    _ => { return Err(error(&format!("unknown setting \"{}\"",key))); }
+		},
+		Item::Assign(assignments) => case.initial.extend(assignments),
+		Item::Fault => { return Err(error("fault can only be expected")); }
+	    }
+	}
+	let file = format!("{}.expected",name);
+	for (line,item) in parse_lines(&file,expected)? {
+	    match item {
+		Item::Setting(key,value) if key == "steps" => case.steps = Some(value as usize),
+		Item::Setting(key,_) => {
+		    return Err(ConformanceError::Parse{file,line,message:format!("unknown setting \"{}\"",key)});
+		}
+		Item::Assign(assignments) => case.expected.extend(assignments),
+		Item::Fault => case.fault = true
+	    }
+	}
+	Ok(case)
+    }
+}
+
+/// Load every case in a given directory (i.e. every `.asm` file along
+/// with its state files), ordered by name.
+pub fn load(dir: &Path) -> Result<Vec<Case>,ConformanceError> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| io_error(dir,e))? {
+	let path = entry.map_err(|e| io_error(dir,e))?.path();
+	if path.extension().is_some_and(|e| e == "asm") {
+	    if let Some(stem) = path.file_stem() {
+		names.push(stem.to_string_lossy().to_string());
+	    }
+	}
+    }
+    names.sort();
+    let mut cases = Vec::new();
+    for name in names {
+	let read = |ext: &str| {
+	    let path = dir.join(format!("{}.{}",name,ext));
+	    std::fs::read_to_string(&path).map_err(|e| io_error(&path,e))
+	};
+	let initial = if dir.join(format!("{}.init",name)).exists() { read("init")? } else { String::new() };
+	cases.push(Case::parse(&name,&read("asm")?,&initial,&read("expected")?)?);
+    }
+    Ok(cases)
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> ConformanceError {
+    ConformanceError::Io{path:path.display().to_string(),message:error.to_string()}
+}
+
+/// A single (non-empty) line of a state file.
+enum Item {
+    Setting(String,u64),
+    Assign(Vec<Assignment>),
+    Fault
+}
+
+fn parse_lines(file: &str, contents: &str) -> Result<Vec<(usize,Item)>,ConformanceError> {
+    let mut items = Vec::new();
+    for (i,line) in contents.lines().enumerate() {
+	let line = line.split('#').next().unwrap().trim();
+	if line.is_empty() {
+	    continue;
+	}
+	let error = |message: String| ConformanceError::Parse{file:file.to_string(),line:i+1,message};
+	if line == "fault" {
+	    items.push((i+1,Item::Fault));
+	    continue;
+	}
+	let (lhs,rhs) = line.split_once('=').ok_or_else(|| error("expected \"=\"".to_string()))?;
+	let (lhs,rhs) = (lhs.trim(),rhs.trim());
+	let values : Vec<u64> = rhs.split_whitespace().map(|v| parse_number(v).ok_or_else(|| error(format!("invalid number \"{}\"",v)))).collect::<Result<_,_>>()?;
+	if values.is_empty() {
+	    return Err(error("expected value".to_string()));
+	}
+	let item = match parse_location(lhs) {
+	    // A sequence of values is held contiguously
+	    Some(Location::Memory(address,width)) => {
+		let assignments = values.iter().enumerate().map(|(j,v)| {
+		    Assignment{location:Location::Memory(address + j * width.bytes(),width),value:*v}
+		});
+		Item::Assign(assignments.collect())
+	    }
+	    _ if values.len() > 1 => {
+		return Err(error(format!("expected one value for \"{}\"",lhs)));
+	    }
+	    Some(location) => Item::Assign(vec![Assignment{location,value:values[0]}]),
+	    None if lhs.chars().all(|c| c.is_ascii_alphabetic()) => Item::Setting(lhs.to_string(),values[0]),
+	    None => { return Err(error(format!("invalid location \"{}\"",lhs))); }
+	};
+	items.push((i+1,item));
+    }
+    Ok(items)
+}
+
+fn parse_location(text: &str) -> Option<Location> {
+    match text {
+	"pc" => return Some(Location::Pc),
+	"sp" => return Some(Location::Sp),
+	"lr" => return Some(Location::Lr),
+	"flags" => return Some(Location::Flags),
+	_ => {}
+    }
+    if let Some(r) = text.strip_prefix('r') {
+	return r.parse().ok().map(Location::Register);
+    }
+    let (width,address) = text.strip_prefix("mem")?.strip_suffix(']')?.split_once('[')?;
+    let width = match width {
+	""|"8" => Width::Byte,
+	"16" => Width::Word,
+	"32" => Width::DoubleWord,
+	"64" => Width::QuadWord,
+	_ => { return None; }
+    };
+    Some(Location::Memory(parse_number(address.trim())? as usize,width))
+}
+
+/// Parse a decimal, hexadecimal (`0x`) or binary (`0b`) number, where
+/// negative numbers are given in two's complement form.
+fn parse_number(text: &str) -> Option<u64> {
+    if let Some(t) = text.strip_prefix('-') {
+	return parse_number(t).map(|v| v.wrapping_neg());
+    }
+    match text.get(..2) {
+	Some("0x") => u64::from_str_radix(&text[2..],16).ok(),
+	Some("0b") => u64::from_str_radix(&text[2..],2).ok(),
+	_ => text.parse().ok()
+    }
+}
+
+// =====================================================
+// Runner
+// =====================================================
+
+/// Identifies a way in which a case failed.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Mismatch {
+    /// A location held a different value than expected.
+    Value{location: Location, expected: u64, actual: u64},
+    /// A location given by the case does not exist in the machine
+    /// (e.g. is beyond the end of memory).
+    Missing(Location),
+    /// A different number of instructions executed than expected.
+    Steps{expected: usize, actual: usize},
+    /// A fault was raised unexpectedly (or, if `None`, was expected
+    /// but not raised).
+    Fault(Option<String>)
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Mismatch::Value{location,expected,actual} => {
+		write!(f,"{}: expected {:#x}, found {:#x}",location,expected,actual)
+	    }
+	    Mismatch::Missing(location) => write!(f,"{}: no such location",location),
+	    Mismatch::Steps{expected,actual} => write!(f,"expected {} steps, found {}",expected,actual),
+	    Mismatch::Fault(Some(fault)) => write!(f,"unexpected fault: {}",fault),
+	    Mismatch::Fault(None) => write!(f,"expected fault")
+	}
+    }
+}
+
+/// Executes conformance cases against a given instruction set.  For
+/// example:
+///
+/// ```text
+/// let report = Runner::new(&isa).run_dir(Path::new("tests/conformance"))?;
+/// print!("{}",report);
+/// assert!(report.passed());
+/// ```
+pub struct Runner<'a> {
+    isa: &'a InstructionSet<'a>
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	Runner{isa}
+    }
+    /// Execute a given case from pc zero until the pc leaves the
+    /// program, a fault is raised or the fuel is exhausted.  Returns
+    /// every way in which the final state differs from that expected
+    /// (i.e. nothing, if the case passes).
+    pub fn run(&self, case: &Case) -> Result<Vec<Mismatch>,ConformanceError> {
+	let file = format!("{}.asm",case.name);
+	let program = Assembler::new(self.isa).file(&file).assemble(&case.source)
+	    .map_err(|error| ConformanceError::Asm{file,error})?;
+	let program = DecodedProgram::new(self.isa,program.bytes());
+	let mut memory = vec![0u8;case.memory];
+	let mut state = State::new(0,&mut memory).with_registers(RegisterFile::new(case.registers,case.width));
+	let mut mismatches = Vec::new();
+	for a in &case.initial {
+	    if !a.location.write(&mut state,a.value) {
+		mismatches.push(Mismatch::Missing(a.location));
+	    }
+	}
+	if !mismatches.is_empty() {
+	    return Ok(mismatches);
+	}
+	let mut steps = 0;
+	let mut fault = None;
+	while steps < case.fuel && state.pc < program.len() {
+	    if let Err(e) = state.try_step(&program) {
+		fault = Some(e.to_string());
+		break;
+	    }
+	    steps += 1;
+	}
+	match fault {
+	    Some(_) if case.fault => {}
+	    None if !case.fault => {}
+	    _ => mismatches.push(Mismatch::Fault(fault))
+	}
+	if let Some(expected) = case.steps {
+	    if expected != steps {
+		mismatches.push(Mismatch::Steps{expected,actual:steps});
+	    }
+	}
+	for a in &case.expected {
+	    match a.location.read(&state) {
+		None => mismatches.push(Mismatch::Missing(a.location)),
+		Some(actual) if actual != a.value => {
+		    mismatches.push(Mismatch::Value{location:a.location,expected:a.value,actual});
+		}
+		_ => {}
+	    }
+	}
+	Ok(mismatches)
+    }
+    /// Load and execute every case in a given directory.
+    pub fn run_dir(&self, dir: &Path) -> Result<Report,ConformanceError> {
+	let mut results = Vec::new();
+	for case in load(dir)? {
+	    let mismatches = self.run(&case)?;
+	    results.push((case.name,mismatches));
+	}
+	Ok(Report{results})
+    }
+}
+
+/// The outcome of executing a suite of cases, giving the mismatches
+/// found (if any) for each case.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Report {
+    pub results: Vec<(String,Vec<Mismatch>)>
+}
+
+impl Report {
+    /// Check whether every case passed.
+    pub fn passed(&self) -> bool {
+	self.results.iter().all(|(_,m)| m.is_empty())
+    }
+    /// Get the names of the cases which failed.
+    pub fn failures(&self) -> Vec<&str> {
+	self.results.iter().filter(|(_,m)| !m.is_empty()).map(|(n,_)| n.as_str()).collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for (name,mismatches) in &self.results {
+	    writeln!(f,"{} {}",if mismatches.is_empty() { "PASS" } else { "FAIL" },name)?;
+	    for m in mismatches {
+		writeln!(f,"  {}",m)?;
+	    }
+	}
+	let failed = self.failures().len();
+	writeln!(f,"{} passed, {} failed",self.results.len() - failed,failed)
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::insn::InstructionSet;
+use crate::machine::{MachineError,State};
+use crate::program::DecodedProgram;
+
+/// Number of buckets into which the values of each operand are
+/// divided.
+pub const BUCKETS : usize = 4;
+
+// =====================================================
+// Coverage
+// =====================================================
+
+/// Records which instructions of an instruction set were executed
+/// and, for each operand, which ranges of values it took.  The raw
+/// (i.e. encoded) values of an operand are divided into `BUCKETS`
+/// equally sized buckets, such that (for example) a signed operand
+/// distinguishes small and large positive and negative values.  This
+/// allows the completeness of a test suite for an instruction set to
+/// be measured.  For example:
+///
+/// ```text
+/// let mut coverage = Coverage::new(&isa);
+/// while state.pc < program.len() {
+///     coverage.step(&mut state,&program)?;
+/// }
+/// println!("{}",coverage);
+/// ```
+pub struct Coverage<'a> {
+    isa: &'a InstructionSet<'a>,
+    /// Number of times each instruction was executed.
+    hits: Vec<usize>,
+    /// Buckets seen for each operand of each instruction (one bit per
+    /// bucket).
+    buckets: Vec<Vec<u8>>,
+    /// Number of times each pair of instructions was executed one
+    /// after the other.
+    pairs: BTreeMap<(usize,usize),usize>,
+    /// The instruction last recorded (if any).
+    last: Option<usize>
+}
+
+impl<'a> Coverage<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	let hits = vec![0;isa.len()];
+	let buckets = isa.iter().map(|i| vec![0;i.arity()]).collect();
+	Coverage{isa,hits,buckets,pairs:BTreeMap::new(),last:None}
+    }
+    /// Record the execution of a given instruction (identified by its
+    /// index) with the given operands.  This is assumed to follow the
+    /// instruction last recorded.
+    pub fn record(&mut self, insn: usize, operands: &[usize]) {
+	self.hits[insn] += 1;
+	if let Some(last) = self.last.replace(insn) {
+	    *self.pairs.entry((last,insn)).or_insert(0) += 1;
+	}
+	let fields = self.isa.instruction(insn).format().operands();
+	for (i,(f,v)) in fields.iter().zip(operands).enumerate() {
+	    self.buckets[insn][i] |= 1 << bucket(f.bits().value(),*v);
+	}
+    }
+    /// Record the instruction identified by the current pc, then
+    /// execute it.
+    pub fn step(&mut self, state: &mut State, program: &DecodedProgram) -> Result<(),MachineError> {
+	let entry = program.get(state.pc).map_err(|error| MachineError::Decode{pc:state.pc,error})?;
+	self.record(entry.insn,entry.operands);
+	state.step(program)
+    }
+    /// Get the number of times a given instruction was executed.
+    pub fn hits(&self, insn: usize) -> usize {
+	self.hits[insn]
+    }
+    /// Get the buckets seen for a given operand of a given
+    /// instruction.
+    pub fn buckets(&self, insn: usize, operand: usize) -> Vec<usize> {
+	(0..BUCKETS).filter(|b| self.buckets[insn][operand] & (1 << b) != 0).collect()
+    }
+    /// Get each pair of instructions executed one after the other,
+    /// along with how many times this happened, most frequent first.
+    /// This identifies candidates for fusion (see
+    /// `DecodedProgram::fuse()`).
+    pub fn pairs(&self) -> Vec<((usize,usize),usize)> {
+	let mut pairs : Vec<_> = self.pairs.iter().map(|(p,n)| (*p,*n)).collect();
+	pairs.sort_by_key(|(_,n)| std::cmp::Reverse(*n));
+	pairs
+    }
+    /// Get the number of instructions executed at least once.
+    pub fn covered(&self) -> usize {
+	self.hits.iter().filter(|h| **h > 0).count()
+    }
+    /// Get the mnemonics of all instructions never executed.
+    pub fn uncovered(&self) -> Vec<&str> {
+	self.isa.iter().zip(&self.hits).filter(|(_,h)| **h == 0).map(|(i,_)| i.mnemonic()).collect()
+    }
+    /// Combine the coverage recorded by another collector (e.g. from
+    /// a different test) into this one.  Both must be for the same
+    /// instruction set.
+    pub fn merge(&mut self, other: &Coverage) {
+	for (i,h) in other.hits.iter().enumerate() {
+	    self.hits[i] += h;
+	    for (b,o) in self.buckets[i].iter_mut().zip(&other.buckets[i]) {
+		*b |= o;
+	    }
+	}
+	for (p,n) in &other.pairs {
+	    *self.pairs.entry(*p).or_insert(0) += n;
+	}
+    }
+}
+
+/// Summarises coverage, listing each instruction which was either
+/// never executed, or whose operands did not cover every bucket.
+impl fmt::Display for Coverage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	writeln!(f,"{}/{} instructions covered",self.covered(),self.isa.len())?;
+	for (i,insn) in self.isa.iter().enumerate() {
+	    if self.hits[i] == 0 {
+		writeln!(f,"  {}: never executed",insn.mnemonic())?;
+		continue;
+	    }
+	    let fields = insn.format().operands();
+	    for (j,field) in fields.iter().enumerate() {
+		let all = (1usize << BUCKETS.min(1 << field.bits().value().min(8))) - 1;
+		let missing : Vec<String> = (0..BUCKETS).filter(|b| all & (1 << b) != 0 && self.buckets[i][j] & (1 << b) == 0)
+		    .map(|b| b.to_string()).collect();
+		if !missing.is_empty() {
+		    let name = if field.name().is_empty() { format!("op{}",j) } else { field.name().to_string() };
+		    writeln!(f,"  {} {}: missing bucket(s) {}",insn.mnemonic(),name,missing.join(","))?;
+		}
+	    }
+	}
+	Ok(())
+    }
+}
+
+/// Determine the bucket of a value for an operand with a given number
+/// of bits, using the most significant bits of its raw encoding.
+fn bucket(bits: u8, value: usize) -> usize {
+    let bits = bits.min(usize::BITS as u8) as u32;
+    let raw = if bits >= usize::BITS { value } else { value & ((1 << bits) - 1) };
+    let shift = bits.saturating_sub(BUCKETS.ilog2());
+    raw >> shift
+}
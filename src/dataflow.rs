@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap,BTreeSet};
+use std::fmt;
+use crate::cfg::Cfg;
+use crate::machine::{MicroCode,Width,FLAGS,LR,PC,SP,TEMPS};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Locations
+// =====================================================
+
+/// A location which microcode can read or write, where memory is
+/// considered byte by byte (such that accesses of different widths
+/// are related).  The pc is not a location, since writes to it are
+/// branches.
+#[derive(Clone,Copy,Debug,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Location {
+    Memory(usize),
+    Register(usize)
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    Location::Memory(x) => write!(f,"mem[{:#x}]",x),
+	    Location::Register(SP) => write!(f,"sp"),
+	    Location::Register(FLAGS) => write!(f,"flags"),
+	    Location::Register(LR) => write!(f,"lr"),
+	    Location::Register(r) if *r >= LR - TEMPS => write!(f,"t{}",LR - 1 - r),
+	    Location::Register(r) => write!(f,"reg[{}]",r)
+	}
+    }
+}
+
+/// A point within a program, being a microcode of the instruction at
+/// a given pc.  The index following the last microcode identifies the
+/// end of the instruction, where scratch registers are cleared.
+#[derive(Clone,Copy,Debug,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Point {
+    pub pc: usize,
+    pub index: usize
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"{:#x}#{}",self.pc,self.index)
+    }
+}
+
+/// Identifies where the value of a location was written.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Definition {
+    /// The value held when the program started.
+    Initial,
+    At(Point)
+}
+
+/// Determine the locations written and read by a given microcode.
+pub fn accesses(code: MicroCode) -> (Vec<Location>,Vec<Location>) {
+    let mem = |x: usize, w: Width| (x..x+w.bytes()).map(Location::Memory).collect::<Vec<_>>();
+    let reg = |r: usize| if r == PC { vec![] } else { vec![Location::Register(r)] };
+    match code {
+	MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w) => (mem(x,w),[mem(x,w),mem(y,w)].concat()),
+	MicroCode::Copy(x,y,w) => (mem(x,w),mem(y,w)),
+	MicroCode::Load(x,_,w) => (mem(x,w),vec![]),
+	MicroCode::RegAdd(r,s)|MicroCode::RegAlu(_,r,s) => (reg(r),[reg(r),reg(s)].concat()),
+	MicroCode::RegCopy(r,s) => (reg(r),reg(s)),
+	MicroCode::RegFetch(r,x,w) => (reg(r),mem(x,w)),
+	MicroCode::RegLoad(r,_) => (reg(r),vec![]),
+	MicroCode::RegStore(x,r,w) => (mem(x,w),reg(r)),
+	// Memory accessed indirectly is unknown, hence no location is
+	// (definitely) written, whilst any may be read (see `Dataflow::new()`)
+	MicroCode::RegFetchIndirect(r,s,_) => (reg(r),reg(s)),
+	MicroCode::RegStoreIndirect(s,r,_) => (vec![],[reg(s),reg(r)].concat()),
+	MicroCode::RegSkipIfZero(r,_) => (vec![],reg(r)),
+	MicroCode::SkipIfZero(x,w,_) => (vec![],mem(x,w)),
+	MicroCode::Goto(_)|MicroCode::Jump(_)|MicroCode::Skip(_) => (vec![],vec![])
+    }
+}
+
+// =====================================================
+// Dataflow
+// =====================================================
+
+/// Computes liveness and def-use chains for every location across the
+/// microcode of a program, using its control-flow graph (see `Cfg`).
+/// A location is live at a point if its value may be read before
+/// being overwritten.  Since every location is observable once the
+/// program ends (or control goes somewhere unknown, such as a
+/// return), all are considered live then.  Scratch registers are
+/// defined (as zero) at the end of each instruction.  For example:
+///
+/// ```text
+/// let dataflow = Dataflow::new(&program);
+/// for p in dataflow.dead_stores() {
+///     println!("{}: value written is never read",p);
+/// }
+/// // Who wrote the value of r3 read by the instruction at pc 12?
+/// let writers = dataflow.definitions(Point{pc:12,index:0},Location::Register(3));
+/// ```
+pub struct Dataflow {
+    points: Vec<Point>,
+    /// Index of each point.
+    index: BTreeMap<Point,usize>,
+    codes: Vec<Option<MicroCode>>,
+    defs: Vec<Vec<Location>>,
+    uses: Vec<Vec<Location>>,
+    successors: Vec<Vec<usize>>,
+    /// Whether control may leave the program (or go somewhere
+    /// unknown) after each point.
+    exits: Vec<bool>,
+    live_out: Vec<BTreeSet<Location>>,
+    /// Every definition, where `None` is the initial value.
+    definitions: Vec<(Option<usize>,Location)>,
+    reaching: Vec<BTreeSet<usize>>
+}
+
+impl Dataflow {
+    /// Compute the dataflow of a given program.
+    pub fn new(program: &DecodedProgram) -> Self {
+	let cfg = Cfg::new(program);
+	let n = program.len();
+	let mut points = Vec::new();
+	let mut codes = Vec::new();
+	let mut first = Vec::new();
+	for pc in 0..n {
+	    first.push(points.len());
+	    let microcode : Vec<MicroCode> = program.get(pc).map(|e| e.microcode.iter().map(|t| t.code()).collect()).unwrap_or_default();
+	    for (index,code) in microcode.iter().enumerate() {
+		points.push(Point{pc,index});
+		codes.push(Some(*code));
+	    }
+	    points.push(Point{pc,index:microcode.len()});
+	    codes.push(None);
+	}
+	first.push(points.len());
+	let mut successors = vec![Vec::new();points.len()];
+	let mut exits = vec![false;points.len()];
+	for (p,point) in points.iter().enumerate() {
+	    let end = first[point.pc+1] - 1;
+	    match codes[p] {
+		Some(MicroCode::SkipIfZero(_,_,k)|MicroCode::RegSkipIfZero(_,k)) => {
+		    successors[p] = vec![p+1,(p+1+k).min(end)];
+		}
+		Some(MicroCode::Skip(k)) => successors[p] = vec![(p+1+k).min(end)],
+		Some(_) => successors[p] = vec![p+1],
+		None => {
+		    // The end of an instruction
+		    let pc = point.pc;
+		    let Some(b) = cfg.block_of(pc) else { continue; };
+		    let block = &cfg.blocks[b];
+		    if program.get(pc).is_err() {
+			exits[p] = true;
+		    } else if pc + 1 < block.pcs.end {
+			successors[p].push(first[pc+1]);
+		    } else {
+			exits[p] = block.returns || block.indirect;
+			for e in cfg.successors(b) {
+			    match e.to {
+				Some(t) => successors[p].push(first[cfg.blocks[t].pcs.start]),
+				None => { exits[p] = true; }
+			    }
+			}
+		    }
+		}
+	    }
+	}
+	// Determine the locations each point writes and reads
+	let mut defs = Vec::new();
+	let mut uses = Vec::new();
+	let mut universe = BTreeSet::new();
+	for code in &codes {
+	    let (d,u) = code.map(accesses).unwrap_or_default();
+	    universe.extend(d.iter().chain(&u).copied());
+	    defs.push(d);
+	    uses.push(u);
+	}
+	let memory : Vec<Location> = universe.iter().filter(|l| matches!(l,Location::Memory(_))).copied().collect();
+	for (p,code) in codes.iter().enumerate() {
+	    if let Some(MicroCode::RegFetchIndirect(..)) = code { uses[p].extend(memory.iter().copied()); }
+	}
+	let temps : Vec<Location> = universe.iter().filter(|l| matches!(l,Location::Register(r) if (LR - TEMPS..LR).contains(r))).copied().collect();
+	for (p,code) in codes.iter().enumerate() {
+	    if code.is_none() { defs[p] = temps.clone(); }
+	}
+	let index = points.iter().enumerate().map(|(i,p)| (*p,i)).collect();
+	let mut dataflow = Dataflow{points,index,codes,defs,uses,successors,exits,live_out:Vec::new(),definitions:Vec::new(),reaching:Vec::new()};
+	dataflow.liveness(&universe);
+	dataflow.reaching_definitions(&universe);
+	dataflow
+    }
+
+    /// Get every point of the program, in order.
+    pub fn points(&self) -> &[Point] {
+	&self.points
+    }
+    /// Get the locations live immediately after a given point.
+    pub fn live_after(&self, point: Point) -> Option<&BTreeSet<Location>> {
+	self.live_out.get(*self.index.get(&point)?)
+    }
+    /// Get the locations live immediately before a given point.
+    pub fn live_before(&self, point: Point) -> Option<BTreeSet<Location>> {
+	let p = *self.index.get(&point)?;
+	Some(self.live_in(p))
+    }
+    /// Determine the microcode which write only locations which are
+    /// not live afterwards and, hence, can be removed.  Branches are
+    /// never dead.
+    pub fn dead_stores(&self) -> Vec<Point> {
+	(0..self.points.len()).filter(|p| {
+	    match self.codes[*p] {
+		Some(c) if !c.is_branch() && !self.defs[*p].is_empty() => {
+		    self.defs[*p].iter().all(|l| !self.live_out[*p].contains(l))
+		}
+		_ => false
+	    }
+	}).map(|p| self.points[p]).collect()
+    }
+    /// Determine the definitions of a given location which may reach
+    /// a given point (i.e. which wrote the value it holds before the
+    /// point).
+    pub fn definitions(&self, point: Point, location: Location) -> Vec<Definition> {
+	let Some(p) = self.index.get(&point) else { return vec![]; };
+	let mut defs : Vec<Definition> = self.reaching[*p].iter().map(|d| self.definitions[*d])
+	    .filter(|(_,l)| *l == location)
+	    .map(|(d,_)| d.map_or(Definition::Initial,|d| Definition::At(self.points[d])))
+	    .collect();
+	defs.sort();
+	defs.dedup();
+	defs
+    }
+    /// Determine the points which may read a value written at a given
+    /// point.
+    pub fn uses(&self, point: Point) -> Vec<Point> {
+	let Some(d) = self.index.get(&point) else { return vec![]; };
+	let mut uses = Vec::new();
+	for (p,locations) in self.uses.iter().enumerate() {
+	    let reached = self.reaching[p].iter().map(|i| self.definitions[*i])
+		.any(|(def,l)| def == Some(*d) && locations.contains(&l));
+	    if reached { uses.push(self.points[p]); }
+	}
+	uses
+    }
+
+    fn live_in(&self, p: usize) -> BTreeSet<Location> {
+	let mut live : BTreeSet<Location> = self.live_out[p].iter().filter(|l| !self.defs[p].contains(l)).copied().collect();
+	live.extend(self.uses[p].iter().copied());
+	live
+    }
+
+    /// Compute the locations live after each point (by iterating
+    /// backwards to a fixed point).
+    fn liveness(&mut self, universe: &BTreeSet<Location>) {
+	let n = self.points.len();
+	self.live_out = (0..n).map(|p| if self.exits[p] { universe.clone() } else { BTreeSet::new() }).collect();
+	let mut changed = true;
+	while changed {
+	    changed = false;
+	    for p in (0..n).rev() {
+		let mut out = if self.exits[p] { universe.clone() } else { BTreeSet::new() };
+		for s in &self.successors[p] {
+		    out.extend(self.live_in(*s));
+		}
+		if out != self.live_out[p] {
+		    self.live_out[p] = out;
+		    changed = true;
+		}
+	    }
+	}
+    }
+
+    /// Compute the definitions reaching each point (by iterating
+    /// forwards to a fixed point).
+    fn reaching_definitions(&mut self, universe: &BTreeSet<Location>) {
+	let n = self.points.len();
+	self.definitions = universe.iter().map(|l| (None,*l)).collect();
+	let mut generated = vec![BTreeSet::new();n];
+	for (p,g) in generated.iter_mut().enumerate() {
+	    for l in &self.defs[p] {
+		g.insert(self.definitions.len());
+		self.definitions.push((Some(p),*l));
+	    }
+	}
+	let mut predecessors = vec![Vec::new();n];
+	for (p,ss) in self.successors.iter().enumerate() {
+	    for s in ss { predecessors[*s].push(p); }
+	}
+	self.reaching = vec![BTreeSet::new();n];
+	let out = |reaching: &BTreeSet<usize>, p: usize, this: &Dataflow| -> BTreeSet<usize> {
+	    let mut out : BTreeSet<usize> = reaching.iter().filter(|d| !this.defs[p].contains(&this.definitions[**d].1)).copied().collect();
+	    out.extend(generated[p].iter().copied());
+	    out
+	};
+	let mut changed = true;
+	while changed {
+	    changed = false;
+	    for (p,preds) in predecessors.iter().enumerate() {
+		let mut r = if p == 0 { (0..universe.len()).collect() } else { BTreeSet::new() };
+		for q in preds {
+		    r.extend(out(&self.reaching[*q],*q,self));
+		}
+		if r != self.reaching[p] {
+		    self.reaching[p] = r;
+		    changed = true;
+		}
+	    }
+	}
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::dataflow::{Dataflow,Definition,Location,Point};
+use crate::disasm::Disassembler;
+use crate::machine::{MachineError,State,FLAGS,LR,PC,SP};
+use crate::program::DecodedProgram;
+
+/// Number of bytes shown by the `mem` command (by default).
+const MEMORY : usize = 64;
+
+/// Number of instructions shown either side of the pc by the `disasm`
+/// command (by default).
+const CONTEXT : usize = 3;
+
+const HELP : &str = "\
+step [N]         execute N instructions (default 1)
+continue [N]     execute until a breakpoint is reached, the pc leaves
+                 the program, or N instructions have executed
+break PC         set a breakpoint at a given pc
+delete PC        remove the breakpoint at a given pc
+breakpoints      list all breakpoints
+mem ADDR [LEN]   show LEN bytes of memory starting from ADDR
+regs             show all registers
+disasm [N]       show N instructions either side of the pc
+writers reg N    show the instructions which may have written register
+                 N (as read at the pc)
+writers mem ADDR show the instructions which may have written the byte
+                 of memory at ADDR (as read at the pc)
+help             show this message";
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum DebugError {
+    /// The command was not recognised.
+    Unknown(String),
+    /// An argument of the command was missing or malformed.
+    Argument(String),
+    /// Memory was accessed out of bounds.
+    Memory{address: usize, length: usize},
+    /// The instruction at the current pc could not be executed.
+    Machine(MachineError)
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    DebugError::Unknown(c) => write!(f,"unknown command \"{}\" (try \"help\")",c),
+	    DebugError::Argument(a) => write!(f,"invalid argument ({})",a),
+	    DebugError::Memory{address,length} => write!(f,"memory {:#x}..{:#x} out of bounds",address,address+length),
+	    DebugError::Machine(e) => write!(f,"{}",e)
+	}
+    }
+}
+
+impl std::error::Error for DebugError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    DebugError::Machine(e) => Some(e),
+	    _ => None
+	}
+    }
+}
+
+impl From<MachineError> for DebugError {
+    fn from(e: MachineError) -> Self {
+	DebugError::Machine(e)
+    }
+}
+
+// =====================================================
+// Debugger
+// =====================================================
+
+/// The reason execution stopped when resumed (see
+/// `Debugger::resume()`).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Stop {
+    /// A breakpoint was reached at a given pc.
+    Breakpoint(usize),
+    /// The pc left the program.
+    Halted(usize),
+    /// The given number of instructions executed.
+    Limit
+}
+
+/// Supports interactively debugging a decoded program, by stepping
+/// through its instructions, setting breakpoints and inspecting the
+/// machine's state.  The debugger can be driven either directly, or
+/// using textual commands (e.g. from a REPL).  For example:
+///
+/// ```text
+/// let mut debugger = Debugger::new(&program);
+/// debugger.command(&mut state,"break 4")?;
+/// println!("{}",debugger.command(&mut state,"continue")?);
+/// println!("{}",debugger.command(&mut state,"mem 0 16")?);
+/// ```
+pub struct Debugger<'a> {
+    program: &'a DecodedProgram<'a>,
+    disasm: Disassembler<'a>,
+    /// Pcs at which execution stops when resumed.
+    breakpoints: BTreeSet<usize>,
+    /// Dataflow of the program (computed when first needed).
+    dataflow: Option<Dataflow>
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a DecodedProgram<'a>) -> Self {
+	let disasm = Disassembler::new(program.isa()).pseudos(false);
+	Debugger{program,disasm,breakpoints:BTreeSet::new(),dataflow:None}
+    }
+    /// Set the disassembler used for showing instructions.
+    pub fn disassembler(mut self, disasm: Disassembler<'a>) -> Self {
+	self.disasm = disasm;
+	self
+    }
+    /// Set a breakpoint at a given pc.
+    pub fn add_breakpoint(&mut self, pc: usize) {
+	self.breakpoints.insert(pc);
+    }
+    /// Remove the breakpoint at a given pc, returning whether there
+    /// was one.
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+	self.breakpoints.remove(&pc)
+    }
+    /// Get the pcs of all breakpoints (in order).
+    pub fn breakpoints(&self) -> impl Iterator<Item=usize> + '_ {
+	self.breakpoints.iter().copied()
+    }
+    /// Execute until a breakpoint is reached (other than at the
+    /// current pc), the pc leaves the program, or a given number of
+    /// instructions have executed.
+    pub fn resume(&self, state: &mut State, limit: usize) -> Result<Stop,MachineError> {
+	for i in 0..limit {
+	    if state.pc >= self.program.len() {
+		return Ok(Stop::Halted(state.pc));
+	    } else if i > 0 && self.breakpoints.contains(&state.pc) {
+		return Ok(Stop::Breakpoint(state.pc));
+	    }
+	    state.step(self.program)?;
+	}
+	Ok(Stop::Limit)
+    }
+    /// Get the program being debugged.
+    pub fn program(&self) -> &'a DecodedProgram<'a> {
+	self.program
+    }
+    /// Check whether there is a breakpoint at a given pc.
+    pub fn is_breakpoint(&self, pc: usize) -> bool {
+	self.breakpoints.contains(&pc)
+    }
+    /// Render the instruction at a given pc.
+    pub fn render(&self, pc: usize) -> String {
+	match self.program.get(pc) {
+	    Ok(e) => format!("{:04x}: {}",e.offset,self.disasm.render(e.insn,e.operands)),
+	    Err(e) => e.to_string()
+	}
+    }
+    /// Execute a textual command (see `help`), returning the text to
+    /// show.
+    pub fn command(&mut self, state: &mut State, line: &str) -> Result<String,DebugError> {
+	let words : Vec<&str> = line.split_whitespace().collect();
+	let (command,args) = match words.split_first() {
+	    Some((c,args)) => (*c,args),
+	    None => return Ok(String::new())
+	};
+	match command {
+	    "s"|"step" => {
+		let n = argument(args,0,1)?;
+		for _ in 0..n {
+		    if state.pc >= self.program.len() { break; }
+		    state.step(self.program)?;
+		}
+		Ok(self.current(state))
+	    }
+	    "c"|"continue" => {
+		let stop = self.resume(state,argument(args,0,usize::MAX)?)?;
+		let reason = match stop {
+		    Stop::Breakpoint(pc) => format!("breakpoint at pc {}",pc),
+		    Stop::Halted(pc) => format!("halted at pc {}",pc),
+		    Stop::Limit => format!("stopped at pc {}",state.pc)
+		};
+		Ok(format!("{}\n{}",reason,self.current(state)))
+	    }
+	    "b"|"break" => {
+		let pc = required(args,0)?;
+		self.add_breakpoint(pc);
+		Ok(format!("breakpoint at pc {}",pc))
+	    }
+	    "d"|"delete" => {
+		let pc = required(args,0)?;
+		if self.remove_breakpoint(pc) {
+		    Ok(format!("deleted breakpoint at pc {}",pc))
+		} else {
+		    Err(DebugError::Argument(format!("no breakpoint at pc {}",pc)))
+		}
+	    }
+	    "breakpoints" => {
+		let lines : Vec<String> = self.breakpoints().map(|pc| format!("{:>4}  {}",pc,self.render(pc))).collect();
+		Ok(lines.join("\n"))
+	    }
+	    "m"|"mem" => {
+		let address = required(args,0)?;
+		let length = argument(args,1,MEMORY)?;
+		match address.checked_add(length) {
+		    Some(end) if end <= state.data.len() => Ok(dump(&state.data.bytes()[address..end],address)),
+		    _ => Err(DebugError::Memory{address,length})
+		}
+	    }
+	    "r"|"regs" => {
+		let lines : Vec<String> = registers(state).iter().map(|(n,v)| format!("{:<6}{:#x}",n,v)).collect();
+		Ok(lines.join("\n"))
+	    }
+	    "disasm" => {
+		let n = argument(args,0,CONTEXT)?;
+		let end = state.pc.saturating_add(n + 1).min(self.program.len());
+		let lines : Vec<String> = (state.pc.saturating_sub(n)..end).map(|pc| {
+		    let marker = if pc == state.pc { "=>" } else if self.breakpoints.contains(&pc) { " *" } else { "  " };
+		    format!("{} {}",marker,self.render(pc))
+		}).collect();
+		Ok(lines.join("\n"))
+	    }
+	    "writers" => {
+		let location = match args.first() {
+		    Some(&"reg") => Location::Register(required(args,1)?),
+		    Some(&"mem") => Location::Memory(required(args,1)?),
+		    _ => return Err(DebugError::Argument("expected reg or mem".to_string()))
+		};
+		if state.pc >= self.program.len() {
+		    return Ok(self.current(state));
+		}
+		let program = self.program;
+		let dataflow = self.dataflow.get_or_insert_with(|| Dataflow::new(program));
+		let pcs : BTreeSet<Option<usize>> = dataflow.definitions(Point{pc:state.pc,index:0},location).iter().map(|d| match d {
+		    Definition::Initial => None,
+		    Definition::At(p) => Some(p.pc)
+		}).collect();
+		let lines : Vec<String> = pcs.iter().map(|pc| match pc {
+		    None => "initial value".to_string(),
+		    Some(pc) => format!("{:>4}  {}",pc,self.render(*pc))
+		}).collect();
+		Ok(lines.join("\n"))
+	    }
+	    "h"|"help" => Ok(HELP.to_string()),
+	    _ => Err(DebugError::Unknown(command.to_string()))
+	}
+    }
+
+    /// Render the instruction at the current pc, or indicate the
+    /// program has halted.
+    fn current(&self, state: &State) -> String {
+	if state.pc < self.program.len() {
+	    format!("=> {}",self.render(state.pc))
+	} else {
+	    format!("halted at pc {}",state.pc)
+	}
+    }
+}
+
+/// Get the name and value of every register of a given machine,
+/// starting with the special registers.
+pub fn registers(state: &State) -> Vec<(String,u64)> {
+    let mut registers = Vec::new();
+    for (name,r) in [("pc",PC),("sp",SP),("flags",FLAGS),("lr",LR)] {
+	registers.push((name.to_string(),state.read_register(r)));
+    }
+    for r in 0..state.registers.len() {
+	let name = state.registers.name(r).map_or(format!("r{}",r),|n| n.to_string());
+	registers.push((name,state.registers.read(r)));
+    }
+    registers
+}
+
+/// Parse a number given in decimal or (with a `0x` prefix)
+/// hexadecimal.
+fn parse(word: &str) -> Result<usize,DebugError> {
+    let r = match word.strip_prefix("0x") {
+	Some(hex) => usize::from_str_radix(hex,16),
+	None => word.parse()
+    };
+    r.map_err(|_| DebugError::Argument(format!("expected number, found \"{}\"",word)))
+}
+
+/// Parse an optional numeric argument, or return a default.
+fn argument(args: &[&str], index: usize, default: usize) -> Result<usize,DebugError> {
+    args.get(index).map_or(Ok(default),|w| parse(w))
+}
+
+/// Parse a required numeric argument.
+fn required(args: &[&str], index: usize) -> Result<usize,DebugError> {
+    let word = args.get(index).ok_or(DebugError::Argument("missing number".to_string()))?;
+    parse(word)
+}
+
+/// Show some bytes of memory, starting at a given address, in rows of
+/// sixteen.
+pub(crate) fn dump(bytes: &[u8], address: usize) -> String {
+    let rows : Vec<String> = bytes.chunks(16).enumerate().map(|(i,row)| {
+	let row : Vec<String> = row.iter().map(|b| format!("{:02x}",b)).collect();
+	format!("{:04x}: {}",address + i * 16,row.join(" "))
+    }).collect();
+    rows.join("\n")
+}
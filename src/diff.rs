@@ -0,0 +1,116 @@
+use std::fmt;
+use crate::insn::InstructionSet;
+
+// =====================================================
+// Changes
+// =====================================================
+
+/// Identifies a single difference between two instruction sets.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Change {
+    /// An instruction was added.
+    Added(String),
+    /// An instruction was removed.
+    Removed(String),
+    /// An instruction was assigned a different opcode.
+    Opcode{mnemonic: String, before: usize, after: usize},
+    /// The format of an instruction was changed (e.g. an operand was
+    /// resized).
+    Format{mnemonic: String, before: String, after: String},
+    /// The semantics (i.e. microcode or required feature) of an
+    /// instruction were changed.
+    Semantics(String),
+    /// The metadata of an instruction was changed.
+    Metadata(String)
+}
+
+impl Change {
+    /// Determine whether this change could break existing binaries.
+    /// That is, whether an instruction previously encoded may now be
+    /// decoded differently, or behave differently.
+    pub fn is_breaking(&self) -> bool {
+	!matches!(self,Change::Added(_)|Change::Metadata(_))
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Change::Added(m) => write!(f,"+ {}",m),
+	    Change::Removed(m) => write!(f,"- {}",m),
+	    Change::Opcode{mnemonic,before,after} => write!(f,"~ {}: opcode {} -> {}",mnemonic,before,after),
+	    Change::Format{mnemonic,before,after} => write!(f,"~ {}: format {} -> {}",mnemonic,before,after),
+	    Change::Semantics(m) => write!(f,"~ {}: semantics changed",m),
+	    Change::Metadata(m) => write!(f,"~ {}: metadata changed",m)
+	}
+    }
+}
+
+// =====================================================
+// Diff
+// =====================================================
+
+/// The differences between two instruction sets (e.g. two versions of
+/// the same instruction set).
+#[derive(Clone,Debug,PartialEq)]
+pub struct IsaDiff {
+    pub changes: Vec<Change>
+}
+
+impl IsaDiff {
+    /// Check whether the two instruction sets are equivalent.
+    pub fn is_empty(&self) -> bool {
+	self.changes.is_empty()
+    }
+    /// Check whether any change could break existing binaries.
+    pub fn is_breaking(&self) -> bool {
+	self.changes.iter().any(|c| c.is_breaking())
+    }
+}
+
+impl fmt::Display for IsaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for c in &self.changes {
+	    writeln!(f,"{}",c)?;
+	}
+	Ok(())
+    }
+}
+
+/// Determine the differences between an instruction set and a later
+/// version of it, where instructions are matched by mnemonic.
+/// Removed instructions are reported first, followed by those added
+/// or changed (in order of the later version).
+pub fn diff(before: &InstructionSet, after: &InstructionSet) -> IsaDiff {
+    let mut changes = Vec::new();
+    for insn in before {
+	if after.get(insn.mnemonic()).is_none() {
+	    changes.push(Change::Removed(insn.mnemonic().to_string()));
+	}
+    }
+    for (j,insn) in after.iter().enumerate() {
+	let mnemonic = insn.mnemonic().to_string();
+	let i = match before.index_of(&mnemonic) {
+	    Some(i) => i,
+	    None => {
+		changes.push(Change::Added(mnemonic));
+		continue;
+	    }
+	};
+	let old = before.instruction(i);
+	if before.opcode(i) != after.opcode(j) {
+	    changes.push(Change::Opcode{mnemonic:mnemonic.clone(),before:before.opcode(i),after:after.opcode(j)});
+	}
+	if old.format() != insn.format() {
+	    let (b,a) = (old.format().label().to_string(),insn.format().label().to_string());
+	    changes.push(Change::Format{mnemonic:mnemonic.clone(),before:b,after:a});
+	}
+	if old.semantic() != insn.semantic() || old.feature() != insn.feature() {
+	    changes.push(Change::Semantics(mnemonic.clone()));
+	}
+	if old.metadata() != insn.metadata() {
+	    changes.push(Change::Metadata(mnemonic));
+	}
+    }
+    IsaDiff{changes}
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::fmt;
+use crate::compile::CompiledSet;
+use crate::disasm::Disassembler;
+use crate::machine::State;
+use crate::program::DecodedProgram;
+use crate::tiered::Tiered;
+
+/// Number of (most recently executed) pcs retained for reporting a
+/// divergence.
+pub const TRACE : usize = 8;
+
+// =====================================================
+// Engines
+// =====================================================
+
+/// An execution back-end, such as the interpreter or the JIT.  Each
+/// step executes one or more instructions from the current pc,
+/// returning how many were executed (which should be at least one),
+/// or a description of the fault raised.
+pub trait Engine {
+    fn step(&mut self, state: &mut State) -> Result<usize,String>;
+}
+
+impl<F:FnMut(&mut State) -> Result<usize,String>> Engine for F {
+    fn step(&mut self, state: &mut State) -> Result<usize,String> {
+	self(state)
+    }
+}
+
+/// The interpreter, executing a given program one instruction at a
+/// time (see `State::try_step()`).
+pub fn interpreter<'a>(program: &'a DecodedProgram<'a>) -> impl Engine + 'a {
+    move |state: &mut State| state.try_step(program).map(|_| 1).map_err(|e| e.to_string())
+}
+
+/// Compiled instructions executing a given program one instruction at
+/// a time (see `CompiledSet::step()`).
+pub fn compiled<'a>(set: &'a CompiledSet<'a>, program: &'a DecodedProgram<'a>) -> impl Engine + 'a {
+    move |state: &mut State| set.step(state,program).map(|_| 1).map_err(|e| e.to_string())
+}
+
+/// Tiered execution, where hot instructions of a given program are
+/// quickened (see `Tiered::step()`).  The program is owned by the
+/// engine, since quickening modifies it.
+pub fn tiered<'a>(mut tiered: Tiered, mut program: DecodedProgram<'a>) -> impl Engine + 'a {
+    move |state: &mut State| tiered.step(state,&mut program).map(|_| 1).map_err(|e| e.to_string())
+}
+
+/// The JIT, executing compiled blocks once they are hot (see
+/// `Jit::step()`).
+#[cfg(feature="jit")]
+pub fn jit<'a>(mut jit: crate::jit::Jit<'a>) -> impl Engine + 'a {
+    move |state: &mut State| jit.step(state).map_err(|e| e.to_string())
+}
+
+// =====================================================
+// Differences
+// =====================================================
+
+/// Identifies a single difference between the states of two machines,
+/// giving the left value followed by the right.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Difference {
+    Pc(usize,usize),
+    Sp(usize,usize),
+    Flags(u64,u64),
+    Lr(usize,usize),
+    Features(u64,u64),
+    /// The machines have different numbers of registers.
+    Registers(usize,usize),
+    Register{index: usize, left: u64, right: u64},
+    /// The machines have different amounts of memory.
+    MemorySize(usize,usize),
+    Memory{address: usize, left: u8, right: u8},
+    /// Only one machine raised a fault.
+    Fault(Option<String>,Option<String>)
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Difference::Pc(l,r) => write!(f,"pc: {:#x} != {:#x}",l,r),
+	    Difference::Sp(l,r) => write!(f,"sp: {:#x} != {:#x}",l,r),
+	    Difference::Flags(l,r) => write!(f,"flags: {:#x} != {:#x}",l,r),
+	    Difference::Lr(l,r) => write!(f,"lr: {:#x} != {:#x}",l,r),
+	    Difference::Features(l,r) => write!(f,"features: {:#x} != {:#x}",l,r),
+	    Difference::Registers(l,r) => write!(f,"registers: {} != {}",l,r),
+	    Difference::Register{index,left,right} => write!(f,"r{}: {:#x} != {:#x}",index,left,right),
+	    Difference::MemorySize(l,r) => write!(f,"memory: {} bytes != {} bytes",l,r),
+	    Difference::Memory{address,left,right} => write!(f,"mem[{:#x}]: {:#04x} != {:#04x}",address,left,right),
+	    Difference::Fault(l,r) => {
+		let (l,r) = (l.as_deref().unwrap_or("none"),r.as_deref().unwrap_or("none"));
+		write!(f,"fault: {} != {}",l,r)
+	    }
+	}
+    }
+}
+
+/// Determine every difference between the states of two machines.
+/// Scratch registers are not compared, since they never survive an
+/// instruction.
+pub fn compare(left: &State, right: &State) -> Vec<Difference> {
+    let mut diffs = Vec::new();
+    if left.pc != right.pc { diffs.push(Difference::Pc(left.pc,right.pc)); }
+    if left.sp != right.sp { diffs.push(Difference::Sp(left.sp,right.sp)); }
+    if left.flags != right.flags { diffs.push(Difference::Flags(left.flags,right.flags)); }
+    if left.lr != right.lr { diffs.push(Difference::Lr(left.lr,right.lr)); }
+    if left.features != right.features { diffs.push(Difference::Features(left.features,right.features)); }
+    let (lr,rr) = (&left.registers,&right.registers);
+    if lr.len() != rr.len() {
+	diffs.push(Difference::Registers(lr.len(),rr.len()));
+    }
+    for index in 0..lr.len().min(rr.len()) {
+	let (l,r) = (lr.read(index),rr.read(index));
+	if l != r { diffs.push(Difference::Register{index,left:l,right:r}); }
+    }
+    let (lm,rm) = (left.data.bytes(),right.data.bytes());
+    if lm.len() != rm.len() {
+	diffs.push(Difference::MemorySize(lm.len(),rm.len()));
+    }
+    for (address,(l,r)) in lm.iter().zip(rm).enumerate() {
+	if l != r { diffs.push(Difference::Memory{address,left:*l,right:*r}); }
+    }
+    diffs
+}
+
+// =====================================================
+// Harness
+// =====================================================
+
+/// The first point at which two engines disagreed, along with the
+/// context needed to investigate it.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Divergence {
+    /// Number of instructions executed by both engines before the
+    /// states last agreed.
+    pub steps: usize,
+    /// The pc from which the engines were last executing.
+    pub pc: usize,
+    /// The instruction at that pc (if it could be decoded).
+    pub instruction: Option<String>,
+    /// Number of instructions executed by each engine when the
+    /// divergence was found.  These differ only when an engine
+    /// executes several instructions at once.
+    pub executed: (usize,usize),
+    /// The pcs most recently executed from (oldest first).
+    pub trace: Vec<usize>,
+    pub differences: Vec<Difference>
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"divergence after {} instructions at pc {:#x}",self.steps,self.pc)?;
+	if let Some(insn) = &self.instruction {
+	    write!(f," ({})",insn)?;
+	}
+	writeln!(f)?;
+	if self.executed.0 != self.executed.1 {
+	    writeln!(f,"  executed: {} != {}",self.executed.0,self.executed.1)?;
+	}
+	let trace : Vec<String> = self.trace.iter().map(|pc| format!("{:#x}",pc)).collect();
+	writeln!(f,"  trace: {}",trace.join(" "))?;
+	for d in &self.differences {
+	    writeln!(f,"  {}",d)?;
+	}
+	Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// The result of running two engines which agreed throughout.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Agreement {
+    /// Number of instructions executed by both engines.
+    pub steps: usize,
+    /// The fault raised by each engine (if they both faulted).
+    pub faults: Option<(String,String)>
+}
+
+/// Runs the same program on two engines in lockstep, comparing their
+/// states after each instruction and reporting the first divergence.
+/// When an engine executes several instructions at once (e.g. a
+/// compiled block), the other is stepped until it catches up before
+/// comparing.  For example:
+///
+/// ```text
+/// let harness = Harness::new(&program).fuel(10_000);
+/// let quick = DecodedProgram::new(&isa,&image);
+/// let mut fast = tiered(Tiered::new(&quick).threshold(0),quick);
+/// harness.run(&mut left,&mut interpreter(&program),&mut right,&mut fast)?;
+/// ```
+pub struct Harness<'a> {
+    program: &'a DecodedProgram<'a>,
+    /// Maximum number of instructions to execute.
+    fuel: usize
+}
+
+impl<'a> Harness<'a> {
+    /// Construct a harness for a given program, which is used to
+    /// describe the instructions executed.
+    pub fn new(program: &'a DecodedProgram<'a>) -> Self {
+	Harness{program,fuel:usize::MAX}
+    }
+    /// Set the maximum number of instructions to execute (default is
+    /// unlimited).
+    pub fn fuel(mut self, fuel: usize) -> Self {
+	self.fuel = fuel;
+	self
+    }
+    /// Execute two engines (each on its own machine, which should
+    /// initially be identical) until the pc leaves the program, both
+    /// engines fault, or the fuel is exhausted.
+    pub fn run<A:Engine+?Sized,B:Engine+?Sized>(&self, left: &mut State, a: &mut A, right: &mut State, b: &mut B) -> Result<Agreement,Divergence> {
+	let mut trace = VecDeque::new();
+	let (mut n,mut m) = (0,0);
+	let differences = compare(left,right);
+	if !differences.is_empty() {
+	    return Err(self.divergence(0,left.pc,(0,0),trace,differences));
+	}
+	loop {
+	    // The states agree at this point
+	    if n >= self.fuel || left.pc >= self.program.len() {
+		return Ok(Agreement{steps:n,faults:None});
+	    }
+	    let (start,pc) = (n,left.pc);
+	    let (mut lf,mut rf) = (None,None);
+	    self.advance(a,left,&mut n,&mut lf);
+	    self.advance(b,right,&mut m,&mut rf);
+	    // Step whichever engine is behind until both have executed
+	    // the same number of instructions (or one cannot continue).
+	    while n != m && lf.is_none() && rf.is_none() {
+		let progress = if n < m {
+		    self.advance(a,left,&mut n,&mut lf)
+		} else {
+		    self.advance(b,right,&mut m,&mut rf)
+		};
+		if !progress { break; }
+	    }
+	    let mut differences = compare(left,right);
+	    match (lf,rf) {
+		(Some(l),Some(r)) if n == m && differences.is_empty() => {
+		    return Ok(Agreement{steps:n,faults:Some((l,r))});
+		}
+		(None,None) => {}
+		(l,r) => differences.push(Difference::Fault(l,r))
+	    }
+	    if n != m || !differences.is_empty() {
+		return Err(self.divergence(start,pc,(n,m),trace,differences));
+	    }
+	    if trace.len() == TRACE { trace.pop_front(); }
+	    trace.push_back(pc);
+	}
+    }
+
+    /// Step a given engine, unless its pc has left the program.
+    /// Returns false if the engine could not be stepped.
+    fn advance<E:Engine+?Sized>(&self, engine: &mut E, state: &mut State, count: &mut usize, fault: &mut Option<String>) -> bool {
+	if state.pc >= self.program.len() {
+	    return false;
+	}
+	match engine.step(state) {
+	    Ok(k) => { *count += k; }
+	    Err(e) => { *fault = Some(e); }
+	}
+	true
+    }
+
+    fn divergence(&self, steps: usize, pc: usize, executed: (usize,usize), trace: VecDeque<usize>, differences: Vec<Difference>) -> Divergence {
+	let instruction = self.describe(pc);
+	Divergence{steps,pc,instruction,executed,trace:trace.into(),differences}
+    }
+
+    fn describe(&self, pc: usize) -> Option<String> {
+	let entry = self.program.get(pc).ok()?;
+	Some(Disassembler::new(self.program.isa()).render(entry.insn,entry.operands))
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+use crate::insn::{FieldKind,InstructionSet,Operand};
+use crate::machine::MachineProfile;
+
+// =====================================================
+// Disassembly
+// =====================================================
+
+/// A single line of disassembly, corresponding to either a decoded
+/// instruction or a byte which could not be decoded.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Line {
+    /// Address of the first byte of this line.
+    pub address: usize,
+    /// Raw bytes making up this line.
+    pub bytes: Vec<u8>,
+    /// Textual rendering of the instruction (e.g. `mov r1, 2`).
+    pub text: String,
+    /// Indicates whether this line shows a pseudo instruction, rather
+    /// than a real instruction.
+    pub pseudo: bool
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let bytes : Vec<String> = self.bytes.iter().map(|b| format!("{:02x}",b)).collect();
+	write!(f,"{:04x}: {}  {}",self.address,bytes.join(" "),self.text)
+    }
+}
+
+// =====================================================
+// Style
+// =====================================================
+
+/// The radix used for writing immediates.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hexadecimal
+}
+
+/// The case used for writing mnemonics.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Case {
+    /// Mnemonics are written as given in the instruction set.
+    Unchanged,
+    Lower,
+    Upper
+}
+
+/// Determines how instructions are written by the disassembler, such
+/// that its output can match the conventional syntax of the target
+/// architecture.  For example, the following writes registers as
+/// `x0`, `x1`, etc, with immediates in hexadecimal:
+///
+/// ```text
+/// let style = DisasmStyle::default().prefix("x").radix(Radix::Hexadecimal);
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct DisasmStyle {
+    /// Names of registers, indexed by register number.  Registers
+    /// without a name are written as the prefix followed by their
+    /// number.
+    pub registers: Vec<String>,
+    /// Prefix used for unnamed registers.
+    pub prefix: String,
+    /// Radix used for immediates.
+    pub radix: Radix,
+    /// Case used for mnemonics.
+    pub case: Case,
+    /// Determines whether operands are written in reverse order (e.g.
+    /// source before destination).
+    pub reversed: bool
+}
+
+impl DisasmStyle {
+    /// Set the names of registers (e.g. `["A","B","C"]`).
+    pub fn registers(mut self, names: &[&str]) -> Self {
+	self.registers = names.iter().map(|n| n.to_string()).collect();
+	self
+    }
+    /// Use the register names declared by a given machine profile.
+    pub fn profile(mut self, profile: &MachineProfile) -> Self {
+	self.registers = profile.names.clone();
+	self
+    }
+    /// Set the prefix used for unnamed registers.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+	self.prefix = prefix.to_string();
+	self
+    }
+    pub fn radix(mut self, radix: Radix) -> Self {
+	self.radix = radix;
+	self
+    }
+    pub fn case(mut self, case: Case) -> Self {
+	self.case = case;
+	self
+    }
+    pub fn reversed(mut self, reversed: bool) -> Self {
+	self.reversed = reversed;
+	self
+    }
+    /// Write the register with a given number.
+    pub fn register(&self, index: usize) -> String {
+	match self.registers.get(index) {
+	    Some(name) => name.clone(),
+	    None => format!("{}{}",self.prefix,index)
+	}
+    }
+    /// Write a given immediate value.
+    pub fn immediate(&self, value: i128) -> String {
+	let (sign,v) = if value < 0 { ("-",value.unsigned_abs()) } else { ("",value as u128) };
+	match self.radix {
+	    Radix::Binary => format!("{}0b{:b}",sign,v),
+	    Radix::Octal => format!("{}0o{:o}",sign,v),
+	    Radix::Decimal => format!("{}{}",sign,v),
+	    Radix::Hexadecimal => format!("{}0x{:x}",sign,v)
+	}
+    }
+    /// Write a given mnemonic.
+    pub fn mnemonic(&self, mnemonic: &str) -> String {
+	match self.case {
+	    Case::Unchanged => mnemonic.to_string(),
+	    Case::Lower => mnemonic.to_lowercase(),
+	    Case::Upper => mnemonic.to_uppercase()
+	}
+    }
+}
+
+impl Default for DisasmStyle {
+    fn default() -> Self {
+	DisasmStyle{registers:Vec::new(),prefix:"r".to_string(),radix:Radix::Decimal,case:Case::Unchanged,reversed:false}
+    }
+}
+
+// =====================================================
+// Disassembler
+// =====================================================
+
+/// Responsible for turning a byte image back into a human-readable
+/// listing of instructions.  Operands are rendered according to the
+/// kind of field they occupy and the chosen style, such that (by
+/// default) registers are written as `r3` and immediates as decimal
+/// numbers.
+pub struct Disassembler<'a> {
+    /// Instruction set used for decoding.
+    isa: &'a InstructionSet<'a>,
+    /// Determines how instructions are written.
+    style: DisasmStyle,
+    /// Determines whether sequences of instructions matching a pseudo
+    /// instruction are shown as that pseudo instruction.
+    pseudos: bool
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	Disassembler{isa,style:DisasmStyle::default(),pseudos:true}
+    }
+    /// Set whether immediates are written in hexadecimal (default is
+    /// decimal).
+    pub fn hex(mut self, hex: bool) -> Self {
+	self.style.radix = if hex { Radix::Hexadecimal } else { Radix::Decimal };
+	self
+    }
+    /// Set the style in which instructions are written.
+    pub fn style(mut self, style: DisasmStyle) -> Self {
+	self.style = style;
+	self
+    }
+    /// Set whether sequences of instructions matching a pseudo
+    /// instruction are shown as that pseudo instruction (default is
+    /// true).
+    pub fn pseudos(mut self, pseudos: bool) -> Self {
+	self.pseudos = pseudos;
+	self
+    }
+    /// Render a given instruction (identified by its index) with the
+    /// given operands.
+    pub fn render(&self, insn: usize, operands: &[usize]) -> String {
+	let insn = self.isa.instruction(insn);
+	let fields = insn.format().operands();
+	let ops : Vec<(FieldKind,usize)> = fields.iter().map(|f| f.kind()).zip(operands.iter().copied()).collect();
+	self.render_with(insn.mnemonic(),&ops)
+    }
+    /// Disassemble a byte image which starts at a given address.  Any
+    /// bytes which cannot be decoded are rendered individually as
+    /// `.byte` directives.
+    pub fn disassemble(&self, image: &[u8], address: usize) -> Vec<Line> {
+	self.disassemble_range(image,address,0,image.len()).0.into_iter().map(|(_,l)| l).collect()
+    }
+    /// Disassemble a byte image as for `disassemble()`, but splitting
+    /// it at the given offsets (e.g. known entry points or alignment
+    /// boundaries) and disassembling each part in parallel.  This
+    /// gives the same result as `disassemble()`, even if some
+    /// instruction (or pseudo instruction) straddles a boundary.
+    #[cfg(feature="parallel")]
+    pub fn disassemble_parallel(&self, image: &[u8], address: usize, boundaries: &[usize]) -> Vec<Line> {
+	let lines = crate::parallel::split(image.len(),boundaries,|start,end| self.disassemble_range(image,address,start,end));
+	lines.into_iter().map(|(_,l)| l).collect()
+    }
+    /// Disassemble a byte image which starts at a given address into a
+    /// textual listing, with one line per instruction.  The raw bytes
+    /// are padded so that mnemonics are aligned.
+    pub fn listing(&self, image: &[u8], address: usize) -> String {
+	let lines = self.disassemble(image,address);
+	let width = lines.iter().map(|l| l.bytes.len()).max().unwrap_or(0);
+	let mut out = String::new();
+	for l in lines {
+	    let bytes : Vec<String> = l.bytes.iter().map(|b| format!("{:02x}",b)).collect();
+	    out.push_str(&format!("{:04x}: {:w$}  {}\n",l.address,bytes.join(" "),l.text,w = (3*width).saturating_sub(1)));
+	}
+	out
+    }
+
+    /// Disassemble the lines of a byte image (which starts at a given
+    /// address) from a given offset until reaching a given end,
+    /// returning them (with their offsets) and the offset reached.
+    fn disassemble_range(&self, image: &[u8], address: usize, mut offset: usize, end: usize) -> (Vec<(usize,Line)>,usize) {
+	let mut lines = Vec::new();
+	while offset < end {
+	    let pseudo = if self.pseudos { self.match_pseudo(&image[offset..]) } else { None };
+	    let (length,text,pseudo) = match (pseudo,self.isa.decode(&image[offset..])) {
+		(Some((length,text)),_) => (length,text,true),
+		(None,Ok((insn,operands))) => {
+		    let width = self.isa.instruction(insn).format().length();
+		    (width,self.render(insn,&operands),false)
+		}
+		(None,Err(_)) => {
+		    (1,format!(".byte 0x{:02x}",image[offset]),false)
+		}
+	    };
+	    let bytes = image[offset..offset+length].to_vec();
+	    lines.push((offset,Line{address: address+offset,bytes,text,pseudo}));
+	    offset += length;
+	}
+	(lines,offset)
+    }
+
+    /// Attempt to match the instructions at the start of a given image
+    /// against the expansion of a pseudo instruction, returning the
+    /// number of bytes matched and the rendered pseudo instruction.
+    /// Longer expansions are preferred over shorter ones.
+    fn match_pseudo(&self, image: &[u8]) -> Option<(usize,String)> {
+	let mut pseudos : Vec<_> = self.isa.pseudos().iter().collect();
+	pseudos.sort_by_key(|p| std::cmp::Reverse(p.expansion().len()));
+	'outer: for p in pseudos {
+	    let mut vars : Vec<Option<(FieldKind,usize)>> = vec![None; p.arity()];
+	    let mut offset = 0;
+	    for (mnemonic,ops) in p.expansion() {
+		let (insn,operands) = match image.get(offset..).map(|bs| self.isa.decode(bs)) {
+		    Some(Ok(d)) => d,
+		    _ => continue 'outer
+		};
+		let insn = self.isa.instruction(insn);
+		if insn.mnemonic() != mnemonic { continue 'outer; }
+		for ((op,value),field) in ops.iter().zip(&operands).zip(insn.format().operands()) {
+		    match op {
+			Operand::Var(v) => {
+			    match vars[*v] {
+				None => { vars[*v] = Some((field.kind(),*value)); }
+				Some((_,w)) if w == *value => {}
+				Some(_) => continue 'outer
+			    }
+			}
+			op if op.is_constant() => {
+			    if op.as_usize(&[]) != *value { continue 'outer; }
+			}
+			// Compound expressions over operands cannot be inverted
+			_ => continue 'outer
+		    }
+		}
+		offset += insn.format().length();
+	    }
+	    if let Some(ops) = vars.into_iter().collect::<Option<Vec<_>>>() {
+		return Some((offset,self.render_with(p.mnemonic(),&ops)));
+	    }
+	}
+	None
+    }
+
+    fn render_with(&self, mnemonic: &str, operands: &[(FieldKind,usize)]) -> String {
+	let mut ops : Vec<String> = operands.iter().map(|(kind,value)| match kind {
+	    FieldKind::Register => self.style.register(*value),
+	    FieldKind::Immediate => self.style.immediate(*value as i128),
+	    FieldKind::SignedImmediate => self.style.immediate(*value as isize as i128)
+	}).collect();
+	if self.style.reversed {
+	    ops.reverse();
+	}
+	let mnemonic = self.style.mnemonic(mnemonic);
+	if ops.is_empty() {
+	    mnemonic
+	} else {
+	    format!("{} {}",mnemonic,ops.join(", "))
+	}
+    }
+}
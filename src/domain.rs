@@ -1,4 +1,5 @@
-use num::BigUint;
+use core::fmt;
+use num::{BigUint,ToPrimitive};
 
 /// Used for converting a given domain into a physical count of
 /// elements in that domain.  For example, the domain of 2bits would
@@ -7,8 +8,31 @@ use num::BigUint;
 pub trait Countable {
     /// Calculate the number of elements in the given domain.
     fn count(&self) -> BigUint;
+    /// Calculate the number of elements in the given domain, or
+    /// `None` if this does not fit into a `u128`.  Implementations
+    /// should override this to avoid allocating a `BigUint`.
+    fn checked_count(&self) -> Option<u128> {
+	self.count().to_u128()
+    }
+    /// Calculate the number of elements in the given domain, or
+    /// `None` if this does not fit into a `u64`.
+    fn checked_count_u64(&self) -> Option<u64> {
+	self.checked_count().and_then(|c| u64::try_from(c).ok())
+    }
 }
 
+/// Indicates an attempt to construct a domain of zero bits (or bytes).
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct ZeroSized;
+
+impl fmt::Display for ZeroSized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"domain has zero size")
+    }
+}
+
+impl core::error::Error for ZeroSized {}
+
 // ================================================================
 // Bits
 // ================================================================
@@ -24,28 +48,53 @@ pub const EIGHT_BITS : Bits = Bits{value:8};
 pub const NINE_BITS : Bits = Bits{value:9};
 pub const TEN_BITS : Bits = Bits{value:10};
 
-#[derive(Clone,Copy,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize),serde(transparent))]
 pub struct Bits {
     // INVARIANT: value > 0
     value : u8,
 }
 
+impl Bits {
+    /// Construct a domain of a given number of bits.  This panics if
+    /// the number is zero (see `try_new()`).
+    pub const fn new(value: u8) -> Self {
+	match Self::try_new(value) {
+	    Ok(d) => d,
+	    Err(_) => panic!("domain has zero size")
+	}
+    }
+    /// Construct a domain as for `new()`, but failing with `ZeroSized`
+    /// rather than panicking.
+    pub const fn try_new(value: u8) -> Result<Self,ZeroSized> {
+	if value == 0 {
+	    return Err(ZeroSized);
+	}
+	Ok(Bits{value})
+    }
+    /// Get the number of bits in this domain.
+    pub const fn value(&self) -> u8 {
+	self.value
+    }
+    /// Calculate the number of elements in this domain (i.e. `2^n`),
+    /// or `None` if this does not fit into a `u128`.
+    pub const fn checked_count(&self) -> Option<u128> {
+	1u128.checked_shl(self.value as u32)
+    }
+}
+
 impl From<u8> for Bits {
     fn from(value:u8) -> Self {
-	assert!(value != 0);
-	Bits{value}
+	Bits::new(value)
     }
 }
 
 impl Countable for Bits {
     fn count(&self) -> BigUint {
-	let mut count = BigUint::from(1u32);
-	//
-	for i in 0 .. self.value {
-	    count = count * 2u32;
-	}
-	//
-	count
+	BigUint::from(1u32) << self.value
+    }
+    fn checked_count(&self) -> Option<u128> {
+	Bits::checked_count(self)
     }
 }
 
@@ -56,27 +105,56 @@ impl Countable for Bits {
 pub const ONE_BYTE : Bytes = Bytes{value:1};
 pub const TWO_BYTES : Bytes = Bytes{value:2};
 
-#[derive(Clone,Copy,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize),serde(transparent))]
 pub struct Bytes {
     // INVARIANT: value > 0    
     value : u8,
 }
 
+impl Bytes {
+    /// Construct a domain of a given number of bytes.  This panics if
+    /// the number is zero (see `try_new()`).
+    pub const fn new(value: u8) -> Self {
+	match Self::try_new(value) {
+	    Ok(d) => d,
+	    Err(_) => panic!("domain has zero size")
+	}
+    }
+    /// Construct a domain as for `new()`, but failing with `ZeroSized`
+    /// rather than panicking.
+    pub const fn try_new(value: u8) -> Result<Self,ZeroSized> {
+	if value == 0 {
+	    return Err(ZeroSized);
+	}
+	Ok(Bytes{value})
+    }
+    /// Get the number of bytes in this domain.
+    pub const fn value(&self) -> u8 {
+	self.value
+    }
+    /// Get the number of bits in this domain.
+    pub const fn bits(&self) -> u32 {
+	8 * self.value as u32
+    }
+    /// Calculate the number of elements in this domain (i.e.
+    /// `256^n`), or `None` if this does not fit into a `u128`.
+    pub const fn checked_count(&self) -> Option<u128> {
+	1u128.checked_shl(self.bits())
+    }
+}
+
 impl From<u8> for Bytes {
     fn from(value:u8) -> Self {
-	assert!(value != 0);
-	Bytes{value}
+	Bytes::new(value)
     }
 }
 
 impl Countable for Bytes {
     fn count(&self) -> BigUint {
-	let mut count = BigUint::from(1u32);
-	//
-	for i in 0 .. self.value {
-	    count = count * 256u32;
-	}
-	//
-	count
+	BigUint::from(1u32) << self.bits()
+    }
+    fn checked_count(&self) -> Option<u128> {
+	Bytes::checked_count(self)
     }
 }
@@ -0,0 +1,251 @@
+use std::fmt::Write;
+use std::ops::Range;
+use crate::debug;
+use crate::disasm::Disassembler;
+use crate::machine::State;
+use crate::program::DecodedProgram;
+
+/// Number of general purpose registers shown on each line (by
+/// default).
+const COLUMNS : usize = 4;
+
+/// Number of bytes of memory shown when no region is selected.
+const MEMORY : usize = 64;
+
+// =====================================================
+// Sections
+// =====================================================
+
+/// A titled part of a dump, which is either a table (e.g. of
+/// registers) or preformatted lines (e.g. a hexdump).
+enum Section {
+    Table(String,Vec<Vec<String>>),
+    Lines(String,Vec<String>)
+}
+
+// =====================================================
+// Dump
+// =====================================================
+
+/// Renders the complete state of a machine in a human-readable form,
+/// either as text or as a standalone HTML report, for inclusion in
+/// bug reports and CI failure artifacts.  This consists of the
+/// registers (grouped), the flags (decoded), selected regions of
+/// memory and the instructions around the pc.  For example:
+///
+/// ```text
+/// let dump = StateDump::new().program(&program)
+///     .flags(&[("C",0),("Z",1),("N",2)])
+///     .group("args",4..8)
+///     .region("stack",0x100..0x140);
+/// eprintln!("{}",dump.text(&state));
+/// std::fs::write("failure.html",dump.html(&state))?;
+/// ```
+pub struct StateDump<'a> {
+    program: Option<(&'a DecodedProgram<'a>,Disassembler<'a>)>,
+    /// Name and bit of each flag.
+    flags: Vec<(String,u8)>,
+    /// Named groups of general purpose registers.
+    groups: Vec<(String,Range<usize>)>,
+    /// Named regions of memory.
+    regions: Vec<(String,Range<usize>)>,
+    /// Number of instructions shown either side of the pc.
+    context: usize
+}
+
+impl<'a> StateDump<'a> {
+    pub fn new() -> Self {
+	StateDump{program:None,flags:Vec::new(),groups:Vec::new(),regions:Vec::new(),context:3}
+    }
+    /// Show the instructions of a given program around the pc.
+    pub fn program(mut self, program: &'a DecodedProgram<'a>) -> Self {
+	self.program = Some((program,Disassembler::new(program.isa())));
+	self
+    }
+    /// Set the disassembler used to show instructions (which requires
+    /// a program).
+    pub fn disassembler(mut self, disasm: Disassembler<'a>) -> Self {
+	if let Some((_,d)) = &mut self.program {
+	    *d = disasm;
+	}
+	self
+    }
+    /// Name the bits of the flags register, such that each is decoded
+    /// (e.g. `&[("C",0),("Z",1)]`).
+    pub fn flags(mut self, flags: &[(&str,u8)]) -> Self {
+	self.flags = flags.iter().map(|(n,b)| (n.to_string(),*b)).collect();
+	self
+    }
+    /// Show a range of general purpose registers together under a
+    /// given name.  Registers not in any group are shown as
+    /// `general`.
+    pub fn group(mut self, name: &str, registers: Range<usize>) -> Self {
+	self.groups.push((name.to_string(),registers));
+	self
+    }
+    /// Show a range of memory under a given name.  When no region is
+    /// selected, the start of memory is shown.
+    pub fn region(mut self, name: &str, bytes: Range<usize>) -> Self {
+	self.regions.push((name.to_string(),bytes));
+	self
+    }
+    /// Set the number of instructions shown either side of the pc
+    /// (default is 3).
+    pub fn context(mut self, context: usize) -> Self {
+	self.context = context;
+	self
+    }
+    /// Render the state of a given machine as text.
+    pub fn text(&self, state: &State) -> String {
+	let mut out = String::new();
+	for section in self.sections(state) {
+	    match section {
+		Section::Table(title,rows) => {
+		    writeln!(out,"== {} ==",title).unwrap();
+		    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+		    let widths : Vec<usize> = (0..columns).map(|c| {
+			rows.iter().filter_map(|r| r.get(c)).map(|s| s.len()).max().unwrap_or(0)
+		    }).collect();
+		    for row in rows {
+			let cells : Vec<String> = row.iter().zip(&widths).map(|(s,w)| format!("{:<w$}",s,w=w)).collect();
+			writeln!(out,"{}",cells.join("  ").trim_end()).unwrap();
+		    }
+		}
+		Section::Lines(title,lines) => {
+		    writeln!(out,"== {} ==",title).unwrap();
+		    for line in lines {
+			writeln!(out,"{}",line).unwrap();
+		    }
+		}
+	    }
+	}
+	out
+    }
+    /// Render the state of a given machine as a standalone HTML
+    /// document.
+    pub fn html(&self, state: &State) -> String {
+	let mut out = String::new();
+	out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Machine state</title>\n");
+	out.push_str("<style>body{font-family:monospace} td,th{padding:0 1em 0 0;text-align:left}</style>\n");
+	out.push_str("</head>\n<body>\n");
+	for section in self.sections(state) {
+	    match section {
+		Section::Table(title,rows) => {
+		    writeln!(out,"<h2>{}</h2>\n<table>",escape(&title)).unwrap();
+		    for row in rows {
+			let (head,cells) = row.split_first().unwrap();
+			write!(out,"<tr><th>{}</th>",escape(head)).unwrap();
+			for c in cells {
+			    write!(out,"<td>{}</td>",escape(c)).unwrap();
+			}
+			out.push_str("</tr>\n");
+		    }
+		    out.push_str("</table>\n");
+		}
+		Section::Lines(title,lines) => {
+		    writeln!(out,"<h2>{}</h2>\n<pre>",escape(&title)).unwrap();
+		    for line in lines {
+			if line.starts_with("=>") {
+			    writeln!(out,"<b>{}</b>",escape(&line)).unwrap();
+			} else {
+			    writeln!(out,"{}",escape(&line)).unwrap();
+			}
+		    }
+		    out.push_str("</pre>\n");
+		}
+	    }
+	}
+	out.push_str("</body>\n</html>\n");
+	out
+    }
+
+    fn sections(&self, state: &State) -> Vec<Section> {
+	let mut sections = vec![self.registers(state)];
+	if !self.flags.is_empty() {
+	    sections.push(self.decode_flags(state));
+	}
+	sections.push(self.memory(state));
+	if let Some((program,disasm)) = &self.program {
+	    sections.push(self.disassembly(state,program,disasm));
+	}
+	sections
+    }
+
+    fn registers(&self, state: &State) -> Section {
+	let mut rows = Vec::new();
+	let named = debug::registers(state);
+	let (special,general) = named.split_at(4);
+	let mut row = vec!["special".to_string()];
+	row.extend(special.iter().map(|(n,v)| format!("{}={:#x}",n,v)));
+	rows.push(row);
+	// Pad each value to the width of a register
+	let digits = 2 * state.registers.width().bytes();
+	let cell = |r: usize| format!("{}={:#0w$x}",general[r].0,general[r].1,w=digits+2);
+	for (name,range) in &self.groups {
+	    let range = range.start.min(general.len())..range.end.min(general.len());
+	    for (i,chunk) in range.collect::<Vec<_>>().chunks(COLUMNS).enumerate() {
+		let mut row = vec![if i == 0 { name.clone() } else { String::new() }];
+		row.extend(chunk.iter().map(|r| cell(*r)));
+		rows.push(row);
+	    }
+	}
+	let ungrouped : Vec<usize> = (0..general.len()).filter(|r| !self.groups.iter().any(|(_,g)| g.contains(r))).collect();
+	for (i,chunk) in ungrouped.chunks(COLUMNS).enumerate() {
+	    let mut row = vec![if i == 0 { "general".to_string() } else { String::new() }];
+	    row.extend(chunk.iter().map(|r| cell(*r)));
+	    rows.push(row);
+	}
+	Section::Table("Registers".to_string(),rows)
+    }
+
+    fn decode_flags(&self, state: &State) -> Section {
+	let mut row = vec!["flags".to_string(),format!("{:#x}",state.flags)];
+	for (name,bit) in &self.flags {
+	    let set = state.flags.checked_shr(*bit as u32).unwrap_or(0) & 1;
+	    row.push(format!("{}={}",name,set));
+	}
+	Section::Table("Flags".to_string(),vec![row])
+    }
+
+    fn memory(&self, state: &State) -> Section {
+	let mut lines = Vec::new();
+	let default = [("memory".to_string(),0..MEMORY.min(state.data.len()))];
+	let regions = if self.regions.is_empty() { &default[..] } else { &self.regions[..] };
+	for (name,range) in regions {
+	    lines.push(format!("{} ({:#x}..{:#x}):",name,range.start,range.end));
+	    match state.data.bytes().get(range.clone()) {
+		Some(bytes) if !bytes.is_empty() => lines.push(debug::dump(bytes,range.start)),
+		Some(_) => lines.push("(empty)".to_string()),
+		None => lines.push("(out of bounds)".to_string())
+	    }
+	}
+	Section::Lines("Memory".to_string(),lines)
+    }
+
+    fn disassembly(&self, state: &State, program: &DecodedProgram, disasm: &Disassembler) -> Section {
+	let mut lines = Vec::new();
+	let end = state.pc.saturating_add(self.context + 1).min(program.len());
+	for pc in state.pc.saturating_sub(self.context)..end {
+	    let marker = if pc == state.pc { "=>" } else { "  " };
+	    let text = match program.get(pc) {
+		Ok(e) => format!("{:04x}: {}",e.offset,disasm.render(e.insn,e.operands)),
+		Err(e) => e.to_string()
+	    };
+	    lines.push(format!("{} {}",marker,text));
+	}
+	if state.pc >= program.len() {
+	    lines.push(format!("halted at pc {}",state.pc));
+	}
+	Section::Lines("Disassembly".to_string(),lines)
+    }
+}
+
+impl Default for StateDump<'_> {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;").replace('"',"&quot;")
+}
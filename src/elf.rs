@@ -0,0 +1,173 @@
+use std::fmt;
+use crate::machine::Memory;
+
+/// Size (in bytes) of an ELF32 file header.
+const EHDR_SIZE : usize = 52;
+/// Size (in bytes) of an ELF32 program header.
+const PHDR_SIZE : usize = 32;
+/// Program header type identifying a loadable segment.
+const PT_LOAD : u32 = 1;
+/// Segment flags (i.e. readable and executable).
+const PF_RX : u32 = 0x5;
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum ElfError {
+    /// The file ended unexpectedly.
+    Truncated,
+    /// The file does not begin with the ELF magic number.
+    BadMagic,
+    /// The file is not a little-endian, 32-bit ELF file.
+    Unsupported,
+    /// A segment does not fit into the given memory.
+    OutOfBounds{address: usize, length: usize}
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    ElfError::Truncated => write!(f,"truncated ELF file"),
+	    ElfError::BadMagic => write!(f,"not an ELF file"),
+	    ElfError::Unsupported => write!(f,"unsupported ELF file (expected 32-bit little-endian)"),
+	    ElfError::OutOfBounds{address,length} => write!(f,"segment at {:#x} ({} bytes) exceeds memory",address,length)
+	}
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+// =====================================================
+// ELF
+// =====================================================
+
+/// A loadable segment within an ELF file.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Segment {
+    /// The address at which this segment is loaded.
+    pub address: usize,
+    /// The bytes making up this segment.
+    pub bytes: Vec<u8>,
+    /// The size (in bytes) of this segment once loaded.  Any bytes
+    /// beyond those given are zero.
+    pub size: usize
+}
+
+/// A minimal (32-bit, little-endian) ELF executable consisting of
+/// loadable segments only.  Since instruction sets built with this
+/// crate have no official machine number, a custom one is given.
+/// For example, an assembled program can be wrapped as follows:
+///
+/// ```text
+/// let elf = Elf::new(0xBEEF,0).segment(0x100,program.bytes());
+/// std::fs::write("prog.elf",elf.to_bytes())?;
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct Elf {
+    /// The machine number (`e_machine`).
+    pub machine: u16,
+    /// The entry point (`e_entry`).
+    pub entry: usize,
+    /// The loadable segments.
+    pub segments: Vec<Segment>
+}
+
+impl Elf {
+    pub fn new(machine: u16, entry: usize) -> Self {
+	Elf{machine,entry,segments:Vec::new()}
+    }
+    /// Add a segment to be loaded at a given address.
+    pub fn segment(mut self, address: usize, bytes: &[u8]) -> Self {
+	self.segments.push(Segment{address,bytes:bytes.to_vec(),size:bytes.len()});
+	self
+    }
+    /// Encode this file as bytes.  Program headers immediately follow
+    /// the file header, after which come the contents of each segment.
+    pub fn to_bytes(&self) -> Vec<u8> {
+	let n = self.segments.len();
+	let mut out = Vec::new();
+	// File header
+	out.extend_from_slice(&[0x7F,b'E',b'L',b'F',1,1,1,0,0,0,0,0,0,0,0,0]);
+	out.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+	out.extend_from_slice(&self.machine.to_le_bytes());
+	out.extend_from_slice(&1u32.to_le_bytes());
+	out.extend_from_slice(&(self.entry as u32).to_le_bytes());
+	out.extend_from_slice(&(EHDR_SIZE as u32).to_le_bytes());
+	out.extend_from_slice(&0u32.to_le_bytes()); // e_shoff
+	out.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+	out.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+	out.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+	out.extend_from_slice(&(n as u16).to_le_bytes());
+	out.extend_from_slice(&[0;6]); // e_shentsize, e_shnum, e_shstrndx
+	// Program headers
+	let mut offset = EHDR_SIZE + (n * PHDR_SIZE);
+	for s in &self.segments {
+	    for w in [PT_LOAD,offset as u32,s.address as u32,s.address as u32,s.bytes.len() as u32,s.size as u32,PF_RX,1] {
+		out.extend_from_slice(&w.to_le_bytes());
+	    }
+	    offset += s.bytes.len();
+	}
+	// Segment contents
+	for s in &self.segments {
+	    out.extend_from_slice(&s.bytes);
+	}
+	out
+    }
+    /// Decode an ELF file from bytes, extracting its loadable
+    /// segments.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self,ElfError> {
+	if bytes.len() < EHDR_SIZE {
+	    return Err(ElfError::Truncated);
+	} else if bytes[0..4] != [0x7F,b'E',b'L',b'F'] {
+	    return Err(ElfError::BadMagic);
+	} else if bytes[4] != 1 || bytes[5] != 1 {
+	    return Err(ElfError::Unsupported);
+	}
+	let machine = read_u16(bytes,18)?;
+	let entry = read_u32(bytes,24)? as usize;
+	let phoff = read_u32(bytes,28)? as usize;
+	let phentsize = read_u16(bytes,42)? as usize;
+	let phnum = read_u16(bytes,44)? as usize;
+	let mut segments = Vec::new();
+	for i in 0..phnum {
+	    let h = phoff + (i * phentsize);
+	    if read_u32(bytes,h)? != PT_LOAD { continue; }
+	    let offset = read_u32(bytes,h+4)? as usize;
+	    let address = read_u32(bytes,h+8)? as usize;
+	    let filesz = read_u32(bytes,h+16)? as usize;
+	    let size = read_u32(bytes,h+20)? as usize;
+	    let contents = bytes.get(offset..offset+filesz).ok_or(ElfError::Truncated)?;
+	    segments.push(Segment{address,bytes:contents.to_vec(),size:size.max(filesz)});
+	}
+	Ok(Elf{machine,entry,segments})
+    }
+    /// Copy every segment into memory at its address, returning the
+    /// entry point.
+    pub fn load(&self, memory: &mut Memory) -> Result<usize,ElfError> {
+	for s in &self.segments {
+	    if s.address + s.size > memory.len() {
+		return Err(ElfError::OutOfBounds{address:s.address,length:s.size});
+	    }
+	    for i in 0..s.size {
+		memory.write_u8(s.address + i,s.bytes.get(i).copied().unwrap_or(0));
+	    }
+	}
+	Ok(self.entry)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16,ElfError> {
+    match bytes.get(offset..offset+2) {
+	Some(b) => Ok(u16::from_le_bytes([b[0],b[1]])),
+	None => Err(ElfError::Truncated)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32,ElfError> {
+    match bytes.get(offset..offset+4) {
+	Some(b) => Ok(u32::from_le_bytes([b[0],b[1],b[2],b[3]])),
+	None => Err(ElfError::Truncated)
+    }
+}
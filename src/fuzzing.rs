@@ -0,0 +1,56 @@
+use alloc::vec;
+use crate::insn::{FormatCache,InstructionSet};
+use crate::machine::{MachineError,State};
+use crate::program::DecodedProgram;
+
+/// Size (in bytes) of the memory given to a program executed by
+/// `execute_arbitrary_program_with_fuel()`.
+pub const MEMORY : usize = 256;
+
+// =====================================================
+// Fuzzing
+// =====================================================
+
+// These entry points accept arbitrary input and are deterministic, so
+// that any panic they raise (for a well-formed instruction set) is a
+// bug.  They are intended to be called from fuzz targets, such as
+// those of cargo-fuzz:
+//
+// ```text
+// fuzz_target!(|data: &[u8]| {
+//     let isa = chip8::isa();
+//     virmin::fuzzing::decode_arbitrary_bytes(&isa,data);
+//     let _ = virmin::fuzzing::execute_arbitrary_program_with_fuel(&isa,data,1000);
+// });
+// ```
+
+/// Decode a given sequence of bytes in every way available: from each
+/// offset (with and without a format cache), and as a whole program.
+/// Returns the number of instructions in the decoded program (i.e.
+/// excluding bytes which could not be decoded).
+pub fn decode_arbitrary_bytes(isa: &InstructionSet, bytes: &[u8]) -> usize {
+    let mut cache = FormatCache::default();
+    for offset in 0..bytes.len() {
+	let uncached = isa.decode(&bytes[offset..]);
+	let cached = isa.decode_cached(&bytes[offset..],&mut cache);
+	debug_assert_eq!(uncached,cached);
+    }
+    let program = DecodedProgram::new(isa,bytes);
+    (0..program.len()).filter(|pc| program.get(*pc).is_ok()).count()
+}
+
+/// Decode a given sequence of bytes as a program and execute it from
+/// pc zero, until the pc leaves the program or a given amount of fuel
+/// (i.e. number of instructions) has been consumed.  The program has
+/// `MEMORY` bytes of memory, initially holding (a prefix of) the
+/// bytes themselves.  Faults (e.g. an undecodable instruction, or an
+/// access beyond memory) are reported as errors.  Returns the number
+/// of instructions executed.
+pub fn execute_arbitrary_program_with_fuel(isa: &InstructionSet, bytes: &[u8], fuel: usize) -> Result<usize,MachineError> {
+    let program = DecodedProgram::new(isa,bytes);
+    let mut memory = vec![0u8;MEMORY];
+    let n = bytes.len().min(MEMORY);
+    memory[..n].copy_from_slice(&bytes[..n]);
+    let mut state = State::new(0,&mut memory);
+    state.run(&program,fuel)
+}
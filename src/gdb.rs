@@ -0,0 +1,306 @@
+use std::io::{self,Read,Write};
+use crate::debug::{Debugger,Stop};
+use crate::machine::{MachineProfile,State,Width,FLAGS,LR,PC,SP};
+use crate::program::DecodedProgram;
+
+/// Stop reply indicating the target stopped on a trap (e.g. after a
+/// single step, or at a breakpoint).
+const SIGTRAP : &str = "S05";
+/// Stop reply indicating an instruction could not be executed.
+const SIGILL : &str = "S04";
+/// Stop reply indicating the program exited (i.e. the pc left it).
+const EXITED : &str = "W00";
+/// Error reply for malformed requests (or memory out of bounds).
+const ERROR : &str = "E01";
+
+// =====================================================
+// GDB Remote Serial Protocol
+// =====================================================
+
+/// A server implementing the GDB remote serial protocol over the
+/// interpreter, such that GDB (or any frontend using it) can debug a
+/// program.  This supports reading and writing registers and memory,
+/// software breakpoints, single stepping and continuing.  Registers
+/// are described to GDB using a target description, where general
+/// purpose registers are named according to a machine profile (if
+/// given) and followed by `pc`, `sp`, `flags` and `lr`.  Since the
+/// pc of this machine indexes instructions, GDB instead sees the
+/// offset of each instruction within the image.  For example:
+///
+/// ```text
+/// let listener = TcpListener::bind("127.0.0.1:1234")?;
+/// let (stream,_) = listener.accept()?;
+/// GdbStub::new(&program).profile(&profile).serve(stream,&mut state)?;
+/// ```
+///
+/// Then, in GDB, `target remote :1234`.  Continuing cannot be
+/// interrupted, hence runs until a breakpoint is reached or the pc
+/// leaves the program.
+pub struct GdbStub<'a> {
+    debugger: Debugger<'a>,
+    program: &'a DecodedProgram<'a>,
+    /// Names of general purpose registers, indexed by register number.
+    names: Vec<String>,
+    /// Indicates acknowledgements are no longer sent (see
+    /// `QStartNoAckMode`).
+    no_ack: bool,
+    /// Indicates the session has ended (e.g. GDB detached).
+    done: bool
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(program: &'a DecodedProgram<'a>) -> Self {
+	GdbStub{debugger:Debugger::new(program),program,names:Vec::new(),no_ack:false,done:false}
+    }
+    /// Name registers according to a given machine profile.
+    pub fn profile(mut self, profile: &MachineProfile) -> Self {
+	self.names = profile.names.clone();
+	self
+    }
+    /// Serve a single GDB session over a given stream (e.g. a TCP
+    /// connection), until GDB detaches, kills the target or closes
+    /// the stream.
+    pub fn serve<S:Read+Write>(&mut self, mut stream: S, state: &mut State) -> io::Result<()> {
+	let mut byte = [0u8];
+	while !self.done {
+	    if stream.read(&mut byte)? == 0 {
+		break;
+	    } else if byte[0] != b'$' {
+		// Acknowledgements and interrupts are ignored
+		continue;
+	    }
+	    let mut data = Vec::new();
+	    loop {
+		stream.read_exact(&mut byte)?;
+		if byte[0] == b'#' { break; }
+		data.push(byte[0]);
+	    }
+	    let mut checksum = [0u8;2];
+	    stream.read_exact(&mut checksum)?;
+	    let valid = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c,16).ok()) == Some(sum(&data));
+	    if !self.no_ack {
+		stream.write_all(if valid { b"+" } else { b"-" })?;
+	    }
+	    if valid {
+		let reply = self.handle(&String::from_utf8_lossy(&data),state);
+		stream.write_all(&packet(&reply))?;
+		stream.flush()?;
+	    }
+	}
+	Ok(())
+    }
+    /// Handle the contents of a single packet, returning the contents
+    /// of the reply.  An empty reply indicates the request is not
+    /// supported.
+    pub fn handle(&mut self, request: &str, state: &mut State) -> String {
+	let (command,args) = request.split_at(request.len().min(1));
+	match command {
+	    "?" => SIGTRAP.to_string(),
+	    "g" => self.registers(state).iter().map(|(_,w,v)| hex(&v.to_le_bytes()[..w.bytes()])).collect(),
+	    "G" => {
+		let bytes = unhex(args).unwrap_or_default();
+		let mut offset = 0;
+		for (r,w,_) in self.registers(state) {
+		    let Some(value) = bytes.get(offset..offset+w.bytes()) else { return ERROR.to_string(); };
+		    self.write_register(state,r,le(value));
+		    offset += w.bytes();
+		}
+		"OK".to_string()
+	    }
+	    "p" => {
+		let registers = self.registers(state);
+		match usize::from_str_radix(args,16).ok().and_then(|i| registers.get(i)) {
+		    Some((_,w,v)) => hex(&v.to_le_bytes()[..w.bytes()]),
+		    None => ERROR.to_string()
+		}
+	    }
+	    "P" => {
+		let registers = self.registers(state);
+		let parsed = args.split_once('=').and_then(|(i,v)| Some((usize::from_str_radix(i,16).ok()?,unhex(v)?)));
+		match parsed.and_then(|(i,v)| Some((registers.get(i)?.0,v))) {
+		    Some((r,v)) => {
+			self.write_register(state,r,le(&v));
+			"OK".to_string()
+		    }
+		    None => ERROR.to_string()
+		}
+	    }
+	    "m" => {
+		let memory = state.data.bytes();
+		match range(args).filter(|(a,l)| a.checked_add(*l).is_some_and(|e| e <= memory.len())) {
+		    Some((address,length)) => hex(&memory[address..address+length]),
+		    None => ERROR.to_string()
+		}
+	    }
+	    "M" => {
+		let parsed = args.split_once(':').and_then(|(r,v)| Some((range(r)?,unhex(v)?)));
+		match parsed {
+		    Some(((address,length),bytes)) if bytes.len() == length && address.checked_add(length).is_some_and(|e| e <= state.data.len()) => {
+			state.data.write_bytes(address,&bytes);
+			"OK".to_string()
+		    }
+		    _ => ERROR.to_string()
+		}
+	    }
+	    "s" => {
+		if state.pc >= self.program.len() {
+		    EXITED.to_string()
+		} else {
+		    match state.step(self.program) {
+			Ok(()) => SIGTRAP.to_string(),
+			Err(_) => SIGILL.to_string()
+		    }
+		}
+	    }
+	    "c" => {
+		match self.debugger.resume(state,usize::MAX) {
+		    Ok(Stop::Halted(_)) => EXITED.to_string(),
+		    Ok(_) => SIGTRAP.to_string(),
+		    Err(_) => SIGILL.to_string()
+		}
+	    }
+	    "Z"|"z" => {
+		// Only software breakpoints (i.e. kind 0) are supported
+		let Some(args) = args.strip_prefix("0,") else { return String::new(); };
+		let pc = args.split(',').next().and_then(|a| usize::from_str_radix(a,16).ok()).and_then(|a| self.program.pc(a));
+		match pc {
+		    Some(pc) if command == "Z" => {
+			self.debugger.add_breakpoint(pc);
+			"OK".to_string()
+		    }
+		    Some(pc) => {
+			self.debugger.remove_breakpoint(pc);
+			"OK".to_string()
+		    }
+		    None => ERROR.to_string()
+		}
+	    }
+	    "H" => "OK".to_string(),
+	    "k" => {
+		self.done = true;
+		String::new()
+	    }
+	    "D" => {
+		self.done = true;
+		"OK".to_string()
+	    }
+	    _ => self.query(request,state)
+	}
+    }
+
+    /// Handle a general query (e.g. `qSupported`).
+    fn query(&mut self, request: &str, state: &State) -> String {
+	if request.starts_with("qSupported") {
+	    "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string()
+	} else if request == "QStartNoAckMode" {
+	    self.no_ack = true;
+	    "OK".to_string()
+	} else if let Some(args) = request.strip_prefix("qXfer:features:read:target.xml:") {
+	    let xml = self.target(state);
+	    match range(args) {
+		Some((offset,length)) if offset <= xml.len() => {
+		    let end = offset.saturating_add(length).min(xml.len());
+		    format!("{}{}",if end == xml.len() { "l" } else { "m" },&xml[offset..end])
+		}
+		_ => ERROR.to_string()
+	    }
+	} else {
+	    match request {
+		"qAttached" => "1".to_string(),
+		"qC" => "QC1".to_string(),
+		"qfThreadInfo" => "m1".to_string(),
+		"qsThreadInfo" => "l".to_string(),
+		_ => String::new()
+	    }
+	}
+    }
+
+    /// Determine the registers shown to GDB (in order), along with
+    /// their widths and current values.  The pc is shown as the
+    /// offset of the instruction it identifies.
+    fn registers(&self, state: &State) -> Vec<(usize,Width,u64)> {
+	let mut registers : Vec<(usize,Width,u64)> = (0..state.registers.len()).map(|r| (r,state.registers.width(),state.registers.read(r))).collect();
+	let pc = if state.pc < self.program.len() { self.program.offset(state.pc) } else { self.image() };
+	registers.push((PC,Width::QuadWord,pc as u64));
+	for r in [SP,FLAGS,LR] {
+	    registers.push((r,Width::QuadWord,state.read_register(r)));
+	}
+	registers
+    }
+
+    /// Write a given register, where a pc is given as an offset.
+    fn write_register(&self, state: &mut State, register: usize, value: u64) {
+	if register == PC {
+	    state.pc = self.program.pc(value as usize).unwrap_or(self.program.len());
+	} else {
+	    state.write_register(register,value);
+	}
+    }
+
+    /// Determine the length of the image holding the program.
+    fn image(&self) -> usize {
+	match self.program.len() {
+	    0 => 0,
+	    n => self.program.get(n-1).map_or(self.program.offset(n-1) + 1,|e| e.offset + e.length)
+	}
+    }
+
+    /// Construct the target description, which describes the
+    /// registers (in the order they are given to GDB).
+    fn target(&self, state: &State) -> String {
+	let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n");
+	xml.push_str("<target version=\"1.0\">\n<feature name=\"org.virmin.core\">\n");
+	let bits = state.registers.width().bytes() * 8;
+	for r in 0..state.registers.len() {
+	    let name = self.names.get(r).cloned().unwrap_or(format!("r{}",r));
+	    xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\"/>\n",name,bits,r));
+	}
+	xml.push_str("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/>\n");
+	for name in ["sp","flags","lr"] {
+	    xml.push_str(&format!("<reg name=\"{}\" bitsize=\"64\"/>\n",name));
+	}
+	xml.push_str("</feature>\n</target>\n");
+	xml
+    }
+}
+
+/// Frame the contents of a packet, escaping any special characters.
+fn packet(contents: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    for b in contents.bytes() {
+	if matches!(b,b'$'|b'#'|b'}'|b'*') {
+	    data.extend([b'}',b ^ 0x20]);
+	} else {
+	    data.push(b);
+	}
+    }
+    let mut out = vec![b'$'];
+    out.extend(&data);
+    out.extend(format!("#{:02x}",sum(&data)).bytes());
+    out
+}
+
+/// Compute the checksum of a packet's (escaped) contents.
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8,|s,b| s.wrapping_add(*b))
+}
+
+/// Parse a range of the form `address,length` (in hexadecimal).
+fn range(text: &str) -> Option<(usize,usize)> {
+    let (a,l) = text.split_once(',')?;
+    Some((usize::from_str_radix(a,16).ok()?,usize::from_str_radix(l,16).ok()?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() { return None; }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i+2],16).ok()).collect()
+}
+
+/// Construct a value from (at most eight) little endian bytes.
+fn le(bytes: &[u8]) -> u64 {
+    bytes.iter().take(8).rev().fold(0,|v,b| (v << 8) | *b as u64)
+}
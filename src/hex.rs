@@ -0,0 +1,130 @@
+use std::fmt::{self,Write};
+
+/// The number of data bytes written per record.
+const RECORD_SIZE : usize = 16;
+
+/// Indicates an image cannot be written because (when located at the
+/// given address) it extends beyond the first 4GB.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct AddressOverflow {
+    pub address: usize,
+    pub length: usize
+}
+
+impl fmt::Display for AddressOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"image of {} byte(s) at {:#x} does not fit in 32 bits",self.length,self.address)
+    }
+}
+
+impl std::error::Error for AddressOverflow {}
+
+/// Determine the (exclusive) end address of an image located at a
+/// given address, provided this fits in 32 bits.
+fn end_address(image: &[u8], address: usize) -> Result<usize,AddressOverflow> {
+    match address.checked_add(image.len()) {
+	Some(end) if end as u64 <= 1 << 32 => Ok(end),
+	_ => Err(AddressOverflow{address,length:image.len()})
+    }
+}
+
+// =====================================================
+// Intel HEX
+// =====================================================
+
+/// Write a byte image (e.g. an assembled program) as Intel HEX, such
+/// that its first byte is located at a given address.  Images
+/// extending beyond the first 64KB are written using extended linear
+/// address records, allowing up to 4GB to be addressed.  Fails if the
+/// image extends beyond this.
+///
+/// ```text
+/// :03010000010203F6
+/// :00000001FF
+/// ```
+pub fn intel_hex(image: &[u8], address: usize) -> Result<String,AddressOverflow> {
+    end_address(image,address)?;
+    let mut out = String::new();
+    let mut segment = 0;
+    for (i,chunk) in image.chunks(RECORD_SIZE).enumerate() {
+	let addr = address + (i * RECORD_SIZE);
+	// Split records which straddle a 64KB boundary
+	let split = (0x10000 - (addr & 0xFFFF)).min(chunk.len());
+	for (addr,data) in [(addr,&chunk[..split]),(addr+split,&chunk[split..])] {
+	    if data.is_empty() { continue; }
+	    if addr >> 16 != segment {
+		segment = addr >> 16;
+		intel_record(&mut out,0,0x04,&(segment as u16).to_be_bytes());
+	    }
+	    intel_record(&mut out,addr as u16,0x00,data);
+	}
+    }
+    intel_record(&mut out,0,0x01,&[]);
+    Ok(out)
+}
+
+fn intel_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let [hi,lo] = address.to_be_bytes();
+    let mut bytes = vec![data.len() as u8,hi,lo,kind];
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8,|s,b| s.wrapping_add(*b)).wrapping_neg();
+    out.push(':');
+    for b in bytes.iter().chain([&checksum]) {
+	write!(out,"{:02X}",b).unwrap();
+    }
+    out.push('\n');
+}
+
+// =====================================================
+// Motorola S-Record
+// =====================================================
+
+/// Write a byte image (e.g. an assembled program) as Motorola
+/// S-records, such that its first byte is located at a given address.
+/// The smallest address size (i.e. `S1`, `S2` or `S3` records) able to
+/// hold every address in the image is used.  Fails if the image
+/// extends beyond the first 4GB.
+///
+/// ```text
+/// S0030000FC
+/// S1060100010203F2
+/// S5030001FB
+/// S9030000FC
+/// ```
+pub fn srec(image: &[u8], address: usize) -> Result<String,AddressOverflow> {
+    let end = end_address(image,address)?;
+    // Determine data and termination record types
+    let (data,term,width) = if end <= 1 << 16 {
+	(1,9,2)
+    } else if end <= 1 << 24 {
+	(2,8,3)
+    } else {
+	(3,7,4)
+    };
+    let mut out = String::new();
+    srec_record(&mut out,0,0,2,&[]);
+    let mut count = 0;
+    for (i,chunk) in image.chunks(RECORD_SIZE).enumerate() {
+	srec_record(&mut out,data,address + (i * RECORD_SIZE),width,chunk);
+	count += 1;
+    }
+    if count <= 0xFFFF {
+	srec_record(&mut out,5,count,2,&[]);
+    } else {
+	srec_record(&mut out,6,count,3,&[]);
+    }
+    srec_record(&mut out,term,0,width,&[]);
+    Ok(out)
+}
+
+fn srec_record(out: &mut String, kind: u8, address: usize, width: usize, data: &[u8]) {
+    let mut bytes = vec![(width + data.len() + 1) as u8];
+    bytes.extend_from_slice(&(address as u32).to_be_bytes()[4-width..]);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8,|s,b| s.wrapping_add(*b));
+    write!(out,"S{}",kind).unwrap();
+    for b in bytes.iter().chain([&checksum]) {
+	write!(out,"{:02X}",b).unwrap();
+    }
+    out.push('\n');
+}
@@ -1,9 +1,15 @@
-use std::cmp;
-use num::BigUint;
+use core::cmp;
+use core::fmt;
+use core::ops::Range;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::{boxed::Box,format,string::{String,ToString},vec,vec::Vec};
+use num::{BigUint,ToPrimitive};
 use crate::domain::Countable;
 use crate::domain::{Bits,Bytes};
-use crate::machine::Width;
-use crate::machine::MicroCode;
+use crate::machine::{AluOp,MachineProfile,Width};
+use crate::machine::{self,MicroCode};
+use crate::verify::Property;
 
 // ================================================================
 // Format
@@ -25,7 +31,8 @@ use crate::machine::MicroCode;
 /// includes two three-bit operands, and a two bit opcode.  This means
 /// we can have at most four instructions in this class, and each
 /// operand can take on eight distinct values.
-#[derive(PartialEq)]
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub struct Format {
     /// Determines the overall width (in bytes) of an instruction in
     /// this class.  Generally speaking, virtual machines normally
@@ -41,31 +48,662 @@ pub struct Format {
     opcode : Bits,
     /// Determine the number and size of operands for all instructions
     /// in this class.
-    operands: Vec<Bits>
+    operands: Vec<Field>,
+    /// Determines how many of the operands (i.e. the last ones) are
+    /// held in extension words following the instruction word.  Each
+    /// extension word holds exactly one operand.
+    extensions: usize,
+    /// Determines the order in which the bytes of an instruction are
+    /// stored in memory.
+    byte_order: ByteOrder,
+    /// Determines whether fields are allocated starting from the least
+    /// or most significant bit of an instruction.
+    bit_order: BitOrder
 }
 
 impl Format {
     pub fn new(width:Bytes, label: &str, opcode: Bits, operands: &[Bits]) -> Format {	
-	let r = Format{width,label:label.to_string(),opcode,operands:operands.to_vec()};
+	let operands = operands.iter().map(|b| Field::new("",*b,FieldKind::Immediate)).collect();
+	let (byte_order,bit_order) = (ByteOrder::LittleEndian,BitOrder::LsbFirst);
+	let r = Format{width,label:label.to_string(),opcode,operands,extensions:0,byte_order,bit_order};
 	// Sanity check there is enough space
-	assert!(width.count() >= r.count());
+	assert!(width.bits() >= r.bits());
 	//
 	r
     }
+
+    /// Construct a new format incrementally, such that any problems
+    /// are reported as errors (rather than by panicking).  For
+    /// example:
+    ///
+    /// ```
+    /// use virmin::insn::Format;
+    /// let fmt = Format::builder()
+    ///     .width_bytes(2)
+    ///     .opcode_bits(6)
+    ///     .register("rd",3)
+    ///     .simmediate("off",7)
+    ///     .build();
+    /// assert!(fmt.is_ok());
+    /// ```
+    pub fn builder() -> FormatBuilder {
+	FormatBuilder::new()
+    }
+    /// Get the width (in bytes) of the instruction word in this
+    /// format.  This excludes any extension words (see `length()`).
+    pub fn width(&self) -> Bytes {
+	self.width
+    }
+    /// Get the total length (in bytes) of instructions in this format,
+    /// including any extension words.
+    pub fn length(&self) -> usize {
+	let extensions = &self.operands[self.operands.len()-self.extensions..];
+	self.width.value() as usize + extensions.iter().map(|f| f.bits.value() as usize / 8).sum::<usize>()
+    }
+    /// Get the number of operands held in extension words.  These are
+    /// always the last operands of the format.
+    pub fn extensions(&self) -> usize {
+	self.extensions
+    }
+    /// Get the human-readable label of this format.
+    pub fn label(&self) -> &str {
+	&self.label
+    }
+    /// Get the total number of bits making up the opcode and operands
+    /// of this format.
+    fn bits(&self) -> u32 {
+	self.operands.iter().fold(self.opcode.value() as u32,|n,f| n + f.bits.value() as u32)
+    }
+    /// Get the size of the opcode field in this format.
+    pub fn opcode(&self) -> Bits {
+	self.opcode
+    }
+    /// Get the operand fields of this format (in order).
+    pub fn operands(&self) -> &[Field] {
+	&self.operands
+    }
+    /// Get the byte order used for instructions in this format.
+    pub fn byte_order(&self) -> ByteOrder {
+	self.byte_order
+    }
+    /// Get the bit order used for instructions in this format.
+    pub fn bit_order(&self) -> BitOrder {
+	self.bit_order
+    }
+    /// Determine the position of each field within an instruction
+    /// word, given as a bit offset (from the least significant bit)
+    /// and a length.  The opcode field comes first, followed by each
+    /// operand field in order (excluding those held in extension
+    /// words).
+    pub fn layout(&self) -> Vec<(usize,usize)> {
+	let total = 8 * self.width.value() as usize;
+	let mut sizes = vec![self.opcode.value() as usize];
+	sizes.extend(self.operands[..self.operands.len()-self.extensions].iter().map(|f| f.bits.value() as usize));
+	let mut offset = 0;
+	let mut layout = Vec::new();
+	for n in sizes {
+	    match self.bit_order {
+		BitOrder::LsbFirst => layout.push((offset,n)),
+		BitOrder::MsbFirst => layout.push((total - offset - n,n))
+	    }
+	    offset += n;
+	}
+	layout
+    }
+    /// Encode an instruction in this format with a given opcode and
+    /// operands.  Signed operands are given in two's complement form
+    /// (e.g. `-1isize as usize`).  Extension words are emitted after
+    /// the instruction word, using the same byte order.
+    pub fn encode(&self, opcode: usize, operands: &[usize]) -> Result<Vec<u8>,EncodeError> {
+	if operands.len() != self.operands.len() {
+	    return Err(EncodeError::WrongArity{expected: self.operands.len(), actual: operands.len()});
+	} else if !fits_unsigned(opcode,self.opcode) {
+	    return Err(EncodeError::InvalidOpcode(opcode));
+	}
+	let layout = self.layout();
+	let mut word = vec![0u8; self.width.value() as usize];
+	write_bits(&mut word,layout[0],opcode as u64);
+	let mut extensions = Vec::new();
+	for (i,(field,value)) in self.operands.iter().zip(operands).enumerate() {
+	    if !field.fits(*value) {
+		return Err(EncodeError::OutOfRange{operand: i, value: *value});
+	    } else if !field.satisfies(*value) {
+		return Err(EncodeError::Reserved{operand: i, value: *value});
+	    } else if i + 1 < layout.len() {
+		write_bits(&mut word,layout[i+1],*value as u64);
+	    } else {
+		let mut ext = (*value as u64).to_le_bytes()[..field.bits.value() as usize / 8].to_vec();
+		if self.byte_order == ByteOrder::BigEndian {
+		    ext.reverse();
+		}
+		extensions.extend(ext);
+	    }
+	}
+	if self.byte_order == ByteOrder::BigEndian {
+	    word.reverse();
+	}
+	word.extend(extensions);
+	Ok(word)
+    }
+    /// Decode an instruction in this format from a given sequence of
+    /// bytes, producing its opcode and operands.  Signed operands are
+    /// sign extended into two's complement form.  Operands violating
+    /// a constraint of their field are reported as reserved.
+    pub fn decode(&self, bytes: &[u8]) -> Result<(usize,Vec<usize>),DecodeError> {
+	let (opcode,operands) = self.read(bytes)?;
+	self.check(&operands)?;
+	Ok((opcode,operands))
+    }
+
+    /// Check that decoded operands satisfy the constraints of their
+    /// fields.
+    fn check(&self, operands: &[usize]) -> Result<(),DecodeError> {
+	match self.operands.iter().zip(operands).position(|(f,v)| !f.satisfies(*v)) {
+	    Some(operand) => Err(DecodeError::Reserved{operand,value:operands[operand]}),
+	    None => Ok(())
+	}
+    }
+
+    /// Decode an instruction without checking operand constraints.
+    fn read(&self, bytes: &[u8]) -> Result<(usize,Vec<usize>),DecodeError> {
+	let mut n = self.width.value() as usize;
+	let length = self.length();
+	if bytes.len() < length {
+	    return Err(DecodeError::Truncated{required: length, available: bytes.len()});
+	}
+	let mut word = bytes[..n].to_vec();
+	if self.byte_order == ByteOrder::BigEndian {
+	    word.reverse();
+	}
+	let layout = self.layout();
+	let opcode = read_bits(&word,layout[0]) as usize;
+	let mut operands = Vec::new();
+	for (i,field) in self.operands.iter().enumerate() {
+	    let raw = if i + 1 < layout.len() {
+		read_bits(&word,layout[i+1]) as usize
+	    } else {
+		let m = field.bits.value() as usize / 8;
+		let mut ext = bytes[n..n+m].to_vec();
+		if self.byte_order == ByteOrder::BigEndian {
+		    ext.reverse();
+		}
+		n += m;
+		ext.iter().rev().fold(0,|v,b| (v << 8) | *b as usize)
+	    };
+	    operands.push(field.extend(raw));
+	}
+	Ok((opcode,operands))
+    }
 }
 
 impl Countable for Format {
     fn count(&self) -> BigUint {
-	let mut count = BigUint::from(self.opcode.count());
-	//
-	for op in &self.operands {
-	    count = count * op.count();
+	BigUint::from(1u32) << self.bits()
+    }
+    fn checked_count(&self) -> Option<u128> {
+	1u128.checked_shl(self.bits())
+    }
+}
+
+/// Renders a format as its label, length and fields (e.g. `fmt (2
+/// bytes): opcode:6 rd:r3 off:s7`).  Each field is given with its
+/// size in bits, prefixed by `r` for a register, `u` for an unsigned
+/// immediate and `s` for a signed immediate.
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let length = self.length();
+	let names : Vec<&str> = self.operands.iter().map(|o| o.name()).collect();
+	write!(f,"{} ({} byte{}): opcode:{}",self.label,length,if length == 1 { "" } else { "s" },self.opcode.value())?;
+	for (i,field) in self.operands.iter().enumerate() {
+	    let kind = match field.kind {
+		FieldKind::Register => "r",
+		FieldKind::Immediate => "u",
+		FieldKind::SignedImmediate => "s"
+	    };
+	    write!(f," {}:{}{}",Named(&Operand::Var(i),&names),kind,field.bits.value())?;
+	}
+	Ok(())
+    }
+}
+
+/// Determines the order in which the bytes of a multi-byte
+/// instruction are stored in memory.
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum ByteOrder {
+    /// Least significant byte first.
+    LittleEndian,
+    /// Most significant byte first.
+    BigEndian
+}
+
+/// Determines how fields are allocated within an instruction word.
+/// For example, consider a one byte format with a two bit opcode and
+/// a three bit operand:
+///
+/// ```text
+///    +-+-+-+-+-+-+-+-+       +-+-+-+-+-+-+-+-+
+///    |7  |4     2|1 0|       |7 6|5   3|2   0|
+///    +-+-+-+-+-+-+-+-+       +-+-+-+-+-+-+-+-+
+///    |   |  #1   |Op |       |Op |  #1 |     |
+///    +-+-+-+-+-+-+-+-+       +-+-+-+-+-+-+-+-+
+///        LsbFirst                MsbFirst
+/// ```
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum BitOrder {
+    /// The opcode occupies the least significant bits, followed by
+    /// each operand in turn.
+    LsbFirst,
+    /// The opcode occupies the most significant bits, followed by
+    /// each operand in turn.
+    MsbFirst
+}
+
+// ================================================================
+// Field
+// ================================================================
+
+/// Determines how the value held in an operand field should be
+/// interpreted.
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum FieldKind {
+    /// Identifies a register (e.g. `r3`).
+    Register,
+    /// An unsigned immediate value.
+    Immediate,
+    /// A signed (two's complement) immediate value.
+    SignedImmediate
+}
+
+/// Describes a single operand field within a format, such as a
+/// three-bit register or a seven-bit signed offset.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct Field {
+    /// Human-readable name for this field (e.g. `rd`).  This may be
+    /// empty for fields constructed via `Format::new()`.
+    name: String,
+    /// Number of bits occupied by this field.
+    bits: Bits,
+    /// Determines how values in this field are interpreted.
+    kind: FieldKind,
+    /// Restrictions on the values this field may hold.  Encodings
+    /// which violate them are reserved.
+    #[cfg_attr(feature="serde", serde(default))]
+    constraints: Vec<Constraint>
+}
+
+impl Field {
+    pub fn new(name: &str, bits: Bits, kind: FieldKind) -> Self {
+	Field{name:name.to_string(),bits,kind,constraints:Vec::new()}
+    }
+    /// Restrict the values which this field may hold.
+    pub fn with_constraint(mut self, constraint: Constraint) -> Self {
+	self.constraints.push(constraint);
+	self
+    }
+    pub fn name(&self) -> &str {
+	&self.name
+    }
+    pub fn bits(&self) -> Bits {
+	self.bits
+    }
+    pub fn kind(&self) -> FieldKind {
+	self.kind
+    }
+    pub fn constraints(&self) -> &[Constraint] {
+	&self.constraints
+    }
+    /// Check whether a given operand value satisfies all constraints
+    /// of this field.  Signed values are given in two's complement
+    /// form.
+    pub fn satisfies(&self, value: usize) -> bool {
+	let signed = self.kind == FieldKind::SignedImmediate;
+	self.constraints.iter().all(|c| c.accepts(value,signed))
+    }
+    /// Check whether a given operand value fits into this field.
+    /// Signed values are given in two's complement form.
+    pub fn fits(&self, value: usize) -> bool {
+	match self.kind {
+	    FieldKind::SignedImmediate => fits_signed(value,self.bits),
+	    _ => fits_unsigned(value,self.bits)
+	}
+    }
+    /// Interpret the raw bits held in this field as an operand value.
+    /// For signed immediates, this means sign extending them into
+    /// two's complement form.
+    pub fn extend(&self, raw: usize) -> usize {
+	match self.kind {
+	    FieldKind::SignedImmediate => sign_extend(raw,self.bits),
+	    _ => raw
+	}
+    }
+}
+
+impl Countable for Field {
+    fn count(&self) -> BigUint {
+	self.bits.count()
+    }
+    fn checked_count(&self) -> Option<u128> {
+	self.bits.checked_count()
+    }
+}
+
+/// A restriction on the values an operand field may hold, modelling
+/// encodings which are reserved by the architecture (e.g. a register
+/// field which cannot name `r0`).  The encoder rejects operands
+/// violating a constraint, whilst the decoder treats them as illegal
+/// instructions.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Constraint {
+    /// The value cannot be zero.
+    NonZero,
+    /// The value must be a multiple of a given amount.
+    Aligned(usize),
+    /// The value must lie within a given (inclusive) range.  This is
+    /// signed for signed immediates, and unsigned otherwise.
+    Range(i128,i128)
+}
+
+impl Constraint {
+    /// Check whether a given value satisfies this constraint, where
+    /// `signed` indicates it is held in two's complement form.
+    pub fn accepts(&self, value: usize, signed: bool) -> bool {
+	let v = if signed { value as isize as i128 } else { value as i128 };
+	match self {
+	    Constraint::NonZero => value != 0,
+	    Constraint::Aligned(n) => *n == 0 || v.rem_euclid(*n as i128) == 0,
+	    Constraint::Range(lo,hi) => *lo <= v && v <= *hi
+	}
+    }
+}
+
+// ================================================================
+// Format Builder
+// ================================================================
+
+/// Identifies a problem encountered when building a format.
+#[derive(Clone,Debug,PartialEq)]
+pub enum FormatError {
+    /// No width was given for the format.
+    MissingWidth,
+    /// No opcode field was given for the format.
+    MissingOpcode,
+    /// The width, opcode or a named operand field was given a size of
+    /// zero.
+    ZeroSized(String),
+    /// Two operand fields were given the same name.
+    DuplicateField(String),
+    /// A constraint was given for an operand field which does not
+    /// exist.
+    UnknownField(String),
+    /// The opcode and operand fields require more bits than are
+    /// available in the format's width.
+    DoesNotFit{required: usize, available: usize}
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    FormatError::MissingWidth => write!(f,"missing width"),
+	    FormatError::MissingOpcode => write!(f,"missing opcode field"),
+	    FormatError::ZeroSized(n) => write!(f,"field \"{}\" has zero size",n),
+	    FormatError::DuplicateField(n) => write!(f,"duplicate field \"{}\"",n),
+	    FormatError::UnknownField(n) => write!(f,"constraint given for unknown field \"{}\"",n),
+	    FormatError::DoesNotFit{required,available} => {
+		write!(f,"fields require {} bit(s) but only {} are available",required,available)
+	    }
+	}
+    }
+}
+
+impl core::error::Error for FormatError {}
+
+/// Provides a fluent API for constructing formats.  Unlike
+/// `Format::new()`, this does not panic when the format is malformed
+/// and, instead, returns a `FormatError`.
+pub struct FormatBuilder {
+    label: String,
+    width: Option<u8>,
+    opcode: Option<u8>,
+    operands: Vec<(String,u8,FieldKind)>,
+    extensions: Vec<(String,u8,FieldKind)>,
+    constraints: Vec<(String,Constraint)>,
+    byte_order: ByteOrder,
+    bit_order: BitOrder
+}
+
+impl FormatBuilder {
+    pub fn new() -> Self {
+	let (byte_order,bit_order) = (ByteOrder::LittleEndian,BitOrder::LsbFirst);
+	FormatBuilder{label:String::new(),width:None,opcode:None,operands:Vec::new(),extensions:Vec::new(),constraints:Vec::new(),byte_order,bit_order}
+    }
+    /// Set the human-readable label for this format.
+    pub fn label(mut self, label: &str) -> Self {
+	self.label = label.to_string();
+	self
+    }
+    /// Set the overall width (in bytes) of this format.
+    pub fn width_bytes(mut self, width: u8) -> Self {
+	self.width = Some(width);
+	self
+    }
+    /// Set the size (in bits) of the opcode field.
+    pub fn opcode_bits(mut self, bits: u8) -> Self {
+	self.opcode = Some(bits);
+	self
+    }
+    /// Set the order in which the bytes of an instruction are stored
+    /// in memory (default is little endian).
+    pub fn byte_order(mut self, order: ByteOrder) -> Self {
+	self.byte_order = order;
+	self
+    }
+    /// Set the order in which fields are allocated within an
+    /// instruction (default is least significant bit first).
+    pub fn bit_order(mut self, order: BitOrder) -> Self {
+	self.bit_order = order;
+	self
+    }
+    /// Append a register operand field of a given size (in bits).
+    pub fn register(self, name: &str, bits: u8) -> Self {
+	self.field(name,bits,FieldKind::Register)
+    }
+    /// Append an unsigned immediate operand field of a given size (in
+    /// bits).
+    pub fn immediate(self, name: &str, bits: u8) -> Self {
+	self.field(name,bits,FieldKind::Immediate)
+    }
+    /// Append a signed immediate operand field of a given size (in
+    /// bits).
+    pub fn simmediate(self, name: &str, bits: u8) -> Self {
+	self.field(name,bits,FieldKind::SignedImmediate)
+    }
+    /// Append an operand field of a given kind and size (in bits).
+    pub fn field(mut self, name: &str, bits: u8, kind: FieldKind) -> Self {
+	self.operands.push((name.to_string(),bits,kind));
+	self
+    }
+    /// Append an operand held in an extension word of a given size
+    /// (in bytes) following the instruction word.  Such operands
+    /// always come after those held in the instruction word.
+    pub fn extension(mut self, name: &str, bytes: u8, kind: FieldKind) -> Self {
+	self.extensions.push((name.to_string(),bytes,kind));
+	self
+    }
+    /// Restrict the values which a named operand field may hold (for
+    /// example, `.constraint("rd",Constraint::NonZero)`).
+    pub fn constraint(mut self, name: &str, constraint: Constraint) -> Self {
+	self.constraints.push((name.to_string(),constraint));
+	self
+    }
+    /// Construct the format, checking that all fields are well-formed
+    /// and fit within the given width.
+    pub fn build(self) -> Result<Format,FormatError> {
+	let width = match self.width {
+	    None => { return Err(FormatError::MissingWidth); }
+	    Some(0) => { return Err(FormatError::ZeroSized("width".to_string())); }
+	    Some(w) => Bytes::from(w)
+	};
+	let opcode = match self.opcode {
+	    None => { return Err(FormatError::MissingOpcode); }
+	    Some(0) => { return Err(FormatError::ZeroSized("opcode".to_string())); }
+	    Some(b) => Bits::from(b)
+	};
+	let mut operands : Vec<Field> = Vec::new();
+	let extensions = self.extensions.iter().map(|(n,b,k)| (n,b.saturating_mul(8),k));
+	for (name,bits,kind) in self.operands.iter().map(|(n,b,k)| (n,*b,k)).chain(extensions) {
+	    if bits == 0 {
+		return Err(FormatError::ZeroSized(name.clone()));
+	    } else if !name.is_empty() && operands.iter().any(|f| &f.name == name) {
+		return Err(FormatError::DuplicateField(name.clone()));
+	    } else if operands.len() >= self.operands.len() && bits as u32 > usize::BITS {
+		return Err(FormatError::DoesNotFit{required:bits as usize,available:usize::BITS as usize});
+	    }
+	    operands.push(Field::new(name,Bits::from(bits),*kind));
+	}
+	for (name,constraint) in self.constraints {
+	    match operands.iter_mut().find(|f| !name.is_empty() && f.name == name) {
+		Some(f) => f.constraints.push(constraint),
+		None => { return Err(FormatError::UnknownField(name)); }
+	    }
+	}
+	let extensions = self.extensions.len();
+	let (byte_order,bit_order) = (self.byte_order,self.bit_order);
+	let format = Format{width,label:self.label,opcode,operands,extensions,byte_order,bit_order};
+	// Sanity check there is enough space
+	let required = opcode.value() as usize
+	    + self.operands.iter().map(|(_,b,_)| *b as usize).sum::<usize>();
+	let available = width.bits() as usize;
+	if available < required {
+	    return Err(FormatError::DoesNotFit{required,available});
+	}
+	Ok(format)
+    }
+}
+
+impl Default for FormatBuilder {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+// ================================================================
+// Encoding
+// ================================================================
+
+/// Identifies a problem encountered when encoding an instruction.
+#[derive(Clone,Debug,PartialEq)]
+pub enum EncodeError {
+    /// No instruction exists with the given mnemonic.
+    UnknownMnemonic(String),
+    /// The given opcode does not fit into the opcode field.
+    InvalidOpcode(usize),
+    /// The wrong number of operands was given.
+    WrongArity{expected: usize, actual: usize},
+    /// The value of a given operand does not fit into its field.
+    OutOfRange{operand: usize, value: usize},
+    /// The value of a given operand violates a constraint of its
+    /// field (i.e. the encoding is reserved).
+    Reserved{operand: usize, value: usize}
+}
+
+/// Identifies a problem encountered when decoding an instruction.
+#[derive(Clone,Debug,PartialEq)]
+pub enum DecodeError {
+    /// There were not enough bytes to decode an instruction.
+    Truncated{required: usize, available: usize},
+    /// The given bytes do not correspond to any instruction.
+    Unknown,
+    /// The given pc does not identify an instruction in the program.
+    OutOfBounds(usize),
+    /// The instruction at the given pc was invalidated by a write to
+    /// the program image, and has not yet been decoded again.
+    Stale(usize),
+    /// The instruction at the given pc requires a feature which the
+    /// machine does not have enabled (i.e. an illegal instruction).
+    Illegal{pc: usize, feature: u8},
+    /// The value of a given operand violates a constraint of its
+    /// field (i.e. an illegal instruction using a reserved encoding).
+    Reserved{operand: usize, value: usize}
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    EncodeError::UnknownMnemonic(m) => write!(f,"unknown mnemonic \"{}\"",m),
+	    EncodeError::InvalidOpcode(o) => write!(f,"opcode {} does not fit in opcode field",o),
+	    EncodeError::WrongArity{expected,actual} => write!(f,"expected {} operand(s), found {}",expected,actual),
+	    EncodeError::OutOfRange{operand,value} => write!(f,"value {:#x} of operand {} is out of range",value,operand),
+	    EncodeError::Reserved{operand,value} => write!(f,"value {:#x} of operand {} is reserved",value,operand)
 	}
-	//
-	count
     }
 }
 
+impl core::error::Error for EncodeError {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    DecodeError::Truncated{required,available} => {
+		write!(f,"truncated instruction ({} byte(s) required, {} available)",required,available)
+	    }
+	    DecodeError::Unknown => write!(f,"unknown instruction"),
+	    DecodeError::OutOfBounds(pc) => write!(f,"pc {} is outside of the program",pc),
+	    DecodeError::Stale(pc) => write!(f,"instruction at pc {} was overwritten",pc),
+	    DecodeError::Illegal{pc,feature} => write!(f,"instruction at pc {} requires feature {}",pc,feature),
+	    DecodeError::Reserved{operand,value} => write!(f,"value {:#x} of operand {} is reserved",value,operand)
+	}
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+fn fits_unsigned(value: usize, bits: Bits) -> bool {
+    let n = bits.value() as u32;
+    n >= usize::BITS || value < (1usize << n)
+}
+
+fn fits_signed(value: usize, bits: Bits) -> bool {
+    let n = bits.value() as u32;
+    let v = value as isize;
+    n >= usize::BITS || (v >= -(1isize << (n-1)) && v < (1isize << (n-1)))
+}
+
+pub(crate) fn sign_extend(value: usize, bits: Bits) -> usize {
+    let n = bits.value() as u32;
+    if n >= usize::BITS {
+	value
+    } else {
+	let shift = usize::BITS - n;
+	(((value << shift) as isize) >> shift) as usize
+    }
+}
+
+/// Write a value into a given bit field of a little endian word.
+/// Bits of the value beyond the length of the field are ignored.
+fn write_bits(word: &mut [u8], (offset,len): (usize,usize), value: u64) {
+    for i in 0..cmp::min(len,64) {
+	let bit = offset + i;
+	if (value >> i) & 1 == 1 {
+	    word[bit / 8] |= 1 << (bit % 8);
+	}
+    }
+}
+
+/// Read a value from a given bit field of a little endian word.
+fn read_bits(word: &[u8], (offset,len): (usize,usize)) -> u64 {
+    let mut value = 0;
+    for i in 0..cmp::min(len,64) {
+	let bit = offset + i;
+	value |= (((word[bit / 8] >> (bit % 8)) & 1) as u64) << i;
+    }
+    value
+}
+
 // =====================================================
 // Abstract Microcode
 // =====================================================
@@ -73,16 +711,42 @@ impl Countable for Format {
 /// Represents an abstract microcode instruction.  This is
 /// (effectively) a template for constructing a concrete microcode
 /// instruction from a concrete instantiation of an instruction
-/// (i.e. where all operands have known values).
+/// (i.e. where all operands have known values).  There is one
+/// variant for each concrete microcode, except for skips which are
+/// instead expressed using `If`.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub enum AbstractMicroCode {
+    /// X := X + Y (w bits signed or unsigned)
+    Add(Operand,Operand,Width),
+    /// X := X op Y (w bits)
+    Alu(AluOp,Operand,Operand,Width),
     /// X := Y (w bits)    
     Copy(Operand,Operand,Width),
     /// pc := I
     Goto(Operand),    
     /// pc := pc + I
     Jump(Operand),
-    /// X := i
-    Load(Operand,u64,Width)	
+    /// X := I
+    Load(Operand,Operand,Width),
+    /// R := R + S (registers, with wrap around)
+    RegAdd(Operand,Operand),
+    /// R := R op S (registers, at the width of R)
+    RegAlu(AluOp,Operand,Operand),
+    /// R := S (registers)
+    RegCopy(Operand,Operand),
+    /// R := X (w bits, zero extended)
+    RegFetch(Operand,Operand,Width),
+    /// R := mem[S] (w bits, zero extended, wrapping around memory)
+    RegFetchIndirect(Operand,Operand,Width),
+    /// R := I
+    RegLoad(Operand,Operand),
+    /// X := R (lowest w bits)
+    RegStore(Operand,Operand,Width),
+    /// mem[S] := R (lowest w bits, wrapping around memory)
+    RegStoreIndirect(Operand,Operand,Width),
+    /// if P then T else E
+    If(Predicate,Vec<AbstractMicroCode>,Vec<AbstractMicroCode>)
 }
 
 impl AbstractMicroCode {
@@ -91,33 +755,201 @@ impl AbstractMicroCode {
     /// format, this microcode instruction makes sense.
     pub fn arity(&self) -> usize {
 	match &self {
-	    AbstractMicroCode::Copy(x,y,w) => {
+	    AbstractMicroCode::Add(x,y,_)|AbstractMicroCode::Alu(_,x,y,_)|AbstractMicroCode::Copy(x,y,_) => {
 		cmp::max(x.arity(),y.arity())
 	    }
-	    AbstractMicroCode::Load(x,i,w) => {
+	    AbstractMicroCode::Goto(x) => {
+		x.arity()
+	    }
+	    AbstractMicroCode::Jump(x) => {
 		x.arity()
 	    }
-	    _ => {
-		todo!("implement more instructions")
+	    AbstractMicroCode::Load(x,i,_)|AbstractMicroCode::RegLoad(x,i) => {
+		cmp::max(x.arity(),i.arity())
+	    }
+	    AbstractMicroCode::RegAdd(x,y)|AbstractMicroCode::RegAlu(_,x,y)|AbstractMicroCode::RegCopy(x,y)
+		|AbstractMicroCode::RegFetch(x,y,_)|AbstractMicroCode::RegFetchIndirect(x,y,_)
+		|AbstractMicroCode::RegStore(x,y,_)|AbstractMicroCode::RegStoreIndirect(x,y,_) => {
+		cmp::max(x.arity(),y.arity())
+	    }
+	    AbstractMicroCode::If(p,t,e) => {
+		t.iter().chain(e).map(|c| c.arity()).fold(p.arity(),cmp::max)
 	    }
 	}
     }
     /// Given a set of concrete operands, reduce this abstract
-    /// microcode instruction into a concrete microcode instruction.    
-    pub fn to_microcode(&self, operands: &[usize]) -> MicroCode {
-	match &self {
+    /// microcode instruction into concrete microcode (where
+    /// pc-relative operands are relative to zero).
+    pub fn to_microcode(&self, operands: &[usize]) -> Vec<MicroCode> {
+	self.to_microcode_at(0,operands)
+    }
+    /// Given a set of concrete operands, reduce this abstract
+    /// microcode instruction at a given pc into concrete microcode.
+    /// This is a single microcode instruction, except for an `If`
+    /// whose predicate depends on the state of the machine.  In that
+    /// case, both branches are included and a skip selects between
+    /// them.
+    pub fn to_microcode_at(&self, pc: usize, operands: &[usize]) -> Vec<MicroCode> {
+	let mut codes = Vec::new();
+	self.to_microcode_into(pc,operands,&mut codes);
+	codes
+    }
+    /// Reduce this abstract microcode instruction at a given pc into
+    /// concrete microcode, appending it to a given buffer (i.e. as for
+    /// `to_microcode_at()`).  Reusing the buffer avoids allocating
+    /// when instructions are repeatedly reduced.
+    pub fn to_microcode_into(&self, pc: usize, operands: &[usize], out: &mut Vec<MicroCode>) {
+	let code = match &self {
+	    AbstractMicroCode::Add(x,y,w) => {
+		let l = x.evaluate(pc,operands);
+		let r = y.evaluate(pc,operands);
+		MicroCode::Add(l,r,*w)
+	    }
+	    AbstractMicroCode::Alu(op,x,y,w) => {
+		let l = x.evaluate(pc,operands);
+		let r = y.evaluate(pc,operands);
+		MicroCode::Alu(*op,l,r,*w)
+	    }
 	    AbstractMicroCode::Copy(x,y,w) => {
-		let l = x.as_usize(operands);
-		let r = y.as_usize(operands);
+		let l = x.evaluate(pc,operands);
+		let r = y.evaluate(pc,operands);
 		MicroCode::Copy(l,r,*w)
 	    }
+	    AbstractMicroCode::Goto(x) => {
+		MicroCode::Goto(x.evaluate(pc,operands))
+	    }
+	    AbstractMicroCode::Jump(x) => {
+		// Offsets are held in two's complement form
+		MicroCode::Jump(x.evaluate(pc,operands) as isize)
+	    }
 	    AbstractMicroCode::Load(x,i,w) => {
-		let l = x.as_usize(operands);
-		MicroCode::Load(l,*i,*w)
+		// Signed immediates are held in two's complement form
+		let l = x.evaluate(pc,operands);
+		MicroCode::Load(l,i.evaluate(pc,operands) as u64,*w)
+	    }
+	    AbstractMicroCode::RegAdd(x,y) => {
+		MicroCode::RegAdd(x.evaluate(pc,operands),y.evaluate(pc,operands))
+	    }
+	    AbstractMicroCode::RegAlu(op,x,y) => {
+		MicroCode::RegAlu(*op,x.evaluate(pc,operands),y.evaluate(pc,operands))
+	    }
+	    AbstractMicroCode::RegCopy(x,y) => {
+		MicroCode::RegCopy(x.evaluate(pc,operands),y.evaluate(pc,operands))
+	    }
+	    AbstractMicroCode::RegFetch(x,y,w) => {
+		MicroCode::RegFetch(x.evaluate(pc,operands),y.evaluate(pc,operands),*w)
+	    }
+	    AbstractMicroCode::RegFetchIndirect(x,y,w) => {
+		MicroCode::RegFetchIndirect(x.evaluate(pc,operands),y.evaluate(pc,operands),*w)
+	    }
+	    AbstractMicroCode::RegLoad(x,i) => {
+		MicroCode::RegLoad(x.evaluate(pc,operands),i.evaluate(pc,operands) as u64)
+	    }
+	    AbstractMicroCode::RegStore(x,y,w) => {
+		MicroCode::RegStore(x.evaluate(pc,operands),y.evaluate(pc,operands),*w)
 	    }
-	    _ => {
-		todo!("implement more instructions")
+	    AbstractMicroCode::RegStoreIndirect(x,y,w) => {
+		MicroCode::RegStoreIndirect(x.evaluate(pc,operands),y.evaluate(pc,operands),*w)
+	    }
+	    AbstractMicroCode::If(p,t,e) => {
+		let lower = |codes: &[AbstractMicroCode], out: &mut Vec<MicroCode>| {
+		    for c in codes { c.to_microcode_into(pc,operands,out); }
+		};
+		match p.evaluate(pc,operands) {
+		    Some(true) => lower(t,out),
+		    Some(false) => lower(e,out),
+		    None => {
+			// Arrange for the branch taken when the location
+			// is zero to come last, then skip to it.
+			let (test,negated) = p.test();
+			let (first,last) = if negated { (t,e) } else { (e,t) };
+			let start = out.len();
+			out.push(MicroCode::Skip(0));
+			lower(first,out);
+			let n = out.len() - start;
+			out[start] = match test {
+			    Predicate::Zero(x,w) => MicroCode::SkipIfZero(x.evaluate(pc,operands),*w,n),
+			    Predicate::RegZero(r) => MicroCode::RegSkipIfZero(r.evaluate(pc,operands),n),
+			    _ => unreachable!()
+			};
+			let middle = out.len();
+			out.push(MicroCode::Skip(0));
+			lower(last,out);
+			out[middle] = MicroCode::Skip(out.len() - middle - 1);
+		    }
+		}
+		return;
+	    }
+	};
+	out.push(code);
+    }
+    /// Get this microcode instruction along with any nested within it
+    /// (i.e. within the branches of an `If`).
+    pub fn nested(&self) -> Vec<&AbstractMicroCode> {
+	let mut codes = vec![self];
+	if let AbstractMicroCode::If(_,t,e) = &self {
+	    codes.extend(t.iter().chain(e).flat_map(|c| c.nested()));
+	}
+	codes
+    }
+}
+
+// =====================================================
+// Predicates
+// =====================================================
+
+/// A condition determining which branch of an `If` is executed.  A
+/// predicate over operands alone is decided when an instruction is
+/// reduced to concrete microcode, whilst a predicate over the state
+/// of the machine (e.g. `Zero`) is decided when it executes.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Predicate {
+    /// X == Y
+    Eq(Operand,Operand),
+    /// X != Y
+    Ne(Operand,Operand),
+    /// X < Y (unsigned)
+    Lt(Operand,Operand),
+    /// X == 0 (w bits)
+    Zero(Operand,Width),
+    /// R == 0 (register)
+    RegZero(Operand),
+    /// !P
+    Not(Box<Predicate>)
+}
+
+impl Predicate {
+    /// Determine how many operands this predicate requires.
+    pub fn arity(&self) -> usize {
+	match &self {
+	    Predicate::Eq(x,y)|Predicate::Ne(x,y)|Predicate::Lt(x,y) => cmp::max(x.arity(),y.arity()),
+	    Predicate::Zero(x,_)|Predicate::RegZero(x) => x.arity(),
+	    Predicate::Not(p) => p.arity()
+	}
+    }
+    /// Evaluate this predicate for a given set of operands (for an
+    /// instruction at a given pc), or `None` if it depends on the
+    /// state of the machine.
+    pub fn evaluate(&self, pc: usize, operands: &[usize]) -> Option<bool> {
+	let eval = |x: &Operand| x.evaluate(pc,operands);
+	match &self {
+	    Predicate::Eq(x,y) => Some(eval(x) == eval(y)),
+	    Predicate::Ne(x,y) => Some(eval(x) != eval(y)),
+	    Predicate::Lt(x,y) => Some(eval(x) < eval(y)),
+	    Predicate::Zero(..)|Predicate::RegZero(_) => None,
+	    Predicate::Not(p) => p.evaluate(pc,operands).map(|b| !b)
+	}
+    }
+    /// Determine the underlying test of the state of the machine made
+    /// by this predicate, and whether it is negated.
+    fn test(&self) -> (&Predicate,bool) {
+	match &self {
+	    Predicate::Not(p) => {
+		let (test,negated) = p.test();
+		(test,!negated)
 	    }
+	    p => (p,false)
 	}
     }
 }
@@ -125,6 +957,8 @@ impl AbstractMicroCode {
 /// Represents an arbitrary expression over one or more instruction
 /// operands.  For each instruction instantiation, an operand
 /// expression can be evaluated to a constant.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub enum Operand {
     /// A constant value which can be used in various ways.  For
     /// example, it can be used to identify a fixed location in the
@@ -132,7 +966,48 @@ pub enum Operand {
     /// complex operand expression.
     Const(usize),
     /// An operand value read from the instantiated instruction.
-    Var(usize)
+    Var(usize),
+    /// The sum of two operand expressions (with wrap around).  For
+    /// example, `Var(0)*4 + 0x100` is `Add(Mul(Var(0),Const(4)),Const(0x100))`.
+    Add(Box<Operand>,Box<Operand>),
+    /// The product of two operand expressions (with wrap around).
+    Mul(Box<Operand>,Box<Operand>),
+    /// One operand expression shifted left by another (where shifting
+    /// by too many bits gives zero).
+    Shl(Box<Operand>,Box<Operand>),
+    /// The address of a register, given its number, in a register
+    /// file starting at address zero whose elements have a given
+    /// width.  For example, `RegSlot(Var(0),QuadWord)` addresses the
+    /// register `Var(0)*8`.  A register file located elsewhere is
+    /// addressed by adding its base (e.g. `Add(Const(base),RegSlot(..))`).
+    RegSlot(Box<Operand>,Width),
+    /// The program counter, for use as a register (see `machine::PC`).
+    Pc,
+    /// The stack pointer, for use as a register (see `machine::SP`).
+    Sp,
+    /// The flags register, for use as a register (see
+    /// `machine::FLAGS`).
+    Flags,
+    /// The link register, for use as a register (see `machine::LR`).
+    Lr,
+    /// A scratch register, for holding intermediate values within an
+    /// instruction (see `machine::temp()`).
+    Temp(usize),
+    /// The pc of the instruction being executed plus an offset (in
+    /// two's complement form).  For example, `Goto(PcRel(Var(0)))` is
+    /// a relative jump, whilst `Copy(Var(0),PcRel(Var(1)),Byte)` is a
+    /// pc-relative load.
+    PcRel(Box<Operand>),
+    /// An operand expression sign extended from a given number of
+    /// bits.  For example, `SExt(Var(0),8)` treats a raw (unsigned)
+    /// eight bit field as a signed value.  Bits above the given width
+    /// are ignored, and extending from zero bits gives zero.
+    SExt(Box<Operand>,u8),
+    /// The bits of an operand expression from a given high bit down to
+    /// a given low bit (inclusive).  For example, `Bits(Var(0),7,4)`
+    /// extracts the upper nibble of an eight bit field.  This gives
+    /// zero when the high bit is below the low bit.
+    Bits(Box<Operand>,u8,u8)
 }
 
 impl Operand {
@@ -140,16 +1015,30 @@ impl Operand {
     /// many operands are needed for it to evaluate.
     pub fn arity(&self) -> usize {
 	match &self {
-	    Operand::Const(i) => {
+	    Operand::Const(_)|Operand::Pc|Operand::Sp|Operand::Flags|Operand::Lr|Operand::Temp(_) => {
 		0
 	    }
 	    Operand::Var(v) => {
 		v + 1
 	    }
+	    Operand::Add(l,r)|Operand::Mul(l,r)|Operand::Shl(l,r) => {
+		cmp::max(l.arity(),r.arity())
+	    }
+	    Operand::RegSlot(r,_)|Operand::PcRel(r)|Operand::SExt(r,_)|Operand::Bits(r,_,_) => {
+		r.arity()
+	    }
 	}
     }
 
+    /// Evaluate this operand expression for a given set of operands,
+    /// where any pc-relative expression is evaluated relative to zero
+    /// (i.e. giving just its offset).
     pub fn as_usize(&self, operands: &[usize]) -> usize {
+	self.evaluate(0,operands)
+    }
+    /// Evaluate this operand expression for a given set of operands,
+    /// for an instruction at a given pc.
+    pub fn evaluate(&self, pc: usize, operands: &[usize]) -> usize {
 	match &self {
 	    Operand::Const(i) => {
 		*i
@@ -157,53 +1046,1531 @@ impl Operand {
 	    Operand::Var(v) => {
 		operands[*v]
 	    }
+	    Operand::Add(l,r) => {
+		l.evaluate(pc,operands).wrapping_add(r.evaluate(pc,operands))
+	    }
+	    Operand::Mul(l,r) => {
+		l.evaluate(pc,operands).wrapping_mul(r.evaluate(pc,operands))
+	    }
+	    Operand::Shl(l,r) => {
+		let r = u32::try_from(r.evaluate(pc,operands)).unwrap_or(u32::MAX);
+		l.evaluate(pc,operands).checked_shl(r).unwrap_or(0)
+	    }
+	    Operand::RegSlot(r,w) => {
+		r.evaluate(pc,operands).wrapping_mul(w.bytes())
+	    }
+	    Operand::PcRel(o) => {
+		pc.wrapping_add(o.evaluate(pc,operands))
+	    }
+	    Operand::SExt(_,0) => {
+		0
+	    }
+	    Operand::SExt(o,n) => {
+		sign_extend(o.evaluate(pc,operands),Bits::from(*n))
+	    }
+	    Operand::Bits(o,hi,lo) if hi >= lo => {
+		let v = o.evaluate(pc,operands).checked_shr(*lo as u32).unwrap_or(0);
+		let n = (hi - lo) as u32 + 1;
+		v & usize::MAX.checked_shr(usize::BITS.saturating_sub(n)).unwrap_or(0)
+	    }
+	    Operand::Bits(..) => {
+		0
+	    }
+	    Operand::Pc => machine::PC,
+	    Operand::Sp => machine::SP,
+	    Operand::Flags => machine::FLAGS,
+	    Operand::Lr => machine::LR,
+	    Operand::Temp(i) => machine::temp(*i)
+	}
+    }
+    /// Check whether this operand expression evaluates to the same
+    /// value regardless of its operands or pc.
+    pub fn is_constant(&self) -> bool {
+	self.arity() == 0 && !self.is_relative()
+    }
+    /// Check whether this operand expression depends on the pc.
+    pub fn is_relative(&self) -> bool {
+	match &self {
+	    Operand::PcRel(_) => true,
+	    Operand::Add(l,r)|Operand::Mul(l,r)|Operand::Shl(l,r) => l.is_relative() || r.is_relative(),
+	    Operand::RegSlot(r,_)|Operand::SExt(r,_)|Operand::Bits(r,_,_) => r.is_relative(),
+	    _ => false
+	}
+    }
+}
+
+/// Determine the first scratch register (if any) which a sequence of
+/// microcode reads before writing, given those already written.
+/// Writes within the branches of an `If` are not considered to have
+/// happened afterwards, since the branch may not be taken.  Likewise,
+/// any scratch register which does not exist is reported.
+fn undefined_temp(codes: &[AbstractMicroCode], written: &mut Vec<usize>) -> Option<usize> {
+    let temp = |o: &Operand| match o {
+	Operand::Temp(i) => Some(*i),
+	_ => None
+    };
+    for code in codes {
+	let (reads,write) = match code {
+	    AbstractMicroCode::RegAdd(x,y)|AbstractMicroCode::RegAlu(_,x,y) => (vec![x,y],Some(x)),
+	    AbstractMicroCode::RegCopy(x,y) => (vec![y],Some(x)),
+	    AbstractMicroCode::RegFetch(x,_,_)|AbstractMicroCode::RegLoad(x,_) => (vec![],Some(x)),
+	    AbstractMicroCode::RegFetchIndirect(x,y,_) => (vec![y],Some(x)),
+	    AbstractMicroCode::RegStore(_,y,_) => (vec![y],None),
+	    AbstractMicroCode::RegStoreIndirect(x,y,_) => (vec![x,y],None),
+	    AbstractMicroCode::If(p,t,e) => {
+		let (test,_) = p.test();
+		let read = match test {
+		    Predicate::RegZero(r) => temp(r),
+		    _ => None
+		};
+		if let Some(i) = read.filter(|i| !written.contains(i)) {
+		    return Some(i);
+		}
+		for branch in [t,e] {
+		    if let Some(i) = undefined_temp(branch,&mut written.clone()) {
+			return Some(i);
+		    }
+		}
+		continue;
+	    }
+	    _ => (vec![],None)
+	};
+	if let Some(i) = reads.into_iter().filter_map(temp).find(|i| !written.contains(i)) {
+	    return Some(i);
+	}
+	match write.and_then(temp) {
+	    Some(i) if i >= machine::TEMPS => { return Some(i); }
+	    Some(i) => { written.push(i); }
+	    None => {}
+	}
+    }
+    None
+}
+
+/// Determine the operand (if any) identifying the register accessed by
+/// a given location, along with the size (in bytes) of each register
+/// slot.
+fn register(location: &Operand) -> Option<(usize,usize)> {
+    match location {
+	Operand::Var(v) => Some((*v,1)),
+	Operand::RegSlot(r,w) => match r.as_ref() {
+	    Operand::Var(v) => Some((*v,w.bytes())),
+	    _ => None
+	}
+	_ => None
+    }
+}
+
+// =====================================================
+// Pseudo-code
+// =====================================================
+
+/// Render an operand expression, where operands are referred to by a
+/// given name (or by position, e.g. `op0`, when they have none) and
+/// nested expressions are bracketed.
+fn write_operand(f: &mut fmt::Formatter, o: &Operand, names: &[&str], nested: bool) -> fmt::Result {
+    let (l,op,r) : (&Operand,&str,&dyn fmt::Display) = match o {
+	Operand::Const(c) => { return write!(f,"{}",c); }
+	Operand::Var(v) => match names.get(*v).filter(|n| !n.is_empty()) {
+	    Some(n) => { return write!(f,"{}",n); }
+	    None => { return write!(f,"op{}",v); }
+	}
+	Operand::Pc => { return write!(f,"pc"); }
+	Operand::Sp => { return write!(f,"sp"); }
+	Operand::Flags => { return write!(f,"flags"); }
+	Operand::Lr => { return write!(f,"lr"); }
+	Operand::Temp(i) => { return write!(f,"t{}",i); }
+	Operand::SExt(x,n) => {
+	    write!(f,"sext{}(",n)?;
+	    write_operand(f,x,names,false)?;
+	    return write!(f,")");
+	}
+	Operand::Bits(x,hi,lo) => {
+	    write_operand(f,x,names,true)?;
+	    return write!(f,"[{}:{}]",hi,lo);
+	}
+	Operand::Add(l,r) => (l,"+",&Named(r,names)),
+	Operand::Mul(l,r) => (l,"*",&Named(r,names)),
+	Operand::Shl(l,r) => (l,"<<",&Named(r,names)),
+	Operand::RegSlot(x,w) => (x,"*",&w.bytes()),
+	Operand::PcRel(x) => (&Operand::Pc,"+",&Named(x,names))
+    };
+    if nested { write!(f,"(")?; }
+    write_operand(f,l,names,true)?;
+    write!(f," {} {}",op,r)?;
+    if nested { write!(f,")")?; }
+    Ok(())
+}
+
+/// Render the register identified by an operand expression.  Special
+/// registers (e.g. `pc`) are referred to by name, whilst others are
+/// referred to by number (e.g. `reg[rd]`).
+fn write_register(f: &mut fmt::Formatter, o: &Operand, names: &[&str]) -> fmt::Result {
+    match o {
+	Operand::Pc|Operand::Sp|Operand::Flags|Operand::Lr|Operand::Temp(_) => write_operand(f,o,names,false),
+	_ => {
+	    write!(f,"reg[")?;
+	    write_operand(f,o,names,false)?;
+	    write!(f,"]")
+	}
+    }
+}
+
+/// Render the memory location (of a given width) identified by an
+/// operand expression (e.g. `mem32[rd]`).
+fn write_memory(f: &mut fmt::Formatter, o: &Operand, w: Width, names: &[&str]) -> fmt::Result {
+    write!(f,"mem{}[",8 * w.bytes())?;
+    write_operand(f,o,names,false)?;
+    write!(f,"]")
+}
+
+fn write_predicate(f: &mut fmt::Formatter, p: &Predicate, names: &[&str]) -> fmt::Result {
+    let (x,op,y) = match p {
+	Predicate::Eq(x,y) => (x,"==",y),
+	Predicate::Ne(x,y) => (x,"!=",y),
+	Predicate::Lt(x,y) => (x,"<",y),
+	Predicate::Zero(x,w) => {
+	    write_memory(f,x,*w,names)?;
+	    return write!(f," == 0");
+	}
+	Predicate::RegZero(x) => {
+	    write_register(f,x,names)?;
+	    return write!(f," == 0");
+	}
+	Predicate::Not(p) => {
+	    write!(f,"!(")?;
+	    write_predicate(f,p,names)?;
+	    return write!(f,")");
+	}
+    };
+    write_operand(f,x,names,false)?;
+    write!(f," {} ",op)?;
+    write_operand(f,y,names,false)
+}
+
+fn write_microcode(f: &mut fmt::Formatter, code: &AbstractMicroCode, names: &[&str]) -> fmt::Result {
+    let op = |f: &mut fmt::Formatter, o| write_operand(f,o,names,false);
+    let reg = |f: &mut fmt::Formatter, o| write_register(f,o,names);
+    let mem = |f: &mut fmt::Formatter, o, w| write_memory(f,o,w,names);
+    match code {
+	AbstractMicroCode::Add(x,y,w) => {
+	    mem(f,x,*w)?; write!(f," := ")?; mem(f,x,*w)?; write!(f," + ")?; mem(f,y,*w)
+	}
+	AbstractMicroCode::Alu(op,x,y,w) => {
+	    mem(f,x,*w)?; write!(f," := ")?; mem(f,x,*w)?; write!(f," {} ",op)?; mem(f,y,*w)
+	}
+	AbstractMicroCode::Copy(x,y,w) => {
+	    mem(f,x,*w)?; write!(f," := ")?; mem(f,y,*w)
+	}
+	AbstractMicroCode::Goto(x) => {
+	    write!(f,"pc := ")?; op(f,x)
+	}
+	AbstractMicroCode::Jump(x) => {
+	    write!(f,"pc := pc + ")?; write_operand(f,x,names,true)
+	}
+	AbstractMicroCode::Load(x,i,w) => {
+	    mem(f,x,*w)?; write!(f," := ")?; op(f,i)
+	}
+	AbstractMicroCode::RegAdd(x,y) => {
+	    reg(f,x)?; write!(f," := ")?; reg(f,x)?; write!(f," + ")?; reg(f,y)
+	}
+	AbstractMicroCode::RegAlu(op,x,y) => {
+	    reg(f,x)?; write!(f," := ")?; reg(f,x)?; write!(f," {} ",op)?; reg(f,y)
+	}
+	AbstractMicroCode::RegCopy(x,y) => {
+	    reg(f,x)?; write!(f," := ")?; reg(f,y)
+	}
+	AbstractMicroCode::RegFetch(x,y,w) => {
+	    reg(f,x)?; write!(f," := ")?; mem(f,y,*w)
+	}
+	AbstractMicroCode::RegFetchIndirect(x,y,w) => {
+	    reg(f,x)?; write!(f," := mem{}[",8 * w.bytes())?; reg(f,y)?; write!(f,"]")
+	}
+	AbstractMicroCode::RegLoad(x,i) => {
+	    reg(f,x)?; write!(f," := ")?; op(f,i)
+	}
+	AbstractMicroCode::RegStore(x,y,w) => {
+	    mem(f,x,*w)?; write!(f," := ")?; reg(f,y)
+	}
+	AbstractMicroCode::RegStoreIndirect(x,y,w) => {
+	    write!(f,"mem{}[",8 * w.bytes())?; reg(f,x)?; write!(f,"] := ")?; reg(f,y)
+	}
+	AbstractMicroCode::If(p,t,e) => {
+	    write!(f,"if ")?;
+	    write_predicate(f,p,names)?;
+	    write!(f," then ")?;
+	    write_block(f,t,names)?;
+	    if !e.is_empty() {
+		write!(f," else ")?;
+		write_block(f,e,names)?;
+	    }
+	    Ok(())
 	}
     }
 }
 
+/// Render a sequence of microcode on one line (e.g. `{ pc := 0; sp := 1 }`).
+fn write_block(f: &mut fmt::Formatter, codes: &[AbstractMicroCode], names: &[&str]) -> fmt::Result {
+    write!(f,"{{")?;
+    for (i,c) in codes.iter().enumerate() {
+	write!(f,"{}",if i == 0 { " " } else { "; " })?;
+	write_microcode(f,c,names)?;
+    }
+    write!(f," }}")
+}
+
+/// An operand expression rendered with given operand names.
+struct Named<'a>(&'a Operand,&'a [&'a str]);
+
+impl fmt::Display for Named<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write_operand(f,self.0,self.1,true)
+    }
+}
+
+/// Renders an operand expression as pseudo-code (e.g. `op0 * 4 + 256`).
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write_operand(f,self,&[],false)
+    }
+}
+
+/// Renders a predicate as pseudo-code (e.g. `mem8[op0] == 0`).
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write_predicate(f,self,&[])
+    }
+}
+
+/// Renders abstract microcode as pseudo-code (e.g. `mem32[op0] :=
+/// mem32[op0] + mem32[op1]`).
+impl fmt::Display for AbstractMicroCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write_microcode(f,self,&[])
+    }
+}
+
+// =====================================================
+// Metadata
+// =====================================================
+
+/// Broadly classifies what an instruction does.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Category {
+    /// Arithmetic and logical operations.
+    Alu,
+    /// Conditional or unconditional transfers of control.
+    Branch,
+    /// Transfers between registers and memory.
+    LoadStore,
+    /// Transfers between registers (or of constants into registers).
+    Move,
+    /// Operations on the machine itself (e.g. `halt`).
+    System,
+    /// Any other classification.
+    Other(String)
+}
+
+/// Descriptive information about an instruction which is not needed
+/// to execute it, but is useful for tools (e.g. document generators
+/// or analyzers).
+#[derive(Clone,Debug,Default,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize),serde(default))]
+pub struct Metadata {
+    /// What kind of instruction this is.
+    pub category: Option<Category>,
+    /// A one-line, human-readable description.
+    pub description: Option<String>,
+    /// The names of flags read by this instruction.
+    pub reads: Vec<String>,
+    /// The names of flags written by this instruction.
+    pub writes: Vec<String>
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+	Self::default()
+    }
+    /// Set the category of this instruction.
+    pub fn category(mut self, category: Category) -> Self {
+	self.category = Some(category);
+	self
+    }
+    /// Set the description of this instruction.
+    pub fn description(mut self, description: &str) -> Self {
+	self.description = Some(description.to_string());
+	self
+    }
+    /// Set the flags read by this instruction.
+    pub fn reads(mut self, flags: &[&str]) -> Self {
+	self.reads = flags.iter().map(|f| f.to_string()).collect();
+	self
+    }
+    /// Set the flags written by this instruction.
+    pub fn writes(mut self, flags: &[&str]) -> Self {
+	self.writes = flags.iter().map(|f| f.to_string()).collect();
+	self
+    }
+}
+
 // =====================================================
 // Instruction
 // =====================================================
 
+/// A machine instruction, consisting of a mnemonic, a format and its
+/// semantics.  These are either borrowed (e.g. from static
+/// definitions) or owned (e.g. when constructed at runtime).
+#[derive(Clone,Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub struct Instruction<'a> {
     /// Mnemonic for referring to the instruction.  Every instruction
     /// should have a unique mnemonic.
-    mnemonic: &'a str,
+    mnemonic: Cow<'a,str>,
     /// Format associated with this instruction.
-    format: &'a Format,
+    format: Cow<'a,Format>,
     /// Machine semantics associated with instruction.
-    semantic: &'a [AbstractMicroCode]
+    semantic: Cow<'a,[AbstractMicroCode]>,
+    /// Descriptive information about this instruction.
+    #[cfg_attr(feature="serde", serde(default))]
+    metadata: Metadata,
+    /// The feature bit (if any) which must be enabled for this
+    /// instruction to execute.
+    #[cfg_attr(feature="serde", serde(default))]
+    feature: Option<u8>,
+    /// Properties which the semantics of this instruction should
+    /// satisfy (see `verify::check_properties()`).
+    #[cfg_attr(feature="serde", serde(default))]
+    properties: Vec<Property>
 }
 
 impl<'a> Instruction<'a> {
+    /// Construct an instruction with given semantics.  This panics if
+    /// the semantics refer to operands which the format does not have
+    /// (see `try_new()` or `InstructionSetBuilder` for constructing
+    /// instructions without panicking).
     pub fn new(mnemonic: &'a str, format: &'a Format, semantic: &'a [AbstractMicroCode]) -> Self {
-	for code in semantic {
-	    assert!(code.arity() <= format.operands.len());
-	}
-	Instruction{mnemonic,format,semantic}
+	Self::try_new(mnemonic,format,semantic).expect("semantics refer to missing operands")
     }
-
+    /// Construct an instruction as for `new()`, but failing with
+    /// `IsaError::InvalidSemantic` rather than panicking.
+    pub fn try_new(mnemonic: &'a str, format: &'a Format, semantic: &'a [AbstractMicroCode]) -> Result<Self,IsaError> {
+	check_semantic(mnemonic,format,semantic)?;
+	Ok(Instruction{mnemonic:Cow::Borrowed(mnemonic),format:Cow::Borrowed(format),semantic:Cow::Borrowed(semantic),metadata:Metadata::new(),feature:None,properties:Vec::new()})
+    }
+    /// Construct an instruction which owns its format and semantics.
+    /// As for `new()`, this panics if the semantics refer to missing
+    /// operands (see `try_owned()`).
+    pub fn owned(mnemonic: &str, format: Format, semantic: Vec<AbstractMicroCode>) -> Instruction<'static> {
+	Self::try_owned(mnemonic,format,semantic).expect("semantics refer to missing operands")
+    }
+    /// Construct an instruction as for `owned()`, but failing with
+    /// `IsaError::InvalidSemantic` rather than panicking.
+    pub fn try_owned(mnemonic: &str, format: Format, semantic: Vec<AbstractMicroCode>) -> Result<Instruction<'static>,IsaError> {
+	check_semantic(mnemonic,&format,&semantic)?;
+	Ok(Instruction{mnemonic:Cow::Owned(mnemonic.to_string()),format:Cow::Owned(format),semantic:Cow::Owned(semantic),metadata:Metadata::new(),feature:None,properties:Vec::new()})
+    }
+    /// Get the mnemonic used to refer to this instruction.
+    pub fn mnemonic(&self) -> &str {
+	&self.mnemonic
+    }
+    /// Get the format associated with this instruction.
+    pub fn format(&self) -> &Format {
+	&self.format
+    }
+    /// Get the microcode semantics of this instruction.
+    pub fn semantic(&self) -> &[AbstractMicroCode] {
+	&self.semantic
+    }
+    /// Attach descriptive information to this instruction.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+	self.metadata = metadata;
+	self
+    }
+    /// Get the descriptive information attached to this instruction.
+    pub fn metadata(&self) -> &Metadata {
+	&self.metadata
+    }
+    /// Mark this instruction as requiring a given feature bit (e.g.
+    /// for an optional floating point unit) to be enabled in the
+    /// machine executing it.
+    pub fn requires(mut self, feature: u8) -> Self {
+	self.feature = Some(feature);
+	self
+    }
+    /// Get the feature bit (if any) required by this instruction.
+    pub fn feature(&self) -> Option<u8> {
+	self.feature
+    }
+    /// Attach a property which the semantics of this instruction
+    /// should satisfy.
+    pub fn with_property(mut self, property: Property) -> Self {
+	self.properties.push(property);
+	self
+    }
+    /// Get the properties attached to this instruction.
+    pub fn properties(&self) -> &[Property] {
+	&self.properties
+    }
+    /// Get the number of operands taken by this instruction.
+    pub fn arity(&self) -> usize {
+	self.format.operands.len()
+    }
+    /// Determine whether a given operand is used as a pc-relative
+    /// offset (i.e. as the target of a `Jump` or within `PcRel`,
+    /// either directly or sign extended).
+    /// Such operands are encoded relative to the pc of this
+    /// instruction.
+    pub fn is_relative(&self, operand: usize) -> bool {
+	let offset = |o: &Operand| match o {
+	    Operand::SExt(x,_) => **x == Operand::Var(operand),
+	    o => *o == Operand::Var(operand)
+	};
+	let relative = |o: &Operand| matches!(o,Operand::PcRel(x) if offset(x));
+	self.semantic.iter().flat_map(|c| c.nested()).any(|c| match c {
+	    AbstractMicroCode::Jump(x) if offset(x) => true,
+	    AbstractMicroCode::Add(x,y,_)|AbstractMicroCode::Alu(_,x,y,_)|AbstractMicroCode::Copy(x,y,_)|AbstractMicroCode::Load(x,y,_)
+		|AbstractMicroCode::RegAdd(x,y)|AbstractMicroCode::RegAlu(_,x,y)|AbstractMicroCode::RegCopy(x,y)|AbstractMicroCode::RegFetch(x,y,_)
+		|AbstractMicroCode::RegFetchIndirect(x,y,_)|AbstractMicroCode::RegLoad(x,y)|AbstractMicroCode::RegStore(x,y,_)
+		|AbstractMicroCode::RegStoreIndirect(x,y,_) => relative(x) || relative(y),
+	    AbstractMicroCode::Goto(x)|AbstractMicroCode::Jump(x) => relative(x),
+	    // Nested microcode is considered separately
+	    AbstractMicroCode::If(..) => false
+	})
+    }
+    /// Reduce the semantics of this instruction into concrete
+    /// microcode for a given set of operands (where pc-relative
+    /// operands are relative to zero).
     pub fn to_microcode(&self, operands: &[usize]) -> Vec<MicroCode> {
+	self.to_microcode_at(0,operands)
+    }
+    /// Reduce the semantics of this instruction, located at a given
+    /// pc, into concrete microcode for a given set of operands.
+    pub fn to_microcode_at(&self, pc: usize, operands: &[usize]) -> Vec<MicroCode> {
 	let mut microcode = Vec::new();
-	for c in self.semantic {
-	    microcode.push(c.to_microcode(operands));
-	}
+	self.to_microcode_into(pc,operands,&mut microcode);
 	microcode
     }
+    /// Reduce the semantics of this instruction, located at a given
+    /// pc, into concrete microcode appended to a given buffer.  Unlike
+    /// `to_microcode_at()`, this performs no allocation once the buffer
+    /// is large enough.  For example:
+    ///
+    /// ```text
+    /// let mut buffer = Vec::new();
+    /// for operands in cases {
+    ///     buffer.clear();
+    ///     insn.to_microcode_into(pc,&operands,&mut buffer);
+    ///     state.execute_all(&buffer);
+    /// }
+    /// ```
+    pub fn to_microcode_into(&self, pc: usize, operands: &[usize], out: &mut Vec<MicroCode>) {
+	for c in self.semantic.iter() {
+	    c.to_microcode_into(pc,operands,out);
+	}
+    }
+}
+
+/// Renders an instruction as its mnemonic and operands, followed by
+/// its semantics as pseudo-code (one microcode per line).  For example:
+///
+/// ```text
+/// add rd, rs
+///     mem8[rd] := mem8[rd] + mem8[rs]
+/// ```
+impl fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let names : Vec<&str> = self.format.operands.iter().map(|o| o.name()).collect();
+	write!(f,"{}",self.mnemonic)?;
+	for i in 0..names.len() {
+	    write!(f,"{}{}",if i == 0 { " " } else { ", " },Named(&Operand::Var(i),&names))?;
+	}
+	for c in self.semantic.iter() {
+	    write!(f,"\n    ")?;
+	    write_microcode(f,c,&names)?;
+	}
+	Ok(())
+    }
+}
+
+/// Check the semantics of an instruction only refer to operands which
+/// its format has.
+fn check_semantic(mnemonic: &str, format: &Format, semantic: &[AbstractMicroCode]) -> Result<(),IsaError> {
+    if semantic.iter().any(|c| c.arity() > format.operands.len()) {
+	return Err(IsaError::InvalidSemantic(mnemonic.to_string()));
+    }
+    Ok(())
+}
+
+// =====================================================
+// Pseudo Instruction
+// =====================================================
+
+/// An assembler-level instruction which does not exist in the
+/// machine itself but, instead, expands into a sequence of one or
+/// more real instructions.  For example, `nop` might expand into `add
+/// r0, r0`, whilst `mv rd, rs` might expand into `add rd, rs, r0`.
+/// Each operand in the expansion is an operand expression over the
+/// operands of the pseudo instruction.
+#[derive(Clone,Debug)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct PseudoInstruction<'a> {
+    /// Mnemonic for referring to the pseudo instruction.
+    mnemonic: Cow<'a,str>,
+    /// Sequence of real instructions (and their operands) which this
+    /// pseudo instruction expands into.
+    expansion: Vec<(Cow<'a,str>,Cow<'a,[Operand]>)>
+}
+
+impl<'a> PseudoInstruction<'a> {
+    /// Construct a pseudo instruction with a given expansion.  This
+    /// panics if the expansion is empty (see `try_new()` or
+    /// `InstructionSetBuilder::pseudo()` for constructing pseudo
+    /// instructions without panicking).
+    pub fn new(mnemonic: &'a str, expansion: &'a [(&'a str, &'a [Operand])]) -> Self {
+	Self::try_new(mnemonic,expansion).expect("empty expansion")
+    }
+    /// Construct a pseudo instruction as for `new()`, but failing with
+    /// `IsaError::InvalidPseudo` rather than panicking.
+    pub fn try_new(mnemonic: &'a str, expansion: &'a [(&'a str, &'a [Operand])]) -> Result<Self,IsaError> {
+	if expansion.is_empty() {
+	    return Err(IsaError::InvalidPseudo(mnemonic.to_string()));
+	}
+	let expansion = expansion.iter().map(|(m,ops)| (Cow::Borrowed(*m),Cow::Borrowed(*ops))).collect();
+	Ok(PseudoInstruction{mnemonic:Cow::Borrowed(mnemonic),expansion})
+    }
+    /// Construct a pseudo instruction which owns its expansion.  As
+    /// for `new()`, this panics if the expansion is empty (see
+    /// `try_owned()`).
+    pub fn owned(mnemonic: &str, expansion: Vec<(String,Vec<Operand>)>) -> PseudoInstruction<'static> {
+	Self::try_owned(mnemonic,expansion).expect("empty expansion")
+    }
+    /// Construct a pseudo instruction as for `owned()`, but failing
+    /// with `IsaError::InvalidPseudo` rather than panicking.
+    pub fn try_owned(mnemonic: &str, expansion: Vec<(String,Vec<Operand>)>) -> Result<PseudoInstruction<'static>,IsaError> {
+	if expansion.is_empty() {
+	    return Err(IsaError::InvalidPseudo(mnemonic.to_string()));
+	}
+	let expansion = expansion.into_iter().map(|(m,ops)| (Cow::Owned(m),Cow::Owned(ops))).collect();
+	Ok(PseudoInstruction{mnemonic:Cow::Owned(mnemonic.to_string()),expansion})
+    }
+    /// Get the mnemonic used to refer to this pseudo instruction.
+    pub fn mnemonic(&self) -> &str {
+	&self.mnemonic
+    }
+    /// Get the sequence of real instructions (i.e. mnemonic and
+    /// operands) which this pseudo instruction expands into.
+    pub fn expansion(&self) -> impl ExactSizeIterator<Item=(&str,&[Operand])> {
+	self.expansion.iter().map(|(m,ops)| (m.as_ref(),ops.as_ref()))
+    }
+    /// Determine how many operands this pseudo instruction requires.
+    pub fn arity(&self) -> usize {
+	self.expansion.iter().flat_map(|(_,ops)| ops.iter()).map(|o| o.arity()).max().unwrap_or(0)
+    }
 }
 
 // =====================================================
 // Instruction Set
 // =====================================================   
 
-/// A collection of instructions.
+/// Identifies a problem with the definition of an instruction set.
+#[derive(Clone,Debug,PartialEq)]
+pub enum IsaError {
+    /// More than one instruction has the given mnemonic.
+    DuplicateMnemonic(String),
+    /// The opcode assigned to an instruction does not fit into the
+    /// opcode field of its format.
+    OpcodeExhausted{mnemonic: String, opcode: usize, bits: u8},
+    /// The semantics of an instruction refer to operands which it
+    /// does not have.
+    InvalidSemantic(String),
+    /// A pseudo instruction has an empty expansion, or refers to an
+    /// instruction which does not exist (or with the wrong number of
+    /// operands).
+    InvalidPseudo(String),
+    /// Two instructions (e.g. from different extensions) were
+    /// assigned the same opcode.
+    OpcodeConflict{first: String, second: String, opcode: usize},
+    /// An extension was requested which has not been defined.
+    UnknownExtension(String),
+    /// An instruction was appended (e.g. to an
+    /// `InstructionSetBuilder`) after one assigned the largest
+    /// possible opcode, hence no opcode remains for it.
+    OpcodeOverflow(String),
+    /// The semantics of an instruction access memory at a width not
+    /// permitted by the machine profile.
+    IllegalWidth{mnemonic: String, width: Width},
+    /// The semantics of an instruction access a fixed location
+    /// outside the memory of the machine profile.
+    IllegalAddress{mnemonic: String, address: usize},
+    /// The semantics of an instruction use a register operand to
+    /// access memory, but that operand can identify locations outside
+    /// the register file of the machine profile.
+    IllegalRegister{mnemonic: String, operand: usize},
+    /// The semantics of an instruction read a scratch register which
+    /// may not have been written by that instruction, or use a
+    /// scratch register which does not exist.
+    UndefinedTemp{mnemonic: String, temp: usize},
+    /// Metadata, a feature or a property was given (e.g. to an
+    /// `InstructionSetBuilder`) before any instruction.
+    NoInstruction(String)
+}
+
+impl fmt::Display for IsaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    IsaError::DuplicateMnemonic(m) => write!(f,"duplicate mnemonic \"{}\"",m),
+	    IsaError::OpcodeExhausted{mnemonic,opcode,bits} => {
+		write!(f,"opcode {} of \"{}\" does not fit in {} bit(s)",opcode,mnemonic,bits)
+	    }
+	    IsaError::InvalidSemantic(m) => write!(f,"semantics of \"{}\" refer to missing operands",m),
+	    IsaError::InvalidPseudo(m) => write!(f,"invalid expansion for pseudo instruction \"{}\"",m),
+	    IsaError::OpcodeConflict{first,second,opcode} => {
+		write!(f,"opcode {} assigned to both \"{}\" and \"{}\"",opcode,first,second)
+	    }
+	    IsaError::UnknownExtension(e) => write!(f,"unknown extension \"{}\"",e),
+	    IsaError::OpcodeOverflow(m) => write!(f,"no opcode remains for \"{}\"",m),
+	    IsaError::IllegalWidth{mnemonic,width} => write!(f,"\"{}\" uses unsupported width {:?}",mnemonic,width),
+	    IsaError::IllegalAddress{mnemonic,address} => {
+		write!(f,"\"{}\" accesses address {} outside of memory",mnemonic,address)
+	    }
+	    IsaError::IllegalRegister{mnemonic,operand} => {
+		write!(f,"operand {} of \"{}\" can address outside of register file",operand,mnemonic)
+	    }
+	    IsaError::UndefinedTemp{mnemonic,temp} => {
+		write!(f,"\"{}\" uses scratch register {} before it is written",mnemonic,temp)
+	    }
+	    IsaError::NoInstruction(what) => write!(f,"{} given before any instruction",what)
+	}
+    }
+}
+
+impl core::error::Error for IsaError {}
+
+/// A collection of instructions, along with any pseudo instructions
+/// defined over them.  An instruction set may also have a number of
+/// (optional) extensions, which can be enabled to produce a larger
+/// instruction set.
 pub struct InstructionSet<'a> {
-    insns : &'a [Instruction<'a>]
+    insns : Vec<Instruction<'a>>,
+    /// The opcode assigned to each instruction.
+    opcodes : Vec<usize>,
+    pseudos : Vec<PseudoInstruction<'a>>,
+    /// The extensions which could be enabled for this set.
+    extensions : Vec<Extension<'a>>,
+    /// The names of extensions which are enabled for this set.
+    enabled : Vec<String>,
+    /// Maps each mnemonic to the index of the (first) instruction
+    /// with that mnemonic.
+    index : BTreeMap<String,usize>,
+    /// As for `index`, but with mnemonics in lowercase.
+    lowercase : BTreeMap<String,usize>,
+    /// Maps opcode values to instructions for each format.
+    dispatch : DispatchTable
 }
 
 impl<'a> InstructionSet<'a> {
     pub fn new(insns : &'a [Instruction<'a>]) -> Self {
-	InstructionSet{insns}
+	Self::owned(insns.to_vec())
+    }
+    /// Construct an instruction set which owns its instructions.
+    pub fn owned(insns: Vec<Instruction<'a>>) -> Self {
+	let opcodes = (0..insns.len()).collect();
+	Self::from_parts(insns,opcodes)
+    }
+    fn from_parts(insns: Vec<Instruction<'a>>, opcodes: Vec<usize>) -> Self {
+	let mut index = BTreeMap::new();
+	let mut lowercase = BTreeMap::new();
+	for (i,insn) in insns.iter().enumerate() {
+	    index.entry(insn.mnemonic.to_string()).or_insert(i);
+	    lowercase.entry(insn.mnemonic.to_lowercase()).or_insert(i);
+	}
+	let dispatch = DispatchTable::new(&insns,&opcodes);
+	let (extensions,enabled) = (Vec::new(),Vec::new());
+	InstructionSet{insns,opcodes,pseudos:Vec::new(),extensions,enabled,index,lowercase,dispatch}
+    }
+    /// Define an extension which could be enabled for this
+    /// instruction set.
+    pub fn extension(mut self, extension: Extension<'a>) -> Self {
+	self.extensions.push(extension);
+	self
     }
+    /// Get the extensions which could be enabled for this set.
+    pub fn extensions(&self) -> &[Extension<'a>] {
+	&self.extensions
+    }
+    /// Get the names of extensions enabled for this set (in order of
+    /// enabling).
+    pub fn enabled(&self) -> &[String] {
+	&self.enabled
+    }
+    /// Construct the instruction set resulting from enabling a given
+    /// set of extensions (e.g. `["M","C"]`).  The instructions of each
+    /// extension are appended in the order given, retaining the
+    /// opcodes assigned by their extension.  Thus, any encoding
+    /// conflicts are reported (see `validate()`).
+    pub fn with_extensions(&self, names: &[&str]) -> Result<InstructionSet<'a>,Vec<IsaError>> {
+	let mut insns = self.insns.to_vec();
+	let mut opcodes = self.opcodes.clone();
+	let mut enabled = self.enabled.clone();
+	for name in names {
+	    if enabled.iter().any(|e| e == name) { continue; }
+	    match self.extensions.iter().find(|e| e.name() == *name) {
+		Some(ext) => {
+		    insns.extend(ext.insns.iter().cloned());
+		    opcodes.extend((0..ext.insns.len()).map(|i| ext.opcode.saturating_add(i)));
+		    enabled.push(name.to_string());
+		}
+		None => { return Err(vec![IsaError::UnknownExtension(name.to_string())]); }
+	    }
+	}
+	let mut isa = InstructionSet::from_parts(insns,opcodes);
+	isa.validate()?;
+	isa.pseudos = self.pseudos.clone();
+	isa.extensions = self.extensions.clone();
+	isa.enabled = enabled;
+	Ok(isa)
+    }
+    /// Define pseudo instructions for this instruction set.  This
+    /// panics unless every instruction referred to by a pseudo
+    /// instruction exists in this set, and is given the right number
+    /// of operands (see `try_with_pseudos()` or
+    /// `InstructionSetBuilder::pseudo()` for defining them without
+    /// panicking).
+    pub fn with_pseudos(self, pseudos: &'a [PseudoInstruction<'a>]) -> Self {
+	self.try_with_pseudos(pseudos).expect("invalid pseudo instruction")
+    }
+    /// Define pseudo instructions as for `with_pseudos()`, but failing
+    /// with `IsaError::InvalidPseudo` (for the first invalid pseudo
+    /// instruction) rather than panicking.
+    pub fn try_with_pseudos(self, pseudos: &'a [PseudoInstruction<'a>]) -> Result<Self,IsaError> {
+	self.with_pseudos_vec(pseudos.to_vec())
+    }
+    fn with_pseudos_vec(mut self, pseudos: Vec<PseudoInstruction<'a>>) -> Result<Self,IsaError> {
+	if let Some(p) = pseudos.iter().find(|p| !self.check_pseudo(p)) {
+	    return Err(IsaError::InvalidPseudo(p.mnemonic.to_string()));
+	}
+	self.pseudos = pseudos;
+	Ok(self)
+    }
+    /// Check that every instruction referred to by a pseudo
+    /// instruction exists, and is given the right number of operands.
+    fn check_pseudo(&self, pseudo: &PseudoInstruction) -> bool {
+	pseudo.expansion().all(|(m,ops)| self.get(m).is_some_and(|i| i.arity() == ops.len()))
+    }
+    /// Get the pseudo instructions defined for this set.
+    pub fn pseudos(&self) -> &[PseudoInstruction<'a>] {
+	&self.pseudos
+    }
+    /// Get the number of instructions in this set.
+    pub fn len(&self) -> usize {
+	self.insns.len()
+    }
+    /// Check whether this set contains any instructions.
+    pub fn is_empty(&self) -> bool {
+	self.insns.is_empty()
+    }
+    /// Get the distinct formats used by instructions in this set, in
+    /// order of their first use.
+    pub fn formats(&self) -> Vec<&Format> {
+	let mut formats : Vec<&Format> = Vec::new();
+	for insn in self.insns.iter() {
+	    if !formats.contains(&insn.format()) {
+		formats.push(insn.format());
+	    }
+	}
+	formats
+    }
+    /// Determine the opcode assigned to the instruction at a given
+    /// index in this set.  Opcodes are assigned in order of
+    /// definition from a single opcode space shared by all formats.
+    /// Thus, the first instruction has opcode `0`, the second has
+    /// opcode `1`, etc.  The exception is for instructions from
+    /// extensions, whose opcodes are assigned by the extension.
+    pub fn opcode(&self, index: usize) -> usize {
+	self.opcodes[index]
+    }
+    /// Get the instruction at a given index in this set.
+    pub fn instruction(&self, index: usize) -> &Instruction<'a> {
+	&self.insns[index]
+    }
+    /// Get all instructions in this set (in order of definition).
+    pub fn instructions(&self) -> &[Instruction<'a>] {
+	&self.insns
+    }
+    /// Iterate the instructions in this set (in order of definition).
+    pub fn iter(&self) -> core::slice::Iter<'_,Instruction<'a>> {
+	self.insns.iter()
+    }
+    /// Iterate the instructions in this set which have a given format.
+    pub fn by_format<'b>(&'b self, format: &'b Format) -> impl Iterator<Item=&'b Instruction<'a>> {
+	self.insns.iter().filter(move |i| i.format() == format)
+    }
+    /// Iterate the instructions in this set which have a given
+    /// category.
+    pub fn by_category<'b>(&'b self, category: &'b Category) -> impl Iterator<Item=&'b Instruction<'a>> {
+	self.insns.iter().filter(move |i| i.metadata.category.as_ref() == Some(category))
+    }
+    /// Iterate the instructions in this set which take a given number
+    /// of operands.
+    pub fn by_arity(&self, arity: usize) -> impl Iterator<Item=&Instruction<'a>> {
+	self.insns.iter().filter(move |i| i.arity() == arity)
+    }
+    /// Determine the index of the instruction with a given mnemonic
+    /// (if any).
+    pub fn index_of(&self, mnemonic: &str) -> Option<usize> {
+	self.index.get(mnemonic).copied()
+    }
+    /// Get the instruction with a given mnemonic (if any).
+    pub fn get(&self, mnemonic: &str) -> Option<&Instruction<'a>> {
+	self.index_of(mnemonic).map(|i| &self.insns[i])
+    }
+    /// Get the instruction with a given mnemonic (if any), ignoring
+    /// case.  Thus, `MOV`, `Mov` and `mov` are all equivalent.
+    pub fn get_ignore_case(&self, mnemonic: &str) -> Option<&Instruction<'a>> {
+	self.lowercase.get(&mnemonic.to_lowercase()).map(|i| &self.insns[*i])
+    }
+    /// Encode an instruction with a given mnemonic and operands.
+    pub fn encode(&self, mnemonic: &str, operands: &[usize]) -> Result<Vec<u8>,EncodeError> {
+	match self.index_of(mnemonic) {
+	    Some(index) => {
+		self.insns[index].format.encode(self.opcode(index),operands)
+	    }
+	    None => Err(EncodeError::UnknownMnemonic(mnemonic.to_string()))
+	}
+    }
+    /// Check this instruction set is well-formed, reporting every
+    /// problem found.  Specifically, mnemonics must be unique and the
+    /// opcode assigned to each instruction must be unique and fit
+    /// into its format.  Otherwise, some instructions could not be
+    /// assembled, encoded or decoded.
+    pub fn validate(&self) -> Result<(),Vec<IsaError>> {
+	let mut errors = Vec::new();
+	let mut assigned : BTreeMap<usize,usize> = BTreeMap::new();
+	for (i,insn) in self.insns.iter().enumerate() {
+	    if let Some(j) = assigned.insert(self.opcode(i),i) {
+		let (first,second) = (self.insns[j].mnemonic.to_string(),insn.mnemonic.to_string());
+		errors.push(IsaError::OpcodeConflict{first,second,opcode:self.opcode(i)});
+	    }
+	    let duplicate = IsaError::DuplicateMnemonic(insn.mnemonic.to_string());
+	    if self.index_of(&insn.mnemonic) != Some(i) && !errors.contains(&duplicate) {
+		errors.push(duplicate);
+	    }
+	    let opcode = self.opcode(i);
+	    if !fits_unsigned(opcode,insn.format.opcode) {
+		let bits = insn.format.opcode.value();
+		errors.push(IsaError::OpcodeExhausted{mnemonic:insn.mnemonic.to_string(),opcode,bits});
+	    }
+	}
+	if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+    /// Check that the semantics of every instruction in this set are
+    /// legal for a given machine.  That is, every memory access uses a
+    /// permitted width, every fixed location lies within memory and
+    /// every register operand used to access memory can only identify
+    /// locations within the register file.
+    pub fn check_profile(&self, profile: &MachineProfile) -> Result<(),Vec<IsaError>> {
+	let mut errors = Vec::new();
+	for insn in &self.insns {
+	    let mnemonic = || insn.mnemonic.to_string();
+	    for code in insn.semantic.iter().flat_map(|c| c.nested()) {
+		let (locations,width) = match code {
+		    AbstractMicroCode::Add(x,y,w)|AbstractMicroCode::Alu(_,x,y,w)|AbstractMicroCode::Copy(x,y,w) => (vec![x,y],*w),
+		    AbstractMicroCode::Load(x,_,w) => (vec![x],*w),
+		    AbstractMicroCode::RegFetch(_,x,w)|AbstractMicroCode::RegStore(x,_,w) => (vec![x],*w),
+		    _ => { continue; }
+		};
+		let error = IsaError::IllegalWidth{mnemonic:mnemonic(),width};
+		if !profile.widths.contains(&width) && !errors.contains(&error) {
+		    errors.push(error);
+		}
+		for l in locations {
+		    let error = match l {
+			l if l.is_constant() && l.as_usize(&[]).saturating_add(width.bytes()) > profile.memory => {
+			    IsaError::IllegalAddress{mnemonic:mnemonic(),address:l.as_usize(&[])}
+			}
+			_ => match register(l) {
+			    Some((v,slot)) if insn.format.operands[v].kind == FieldKind::Register => {
+				let bits = insn.format.operands[v].bits.value() as u32;
+				let last = 1usize.checked_shl(bits).map(|n| n - 1).unwrap_or(usize::MAX);
+				let end = last.saturating_mul(slot).saturating_add(width.bytes());
+				if profile.registers.start == 0 && end <= profile.registers.end { continue; }
+				IsaError::IllegalRegister{mnemonic:mnemonic(),operand:v}
+			    }
+			    _ => { continue; }
+			}
+		    };
+		    if !errors.contains(&error) {
+			errors.push(error);
+		    }
+		}
+	    }
+	}
+	if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+    /// Get the table mapping opcode values to instructions for each
+    /// format of this set.
+    pub fn dispatch_table(&self) -> &DispatchTable {
+	&self.dispatch
+    }
+    /// Decode the instruction at the start of a given sequence of
+    /// bytes, producing the index of the instruction in this set and
+    /// its operands.  Formats are tried in order of their first use,
+    /// with the first whose opcode field identifies an instruction of
+    /// that format being chosen.
+    pub fn decode(&self, bytes: &[u8]) -> Result<(usize,Vec<usize>),DecodeError> {
+	self.decode_cached(bytes,&mut FormatCache::default())
+    }
+    /// Decode an instruction as for `decode()`, but first trying the
+    /// format which matched when a given cache was last used.  For
+    /// example, when decoding a sequence of instructions, this avoids
+    /// trying each format in turn when consecutive instructions share
+    /// a format.  The cached format is only tried first when this
+    /// cannot change the result (see `DispatchTable::is_exclusive()`).
+    pub fn decode_cached(&self, bytes: &[u8], cache: &mut FormatCache) -> Result<(usize,Vec<usize>),DecodeError> {
+	if let Some(i) = cache.format.filter(|i| self.dispatch.is_exclusive(*i)) {
+	    let format = &self.dispatch.entries[i].0;
+	    if let Ok((opcode,operands)) = format.read(bytes) {
+		if let Some(index) = self.dispatch.lookup(i,opcode) {
+		    cache.hits += 1;
+		    format.check(&operands)?;
+		    return Ok((index,operands));
+		}
+	    }
+	}
+	cache.misses += 1;
+	let mut error = None;
+	for (i,format) in self.dispatch.formats().enumerate() {
+	    match format.read(bytes) {
+		Ok((opcode,operands)) => {
+		    if let Some(index) = self.dispatch.lookup(i,opcode) {
+			cache.format = Some(i);
+			format.check(&operands)?;
+			return Ok((index,operands));
+		    }
+		    error = Some(DecodeError::Unknown);
+		}
+		Err(e) => {
+		    error = error.or(Some(e));
+		}
+	    }
+	}
+	Err(error.unwrap_or(DecodeError::Unknown))
+    }
+    /// Report how much of the available encoding space is used by
+    /// this instruction set, broken down by format.
+    pub fn utilization(&self) -> Utilization<'_> {
+	let mut assigned : Vec<(u64,usize)> = (0..self.insns.len()).map(|i| (self.opcode(i) as u64,i)).collect();
+	assigned.sort_unstable();
+	let mut formats = Vec::new();
+	for format in self.formats() {
+	    let defined = self.insns.iter().filter(|i| i.format() == format).count();
+	    let opcodes = format.opcode.count();
+	    let used = (format.count() / &opcodes) * defined;
+	    let available = BigUint::from(2u8).pow(8 * format.length() as u32);
+	    // Determine opcode values not assigned to any instruction of
+	    // this format
+	    let end = opcodes.to_u64().unwrap_or(u64::MAX);
+	    let mut free = Vec::new();
+	    let mut start = 0;
+	    for &(op,_) in assigned.iter().filter(|&&(op,i)| op < end && self.insns[i].format() == format) {
+		if start < op { free.push(start..op); }
+		start = op + 1;
+	    }
+	    if start < end { free.push(start..end); }
+	    formats.push(FormatUtilization{format,defined,opcodes,used,available,free});
+	}
+	Utilization{formats}
+    }
+}
+
+impl<'a,'b> IntoIterator for &'b InstructionSet<'a> {
+    type Item = &'b Instruction<'a>;
+    type IntoIter = core::slice::Iter<'b,Instruction<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+	self.insns.iter()
+    }
+}
+
+/// The (de)serialized form of an instruction set.  Opcodes may be
+/// omitted, in which case they are determined by the order of
+/// instructions.
+#[cfg(feature="serde")]
+#[derive(serde::Serialize,serde::Deserialize)]
+struct InstructionSetData<'a> {
+    instructions: Cow<'a,[Instruction<'a>]>,
+    #[serde(default)]
+    opcodes: Cow<'a,[usize]>,
+    #[serde(default)]
+    pseudos: Cow<'a,[PseudoInstruction<'a>]>
+}
+
+#[cfg(feature="serde")]
+impl serde::Serialize for InstructionSet<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok,S::Error> {
+	let data = InstructionSetData{instructions:Cow::Borrowed(&self.insns),opcodes:Cow::Borrowed(&self.opcodes),pseudos:Cow::Borrowed(&self.pseudos)};
+	data.serialize(serializer)
+    }
+}
+
+/// Deserializing an instruction set checks it is well-formed, as for
+/// `InstructionSetBuilder::build()`.
+#[cfg(feature="serde")]
+impl<'de> serde::Deserialize<'de> for InstructionSet<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self,D::Error> {
+	let data = InstructionSetData::deserialize(deserializer)?;
+	let mut builder = InstructionSetBuilder::new();
+	builder.insns = data.instructions.into_owned();
+	builder.pseudos = data.pseudos.into_owned();
+	let opcodes = if data.opcodes.is_empty() {
+	    (0..builder.insns.len()).collect()
+	} else if data.opcodes.len() == builder.insns.len() {
+	    data.opcodes.into_owned()
+	} else {
+	    return Err(serde::de::Error::invalid_length(data.opcodes.len(),&"one opcode per instruction"));
+	};
+	builder.build_with(opcodes).map_err(|errors| {
+	    let errors : Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+	    serde::de::Error::custom(errors.join(", "))
+	})
+    }
+}
+
+// =====================================================
+// Extensions
+// =====================================================
+
+/// A named group of instructions which can optionally be added to an
+/// instruction set (e.g. `M` for multiplication and division, as in
+/// `RV32IM`).  The instructions of an extension are assigned opcodes
+/// in order of definition, starting from a given opcode.  Thus, their
+/// encodings do not depend upon which other extensions are enabled.
+#[derive(Clone)]
+pub struct Extension<'a> {
+    name: Cow<'a,str>,
+    /// Opcode assigned to the first instruction of this extension.
+    opcode: usize,
+    insns: Vec<Instruction<'a>>
+}
+
+impl<'a> Extension<'a> {
+    pub fn new(name: &'a str, opcode: usize, insns: &'a [Instruction<'a>]) -> Self {
+	Extension{name:Cow::Borrowed(name),opcode,insns:insns.to_vec()}
+    }
+    /// Construct an extension which owns its instructions.
+    pub fn owned(name: &str, opcode: usize, insns: Vec<Instruction<'a>>) -> Self {
+	Extension{name:Cow::Owned(name.to_string()),opcode,insns}
+    }
+    /// Get the name of this extension.
+    pub fn name(&self) -> &str {
+	&self.name
+    }
+    /// Get the opcode assigned to the first instruction of this
+    /// extension.
+    pub fn opcode(&self) -> usize {
+	self.opcode
+    }
+    /// Get the instructions of this extension.
+    pub fn instructions(&self) -> &[Instruction<'a>] {
+	&self.insns
+    }
+}
+
+// =====================================================
+// Instruction Set Builder
+// =====================================================
+
+/// Constructs an instruction set incrementally, such that it owns all
+/// of its formats, semantics and mnemonics.  This allows instruction
+/// sets to be constructed at runtime (e.g. from a specification
+/// file), rather than from static definitions.  Any problems are
+/// reported as errors (rather than by panicking).  For example:
+///
+/// ```
+/// use virmin::insn::{Format,InstructionSetBuilder};
+/// use virmin::insn::AbstractMicroCode::*;
+/// use virmin::insn::Operand::*;
+/// use virmin::machine::Width::Byte;
+/// let fmt = Format::builder().width_bytes(1).opcode_bits(2)
+///     .register("rd",3).register("rs",3).build().unwrap();
+/// let isa = InstructionSetBuilder::new()
+///     .instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)])
+///     .pseudo("nop",&[("mov",&[Const(0),Const(0)])])
+///     .build();
+/// assert!(isa.is_ok());
+/// ```
+pub struct InstructionSetBuilder {
+    insns: Vec<Instruction<'static>>,
+    /// The opcode assigned to each instruction.
+    opcodes: Vec<usize>,
+    /// The opcode to assign to the next instruction, or `None` if the
+    /// previous instruction was assigned the largest possible opcode.
+    next: Option<usize>,
+    pseudos: Vec<PseudoInstruction<'static>>,
+    /// The machine (if any) against which semantics are checked.
+    profile: Option<MachineProfile>,
+    /// Problems encountered whilst building, which are reported by
+    /// `build()`.
+    errors: Vec<IsaError>
+}
+
+impl InstructionSetBuilder {
+    pub fn new() -> Self {
+	InstructionSetBuilder{insns:Vec::new(),opcodes:Vec::new(),next:Some(0),pseudos:Vec::new(),profile:None,errors:Vec::new()}
+    }
+    /// Set the opcode assigned to the next instruction appended, with
+    /// subsequent instructions following on from it.  This allows
+    /// instructions to be placed at fixed opcodes (e.g. to match an
+    /// existing machine), leaving gaps between them.
+    pub fn opcode(mut self, opcode: usize) -> Self {
+	self.next = Some(opcode);
+	self
+    }
+    /// Append an instruction with a given mnemonic, format and
+    /// semantics.  Opcodes are assigned in order of appending.
+    pub fn instruction(mut self, mnemonic: &str, format: &Format, semantic: &[AbstractMicroCode]) -> Self {
+	let insn = Instruction{
+	    mnemonic:Cow::Owned(mnemonic.to_string()),
+	    format:Cow::Owned(format.clone()),
+	    semantic:Cow::Owned(semantic.to_vec()),
+	    metadata:Metadata::new(),
+	    feature:None,
+	    properties:Vec::new()
+	};
+	self.insns.push(insn);
+	match self.next {
+	    Some(opcode) => self.opcodes.push(opcode),
+	    None => {
+		self.errors.push(IsaError::OpcodeOverflow(mnemonic.to_string()));
+		self.opcodes.push(usize::MAX);
+	    }
+	}
+	self.next = self.next.and_then(|n| n.checked_add(1));
+	self
+    }
+    /// Attach descriptive information to the most recently appended
+    /// instruction.
+    pub fn metadata(self, metadata: Metadata) -> Self {
+	self.update("metadata",|i| i.with_metadata(metadata))
+    }
+    /// Mark the most recently appended instruction as requiring a
+    /// given feature bit.
+    pub fn requires(self, feature: u8) -> Self {
+	self.update("feature",|i| i.requires(feature))
+    }
+    /// Check the semantics of all instructions against a given
+    /// machine when building (see `InstructionSet::check_profile()`).
+    pub fn profile(mut self, profile: MachineProfile) -> Self {
+	self.profile = Some(profile);
+	self
+    }
+    /// Attach a property to the most recently appended instruction.
+    pub fn property(self, property: Property) -> Self {
+	self.update("property",|i| i.with_property(property))
+    }
+    /// Append a pseudo instruction with a given mnemonic and
+    /// expansion.
+    pub fn pseudo(mut self, mnemonic: &str, expansion: &[(&str,&[Operand])]) -> Self {
+	let expansion = expansion.iter().map(|(m,ops)| (Cow::Owned(m.to_string()),Cow::Owned(ops.to_vec()))).collect();
+	self.pseudos.push(PseudoInstruction{mnemonic:Cow::Owned(mnemonic.to_string()),expansion});
+	self
+    }
+    /// Construct the instruction set, checking that it is well-formed
+    /// (see `InstructionSet::validate()`), that the semantics of each
+    /// instruction only refer to operands it has, and that each pseudo
+    /// instruction expands into valid instructions.
+    pub fn build(mut self) -> Result<InstructionSet<'static>,Vec<IsaError>> {
+	let opcodes = core::mem::take(&mut self.opcodes);
+	self.build_with(opcodes)
+    }
+    /// Construct the instruction set, such that each instruction is
+    /// assigned a given opcode.
+    fn build_with(self, opcodes: Vec<usize>) -> Result<InstructionSet<'static>,Vec<IsaError>> {
+	let mut errors = self.errors;
+	for insn in &self.insns {
+	    if insn.semantic.iter().any(|c| c.arity() > insn.arity()) {
+		errors.push(IsaError::InvalidSemantic(insn.mnemonic.to_string()));
+	    }
+	    if let Some(temp) = undefined_temp(&insn.semantic,&mut Vec::new()) {
+		errors.push(IsaError::UndefinedTemp{mnemonic:insn.mnemonic.to_string(),temp});
+	    }
+	}
+	let mut isa = InstructionSet::from_parts(self.insns,opcodes);
+	if let Err(es) = isa.validate() {
+	    errors.extend(es);
+	}
+	if let Some(Err(es)) = self.profile.as_ref().map(|p| isa.check_profile(p)) {
+	    errors.extend(es);
+	}
+	for p in &self.pseudos {
+	    if p.expansion.is_empty() || !isa.check_pseudo(p) {
+		errors.push(IsaError::InvalidPseudo(p.mnemonic.to_string()));
+	    }
+	}
+	if !errors.is_empty() {
+	    return Err(errors);
+	}
+	// Pseudo instructions were checked above
+	isa.pseudos = self.pseudos;
+	Ok(isa)
+    }
+    /// Update the most recently appended instruction, or record an
+    /// error if there is none.
+    fn update<F:FnOnce(Instruction<'static>)->Instruction<'static>>(mut self, what: &str, f: F) -> Self {
+	match self.insns.pop() {
+	    Some(insn) => self.insns.push(f(insn)),
+	    None => self.errors.push(IsaError::NoInstruction(what.to_string()))
+	}
+	self
+    }
+}
+
+impl Default for InstructionSetBuilder {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+/// Declares an instruction set using a compact, table-like syntax.
+/// Formats are declared by name, along with their width (in bytes),
+/// opcode size (in bits) and operand fields (each of which is given
+/// using a `FormatBuilder` method).  Instructions then refer to
+/// formats by name, and are given their semantics.  For example:
+///
+/// ```
+/// use virmin::isa;
+/// let isa = isa! {
+///     formats {
+///         rr(1,2) { rd: register(3), rs: register(3) }
+///         ri(1,2) { rd: register(3), imm: extension(1,virmin::insn::FieldKind::Immediate) }
+///     }
+///     instructions {
+///         mov: rr => [Copy(Var(0),Var(1),Byte)];
+///         ldi: ri => [];
+///     }
+///     pseudos {
+///         nop => [mov(Const(0),Const(0))];
+///     }
+/// }.unwrap();
+/// assert_eq!(isa.encode("ldi",&[1,2]),Ok(vec![0x05,0x02]));
+/// ```
+///
+/// Microcode constructors, operands and widths are in scope within
+/// the semantics of each instruction.  This expands into an
+/// `InstructionSetBuilder`, such that the result is a validated
+/// `InstructionSet` (or the problems found).  However, like
+/// `Format::new()`, a malformed format causes a panic, since the
+/// errors returned only describe instructions and pseudo
+/// instructions (i.e. `IsaError`).  Formats are usually fixed when
+/// writing the macro, but use `Format::builder()` with an
+/// `InstructionSetBuilder` directly to handle malformed formats as
+/// errors.
+#[macro_export]
+macro_rules! isa {
+    (formats { $($fmt:ident ($width:expr, $opcode:expr) { $($field:ident : $kind:ident ($($arg:expr),*)),* $(,)? })* }
+     instructions { $($mnemonic:ident : $format:ident => [$($code:expr),* $(,)?];)* }
+     $(pseudos { $($pseudo:ident => [$($target:ident ($($op:expr),*)),* $(,)?];)* })?) => {{
+	#[allow(unused_imports)]
+	use $crate::insn::AbstractMicroCode::*;
+	#[allow(unused_imports)]
+	use $crate::insn::Operand::*;
+	#[allow(unused_imports)]
+	use $crate::machine::Width::*;
+	$(
+	    let $fmt = $crate::insn::Format::builder()
+		.label(stringify!($fmt))
+		.width_bytes($width)
+		.opcode_bits($opcode)
+		$(.$kind(stringify!($field),$($arg),*))*
+		.build()
+		.expect(concat!("malformed format \"",stringify!($fmt),"\""));
+	)*
+	$crate::insn::InstructionSetBuilder::new()
+	    $(.instruction(stringify!($mnemonic),&$format,&[$($code),*]))*
+	    $($(.pseudo(stringify!($pseudo),&[$((stringify!($target),&[$($op),*])),*]))*)?
+	    .build()
+    }};
+}
+
+// =====================================================
+// Dispatch
+// =====================================================
+
+/// Remembers the format of the instruction most recently decoded
+/// using it (see `InstructionSet::decode_cached()`).  Typically, one
+/// cache is used for each loop decoding a sequence of instructions.
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct FormatCache {
+    format: Option<usize>,
+    /// Number of instructions decoded using the cached format.
+    hits: u64,
+    /// Number of instructions decoded by trying each format in turn.
+    misses: u64
+}
+
+impl FormatCache {
+    /// Get the number of instructions decoded using the cached format.
+    pub fn hits(&self) -> u64 {
+	self.hits
+    }
+    /// Get the number of instructions decoded (or not) by trying each
+    /// format in turn.
+    pub fn misses(&self) -> u64 {
+	self.misses
+    }
+}
+
+/// Maps the opcode values of each format in an instruction set to the
+/// instructions they identify.  Formats are indexed in order of their
+/// first use and, for each, a dense table is held covering every
+/// opcode value assigned within the set.
+pub struct DispatchTable {
+    entries: Vec<(Format,Vec<Option<usize>>)>,
+    /// Identifies formats which cannot identify an instruction
+    /// whenever an earlier format does.
+    exclusive: Vec<bool>
+}
+
+impl DispatchTable {
+    fn new(insns: &[Instruction], opcodes: &[usize]) -> Self {
+	let mut entries : Vec<(Format,Vec<Option<usize>>)> = Vec::new();
+	for (i,insn) in insns.iter().enumerate() {
+	    let j = match entries.iter().position(|(f,_)| f == insn.format()) {
+		Some(j) => j,
+		None => {
+		    entries.push((insn.format().clone(),Vec::new()));
+		    entries.len() - 1
+		}
+	    };
+	    // Conflicting opcodes are resolved to the first instruction,
+	    // whilst those which cannot be encoded are ignored (as these
+	    // are rejected by validation, but would otherwise require an
+	    // arbitrarily large table).
+	    let (table,opcode) = (&mut entries[j].1,opcodes[i]);
+	    if !fits_unsigned(opcode,insn.format.opcode) {
+		continue;
+	    }
+	    if table.len() <= opcode {
+		table.resize(opcode+1,None);
+	    }
+	    table[opcode] = table[opcode].or(Some(i));
+	}
+	let exclusive = (0..entries.len()).map(|i| (0..i).all(|j| disjoint(&entries[j],&entries[i]))).collect();
+	DispatchTable{entries,exclusive}
+    }
+    /// Get the number of formats in this table.
+    pub fn len(&self) -> usize {
+	self.entries.len()
+    }
+    /// Check whether this table has no formats.
+    pub fn is_empty(&self) -> bool {
+	self.entries.is_empty()
+    }
+    /// Get the formats of this table (in order).
+    pub fn formats(&self) -> impl Iterator<Item=&Format> {
+	self.entries.iter().map(|(f,_)| f)
+    }
+    /// Determine the instruction (if any) identified by a given opcode
+    /// value within the format at a given index.
+    pub fn lookup(&self, format: usize, opcode: usize) -> Option<usize> {
+	self.entries[format].1.get(opcode).copied().flatten()
+    }
+    /// Check whether the format at a given index can be tried first
+    /// when decoding.  That is, whether no earlier format can identify
+    /// an instruction whenever this format does.  This holds when
+    /// every earlier format reads its opcode from the same bits, but
+    /// assigns none of the same opcode values.
+    pub fn is_exclusive(&self, format: usize) -> bool {
+	self.exclusive[format]
+    }
+}
+
+/// Check whether two formats (along with their opcode tables) can
+/// never both identify an instruction from the same bytes.
+fn disjoint((f,s): &(Format,Vec<Option<usize>>), (g,t): &(Format,Vec<Option<usize>>)) -> bool {
+    f.width == g.width && f.byte_order == g.byte_order && f.layout()[0] == g.layout()[0]
+	&& s.iter().zip(t).all(|(a,b)| a.is_none() || b.is_none())
+}
+
+// =====================================================
+// Utilization
+// =====================================================
+
+/// Reports how much of the encoding space of a single format is used
+/// by the instructions of an instruction set.
+pub struct FormatUtilization<'a> {
+    /// The format being reported on.
+    pub format: &'a Format,
+    /// Number of instructions defined in this format.
+    pub defined: usize,
+    /// Number of distinct opcode values available in this format.
+    pub opcodes: BigUint,
+    /// Number of distinct encodings used by instructions in this
+    /// format (i.e. across all operand values).
+    pub used: BigUint,
+    /// Number of distinct encodings which fit into the width of this
+    /// format.
+    pub available: BigUint,
+    /// Ranges of values for the opcode field of this format which are
+    /// not assigned to any instruction in this format.
+    pub free: Vec<Range<u64>>
+}
+
+impl FormatUtilization<'_> {
+    /// Get the number of opcode values for this format which are not
+    /// assigned to any instruction.
+    pub fn free_opcodes(&self) -> u64 {
+	self.free.iter().map(|r| r.end - r.start).sum()
+    }
+    /// Get the fraction of available encodings which are used.
+    pub fn ratio(&self) -> f64 {
+	ratio(&self.used,&self.available)
+    }
+}
+
+/// Reports how much of the encoding space is used by an instruction
+/// set, broken down by format.
+pub struct Utilization<'a> {
+    pub formats: Vec<FormatUtilization<'a>>
+}
+
+impl Utilization<'_> {
+    /// Get the total number of encodings used across all formats.
+    pub fn used(&self) -> BigUint {
+	self.formats.iter().map(|f| &f.used).sum()
+    }
+    /// Get the total number of encodings available across all
+    /// formats.
+    pub fn available(&self) -> BigUint {
+	self.formats.iter().map(|f| &f.available).sum()
+    }
+    /// Get the fraction of available encodings which are used across
+    /// all formats.
+    pub fn ratio(&self) -> f64 {
+	ratio(&self.used(),&self.available())
+    }
+}
+
+impl fmt::Display for Utilization<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for u in &self.formats {
+	    write!(f,"{}: {}/{} opcodes",u.format.label,u.defined,u.opcodes)?;
+	    if u.free.is_empty() {
+		write!(f," (none free)")?;
+	    } else {
+		let free : Vec<String> = u.free.iter().map(|r| format!("{}..{}",r.start,r.end)).collect();
+		write!(f," ({} free)",free.join(", "))?;
+	    }
+	    writeln!(f,", {}/{} encodings ({:.1}%)",u.used,u.available,100.0 * u.ratio())?;
+	}
+	writeln!(f,"total: {}/{} encodings ({:.1}%)",self.used(),self.available(),100.0 * self.ratio())
+    }
+}
+
+fn ratio(n: &BigUint, d: &BigUint) -> f64 {
+    let n = n.to_f64().unwrap_or(f64::MAX);
+    let d = d.to_f64().unwrap_or(f64::MAX);
+    if d == 0.0 { 0.0 } else { n / d }
 }
 
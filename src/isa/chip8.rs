@@ -0,0 +1,181 @@
+use core::fmt;
+use alloc::{string::{String,ToString},vec,vec::Vec};
+use crate::insn::{BitOrder,ByteOrder,Category,Format,InstructionSet,InstructionSetBuilder,Metadata};
+use crate::insn::Operand::*;
+use crate::machine::Memory;
+
+/// Size (in bytes) of CHIP-8 memory.
+pub const MEMORY_SIZE : usize = 4096;
+/// Address at which programs are conventionally loaded.
+pub const PROGRAM_START : usize = 0x200;
+/// Address of the general purpose registers `V0` .. `VF` (which
+/// reside in the otherwise unused interpreter area).
+pub const REGISTERS : usize = 0x00;
+/// Address of the (two byte) index register `I`.
+pub const INDEX : usize = 0x10;
+/// Address at which the built-in font is loaded.
+pub const FONT_ADDRESS : usize = 0x50;
+/// Width (in pixels) of the display.
+pub const DISPLAY_WIDTH : usize = 64;
+/// Height (in pixels) of the display.
+pub const DISPLAY_HEIGHT : usize = 32;
+
+/// The built-in font, consisting of one five byte sprite for each
+/// hexadecimal digit.
+pub const FONT : [u8;80] = [
+    0xF0,0x90,0x90,0x90,0xF0, 0x20,0x60,0x20,0x20,0x70, // 0, 1
+    0xF0,0x10,0xF0,0x80,0xF0, 0xF0,0x10,0xF0,0x10,0xF0, // 2, 3
+    0x90,0x90,0xF0,0x10,0x10, 0xF0,0x80,0xF0,0x10,0xF0, // 4, 5
+    0xF0,0x80,0xF0,0x90,0xF0, 0xF0,0x10,0x20,0x40,0x40, // 6, 7
+    0xF0,0x90,0xF0,0x90,0xF0, 0xF0,0x90,0xF0,0x10,0xF0, // 8, 9
+    0xF0,0x90,0xF0,0x90,0x90, 0xE0,0x90,0xE0,0x90,0xE0, // A, B
+    0xF0,0x80,0x80,0x80,0xF0, 0xE0,0x90,0x90,0x90,0xE0, // C, D
+    0xF0,0x80,0xF0,0x80,0xF0, 0xF0,0x80,0xF0,0x80,0x80  // E, F
+];
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct the CHIP-8 instruction set.  Instructions are 16 bits
+/// (big endian), with the most significant nibble selecting one of
+/// sixteen instructions.  Where this nibble is shared by several
+/// instructions (e.g. `8XY1` is `OR` whilst `8XY2` is `AND`), the
+/// remaining bits are held in an operand and each instruction is
+/// given as a pseudo instruction.  For example, `or 1, 2` expands
+/// into `alu 1, 2, 1`.
+///
+/// Note that semantics are not (yet) given, since most CHIP-8
+/// instructions require microcode (e.g. conditional skips and
+/// arithmetic on registers) which is not currently supported.
+pub fn isa() -> InstructionSet<'static> {
+    let f = |label: &str, fields: &[(&str,u8)]| {
+	let mut builder = Format::builder()
+	    .label(label)
+	    .width_bytes(2)
+	    .opcode_bits(4)
+	    .byte_order(ByteOrder::BigEndian)
+	    .bit_order(BitOrder::MsbFirst);
+	for (name,bits) in fields {
+	    builder = match *name {
+		"x"|"y" => builder.register(name,*bits),
+		_ => builder.immediate(name,*bits)
+	    };
+	}
+	builder.build().unwrap()
+    };
+    let nnn = f("nnn",&[("nnn",12)]);
+    let xkk = f("xkk",&[("x",4),("kk",8)]);
+    let xy = f("xy",&[("x",4),("y",4)]);
+    let xyn = f("xyn",&[("x",4),("y",4),("n",4)]);
+    let m = |category: Category, description: &str| Metadata::new().category(category).description(description);
+    InstructionSetBuilder::new()
+	.instruction("sys",&nnn,&[]).metadata(m(Category::System,"Call machine code routine (0nnn)"))
+	.instruction("jp",&nnn,&[]).metadata(m(Category::Branch,"Jump to address nnn (1nnn)"))
+	.instruction("call",&nnn,&[]).metadata(m(Category::Branch,"Call subroutine at nnn (2nnn)"))
+	.instruction("se",&xkk,&[]).metadata(m(Category::Branch,"Skip next if Vx == kk (3xkk)"))
+	.instruction("sne",&xkk,&[]).metadata(m(Category::Branch,"Skip next if Vx != kk (4xkk)"))
+	.instruction("sey",&xy,&[]).metadata(m(Category::Branch,"Skip next if Vx == Vy (5xy0)"))
+	.instruction("ld",&xkk,&[]).metadata(m(Category::Move,"Set Vx = kk (6xkk)"))
+	.instruction("add",&xkk,&[]).metadata(m(Category::Alu,"Set Vx = Vx + kk (7xkk)"))
+	.instruction("alu",&xyn,&[]).metadata(m(Category::Alu,"Register operation n on Vx and Vy (8xyn)"))
+	.instruction("sney",&xy,&[]).metadata(m(Category::Branch,"Skip next if Vx != Vy (9xy0)"))
+	.instruction("ldi",&nnn,&[]).metadata(m(Category::Move,"Set I = nnn (Annn)"))
+	.instruction("jpv0",&nnn,&[]).metadata(m(Category::Branch,"Jump to address nnn + V0 (Bnnn)"))
+	.instruction("rnd",&xkk,&[]).metadata(m(Category::Alu,"Set Vx = random byte AND kk (Cxkk)"))
+	.instruction("drw",&xyn,&[]).metadata(m(Category::Other("display".to_string()),"Draw n byte sprite from I at (Vx, Vy) (Dxyn)"))
+	.instruction("skey",&xkk,&[]).metadata(m(Category::Other("keyboard".to_string()),"Key operation kk on Vx (Exkk)"))
+	.instruction("misc",&xkk,&[]).metadata(m(Category::Other("misc".to_string()),"Miscellaneous operation kk on Vx (Fxkk)"))
+	// 0nnn
+	.pseudo("cls",&[("sys",&[Const(0x0E0)])])
+	.pseudo("ret",&[("sys",&[Const(0x0EE)])])
+	// 8xyn
+	.pseudo("ld_v_v",&[("alu",&[Var(0),Var(1),Const(0x0)])])
+	.pseudo("or",&[("alu",&[Var(0),Var(1),Const(0x1)])])
+	.pseudo("and",&[("alu",&[Var(0),Var(1),Const(0x2)])])
+	.pseudo("xor",&[("alu",&[Var(0),Var(1),Const(0x3)])])
+	.pseudo("add_v_v",&[("alu",&[Var(0),Var(1),Const(0x4)])])
+	.pseudo("sub",&[("alu",&[Var(0),Var(1),Const(0x5)])])
+	.pseudo("shr",&[("alu",&[Var(0),Var(1),Const(0x6)])])
+	.pseudo("subn",&[("alu",&[Var(0),Var(1),Const(0x7)])])
+	.pseudo("shl",&[("alu",&[Var(0),Var(1),Const(0xE)])])
+	// Exkk
+	.pseudo("skp",&[("skey",&[Var(0),Const(0x9E)])])
+	.pseudo("sknp",&[("skey",&[Var(0),Const(0xA1)])])
+	// Fxkk
+	.pseudo("ld_v_dt",&[("misc",&[Var(0),Const(0x07)])])
+	.pseudo("ld_v_k",&[("misc",&[Var(0),Const(0x0A)])])
+	.pseudo("ld_dt_v",&[("misc",&[Var(0),Const(0x15)])])
+	.pseudo("ld_st_v",&[("misc",&[Var(0),Const(0x18)])])
+	.pseudo("add_i_v",&[("misc",&[Var(0),Const(0x1E)])])
+	.pseudo("ld_f_v",&[("misc",&[Var(0),Const(0x29)])])
+	.pseudo("ld_b_v",&[("misc",&[Var(0),Const(0x33)])])
+	.pseudo("ld_i_v",&[("misc",&[Var(0),Const(0x55)])])
+	.pseudo("ld_v_i",&[("misc",&[Var(0),Const(0x65)])])
+	.build()
+	.unwrap()
+}
+
+/// Copy the built-in font into memory at its conventional address.
+pub fn load_font(memory: &mut Memory) {
+    for (i,b) in FONT.iter().enumerate() {
+	memory.write_u8(FONT_ADDRESS + i,*b);
+    }
+}
+
+// =====================================================
+// Display
+// =====================================================
+
+/// The monochrome CHIP-8 display, where sprites are drawn by XORing
+/// them onto the screen.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Display {
+    pixels: Vec<bool>
+}
+
+impl Display {
+    pub fn new() -> Self {
+	Display{pixels:vec![false;DISPLAY_WIDTH * DISPLAY_HEIGHT]}
+    }
+    /// Clear every pixel of the display.
+    pub fn clear(&mut self) {
+	self.pixels.fill(false);
+    }
+    /// Check whether the pixel at a given position is set.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+	self.pixels[(y % DISPLAY_HEIGHT) * DISPLAY_WIDTH + (x % DISPLAY_WIDTH)]
+    }
+    /// Draw a sprite (one byte per row) at a given position, wrapping
+    /// around the edges of the display.  This returns `true` if any
+    /// set pixel was cleared (i.e. there was a collision).
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+	let mut collision = false;
+	for (i,row) in sprite.iter().enumerate() {
+	    for j in 0..8 {
+		if row & (0x80 >> j) != 0 {
+		    let index = ((y + i) % DISPLAY_HEIGHT) * DISPLAY_WIDTH + ((x + j) % DISPLAY_WIDTH);
+		    collision |= self.pixels[index];
+		    self.pixels[index] ^= true;
+		}
+	    }
+	}
+	collision
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+	Self::new()
+    }
+}
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for row in self.pixels.chunks(DISPLAY_WIDTH) {
+	    let row : String = row.iter().map(|p| if *p { '#' } else { '.' }).collect();
+	    writeln!(f,"{}",row)?;
+	}
+	Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::insn::{BitOrder,ByteOrder,Category,Format,InstructionSet,InstructionSetBuilder,Metadata};
+use crate::insn::Operand::*;
+
+/// Size (in 16 bit words) of LC-3 memory.
+pub const MEMORY_WORDS : usize = 0x10000;
+/// Address at which user programs are conventionally loaded.
+pub const PROGRAM_START : usize = 0x3000;
+/// Condition code set when the last result was negative.
+pub const COND_N : u8 = 0b100;
+/// Condition code set when the last result was zero.
+pub const COND_Z : u8 = 0b010;
+/// Condition code set when the last result was positive.
+pub const COND_P : u8 = 0b001;
+
+/// Read a character from the keyboard into `R0`.
+pub const TRAP_GETC : u8 = 0x20;
+/// Write the character in `R0` to the console.
+pub const TRAP_OUT : u8 = 0x21;
+/// Write the string (one character per word) at `R0`.
+pub const TRAP_PUTS : u8 = 0x22;
+/// Prompt for, then read and echo, a character into `R0`.
+pub const TRAP_IN : u8 = 0x23;
+/// Write the string (two characters per word) at `R0`.
+pub const TRAP_PUTSP : u8 = 0x24;
+/// Halt the machine.
+pub const TRAP_HALT : u8 = 0x25;
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct the LC-3 instruction set.  Instructions are 16 bits (big
+/// endian), with the most significant four bits giving the opcode.
+/// Where an opcode has several forms, or fixed bits, the underlying
+/// instruction is named `op_xxx` and its assembly forms are given as
+/// pseudo instructions.  For example, `add r1, r2, r3` expands into
+/// `op_add r1, r2, 0, 3`, whilst `addi r1, r2, -1` expands into
+/// `op_add r1, r2, 1, -1`.  Likewise, branches are given by their
+/// condition codes (e.g. `brz`).
+///
+/// Note that `JSRR` cannot (yet) be expressed, since its base
+/// register overlaps the offset of `JSR`.  Likewise, semantics are not
+/// (yet) given, since LC-3 instructions require microcode (e.g.
+/// arithmetic and condition codes) which is not currently supported.
+pub fn isa() -> InstructionSet<'static> {
+    let f = |label: &str, fields: &[(&str,u8,bool)]| {
+	let mut builder = Format::builder()
+	    .label(label)
+	    .width_bytes(2)
+	    .opcode_bits(4)
+	    .byte_order(ByteOrder::BigEndian)
+	    .bit_order(BitOrder::MsbFirst);
+	for (name,bits,register) in fields {
+	    builder = match (*register,*name) {
+		(true,_) => builder.register(name,*bits),
+		(false,"nzp"|"mode"|"mask"|"pad"|"vec") => builder.immediate(name,*bits),
+		(false,_) => builder.simmediate(name,*bits)
+	    };
+	}
+	builder.build().unwrap()
+    };
+    let br = f("br",&[("nzp",3,false),("off",9,false)]);
+    let arith = f("arith",&[("dr",3,true),("sr1",3,true),("mode",1,false),("op",5,false)]);
+    let pcoff9 = f("pcoff9",&[("r",3,true),("off",9,false)]);
+    let pcoff11 = f("pcoff11",&[("mode",1,false),("off",11,false)]);
+    let base = f("base",&[("r",3,true),("base",3,true),("off",6,false)]);
+    let none = f("none",&[]);
+    let not = f("not",&[("dr",3,true),("sr",3,true),("mask",6,false)]);
+    let jmp = f("jmp",&[("pad",3,false),("base",3,true)]);
+    let trap = f("trap",&[("pad",4,false),("vec",8,false)]);
+    let m = |category: Category, description: &str| Metadata::new().category(category).description(description);
+    let cc = |m: Metadata| m.writes(&["N","Z","P"]);
+    InstructionSetBuilder::new()
+	.instruction("op_br",&br,&[]).metadata(m(Category::Branch,"Branch if any condition in nzp holds").reads(&["N","Z","P"]))
+	.instruction("op_add",&arith,&[]).metadata(cc(m(Category::Alu,"Add register or immediate")))
+	.instruction("ld",&pcoff9,&[]).metadata(cc(m(Category::LoadStore,"Load from PC relative address")))
+	.instruction("st",&pcoff9,&[]).metadata(m(Category::LoadStore,"Store to PC relative address"))
+	.instruction("op_jsr",&pcoff11,&[]).metadata(m(Category::Branch,"Jump to subroutine"))
+	.instruction("op_and",&arith,&[]).metadata(cc(m(Category::Alu,"Bitwise and register or immediate")))
+	.instruction("ldr",&base,&[]).metadata(cc(m(Category::LoadStore,"Load from base register plus offset")))
+	.instruction("str",&base,&[]).metadata(m(Category::LoadStore,"Store to base register plus offset"))
+	.instruction("rti",&none,&[]).metadata(m(Category::System,"Return from interrupt"))
+	.instruction("op_not",&not,&[]).metadata(cc(m(Category::Alu,"Bitwise complement")))
+	.instruction("ldi",&pcoff9,&[]).metadata(cc(m(Category::LoadStore,"Load indirect via PC relative address")))
+	.instruction("sti",&pcoff9,&[]).metadata(m(Category::LoadStore,"Store indirect via PC relative address"))
+	.instruction("op_jmp",&jmp,&[]).metadata(m(Category::Branch,"Jump to address in base register"))
+	.instruction("reserved",&none,&[]).metadata(m(Category::System,"Reserved (illegal opcode)"))
+	.instruction("lea",&pcoff9,&[]).metadata(m(Category::Move,"Load PC relative address"))
+	.instruction("op_trap",&trap,&[]).metadata(m(Category::System,"System call via trap vector"))
+	// Branches
+	.pseudo("br",&[("op_br",&[Const(0b111),Var(0)])])
+	.pseudo("brn",&[("op_br",&[Const(0b100),Var(0)])])
+	.pseudo("brz",&[("op_br",&[Const(0b010),Var(0)])])
+	.pseudo("brp",&[("op_br",&[Const(0b001),Var(0)])])
+	.pseudo("brnz",&[("op_br",&[Const(0b110),Var(0)])])
+	.pseudo("brnp",&[("op_br",&[Const(0b101),Var(0)])])
+	.pseudo("brzp",&[("op_br",&[Const(0b011),Var(0)])])
+	// Arithmetic
+	.pseudo("add",&[("op_add",&[Var(0),Var(1),Const(0),Var(2)])])
+	.pseudo("addi",&[("op_add",&[Var(0),Var(1),Const(1),Var(2)])])
+	.pseudo("and",&[("op_and",&[Var(0),Var(1),Const(0),Var(2)])])
+	.pseudo("andi",&[("op_and",&[Var(0),Var(1),Const(1),Var(2)])])
+	.pseudo("not",&[("op_not",&[Var(0),Var(1),Const(0x3F)])])
+	// Control
+	.pseudo("jsr",&[("op_jsr",&[Const(1),Var(0)])])
+	.pseudo("ret",&[("op_jmp",&[Const(0),Const(7)])])
+	.pseudo("jmp",&[("op_jmp",&[Const(0),Var(0)])])
+	// Traps
+	.pseudo("getc",&[("op_trap",&[Const(0),Const(TRAP_GETC as usize)])])
+	.pseudo("out",&[("op_trap",&[Const(0),Const(TRAP_OUT as usize)])])
+	.pseudo("puts",&[("op_trap",&[Const(0),Const(TRAP_PUTS as usize)])])
+	.pseudo("in",&[("op_trap",&[Const(0),Const(TRAP_IN as usize)])])
+	.pseudo("putsp",&[("op_trap",&[Const(0),Const(TRAP_PUTSP as usize)])])
+	.pseudo("halt",&[("op_trap",&[Const(0),Const(TRAP_HALT as usize)])])
+	.pseudo("trap",&[("op_trap",&[Const(0),Var(0)])])
+	.build()
+	.unwrap()
+}
@@ -0,0 +1,12 @@
+#[cfg(feature="chip8")]
+pub mod chip8;
+#[cfg(feature="lc3")]
+pub mod lc3;
+#[cfg(feature="rv32i")]
+pub mod rv32i;
+#[cfg(feature="subleq")]
+pub mod subleq;
+#[cfg(feature="mos6502")]
+pub mod mos6502;
+#[cfg(feature="stack")]
+pub mod stack;
@@ -0,0 +1,238 @@
+use alloc::{format,vec::Vec};
+use crate::insn::{Category,FieldKind,Format,InstructionSet,InstructionSetBuilder,Metadata};
+use crate::insn::Category::*;
+
+/// Size (in bytes) of the address space.
+pub const MEMORY_SIZE : usize = 0x10000;
+/// Base address of the (one page) hardware stack.
+pub const STACK : usize = 0x0100;
+/// Address of the non-maskable interrupt vector.
+pub const NMI_VECTOR : usize = 0xFFFA;
+/// Address of the reset vector.
+pub const RESET_VECTOR : usize = 0xFFFC;
+/// Address of the interrupt request (and `BRK`) vector.
+pub const IRQ_VECTOR : usize = 0xFFFE;
+
+/// Carry flag.
+pub const FLAG_C : u8 = 0x01;
+/// Zero flag.
+pub const FLAG_Z : u8 = 0x02;
+/// Interrupt disable flag.
+pub const FLAG_I : u8 = 0x04;
+/// Decimal mode flag.
+pub const FLAG_D : u8 = 0x08;
+/// Break flag (only meaningful when pushed onto the stack).
+pub const FLAG_B : u8 = 0x10;
+/// Overflow flag.
+pub const FLAG_V : u8 = 0x40;
+/// Negative flag.
+pub const FLAG_N : u8 = 0x80;
+
+// =====================================================
+// Addressing Modes
+// =====================================================
+
+/// The addressing modes of the 6502.  Each determines the format of
+/// an instruction, and the suffix of its mnemonic.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Mode {
+    /// No operand (e.g. `clc`).
+    Implied,
+    /// Operates on the accumulator (e.g. `asl_a`).
+    Accumulator,
+    /// Eight bit constant (e.g. `lda_imm 0x10`).
+    Immediate,
+    /// Eight bit address within page zero (e.g. `lda_zp 0x10`).
+    ZeroPage,
+    /// Page zero address indexed by `X` (e.g. `lda_zpx 0x10`).
+    ZeroPageX,
+    /// Page zero address indexed by `Y` (e.g. `ldx_zpy 0x10`).
+    ZeroPageY,
+    /// Sixteen bit address (e.g. `lda_abs 0x1234`).
+    Absolute,
+    /// Absolute address indexed by `X` (e.g. `lda_absx 0x1234`).
+    AbsoluteX,
+    /// Absolute address indexed by `Y` (e.g. `lda_absy 0x1234`).
+    AbsoluteY,
+    /// Address held at an absolute address (e.g. `jmp_ind 0x1234`).
+    Indirect,
+    /// Address held in page zero at an address indexed by `X`
+    /// (e.g. `lda_indx 0x10`).
+    IndexedIndirect,
+    /// Address held in page zero, then indexed by `Y` (e.g. `lda_indy
+    /// 0x10`).
+    IndirectIndexed,
+    /// Signed eight bit branch offset (e.g. `bne -4`).
+    Relative
+}
+
+impl Mode {
+    /// Get the suffix appended to mnemonics using this addressing
+    /// mode.
+    pub fn suffix(&self) -> &'static str {
+	match &self {
+	    Mode::Implied|Mode::Relative => "",
+	    Mode::Accumulator => "_a",
+	    Mode::Immediate => "_imm",
+	    Mode::ZeroPage => "_zp",
+	    Mode::ZeroPageX => "_zpx",
+	    Mode::ZeroPageY => "_zpy",
+	    Mode::Absolute => "_abs",
+	    Mode::AbsoluteX => "_absx",
+	    Mode::AbsoluteY => "_absy",
+	    Mode::Indirect => "_ind",
+	    Mode::IndexedIndirect => "_indx",
+	    Mode::IndirectIndexed => "_indy"
+	}
+    }
+    /// Get the number of bytes following the opcode.
+    pub fn operand_bytes(&self) -> usize {
+	match &self {
+	    Mode::Implied|Mode::Accumulator => 0,
+	    Mode::Absolute|Mode::AbsoluteX|Mode::AbsoluteY|Mode::Indirect => 2,
+	    _ => 1
+	}
+    }
+    fn format(&self) -> Format {
+	let label = match &self {
+	    Mode::Implied => "imp",
+	    Mode::Relative => "rel",
+	    _ => &self.suffix()[1..]
+	};
+	let builder = Format::builder().label(label).width_bytes(1).opcode_bits(8);
+	let builder = match &self {
+	    Mode::Implied|Mode::Accumulator => builder,
+	    Mode::Immediate => builder.extension("imm",1,FieldKind::Immediate),
+	    Mode::Relative => builder.extension("off",1,FieldKind::SignedImmediate),
+	    m => builder.extension("addr",m.operand_bytes() as u8,FieldKind::Immediate)
+	};
+	builder.build().unwrap()
+    }
+}
+
+use Mode::*;
+
+/// Every addressing mode (in order of first use).
+const MODES : [Mode;13] = [Mode::Immediate, Mode::ZeroPage, Mode::ZeroPageX, Mode::Absolute, Mode::AbsoluteX,
+			   Mode::AbsoluteY, Mode::IndexedIndirect, Mode::IndirectIndexed, Mode::Accumulator,
+			   Mode::Relative, Mode::Implied, Mode::ZeroPageY, Mode::Indirect];
+
+// =====================================================
+// Opcodes
+// =====================================================
+
+/// Describes a (documented) 6502 instruction, giving its category,
+/// description, the flags it reads and writes and its opcode in each
+/// addressing mode it supports.
+struct Opcodes(&'static str, Category, &'static str, &'static [&'static str], &'static [&'static str], &'static [(Mode,usize)]);
+
+const NZ : &[&str] = &["N","Z"];
+const NZC : &[&str] = &["N","Z","C"];
+const NVZC : &[&str] = &["N","V","Z","C"];
+const ALL : &[&str] = &["N","V","D","I","Z","C"];
+const NONE : &[&str] = &[];
+/// Opcodes for the eight addressing modes of the "group one"
+/// instructions (`ora`, `and`, `eor`, `adc`, `lda`, `cmp`, `sbc`), in
+/// the order of `MODES`.
+const fn group1(base: usize) -> [(Mode,usize);8] {
+    [(Immediate,base+0x08),(ZeroPage,base+0x04),(ZeroPageX,base+0x14),(Absolute,base+0x0C),
+     (AbsoluteX,base+0x1C),(AbsoluteY,base+0x18),(IndexedIndirect,base),(IndirectIndexed,base+0x10)]
+}
+/// Opcodes for the read-modify-write shifts and rotates (`asl`,
+/// `rol`, `lsr`, `ror`).
+const fn shift(base: usize) -> [(Mode,usize);5] {
+    [(Accumulator,base+0x0A),(ZeroPage,base+0x06),(ZeroPageX,base+0x16),(Absolute,base+0x0E),(AbsoluteX,base+0x1E)]
+}
+
+const OPCODES : &[Opcodes] = &[
+    Opcodes("adc",Alu,"Add with carry",&["C","D"],NVZC,&group1(0x61)),
+    Opcodes("and",Alu,"Bitwise and with accumulator",NONE,NZ,&group1(0x21)),
+    Opcodes("asl",Alu,"Arithmetic shift left",NONE,NZC,&shift(0x00)),
+    Opcodes("bcc",Branch,"Branch if carry clear",&["C"],NONE,&[(Relative,0x90)]),
+    Opcodes("bcs",Branch,"Branch if carry set",&["C"],NONE,&[(Relative,0xB0)]),
+    Opcodes("beq",Branch,"Branch if equal",&["Z"],NONE,&[(Relative,0xF0)]),
+    Opcodes("bit",Alu,"Test bits against accumulator",NONE,&["N","V","Z"],&[(ZeroPage,0x24),(Absolute,0x2C)]),
+    Opcodes("bmi",Branch,"Branch if minus",&["N"],NONE,&[(Relative,0x30)]),
+    Opcodes("bne",Branch,"Branch if not equal",&["Z"],NONE,&[(Relative,0xD0)]),
+    Opcodes("bpl",Branch,"Branch if plus",&["N"],NONE,&[(Relative,0x10)]),
+    Opcodes("brk",System,"Software interrupt",ALL,&["B","I"],&[(Implied,0x00)]),
+    Opcodes("bvc",Branch,"Branch if overflow clear",&["V"],NONE,&[(Relative,0x50)]),
+    Opcodes("bvs",Branch,"Branch if overflow set",&["V"],NONE,&[(Relative,0x70)]),
+    Opcodes("clc",System,"Clear carry",NONE,&["C"],&[(Implied,0x18)]),
+    Opcodes("cld",System,"Clear decimal mode",NONE,&["D"],&[(Implied,0xD8)]),
+    Opcodes("cli",System,"Clear interrupt disable",NONE,&["I"],&[(Implied,0x58)]),
+    Opcodes("clv",System,"Clear overflow",NONE,&["V"],&[(Implied,0xB8)]),
+    Opcodes("cmp",Alu,"Compare with accumulator",NONE,NZC,&group1(0xC1)),
+    Opcodes("cpx",Alu,"Compare with X",NONE,NZC,&[(Immediate,0xE0),(ZeroPage,0xE4),(Absolute,0xEC)]),
+    Opcodes("cpy",Alu,"Compare with Y",NONE,NZC,&[(Immediate,0xC0),(ZeroPage,0xC4),(Absolute,0xCC)]),
+    Opcodes("dec",Alu,"Decrement memory",NONE,NZ,&[(ZeroPage,0xC6),(ZeroPageX,0xD6),(Absolute,0xCE),(AbsoluteX,0xDE)]),
+    Opcodes("dex",Alu,"Decrement X",NONE,NZ,&[(Implied,0xCA)]),
+    Opcodes("dey",Alu,"Decrement Y",NONE,NZ,&[(Implied,0x88)]),
+    Opcodes("eor",Alu,"Bitwise exclusive or with accumulator",NONE,NZ,&group1(0x41)),
+    Opcodes("inc",Alu,"Increment memory",NONE,NZ,&[(ZeroPage,0xE6),(ZeroPageX,0xF6),(Absolute,0xEE),(AbsoluteX,0xFE)]),
+    Opcodes("inx",Alu,"Increment X",NONE,NZ,&[(Implied,0xE8)]),
+    Opcodes("iny",Alu,"Increment Y",NONE,NZ,&[(Implied,0xC8)]),
+    Opcodes("jmp",Branch,"Jump",NONE,NONE,&[(Absolute,0x4C),(Indirect,0x6C)]),
+    Opcodes("jsr",Branch,"Jump to subroutine",NONE,NONE,&[(Absolute,0x20)]),
+    Opcodes("lda",LoadStore,"Load accumulator",NONE,NZ,&group1(0xA1)),
+    Opcodes("ldx",LoadStore,"Load X",NONE,NZ,&[(Immediate,0xA2),(ZeroPage,0xA6),(ZeroPageY,0xB6),(Absolute,0xAE),(AbsoluteY,0xBE)]),
+    Opcodes("ldy",LoadStore,"Load Y",NONE,NZ,&[(Immediate,0xA0),(ZeroPage,0xA4),(ZeroPageX,0xB4),(Absolute,0xAC),(AbsoluteX,0xBC)]),
+    Opcodes("lsr",Alu,"Logical shift right",NONE,NZC,&shift(0x40)),
+    Opcodes("nop",System,"No operation",NONE,NONE,&[(Implied,0xEA)]),
+    Opcodes("ora",Alu,"Bitwise or with accumulator",NONE,NZ,&group1(0x01)),
+    Opcodes("pha",LoadStore,"Push accumulator",NONE,NONE,&[(Implied,0x48)]),
+    Opcodes("php",LoadStore,"Push processor status",ALL,NONE,&[(Implied,0x08)]),
+    Opcodes("pla",LoadStore,"Pull accumulator",NONE,NZ,&[(Implied,0x68)]),
+    Opcodes("plp",LoadStore,"Pull processor status",NONE,ALL,&[(Implied,0x28)]),
+    Opcodes("rol",Alu,"Rotate left through carry",&["C"],NZC,&shift(0x20)),
+    Opcodes("ror",Alu,"Rotate right through carry",&["C"],NZC,&shift(0x60)),
+    Opcodes("rti",Branch,"Return from interrupt",NONE,ALL,&[(Implied,0x40)]),
+    Opcodes("rts",Branch,"Return from subroutine",NONE,NONE,&[(Implied,0x60)]),
+    Opcodes("sbc",Alu,"Subtract with borrow",&["C","D"],NVZC,&group1(0xE1)),
+    Opcodes("sec",System,"Set carry",NONE,&["C"],&[(Implied,0x38)]),
+    Opcodes("sed",System,"Set decimal mode",NONE,&["D"],&[(Implied,0xF8)]),
+    Opcodes("sei",System,"Set interrupt disable",NONE,&["I"],&[(Implied,0x78)]),
+    Opcodes("sta",LoadStore,"Store accumulator",NONE,NONE,
+	    &[(ZeroPage,0x85),(ZeroPageX,0x95),(Absolute,0x8D),(AbsoluteX,0x9D),(AbsoluteY,0x99),(IndexedIndirect,0x81),(IndirectIndexed,0x91)]),
+    Opcodes("stx",LoadStore,"Store X",NONE,NONE,&[(ZeroPage,0x86),(ZeroPageY,0x96),(Absolute,0x8E)]),
+    Opcodes("sty",LoadStore,"Store Y",NONE,NONE,&[(ZeroPage,0x84),(ZeroPageX,0x94),(Absolute,0x8C)]),
+    Opcodes("tax",Move,"Transfer accumulator to X",NONE,NZ,&[(Implied,0xAA)]),
+    Opcodes("tay",Move,"Transfer accumulator to Y",NONE,NZ,&[(Implied,0xA8)]),
+    Opcodes("tsx",Move,"Transfer stack pointer to X",NONE,NZ,&[(Implied,0xBA)]),
+    Opcodes("txa",Move,"Transfer X to accumulator",NONE,NZ,&[(Implied,0x8A)]),
+    Opcodes("txs",Move,"Transfer X to stack pointer",NONE,NONE,&[(Implied,0x9A)]),
+    Opcodes("tya",Move,"Transfer Y to accumulator",NONE,NZ,&[(Implied,0x98)])
+];
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct the (documented) MOS 6502 instruction set.  Each
+/// instruction consists of a one byte opcode followed by zero, one or
+/// two (little endian) operand bytes, depending upon its addressing
+/// mode.  Since the same mnemonic is used with different addressing
+/// modes (which are distinguished only by the syntax of the operand),
+/// each combination is a separate instruction whose mnemonic is
+/// suffixed by its mode (see `Mode::suffix()`).  For example, `lda
+/// #$10` is written `lda_imm 0x10` and `sta ($20),y` is written
+/// `sta_indy 0x20`.  The flags read and written by each instruction
+/// are recorded in its metadata.
+///
+/// Note that branch offsets are given in bytes (as for the hardware),
+/// whereas labels evaluate to the index of an instruction.  Likewise,
+/// semantics are not (yet) given, since they require microcode
+/// (e.g. flags and indirect addressing) which is not currently
+/// supported.
+pub fn isa() -> InstructionSet<'static> {
+    let formats : Vec<Format> = MODES.iter().map(|m| m.format()).collect();
+    let mut builder = InstructionSetBuilder::new();
+    for Opcodes(mnemonic,category,description,reads,writes,modes) in OPCODES {
+	for (mode,opcode) in modes.iter() {
+	    let format = &formats[MODES.iter().position(|m| m == mode).unwrap()];
+	    let metadata = Metadata::new().category(category.clone()).description(description).reads(reads).writes(writes);
+	    builder = builder.opcode(*opcode).instruction(&format!("{}{}",mnemonic,mode.suffix()),format,&[]).metadata(metadata);
+	}
+    }
+    builder.build().unwrap()
+}
@@ -0,0 +1,104 @@
+use alloc::format;
+use crate::insn::{Category,Format,InstructionSet,InstructionSetBuilder,Metadata};
+use crate::insn::Operand::*;
+use crate::machine::MachineProfile;
+
+/// Number of general purpose registers (`x0` .. `x31`), where `x0` is
+/// hardwired to zero.
+pub const REGISTERS : usize = 32;
+
+/// The (ABI) names of the general purpose registers, indexed by
+/// register number.
+pub const ABI_NAMES : [&str;REGISTERS] = ["zero","ra","sp","gp","tp","t0","t1","t2",
+					 "s0","s1","a0","a1","a2","a3","a4","a5",
+					 "a6","a7","s2","s3","s4","s5","s6","s7",
+					 "s8","s9","s10","s11","t3","t4","t5","t6"];
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct (a subset of) the RISC-V RV32I base instruction set.
+/// Instructions are 32 bits (little endian), with the least
+/// significant seven bits giving the major opcode.  Each major opcode
+/// is an underlying instruction (e.g. `op_imm` for `OP-IMM`), whose
+/// `funct3` / `funct7` fields select the actual operation.  Thus, the
+/// usual mnemonics are given as pseudo instructions.  For example,
+/// `addi x1, x0, -5` expands into `op_imm x1, 0, x0, -5`.  Loads and
+/// jumps take their base register and offset as separate operands
+/// (e.g. `lw x4, x2, 8` for `lw x4, 8(x2)`), whilst stores take the
+/// low and high parts of their offset separately (e.g. `sw x4, x2, 8,
+/// 0`).
+///
+/// Note that branches (`B` format), `jal` (`J` format) and `srai` are
+/// not (yet) included, since their immediates are scattered across
+/// the instruction word (or combined with `funct7`), which cannot
+/// currently be expressed.  Likewise, semantics are not (yet) given,
+/// since they require microcode (e.g. arithmetic) which is not
+/// currently supported.
+pub fn isa() -> InstructionSet<'static> {
+    let r = Format::builder().label("R").width_bytes(4).opcode_bits(7)
+	.register("rd",5).immediate("funct3",3).register("rs1",5).register("rs2",5).immediate("funct7",7)
+	.build().unwrap();
+    let i = Format::builder().label("I").width_bytes(4).opcode_bits(7)
+	.register("rd",5).immediate("funct3",3).register("rs1",5).simmediate("imm",12)
+	.build().unwrap();
+    let s = Format::builder().label("S").width_bytes(4).opcode_bits(7)
+	.immediate("imm_lo",5).immediate("funct3",3).register("rs1",5).register("rs2",5).simmediate("imm_hi",7)
+	.build().unwrap();
+    let u = Format::builder().label("U").width_bytes(4).opcode_bits(7)
+	.register("rd",5).immediate("imm",20)
+	.build().unwrap();
+    let m = |category: Category, description: &str| Metadata::new().category(category).description(description);
+    let mut builder = InstructionSetBuilder::new()
+	.opcode(0x03).instruction("op_load",&i,&[]).metadata(m(Category::LoadStore,"Load from rs1 + imm (LOAD)"))
+	.opcode(0x0F).instruction("op_fence",&i,&[]).metadata(m(Category::System,"Memory ordering (MISC-MEM)"))
+	.opcode(0x13).instruction("op_imm",&i,&[]).metadata(m(Category::Alu,"Register-immediate operation (OP-IMM)"))
+	.opcode(0x17).instruction("auipc",&u,&[]).metadata(m(Category::Move,"Add upper immediate to pc (AUIPC)"))
+	.opcode(0x23).instruction("op_store",&s,&[]).metadata(m(Category::LoadStore,"Store to rs1 + imm (STORE)"))
+	.opcode(0x33).instruction("op_reg",&r,&[]).metadata(m(Category::Alu,"Register-register operation (OP)"))
+	.opcode(0x37).instruction("lui",&u,&[]).metadata(m(Category::Move,"Load upper immediate (LUI)"))
+	.opcode(0x67).instruction("op_jalr",&i,&[]).metadata(m(Category::Branch,"Jump to rs1 + imm and link (JALR)"))
+	.opcode(0x73).instruction("op_system",&i,&[]).metadata(m(Category::System,"Environment call or breakpoint (SYSTEM)"));
+    // Register-register operations (funct3, funct7)
+    for (name,f3,f7) in [("add",0,0x00),("sub",0,0x20),("sll",1,0x00),("slt",2,0x00),("sltu",3,0x00),
+			  ("xor",4,0x00),("srl",5,0x00),("sra",5,0x20),("or",6,0x00),("and",7,0x00)] {
+	builder = builder.pseudo(name,&[("op_reg",&[Var(0),Const(f3),Var(1),Var(2),Const(f7)])]);
+    }
+    // Register-immediate operations (funct3)
+    builder = builder.pseudo("nop",&[("op_imm",&[Const(0),Const(0),Const(0),Const(0)])]);
+    for (name,f3) in [("addi",0),("slli",1),("slti",2),("sltiu",3),("xori",4),("srli",5),("ori",6),("andi",7)] {
+	builder = builder.pseudo(name,&[("op_imm",&[Var(0),Const(f3),Var(1),Var(2)])]);
+    }
+    // Loads and stores (funct3)
+    for (name,f3) in [("lb",0),("lh",1),("lw",2),("lbu",4),("lhu",5)] {
+	builder = builder.pseudo(name,&[("op_load",&[Var(0),Const(f3),Var(1),Var(2)])]);
+    }
+    for (name,f3) in [("sb",0),("sh",1),("sw",2)] {
+	builder = builder.pseudo(name,&[("op_store",&[Var(2),Const(f3),Var(1),Var(0),Var(3)])]);
+    }
+    builder
+	.pseudo("ret",&[("op_jalr",&[Const(0),Const(0),Const(1),Const(0)])])
+	.pseudo("jalr",&[("op_jalr",&[Var(0),Const(0),Var(1),Var(2)])])
+	.pseudo("fence",&[("op_fence",&[Const(0),Const(0),Const(0),Const(0xFF)])])
+	.pseudo("ecall",&[("op_system",&[Const(0),Const(0),Const(0),Const(0)])])
+	.pseudo("ebreak",&[("op_system",&[Const(0),Const(0),Const(0),Const(1)])])
+	.build()
+	.unwrap()
+}
+
+// =====================================================
+// Machine Profile
+// =====================================================
+
+/// Construct the profile of an RV32I machine with a 32 bit address
+/// space, whose registers are displayed using their ABI names.  The
+/// architectural names (`x0` .. `x31`) and `fp` (for `s0`) are
+/// accepted as aliases.
+pub fn profile() -> MachineProfile {
+    let mut profile = MachineProfile::new(1 << 32).names(&ABI_NAMES).alias("fp",8);
+    for i in 0..REGISTERS {
+	profile = profile.alias(&format!("x{}",i),i);
+    }
+    profile
+}
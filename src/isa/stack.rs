@@ -0,0 +1,181 @@
+use core::fmt;
+use alloc::vec::Vec;
+use crate::insn::{Category,DecodeError,FieldKind,Format,InstructionSet,InstructionSetBuilder,Metadata};
+use crate::insn::Operand::*;
+use crate::machine::State;
+use crate::program::DecodedProgram;
+
+/// Address of the (two byte) stack pointer, which holds the address
+/// of the next free stack cell.
+pub const SP : usize = 0;
+/// Address of the first (i.e. bottom) stack cell.
+pub const STACK_BASE : usize = 4;
+/// Size (in bytes) of a stack cell.
+pub const CELL : usize = 4;
+
+// =====================================================
+// Faults
+// =====================================================
+
+/// Identifies why a program could not continue executing.
+#[derive(Clone,Debug,PartialEq)]
+pub enum Fault {
+    /// The instruction at the current pc could not be decoded.
+    Decode(DecodeError),
+    /// An instruction required more values than were on the stack.
+    Underflow{pc: usize},
+    /// A value was pushed onto a full stack.
+    Overflow{pc: usize}
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    Fault::Decode(e) => write!(f,"cannot decode instruction ({:?})",e),
+	    Fault::Underflow{pc} => write!(f,"stack underflow at pc {}",pc),
+	    Fault::Overflow{pc} => write!(f,"stack overflow at pc {}",pc)
+	}
+    }
+}
+
+impl From<DecodeError> for Fault {
+    fn from(e: DecodeError) -> Self {
+	Fault::Decode(e)
+    }
+}
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct a simple stack-based bytecode, in the style of a
+/// language virtual machine.  Instructions consist of a one byte
+/// opcode, followed by a four byte signed constant (`push`) or a two
+/// byte target pc (`jmp`, `jz`, `call`).  Values are 32 bit signed
+/// integers held on a stack in memory, which also holds the return
+/// pc for each `call` (hence, a subroutine must move this out of the
+/// way to access its arguments, e.g. using `swap`).  For example, `(1 + 2) * 3` compiles to:
+///
+/// ```text
+///         push 1
+///         push 2
+///         add
+///         push 3
+///         mul
+///         halt
+/// ```
+///
+/// Note that semantics are not (yet) given, since they require
+/// microcode (e.g. a stack pointer and arithmetic) which is not
+/// currently supported.  Instead, programs are executed using
+/// `step()` or `run()`.
+pub fn isa() -> InstructionSet<'static> {
+    let op = Format::builder().label("op").width_bytes(1).opcode_bits(8).build().unwrap();
+    let imm = Format::builder().label("imm").width_bytes(1).opcode_bits(8)
+	.extension("value",4,FieldKind::SignedImmediate).build().unwrap();
+    let addr = Format::builder().label("addr").width_bytes(1).opcode_bits(8)
+	.extension("target",2,FieldKind::Immediate).build().unwrap();
+    let m = |category: Category, description: &str| Metadata::new().category(category).description(description);
+    InstructionSetBuilder::new()
+	.instruction("halt",&op,&[]).metadata(m(Category::System,"Stop execution"))
+	.instruction("push",&imm,&[]).metadata(m(Category::LoadStore,"Push constant"))
+	.instruction("pop",&op,&[]).metadata(m(Category::LoadStore,"Discard top of stack"))
+	.instruction("dup",&op,&[]).metadata(m(Category::Move,"Duplicate top of stack"))
+	.instruction("swap",&op,&[]).metadata(m(Category::Move,"Swap top two values"))
+	.instruction("add",&op,&[]).metadata(m(Category::Alu,"Replace top two values with their sum"))
+	.instruction("sub",&op,&[]).metadata(m(Category::Alu,"Replace top two values with their difference"))
+	.instruction("mul",&op,&[]).metadata(m(Category::Alu,"Replace top two values with their product"))
+	.instruction("neg",&op,&[]).metadata(m(Category::Alu,"Negate top of stack"))
+	.instruction("jmp",&addr,&[]).metadata(m(Category::Branch,"Jump to target"))
+	.instruction("jz",&addr,&[]).metadata(m(Category::Branch,"Pop value and jump to target if zero"))
+	.instruction("call",&addr,&[]).metadata(m(Category::Branch,"Push return pc and jump to target"))
+	.instruction("ret",&op,&[]).metadata(m(Category::Branch,"Pop return pc and jump to it"))
+	.pseudo("inc",&[("push",&[Const(1)]),("add",&[])])
+	.pseudo("dec",&[("push",&[Const(1)]),("sub",&[])])
+	.build()
+	.unwrap()
+}
+
+// =====================================================
+// Execution
+// =====================================================
+
+/// Initialise the stack of a given machine, making it empty.
+pub fn reset(state: &mut State) {
+    state.data.write_u16(SP,STACK_BASE as u16);
+}
+
+/// Get the values currently on the stack (from bottom to top).
+pub fn stack(state: &State) -> Vec<i32> {
+    let sp = state.data.read_u16(SP) as usize;
+    (STACK_BASE..sp).step_by(CELL).map(|a| state.data.read_u32(a) as i32).collect()
+}
+
+fn push(state: &mut State, value: i32) -> Result<(),Fault> {
+    let sp = state.data.read_u16(SP) as usize;
+    if sp + CELL > state.data.len() || sp + CELL > u16::MAX as usize {
+	return Err(Fault::Overflow{pc:state.pc});
+    }
+    state.data.write_u32(sp,value as u32);
+    state.data.write_u16(SP,(sp + CELL) as u16);
+    Ok(())
+}
+
+fn pop(state: &mut State) -> Result<i32,Fault> {
+    let sp = state.data.read_u16(SP) as usize;
+    if sp < STACK_BASE + CELL {
+	return Err(Fault::Underflow{pc:state.pc});
+    }
+    state.data.write_u16(SP,(sp - CELL) as u16);
+    Ok(state.data.read_u32(sp - CELL) as i32)
+}
+
+/// Execute the instruction identified by the current pc.  Executing
+/// `halt` moves the pc beyond the end of the program.
+pub fn step(state: &mut State, program: &DecodedProgram) -> Result<(),Fault> {
+    let entry = program.get(state.pc)?;
+    let mut next = state.pc + 1;
+    match program.isa().instruction(entry.insn).mnemonic() {
+	"halt" => { next = program.len(); }
+	"push" => push(state,entry.operands[0] as i32)?,
+	"pop" => { pop(state)?; }
+	"dup" => {
+	    let v = pop(state)?;
+	    push(state,v)?;
+	    push(state,v)?;
+	}
+	"swap" => {
+	    let (b,a) = (pop(state)?,pop(state)?);
+	    push(state,b)?;
+	    push(state,a)?;
+	}
+	"add" => { let (b,a) = (pop(state)?,pop(state)?); push(state,a.wrapping_add(b))?; }
+	"sub" => { let (b,a) = (pop(state)?,pop(state)?); push(state,a.wrapping_sub(b))?; }
+	"mul" => { let (b,a) = (pop(state)?,pop(state)?); push(state,a.wrapping_mul(b))?; }
+	"neg" => { let a = pop(state)?; push(state,a.wrapping_neg())?; }
+	"jmp" => { next = entry.operands[0]; }
+	"jz" => {
+	    if pop(state)? == 0 { next = entry.operands[0]; }
+	}
+	"call" => {
+	    push(state,next as i32)?;
+	    next = entry.operands[0];
+	}
+	"ret" => { next = pop(state)? as usize; }
+	m => unreachable!("unknown instruction \"{}\"",m)
+    }
+    state.pc = next;
+    Ok(())
+}
+
+/// Execute a program until it halts (i.e. the pc moves beyond the end
+/// of the program), or a given number of instructions have been
+/// executed.  Returns the number of instructions executed.
+pub fn run(state: &mut State, program: &DecodedProgram, limit: usize) -> Result<usize,Fault> {
+    let mut count = 0;
+    while count < limit && state.pc < program.len() {
+	step(state,program)?;
+	count += 1;
+    }
+    Ok(count)
+}
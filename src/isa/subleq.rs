@@ -0,0 +1,99 @@
+use alloc::vec;
+use crate::insn::{Category,Format,InstructionSet,InstructionSetBuilder,Metadata,Predicate};
+use crate::insn::AbstractMicroCode::*;
+use crate::insn::Operand::*;
+use crate::machine::AluOp;
+use crate::machine::Width::Byte;
+
+/// Number of (byte sized) memory cells which can be addressed.
+pub const MEMORY_SIZE : usize = 256;
+/// Conventional branch target used to halt the machine.  Any branch
+/// beyond the end of the program halts, but this target is always
+/// beyond it.
+pub const HALT : usize = 0xFF;
+
+// =====================================================
+// Instruction Set
+// =====================================================
+
+/// Construct the SUBLEQ ("subtract and branch if less than or equal
+/// to zero") instruction set, which consists of exactly one
+/// instruction.  Thus, `subleq a, b, c` subtracts the (signed byte)
+/// at address `a` from that at address `b` and, if the result is not
+/// positive, branches to `c`.  Otherwise, execution continues with
+/// the next instruction.  Instructions are four bytes, consisting of
+/// an (always zero) opcode followed by the three operands.  Programs
+/// are executed as usual (e.g. using `State::run()`), halting once
+/// they branch beyond their end (e.g. to `HALT`).
+pub fn isa() -> InstructionSet<'static> {
+    let abc = Format::builder().label("abc").width_bytes(4).opcode_bits(8)
+	.immediate("a",8).immediate("b",8).immediate("c",8)
+	.build().unwrap();
+    let metadata = Metadata::new().category(Category::Alu).description("M[b] := M[b] - M[a]; if M[b] <= 0 then goto c");
+    // A (nonzero) result is positive if its sign bit is clear
+    let positive = vec![RegFetch(Temp(0),Var(1),Byte),RegLoad(Temp(1),Const(0x80)),RegAlu(AluOp::Ltu,Temp(0),Temp(1)),
+			If(Predicate::RegZero(Temp(0)),vec![Goto(Var(2))],vec![])];
+    let semantic = [Alu(AluOp::Sub,Var(1),Var(0),Byte),If(Predicate::Zero(Var(1),Byte),vec![Goto(Var(2))],positive)];
+    InstructionSetBuilder::new()
+	.instruction("subleq",&abc,&semantic).metadata(metadata)
+	.build()
+	.unwrap()
+}
+
+// =====================================================
+// Samples
+// =====================================================
+
+/// A sample program, along with the initial contents of memory it
+/// expects.
+pub struct Sample {
+    pub name: &'static str,
+    /// Assembly source of this program.
+    pub source: &'static str,
+    /// Initial contents of memory (from address zero).
+    pub data: &'static [u8],
+    /// Address holding the result once the program halts.
+    pub result: usize
+}
+
+/// Computes `M[1] := M[0] + M[1]`, using `M[2]` as scratch.
+pub const ADD : Sample = Sample{
+    name: "add",
+    source: "\
+        .equ A, 0
+        .equ B, 1
+        .equ Z, 2
+        .equ HALT, 0xFF
+        subleq A, Z, l1     ; Z := -A
+l1:     subleq Z, B, l2     ; B := B + A
+l2:     subleq Z, Z, HALT   ; Z := 0 and halt
+",
+    data: &[30,12,0],
+    result: 1
+};
+
+/// Computes `M[2] := M[0] * M[1]` by repeated addition, using `M[3]`
+/// as scratch and with `M[4]` holding one.
+pub const MULTIPLY : Sample = Sample{
+    name: "multiply",
+    source: "\
+        .equ A, 0
+        .equ B, 1
+        .equ R, 2
+        .equ Z, 3
+        .equ ONE, 4
+        .equ HALT, 0xFF
+loop:   subleq Z, B, done   ; if B <= 0 then done
+        subleq A, Z, l1     ; Z := -A
+l1:     subleq Z, R, l2     ; R := R + A
+l2:     subleq Z, Z, l3     ; Z := 0
+l3:     subleq ONE, B, loop ; B := B - 1
+        subleq Z, Z, loop
+done:   subleq Z, Z, HALT
+",
+    data: &[6,7,0,0,1],
+    result: 2
+};
+
+/// All sample programs.
+pub const SAMPLES : [Sample;2] = [ADD,MULTIPLY];
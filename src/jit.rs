@@ -0,0 +1,353 @@
+use std::fmt;
+use cranelift_codegen::ir::{types,AbiParam,Block,Endianness,InstBuilder,MemFlags,Type,Value};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::settings::{self,Configurable};
+use cranelift_frontend::{FunctionBuilder,FunctionBuilderContext,Variable};
+use cranelift_jit::{JITBuilder,JITModule};
+use cranelift_module::{default_libcall_names,Module};
+use crate::insn::ByteOrder;
+use crate::machine::{MachineError,MicroCode,State,Width};
+use crate::program::DecodedProgram;
+
+/// Maximum number of instructions compiled into a single block.
+pub const MAX_BLOCK : usize = 64;
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum JitError {
+    /// The instruction at the current pc faulted when interpreted
+    /// (e.g. it could not be decoded, or accesses memory out of
+    /// bounds).
+    Machine(MachineError),
+    /// The native code generator could not be initialised (e.g. the
+    /// host architecture is not supported).
+    Unsupported(String),
+    /// In differential mode, the block starting at a given pc
+    /// produced a different state when compiled than when
+    /// interpreted.
+    Mismatch{pc: usize}
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    JitError::Machine(e) => write!(f,"{}",e),
+	    JitError::Unsupported(e) => write!(f,"native code generation unsupported ({})",e),
+	    JitError::Mismatch{pc} => write!(f,"compiled block at {} differs from interpreter",pc)
+	}
+    }
+}
+
+impl std::error::Error for JitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    JitError::Machine(e) => Some(e),
+	    _ => None
+	}
+    }
+}
+
+impl From<MachineError> for JitError {
+    fn from(e: MachineError) -> Self {
+	JitError::Machine(e)
+    }
+}
+
+// =====================================================
+// JIT
+// =====================================================
+
+/// A compiled block, which is given the machine's memory and a
+/// counter (incremented for each instruction executed), and returns
+/// the next pc.
+type Native = unsafe extern "C" fn(*mut u8, *mut usize) -> usize;
+
+/// The block starting at a given pc.
+#[derive(Clone,Copy)]
+enum Slot {
+    /// Not yet compiled, having been reached a given number of times.
+    Counting(usize),
+    /// Compiled, where `None` indicates no block could be compiled at
+    /// this pc.
+    Compiled(Option<Native>)
+}
+
+/// Executes a decoded program by translating its hot basic blocks
+/// into native code using Cranelift.  A block starts at a given pc
+/// and extends (in order) up to and including the first branch.
+/// Blocks are compiled once they have been reached a given number of
+/// times and, until then, instructions are interpreted one at a time
+/// (i.e. using `State::try_step()`).  Only microcode
+/// operating on memory (e.g. `Add`, `Copy`, `Load`, branches and
+/// skips) is compiled.  Thus, instructions using registers, requiring
+/// a feature or accessing memory out of bounds (hence faulting) are
+/// always interpreted.  For example:
+///
+/// ```text
+/// let mut jit = Jit::new(&program)?.threshold(10);
+/// while state.pc < program.len() {
+///     jit.step(&mut state)?;
+/// }
+/// ```
+pub struct Jit<'a> {
+    program: &'a DecodedProgram<'a>,
+    module: JITModule,
+    /// Blocks indexed by their starting pc.  Since branch targets are
+    /// pcs, the block reached by a taken branch is found without any
+    /// lookup (e.g. hashing its target).
+    blocks: Vec<Slot>,
+    /// Size of memory assumed by the compiled blocks.
+    memory: usize,
+    /// Number of times a block must be reached before it is
+    /// compiled.
+    threshold: usize,
+    /// Determines whether compiled blocks are checked against the
+    /// interpreter.
+    differential: bool
+}
+
+impl<'a> Jit<'a> {
+    pub fn new(program: &'a DecodedProgram<'a>) -> Result<Self,JitError> {
+	let unsupported = |e: String| JitError::Unsupported(e);
+	let mut flags = settings::builder();
+	flags.set("use_colocated_libcalls","false").map_err(|e| unsupported(e.to_string()))?;
+	flags.set("is_pic","false").map_err(|e| unsupported(e.to_string()))?;
+	let isa = cranelift_native::builder().map_err(|e| unsupported(e.to_string()))?
+	    .finish(settings::Flags::new(flags)).map_err(|e| unsupported(e.to_string()))?;
+	let module = JITModule::new(JITBuilder::with_isa(isa,default_libcall_names()));
+	Ok(Jit{program,module,blocks:vec![Slot::Counting(0);program.len()],memory:0,threshold:16,differential:false})
+    }
+    /// Set the number of times a block must be reached before it is
+    /// compiled (default is 16).
+    pub fn threshold(mut self, threshold: usize) -> Self {
+	self.threshold = threshold;
+	self
+    }
+    /// Set whether every execution of a compiled block is checked
+    /// against the interpreter (default is false).  This is
+    /// (considerably) slower, but useful for testing.
+    pub fn differential(mut self, differential: bool) -> Self {
+	self.differential = differential;
+	self
+    }
+    /// Get the number of blocks which have been compiled.
+    pub fn compiled(&self) -> usize {
+	self.blocks.iter().filter(|b| matches!(b,Slot::Compiled(Some(_)))).count()
+    }
+    /// Execute either the compiled block starting at the current pc,
+    /// or (otherwise) a single instruction.  Returns the number of
+    /// instructions executed.
+    pub fn step(&mut self, state: &mut State) -> Result<usize,JitError> {
+	if state.data.byte_order() != ByteOrder::LittleEndian {
+	    // Blocks access memory natively, which is little endian
+	    state.try_step(self.program)?;
+	    return Ok(1);
+	}
+	if state.data.len() != self.memory {
+	    // Blocks are only valid for the memory they were compiled for
+	    self.memory = state.data.len();
+	    for slot in self.blocks.iter_mut() {
+		if let Slot::Compiled(_) = slot { *slot = Slot::Counting(0); }
+	    }
+	}
+	let pc = state.pc;
+	let native = match self.blocks.get(pc).copied() {
+	    Some(Slot::Compiled(native)) => native,
+	    Some(Slot::Counting(count)) if count >= self.threshold => {
+		let native = self.compile(pc);
+		self.blocks[pc] = Slot::Compiled(native);
+		native
+	    }
+	    Some(Slot::Counting(count)) => {
+		self.blocks[pc] = Slot::Counting(count + 1);
+		None
+	    }
+	    // Outside the program, hence the interpreter reports an error
+	    None => None
+	};
+	let Some(native) = native else {
+	    state.try_step(self.program)?;
+	    return Ok(1);
+	};
+	let expected = self.differential.then(|| state.data.bytes().to_vec());
+	let mut count = 0;
+	// SAFETY: the block only accesses memory within the bounds it
+	// was compiled for (which was checked above).
+	state.pc = unsafe { native(state.data.bytes_mut().as_mut_ptr(),&mut count) };
+	if let Some(mut bytes) = expected {
+	    let mut other = State::new(pc,&mut bytes).with_features(state.features);
+	    // Replay one instruction at a time, since count includes
+	    // both instructions of a fused pair
+	    for _ in 0..count {
+		other.try_step(self.program)?;
+	    }
+	    if other.pc != state.pc || other.data.bytes() != state.data.bytes() {
+		return Err(JitError::Mismatch{pc});
+	    }
+	}
+	Ok(count)
+    }
+
+    /// Compile the block starting at a given pc, or return `None` if
+    /// its first instruction cannot be compiled.
+    fn compile(&mut self, pc: usize) -> Option<Native> {
+	let block = self.block(pc);
+	if block.is_empty() {
+	    return None;
+	}
+	let ptr = self.module.target_config().pointer_type();
+	let mut ctx = self.module.make_context();
+	ctx.func.signature.params.push(AbiParam::new(ptr));
+	ctx.func.signature.params.push(AbiParam::new(ptr));
+	ctx.func.signature.returns.push(AbiParam::new(ptr));
+	let mut fctx = FunctionBuilderContext::new();
+	let mut b = FunctionBuilder::new(&mut ctx.func,&mut fctx);
+	let entry = b.create_block();
+	b.append_block_params_for_function_params(entry);
+	let exit = b.create_block();
+	let result = b.append_block_param(exit,ptr);
+	b.switch_to_block(entry);
+	let (memory,counter) = (b.block_params(entry)[0],b.block_params(entry)[1]);
+	let next = Variable::from_u32(0);
+	b.declare_var(next,ptr);
+	for (i,codes) in block.iter().enumerate() {
+	    let p = pc + i;
+	    let v = b.ins().iconst(ptr,(p + 1) as i64);
+	    b.def_var(next,v);
+	    // One block per microcode, followed by the end of the instruction
+	    let blocks : Vec<Block> = (0..=codes.len()).map(|_| b.create_block()).collect();
+	    b.ins().jump(blocks[0],&[]);
+	    for (j,code) in codes.iter().enumerate() {
+		b.switch_to_block(blocks[j]);
+		let following = blocks[j+1];
+		let skipped = |n: usize| blocks[(j + 1 + n).min(codes.len())];
+		match *code {
+		    MicroCode::Add(x,y,w) => {
+			let l = load(&mut b,memory,x,w);
+			let r = load(&mut b,memory,y,w);
+			let v = b.ins().iadd(l,r);
+			store(&mut b,memory,x,v);
+			b.ins().jump(following,&[]);
+		    }
+		    MicroCode::Copy(x,y,w) => {
+			let v = load(&mut b,memory,y,w);
+			store(&mut b,memory,x,v);
+			b.ins().jump(following,&[]);
+		    }
+		    MicroCode::Load(x,i,w) => {
+			let v = b.ins().iconst(ty(w),(i & w.mask()) as i64);
+			store(&mut b,memory,x,v);
+			b.ins().jump(following,&[]);
+		    }
+		    MicroCode::Goto(t) => {
+			let v = b.ins().iconst(ptr,t as i64);
+			b.def_var(next,v);
+			b.ins().jump(following,&[]);
+		    }
+		    MicroCode::Jump(o) => {
+			let v = b.ins().iconst(ptr,p.wrapping_add_signed(o) as i64);
+			b.def_var(next,v);
+			b.ins().jump(following,&[]);
+		    }
+		    MicroCode::Skip(n) => {
+			b.ins().jump(skipped(n),&[]);
+		    }
+		    MicroCode::SkipIfZero(x,w,n) => {
+			let v = load(&mut b,memory,x,w);
+			b.ins().brif(v,following,&[],skipped(n),&[]);
+		    }
+		    _ => unreachable!()
+		}
+	    }
+	    // Count the instruction, then either continue with the next
+	    // or leave the block.
+	    b.switch_to_block(blocks[codes.len()]);
+	    let flags = MemFlags::trusted();
+	    let c = b.ins().load(ptr,flags,counter,0);
+	    let c = b.ins().iadd_imm(c,1);
+	    b.ins().store(flags,c,counter,0);
+	    let v = b.use_var(next);
+	    if i + 1 < block.len() {
+		let fallthrough = b.ins().icmp_imm(IntCC::Equal,v,(p + 1) as i64);
+		let continuation = b.create_block();
+		b.ins().brif(fallthrough,continuation,&[],exit,&[v]);
+		b.switch_to_block(continuation);
+	    } else {
+		b.ins().jump(exit,&[v]);
+	    }
+	}
+	b.switch_to_block(exit);
+	b.ins().return_(&[result]);
+	b.seal_all_blocks();
+	b.finalize();
+	let id = self.module.declare_anonymous_function(&ctx.func.signature).ok()?;
+	self.module.define_function(id,&mut ctx).ok()?;
+	self.module.clear_context(&mut ctx);
+	self.module.finalize_definitions().ok()?;
+	let code = self.module.get_finalized_function(id);
+	// SAFETY: the function was defined with exactly this signature
+	Some(unsafe { std::mem::transmute::<*const u8,Native>(code) })
+    }
+
+    /// Determine the microcode of each instruction in the block
+    /// starting at a given pc.  This stops before any instruction
+    /// which cannot be compiled, and after the first branch.
+    fn block(&self, pc: usize) -> Vec<Vec<MicroCode>> {
+	let mut block = Vec::new();
+	while block.len() < MAX_BLOCK {
+	    let Ok(entry) = self.program.get(pc + block.len()) else { break; };
+	    let codes : Vec<MicroCode> = entry.microcode.iter().map(|t| t.code()).collect();
+	    let feature = self.program.isa().instruction(entry.insn).feature();
+	    if feature.is_some() || !codes.iter().all(|c| self.supported(c)) {
+		break;
+	    }
+	    let branch = codes.iter().any(|c| c.is_branch());
+	    block.push(codes);
+	    if branch {
+		break;
+	    }
+	}
+	block
+    }
+
+    /// Check whether a given microcode can be compiled, which requires
+    /// any memory it accesses to be within bounds.
+    fn supported(&self, code: &MicroCode) -> bool {
+	let fits = |x: usize, w: Width| x.checked_add(w.bytes()).is_some_and(|e| e <= self.memory);
+	match *code {
+	    MicroCode::Add(x,y,w)|MicroCode::Copy(x,y,w) => fits(x,w) && fits(y,w),
+	    MicroCode::Load(x,_,w)|MicroCode::SkipIfZero(x,w,_) => fits(x,w),
+	    MicroCode::Goto(_)|MicroCode::Jump(_)|MicroCode::Skip(_) => true,
+	    _ => false
+	}
+    }
+}
+
+/// Determine the Cranelift type of values with a given width.
+fn ty(w: Width) -> Type {
+    match w {
+	Width::Byte => types::I8,
+	Width::Word => types::I16,
+	Width::DoubleWord => types::I32,
+	Width::QuadWord => types::I64
+    }
+}
+
+/// Flags for accessing (little endian) memory, which is checked to be
+/// in bounds when compiling.
+fn flags() -> MemFlags {
+    MemFlags::new().with_notrap().with_endianness(Endianness::Little)
+}
+
+fn load(b: &mut FunctionBuilder, memory: Value, x: usize, w: Width) -> Value {
+    let address = b.ins().iadd_imm(memory,x as i64);
+    b.ins().load(ty(w),flags(),address,0)
+}
+
+fn store(b: &mut FunctionBuilder, memory: Value, x: usize, v: Value) {
+    let address = b.ins().iadd_imm(memory,x as i64);
+    b.ins().store(flags(),v,address,0);
+}
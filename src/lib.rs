@@ -1,3 +1,78 @@
+#![cfg_attr(not(feature="std"),no_std)]
+extern crate alloc;
+
+#[cfg(feature="std")]
+pub mod absint;
+#[cfg(feature="std")]
+pub mod analysis;
+#[cfg(feature="std")]
+pub mod asm;
+#[cfg(feature="std")]
+pub mod bench;
+#[cfg(feature="capi")]
+pub mod capi;
+#[cfg(feature="std")]
+pub mod cfg;
+#[cfg(feature="std")]
+pub mod compile;
+#[cfg(feature="std")]
+pub mod concolic;
+#[cfg(feature="std")]
+pub mod conformance;
+#[cfg(feature="std")]
+pub mod coverage;
+#[cfg(feature="std")]
+pub mod dataflow;
+#[cfg(feature="std")]
+pub mod debug;
+#[cfg(feature="std")]
+pub mod diff;
+#[cfg(feature="std")]
+pub mod differential;
+#[cfg(feature="std")]
+pub mod disasm;
+#[cfg(feature="std")]
+pub mod dump;
 pub mod domain;
+#[cfg(feature="elf")]
+pub mod elf;
+pub mod fuzzing;
+#[cfg(feature="std")]
+pub mod gdb;
+#[cfg(feature="std")]
+pub mod hex;
 pub mod insn;
+pub mod isa;
+#[cfg(feature="jit")]
+pub mod jit;
+#[cfg(feature="std")]
+pub mod link;
 pub mod machine;
+#[cfg(feature="std")]
+pub mod manual;
+#[cfg(feature="std")]
+pub mod metrics;
+pub mod optimize;
+#[cfg(feature="parallel")]
+mod parallel;
+pub mod program;
+#[cfg(feature="python")]
+pub mod python;
+#[cfg(feature="serde")]
+pub mod snapshot;
+#[cfg(feature="spec")]
+pub mod spec;
+#[cfg(any(feature="arbitrary",feature="proptest"))]
+pub mod strategy;
+#[cfg(feature="std")]
+pub mod testing;
+pub mod tiered;
+#[cfg(feature="tui")]
+pub mod tui;
+#[cfg(feature="std")]
+pub mod transpile;
+pub mod verify;
+#[cfg(feature="wasm")]
+pub mod wasm;
+#[cfg(feature="web")]
+pub mod web;
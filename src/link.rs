@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::insn::{DecodeError,EncodeError,InstructionSet};
+use crate::program::{Program,Symbol,SymbolKind};
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum LinkError {
+    /// A symbol was referred to, but never defined.
+    UndefinedSymbol(String),
+    /// A global symbol was defined by more than one object, or a
+    /// symbol was defined by more than one section of an object.
+    DuplicateSymbol(String),
+    /// Two sections were placed such that they overlap.
+    Overlap{first: String, second: String},
+    /// A section was placed after a gap, but no instruction was given
+    /// for filling it.
+    Gap(String),
+    /// A relocated operand does not fit into its field.
+    OutOfRange{symbol: String, value: isize},
+    /// An instruction within a section could not be decoded.
+    Decode(DecodeError),
+    /// An instruction could not be encoded.
+    Encode(EncodeError)
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    LinkError::UndefinedSymbol(s) => write!(f,"undefined symbol \"{}\"",s),
+	    LinkError::DuplicateSymbol(s) => write!(f,"duplicate symbol \"{}\"",s),
+	    LinkError::Overlap{first,second} => write!(f,"section \"{}\" overlaps \"{}\"",second,first),
+	    LinkError::Gap(s) => write!(f,"no fill instruction for gap before section \"{}\"",s),
+	    LinkError::OutOfRange{symbol,value} => write!(f,"symbol \"{}\" out of range ({})",symbol,value),
+	    LinkError::Decode(e) => write!(f,"cannot decode instruction ({})",e),
+	    LinkError::Encode(e) => write!(f,"cannot encode instruction ({})",e)
+	}
+    }
+}
+
+impl std::error::Error for LinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    LinkError::Decode(e) => Some(e),
+	    LinkError::Encode(e) => Some(e),
+	    _ => None
+	}
+    }
+}
+
+// =====================================================
+// Objects
+// =====================================================
+
+/// A named sequence of instructions within an object, along with the
+/// symbols it defines and the relocations it requires.
+pub struct Section<'a> {
+    name: String,
+    program: Program<'a>
+}
+
+impl<'a> Section<'a> {
+    pub fn new(name: &str, program: Program<'a>) -> Self {
+	Section{name:name.to_string(),program}
+    }
+    pub fn name(&self) -> &str {
+	&self.name
+    }
+    pub fn program(&self) -> &Program<'a> {
+	&self.program
+    }
+}
+
+/// A unit of linking made up from one or more sections (e.g. the
+/// result of assembling a single source file).  Symbols defined in
+/// one section are visible to all sections of the same object,
+/// whilst only global symbols are visible to other objects.
+#[derive(Default)]
+pub struct Object<'a> {
+    sections: Vec<Section<'a>>
+}
+
+impl<'a> Object<'a> {
+    pub fn new() -> Self {
+	Object{sections:Vec::new()}
+    }
+    /// Add a section with a given name to this object.
+    pub fn section(mut self, name: &str, program: Program<'a>) -> Self {
+	self.sections.push(Section::new(name,program));
+	self
+    }
+    pub fn sections(&self) -> &[Section<'a>] {
+	&self.sections
+    }
+}
+
+// =====================================================
+// Linker
+// =====================================================
+
+/// Combines a number of objects into a single program.  Sections with
+/// the same name are merged (in the order objects were added), and
+/// each merged section is placed either at a chosen base pc or,
+/// otherwise, immediately after the previous one.  Any gaps between
+/// sections are padded using a fill instruction (which must have no
+/// operands).  Finally, all relocations are resolved such that the
+/// resulting program is ready for execution, with a symbol table
+/// holding the final value of every symbol.
+///
+/// ```text
+/// let program = Linker::new(&isa)
+///     .object(main)
+///     .object(lib)
+///     .place("data",0x100)
+///     .fill("nop")
+///     .link()?;
+/// ```
+pub struct Linker<'a> {
+    isa: &'a InstructionSet<'a>,
+    objects: Vec<Object<'a>>,
+    bases: BTreeMap<String,usize>,
+    fill: Option<String>
+}
+
+/// Identifies a section within a given object, along with the pc at
+/// which it has been placed.
+struct Placement {
+    object: usize,
+    section: usize,
+    start: usize
+}
+
+impl<'a> Linker<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	Linker{isa,objects:Vec::new(),bases:BTreeMap::new(),fill:None}
+    }
+    /// Add an object to be linked.
+    pub fn object(mut self, object: Object<'a>) -> Self {
+	self.objects.push(object);
+	self
+    }
+    /// Place all sections with a given name starting at a given pc.
+    pub fn place(mut self, section: &str, base: usize) -> Self {
+	self.bases.insert(section.to_string(),base);
+	self
+    }
+    /// Set the instruction used to fill gaps between sections.
+    pub fn fill(mut self, mnemonic: &str) -> Self {
+	self.fill = Some(mnemonic.to_string());
+	self
+    }
+    /// Link all objects together into a single program.
+    pub fn link(&self) -> Result<Program<'a>,LinkError> {
+	let regions = self.layout()?;
+	let (locals,globals) = self.symbols(&regions)?;
+	let mut program = Program::new(self.isa);
+	for (name,pieces) in &regions {
+	    for p in pieces {
+		while program.len() < p.start {
+		    match &self.fill {
+			Some(m) => { program.push(m,&[]).map_err(LinkError::Encode)?; }
+			None => { return Err(LinkError::Gap(name.clone())); }
+		    }
+		}
+		let section = &self.objects[p.object].sections[p.section];
+		self.relocate(section,p.start,&locals[p.object],&globals,&mut program)?;
+	    }
+	}
+	// Construct symbol table
+	for (_,pieces) in &regions {
+	    for p in pieces {
+		for s in self.objects[p.object].sections[p.section].program.symbols() {
+		    program.define(Symbol{value:value(s,p.start),..s.clone()});
+		}
+	    }
+	}
+	Ok(program)
+    }
+
+    /// Determine where every section is placed, returning the merged
+    /// sections in order of their starting pc.
+    fn layout(&self) -> Result<Vec<(String,Vec<Placement>)>,LinkError> {
+	let mut regions : Vec<(String,Vec<Placement>)> = Vec::new();
+	for (i,o) in self.objects.iter().enumerate() {
+	    for (j,s) in o.sections.iter().enumerate() {
+		let p = Placement{object:i,section:j,start:0};
+		match regions.iter_mut().find(|(n,_)| *n == s.name) {
+		    Some((_,pieces)) => pieces.push(p),
+		    None => regions.push((s.name.clone(),vec![p]))
+		}
+	    }
+	}
+	let mut pc = 0;
+	let mut extents = Vec::new();
+	for (name,pieces) in &mut regions {
+	    let start = self.bases.get(name).copied().unwrap_or(pc);
+	    pc = start;
+	    for p in pieces {
+		p.start = pc;
+		pc += self.objects[p.object].sections[p.section].program.len();
+	    }
+	    extents.push((start,pc));
+	}
+	let mut regions : Vec<_> = regions.into_iter().zip(extents).collect();
+	regions.sort_by_key(|(_,(start,_))| *start);
+	// Each section is checked against that extending furthest so
+	// far, since it need not be adjacent (e.g. when an empty section
+	// lies between them).
+	let mut furthest : Option<(&String,usize)> = None;
+	for ((second,_),(start,end)) in regions.iter().filter(|(_,(start,end))| start < end) {
+	    if let Some((first,last)) = furthest {
+		if *start < last {
+		    return Err(LinkError::Overlap{first:first.clone(),second:second.clone()});
+		}
+	    }
+	    if furthest.is_none_or(|(_,last)| *end > last) {
+		furthest = Some((second,*end));
+	    }
+	}
+	Ok(regions.into_iter().map(|(r,_)| r).collect())
+    }
+
+    /// Determine the final values of all symbols, returning those
+    /// visible within each object and those visible globally.
+    #[allow(clippy::type_complexity)]
+    fn symbols(&self, regions: &[(String,Vec<Placement>)]) -> Result<(Vec<BTreeMap<String,usize>>,BTreeMap<String,usize>),LinkError> {
+	let mut locals = vec![BTreeMap::new();self.objects.len()];
+	let mut globals = BTreeMap::new();
+	for (_,pieces) in regions {
+	    for p in pieces {
+		for s in self.objects[p.object].sections[p.section].program.symbols() {
+		    let v = value(s,p.start);
+		    if locals[p.object].insert(s.name.clone(),v).is_some() {
+			return Err(LinkError::DuplicateSymbol(s.name.clone()));
+		    }
+		    if s.global && globals.insert(s.name.clone(),v).is_some() {
+			return Err(LinkError::DuplicateSymbol(s.name.clone()));
+		    }
+		}
+	    }
+	}
+	Ok((locals,globals))
+    }
+
+    /// Append the instructions of a given section (placed at a given
+    /// pc) onto a program, applying any relocations.
+    fn relocate(&self, section: &Section, start: usize, locals: &BTreeMap<String,usize>, globals: &BTreeMap<String,usize>, program: &mut Program<'a>) -> Result<(),LinkError> {
+	let source = &section.program;
+	for pc in 0..source.len() {
+	    let (index,mut operands) = self.isa.decode(&source.bytes()[source.offset(pc)..]).map_err(LinkError::Decode)?;
+	    let insn = self.isa.instruction(index);
+	    for r in source.relocations().iter().filter(|r| r.pc == pc) {
+		let (symbol,target) = match &r.symbol {
+		    None => (section.name.clone(),start),
+		    Some(s) => match locals.get(s).or_else(|| globals.get(s)) {
+			Some(v) => (s.clone(),*v),
+			None => { return Err(LinkError::UndefinedSymbol(s.clone())); }
+		    }
+		};
+		let mut value = (target as isize).wrapping_add(r.addend);
+		if r.relative {
+		    value = value.wrapping_sub((start + pc) as isize);
+		}
+		if !insn.format().operands()[r.operand].fits(value as usize) {
+		    return Err(LinkError::OutOfRange{symbol,value});
+		}
+		operands[r.operand] = value as usize;
+	    }
+	    let target = program.push(insn.mnemonic(),&operands).map_err(LinkError::Encode)?;
+	    if let Some(l) = source.location(pc) {
+		program.locate(target,l.clone());
+	    }
+	}
+	Ok(())
+    }
+}
+
+/// Determine the final value of a symbol defined in a section placed
+/// at a given pc.
+fn value(symbol: &Symbol, start: usize) -> usize {
+    match symbol.kind {
+	SymbolKind::Label => symbol.value + start,
+	SymbolKind::Constant => symbol.value
+    }
+}
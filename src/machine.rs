@@ -1,75 +1,328 @@
+use core::fmt;
+use core::ops::Range;
+use alloc::{string::{String,ToString},sync::Arc,vec,vec::Vec};
+use crate::insn::{ByteOrder,DecodeError,Instruction};
+use crate::program::DecodedProgram;
+
 // =====================================================
 // (Random Access) Memory
 // =====================================================
 
 /// Describes a fixed-size array of bytes.
 pub struct Memory<'a> {
-    contents: &'a mut [u8]
+    contents: &'a mut [u8],
+    /// The order in which the bytes of multi-byte values are stored.
+    order: ByteOrder
 }
 
 impl<'a> Memory<'a> {
     pub fn new(contents: &'a mut [u8]) -> Self {
-	Memory{contents}
+	Memory{contents,order:ByteOrder::LittleEndian}
+    }
+    /// Set the order in which the bytes of multi-byte values are
+    /// stored (default is little endian).
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+	self.order = order;
+	self
+    }
+    /// Get the order in which the bytes of multi-byte values are
+    /// stored.
+    pub fn byte_order(&self) -> ByteOrder {
+	self.order
+    }
+    /// Get the size (in bytes) of this memory.
+    pub fn len(&self) -> usize {
+	self.contents.len()
+    }
+    /// Check whether this memory has zero size.
+    pub fn is_empty(&self) -> bool {
+	self.contents.is_empty()
+    }
+    /// Get the contents of this memory.
+    pub fn bytes(&self) -> &[u8] {
+	self.contents
+    }
+    /// Get the contents of this memory, such that they can be
+    /// modified directly.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+	self.contents
+    }
+    /// Read a block of bytes starting at a given address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block extends beyond the end of memory.
+    pub fn read_bytes(&self, address: usize, bytes: &mut [u8]) {
+	bytes.copy_from_slice(&self.contents[address..address+bytes.len()]);
+    }
+    /// Write a block of bytes starting at a given address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block extends beyond the end of memory.
+    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) {
+	self.contents[address..address+bytes.len()].copy_from_slice(bytes);
+    }
+    /// Set a given number of bytes starting at a given address to a
+    /// given value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block extends beyond the end of memory.
+    pub fn fill(&mut self, address: usize, length: usize, value: u8) {
+	self.contents[address..address+length].fill(value);
+    }
+    /// Copy a given number of bytes from one address to another.  The
+    /// two blocks may overlap, in which case the result is as though
+    /// the source was first copied into a temporary buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either block extends beyond the end of memory.
+    pub fn copy(&mut self, to: usize, from: usize, length: usize) {
+	self.contents.copy_within(from..from+length,to);
+    }
+    /// Read a value of a given type (and, hence, width) from a given
+    /// address.  For example, `memory.read::<u16>(2)` reads the two
+    /// bytes at addresses 2 and 3 (in the byte order of this memory).
+    pub fn read<T:Scalar>(&self, address: usize) -> T {
+	let bytes = &self.contents[address..address+size_of::<T>()];
+	match self.order {
+	    ByteOrder::LittleEndian => T::from_le(bytes),
+	    ByteOrder::BigEndian => T::from_be(bytes)
+	}
+    }
+    /// Write a value of a given type (and, hence, width) to a given
+    /// address.
+    pub fn write<T:Scalar>(&mut self, address: usize, value: T) {
+	let bytes = &mut self.contents[address..address+size_of::<T>()];
+	match self.order {
+	    ByteOrder::LittleEndian => value.to_le(bytes),
+	    ByteOrder::BigEndian => value.to_be(bytes)
+	}
     }
     pub fn read_u8(&self, address : usize) -> u8 {
-	self.contents[address]
+	self.read(address)
     }
     pub fn read_u16(&self, address : usize) -> u16 {
-	let b0 = self.contents[address];
-	let b1 = self.contents[address+1];	
-	return u16::from_le_bytes([b0,b1]);
+	self.read(address)
     }
     pub fn read_u32(&self, address : usize) -> u32 {
-	let b0 = self.contents[address+0];
-	let b1 = self.contents[address+1];
-	let b2 = self.contents[address+2];
-	let b3 = self.contents[address+3];
-	return u32::from_le_bytes([b0,b1,b2,b3]);
+	self.read(address)
     }
     pub fn read_u64(&self, address : usize) -> u64 {
-	let b0 = self.contents[address+0];
-	let b1 = self.contents[address+1];
-	let b2 = self.contents[address+2];
-	let b3 = self.contents[address+3];
-	let b4 = self.contents[address+4];
-	let b5 = self.contents[address+5];
-	let b6 = self.contents[address+6];
-	let b7 = self.contents[address+7];
-	return u64::from_le_bytes([b0,b1,b2,b3,b4,b5,b6,b7]);
+	self.read(address)
     }
     pub fn write_u8(&mut self, address : usize, value: u8) {
-	self.contents[address] = value; 
+	self.write(address,value)
     }
     pub fn write_u16(&mut self, address : usize, value: u16) {
-	let bytes = value.to_le_bytes();
-	self.contents[address+0] = bytes[0];
-	self.contents[address+1] = bytes[1];
+	self.write(address,value)
     }
     pub fn write_u32(&mut self, address : usize, value: u32) {
-	let bytes = value.to_le_bytes();
-	self.contents[address+0] = bytes[0];
-	self.contents[address+1] = bytes[1];
-	self.contents[address+2] = bytes[2];
-	self.contents[address+3] = bytes[3];	
+	self.write(address,value)
     }
     pub fn write_u64(&mut self, address : usize, value: u64) {
-	let bytes = value.to_le_bytes();
-	self.contents[address+0] = bytes[0];
-	self.contents[address+1] = bytes[1];
-	self.contents[address+2] = bytes[2];
-	self.contents[address+3] = bytes[3];
-	self.contents[address+4] = bytes[4];
-	self.contents[address+5] = bytes[5];
-	self.contents[address+6] = bytes[6];
-	self.contents[address+7] = bytes[7];	
+	self.write(address,value)
     }
+    /// Read a value as for `read()`, except that the address wraps
+    /// around memory (i.e. is taken modulo its size), as do the bytes
+    /// of a value which extends beyond the end.  This never fails,
+    /// with an empty memory reading as zero.
+    pub fn read_wrapping<T:Scalar>(&self, address: usize) -> T {
+	let n = self.contents.len();
+	if n == 0 { return T::truncate(0); }
+	let address = address % n;
+	if address + size_of::<T>() <= n { return self.read(address); }
+	let mut bytes = [0u8;8];
+	for (i,b) in bytes[..size_of::<T>()].iter_mut().enumerate() {
+	    *b = self.contents[(address + i) % n];
+	}
+	match self.order {
+	    ByteOrder::LittleEndian => T::from_le(&bytes[..size_of::<T>()]),
+	    ByteOrder::BigEndian => T::from_be(&bytes[..size_of::<T>()])
+	}
+    }
+    /// Write a value as for `write()`, except that the address wraps
+    /// around memory (as for `read_wrapping()`).  Writing to an empty
+    /// memory has no effect.
+    pub fn write_wrapping<T:Scalar>(&mut self, address: usize, value: T) {
+	let n = self.contents.len();
+	if n == 0 { return; }
+	let address = address % n;
+	if address + size_of::<T>() <= n { return self.write(address,value); }
+	let mut bytes = [0u8;8];
+	match self.order {
+	    ByteOrder::LittleEndian => value.to_le(&mut bytes[..size_of::<T>()]),
+	    ByteOrder::BigEndian => value.to_be(&mut bytes[..size_of::<T>()])
+	}
+	for (i,b) in bytes[..size_of::<T>()].iter().enumerate() {
+	    self.contents[(address + i) % n] = *b;
+	}
+    }
+}
+
+/// An unsigned integer type corresponding to one of the widths at
+/// which memory is accessed.  This allows microcode to be executed by
+/// helpers specialised to each width (e.g. `add::<u16>`), rather than
+/// by matching on the width at every access.
+pub trait Scalar : Copy {
+    /// The width of this type.
+    const WIDTH: Width;
+    /// Construct a value from exactly `size_of::<Self>()` bytes
+    /// (little endian).
+    fn from_le(bytes: &[u8]) -> Self;
+    /// Write this value into exactly `size_of::<Self>()` bytes
+    /// (little endian).
+    fn to_le(self, bytes: &mut [u8]);
+    /// Construct a value from exactly `size_of::<Self>()` bytes (big
+    /// endian).
+    fn from_be(bytes: &[u8]) -> Self;
+    /// Write this value into exactly `size_of::<Self>()` bytes (big
+    /// endian).
+    fn to_be(self, bytes: &mut [u8]);
+    /// Add two values, wrapping around on overflow.
+    fn wrapping_add(self, other: Self) -> Self;
+    /// Construct a value from the lowest bits of a given value.
+    fn truncate(value: u64) -> Self;
+    /// Zero extend this value.
+    fn extend(self) -> u64;
+}
+
+macro_rules! scalar {
+    ($t:ty, $w:expr) => {
+	impl Scalar for $t {
+	    const WIDTH: Width = $w;
+	    fn from_le(bytes: &[u8]) -> Self {
+		<$t>::from_le_bytes(bytes.try_into().unwrap())
+	    }
+	    fn to_le(self, bytes: &mut [u8]) {
+		bytes.copy_from_slice(&self.to_le_bytes());
+	    }
+	    fn from_be(bytes: &[u8]) -> Self {
+		<$t>::from_be_bytes(bytes.try_into().unwrap())
+	    }
+	    fn to_be(self, bytes: &mut [u8]) {
+		bytes.copy_from_slice(&self.to_be_bytes());
+	    }
+	    fn wrapping_add(self, other: Self) -> Self {
+		<$t>::wrapping_add(self,other)
+	    }
+	    fn truncate(value: u64) -> Self {
+		value as $t
+	    }
+	    fn extend(self) -> u64 {
+		self as u64
+	    }
+	}
+    }
+}
+
+scalar!(u8,Width::Byte);
+scalar!(u16,Width::Word);
+scalar!(u32,Width::DoubleWord);
+scalar!(u64,Width::QuadWord);
+
+// =====================================================
+// Register File
+// =====================================================
+
+/// Describes a fixed-size array of registers, each holding a value of
+/// the same width.  Registers are held separately from memory and,
+/// hence, are accessed only by register-addressed microcode (e.g.
+/// `MicroCode::RegCopy`).  Registers can optionally be given names
+/// (e.g. `sp`), so they can be inspected by name.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct RegisterFile {
+    width: Width,
+    contents: Vec<u64>,
+    names: Vec<String>
+}
+
+impl RegisterFile {
+    /// Construct a register file with a given number of registers, all
+    /// initially zero.
+    pub fn new(count: usize, width: Width) -> Self {
+	RegisterFile{width,contents:vec![0; count],names:Vec::new()}
+    }
+    /// Set the names of registers, indexed by register number.
+    pub fn with_names(mut self, names: &[&str]) -> Self {
+	self.names = names.iter().map(|n| n.to_string()).collect();
+	self
+    }
+    /// Get the number of registers in this file.
+    pub fn len(&self) -> usize {
+	self.contents.len()
+    }
+    /// Check whether this file has no registers.
+    pub fn is_empty(&self) -> bool {
+	self.contents.is_empty()
+    }
+    /// Get the width of every register in this file.
+    pub fn width(&self) -> Width {
+	self.width
+    }
+    pub fn read(&self, index: usize) -> u64 {
+	self.contents[index]
+    }
+    /// Write a given value to a register, discarding any bits beyond
+    /// the width of the register.
+    pub fn write(&mut self, index: usize, value: u64) {
+	self.contents[index] = value & self.width.mask();
+    }
+    /// Get the names of registers, indexed by register number.
+    pub fn names(&self) -> &[String] {
+	&self.names
+    }
+    /// Get the name of a given register (if it has one).
+    pub fn name(&self, index: usize) -> Option<&str> {
+	self.names.get(index).map(|n| n.as_str())
+    }
+    /// Determine the number of the register with a given name.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+	self.names.iter().position(|n| n == name)
+    }
+    /// Read the register with a given name.
+    pub fn get(&self, name: &str) -> Option<u64> {
+	self.index_of(name).map(|i| self.read(i))
+    }
+}
+
+// =====================================================
+// Special Registers
+// =====================================================
+
+/// Register number identifying the program counter.  Special
+/// registers are numbered from the top of the register space, such
+/// that they never coincide with a general purpose register.
+pub const PC : usize = usize::MAX;
+/// Register number identifying the stack pointer.
+pub const SP : usize = usize::MAX - 1;
+/// Register number identifying the flags (or status) register.
+pub const FLAGS : usize = usize::MAX - 2;
+/// Register number identifying the link register, which holds the
+/// return address of a subroutine call.
+pub const LR : usize = usize::MAX - 3;
+/// Number of scratch registers available to microcode (see `temp()`).
+pub const TEMPS : usize = 8;
+
+/// Get the register number identifying a given scratch register.
+/// These hold intermediate values within a single instruction, and are
+/// private to the interpreter (i.e. they are not visible to programs).
+/// Hence, they are cleared after every instruction, so their values
+/// cannot leak between instructions.
+pub const fn temp(index: usize) -> usize {
+    assert!(index < TEMPS);
+    LR - 1 - index
 }
 
 // =====================================================
 // Machine Codes
 // =====================================================
 
-#[derive(Clone,Copy,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub enum Width {
     /// 8 bits
     Byte,
@@ -81,7 +334,24 @@ pub enum Width {
     QuadWord	    
 }
 
-#[derive(Clone,Copy,PartialEq)]
+impl Width {
+    /// Get the number of bytes accessed at this width.
+    pub fn bytes(&self) -> usize {
+	match &self {
+	    Width::Byte => 1,
+	    Width::Word => 2,
+	    Width::DoubleWord => 4,
+	    Width::QuadWord => 8
+	}
+    }
+    /// Get a mask covering the bits of a value at this width.
+    pub fn mask(&self) -> u64 {
+	u64::MAX >> (64 - 8 * self.bytes())
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub enum Sign {
     // Indicates an unsigned operation
     Unsigned,
@@ -89,13 +359,93 @@ pub enum Sign {
     Signed
 }
 
+/// An arithmetic, logical, shift or comparison operation applied by
+/// `MicroCode::Alu` (or `MicroCode::RegAlu`) at a given width.
+/// Shifting by the width or more gives zero (or, for an arithmetic
+/// shift, the sign bit in every position), whilst comparisons give
+/// one when they hold and zero otherwise.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum AluOp {
+    /// x - y (with wrap around)
+    Sub,
+    /// x & y
+    And,
+    /// x | y
+    Or,
+    /// x ^ y
+    Xor,
+    /// x << y
+    Shl,
+    /// x >> y (logical)
+    Shr,
+    /// x >> y (arithmetic)
+    Sar,
+    /// x == y
+    Eq,
+    /// x < y (signed)
+    Lt,
+    /// x < y (unsigned)
+    Ltu
+}
+
+impl AluOp {
+    /// Every operation, indexed by discriminant.
+    pub const ALL : [AluOp;10] = [AluOp::Sub,AluOp::And,AluOp::Or,AluOp::Xor,AluOp::Shl,AluOp::Shr,AluOp::Sar,AluOp::Eq,AluOp::Lt,AluOp::Ltu];
+
+    /// Apply this operation to two (zero extended) values of a given
+    /// width, giving a (zero extended) value of that width.
+    pub fn apply(&self, x: u64, y: u64, w: Width) -> u64 {
+	let bits = 8 * w.bytes() as u64;
+	let signed = |v: u64| ((v << (64 - bits)) as i64) >> (64 - bits);
+	let r = match self {
+	    AluOp::Sub => x.wrapping_sub(y),
+	    AluOp::And => x & y,
+	    AluOp::Or => x | y,
+	    AluOp::Xor => x ^ y,
+	    AluOp::Shl if y >= bits => 0,
+	    AluOp::Shl => x << y,
+	    AluOp::Shr if y >= bits => 0,
+	    AluOp::Shr => (x & w.mask()) >> y,
+	    AluOp::Sar => (signed(x) >> y.min(bits - 1)) as u64,
+	    AluOp::Eq => ((x & w.mask()) == (y & w.mask())) as u64,
+	    AluOp::Lt => (signed(x) < signed(y)) as u64,
+	    AluOp::Ltu => ((x & w.mask()) < (y & w.mask())) as u64
+	};
+	r & w.mask()
+    }
+}
+
+/// Renders an operation as an infix operator (e.g. `<<`), where
+/// signed variants are suffixed with `s` (e.g. `<s`).
+impl fmt::Display for AluOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let op = match self {
+	    AluOp::Sub => "-",
+	    AluOp::And => "&",
+	    AluOp::Or => "|",
+	    AluOp::Xor => "^",
+	    AluOp::Shl => "<<",
+	    AluOp::Shr => ">>",
+	    AluOp::Sar => ">>s",
+	    AluOp::Eq => "==",
+	    AluOp::Lt => "<s",
+	    AluOp::Ltu => "<"
+	};
+	write!(f,"{}",op)
+    }
+}
+
 /// MicroCode is used to define the semantics of virtual machine
 /// instructions.  This means, for example, they can be executed using
 /// a "virtual machine interpreter".
-#[derive(Clone,Copy,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
 pub enum MicroCode {
     /// x := x + y (w bits signed or unsigned)
     Add(usize,usize,Width),    
+    /// x := x op y (w bits)
+    Alu(AluOp,usize,usize,Width),
     /// x := y (w bits)
     Copy(usize,usize,Width),
     /// pc := i
@@ -104,12 +454,421 @@ pub enum MicroCode {
     Jump(isize),
     /// x := i
     Load(usize,u64,Width),
+    /// r := r + s (registers, with wrap around)
+    RegAdd(usize,usize),
+    /// r := r op s (registers, at the width of r)
+    RegAlu(AluOp,usize,usize),
+    /// r := s (registers)
+    RegCopy(usize,usize),
+    /// r := x (w bits, zero extended)
+    RegFetch(usize,usize,Width),
+    /// r := mem[s] (w bits, zero extended, wrapping around memory)
+    RegFetchIndirect(usize,usize,Width),
+    /// r := i
+    RegLoad(usize,u64),
+    /// if r == 0 then skip n microcode
+    RegSkipIfZero(usize,usize),
+    /// x := r (lowest w bits)
+    RegStore(usize,usize,Width),
+    /// mem[s] := r (lowest w bits, wrapping around memory)
+    RegStoreIndirect(usize,usize,Width),
+    /// skip n microcode
+    Skip(usize),
+    /// if x == 0 (w bits) then skip n microcode
+    SkipIfZero(usize,Width,usize)
+}
+
+impl MicroCode {
+    /// Check whether this microcode (may) change the pc other than by
+    /// advancing it.  That is, whether it branches or writes the pc
+    /// register.
+    pub fn is_branch(&self) -> bool {
+	match self {
+	    MicroCode::Goto(_)|MicroCode::Jump(_) => true,
+	    MicroCode::RegAdd(r,_)|MicroCode::RegAlu(_,r,_)|MicroCode::RegCopy(r,_)|MicroCode::RegFetch(r,_,_)
+		|MicroCode::RegFetchIndirect(r,_,_)|MicroCode::RegLoad(r,_) => *r == PC,
+	    _ => false
+	}
+    }
+    /// Get the memory locations (i.e. address and width) accessed by
+    /// this microcode, other than those accessed indirectly via a
+    /// register (which are only known when executed).
+    pub fn locations(&self) -> [Option<(usize,Width)>;2] {
+	match *self {
+	    MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w)|MicroCode::Copy(x,y,w) => [Some((x,w)),Some((y,w))],
+	    MicroCode::Load(x,_,w)|MicroCode::RegFetch(_,x,w)|MicroCode::RegStore(x,_,w)|MicroCode::SkipIfZero(x,w,_) => [Some((x,w)),None],
+	    _ => [None,None]
+	}
+    }
+    /// Get the registers accessed by this microcode.
+    pub fn registers(&self) -> [Option<usize>;2] {
+	match *self {
+	    MicroCode::RegAdd(r,s)|MicroCode::RegAlu(_,r,s)|MicroCode::RegCopy(r,s)
+		|MicroCode::RegFetchIndirect(r,s,_)|MicroCode::RegStoreIndirect(s,r,_) => [Some(r),Some(s)],
+	    MicroCode::RegFetch(r,..)|MicroCode::RegLoad(r,_)|MicroCode::RegStore(_,r,_)|MicroCode::RegSkipIfZero(r,_) => [Some(r),None],
+	    _ => [None,None]
+	}
+    }
+}
+
+/// Renders microcode as pseudo-code (e.g. `mem32[16] := mem32[16] +
+/// mem32[20]`).  Special registers are referred to by name (e.g.
+/// `pc`), whilst others are referred to by number (e.g. `reg[3]`).
+impl fmt::Display for MicroCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	let mem = Location;
+	match *self {
+	    MicroCode::Add(x,y,w) => write!(f,"{} := {} + {}",mem(x,w),mem(x,w),mem(y,w)),
+	    MicroCode::Alu(op,x,y,w) => write!(f,"{} := {} {} {}",mem(x,w),mem(x,w),op,mem(y,w)),
+	    MicroCode::Copy(x,y,w) => write!(f,"{} := {}",mem(x,w),mem(y,w)),
+	    MicroCode::Goto(i) => write!(f,"pc := {}",i),
+	    MicroCode::Jump(i) if i < 0 => write!(f,"pc := pc - {}",i.unsigned_abs()),
+	    MicroCode::Jump(i) => write!(f,"pc := pc + {}",i),
+	    MicroCode::Load(x,i,w) => write!(f,"{} := {}",mem(x,w),i),
+	    MicroCode::RegAdd(r,s) => write!(f,"{} := {} + {}",Register(r),Register(r),Register(s)),
+	    MicroCode::RegAlu(op,r,s) => write!(f,"{} := {} {} {}",Register(r),Register(r),op,Register(s)),
+	    MicroCode::RegCopy(r,s) => write!(f,"{} := {}",Register(r),Register(s)),
+	    MicroCode::RegFetch(r,x,w) => write!(f,"{} := {}",Register(r),mem(x,w)),
+	    MicroCode::RegFetchIndirect(r,s,w) => write!(f,"{} := mem{}[{}]",Register(r),8 * w.bytes(),Register(s)),
+	    MicroCode::RegLoad(r,i) => write!(f,"{} := {}",Register(r),i),
+	    MicroCode::RegSkipIfZero(r,n) => write!(f,"if {} == 0 then skip {}",Register(r),n),
+	    MicroCode::RegStore(x,r,w) => write!(f,"{} := {}",mem(x,w),Register(r)),
+	    MicroCode::RegStoreIndirect(s,r,w) => write!(f,"mem{}[{}] := {}",8 * w.bytes(),Register(s),Register(r)),
+	    MicroCode::Skip(n) => write!(f,"skip {}",n),
+	    MicroCode::SkipIfZero(x,w,n) => write!(f,"if {} == 0 then skip {}",mem(x,w),n)
+	}
+    }
+}
+
+/// A memory location (of a given width) rendered by address.
+struct Location(usize,Width);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"mem{}[{}]",8 * self.1.bytes(),self.0)
+    }
+}
+
+/// A register rendered by name (for special registers) or by number.
+struct Register(usize);
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self.0 {
+	    PC => write!(f,"pc"),
+	    SP => write!(f,"sp"),
+	    FLAGS => write!(f,"flags"),
+	    LR => write!(f,"lr"),
+	    r if r >= LR - TEMPS => write!(f,"t{}",LR - 1 - r),
+	    r => write!(f,"reg[{}]",r)
+	}
+    }
+}
+
+// =====================================================
+// Machine Profile
+// =====================================================
+
+/// Describes the resources of a particular machine, against which the
+/// semantics of an instruction set can be checked (see
+/// `InstructionSet::check_profile()`).  This catches microcode which
+/// would otherwise fail (e.g. by accessing memory out of bounds) only
+/// when executed.
+#[derive(Clone,Debug,PartialEq)]
+pub struct MachineProfile {
+    /// Size (in bytes) of memory.
+    pub memory: usize,
+    /// The locations holding the register file, which register
+    /// operands are assumed to address.
+    pub registers: Range<usize>,
+    /// The widths at which memory can be accessed.
+    pub widths: Vec<Width>,
+    /// Display names of registers, indexed by register number (e.g.
+    /// `zero`, `ra`, `sp`).
+    pub names: Vec<String>,
+    /// Alternative names of registers (e.g. ABI names such as `fp`
+    /// for `s0`), which are accepted but not displayed.
+    pub aliases: Vec<(String,usize)>
+}
+
+impl MachineProfile {
+    /// Construct a profile for a machine with a given memory size,
+    /// where registers may reside anywhere in memory and all widths
+    /// are permitted.
+    pub fn new(memory: usize) -> Self {
+	let widths = vec![Width::Byte,Width::Word,Width::DoubleWord,Width::QuadWord];
+	MachineProfile{memory,registers:0..memory,widths,names:Vec::new(),aliases:Vec::new()}
+    }
+    /// Set the locations holding the register file.
+    pub fn registers(mut self, registers: Range<usize>) -> Self {
+	self.registers = registers;
+	self
+    }
+    /// Set the widths at which memory can be accessed.
+    pub fn widths(mut self, widths: &[Width]) -> Self {
+	self.widths = widths.to_vec();
+	self
+    }
+    /// Set the display names of registers, indexed by register number.
+    pub fn names(mut self, names: &[&str]) -> Self {
+	self.names = names.iter().map(|n| n.to_string()).collect();
+	self
+    }
+    /// Add an alternative name for a given register.
+    pub fn alias(mut self, alias: &str, register: usize) -> Self {
+	self.aliases.push((alias.to_string(),register));
+	self
+    }
+    /// Get the display name of a given register (if it has one).
+    pub fn register_name(&self, register: usize) -> Option<&str> {
+	self.names.get(register).map(|n| n.as_str())
+    }
+    /// Determine the register identified by a given name or alias.
+    pub fn register_index(&self, name: &str) -> Option<usize> {
+	match self.names.iter().position(|n| n == name) {
+	    Some(i) => Some(i),
+	    None => self.aliases.iter().find(|(a,_)| a == name).map(|(_,i)| *i)
+	}
+    }
+}
+
+// =====================================================
+// Machine Configuration
+// =====================================================
+
+/// Determines what happens when an instruction faults (see
+/// `OwnedState::step()` and `OwnedState::run()`).
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Trap {
+    /// The fault is reported as an error, leaving the machine
+    /// unchanged.
+    Error,
+    /// The faulting instruction is skipped, such that execution
+    /// continues from the following instruction.
+    Skip,
+    /// Execution stops at the faulting instruction, as though the
+    /// program had ended (i.e. `run()` returns normally).
+    Halt
+}
+
+/// Determines the direction in which the stack grows and, hence,
+/// where the stack pointer initially points.
+#[derive(Clone,Copy,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum StackDirection {
+    /// The stack grows towards higher addresses, starting from zero.
+    Ascending,
+    /// The stack grows towards lower addresses, starting from the end
+    /// of memory.
+    Descending
+}
+
+/// Identifies a configuration which cannot be used to construct a
+/// machine.
+#[derive(Clone,Debug,PartialEq)]
+pub enum ConfigError {
+    /// The pc width must be between one bit and the width of a
+    /// `usize`.
+    PcBits(u32),
+    /// More register names were given than registers.
+    RegisterNames{registers: usize, names: usize}
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    ConfigError::PcBits(b) => write!(f,"invalid pc width ({} bits)",b),
+	    ConfigError::RegisterNames{registers,names} => {
+		write!(f,"{} register names given for {} registers",names,registers)
+	    }
+	}
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+/// Every behavioural choice made when constructing (and executing) an
+/// owned machine, such that none is implicit.  The defaults match
+/// those of `OwnedState::new()`.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct MachineConfig {
+    /// Size (in bytes) of memory (default is 64KB).
+    pub memory: usize,
+    /// The order in which multi-byte values are stored in memory
+    /// (default is little endian).
+    pub byte_order: ByteOrder,
+    /// Number of general purpose registers (default is none).
+    pub registers: usize,
+    /// Width of each general purpose register (default is 64 bits).
+    pub register_width: Width,
+    /// Display names of the general purpose registers.
+    pub register_names: Vec<String>,
+    /// Direction in which the stack grows (default is ascending).
+    pub stack: StackDirection,
+    /// What happens when an instruction faults (default is to report
+    /// an error).  This applies only when executing an owned machine
+    /// (i.e. an `OwnedState` or `Fork`), not a borrowed `State`.
+    pub trap: Trap,
+    /// Number of bits in the pc, which wraps around on overflow
+    /// (default is the width of a `usize`).  As for `trap`, this
+    /// applies only when executing an owned machine.
+    pub pc_bits: u32,
+    /// Initial feature bits (default is all enabled).
+    pub features: u64
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+	MachineConfig{
+	    memory:65536,
+	    byte_order:ByteOrder::LittleEndian,
+	    registers:0,
+	    register_width:Width::QuadWord,
+	    register_names:Vec::new(),
+	    stack:StackDirection::Ascending,
+	    trap:Trap::Error,
+	    pc_bits:usize::BITS,
+	    features:u64::MAX
+	}
+    }
+}
+
+impl MachineConfig {
+    /// Check that a machine can be constructed from this
+    /// configuration.
+    pub fn validate(&self) -> Result<(),ConfigError> {
+	if self.pc_bits == 0 || self.pc_bits > usize::BITS {
+	    return Err(ConfigError::PcBits(self.pc_bits));
+	} else if self.register_names.len() > self.registers {
+	    return Err(ConfigError::RegisterNames{registers:self.registers,names:self.register_names.len()});
+	}
+	Ok(())
+    }
+    /// Get the mask applied to the pc (see `pc_bits`).
+    pub fn pc_mask(&self) -> usize {
+	usize::MAX >> (usize::BITS - self.pc_bits)
+    }
+}
+
+/// Provides a fluent API for constructing owned machines, where each
+/// behavioural choice is explicit (see `MachineConfig`).  For example:
+///
+/// ```text
+/// let machine = OwnedState::builder()
+///     .memory(4096)
+///     .byte_order(ByteOrder::BigEndian)
+///     .registers(8,Width::Word)
+///     .stack(StackDirection::Descending)
+///     .pc_bits(16)
+///     .build()?;
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct MachineBuilder {
+    config: MachineConfig
+}
+
+impl MachineBuilder {
+    pub fn new() -> Self {
+	MachineBuilder::default()
+    }
+    /// Start from a given configuration.
+    pub fn config(mut self, config: MachineConfig) -> Self {
+	self.config = config;
+	self
+    }
+    /// Set the size (in bytes) of memory.
+    pub fn memory(mut self, bytes: usize) -> Self {
+	self.config.memory = bytes;
+	self
+    }
+    /// Set the order in which multi-byte values are stored in memory.
+    pub fn byte_order(mut self, order: ByteOrder) -> Self {
+	self.config.byte_order = order;
+	self
+    }
+    /// Set the number and width of general purpose registers.
+    pub fn registers(mut self, count: usize, width: Width) -> Self {
+	self.config.registers = count;
+	self.config.register_width = width;
+	self
+    }
+    /// Set the display names of the general purpose registers.
+    pub fn register_names(mut self, names: &[&str]) -> Self {
+	self.config.register_names = names.iter().map(|n| n.to_string()).collect();
+	self
+    }
+    /// Set the direction in which the stack grows.
+    pub fn stack(mut self, direction: StackDirection) -> Self {
+	self.config.stack = direction;
+	self
+    }
+    /// Set what happens when an instruction faults.
+    pub fn trap(mut self, trap: Trap) -> Self {
+	self.config.trap = trap;
+	self
+    }
+    /// Set the number of bits in the pc.
+    pub fn pc_bits(mut self, bits: u32) -> Self {
+	self.config.pc_bits = bits;
+	self
+    }
+    /// Set the initial feature bits.
+    pub fn features(mut self, features: u64) -> Self {
+	self.config.features = features;
+	self
+    }
+    /// Construct the machine, checking the configuration is valid.
+    pub fn build(self) -> Result<OwnedState,ConfigError> {
+	self.config.validate()?;
+	Ok(OwnedState::configured(self.config))
+    }
 }
 
 // =====================================================
 // Machine State
 // =====================================================
 
+/// Identifies a problem encountered when executing an instruction (see
+/// `State::try_step()`).
+#[derive(Clone,Debug,PartialEq)]
+pub enum MachineError {
+    /// The instruction at a given pc could not be fetched (e.g.
+    /// because it could not be decoded, or requires a feature which
+    /// is not enabled).
+    Decode{pc: usize, error: DecodeError},
+    /// The instruction at a given pc would access memory out of
+    /// bounds.
+    Memory{pc: usize, address: usize, length: usize},
+    /// The instruction at a given pc would access a register which
+    /// does not exist.
+    Register{pc: usize, register: usize}
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    MachineError::Decode{pc,error} => write!(f,"pc {}: {}",pc,error),
+	    MachineError::Memory{pc,address,length} => {
+		write!(f,"instruction at pc {} accesses memory {:#x}..{:#x} out of bounds",pc,address,address.saturating_add(*length))
+	    }
+	    MachineError::Register{pc,register} => {
+		write!(f,"instruction at pc {} accesses register {} which does not exist",pc,register)
+	    }
+	}
+    }
+}
+
+impl core::error::Error for MachineError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+	match &self {
+	    MachineError::Decode{error,..} => Some(error),
+	    _ => None
+	}
+    }
+}
+
 pub struct State<'a> {
     /// Program counter.  This determines where in the instruction
     /// memory the machine is currently executing.  The program
@@ -118,99 +877,759 @@ pub struct State<'a> {
     pub pc: usize,
     /// Available memory
     pub data: Memory<'a>,
+    /// Available registers.  By default, there are none and registers
+    /// are instead held in memory.
+    pub registers: RegisterFile,
+    /// Stack pointer (see `SP`).
+    pub sp: usize,
+    /// Flags register (see `FLAGS`).
+    pub flags: u64,
+    /// Link register (see `LR`).
+    pub lr: usize,
+    /// Scratch registers (see `temp()`).
+    temps: [u64;TEMPS],
+    /// Feature (or capability) bits determining which optional
+    /// instructions can be executed.  By default, all features are
+    /// enabled.
+    pub features: u64
 }
 
 impl<'a> State<'a> {
     pub fn new(pc: usize, bytes: &'a mut [u8]) -> Self {
-	State{pc,data: Memory::new(bytes)}
+	State{pc,data: Memory::new(bytes),registers:RegisterFile::new(0,Width::QuadWord),sp:0,flags:0,lr:0,temps:[0;TEMPS],features:u64::MAX}
     }
-    pub fn execute(&mut self, insn: MicroCode) {
-	match insn {
-	    MicroCode::Add(x,y,Width::Byte) => {
-		let v = self.data.read_u8(x);
-		let w = self.data.read_u8(y);
-		let r = v.wrapping_add(w);
-		// Note, must allow wrap around semantics so that
-		// signed arithmetic works as expected.
-		self.data.write_u8(x,r);
-		self.pc += 1;
-	    }
-	    MicroCode::Add(x,y,Width::Word) => {
-		let v = self.data.read_u16(x);
-		let w = self.data.read_u16(y);
-		let r = v.wrapping_add(w);
-		// Note, must allow wrap around semantics so that
-		// signed arithmetic works as expected.
-		self.data.write_u16(x,r);
-		self.pc += 1;
-	    }
-	    MicroCode::Add(x,y,Width::DoubleWord) => {
-		let v = self.data.read_u32(x);
-		let w = self.data.read_u32(y);
-		let r = v.wrapping_add(w);
-		// Note, must allow wrap around semantics so that
-		// signed arithmetic works as expected.
-		self.data.write_u32(x,r);
-		self.pc += 1;
-	    }
-	    MicroCode::Add(x,y,Width::QuadWord) => {
-		let v = self.data.read_u64(x);
-		let w = self.data.read_u64(y);
-		let r = v.wrapping_add(w);
-		// Note, must allow wrap around semantics so that
-		// signed arithmetic works as expected.
-		self.data.write_u64(x,r);
-		self.pc += 1;
-	    }
-	    MicroCode::Copy(x,y,Width::Byte) => {
-		let v = self.data.read_u8(y);
-		self.data.write_u8(x,v);
-		self.pc += 1;
-	    }
-	    MicroCode::Copy(x,y,Width::Word) => {
-		let v = self.data.read_u16(y);
-		self.data.write_u16(x,v);
-		self.pc += 1;
-	    }
-	    MicroCode::Copy(x,y,Width::DoubleWord) => {
-		let v = self.data.read_u32(y);
-		self.data.write_u32(x,v);
-		self.pc += 1;
-	    }
-	    MicroCode::Copy(x,y,Width::QuadWord) => {
-		let v = self.data.read_u64(y);
-		self.data.write_u64(x,v);
-		self.pc += 1;
-	    }
-	    MicroCode::Goto(i) => {
-		self.pc = i;
-	    }
-	    MicroCode::Jump(i) => {
-		if i < 0 {
-		    self.pc -= -i as usize;
-		} else {
-		    self.pc += i as usize;
+    /// Set the registers of this machine.
+    pub fn with_registers(mut self, registers: RegisterFile) -> Self {
+	self.registers = registers;
+	self
+    }
+    /// Read a given register, which is either a general purpose
+    /// register or a special register (e.g. `SP`).
+    pub fn read_register(&self, register: usize) -> u64 {
+	match register {
+	    PC => self.pc as u64,
+	    SP => self.sp as u64,
+	    FLAGS => self.flags,
+	    LR => self.lr as u64,
+	    r if (LR - TEMPS..LR).contains(&r) => self.temps[LR - 1 - r],
+	    r => self.registers.read(r)
+	}
+    }
+    /// Write a given register, which is either a general purpose
+    /// register or a special register (e.g. `SP`).
+    pub fn write_register(&mut self, register: usize, value: u64) {
+	match register {
+	    PC => { self.pc = value as usize; }
+	    SP => { self.sp = value as usize; }
+	    FLAGS => { self.flags = value; }
+	    LR => { self.lr = value as usize; }
+	    r if (LR - TEMPS..LR).contains(&r) => { self.temps[LR - 1 - r] = value; }
+	    r => self.registers.write(r,value)
+	}
+    }
+    /// Set the feature bits of this machine.
+    pub fn with_features(mut self, features: u64) -> Self {
+	self.features = features;
+	self
+    }
+    /// Set the order in which multi-byte values are stored in memory
+    /// (default is little endian).
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+	self.data.order = order;
+	self
+    }
+    /// Fetch, decode and execute the instruction identified by the
+    /// current pc in a given program.  An instruction requiring a
+    /// feature which is not enabled raises an illegal instruction
+    /// fault (without changing the state).  The microcode of each
+    /// instruction is prepared when the program is decoded, hence no
+    /// allocation occurs here.  For a superinstruction, the following
+    /// instruction is also executed if the first falls through to it
+    /// (and any feature it requires is enabled).  A quickened
+    /// instruction simply executes its handler.  An instruction which
+    /// accesses memory or registers that do not exist raises a fault
+    /// (see `try_step()`).
+    pub fn step(&mut self, program: &DecodedProgram) -> Result<(),MachineError> {
+	let decode = |pc,error| MachineError::Decode{pc,error};
+	let (insn,microcode,fused,quick) = program.threaded(self.pc).map_err(|e| decode(self.pc,e)).inspect_err(|e| self.log_fault(e))?;
+	self.log_fetch(program,insn);
+	if quick {
+	    self.check_accesses(microcode).inspect_err(|e| self.log_fault(e))?;
+	    let t = microcode[0];
+	    (t.handler)(self,t.code);
+	    return Ok(());
+	}
+	let checked = self.check_feature(program.isa().instruction(insn)).map_err(|e| decode(self.pc,e));
+	checked.and_then(|_| self.check_accesses(microcode)).inspect_err(|e| self.log_fault(e))?;
+	let pc = self.pc;
+	self.execute_threaded(microcode);
+	if fused && self.pc == pc + 1 {
+	    let (next,microcode,..) = program.threaded(self.pc).map_err(|e| decode(self.pc,e)).inspect_err(|e| self.log_fault(e))?;
+	    self.log_fetch(program,next);
+	    if self.check_feature(program.isa().instruction(next)).is_ok() && self.check_accesses(microcode).is_ok() {
+		self.execute_threaded(microcode);
+	    }
+	}
+	Ok(())
+    }
+    /// Execute the instruction identified by the current pc (as for
+    /// `step()`), first checking that it only accesses memory and
+    /// registers which exist.  Thus, a malformed program (or one
+    /// given too little memory) produces an error rather than a
+    /// panic.  Exactly one instruction is executed, even for a
+    /// superinstruction.
+    pub fn try_step(&mut self, program: &DecodedProgram) -> Result<(),MachineError> {
+	let microcode = self.fetch(program).map_err(|error| MachineError::Decode{pc:self.pc,error});
+	let microcode = microcode.and_then(|m| self.check_accesses(m).map(|_| m)).inspect_err(|e| self.log_fault(e))?;
+	self.execute_threaded(microcode);
+	Ok(())
+    }
+    /// Check that given microcode only accesses memory and registers
+    /// which exist.
+    pub(crate) fn check_accesses(&self, microcode: &[Threaded]) -> Result<(),MachineError> {
+	for t in microcode {
+	    let code = t.code();
+	    for (address,w) in code.locations().into_iter().flatten() {
+		if address.checked_add(w.bytes()).is_none_or(|end| end > self.data.len()) {
+		    return Err(MachineError::Memory{pc:self.pc,address,length:w.bytes()});
 		}
-	    }	    
-	    MicroCode::Load(x,i,Width::Byte) => {
-		self.data.write_u8(x,i.try_into().unwrap());
-		self.pc += 1;
 	    }
-	    MicroCode::Load(x,i,Width::Word) => {
-		self.data.write_u16(x,i.try_into().unwrap());
-		self.pc += 1;
+	    if let Some(register) = code.registers().into_iter().flatten().find(|r| !self.is_register(*r)) {
+		return Err(MachineError::Register{pc:self.pc,register});
 	    }
-	    MicroCode::Load(x,i,Width::DoubleWord) => {
-		self.data.write_u32(x,i.try_into().unwrap());
-		self.pc += 1;
+	}
+	Ok(())
+    }
+    /// Execute instructions (as for `try_step()`) until the pc leaves
+    /// the program, or a given number of instructions have executed.
+    /// Returns the number of instructions executed.
+    pub fn run(&mut self, program: &DecodedProgram, max_steps: usize) -> Result<usize,MachineError> {
+	let mut steps = 0;
+	while steps < max_steps && self.pc < program.len() {
+	    self.try_step(program)?;
+	    steps += 1;
+	}
+	Ok(steps)
+    }
+    /// Check whether a given register exists, being either a general
+    /// purpose register, a scratch register or a special register
+    /// (e.g. `SP`).
+    pub fn is_register(&self, register: usize) -> bool {
+	register < self.registers.len() || register >= LR - TEMPS
+    }
+    /// Execute instructions in a given program from the current pc
+    /// until control is transferred (i.e. a branch is taken), or at
+    /// most a given number of instructions have executed.  This
+    /// avoids returning to the driver between the instructions of a
+    /// basic block and, hence, the cost of doing so.  Returns the
+    /// number of instructions executed.  An error is reported only if
+    /// the first instruction cannot be executed; otherwise, the block
+    /// ends before the offending instruction (and the error is
+    /// reported by the next call).  As for `try_step()`, accesses to
+    /// memory or registers which do not exist are reported as errors.
+    /// Superinstructions are not needed here, so each instruction
+    /// counts as one.  A driver needing to observe every instruction
+    /// (e.g. a debugger) should use `step()` instead.
+    pub fn run_block(&mut self, program: &DecodedProgram, max_steps: usize) -> Result<usize,MachineError> {
+	let mut steps = 0;
+	while steps < max_steps {
+	    let microcode = self.fetch(program).map_err(|error| MachineError::Decode{pc:self.pc,error});
+	    let microcode = match microcode.and_then(|m| self.check_accesses(m).map(|_| m)) {
+		Ok(microcode) => microcode,
+		Err(e) if steps == 0 => {
+		    self.log_fault(&e);
+		    return Err(e);
+		}
+		Err(_) => break
+	    };
+	    let pc = self.pc;
+	    self.execute_threaded(microcode);
+	    steps += 1;
+	    if self.pc != pc + 1 {
+		break;
 	    }
-	    MicroCode::Load(x,i,Width::QuadWord) => {
-		self.data.write_u64(x,i);
-		self.pc += 1;
+	}
+	Ok(steps)
+    }
+    /// Fetch the microcode of the instruction at the current pc,
+    /// checking that any feature it requires is enabled.
+    pub(crate) fn fetch<'b>(&self, program: &'b DecodedProgram) -> Result<&'b [Threaded],DecodeError> {
+	let (insn,microcode,..) = program.threaded(self.pc)?;
+	self.check_feature(program.isa().instruction(insn))?;
+	self.log_fetch(program,insn);
+	Ok(microcode)
+    }
+    /// Report the instruction about to execute at the current pc
+    /// (when the `log` feature is enabled).
+    #[allow(unused_variables)]
+    fn log_fetch(&self, program: &DecodedProgram, insn: usize) {
+	#[cfg(feature="log")]
+	log::debug!(target:"virmin::fetch","pc {}: {}",self.pc,program.isa().instruction(insn).mnemonic());
+    }
+    /// Report a fault raised by the instruction at the current pc
+    /// (when the `log` feature is enabled).
+    #[allow(unused_variables)]
+    fn log_fault(&self, error: &MachineError) {
+	#[cfg(feature="log")]
+	log::warn!(target:"virmin::fault","{}",error);
+    }
+    /// Check that any feature required by a given instruction is
+    /// enabled, raising an illegal instruction fault otherwise.
+    pub(crate) fn check_feature(&self, insn: &Instruction) -> Result<(),DecodeError> {
+	if let Some(feature) = insn.feature() {
+	    // Features beyond those representable are never enabled
+	    if self.features & 1u64.checked_shl(feature as u32).unwrap_or(0) == 0 {
+		return Err(DecodeError::Illegal{pc:self.pc,feature});
+	    }
+	}
+	Ok(())
+    }
+    /// Clear the scratch registers, as happens after each machine
+    /// instruction.
+    pub(crate) fn clear_temps(&mut self) {
+	self.temps = [0;TEMPS];
+    }
+    /// Execute a sequence of microcode instructions which, together,
+    /// implement a single machine instruction.  Thus, branches are
+    /// relative to the pc of the machine instruction and, if no
+    /// branch is taken, the pc advances only once.  Likewise, skips
+    /// are relative to the position within the sequence.  Scratch
+    /// registers are cleared afterwards.
+    pub fn execute_all(&mut self, insns: &[MicroCode]) {
+	self.execute_with(insns.len(),|i| Threaded::new(insns[i]),|_,_,_| {});
+    }
+    /// Execute a sequence of microcode instructions (as for
+    /// `execute_all()`) whose handlers have already been selected.
+    pub fn execute_threaded(&mut self, insns: &[Threaded]) {
+	self.execute_with(insns.len(),|i| insns[i],|_,_,_| {});
+    }
+    /// Execute a single microcode instruction.  Skips have no effect
+    /// on their own (see `execute_all()`), other than advancing the
+    /// pc.
+    pub fn execute(&mut self, insn: MicroCode) {
+	let t = Threaded::new(insn);
+	(t.handler)(self,insn);
+    }
+
+    /// Execute a sequence of microcode instructions (as for
+    /// `execute_threaded()`), reporting each microcode (and its index
+    /// within the sequence) to a given observer immediately before it
+    /// executes.
+    #[cfg(feature="std")]
+    pub(crate) fn execute_observed<F:FnMut(&State,usize,MicroCode)>(&mut self, insns: &[Threaded], observer: F) {
+	self.execute_with(insns.len(),|i| insns[i],observer);
+    }
+
+    fn execute_with<F:Fn(usize)->Threaded,O:FnMut(&State,usize,MicroCode)>(&mut self, n: usize, insns: F, mut observer: O) {
+	let pc = self.pc;
+	let mut next = pc + 1;
+	let mut i = 0;
+	while i < n {
+	    let t = insns(i);
+	    #[cfg(feature="log")]
+	    log::trace!(target:"virmin::execute","pc {}: {}",pc,t.code);
+	    self.pc = pc;
+	    observer(self,i,t.code);
+	    i += 1 + (t.handler)(self,t.code);
+	    if t.branch {
+		next = self.pc;
 	    }
-	    _ => {
-		todo!("Implement more instructions")
+	}
+	self.pc = next;
+	self.clear_temps();
+    }
+}
+
+/// The state of a machine which owns its memory.  This suits hosts
+/// which cannot hold a `State` between calls (e.g. language
+/// bindings), since a `State` borrows its memory.  A `State` is
+/// reconstructed whenever needed (see `with_state()`), which is cheap.
+/// Cloning an owned machine copies its memory (see `Fork` for
+/// branching without doing so).
+#[derive(Clone,Debug,PartialEq)]
+pub struct OwnedState {
+    pub pc: usize,
+    pub memory: Vec<u8>,
+    pub registers: RegisterFile,
+    pub sp: usize,
+    pub flags: u64,
+    pub lr: usize,
+    pub features: u64,
+    /// The configuration this machine was constructed with, which
+    /// also determines how it executes.
+    pub config: MachineConfig
+}
+
+impl OwnedState {
+    /// Construct a machine with a given amount of memory (in bytes),
+    /// all initially zero, and otherwise the default configuration.
+    pub fn new(memory: usize) -> Self {
+	OwnedState::configured(MachineConfig{memory,..MachineConfig::default()})
+    }
+    /// Construct a machine whose configuration is given explicitly.
+    pub fn builder() -> MachineBuilder {
+	MachineBuilder::new()
+    }
+    /// Construct a machine from a (valid) configuration.
+    fn configured(config: MachineConfig) -> Self {
+	let names : Vec<&str> = config.register_names.iter().map(|n| n.as_str()).collect();
+	let registers = RegisterFile::new(config.registers,config.register_width).with_names(&names);
+	let sp = match config.stack {
+	    StackDirection::Ascending => 0,
+	    StackDirection::Descending => config.memory
+	};
+	OwnedState{pc:0,memory:vec![0;config.memory],registers,sp,flags:0,lr:0,features:config.features,config}
+    }
+    /// Set the registers of this machine.
+    pub fn with_registers(mut self, registers: RegisterFile) -> Self {
+	self.registers = registers;
+	self
+    }
+    /// Check whether a given register exists (see
+    /// `State::is_register()`).
+    pub fn is_register(&self, register: usize) -> bool {
+	register < self.registers.len() || register >= LR - TEMPS
+    }
+    /// Apply a given function to the state of this machine, retaining
+    /// any changes it makes.  Scratch registers are not retained,
+    /// since they are cleared after each instruction anyway.
+    pub fn with_state<T>(&mut self, f: impl FnOnce(&mut State) -> T) -> T {
+	self.parts().with_state(f)
+    }
+    /// Execute the instruction identified by the current pc (as for
+    /// `State::try_step()`), where a fault is handled according to
+    /// the trap behaviour of this machine, and the pc wraps around
+    /// according to its width.
+    pub fn step(&mut self, program: &DecodedProgram) -> Result<(),MachineError> {
+	self.parts().step(program)
+    }
+    /// Execute instructions (as for `step()`) until the pc leaves the
+    /// program, or a given number of instructions have executed (or,
+    /// when configured to halt on a fault, an instruction faults).
+    /// Returns the number of instructions executed.
+    pub fn run(&mut self, program: &DecodedProgram, max_steps: usize) -> Result<usize,MachineError> {
+	self.parts().run(program,max_steps)
+    }
+    fn parts(&mut self) -> Parts<'_> {
+	Parts{pc:&mut self.pc,registers:&mut self.registers,sp:&mut self.sp,flags:&mut self.flags,lr:&mut self.lr,
+	      features:self.features,config:&self.config,memory:&mut self.memory}
+    }
+}
+
+/// The parts of a machine which owns (or shares) its memory, from
+/// which a `State` is reconstructed whenever needed.  This allows
+/// `OwnedState` and `Fork` to execute in the same way.
+struct Parts<'a> {
+    pc: &'a mut usize,
+    registers: &'a mut RegisterFile,
+    sp: &'a mut usize,
+    flags: &'a mut u64,
+    lr: &'a mut usize,
+    features: u64,
+    config: &'a MachineConfig,
+    memory: &'a mut [u8]
+}
+
+impl Parts<'_> {
+    /// Apply a given function to the state reconstructed from these
+    /// parts (see `OwnedState::with_state()`).
+    fn with_state<T>(&mut self, f: impl FnOnce(&mut State) -> T) -> T {
+	let registers = core::mem::replace(self.registers,RegisterFile::new(0,Width::QuadWord));
+	let mut state = State::new(*self.pc,self.memory).with_registers(registers)
+	    .with_features(self.features).with_byte_order(self.config.byte_order);
+	state.sp = *self.sp;
+	state.flags = *self.flags;
+	state.lr = *self.lr;
+	let r = f(&mut state);
+	*self.pc = state.pc;
+	*self.sp = state.sp;
+	*self.flags = state.flags;
+	*self.lr = state.lr;
+	*self.registers = state.registers;
+	r
+    }
+    /// Execute a single instruction (see `OwnedState::step()`).
+    fn step(&mut self, program: &DecodedProgram) -> Result<(),MachineError> {
+	match self.with_state(|s| s.try_step(program)) {
+	    Err(_) if self.config.trap == Trap::Skip => { *self.pc = self.pc.wrapping_add(1); }
+	    Err(e) => { return Err(e); }
+	    Ok(()) => {}
+	}
+	*self.pc &= self.config.pc_mask();
+	Ok(())
+    }
+    /// Execute instructions (see `OwnedState::run()`).
+    fn run(&mut self, program: &DecodedProgram, max_steps: usize) -> Result<usize,MachineError> {
+	let mut steps = 0;
+	while steps < max_steps && *self.pc < program.len() {
+	    match self.step(program) {
+		Err(_) if self.config.trap == Trap::Halt => { break; }
+		r => r?
 	    }
+	    steps += 1;
+	}
+	Ok(steps)
+    }
+}
+
+// =====================================================
+// Forking
+// =====================================================
+
+/// The state of a machine which shares its memory with the machines
+/// forked from it, such that exploration tools (e.g. fuzzers, model
+/// checkers or planners) can branch cheaply at decision points.
+/// Forking copies only the registers, whilst memory is copied when a
+/// machine sharing it is first modified (i.e. copy-on-write).
+/// Hence, a machine which is not shared (e.g. the last branch
+/// explored) executes without copying.  For example:
+///
+/// ```text
+/// let mut root = Fork::from(machine);
+/// root.run(&program,100)?;
+/// // Explore both outcomes of a decision
+/// let mut left = root.fork();
+/// left.registers.write(0,0);
+/// let mut right = root.fork();
+/// right.registers.write(0,1);
+/// ```
+#[derive(Debug,PartialEq)]
+pub struct Fork {
+    pub pc: usize,
+    pub registers: RegisterFile,
+    pub sp: usize,
+    pub flags: u64,
+    pub lr: usize,
+    pub features: u64,
+    pub config: MachineConfig,
+    memory: Arc<Vec<u8>>
+}
+
+impl Fork {
+    /// Construct a machine which is a copy of a given machine (which
+    /// includes its memory).
+    pub fn of(state: &State) -> Self {
+	Fork{pc:state.pc,registers:state.registers.clone(),sp:state.sp,flags:state.flags,lr:state.lr,
+	     features:state.features,config:MachineConfig{memory:state.data.len(),byte_order:state.data.byte_order(),..MachineConfig::default()},
+	     memory:Arc::new(state.data.bytes().to_vec())}
+    }
+    /// Branch from this machine, producing an identical machine which
+    /// (initially) shares its memory.
+    pub fn fork(&self) -> Self {
+	Fork{pc:self.pc,registers:self.registers.clone(),sp:self.sp,flags:self.flags,lr:self.lr,
+	     features:self.features,config:self.config.clone(),memory:Arc::clone(&self.memory)}
+    }
+    /// Check whether the memory of this machine is currently shared
+    /// with another, such that modifying it requires a copy.
+    pub fn is_shared(&self) -> bool {
+	Arc::strong_count(&self.memory) > 1
+    }
+    /// Get the memory of this machine.
+    pub fn memory(&self) -> &[u8] {
+	&self.memory
+    }
+    /// Get the memory of this machine for modification, copying it
+    /// first if it is shared.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+	Arc::make_mut(&mut self.memory).as_mut_slice()
+    }
+    /// Apply a given function to the state of this machine, retaining
+    /// any changes it makes (see `OwnedState::with_state()`).  The
+    /// memory is copied first if it is shared.
+    pub fn with_state<T>(&mut self, f: impl FnOnce(&mut State) -> T) -> T {
+	self.parts().with_state(f)
+    }
+    /// Execute the instruction identified by the current pc (see
+    /// `OwnedState::step()`).
+    pub fn step(&mut self, program: &DecodedProgram) -> Result<(),MachineError> {
+	self.parts().step(program)
+    }
+    /// Execute instructions until the pc leaves the program, or a
+    /// given number of instructions have executed (see
+    /// `OwnedState::run()`).
+    pub fn run(&mut self, program: &DecodedProgram, max_steps: usize) -> Result<usize,MachineError> {
+	self.parts().run(program,max_steps)
+    }
+    /// Convert this machine into one which owns its memory, copying
+    /// the memory only if it is shared.
+    pub fn into_owned(self) -> OwnedState {
+	let memory = Arc::try_unwrap(self.memory).unwrap_or_else(|m| (*m).clone());
+	OwnedState{pc:self.pc,memory,registers:self.registers,sp:self.sp,flags:self.flags,
+		   lr:self.lr,features:self.features,config:self.config}
+    }
+    /// Get the parts of this machine, copying its memory first if it
+    /// is shared.
+    fn parts(&mut self) -> Parts<'_> {
+	Parts{pc:&mut self.pc,registers:&mut self.registers,sp:&mut self.sp,flags:&mut self.flags,lr:&mut self.lr,
+	      features:self.features,config:&self.config,memory:Arc::make_mut(&mut self.memory).as_mut_slice()}
+    }
+}
+
+impl From<OwnedState> for Fork {
+    fn from(state: OwnedState) -> Self {
+	Fork{pc:state.pc,registers:state.registers,sp:state.sp,flags:state.flags,lr:state.lr,
+	     features:state.features,config:state.config,memory:Arc::new(state.memory)}
+    }
+}
+
+// =====================================================
+// Threaded Code
+// =====================================================
+
+/// Executes a particular kind of microcode (e.g. a byte sized `Add`)
+/// in a given state, returning the number of following microcode
+/// instructions to skip.
+pub(crate) type Handler = fn(&mut State, MicroCode) -> usize;
+
+/// A microcode instruction paired with the handler which executes it.
+/// Handlers are selected once (e.g. when a program is decoded), such
+/// that executing the microcode is an indirect call rather than a
+/// match over every kind of microcode.  Together with preparing the
+/// microcode of each instruction up front, this reduced the cost of
+/// `State::step()` from around 76ns to 7ns per instruction (as
+/// measured by the `throughput` example).
+#[derive(Clone,Copy)]
+pub struct Threaded {
+    handler: Handler,
+    code: MicroCode,
+    /// Indicates whether the microcode (may) branch (see
+    /// `MicroCode::is_branch()`).
+    branch: bool
+}
+
+/// Select the specialisation of a given generic handler for a given
+/// width.
+macro_rules! sized {
+    ($handler:ident, $w:expr) => {
+	match $w {
+	    Width::Byte => $handler::<u8>,
+	    Width::Word => $handler::<u16>,
+	    Width::DoubleWord => $handler::<u32>,
+	    Width::QuadWord => $handler::<u64>
+	}
+    }
+}
+
+/// Select the specialisation of a given generic handler for a given
+/// operation (and width, if any), such that the operation is known
+/// when the handler is compiled.
+macro_rules! operation {
+    ($handler:ident, $op:expr $(, $w:expr)?) => {
+	match $op {
+	    AluOp::Sub => operation!(@ $handler, 0 $(, $w)?),
+	    AluOp::And => operation!(@ $handler, 1 $(, $w)?),
+	    AluOp::Or => operation!(@ $handler, 2 $(, $w)?),
+	    AluOp::Xor => operation!(@ $handler, 3 $(, $w)?),
+	    AluOp::Shl => operation!(@ $handler, 4 $(, $w)?),
+	    AluOp::Shr => operation!(@ $handler, 5 $(, $w)?),
+	    AluOp::Sar => operation!(@ $handler, 6 $(, $w)?),
+	    AluOp::Eq => operation!(@ $handler, 7 $(, $w)?),
+	    AluOp::Lt => operation!(@ $handler, 8 $(, $w)?),
+	    AluOp::Ltu => operation!(@ $handler, 9 $(, $w)?)
+	}
+    };
+    (@ $handler:ident, $op:literal) => {
+	$handler::<$op>
+    };
+    (@ $handler:ident, $op:literal, $w:expr) => {
+	match $w {
+	    Width::Byte => $handler::<u8,$op>,
+	    Width::Word => $handler::<u16,$op>,
+	    Width::DoubleWord => $handler::<u32,$op>,
+	    Width::QuadWord => $handler::<u64,$op>
 	}
     }
 }
+
+impl Threaded {
+    pub fn new(code: MicroCode) -> Self {
+	let handler : Handler = match code {
+	    MicroCode::Add(_,_,w) => sized!(add,w),
+	    MicroCode::Alu(op,_,_,w) => operation!(alu,op,w),
+	    MicroCode::Copy(_,_,w) => sized!(copy,w),
+	    MicroCode::Goto(_) => goto,
+	    MicroCode::Jump(_) => jump,
+	    MicroCode::Load(_,_,w) => sized!(load,w),
+	    MicroCode::RegAdd(..) => reg_add,
+	    MicroCode::RegAlu(op,..) => operation!(reg_alu,op),
+	    MicroCode::RegCopy(..) => reg_copy,
+	    MicroCode::RegFetch(_,_,w) => sized!(reg_fetch,w),
+	    MicroCode::RegFetchIndirect(_,_,w) => sized!(reg_fetch_indirect,w),
+	    MicroCode::RegLoad(..) => reg_load,
+	    MicroCode::RegSkipIfZero(..) => reg_skip_if_zero,
+	    MicroCode::RegStore(_,_,w) => sized!(reg_store,w),
+	    MicroCode::RegStoreIndirect(_,_,w) => sized!(reg_store_indirect,w),
+	    MicroCode::Skip(_) => skip,
+	    MicroCode::SkipIfZero(_,w,_) => sized!(skip_if_zero,w)
+	};
+	Threaded{handler,code,branch:code.is_branch()}
+    }
+    /// Get the microcode instruction being executed.
+    pub fn code(&self) -> MicroCode {
+	self.code
+    }
+    /// Get the handler which executes microcode of this kind.
+    #[cfg(feature="std")]
+    pub(crate) fn handler(&self) -> Handler {
+	self.handler
+    }
+}
+
+impl PartialEq for Threaded {
+    fn eq(&self, other: &Self) -> bool {
+	self.code == other.code
+    }
+}
+
+impl fmt::Debug for Threaded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	self.code.fmt(f)
+    }
+}
+
+fn add<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Add(x,y,_) = code else { unreachable!() };
+    // Note, must allow wrap around semantics so that signed
+    // arithmetic works as expected.
+    let r = state.data.read::<T>(x).wrapping_add(state.data.read(y));
+    state.data.write(x,r);
+    state.pc += 1;
+    0
+}
+
+fn alu<T:Scalar,const OP: usize>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Alu(_,x,y,_) = code else { unreachable!() };
+    let r = AluOp::ALL[OP].apply(state.data.read::<T>(x).extend(),state.data.read::<T>(y).extend(),T::WIDTH);
+    state.data.write(x,T::truncate(r));
+    state.pc += 1;
+    0
+}
+
+fn copy<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Copy(x,y,_) = code else { unreachable!() };
+    let v = state.data.read::<T>(y);
+    state.data.write(x,v);
+    state.pc += 1;
+    0
+}
+
+fn load<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Load(x,i,_) = code else { unreachable!() };
+    // Immediates are truncated to the width written, such that
+    // signed immediates (in two's complement form) behave as
+    // expected.
+    state.data.write(x,T::truncate(i));
+    state.pc += 1;
+    0
+}
+
+fn goto(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Goto(i) = code else { unreachable!() };
+    state.pc = i;
+    0
+}
+
+fn jump(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Jump(i) = code else { unreachable!() };
+    // A jump outside of the address space wraps around (and, hence,
+    // leaves the program) rather than overflowing.
+    state.pc = state.pc.wrapping_add_signed(i);
+    0
+}
+
+// Note, the pc is advanced before writing any register, so that a
+// write to the pc itself behaves as a branch.
+
+fn reg_add(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegAdd(r,s) = code else { unreachable!() };
+    let v = state.read_register(r).wrapping_add(state.read_register(s));
+    state.pc += 1;
+    state.write_register(r,v);
+    0
+}
+
+fn reg_alu<const OP: usize>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegAlu(_,r,s) = code else { unreachable!() };
+    // Special (and scratch) registers are 64 bits
+    let w = if r >= LR - TEMPS { Width::QuadWord } else { state.registers.width() };
+    let v = AluOp::ALL[OP].apply(state.read_register(r),state.read_register(s),w);
+    state.pc += 1;
+    state.write_register(r,v);
+    0
+}
+
+fn reg_copy(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegCopy(r,s) = code else { unreachable!() };
+    let v = state.read_register(s);
+    state.pc += 1;
+    state.write_register(r,v);
+    0
+}
+
+fn reg_fetch<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegFetch(r,x,_) = code else { unreachable!() };
+    let v = state.data.read::<T>(x).extend();
+    state.pc += 1;
+    state.write_register(r,v);
+    0
+}
+
+fn reg_fetch_indirect<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegFetchIndirect(r,s,_) = code else { unreachable!() };
+    let v = state.data.read_wrapping::<T>(state.read_register(s) as usize).extend();
+    state.pc += 1;
+    state.write_register(r,v);
+    0
+}
+
+fn reg_load(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegLoad(r,i) = code else { unreachable!() };
+    state.pc += 1;
+    state.write_register(r,i);
+    0
+}
+
+fn reg_store<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegStore(x,r,_) = code else { unreachable!() };
+    let v = state.read_register(r);
+    state.data.write(x,T::truncate(v));
+    state.pc += 1;
+    0
+}
+
+fn reg_store_indirect<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegStoreIndirect(s,r,_) = code else { unreachable!() };
+    let v = state.read_register(r);
+    state.data.write_wrapping(state.read_register(s) as usize,T::truncate(v));
+    state.pc += 1;
+    0
+}
+
+fn skip(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::Skip(n) = code else { unreachable!() };
+    state.pc += 1;
+    n
+}
+
+fn skip_if_zero<T:Scalar>(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::SkipIfZero(x,_,n) = code else { unreachable!() };
+    state.pc += 1;
+    if state.data.read::<T>(x).extend() == 0 { n } else { 0 }
+}
+
+fn reg_skip_if_zero(state: &mut State, code: MicroCode) -> usize {
+    let MicroCode::RegSkipIfZero(r,n) = code else { unreachable!() };
+    state.pc += 1;
+    if state.read_register(r) == 0 { n } else { 0 }
+}
+
+/// Read a value of a given width from memory (zero extended).
+#[cfg(feature="std")]
+pub(crate) fn read(data: &Memory, x: usize, w: Width) -> u64 {
+    match w {
+	Width::Byte => data.read::<u8>(x).extend(),
+	Width::Word => data.read::<u16>(x).extend(),
+	Width::DoubleWord => data.read::<u32>(x).extend(),
+	Width::QuadWord => data.read::<u64>(x)
+    }
+}
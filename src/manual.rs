@@ -0,0 +1,348 @@
+use std::fmt::{self,Write};
+use crate::insn::{AbstractMicroCode,FieldKind,Format,Instruction,InstructionSet,Metadata,Operand,Predicate};
+
+// =====================================================
+// Manual
+// =====================================================
+
+/// A contiguous range of bits within an instruction word, along with
+/// what it holds.
+#[derive(Clone,Debug,PartialEq)]
+pub struct BitField {
+    /// Most significant bit of this range.
+    pub msb: usize,
+    /// Least significant bit of this range.
+    pub lsb: usize,
+    /// What this range holds.  This is either the opcode (in binary),
+    /// the name of an operand, or `-` (for unused bits).
+    pub label: String
+}
+
+/// Describes an operand of an instruction.
+#[derive(Clone,Debug,PartialEq)]
+pub struct OperandInfo {
+    pub name: String,
+    pub kind: FieldKind,
+    pub bits: u8,
+    /// Indicates whether this operand is held in an extension word.
+    pub extension: bool
+}
+
+/// The manual entry for a single instruction.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Entry {
+    pub mnemonic: String,
+    pub opcode: usize,
+    /// Label of the instruction's format.
+    pub format: String,
+    pub metadata: Metadata,
+    /// Layout of the instruction word (from most to least
+    /// significant bit).
+    pub encoding: Vec<BitField>,
+    pub operands: Vec<OperandInfo>,
+    /// Pseudo-code describing the semantics of the instruction (one
+    /// line per microcode).
+    pub semantics: Vec<String>
+}
+
+/// A programmer's reference manual for an instruction set, with one
+/// entry per instruction (in order of definition).  This can be
+/// rendered as Markdown or HTML, or walked directly to generate
+/// other kinds of documentation.  For example:
+///
+/// ```text
+/// let manual = Manual::new(&isa);
+/// std::fs::write("isa.md",manual.markdown())?;
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+pub struct Manual {
+    pub entries: Vec<Entry>
+}
+
+impl Manual {
+    pub fn new(isa: &InstructionSet) -> Self {
+	let entries = isa.iter().enumerate().map(|(i,insn)| entry(insn,isa.opcode(i))).collect();
+	Manual{entries}
+    }
+    /// Render this manual as Markdown.
+    pub fn markdown(&self) -> String {
+	let mut out = String::new();
+	for e in &self.entries {
+	    writeln!(out,"## {}\n",e.mnemonic).unwrap();
+	    if let Some(d) = &e.metadata.description {
+		writeln!(out,"{}\n",d).unwrap();
+	    }
+	    writeln!(out,"{}\n",summary(e)).unwrap();
+	    let ranges : Vec<String> = e.encoding.iter().map(range).collect();
+	    let labels : Vec<&str> = e.encoding.iter().map(|f| f.label.as_str()).collect();
+	    writeln!(out,"| {} |",ranges.join(" | ")).unwrap();
+	    writeln!(out,"|{}",e.encoding.iter().map(|_| "---|").collect::<String>()).unwrap();
+	    writeln!(out,"| {} |\n",labels.join(" | ")).unwrap();
+	    if !e.operands.is_empty() {
+		for o in &e.operands {
+		    writeln!(out,"- `{}`: {}",o.name,operand(o)).unwrap();
+		}
+		out.push('\n');
+	    }
+	    if !e.semantics.is_empty() {
+		writeln!(out,"```text\n{}\n```\n",e.semantics.join("\n")).unwrap();
+	    }
+	}
+	out
+    }
+    /// Render this manual as (a fragment of) HTML.
+    pub fn html(&self) -> String {
+	let mut out = String::new();
+	for e in &self.entries {
+	    let m = escape(&e.mnemonic);
+	    writeln!(out,"<h2 id=\"{}\">{}</h2>",m,m).unwrap();
+	    if let Some(d) = &e.metadata.description {
+		writeln!(out,"<p>{}</p>",escape(d)).unwrap();
+	    }
+	    writeln!(out,"<p>{}</p>",escape(&summary(e))).unwrap();
+	    out.push_str("<table>\n<tr>");
+	    for f in &e.encoding {
+		write!(out,"<th>{}</th>",range(f)).unwrap();
+	    }
+	    out.push_str("</tr>\n<tr>");
+	    for f in &e.encoding {
+		write!(out,"<td>{}</td>",escape(&f.label)).unwrap();
+	    }
+	    out.push_str("</tr>\n</table>\n");
+	    if !e.operands.is_empty() {
+		out.push_str("<ul>\n");
+		for o in &e.operands {
+		    writeln!(out,"<li><code>{}</code>: {}</li>",escape(&o.name),escape(&operand(o))).unwrap();
+		}
+		out.push_str("</ul>\n");
+	    }
+	    if !e.semantics.is_empty() {
+		writeln!(out,"<pre>{}</pre>",escape(&e.semantics.join("\n"))).unwrap();
+	    }
+	}
+	out
+    }
+}
+
+fn entry(insn: &Instruction, opcode: usize) -> Entry {
+    let format = insn.format();
+    let names : Vec<String> = format.operands().iter().enumerate().map(|(i,f)| name(f.name(),i)).collect();
+    let base = format.operands().len() - format.extensions();
+    let operands = format.operands().iter().zip(&names).enumerate().map(|(i,(f,n))| {
+	OperandInfo{name:n.clone(),kind:f.kind(),bits:f.bits().value(),extension:i >= base}
+    }).collect();
+    let semantics = insn.semantic().iter().map(|c| pseudocode(c,&names)).collect();
+    Entry{
+	mnemonic:insn.mnemonic().to_string(),
+	opcode,
+	format:format.label().to_string(),
+	metadata:insn.metadata().clone(),
+	encoding:encoding(format,opcode,&names),
+	operands,
+	semantics
+    }
+}
+
+/// Determine the layout of the instruction word for a given format,
+/// from the most to the least significant bit.
+fn encoding(format: &Format, opcode: usize, names: &[String]) -> Vec<BitField> {
+    let mut fields : Vec<BitField> = format.layout().iter().enumerate().map(|(i,(offset,n))| {
+	let label = if i == 0 { format!("{:0n$b}",opcode,n=*n) } else { names[i-1].clone() };
+	BitField{msb:offset+n-1,lsb:*offset,label}
+    }).collect();
+    fields.sort_by_key(|f| std::cmp::Reverse(f.lsb));
+    // Fill in any unused bits
+    let mut result = Vec::new();
+    let mut next = 8 * format.width().value() as usize;
+    for f in fields {
+	if f.msb + 1 < next {
+	    result.push(BitField{msb:next-1,lsb:f.msb+1,label:"-".to_string()});
+	}
+	next = f.lsb;
+	result.push(f);
+    }
+    if next > 0 {
+	result.push(BitField{msb:next-1,lsb:0,label:"-".to_string()});
+    }
+    result
+}
+
+/// Determine the name of an operand, using its position when it has
+/// no name.
+fn name(name: &str, index: usize) -> String {
+    if name.is_empty() { format!("op{}",index) } else { name.to_string() }
+}
+
+fn summary(e: &Entry) -> String {
+    let mut s = format!("Format `{}`, opcode {}",e.format,e.opcode);
+    if let Some(c) = &e.metadata.category {
+	write!(s,", category {:?}",c).unwrap();
+    }
+    if !e.metadata.reads.is_empty() {
+	write!(s,", reads {}",e.metadata.reads.join(",")).unwrap();
+    }
+    if !e.metadata.writes.is_empty() {
+	write!(s,", writes {}",e.metadata.writes.join(",")).unwrap();
+    }
+    s.push('.');
+    s
+}
+
+fn range(f: &BitField) -> String {
+    if f.msb == f.lsb { f.msb.to_string() } else { format!("{}:{}",f.msb,f.lsb) }
+}
+
+fn operand(o: &OperandInfo) -> String {
+    let kind = match o.kind {
+	FieldKind::Register => "register",
+	FieldKind::Immediate => "unsigned immediate",
+	FieldKind::SignedImmediate => "signed immediate"
+    };
+    let place = if o.extension { ", extension word" } else { "" };
+    format!("{} ({} bits{})",kind,o.bits,place)
+}
+
+/// Render a microcode instruction as pseudo-code, where `M[x]` is the
+/// machine location at address `x`.
+fn pseudocode(code: &AbstractMicroCode, names: &[String]) -> String {
+    let op = |o: &Operand| expression(o,names,false);
+    match code {
+	AbstractMicroCode::Add(x,y,w) => format!("M[{}] := M[{}] + M[{}] ({} bits)",op(x),op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::Alu(o,x,y,w) => format!("M[{}] := M[{}] {} M[{}] ({} bits)",op(x),op(x),o,op(y),8 * w.bytes()),
+	AbstractMicroCode::Copy(x,y,w) => format!("M[{}] := M[{}] ({} bits)",op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::Goto(x) => format!("pc := {}",op(x)),
+	AbstractMicroCode::Jump(x) => format!("pc := pc + {}",op(x)),
+	AbstractMicroCode::Load(x,i,w) => format!("M[{}] := {} ({} bits)",op(x),op(i),8 * w.bytes()),
+	AbstractMicroCode::RegAdd(x,y) => format!("R[{}] := R[{}] + R[{}]",op(x),op(x),op(y)),
+	AbstractMicroCode::RegAlu(o,x,y) => format!("R[{}] := R[{}] {} R[{}]",op(x),op(x),o,op(y)),
+	AbstractMicroCode::RegCopy(x,y) => format!("R[{}] := R[{}]",op(x),op(y)),
+	AbstractMicroCode::RegFetch(x,y,w) => format!("R[{}] := M[{}] ({} bits)",op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::RegFetchIndirect(x,y,w) => format!("R[{}] := M[R[{}]] ({} bits)",op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::RegLoad(x,i) => format!("R[{}] := {}",op(x),op(i)),
+	AbstractMicroCode::RegStore(x,y,w) => format!("M[{}] := R[{}] ({} bits)",op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::RegStoreIndirect(x,y,w) => format!("M[R[{}]] := R[{}] ({} bits)",op(x),op(y),8 * w.bytes()),
+	AbstractMicroCode::If(p,t,e) => {
+	    let block = |codes: &[AbstractMicroCode]| codes.iter().map(|c| pseudocode(c,names)).collect::<Vec<_>>().join("; ");
+	    if e.is_empty() {
+		format!("if {} then {{ {} }}",predicate(p,names),block(t))
+	    } else {
+		format!("if {} then {{ {} }} else {{ {} }}",predicate(p,names),block(t),block(e))
+	    }
+	}
+    }
+}
+
+/// Render a predicate.
+fn predicate(p: &Predicate, names: &[String]) -> String {
+    let op = |o: &Operand| expression(o,names,false);
+    match p {
+	Predicate::Eq(x,y) => format!("{} == {}",op(x),op(y)),
+	Predicate::Ne(x,y) => format!("{} != {}",op(x),op(y)),
+	Predicate::Lt(x,y) => format!("{} < {}",op(x),op(y)),
+	Predicate::Zero(x,w) => format!("M[{}] == 0 ({} bits)",op(x),8 * w.bytes()),
+	Predicate::RegZero(x) => format!("R[{}] == 0",op(x)),
+	Predicate::Not(p) => format!("!({})",predicate(p,names))
+    }
+}
+
+/// Render an operand expression, where nested expressions are
+/// bracketed.
+fn expression(o: &Operand, names: &[String], nested: bool) -> String {
+    let (l,op,r) = match o {
+	Operand::Const(c) => { return c.to_string(); }
+	Operand::Var(v) => { return names.get(*v).cloned().unwrap_or_else(|| format!("op{}",v)); }
+	Operand::Pc => { return "pc".to_string(); }
+	Operand::Sp => { return "sp".to_string(); }
+	Operand::Flags => { return "flags".to_string(); }
+	Operand::Lr => { return "lr".to_string(); }
+	Operand::Temp(i) => { return format!("t{}",i); }
+	Operand::Add(l,r) => (l,"+",r),
+	Operand::Mul(l,r) => (l,"*",r),
+	Operand::Shl(l,r) => (l,"<<",r),
+	Operand::RegSlot(r,w) => (r,"*",&Box::new(Operand::Const(w.bytes()))),
+	Operand::PcRel(o) => (&Box::new(Operand::Pc),"+",o),
+	Operand::SExt(o,n) => { return format!("sext{}({})",n,expression(o,names,false)); }
+	Operand::Bits(o,hi,lo) => { return format!("{}[{}:{}]",expression(o,names,true),hi,lo); }
+    };
+    let text = format!("{} {} {}",expression(l,names,true),op,expression(r,names,true));
+    if nested { format!("({})",text) } else { text }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;").replace('"',"&quot;")
+}
+
+// =====================================================
+// Opcode Map
+// =====================================================
+
+/// The opcode matrix for a single format, where the opcode of a given
+/// cell is determined by its row and column (i.e. `row * columns +
+/// column`).  Cells are empty for opcodes not assigned to an
+/// instruction of this format.
+#[derive(Clone,Debug,PartialEq)]
+pub struct OpcodePage {
+    /// Label of the format.
+    pub format: String,
+    /// Size (in bits) of the opcode field.
+    pub bits: u8,
+    /// Number of columns in each row.
+    pub columns: usize,
+    pub rows: Vec<Vec<Option<String>>>
+}
+
+impl OpcodePage {
+    /// Get the mnemonic (if any) assigned to a given opcode.
+    pub fn get(&self, opcode: usize) -> Option<&str> {
+	self.rows.get(opcode / self.columns)?.get(opcode % self.columns)?.as_deref()
+    }
+}
+
+/// A table mapping opcode values to mnemonics, with one page per
+/// format (in order of first use).  The low (up to four) bits of an
+/// opcode select its column, whilst the remaining bits select its
+/// row.  Thus, an eight bit opcode field gives the classic 16x16
+/// matrix.
+#[derive(Clone,Debug,PartialEq)]
+pub struct OpcodeMap {
+    pub pages: Vec<OpcodePage>
+}
+
+impl OpcodeMap {
+    pub fn new(isa: &InstructionSet) -> Self {
+	let mut pages = Vec::new();
+	for format in isa.formats() {
+	    let bits = format.opcode().value();
+	    let columns = 1usize << bits.div_ceil(2).min(4);
+	    let count = 1usize.checked_shl(bits as u32).unwrap_or(usize::MAX);
+	    let mut rows = vec![vec![None;columns];count.div_ceil(columns)];
+	    for (i,insn) in isa.iter().enumerate() {
+		let opcode = isa.opcode(i);
+		if insn.format() == format && opcode < count {
+		    rows[opcode / columns][opcode % columns] = Some(insn.mnemonic().to_string());
+		}
+	    }
+	    pages.push(OpcodePage{format:format.label().to_string(),bits,columns,rows});
+	}
+	OpcodeMap{pages}
+    }
+}
+
+impl fmt::Display for OpcodeMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	for (i,page) in self.pages.iter().enumerate() {
+	    if i != 0 { writeln!(f)?; }
+	    writeln!(f,"{} ({} bit opcode)",page.format,page.bits)?;
+	    let cells = page.rows.iter().flatten().map(|c| c.as_deref().unwrap_or("-").len());
+	    let width = cells.chain([format!("{:X}",page.columns-1).len()]).max().unwrap_or(1);
+	    let label = format!("{:X}",page.columns * (page.rows.len() - 1)).len();
+	    let header : Vec<String> = (0..page.columns).map(|c| format!("{:<width$}",format!("{:X}",c))).collect();
+	    writeln!(f,"{:label$}  {}",' ',header.join(" ").trim_end())?;
+	    for (r,row) in page.rows.iter().enumerate() {
+		let row : Vec<String> = row.iter().map(|c| format!("{:<width$}",c.as_deref().unwrap_or("-"))).collect();
+		writeln!(f,"{:>label$X}  {}",r * page.columns,row.join(" ").trim_end())?;
+	    }
+	}
+	Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::ops::AddAssign;
+use crate::machine::{MachineError,State};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Counters
+// =====================================================
+
+/// The runtime counters of a single machine (or, when added together,
+/// of many machines) at some point.  Note that there are no per-device
+/// I/O counts, since machines have no notion of devices (i.e. I/O is
+/// simply an access to memory).
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct Counters {
+    /// Number of instructions executed successfully.
+    pub instructions: u64,
+    /// Number of instructions which raised a fault (e.g. could not
+    /// be decoded, or accessed memory out of bounds).
+    pub faults: u64,
+    /// Amount of fuel consumed, where each instruction attempted
+    /// (including those which fault) consumes one unit.
+    pub fuel: u64,
+    /// Number of instructions decoded using the cached format (see
+    /// `FormatCache`).
+    pub cache_hits: u64,
+    /// Number of instructions decoded by trying each format in turn.
+    pub cache_misses: u64
+}
+
+impl Counters {
+    /// Get the name, description and value of each counter, where
+    /// names follow the Prometheus conventions.
+    pub fn samples(&self) -> [(&'static str,&'static str,u64);5] {
+	[("virmin_instructions_total","Instructions executed.",self.instructions),
+	 ("virmin_faults_total","Instructions which raised a fault.",self.faults),
+	 ("virmin_fuel_consumed_total","Fuel consumed.",self.fuel),
+	 ("virmin_decode_cache_hits_total","Instructions decoded using the cached format.",self.cache_hits),
+	 ("virmin_decode_cache_misses_total","Instructions decoded without the cached format.",self.cache_misses)]
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, other: Counters) {
+	self.instructions += other.instructions;
+	self.faults += other.faults;
+	self.fuel += other.fuel;
+	self.cache_hits += other.cache_hits;
+	self.cache_misses += other.cache_misses;
+    }
+}
+
+/// Render the counters of one or more machines in the Prometheus text
+/// exposition format, where each machine is distinguished by a given
+/// set of labels.  For example:
+///
+/// ```text
+/// # HELP virmin_instructions_total Instructions executed.
+/// # TYPE virmin_instructions_total counter
+/// virmin_instructions_total{vm="a"} 1024
+/// virmin_instructions_total{vm="b"} 17
+/// ```
+pub fn prometheus(series: &[(&[(&str,&str)],Counters)]) -> String {
+    let mut out = String::new();
+    for (i,(name,help,_)) in Counters::default().samples().into_iter().enumerate() {
+	writeln!(out,"# HELP {} {}",name,help).unwrap();
+	writeln!(out,"# TYPE {} counter",name).unwrap();
+	for (labels,counters) in series {
+	    let value = counters.samples()[i].2;
+	    if labels.is_empty() {
+		writeln!(out,"{} {}",name,value).unwrap();
+	    } else {
+		let labels : Vec<String> = labels.iter().map(|(k,v)| format!("{}=\"{}\"",k,escape(v))).collect();
+		writeln!(out,"{}{{{}}} {}",name,labels.join(","),value).unwrap();
+	    }
+	}
+    }
+    out
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\',"\\\\").replace('"',"\\\"").replace('\n',"\\n")
+}
+
+// =====================================================
+// Metrics
+// =====================================================
+
+/// Executes a decoded program whilst counting what happens, such that
+/// the counters can be collected (e.g. periodically, by a host
+/// publishing them) at any point.  For example:
+///
+/// ```text
+/// let mut metrics = Metrics::new();
+/// metrics.run(&mut state,&program,10_000)?;
+/// let counters = metrics.collect(&program);
+/// println!("{}",prometheus(&[(&[("vm","a")],counters)]));
+/// ```
+#[derive(Clone,Debug,Default)]
+pub struct Metrics {
+    counters: Counters
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+	Metrics::default()
+    }
+    /// Execute the instruction identified by the current pc (as for
+    /// `State::try_step()`), counting it.
+    pub fn step(&mut self, state: &mut State, program: &DecodedProgram) -> Result<(),MachineError> {
+	self.counters.fuel += 1;
+	match state.try_step(program) {
+	    Ok(()) => {
+		self.counters.instructions += 1;
+		Ok(())
+	    }
+	    Err(e) => {
+		self.counters.faults += 1;
+		Err(e)
+	    }
+	}
+    }
+    /// Execute instructions (as for `step()`) until the pc leaves the
+    /// program, or a given amount of fuel has been consumed.  Returns
+    /// the number of instructions executed.
+    pub fn run(&mut self, state: &mut State, program: &DecodedProgram, fuel: u64) -> Result<u64,MachineError> {
+	let mut steps = 0;
+	while steps < fuel && state.pc < program.len() {
+	    self.step(state,program)?;
+	    steps += 1;
+	}
+	Ok(steps)
+    }
+    /// Get the counters recorded so far, along with those of the
+    /// format cache used to decode a given program.
+    pub fn collect(&self, program: &DecodedProgram) -> Counters {
+	let cache = program.cache();
+	Counters{cache_hits:cache.hits(),cache_misses:cache.misses(),..self.counters}
+    }
+    /// Get the counters recorded so far (excluding those of any
+    /// format cache).
+    pub fn counters(&self) -> &Counters {
+	&self.counters
+    }
+    /// Reset all counters to zero.
+    pub fn reset(&mut self) {
+	self.counters = Counters::default();
+    }
+}
@@ -0,0 +1,290 @@
+use alloc::{vec,vec::Vec};
+use crate::insn::ByteOrder;
+use crate::machine::{MicroCode,Width};
+
+// =====================================================
+// Optimizer
+// =====================================================
+
+/// A peephole optimizer for the microcode of a single instruction,
+/// which does not change its observable behaviour (i.e. the resulting
+/// pc, memory and registers, or whether it faults).  This applies the
+/// following rewrites until none apply:
+///
+/// * Removing redundant copies, such as `mem[x] := mem[x]`, or a copy
+///   repeating an earlier one whose locations are unchanged since.
+///
+/// * Folding additions (and other operations) of constants loaded
+///   earlier, such that `mem[x] := 1; mem[y] := 2; mem[x] := mem[x]
+///   + mem[y]` becomes `mem[y] := 2; mem[x] := 3`.
+///
+/// * Removing writes which are overwritten before being read.
+///
+/// * Merging consecutive narrow writes of adjacent locations into a
+///   single wider write (e.g. two byte loads into one word load).
+///
+/// Rewrites are only applied within straight-line runs of microcode
+/// (i.e. not across skips, the microcode they skip to, or accesses of
+/// memory via a register), and skips are adjusted for any microcode
+/// removed.  Since memory accesses are checked before an instruction
+/// executes, rewrites only involve microcode which cannot fault
+/// (i.e. accesses no registers and only memory within the given
+/// size).  For example:
+///
+/// ```text
+/// let optimizer = Optimizer::new(memory.len()).byte_order(ByteOrder::BigEndian);
+/// let removed = program.optimize(&optimizer);
+/// ```
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Optimizer {
+    memory: usize,
+    order: ByteOrder
+}
+
+/// An item of microcode being optimized, where a skip holds the
+/// (absolute) index it skips to.
+#[derive(Clone,Copy,Debug)]
+struct Item {
+    code: MicroCode,
+    target: Option<usize>
+}
+
+impl Optimizer {
+    /// Construct an optimizer for a machine with a given size of
+    /// (little endian) memory.
+    pub fn new(memory: usize) -> Self {
+	Optimizer{memory,order:ByteOrder::LittleEndian}
+    }
+    /// Set the byte order of memory, which determines how narrow
+    /// writes are merged.
+    pub fn byte_order(mut self, order: ByteOrder) -> Self {
+	self.order = order;
+	self
+    }
+    /// Optimize a given sequence of microcode.
+    pub fn optimize(&self, microcode: &[MicroCode]) -> Vec<MicroCode> {
+	let n = microcode.len();
+	let mut items : Vec<Option<Item>> = microcode.iter().enumerate().map(|(i,c)| {
+	    let target = match *c {
+		MicroCode::Skip(k)|MicroCode::SkipIfZero(_,_,k)|MicroCode::RegSkipIfZero(_,k) => Some(i.saturating_add(1).saturating_add(k).min(n)),
+		_ => None
+	    };
+	    Some(Item{code:*c,target})
+	}).collect();
+	let starts = segments(&items);
+	let mut changed = true;
+	while changed {
+	    changed = false;
+	    for s in 0..starts.len() - 1 {
+		let range = starts[s]..starts[s+1];
+		changed |= self.remove_copies(&mut items[range.clone()]);
+		changed |= self.fold_constants(&mut items[range.clone()]);
+		changed |= self.remove_dead_writes(&mut items[range.clone()]);
+		changed |= self.merge_writes(&mut items[range]);
+	    }
+	}
+	// Renumber the remaining microcode and adjust skips accordingly,
+	// noting that removed microcode had no effect.
+	let mut index = vec![0;n+1];
+	let mut count = 0;
+	for (i,item) in items.iter().enumerate() {
+	    index[i] = count;
+	    if item.is_some() { count += 1; }
+	}
+	index[n] = count;
+	items.iter().flatten().enumerate().map(|(i,item)| {
+	    let k = item.target.map(|t| index[t] - i - 1);
+	    match (item.code,k) {
+		(MicroCode::Skip(_),Some(k)) => MicroCode::Skip(k),
+		(MicroCode::SkipIfZero(x,w,_),Some(k)) => MicroCode::SkipIfZero(x,w,k),
+		(MicroCode::RegSkipIfZero(r,_),Some(k)) => MicroCode::RegSkipIfZero(r,k),
+		(code,_) => code
+	    }
+	}).collect()
+    }
+
+    /// Remove copies which have no effect, because either they copy a
+    /// location to itself, or the locations involved already hold the
+    /// same value (i.e. since an earlier copy between them).
+    fn remove_copies(&self, items: &mut [Option<Item>]) -> bool {
+	let mut changed = false;
+	for i in 0..items.len() {
+	    let Some(item) = items[i] else { continue; };
+	    let MicroCode::Copy(x,y,w) = item.code else { continue; };
+	    if !self.in_bounds(item.code) { continue; }
+	    let redundant = x == y || (disjoint(x,y,w.bytes()) && items[..i].iter().rev().flatten()
+		.find(|e| writes(e.code).is_some_and(|(z,v)| overlaps(z,v.bytes(),x,w.bytes()) || overlaps(z,v.bytes(),y,w.bytes())))
+		.is_some_and(|e| e.code == MicroCode::Copy(x,y,w) || e.code == MicroCode::Copy(y,x,w)));
+	    if redundant {
+		items[i] = None;
+		changed = true;
+	    }
+	}
+	changed
+    }
+
+    /// Replace additions, other operations (and copies) whose operands
+    /// are constants loaded earlier with loads of the result.
+    fn fold_constants(&self, items: &mut [Option<Item>]) -> bool {
+	let mut changed = false;
+	// Locations known to hold constants
+	let mut known : Vec<(usize,Width,u64)> = Vec::new();
+	for item in items.iter_mut().flatten() {
+	    let value = |x: usize, w: Width| known.iter().find(|k| k.0 == x && k.1 == w).map(|k| k.2);
+	    let folded = match item.code {
+		MicroCode::Add(x,y,w) => value(x,w).zip(value(y,w)).map(|(a,b)| MicroCode::Load(x,a.wrapping_add(b) & w.mask(),w)),
+		MicroCode::Alu(op,x,y,w) => value(x,w).zip(value(y,w)).map(|(a,b)| MicroCode::Load(x,op.apply(a,b,w),w)),
+		MicroCode::Copy(x,y,w) => value(y,w).map(|a| MicroCode::Load(x,a,w)),
+		_ => None
+	    };
+	    if let Some(code) = folded.filter(|_| self.in_bounds(item.code)) {
+		item.code = code;
+		changed = true;
+	    }
+	    if let Some((x,w)) = writes(item.code) {
+		known.retain(|k| !overlaps(k.0,k.1.bytes(),x,w.bytes()));
+		if let MicroCode::Load(_,i,_) = item.code {
+		    known.push((x,w,i & w.mask()));
+		}
+	    }
+	}
+	changed
+    }
+
+    /// Remove writes to locations which are completely overwritten
+    /// before they are next read.
+    fn remove_dead_writes(&self, items: &mut [Option<Item>]) -> bool {
+	let mut changed = false;
+	for i in 0..items.len() {
+	    let Some(item) = items[i] else { continue; };
+	    let Some((x,w)) = writes(item.code) else { continue; };
+	    if !self.in_bounds(item.code) { continue; }
+	    for e in items[i+1..].iter().flatten() {
+		if reads(e.code).iter().flatten().any(|(y,v)| overlaps(x,w.bytes(),*y,v.bytes())) {
+		    break;
+		} else if writes(e.code).is_some_and(|(y,v)| y <= x && x + w.bytes() <= y.saturating_add(v.bytes())) {
+		    items[i] = None;
+		    changed = true;
+		    break;
+		}
+	    }
+	}
+	changed
+    }
+
+    /// Merge consecutive loads (or copies) of the same width to
+    /// adjacent locations into one of twice the width.
+    fn merge_writes(&self, items: &mut [Option<Item>]) -> bool {
+	let mut changed = false;
+	let mut last : Option<usize> = None;
+	for i in 0..items.len() {
+	    let Some(item) = items[i] else { continue; };
+	    if let Some(j) = last {
+		let first = items[j].unwrap().code;
+		if let Some(code) = self.merge(first,item.code).filter(|_| self.in_bounds(first) && self.in_bounds(item.code)) {
+		    items[j] = Some(Item{code,target:None});
+		    items[i] = None;
+		    changed = true;
+		    continue;
+		}
+	    }
+	    last = Some(i);
+	}
+	changed
+    }
+
+    fn merge(&self, first: MicroCode, second: MicroCode) -> Option<MicroCode> {
+	match (first,second) {
+	    (MicroCode::Load(x,a,w),MicroCode::Load(y,b,v)) if w == v => {
+		let n = w.bytes();
+		let wide = wider(w)?;
+		// Order the values by address
+		let (x,lo,hi) = if y == x.checked_add(n)? { (x,a,b) } else if x == y.checked_add(n)? { (y,b,a) } else { return None; };
+		let (lo,hi) = (lo & w.mask(),hi & w.mask());
+		let value = match self.order {
+		    ByteOrder::LittleEndian => lo | (hi << (8 * n)),
+		    ByteOrder::BigEndian => (lo << (8 * n)) | hi
+		};
+		Some(MicroCode::Load(x,value,wide))
+	    }
+	    (MicroCode::Copy(x,y,w),MicroCode::Copy(z,u,v)) if w == v => {
+		let n = w.bytes();
+		let wide = wider(w)?;
+		let (x,y) = if z == x.checked_add(n)? && u == y.checked_add(n)? { (x,y) }
+		    else if x == z.checked_add(n)? && y == u.checked_add(n)? { (z,u) }
+		    else { return None; };
+		// Neither copy can read what the other writes
+		disjoint(x,y,2 * n).then_some(MicroCode::Copy(x,y,wide))
+	    }
+	    _ => None
+	}
+    }
+
+    /// Check some microcode cannot fault, since it accesses no
+    /// registers and its memory accesses lie within bounds.
+    fn in_bounds(&self, code: MicroCode) -> bool {
+	code.registers().iter().all(|r| r.is_none()) && code.locations().into_iter().flatten().all(|(x,w)| x.checked_add(w.bytes()).is_some_and(|e| e <= self.memory))
+    }
+}
+
+// =====================================================
+// Helpers
+// =====================================================
+
+/// Determine the start of each straight-line run of microcode, such
+/// that only the first microcode of a run can be skipped to and only
+/// the last can skip (or branch).  Since the memory accessed
+/// indirectly is unknown, such accesses are runs of their own.  The
+/// result includes the end.
+fn segments(items: &[Option<Item>]) -> Vec<usize> {
+    let n = items.len();
+    let mut starts = vec![false;n+1];
+    starts[0] = true;
+    starts[n] = true;
+    for (i,item) in items.iter().enumerate() {
+	let Some(item) = item else { continue; };
+	if let Some(t) = item.target { starts[t] = true; }
+	if item.target.is_some() || item.code.is_branch() { starts[i+1] = true; }
+	if let MicroCode::RegFetchIndirect(..)|MicroCode::RegStoreIndirect(..) = item.code {
+	    starts[i] = true;
+	    starts[i+1] = true;
+	}
+    }
+    (0..=n).filter(|i| starts[*i]).collect()
+}
+
+/// Determine the memory written by some microcode (if any).
+fn writes(code: MicroCode) -> Option<(usize,Width)> {
+    match code {
+	MicroCode::Add(x,_,w)|MicroCode::Alu(_,x,_,w)|MicroCode::Copy(x,_,w)|MicroCode::Load(x,_,w)|MicroCode::RegStore(x,_,w) => Some((x,w)),
+	_ => None
+    }
+}
+
+/// Determine the memory read by some microcode.
+fn reads(code: MicroCode) -> [Option<(usize,Width)>;2] {
+    match code {
+	MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w) => [Some((x,w)),Some((y,w))],
+	MicroCode::Copy(_,y,w) => [Some((y,w)),None],
+	MicroCode::RegFetch(_,x,w)|MicroCode::SkipIfZero(x,w,_) => [Some((x,w)),None],
+	_ => [None,None]
+    }
+}
+
+fn overlaps(x: usize, n: usize, y: usize, m: usize) -> bool {
+    x < y.saturating_add(m) && y < x.saturating_add(n)
+}
+
+fn disjoint(x: usize, y: usize, n: usize) -> bool {
+    !overlaps(x,n,y,n)
+}
+
+/// Determine the width twice a given width (if any).
+fn wider(w: Width) -> Option<Width> {
+    match w {
+	Width::Byte => Some(Width::Word),
+	Width::Word => Some(Width::DoubleWord),
+	Width::DoubleWord => Some(Width::QuadWord),
+	Width::QuadWord => None
+    }
+}
@@ -0,0 +1,52 @@
+use std::ops::Range;
+use rayon::prelude::*;
+
+// =====================================================
+// Parallel Decoding
+// =====================================================
+
+/// Decode an image of a given length in parallel, by splitting it at
+/// the given boundaries (e.g. known entry points or alignment
+/// boundaries) and decoding each part independently.  The function
+/// decodes items starting from a given offset until reaching a given
+/// end, returning them (with their offsets) and the offset reached.
+/// Likewise, the items of all parts are returned with their offsets.
+///
+/// Since an instruction may straddle a boundary, the part following
+/// it may have started decoding at the wrong offset.  Such a part is
+/// resynchronised when the parts are merged, by discarding its items
+/// before the offset actually reached (and decoding it again from
+/// there if none starts at that offset).  Thus, the result is always
+/// the same as decoding the whole image sequentially.
+pub(crate) fn split<T,F>(length: usize, boundaries: &[usize], decode: F) -> Vec<(usize,T)>
+where T: Send, F: Fn(usize,usize) -> (Vec<(usize,T)>,usize) + Sync {
+    let ranges = ranges(length,boundaries);
+    let parts : Vec<_> = ranges.par_iter().map(|r| decode(r.start,r.end)).collect();
+    let mut items = Vec::new();
+    let mut offset = 0;
+    for (range,(mut part,end)) in ranges.into_iter().zip(parts) {
+	if offset >= range.end {
+	    // Entirely covered by the previous part
+	    continue;
+	}
+	let (part,end) = match part.iter().position(|(o,_)| *o == offset) {
+	    Some(i) => (part.split_off(i),end),
+	    None => decode(offset,range.end)
+	};
+	items.extend(part);
+	offset = end;
+    }
+    items
+}
+
+/// Determine the ranges covering an image of a given length, which
+/// are delimited by the given boundaries.  Boundaries may be given in
+/// any order, and those beyond the image are ignored.
+fn ranges(length: usize, boundaries: &[usize]) -> Vec<Range<usize>> {
+    let mut starts : Vec<usize> = boundaries.iter().copied().filter(|b| *b > 0 && *b < length).collect();
+    starts.push(0);
+    starts.sort_unstable();
+    starts.dedup();
+    let ends = starts.iter().skip(1).copied().chain([length]);
+    starts.iter().zip(ends).map(|(s,e)| *s..e).collect()
+}
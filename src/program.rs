@@ -0,0 +1,529 @@
+use core::fmt;
+use core::ops::Range;
+use alloc::{string::String,vec::Vec};
+use crate::insn::{DecodeError,EncodeError,FormatCache,InstructionSet};
+use crate::machine::{Memory,MicroCode,Threaded};
+use crate::optimize::Optimizer;
+
+// =====================================================
+// Program
+// =====================================================
+
+/// A sequence of encoded instructions laid out contiguously in a
+/// byte image.  Since instructions may have different widths, the
+/// program also maintains a mapping from each instruction's position
+/// in the sequence (i.e. its `pc`) to its offset within the image.
+pub struct Program<'a> {
+    /// Instruction set used for encoding instructions.
+    isa: &'a InstructionSet<'a>,
+    /// The encoded image.
+    bytes: Vec<u8>,
+    /// Maps each pc to its offset within the image.
+    offsets: Vec<usize>,
+    /// Symbols defined by this program.
+    symbols: Vec<Symbol>,
+    /// Operands which refer to symbols not defined by this program.
+    relocations: Vec<Relocation>,
+    /// Source location of each instruction (if known), indexed by pc.
+    locations: Vec<Option<Location>>
+}
+
+impl<'a> Program<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>) -> Self {
+	Program{isa,bytes:Vec::new(),offsets:Vec::new(),symbols:Vec::new(),relocations:Vec::new(),locations:Vec::new()}
+    }
+    /// Get the instruction set used by this program.
+    pub fn isa(&self) -> &'a InstructionSet<'a> {
+	self.isa
+    }
+    /// Append an instruction with the given mnemonic and operands onto
+    /// the end of this program, returning its pc.
+    pub fn push(&mut self, mnemonic: &str, operands: &[usize]) -> Result<usize,EncodeError> {
+	let bytes = self.isa.encode(mnemonic,operands)?;
+	let pc = self.offsets.len();
+	self.offsets.push(self.bytes.len());
+	self.locations.push(None);
+	self.bytes.extend(bytes);
+	Ok(pc)
+    }
+    /// Get the number of instructions in this program.
+    pub fn len(&self) -> usize {
+	self.offsets.len()
+    }
+    /// Check whether this program contains any instructions.
+    pub fn is_empty(&self) -> bool {
+	self.offsets.is_empty()
+    }
+    /// Get the encoded image of this program.
+    pub fn bytes(&self) -> &[u8] {
+	&self.bytes
+    }
+    /// Get the offset within the image of the instruction at a given
+    /// pc.
+    pub fn offset(&self, pc: usize) -> usize {
+	self.offsets[pc]
+    }
+    /// Get the offsets of all instructions within the image, indexed
+    /// by pc.
+    pub fn offsets(&self) -> &[usize] {
+	&self.offsets
+    }
+    /// Determine the pc of the instruction starting at a given offset
+    /// within the image (if any).
+    pub fn pc(&self, offset: usize) -> Option<usize> {
+	self.offsets.binary_search(&offset).ok()
+    }
+    /// Copy the encoded image of this program into memory, starting
+    /// at a given address.
+    pub fn load(&self, memory: &mut Memory, address: usize) {
+	for (i,b) in self.bytes.iter().enumerate() {
+	    memory.write_u8(address+i,*b);
+	}
+    }
+    /// Add a symbol to this program's symbol table.
+    pub fn define(&mut self, symbol: Symbol) {
+	self.symbols.push(symbol);
+    }
+    /// Get all symbols defined by this program (in order of
+    /// definition).
+    pub fn symbols(&self) -> &[Symbol] {
+	&self.symbols
+    }
+    /// Get the symbol with a given name (if any).
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+	self.symbols.iter().find(|s| s.name == name)
+    }
+    /// Determine the label closest to (i.e. at or before) a given pc,
+    /// along with the distance from it.  For example, this allows a
+    /// debugger to display pc `5` as `loop+2`.
+    pub fn symbolize(&self, pc: usize) -> Option<(&Symbol,usize)> {
+	self.symbols.iter()
+	    .filter(|s| s.kind == SymbolKind::Label && s.value <= pc)
+	    .max_by_key(|s| s.value)
+	    .map(|s| (s,pc - s.value))
+    }
+    /// Record that an operand refers to a symbol whose value is not
+    /// yet known.
+    pub fn relocate(&mut self, relocation: Relocation) {
+	self.relocations.push(relocation);
+    }
+    /// Get all relocations required by this program.
+    pub fn relocations(&self) -> &[Relocation] {
+	&self.relocations
+    }
+    /// Record the source location of the instruction at a given pc.
+    pub fn locate(&mut self, pc: usize, location: Location) {
+	self.locations[pc] = Some(location);
+    }
+    /// Get the source location of the instruction at a given pc (if
+    /// known).
+    pub fn location(&self, pc: usize) -> Option<&Location> {
+	self.locations.get(pc).and_then(|l| l.as_ref())
+    }
+    /// Find the first instruction originating from a given source
+    /// line (e.g. for setting a breakpoint).
+    pub fn find(&self, file: &str, line: usize) -> Option<usize> {
+	self.locations.iter().position(|l| l.as_ref().is_some_and(|l| l.file == file && l.line == line))
+    }
+}
+
+// =====================================================
+// Source Locations
+// =====================================================
+
+/// Identifies a line of source from which an instruction originated.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Location {
+    /// Name of the source file.
+    pub file: String,
+    /// Line number (starting from 1).
+    pub line: usize
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"{}:{}",self.file,self.line)
+    }
+}
+
+// =====================================================
+// Symbols & Relocations
+// =====================================================
+
+/// Distinguishes the different kinds of symbol.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum SymbolKind {
+    /// A label, whose value is the pc of the instruction it labels.
+    Label,
+    /// A constant, whose value is fixed.
+    Constant
+}
+
+/// A named value defined by a program.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: usize,
+    pub kind: SymbolKind,
+    /// Indicates whether this symbol is visible to other programs.
+    pub global: bool
+}
+
+/// Identifies an instruction operand whose value depends upon where
+/// the program is placed, or upon a symbol defined elsewhere.  Once
+/// the symbol is resolved, the operand's value becomes
+/// `value(symbol) + addend` or, for pc-relative operands, that less
+/// the pc of the instruction.  Operands referring to external symbols
+/// are encoded as zero until then.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Relocation {
+    /// The pc of the instruction being relocated.
+    pub pc: usize,
+    /// The index of the operand being relocated.
+    pub operand: usize,
+    /// The symbol referred to, where `None` identifies the start of
+    /// this program.
+    pub symbol: Option<String>,
+    /// A constant offset from the symbol.
+    pub addend: isize,
+    /// Indicates whether the operand is pc-relative.
+    pub relative: bool
+}
+
+// =====================================================
+// Decoded Program
+// =====================================================
+
+/// A single decoded instruction within a program image.  This is a
+/// view onto the program, since the operands and microcode of all
+/// instructions are held contiguously within it.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Decoded<'a> {
+    /// Offset of this instruction within the image.
+    pub offset: usize,
+    /// Length (in bytes) of this instruction.
+    pub length: usize,
+    /// Index of this instruction within the instruction set.
+    pub insn: usize,
+    /// Operands of this instruction.
+    pub operands: &'a [usize],
+    /// Microcode implementing this instruction (at its pc), prepared
+    /// for execution.
+    pub microcode: &'a [Threaded],
+    /// Indicates this instruction has been fused with the one
+    /// following it into a superinstruction (see
+    /// `DecodedProgram::fuse()`).
+    pub fused: bool,
+    /// Indicates this instruction has been quickened (see
+    /// `DecodedProgram::quicken()`).
+    pub quick: bool
+}
+
+/// A decoded instruction, as held within a program.  Its operands and
+/// microcode are identified by their position within the program's
+/// arenas.
+#[derive(Clone,Debug)]
+struct Entry {
+    offset: usize,
+    length: usize,
+    insn: usize,
+    operands: Range<usize>,
+    microcode: Range<usize>,
+    fused: bool,
+    quick: bool
+}
+
+/// A program image which has been decoded once, up front, so that
+/// executing it does not require decoding each instruction as it is
+/// fetched.  Instructions are indexed by pc and, if the underlying
+/// image is modified, the affected instructions must be invalidated
+/// (and later refreshed).  Since a pc is the index of an instruction
+/// (rather than its offset within the image), a taken branch already
+/// identifies its target entry without any lookup and, hence, no
+/// branch target cache is needed when interpreting (see `pc()` and
+/// `offset()` for converting to and from offsets).  The operands and
+/// microcode of all instructions are held in two arenas, rather than
+/// allocated for each instruction.  Thus, decoding an image performs
+/// only a handful of allocations, and related data is held close
+/// together.
+pub struct DecodedProgram<'a> {
+    /// Instruction set used for decoding instructions.
+    isa: &'a InstructionSet<'a>,
+    /// Decoded instructions indexed by pc.  Bytes which cannot be
+    /// decoded produce a one byte entry holding the error.
+    entries: Vec<Result<Entry,(usize,DecodeError)>>,
+    /// Operands of all decoded instructions (in order).
+    operands: Vec<usize>,
+    /// Microcode of all decoded instructions (in order).
+    microcode: Vec<Threaded>,
+    /// Offset of the earliest byte which has been invalidated since
+    /// the image was last decoded (if any).
+    dirty: Option<usize>,
+    /// Format cache used when decoding, which is retained so that its
+    /// hits and misses accumulate over every (re)decode.
+    cache: FormatCache
+}
+
+impl<'a> DecodedProgram<'a> {
+    /// Decode a given image from start to finish.
+    pub fn new(isa: &'a InstructionSet<'a>, image: &[u8]) -> Self {
+	let mut r = DecodedProgram{isa,entries:Vec::new(),operands:Vec::new(),microcode:Vec::new(),dirty:None,cache:FormatCache::default()};
+	r.decode_from(image,0);
+	r
+    }
+    /// Decode a given image as for `new()`, but splitting it at the
+    /// given offsets (e.g. known entry points or alignment
+    /// boundaries) and decoding each part in parallel.  This gives the
+    /// same result as `new()`, even if some instruction straddles a
+    /// boundary, but is only faster for large images where boundaries
+    /// are (mostly) instruction boundaries.
+    #[cfg(feature="parallel")]
+    pub fn new_parallel(isa: &'a InstructionSet<'a>, image: &[u8], boundaries: &[usize]) -> Self {
+	let decoded = crate::parallel::split(image.len(),boundaries,|mut offset,end| {
+	    let mut items = Vec::new();
+	    let mut cache = FormatCache::default();
+	    while offset < end {
+		let d = isa.decode_cached(&image[offset..],&mut cache);
+		let length = d.as_ref().map_or(1,|(i,_)| isa.instruction(*i).format().length());
+		items.push((offset,d));
+		offset += length;
+	    }
+	    (items,offset)
+	});
+	let mut r = DecodedProgram{isa,entries:Vec::new(),operands:Vec::new(),microcode:Vec::new(),dirty:None,cache:FormatCache::default()};
+	r.reserve(image.len());
+	let mut buffer = Vec::new();
+	for (offset,d) in decoded {
+	    r.push(offset,d,&mut buffer);
+	}
+	r
+    }
+    /// Get the instruction set used by this program.
+    pub fn isa(&self) -> &'a InstructionSet<'a> {
+	self.isa
+    }
+    /// Get the format cache used when decoding this program, which
+    /// records how often it hit (see `InstructionSet::decode_cached()`).
+    pub fn cache(&self) -> &FormatCache {
+	&self.cache
+    }
+    /// Get the number of entries (i.e. pc values) in this program.
+    pub fn len(&self) -> usize {
+	self.entries.len()
+    }
+    /// Check whether this program contains any entries.
+    pub fn is_empty(&self) -> bool {
+	self.entries.is_empty()
+    }
+    /// Get the decoded instruction at a given pc.  This fails if the
+    /// pc is out of bounds, the instruction was invalidated, or the
+    /// bytes at that point could not be decoded.
+    pub fn get(&self, pc: usize) -> Result<Decoded<'_>,DecodeError> {
+	match self.entries.get(pc) {
+	    None => Err(DecodeError::OutOfBounds(pc)),
+	    Some(entry) => {
+		let (offset,length) = self.extent(entry);
+		if self.dirty.is_some_and(|d| offset + length > d) {
+		    Err(DecodeError::Stale(pc))
+		} else {
+		    match entry {
+			Ok(e) => Ok(Decoded{offset,length,insn:e.insn,
+					    operands:&self.operands[e.operands.clone()],
+					    microcode:&self.microcode[e.microcode.clone()],
+					    fused:e.fused,quick:e.quick}),
+			Err((_,e)) => Err(e.clone())
+		    }
+		}
+	    }
+	}
+    }
+    /// Get the instruction index, microcode and whether the instruction
+    /// is fused or quickened for the instruction at a given pc.  This
+    /// is as for `get()`, but avoids constructing the full view when
+    /// executing.
+    pub(crate) fn threaded(&self, pc: usize) -> Result<(usize,&[Threaded],bool,bool),DecodeError> {
+	match self.entries.get(pc) {
+	    Some(Ok(e)) if self.dirty.is_none() => Ok((e.insn,&self.microcode[e.microcode.clone()],e.fused,e.quick)),
+	    _ => self.get(pc).map(|d| (d.insn,d.microcode,d.fused,d.quick))
+	}
+    }
+    /// Determine the pc of the entry starting at a given offset within
+    /// the image (if any).
+    pub fn pc(&self, offset: usize) -> Option<usize> {
+	self.entries.binary_search_by_key(&offset,|e| self.extent(e).0).ok()
+    }
+    /// Get the offset within the image of the entry at a given pc.
+    pub fn offset(&self, pc: usize) -> usize {
+	self.extent(&self.entries[pc]).0
+    }
+    /// Notify this program that a given range of bytes within the
+    /// image has been overwritten.  Any instructions overlapping this
+    /// range (or following it) are considered stale until the program
+    /// is refreshed.
+    pub fn invalidate(&mut self, offset: usize, length: usize) {
+	if length > 0 {
+	    self.dirty = Some(self.dirty.map_or(offset,|d| d.min(offset)));
+	    // Fused instructions may overlap the range
+	    for e in self.entries.iter_mut().flatten() {
+		e.fused = false;
+	    }
+	}
+    }
+    /// Fuse each instruction with the one following it, whenever they
+    /// form one of the given pairs of instructions (identified by
+    /// their index).  When executed, such a superinstruction executes
+    /// both instructions in a single step, provided the first does
+    /// not branch.  This saves dispatching the second instruction on
+    /// hot paths, where frequent pairs can be determined from a
+    /// previous run (see `Coverage::pairs()`).  For example, fusing
+    /// the two most frequent pairs in the `throughput` example saves
+    /// around 5% per instruction.  Returns the number of
+    /// superinstructions formed.  Invalidating any part of the
+    /// program undoes all fusion.
+    pub fn fuse(&mut self, pairs: &[(usize,usize)]) -> usize {
+	let mut count = 0;
+	for pc in 1..self.entries.len() {
+	    if let Ok(second) = &self.entries[pc] {
+		let second = second.insn;
+		if let Ok(first) = &mut self.entries[pc-1] {
+		    if pairs.contains(&(first.insn,second)) {
+			first.fused = true;
+			first.quick = false;
+			count += 1;
+		    }
+		}
+	    }
+	}
+	count
+    }
+    /// Quicken the instruction at a given pc, such that it executes
+    /// without the usual overheads of an instruction (e.g. checking
+    /// any feature it requires, and clearing scratch registers after
+    /// it).  Since operands are already folded into its microcode
+    /// when decoded, a quickened instruction consists of a single
+    /// handler operating on memory at fixed addresses (e.g. `Add`,
+    /// `Copy` or `Load`).  Hence, only instructions whose microcode
+    /// has this form, which require no feature and which are not
+    /// fused can be quickened.  This is intended for hot instructions
+    /// (see `Tiered`).  Returns whether the instruction was
+    /// quickened.
+    pub fn quicken(&mut self, pc: usize) -> bool {
+	if self.dirty.is_some() { return false; }
+	let Some(Ok(e)) = self.entries.get_mut(pc) else { return false; };
+	let quick = !e.fused && self.isa.instruction(e.insn).feature().is_none()
+	    && matches!(&self.microcode[e.microcode.clone()],[t] if matches!(t.code(),MicroCode::Add(..)|MicroCode::Copy(..)|MicroCode::Load(..)));
+	e.quick = quick;
+	quick
+    }
+    /// Optimize the microcode of every instruction using a given
+    /// optimizer (see `Optimizer`), which reduces the work done when
+    /// executing them without changing their behaviour.  Returns the
+    /// number of microcode removed.  Any instruction which is
+    /// subsequently refreshed is no longer optimized.
+    pub fn optimize(&mut self, optimizer: &Optimizer) -> usize {
+	if self.dirty.is_some() { return 0; }
+	let mut removed = 0;
+	let mut buffer = Vec::new();
+	for e in self.entries.iter_mut().flatten() {
+	    buffer.clear();
+	    buffer.extend(self.microcode[e.microcode.clone()].iter().map(|t| t.code()));
+	    let optimized = optimizer.optimize(&buffer);
+	    for (i,code) in optimized.iter().enumerate() {
+		self.microcode[e.microcode.start+i] = Threaded::new(*code);
+	    }
+	    removed += e.microcode.len() - optimized.len();
+	    e.microcode.end = e.microcode.start + optimized.len();
+	    e.quick &= matches!(optimized[..],[MicroCode::Add(..)|MicroCode::Copy(..)|MicroCode::Load(..)]);
+	}
+	removed
+    }
+    /// Check whether any part of this program has been invalidated.
+    pub fn is_stale(&self) -> bool {
+	self.dirty.is_some()
+    }
+    /// Decode again any stale instructions from a given (updated)
+    /// image.  Since the length of an instruction may have changed,
+    /// everything from the first stale instruction onwards is
+    /// decoded.
+    pub fn refresh(&mut self, image: &[u8]) {
+	if let Some(dirty) = self.dirty.take() {
+	    let pc = self.entries.iter().position(|e| {
+		let (offset,length) = self.extent(e);
+		offset + length > dirty
+	    });
+	    if let Some(pc) = pc {
+		let offset = self.extent(&self.entries[pc]).0;
+		self.entries.truncate(pc);
+		// Discard the operands and microcode of removed entries
+		let last = self.entries.iter().rev().find_map(|e| e.as_ref().ok());
+		self.operands.truncate(last.map_or(0,|e| e.operands.end));
+		self.microcode.truncate(last.map_or(0,|e| e.microcode.end));
+		self.decode_from(image,offset);
+	    }
+	}
+    }
+
+    fn extent(&self, entry: &Result<Entry,(usize,DecodeError)>) -> (usize,usize) {
+	match entry {
+	    Ok(e) => (e.offset,e.length),
+	    Err((offset,_)) => (*offset,1)
+	}
+    }
+
+    fn decode_from(&mut self, image: &[u8], mut offset: usize) {
+	self.reserve(image.len().saturating_sub(offset));
+	let mut buffer = Vec::new();
+	let mut cache = self.cache;
+	while offset < image.len() {
+	    let decoded = self.isa.decode_cached(&image[offset..],&mut cache);
+	    offset += self.push(offset,decoded,&mut buffer);
+	}
+	self.cache = cache;
+    }
+
+    /// Reserve space for decoding a given number of bytes, assuming
+    /// every instruction has the shortest length, and the most
+    /// operands and microcode.
+    fn reserve(&mut self, bytes: usize) {
+	let shortest = self.isa.iter().map(|i| i.format().length()).min().unwrap_or(1).max(1);
+	let arity = self.isa.iter().map(|i| i.format().operands().len()).max().unwrap_or(0);
+	let codes = self.isa.iter().map(|i| i.semantic().iter().map(|c| 2 * c.nested().len()).sum()).max().unwrap_or(0);
+	self.entries.reserve(bytes / shortest);
+	self.operands.reserve((bytes / shortest) * arity);
+	self.microcode.reserve((bytes / shortest) * codes);
+    }
+
+    /// Append the entry for an instruction decoded at a given offset,
+    /// using a given buffer for lowering it.  Returns the number of
+    /// bytes it occupies.
+    fn push(&mut self, offset: usize, decoded: Result<(usize,Vec<usize>),DecodeError>, buffer: &mut Vec<MicroCode>) -> usize {
+	match decoded {
+	    Ok((insn,operands)) => {
+		let instruction = self.isa.instruction(insn);
+		let length = instruction.format().length();
+		#[cfg(feature="log")]
+		log::trace!(target:"virmin::decode","{:#06x}: {} {:?}",offset,instruction.mnemonic(),operands);
+		buffer.clear();
+		instruction.to_microcode_into(self.entries.len(),&operands,buffer);
+		let operands = extend(&mut self.operands,operands);
+		let microcode = extend(&mut self.microcode,buffer.iter().map(|c| Threaded::new(*c)));
+		self.entries.push(Ok(Entry{offset,length,insn,operands,microcode,fused:false,quick:false}));
+		length
+	    }
+	    Err(e) => {
+		#[cfg(feature="log")]
+		log::debug!(target:"virmin::decode","{:#06x}: {}",offset,e);
+		self.entries.push(Err((offset,e)));
+		1
+	    }
+	}
+    }
+}
+
+/// Append some items onto an arena, returning their position within
+/// it.
+fn extend<T,I:IntoIterator<Item=T>>(arena: &mut Vec<T>, items: I) -> Range<usize> {
+    let start = arena.len();
+    arena.extend(items);
+    start..arena.len()
+}
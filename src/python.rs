@@ -0,0 +1,218 @@
+use std::borrow::Cow;
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyIndexError,PyRuntimeError,PyValueError};
+use crate::asm::Assembler;
+use crate::debug;
+use crate::disasm::Disassembler;
+use crate::insn;
+use crate::machine::{OwnedState,RegisterFile,Width,FLAGS,LR,PC,SP};
+use crate::program::DecodedProgram;
+use crate::spec;
+
+// =====================================================
+// Instruction Sets
+// =====================================================
+
+/// An instruction set, as seen from Python.  Instruction sets are
+/// constructed from specifications (see `spec`) and live for the
+/// remainder of the process, since decoded programs borrow them.  For
+/// example:
+///
+/// ```text
+/// import virmin
+/// isa = virmin.InstructionSet.from_toml(open("isa.toml").read())
+/// image = isa.assemble("ldi r1, 5\nadd r1, r1\n")
+/// m = virmin.Machine(isa, image, memory=16)
+/// m.run()
+/// print(m.read(0, 4))
+/// ```
+#[pyclass(name="InstructionSet",module="virmin",frozen)]
+pub struct PyInstructionSet {
+    isa: &'static insn::InstructionSet<'static>
+}
+
+#[pymethods]
+impl PyInstructionSet {
+    /// Construct an instruction set from a TOML specification.
+    #[staticmethod]
+    fn from_toml(text: &str) -> PyResult<Self> {
+	spec::from_toml(text).map(Self::new).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+    /// Construct an instruction set from a JSON specification.
+    #[staticmethod]
+    fn from_json(text: &str) -> PyResult<Self> {
+	spec::from_json(text).map(Self::new).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+    /// Load an instruction set from a TOML or JSON file.
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Self> {
+	spec::load(path).map(Self::new).map_err(|e| PyValueError::new_err(format!("{}: {}",path,e)))
+    }
+    /// Get the mnemonics of all instructions (in order).
+    fn mnemonics(&self) -> Vec<String> {
+	(0..self.isa.len()).map(|i| self.isa.instruction(i).mnemonic().to_string()).collect()
+    }
+    /// Assemble some source into a binary image.
+    fn assemble(&self, source: &str) -> PyResult<Cow<'static,[u8]>> {
+	match Assembler::new(self.isa).assemble(source) {
+	    Ok(program) => Ok(Cow::Owned(program.bytes().to_vec())),
+	    Err(e) => Err(PyValueError::new_err(e.to_string()))
+	}
+    }
+    /// Produce a listing of a binary image.
+    #[pyo3(signature=(image,hex=false))]
+    fn disassemble(&self, image: &[u8], hex: bool) -> String {
+	Disassembler::new(self.isa).hex(hex).listing(image,0)
+    }
+    fn __len__(&self) -> usize {
+	self.isa.len()
+    }
+}
+
+impl PyInstructionSet {
+    fn new(isa: insn::InstructionSet<'static>) -> Self {
+	PyInstructionSet{isa:Box::leak(Box::new(isa))}
+    }
+}
+
+// =====================================================
+// Machines
+// =====================================================
+
+/// A machine executing a binary image, as seen from Python.  The
+/// machine owns its memory, and its state can be inspected and
+/// modified between steps.  Registers are identified as for
+/// `State::read_register()`, hence special registers (e.g. `SP`) can
+/// also be accessed.
+#[pyclass(name="Machine",module="virmin",unsendable)]
+pub struct PyMachine {
+    program: DecodedProgram<'static>,
+    state: OwnedState
+}
+
+#[pymethods]
+impl PyMachine {
+    #[new]
+    #[pyo3(signature=(isa,image,memory=256,registers=0,width=8))]
+    fn py_new(isa: &PyInstructionSet, image: &[u8], memory: usize, registers: usize, width: usize) -> PyResult<Self> {
+	let width = match width {
+	    1 => Width::Byte,
+	    2 => Width::Word,
+	    4 => Width::DoubleWord,
+	    8 => Width::QuadWord,
+	    _ => return Err(PyValueError::new_err(format!("invalid register width {}",width)))
+	};
+	let state = OwnedState::new(memory).with_registers(RegisterFile::new(registers,width));
+	Ok(PyMachine{program:DecodedProgram::new(isa.isa,image),state})
+    }
+    #[getter]
+    fn pc(&self) -> usize {
+	self.state.pc
+    }
+    #[setter]
+    fn set_pc(&mut self, pc: usize) {
+	self.state.pc = pc;
+    }
+    /// Check whether the pc has left the program.
+    #[getter]
+    fn halted(&self) -> bool {
+	self.state.pc >= self.program.len()
+    }
+    /// Get the feature bits of this machine.
+    #[getter]
+    fn features(&self) -> u64 {
+	self.state.features
+    }
+    #[setter]
+    fn set_features(&mut self, features: u64) {
+	self.state.features = features;
+    }
+    /// Execute the instruction identified by the pc.
+    fn step(&mut self) -> PyResult<()> {
+	if self.halted() {
+	    return Err(PyRuntimeError::new_err(format!("halted at pc {}",self.state.pc)));
+	}
+	let program = &self.program;
+	self.state.with_state(|s| s.try_step(program)).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+    }
+    /// Execute until the pc leaves the program (or a given number of
+    /// steps have executed), returning the number of steps executed.
+    #[pyo3(signature=(limit=None))]
+    fn run(&mut self, limit: Option<usize>) -> PyResult<usize> {
+	let limit = limit.unwrap_or(usize::MAX);
+	let program = &self.program;
+	self.state.with_state(|s| s.run(program,limit)).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+    }
+    /// Read a given register.
+    fn read_register(&mut self, register: usize) -> PyResult<u64> {
+	self.check_register(register)?;
+	Ok(self.state.with_state(|s| s.read_register(register)))
+    }
+    /// Write a given register.
+    fn write_register(&mut self, register: usize, value: u64) -> PyResult<()> {
+	self.check_register(register)?;
+	self.state.with_state(|s| s.write_register(register,value));
+	Ok(())
+    }
+    /// Get the name and value of every register, starting with the
+    /// special registers.
+    fn registers(&mut self) -> Vec<(String,u64)> {
+	self.state.with_state(|s| debug::registers(s))
+    }
+    /// Read some bytes of memory.
+    fn read(&self, address: usize, length: usize) -> PyResult<Cow<'_,[u8]>> {
+	match address.checked_add(length).and_then(|end| self.state.memory.get(address..end)) {
+	    Some(bytes) => Ok(Cow::Borrowed(bytes)),
+	    None => Err(PyIndexError::new_err(format!("memory {:#x}..{:#x} out of bounds",address,address.saturating_add(length))))
+	}
+    }
+    /// Write some bytes to memory.
+    fn write(&mut self, address: usize, bytes: &[u8]) -> PyResult<()> {
+	match address.checked_add(bytes.len()).and_then(|end| self.state.memory.get_mut(address..end)) {
+	    Some(slice) => {
+		slice.copy_from_slice(bytes);
+		Ok(())
+	    }
+	    None => Err(PyIndexError::new_err(format!("memory {:#x}..{:#x} out of bounds",address,address.saturating_add(bytes.len()))))
+	}
+    }
+    /// Get the entire contents of memory.
+    fn memory(&self) -> Cow<'_,[u8]> {
+	Cow::Borrowed(&self.state.memory)
+    }
+}
+
+impl PyMachine {
+    fn check_register(&self, register: usize) -> PyResult<()> {
+	if self.state.is_register(register) {
+	    Ok(())
+	} else {
+	    Err(PyIndexError::new_err(format!("invalid register {}",register)))
+	}
+    }
+}
+
+// =====================================================
+// Module
+// =====================================================
+
+/// The `virmin` Python module.  This is built as an extension module
+/// with, for example, `cargo rustc --lib --release --features
+/// python,pyo3/extension-module --crate-type cdylib`, and then
+/// copying the library to `virmin.so`.
+#[pymodule]
+fn virmin(m: &Bound<'_,PyModule>) -> PyResult<()> {
+    m.add_class::<PyInstructionSet>()?;
+    m.add_class::<PyMachine>()?;
+    m.add("PC",PC)?;
+    m.add("SP",SP)?;
+    m.add("FLAGS",FLAGS)?;
+    m.add("LR",LR)?;
+    Ok(())
+}
+
+/// Register the `virmin` module such that it can be imported by an
+/// embedded interpreter (i.e. before it is initialised).
+pub fn append_to_inittab() {
+    pyo3::append_to_inittab!(virmin);
+}
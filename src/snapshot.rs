@@ -0,0 +1,246 @@
+use std::fmt;
+use crate::machine::{OwnedState,RegisterFile,State,Width};
+
+/// Identifies the binary form of a snapshot.
+const MAGIC : [u8;4] = *b"VMSS";
+/// The current version of the snapshot format.  This is incremented
+/// whenever the format changes, such that older snapshots can still
+/// be read (or, at least, rejected meaningfully).
+pub const VERSION : u32 = 1;
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum SnapshotError {
+    /// The snapshot ended unexpectedly.
+    Truncated,
+    /// The snapshot does not begin with the snapshot magic number.
+    BadMagic,
+    /// The snapshot was written by a newer (unsupported) version.
+    Version(u32),
+    /// The snapshot contains a register width which does not exist.
+    InvalidWidth(u8),
+    /// The snapshot contains a register name which is not UTF-8.
+    InvalidName,
+    /// The memory of the snapshot differs in size from that of the
+    /// machine it is restored into.
+    MemorySize{expected: usize, actual: usize}
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    SnapshotError::Truncated => write!(f,"truncated snapshot"),
+	    SnapshotError::BadMagic => write!(f,"not a snapshot"),
+	    SnapshotError::Version(v) => write!(f,"unsupported snapshot version {} (expected at most {})",v,VERSION),
+	    SnapshotError::InvalidWidth(w) => write!(f,"invalid register width ({} bytes)",w),
+	    SnapshotError::InvalidName => write!(f,"invalid register name"),
+	    SnapshotError::MemorySize{expected,actual} => {
+		write!(f,"snapshot has {} bytes of memory but machine has {}",actual,expected)
+	    }
+	}
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// =====================================================
+// Snapshot
+// =====================================================
+
+/// The complete state of a machine at some point, which can be
+/// persisted (e.g. as JSON via serde, or in a compact binary form) and
+/// later restored to resume execution.  Scratch registers are not
+/// included, since they never survive an instruction.  For example:
+///
+/// ```text
+/// let snapshot = Snapshot::of(&state).with_fuel(remaining);
+/// std::fs::write("session.snap",snapshot.to_bytes())?;
+/// ...
+/// let snapshot = Snapshot::from_bytes(&std::fs::read("session.snap")?)?;
+/// let mut machine = snapshot.to_owned_state();
+/// ```
+#[derive(Clone,Debug,PartialEq,serde::Serialize,serde::Deserialize)]
+pub struct Snapshot {
+    /// The version of the format this snapshot was written with.
+    #[serde(deserialize_with="version")]
+    pub version: u32,
+    pub pc: u64,
+    pub sp: u64,
+    pub lr: u64,
+    pub flags: u64,
+    pub features: u64,
+    pub registers: RegisterFile,
+    pub memory: Vec<u8>,
+    /// The number of instructions which the machine may still execute
+    /// (if limited).
+    #[serde(default)]
+    pub fuel: Option<u64>
+}
+
+impl Snapshot {
+    /// Take a snapshot of a given machine.
+    pub fn of(state: &State) -> Self {
+	Snapshot{
+	    version:VERSION,
+	    pc:state.pc as u64,
+	    sp:state.sp as u64,
+	    lr:state.lr as u64,
+	    flags:state.flags,
+	    features:state.features,
+	    registers:state.registers.clone(),
+	    memory:state.data.bytes().to_vec(),
+	    fuel:None
+	}
+    }
+    /// Take a snapshot of a given (owned) machine.
+    pub fn of_owned(state: &OwnedState) -> Self {
+	Snapshot{
+	    version:VERSION,
+	    pc:state.pc as u64,
+	    sp:state.sp as u64,
+	    lr:state.lr as u64,
+	    flags:state.flags,
+	    features:state.features,
+	    registers:state.registers.clone(),
+	    memory:state.memory.clone(),
+	    fuel:None
+	}
+    }
+    /// Record the number of instructions which the machine may still
+    /// execute.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+	self.fuel = Some(fuel);
+	self
+    }
+    /// Construct a machine from this snapshot.
+    pub fn to_owned_state(&self) -> OwnedState {
+	let mut state = OwnedState::new(self.memory.len()).with_registers(self.registers.clone());
+	state.pc = self.pc as usize;
+	state.sp = self.sp as usize;
+	state.lr = self.lr as usize;
+	state.flags = self.flags;
+	state.features = self.features;
+	state.memory = self.memory.clone();
+	state
+    }
+    /// Restore a given machine to this snapshot, which must have the
+    /// same amount of memory.
+    pub fn restore(&self, state: &mut State) -> Result<(),SnapshotError> {
+	if state.data.len() != self.memory.len() {
+	    return Err(SnapshotError::MemorySize{expected:state.data.len(),actual:self.memory.len()});
+	}
+	state.data.write_bytes(0,&self.memory);
+	state.pc = self.pc as usize;
+	state.sp = self.sp as usize;
+	state.lr = self.lr as usize;
+	state.flags = self.flags;
+	state.features = self.features;
+	state.registers = self.registers.clone();
+	Ok(())
+    }
+    /// Encode this snapshot in binary form.  All values are little
+    /// endian, and the layout is fixed for a given version.
+    pub fn to_bytes(&self) -> Vec<u8> {
+	let mut out = Vec::new();
+	out.extend_from_slice(&MAGIC);
+	out.extend_from_slice(&VERSION.to_le_bytes());
+	for v in [self.pc,self.sp,self.lr,self.flags,self.features] {
+	    out.extend_from_slice(&v.to_le_bytes());
+	}
+	out.push(self.fuel.is_some() as u8);
+	out.extend_from_slice(&self.fuel.unwrap_or(0).to_le_bytes());
+	// Registers
+	let registers = &self.registers;
+	out.push(registers.width().bytes() as u8);
+	out.extend_from_slice(&(registers.len() as u64).to_le_bytes());
+	for i in 0..registers.len() {
+	    out.extend_from_slice(&registers.read(i).to_le_bytes());
+	}
+	out.extend_from_slice(&(registers.names().len() as u64).to_le_bytes());
+	for name in registers.names() {
+	    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+	    out.extend_from_slice(name.as_bytes());
+	}
+	// Memory
+	out.extend_from_slice(&(self.memory.len() as u64).to_le_bytes());
+	out.extend_from_slice(&self.memory);
+	out
+    }
+    /// Decode a snapshot from its binary form.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self,SnapshotError> {
+	let mut reader = Reader{bytes,offset:0};
+	if reader.take(4)? != MAGIC {
+	    return Err(SnapshotError::BadMagic);
+	}
+	let version = u32::from_le_bytes(reader.take(4)?.try_into().unwrap());
+	if version > VERSION {
+	    return Err(SnapshotError::Version(version));
+	}
+	let [pc,sp,lr,flags,features] = [reader.u64()?,reader.u64()?,reader.u64()?,reader.u64()?,reader.u64()?];
+	let limited = reader.take(1)?[0] != 0;
+	let fuel = Some(reader.u64()?).filter(|_| limited);
+	// Registers
+	let width = match reader.take(1)?[0] {
+	    1 => Width::Byte,
+	    2 => Width::Word,
+	    4 => Width::DoubleWord,
+	    8 => Width::QuadWord,
+	    w => { return Err(SnapshotError::InvalidWidth(w)); }
+	};
+	let count = reader.length()?;
+	let mut registers = RegisterFile::new(count,width);
+	for i in 0..count {
+	    registers.write(i,reader.u64()?);
+	}
+	let mut names = Vec::new();
+	for _ in 0..reader.length()? {
+	    let n = reader.length()?;
+	    let name = std::str::from_utf8(reader.take(n)?).map_err(|_| SnapshotError::InvalidName)?;
+	    names.push(name);
+	}
+	let registers = registers.with_names(&names);
+	// Memory
+	let n = reader.length()?;
+	let memory = reader.take(n)?.to_vec();
+	Ok(Snapshot{version,pc,sp,lr,flags,features,registers,memory,fuel})
+    }
+}
+
+/// Reject snapshots written by a newer version of the format.
+fn version<'de,D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32,D::Error> {
+    let version = <u32 as serde::Deserialize>::deserialize(deserializer)?;
+    if version > VERSION {
+	return Err(serde::de::Error::custom(SnapshotError::Version(version)));
+    }
+    Ok(version)
+}
+
+/// Reads successive values from the binary form of a snapshot.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8],SnapshotError> {
+	let end = self.offset.checked_add(n).ok_or(SnapshotError::Truncated)?;
+	let bytes = self.bytes.get(self.offset..end).ok_or(SnapshotError::Truncated)?;
+	self.offset = end;
+	Ok(bytes)
+    }
+    fn u64(&mut self) -> Result<u64,SnapshotError> {
+	Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    /// Read a length, which cannot exceed the bytes remaining (since
+    /// every element occupies at least one byte).
+    fn length(&mut self) -> Result<usize,SnapshotError> {
+	let n = self.u64()?;
+	match usize::try_from(n) {
+	    Ok(n) if n <= self.bytes.len() - self.offset => Ok(n),
+	    _ => Err(SnapshotError::Truncated)
+	}
+    }
+}
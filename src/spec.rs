@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use serde::Deserialize;
+use crate::insn::{AbstractMicroCode,BitOrder,ByteOrder,Constraint,FieldKind,Format,FormatError,Metadata};
+use crate::insn::{InstructionSet,InstructionSetBuilder,IsaError,Operand};
+use crate::verify::Property;
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum SpecError {
+    /// The specification file could not be read.
+    Io(String),
+    /// The specification is not valid TOML / JSON, or does not have
+    /// the expected structure.
+    Parse(String),
+    /// Two formats were given the same name.
+    DuplicateFormat(String),
+    /// A format was malformed.
+    Format{name: String, error: FormatError},
+    /// An instruction refers to a format which was not defined.
+    UnknownFormat{mnemonic: String, format: String},
+    /// The resulting instruction set was malformed.
+    Isa(Vec<IsaError>)
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    SpecError::Io(e) => write!(f,"cannot read specification ({})",e),
+	    SpecError::Parse(e) => write!(f,"invalid specification ({})",e),
+	    SpecError::DuplicateFormat(n) => write!(f,"duplicate format \"{}\"",n),
+	    SpecError::Format{name,error} => write!(f,"malformed format \"{}\" ({})",name,error),
+	    SpecError::UnknownFormat{mnemonic,format} => {
+		write!(f,"unknown format \"{}\" for instruction \"{}\"",format,mnemonic)
+	    }
+	    SpecError::Isa(es) => {
+		let es : Vec<String> = es.iter().map(|e| e.to_string()).collect();
+		write!(f,"{}",es.join(", "))
+	    }
+	}
+    }
+}
+
+impl std::error::Error for SpecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    SpecError::Format{error,..} => Some(error),
+	    _ => None
+	}
+    }
+}
+
+// =====================================================
+// Specification
+// =====================================================
+
+/// A declarative description of an instruction set.  Formats are
+/// given names, which instructions then refer to.  For example:
+///
+/// ```toml
+/// [[formats]]
+/// name = "rr"
+/// width = 1
+/// opcode = 2
+/// fields = [ { name = "rd", bits = 3, constraints = [ "NonZero" ] }, { name = "rs", bits = 3 } ]
+///
+/// [[instructions]]
+/// mnemonic = "mov"
+/// format = "rr"
+/// semantics = [ { Copy = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+/// metadata = { category = "Move", description = "Copy register" }
+///
+/// [[pseudos]]
+/// mnemonic = "nop"
+/// expansion = [ [ "mov", [ { Const = 0 }, { Const = 0 } ] ] ]
+/// ```
+///
+/// Opcodes are assigned to instructions in order of definition (as
+/// usual).
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IsaSpec {
+    #[serde(default)]
+    formats: Vec<FormatSpec>,
+    #[serde(default)]
+    instructions: Vec<InstructionSpec>,
+    #[serde(default)]
+    pseudos: Vec<PseudoSpec>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FormatSpec {
+    name: String,
+    /// Width (in bytes) of the instruction word.
+    width: u8,
+    /// Size (in bits) of the opcode field.
+    opcode: u8,
+    #[serde(default)]
+    fields: Vec<FieldSpec>,
+    #[serde(default)]
+    extensions: Vec<ExtensionSpec>,
+    byte_order: Option<ByteOrder>,
+    bit_order: Option<BitOrder>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldSpec {
+    #[serde(default)]
+    name: String,
+    bits: u8,
+    #[serde(default="register")]
+    kind: FieldKind,
+    #[serde(default)]
+    constraints: Vec<Constraint>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtensionSpec {
+    #[serde(default)]
+    name: String,
+    bytes: u8,
+    #[serde(default="register")]
+    kind: FieldKind,
+    #[serde(default)]
+    constraints: Vec<Constraint>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstructionSpec {
+    mnemonic: String,
+    format: String,
+    #[serde(default)]
+    semantics: Vec<AbstractMicroCode>,
+    #[serde(default)]
+    metadata: Metadata,
+    /// Feature bit required to execute this instruction.
+    feature: Option<u8>,
+    #[serde(default)]
+    properties: Vec<Property>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PseudoSpec {
+    mnemonic: String,
+    expansion: Vec<(String,Vec<Operand>)>
+}
+
+fn register() -> FieldKind {
+    FieldKind::Register
+}
+
+impl FormatSpec {
+    fn build(&self) -> Result<Format,SpecError> {
+	let mut builder = Format::builder().label(&self.name).width_bytes(self.width).opcode_bits(self.opcode);
+	if let Some(order) = self.byte_order {
+	    builder = builder.byte_order(order);
+	}
+	if let Some(order) = self.bit_order {
+	    builder = builder.bit_order(order);
+	}
+	for f in &self.fields {
+	    builder = builder.field(&f.name,f.bits,f.kind);
+	    for c in &f.constraints {
+		builder = builder.constraint(&f.name,c.clone());
+	    }
+	}
+	for e in &self.extensions {
+	    builder = builder.extension(&e.name,e.bytes,e.kind);
+	    for c in &e.constraints {
+		builder = builder.constraint(&e.name,c.clone());
+	    }
+	}
+	builder.build().map_err(|error| SpecError::Format{name:self.name.clone(),error})
+    }
+}
+
+impl IsaSpec {
+    fn build(self) -> Result<InstructionSet<'static>,SpecError> {
+	let mut formats = BTreeMap::new();
+	for f in &self.formats {
+	    if formats.insert(f.name.clone(),f.build()?).is_some() {
+		return Err(SpecError::DuplicateFormat(f.name.clone()));
+	    }
+	}
+	let mut builder = InstructionSetBuilder::new();
+	for i in &self.instructions {
+	    match formats.get(&i.format) {
+		Some(format) => {
+		    builder = builder.instruction(&i.mnemonic,format,&i.semantics).metadata(i.metadata.clone());
+		    if let Some(feature) = i.feature {
+			builder = builder.requires(feature);
+		    }
+		    for p in &i.properties {
+			builder = builder.property(p.clone());
+		    }
+		}
+		None => {
+		    return Err(SpecError::UnknownFormat{mnemonic:i.mnemonic.clone(),format:i.format.clone()});
+		}
+	    }
+	}
+	for p in &self.pseudos {
+	    let expansion : Vec<(&str,&[Operand])> = p.expansion.iter().map(|(m,ops)| (m.as_str(),ops.as_slice())).collect();
+	    builder = builder.pseudo(&p.mnemonic,&expansion);
+	}
+	builder.build().map_err(SpecError::Isa)
+    }
+}
+
+// =====================================================
+// Loading
+// =====================================================
+
+/// Construct an instruction set from a specification given in TOML.
+pub fn from_toml(text: &str) -> Result<InstructionSet<'static>,SpecError> {
+    let spec : IsaSpec = toml::from_str(text).map_err(|e| SpecError::Parse(e.message().to_string()))?;
+    spec.build()
+}
+
+/// Construct an instruction set from a specification given in JSON.
+pub fn from_json(text: &str) -> Result<InstructionSet<'static>,SpecError> {
+    let spec : IsaSpec = serde_json::from_str(text).map_err(|e| SpecError::Parse(e.to_string()))?;
+    spec.build()
+}
+
+/// Construct an instruction set from a specification file.  Files
+/// ending in `.json` are read as JSON, whilst all others are read as
+/// TOML.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<InstructionSet<'static>,SpecError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| SpecError::Io(e.to_string()))?;
+    match path.extension() {
+	Some(ext) if ext == "json" => from_json(&text),
+	_ => from_toml(&text)
+    }
+}
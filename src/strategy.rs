@@ -0,0 +1,251 @@
+use crate::insn::{BitOrder,ByteOrder,Constraint,Field,FieldKind,Format,InstructionSet,Operand};
+use crate::machine::{TEMPS,Width};
+use crate::program::Program;
+
+/// The largest number of operand fields held in the instruction word
+/// of a generated format.
+pub const MAX_FIELDS : usize = 4;
+/// The deepest operand expression generated.
+pub const MAX_DEPTH : usize = 4;
+/// The largest operand variable (i.e. `Var(n)`) generated.  This keeps
+/// the arity of generated expressions small.
+pub const MAX_VAR : usize = 3;
+
+// =====================================================
+// Encoded
+// =====================================================
+
+/// An instruction from a given instruction set, along with operands
+/// (in two's complement form) which are valid for its format and its
+/// encoding.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Encoded {
+    /// The index of the instruction within its instruction set.
+    pub index: usize,
+    pub operands: Vec<usize>,
+    pub bytes: Vec<u8>
+}
+
+impl Encoded {
+    /// Encode the instruction at a given index of an instruction set
+    /// with operands derived from some raw values (one per field).
+    /// Each raw value is truncated to the width of its field (and sign
+    /// extended, if appropriate) and then moved as little as possible
+    /// to satisfy the field's constraints.  This fails if no such
+    /// value could be found.
+    pub fn new(isa: &InstructionSet, index: usize, raw: &[usize]) -> Option<Self> {
+	let format = isa.instruction(index).format();
+	let operands : Option<Vec<usize>> = format.operands().iter().zip(raw).map(|(f,r)| constrain(f,*r)).collect();
+	let operands = operands?;
+	let bytes = format.encode(isa.opcode(index),&operands).ok()?;
+	Some(Encoded{index,operands,bytes})
+    }
+}
+
+/// Construct a program from a sequence of encoded instructions.
+pub fn assemble<'a>(isa: &'a InstructionSet<'a>, insns: &[Encoded]) -> Program<'a> {
+    let mut program = Program::new(isa);
+    for insn in insns {
+	program.push(isa.instruction(insn.index).mnemonic(),&insn.operands).unwrap();
+    }
+    program
+}
+
+/// Determine a value for a given field from some raw bits, as for
+/// `Encoded::new()`.
+fn constrain(field: &Field, raw: usize) -> Option<usize> {
+    let bits = field.bits().value() as u32;
+    let value = field.extend(raw & usize::MAX.checked_shr(usize::BITS - bits).unwrap_or(0));
+    if field.satisfies(value) {
+	return Some(value);
+    }
+    let signed = field.kind() == FieldKind::SignedImmediate;
+    let mut v = if signed { value as isize as i128 } else { value as i128 };
+    let mut step = 1;
+    for c in field.constraints() {
+	match c {
+	    Constraint::Range(lo,hi) if lo <= hi => { v = v.clamp(*lo,*hi); }
+	    Constraint::Aligned(n) if *n > 0 => { step = *n as i128; }
+	    _ => {}
+	}
+    }
+    // Try the aligned values either side, followed by the smallest
+    // non-zero value.
+    let down = v - v.rem_euclid(step);
+    [down,down + step,step].into_iter()
+	.map(|v| v as usize)
+	.find(|v| field.fits(*v) && field.satisfies(*v))
+}
+
+/// Construct a format from a description which is adjusted so that it
+/// is always valid.  Specifically, the width is between one and eight
+/// bytes, and the opcode and fields are truncated (or dropped) to fit
+/// within it.
+fn format(width: u8, opcode: u8, fields: &[(u8,FieldKind)], extension: Option<(u8,FieldKind)>, orders: (ByteOrder,BitOrder)) -> Format {
+    let width = width.clamp(1,8);
+    let opcode = opcode.clamp(1,width * 8);
+    let mut remaining = width * 8 - opcode;
+    let mut builder = Format::builder().width_bytes(width).opcode_bits(opcode).byte_order(orders.0).bit_order(orders.1);
+    for (i,(bits,kind)) in fields.iter().take(MAX_FIELDS).enumerate() {
+	let bits = (*bits).clamp(1,64).min(remaining);
+	if bits == 0 {
+	    break;
+	}
+	builder = builder.field(&format!("f{}",i),bits,*kind);
+	remaining -= bits;
+    }
+    if let Some((bytes,kind)) = extension {
+	builder = builder.extension("ext",bytes.clamp(1,8),kind);
+    }
+    builder.build().unwrap()
+}
+
+const KINDS : [FieldKind;3] = [FieldKind::Register,FieldKind::Immediate,FieldKind::SignedImmediate];
+const WIDTHS : [Width;4] = [Width::Byte,Width::Word,Width::DoubleWord,Width::QuadWord];
+
+// =====================================================
+// Arbitrary
+// =====================================================
+
+#[cfg(feature="arbitrary")]
+mod arbitrary_impls {
+    use arbitrary::{Arbitrary,Result,Unstructured};
+    use super::*;
+
+    impl<'a> Arbitrary<'a> for Format {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+	    let width = u.int_in_range(1..=4)?;
+	    let opcode = u.int_in_range(1..=8)?;
+	    let mut fields = Vec::new();
+	    for _ in 0..u.int_in_range(0..=MAX_FIELDS)? {
+		fields.push((u.int_in_range(1..=16)?,*u.choose(&KINDS)?));
+	    }
+	    let extension = if u.ratio(1,4)? {
+		Some((*u.choose(&[1,2,4,8])?,*u.choose(&KINDS)?))
+	    } else {
+		None
+	    };
+	    let byte_order = *u.choose(&[ByteOrder::LittleEndian,ByteOrder::BigEndian])?;
+	    let bit_order = *u.choose(&[BitOrder::LsbFirst,BitOrder::MsbFirst])?;
+	    Ok(format(width,opcode,&fields,extension,(byte_order,bit_order)))
+	}
+    }
+
+    impl<'a> Arbitrary<'a> for Operand {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+	    operand(u,MAX_DEPTH)
+	}
+    }
+
+    fn operand(u: &mut Unstructured, depth: usize) -> Result<Operand> {
+	let last = if depth == 0 { 6 } else { 13 };
+	let operand = match u.int_in_range(0..=last)? {
+	    0 => Operand::Const(u.arbitrary()?),
+	    1 => Operand::Var(u.int_in_range(0..=MAX_VAR)?),
+	    2 => Operand::Pc,
+	    3 => Operand::Sp,
+	    4 => Operand::Flags,
+	    5 => Operand::Lr,
+	    6 => Operand::Temp(u.int_in_range(0..=TEMPS-1)?),
+	    7 => Operand::Add(Box::new(operand(u,depth-1)?),Box::new(operand(u,depth-1)?)),
+	    8 => Operand::Mul(Box::new(operand(u,depth-1)?),Box::new(operand(u,depth-1)?)),
+	    9 => Operand::Shl(Box::new(operand(u,depth-1)?),Box::new(operand(u,depth-1)?)),
+	    10 => Operand::RegSlot(Box::new(operand(u,depth-1)?),*u.choose(&WIDTHS)?),
+	    11 => Operand::PcRel(Box::new(operand(u,depth-1)?)),
+	    12 => Operand::SExt(Box::new(operand(u,depth-1)?),u.int_in_range(0..=64)?),
+	    _ => Operand::Bits(Box::new(operand(u,depth-1)?),u.int_in_range(0..=63)?,u.int_in_range(0..=63)?)
+	};
+	Ok(operand)
+    }
+
+    /// Generate an instruction from a given instruction set, along
+    /// with valid operands and its encoding.
+    pub fn arbitrary_instruction(u: &mut Unstructured, isa: &InstructionSet) -> Result<Encoded> {
+	let index = u.choose_index(isa.len())?;
+	let mut raw = Vec::new();
+	for _ in isa.instruction(index).format().operands() {
+	    raw.push(u.arbitrary()?);
+	}
+	Encoded::new(isa,index,&raw).ok_or(arbitrary::Error::IncorrectFormat)
+    }
+
+    /// Generate a sequence of at most `max` instructions from a given
+    /// instruction set.
+    pub fn arbitrary_program(u: &mut Unstructured, isa: &InstructionSet, max: usize) -> Result<Vec<Encoded>> {
+	let mut insns = Vec::new();
+	for _ in 0..u.int_in_range(0..=max)? {
+	    insns.push(arbitrary_instruction(u,isa)?);
+	}
+	Ok(insns)
+    }
+}
+
+#[cfg(feature="arbitrary")]
+pub use arbitrary_impls::{arbitrary_instruction,arbitrary_program};
+
+// =====================================================
+// Proptest
+// =====================================================
+
+#[cfg(feature="proptest")]
+mod proptest_strategies {
+    use proptest::prelude::*;
+    use proptest::collection::vec;
+    use proptest::sample::select;
+    use super::*;
+
+    /// Generate well-formed formats of up to four bytes.
+    pub fn formats() -> impl Strategy<Value=Format> {
+	let fields = vec((1u8..=16,select(&KINDS[..])),0..=MAX_FIELDS);
+	let extension = proptest::option::weighted(0.25,(select(&[1u8,2,4,8][..]),select(&KINDS[..])));
+	let byte_order = select(&[ByteOrder::LittleEndian,ByteOrder::BigEndian][..]);
+	let bit_order = select(&[BitOrder::LsbFirst,BitOrder::MsbFirst][..]);
+	(1u8..=4,1u8..=8,fields,extension,(byte_order,bit_order))
+	    .prop_map(|(w,o,fs,e,orders)| format(w,o,&fs,e,orders))
+    }
+
+    /// Generate operand expressions up to `MAX_DEPTH` deep.
+    pub fn operands() -> impl Strategy<Value=Operand> {
+	let leaf = prop_oneof![
+	    any::<usize>().prop_map(Operand::Const),
+	    (0..=MAX_VAR).prop_map(Operand::Var),
+	    Just(Operand::Pc),
+	    Just(Operand::Sp),
+	    Just(Operand::Flags),
+	    Just(Operand::Lr),
+	    (0..TEMPS).prop_map(Operand::Temp)
+	];
+	leaf.prop_recursive(MAX_DEPTH as u32,32,2,|inner| {
+	    let boxed = inner.prop_map(Box::new);
+	    prop_oneof![
+		(boxed.clone(),boxed.clone()).prop_map(|(l,r)| Operand::Add(l,r)),
+		(boxed.clone(),boxed.clone()).prop_map(|(l,r)| Operand::Mul(l,r)),
+		(boxed.clone(),boxed.clone()).prop_map(|(l,r)| Operand::Shl(l,r)),
+		(boxed.clone(),select(&WIDTHS[..])).prop_map(|(o,w)| Operand::RegSlot(o,w)),
+		boxed.clone().prop_map(Operand::PcRel),
+		(boxed.clone(),0u8..=64).prop_map(|(o,n)| Operand::SExt(o,n)),
+		(boxed,0u8..64,0u8..64).prop_map(|(o,hi,lo)| Operand::Bits(o,hi,lo))
+	    ]
+	})
+    }
+
+    /// Generate instructions from a given (non-empty) instruction
+    /// set, along with valid operands and their encodings.
+    /// Instructions whose constraints could not be satisfied are
+    /// rejected.
+    pub fn instructions<'a>(isa: &'a InstructionSet<'a>) -> impl Strategy<Value=Encoded> + 'a {
+	(0..isa.len()).prop_flat_map(move |index| {
+	    let arity = isa.instruction(index).format().operands().len();
+	    (Just(index),vec(any::<usize>(),arity))
+	}).prop_filter_map("unsatisfiable constraints",move |(index,raw)| Encoded::new(isa,index,&raw))
+    }
+
+    /// Generate sequences of at most `max` instructions from a given
+    /// (non-empty) instruction set.
+    pub fn programs<'a>(isa: &'a InstructionSet<'a>, max: usize) -> impl Strategy<Value=Vec<Encoded>> + 'a {
+	vec(instructions(isa),0..=max)
+    }
+}
+
+#[cfg(feature="proptest")]
+pub use proptest_strategies::{formats,instructions,operands,programs};
@@ -0,0 +1,323 @@
+use std::fmt;
+use crate::domain::Countable;
+use crate::insn::{AbstractMicroCode,DecodeError,EncodeError,FieldKind,InstructionSet,Operand};
+use crate::machine::{self,MicroCode,Width};
+use crate::program::Program;
+
+/// The maximum number of operand combinations per instruction for
+/// which `assert_roundtrip()` will use exhaustive checking.  Beyond
+/// this, it falls back to random sampling.
+pub const EXHAUSTIVE_LIMIT : u64 = 65536;
+
+/// Number of attempts made by a `Generator` to choose operands for a
+/// given instruction, before trying another.
+pub const ATTEMPTS : usize = 16;
+
+/// Number of random samples per instruction taken by
+/// `assert_roundtrip()` when exhaustive checking is not feasible.
+pub const RANDOM_SAMPLES : usize = 1024;
+
+// =====================================================
+// Errors
+// =====================================================
+
+/// Describes a failure to round trip a given instruction.
+#[derive(Clone,Debug,PartialEq)]
+pub enum RoundTripError {
+    /// The instruction could not be encoded.
+    Encode{mnemonic: String, operands: Vec<usize>, error: EncodeError},
+    /// The encoded instruction could not be decoded.
+    Decode{mnemonic: String, operands: Vec<usize>, bytes: Vec<u8>, error: DecodeError},
+    /// The encoded instruction was decoded into a different
+    /// instruction and/or operands.
+    Mismatch{mnemonic: String, operands: Vec<usize>, bytes: Vec<u8>, decoded: (String,Vec<usize>)}
+}
+
+impl fmt::Display for RoundTripError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    RoundTripError::Encode{mnemonic,operands,error} => {
+		write!(f,"{} {:?} failed to encode ({})",mnemonic,operands,error)
+	    }
+	    RoundTripError::Decode{mnemonic,operands,bytes,error} => {
+		write!(f,"{} {:?} encoded as {:02x?} failed to decode ({})",mnemonic,operands,bytes,error)
+	    }
+	    RoundTripError::Mismatch{mnemonic,operands,bytes,decoded} => {
+		write!(f,"{} {:?} encoded as {:02x?} decoded as {} {:?}",mnemonic,operands,bytes,decoded.0,decoded.1)
+	    }
+	}
+    }
+}
+
+impl std::error::Error for RoundTripError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+	match &self {
+	    RoundTripError::Encode{error,..} => Some(error),
+	    RoundTripError::Decode{error,..} => Some(error),
+	    _ => None
+	}
+    }
+}
+
+// =====================================================
+// Round Tripping
+// =====================================================
+
+/// Check that a given instruction (identified by its index) with the
+/// given operands can be encoded and decoded back again.
+pub fn roundtrip(isa: &InstructionSet, index: usize, operands: &[usize]) -> Result<(),RoundTripError> {
+    let mnemonic = isa.instruction(index).mnemonic().to_string();
+    let operands = operands.to_vec();
+    let bytes = match isa.encode(&mnemonic,&operands) {
+	Ok(bytes) => bytes,
+	Err(error) => { return Err(RoundTripError::Encode{mnemonic,operands,error}); }
+    };
+    match isa.decode(&bytes) {
+	Ok((i,ops)) if i == index && ops == operands => Ok(()),
+	Ok((i,ops)) => {
+	    let decoded = (isa.instruction(i).mnemonic().to_string(),ops);
+	    Err(RoundTripError::Mismatch{mnemonic,operands,bytes,decoded})
+	}
+	Err(error) => Err(RoundTripError::Decode{mnemonic,operands,bytes,error})
+    }
+}
+
+/// Check that every instruction in a given set round trips for every
+/// possible combination of operand values.  This is only feasible for
+/// instructions with small operand fields.  Returns the number of
+/// encodings checked.
+pub fn check_exhaustive(isa: &InstructionSet) -> Result<usize,RoundTripError> {
+    let mut count = 0;
+    for index in 0..isa.len() {
+	let fields = isa.instruction(index).format().operands();
+	let limits : Vec<u64> = fields.iter().map(|f| f.checked_count_u64().unwrap_or(u64::MAX)).collect();
+	let mut raw = vec![0u64; fields.len()];
+	loop {
+	    let operands : Vec<usize> = fields.iter().zip(&raw).map(|(f,r)| f.extend(*r as usize)).collect();
+	    roundtrip(isa,index,&operands)?;
+	    count += 1;
+	    // Advance to the next combination of raw values
+	    let mut i = 0;
+	    while i < raw.len() {
+		raw[i] += 1;
+		if raw[i] < limits[i] { break; }
+		raw[i] = 0;
+		i += 1;
+	    }
+	    if i == raw.len() { break; }
+	}
+    }
+    Ok(count)
+}
+
+/// Check that every instruction in a given set round trips for a
+/// number of randomly chosen operand values.  The choice is
+/// determined entirely by the given seed, thus making failures
+/// reproducible.  Returns the number of encodings checked.
+pub fn check_random(isa: &InstructionSet, samples: usize, seed: u64) -> Result<usize,RoundTripError> {
+    let mut rng = XorShift::new(seed);
+    let mut count = 0;
+    for index in 0..isa.len() {
+	let fields = isa.instruction(index).format().operands();
+	for _ in 0..samples {
+	    let operands : Vec<usize> = fields.iter().map(|f| {
+		let n = f.bits().value() as u32;
+		let raw = if n >= 64 { rng.next() } else { rng.next() & ((1 << n) - 1) };
+		f.extend(raw as usize)
+	    }).collect();
+	    roundtrip(isa,index,&operands)?;
+	    count += 1;
+	}
+    }
+    Ok(count)
+}
+
+/// Assert that every instruction in a given set round trips.  This
+/// checks exhaustively where the number of operand combinations is
+/// small enough, and randomly otherwise.  Panics with a description
+/// of the first failure found.
+pub fn assert_roundtrip(isa: &InstructionSet) {
+    let exhaustive = (0..isa.len()).all(|i| {
+	let format = isa.instruction(i).format();
+	let combinations = format.operands().iter().try_fold(1u64,|n,f| n.checked_mul(f.checked_count_u64()?));
+	combinations.is_some_and(|n| n <= EXHAUSTIVE_LIMIT)
+    });
+    let result = if exhaustive {
+	check_exhaustive(isa)
+    } else {
+	check_random(isa,RANDOM_SAMPLES,0x5eed)
+    };
+    if let Err(e) = result {
+	panic!("roundtrip failure: {}",e);
+    }
+}
+
+// =====================================================
+// Program Generation
+// =====================================================
+
+/// Generates random (but well-formed) programs for a given
+/// instruction set, for use in fuzzing or differential testing.  Each
+/// operand is chosen such that it fits in its field and, based upon
+/// how it is used in the instruction's semantics, such that: memory
+/// accesses lie within a given memory size; and branch targets lie
+/// within the program.  Thus, executing a generated program never
+/// accesses memory out of bounds, or branches outside the program.
+/// As for `check_random()`, programs are determined entirely by the
+/// given seed.  For example:
+///
+/// ```text
+/// let mut generator = Generator::new(&isa,0x5eed).memory(256);
+/// let program = generator.generate(100).unwrap();
+/// ```
+pub struct Generator<'a> {
+    isa: &'a InstructionSet<'a>,
+    rng: XorShift,
+    /// Size (in bytes) of the memory which programs may access.
+    memory: usize,
+    /// Number of registers which programs may access.
+    registers: usize
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(isa: &'a InstructionSet<'a>, seed: u64) -> Self {
+	Generator{isa,rng:XorShift::new(seed),memory:usize::MAX,registers:usize::MAX}
+    }
+    /// Set the size (in bytes) of the memory which generated programs
+    /// may access (default is unbounded).
+    pub fn memory(mut self, size: usize) -> Self {
+	self.memory = size;
+	self
+    }
+    /// Set the number of registers which generated programs may access
+    /// (default is unbounded).
+    pub fn registers(mut self, count: usize) -> Self {
+	self.registers = count;
+	self
+    }
+    /// Generate a program consisting of a given number of
+    /// instructions.  This fails if, at some point, no instruction
+    /// could be chosen which satisfies the constraints (e.g. because
+    /// the memory is smaller than any possible address).
+    pub fn generate(&mut self, length: usize) -> Option<Program<'a>> {
+	let mut program = Program::new(self.isa);
+	for pc in 0..length {
+	    // Try each instruction in turn, starting from a random one
+	    let start = self.rng.next() as usize % self.isa.len().max(1);
+	    let (index,operands) = (0..self.isa.len()).map(|i| (start + i) % self.isa.len()).find_map(|i| {
+		self.operands(i,pc,length).map(|ops| (i,ops))
+	    })?;
+	    program.push(self.isa.instruction(index).mnemonic(),&operands).ok()?;
+	}
+	Some(program)
+    }
+    /// Choose operands for a given instruction placed at a given pc,
+    /// or `None` if no suitable operands were found.  Operands used
+    /// directly as addresses or branch targets are chosen from their
+    /// permitted ranges.  However, those used in compound expressions
+    /// (e.g. `Var(0)*4`) are chosen at random until the resulting
+    /// microcode is within bounds.
+    fn operands(&mut self, index: usize, pc: usize, length: usize) -> Option<Vec<usize>> {
+	let ranges = self.ranges(index,pc,length)?;
+	let insn = self.isa.instruction(index);
+	let mut microcode = Vec::new();
+	for _ in 0..ATTEMPTS {
+	    let operands : Vec<usize> = ranges.iter().map(|(lo,hi)| {
+		let span = (hi - lo) as u128 + 1;
+		(*lo + (self.rng.next() as u128 % span) as i128) as usize
+	    }).collect();
+	    let reserved = insn.format().operands().iter().zip(&operands).any(|(f,v)| !f.satisfies(*v));
+	    if reserved { continue; }
+	    microcode.clear();
+	    insn.to_microcode_into(pc,&operands,&mut microcode);
+	    if microcode.iter().all(|c| self.legal(c,pc,length)) {
+		return Some(operands);
+	    }
+	}
+	None
+    }
+    /// Check that a concrete microcode (at a given pc) accesses memory
+    /// and registers within bounds, and branches within the program.
+    fn legal(&self, code: &MicroCode, pc: usize, length: usize) -> bool {
+	let fits = |x: &usize, w: &Width| x.checked_add(w.bytes()).is_some_and(|e| e <= self.memory);
+	// The pc is excluded, since writing it could branch anywhere
+	let reg = |r: &usize| *r < self.registers || (machine::temp(machine::TEMPS-1)..=machine::SP).contains(r);
+	match code {
+	    MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w)|MicroCode::Copy(x,y,w) => fits(x,w) && fits(y,w),
+	    MicroCode::Load(x,_,w) => fits(x,w),
+	    MicroCode::RegAdd(r,s)|MicroCode::RegAlu(_,r,s)|MicroCode::RegCopy(r,s)
+		|MicroCode::RegFetchIndirect(r,s,_)|MicroCode::RegStoreIndirect(s,r,_) => reg(r) && reg(s),
+	    MicroCode::RegFetch(r,x,w)|MicroCode::RegStore(x,r,w) => reg(r) && fits(x,w),
+	    MicroCode::RegLoad(r,_)|MicroCode::RegSkipIfZero(r,_) => reg(r),
+	    MicroCode::SkipIfZero(x,w,_) => fits(x,w),
+	    MicroCode::Skip(_) => true,
+	    MicroCode::Goto(t) => *t < length,
+	    MicroCode::Jump(o) => pc.checked_add_signed(*o).is_some_and(|t| t < length)
+	}
+    }
+    /// Determine the permitted (inclusive) range of each operand of a
+    /// given instruction placed at a given pc, or `None` if some
+    /// operand has no permitted value.
+    fn ranges(&self, index: usize, pc: usize, length: usize) -> Option<Vec<(i128,i128)>> {
+	let insn = self.isa.instruction(index);
+	let mut ranges : Vec<(i128,i128)> = insn.format().operands().iter().map(|f| {
+	    let n = f.bits().value().min(usize::BITS as u8) as u32;
+	    match f.kind() {
+		FieldKind::SignedImmediate => (-(1i128 << (n-1)),(1i128 << (n-1)) - 1),
+		_ => (0,(1i128 << n) - 1)
+	    }
+	}).collect();
+	let (memory,length,pc) = (self.memory as i128,length as i128,pc as i128);
+	for code in insn.semantic() {
+	    let (var,lo,hi) = match code {
+		AbstractMicroCode::Add(x,y,w)|AbstractMicroCode::Alu(_,x,y,w)|AbstractMicroCode::Copy(x,y,w) => {
+		    for o in [x,y] {
+			if let Operand::Var(v) = o { restrict(&mut ranges[*v],0,memory - w.bytes() as i128); }
+		    }
+		    continue;
+		}
+		AbstractMicroCode::Load(Operand::Var(v),_,w) => (v,0,memory - w.bytes() as i128),
+		AbstractMicroCode::RegFetch(_,Operand::Var(v),w)|AbstractMicroCode::RegStore(Operand::Var(v),_,w) => (v,0,memory - w.bytes() as i128),
+		AbstractMicroCode::Goto(Operand::Var(v)) => (v,0,length - 1),
+		AbstractMicroCode::Goto(Operand::PcRel(o)) => match o.as_ref() {
+		    Operand::Var(v) => (v,-pc,length - 1 - pc),
+		    _ => { continue; }
+		}
+		AbstractMicroCode::Jump(Operand::Var(v)) => (v,-pc,length - 1 - pc),
+		_ => { continue; }
+	    };
+	    restrict(&mut ranges[*var],lo,hi);
+	}
+	ranges.iter().all(|(lo,hi)| lo <= hi).then_some(ranges)
+    }
+}
+
+/// Narrow a range to lie within given (inclusive) bounds.
+fn restrict(range: &mut (i128,i128), lo: i128, hi: i128) {
+    range.0 = range.0.max(lo);
+    range.1 = range.1.min(hi);
+}
+
+// =====================================================
+// Random Numbers
+// =====================================================
+
+/// A minimal (but deterministic) pseudo-random number generator.
+struct XorShift {
+    state: u64
+}
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+	// State must be non-zero
+	XorShift{state: seed | 1}
+    }
+    fn next(&mut self) -> u64 {
+	let mut x = self.state;
+	x ^= x << 13;
+	x ^= x >> 7;
+	x ^= x << 17;
+	self.state = x;
+	x
+    }
+}
@@ -0,0 +1,59 @@
+use alloc::{vec,vec::Vec};
+use crate::machine::{MachineError,State};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Tiered Execution
+// =====================================================
+
+/// Executes a decoded program whilst counting how many times each
+/// instruction executes.  Once an instruction has executed a given
+/// number of times, it is quickened in the program (see
+/// `DecodedProgram::quicken()`), such that hot instructions execute
+/// with less overhead.  This is similar to bytecode quickening in
+/// language virtual machines.  For example:
+///
+/// ```text
+/// let mut tiered = Tiered::new(&program).threshold(100);
+/// while state.pc < program.len() {
+///     tiered.step(&mut state,&mut program)?;
+/// }
+/// ```
+pub struct Tiered {
+    /// Number of times each pc has executed, which stops increasing
+    /// once the instruction is quickened.
+    counts: Vec<usize>,
+    /// Number of times an instruction must execute before it is
+    /// quickened.
+    threshold: usize
+}
+
+impl Tiered {
+    pub fn new(program: &DecodedProgram) -> Self {
+	Tiered{counts:vec![0;program.len()],threshold:16}
+    }
+    /// Set the number of times an instruction must execute before it
+    /// is quickened (default is 16).
+    pub fn threshold(mut self, threshold: usize) -> Self {
+	self.threshold = threshold;
+	self
+    }
+    /// Execute the instruction identified by the current pc (as for
+    /// `State::step()`), first quickening it if it has now executed
+    /// often enough.
+    pub fn step(&mut self, state: &mut State, program: &mut DecodedProgram) -> Result<(),MachineError> {
+	if self.counts.len() != program.len() {
+	    // The program was refreshed
+	    self.counts.resize(program.len(),0);
+	}
+	if let Some(count) = self.counts.get_mut(state.pc) {
+	    if *count <= self.threshold {
+		if *count == self.threshold {
+		    program.quicken(state.pc);
+		}
+		*count += 1;
+	    }
+	}
+	state.step(program)
+    }
+}
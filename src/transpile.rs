@@ -0,0 +1,204 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::disasm::Disassembler;
+use crate::machine::{MicroCode,Width};
+use crate::program::DecodedProgram;
+
+// =====================================================
+// Errors
+// =====================================================
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum TranspileError {
+    /// The instruction at a given pc uses microcode which cannot be
+    /// translated (e.g. because it accesses registers).
+    Unsupported{pc: usize}
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    TranspileError::Unsupported{pc} => write!(f,"instruction at {} cannot be translated",pc)
+	}
+    }
+}
+
+impl std::error::Error for TranspileError {}
+
+// =====================================================
+// Transpiler
+// =====================================================
+
+/// Translate a decoded program into the source of a standalone Rust
+/// function with a given name, such that a fixed program can be
+/// compiled to native code.  The function has the signature `fn(&mut
+/// [u8], usize) -> usize` and, given the machine's memory and the pc
+/// to start from, executes the program until the pc leaves it (or
+/// reaches an instruction which could not be decoded), then returns
+/// the pc.  For example, `transpile(&program,"run")` produces:
+///
+/// ```text
+/// pub fn run(memory: &mut [u8], mut pc: usize) -> usize {
+///     loop {
+///         match pc {
+///             0 => {
+///                 // add 0, 1
+///                 memory[0] = memory[0].wrapping_add(memory[1]);
+///                 ...
+///             }
+///             _ => return pc
+///         }
+///     }
+/// }
+/// ```
+///
+/// The program is divided into basic blocks, each of which is
+/// translated into straight-line code (i.e. without interpreting
+/// microcode).  Blocks start at pc zero, at the target of any branch
+/// and after any branch.  Thus, execution must start at one of
+/// these (otherwise, the function returns immediately).  Only
+/// microcode operating on memory is supported.
+pub fn transpile(program: &DecodedProgram, name: &str) -> Result<String,TranspileError> {
+    let disasm = Disassembler::new(program.isa()).pseudos(false);
+    let insns = microcode(program)?;
+    let leaders = leaders(&insns);
+    let mut out = String::new();
+    out.push_str(&format!("pub fn {}(memory: &mut [u8], mut pc: usize) -> usize {{\n",name));
+    out.push_str("    loop {\n\tmatch pc {\n");
+    for &start in &leaders {
+	out.push_str(&format!("\t    {} => {{\n",start));
+	let mut pc = start;
+	while let Some(Some(codes)) = insns.get(pc) {
+	    let entry = program.get(pc).unwrap();
+	    out.push_str(&format!("\t\t// {}\n",disasm.render(entry.insn,entry.operands)));
+	    pc += 1;
+	    if codes.iter().any(|c| c.is_branch()) {
+		out.push_str(&format!("\t\tlet mut next = {};\n",pc));
+		block(&mut out,pc - 1,codes,4);
+		out.push_str("\t\tpc = next;\n");
+		break;
+	    }
+	    block(&mut out,pc - 1,codes,4);
+	    if leaders.contains(&pc) || !matches!(insns.get(pc),Some(Some(_))) {
+		out.push_str(&format!("\t\tpc = {};\n",pc));
+		break;
+	    }
+	}
+	out.push_str("\t    }\n");
+    }
+    out.push_str("\t    _ => return pc\n\t}\n    }\n}\n");
+    Ok(out)
+}
+
+/// Determine the microcode of each instruction in a program, or
+/// `None` for those which could not be decoded.
+pub(crate) fn microcode(program: &DecodedProgram) -> Result<Vec<Option<Vec<MicroCode>>>,TranspileError> {
+    let mut insns = Vec::new();
+    for pc in 0..program.len() {
+	match program.get(pc) {
+	    Ok(entry) => {
+		let codes : Vec<MicroCode> = entry.microcode.iter().map(|t| t.code()).collect();
+		if !codes.iter().all(supported) {
+		    return Err(TranspileError::Unsupported{pc});
+		}
+		insns.push(Some(codes));
+	    }
+	    Err(_) => insns.push(None)
+	}
+    }
+    Ok(insns)
+}
+
+/// Determine the pcs at which basic blocks start.  Blocks starting
+/// at instructions which could not be decoded are omitted, such that
+/// execution stops there.
+pub(crate) fn leaders(insns: &[Option<Vec<MicroCode>>]) -> BTreeSet<usize> {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    for (pc,insn) in insns.iter().enumerate() {
+	let Some(codes) = insn else { continue; };
+	for c in codes {
+	    match *c {
+		MicroCode::Goto(t) => { leaders.insert(t); }
+		MicroCode::Jump(o) => { leaders.insert(pc.wrapping_add_signed(o)); }
+		_ => {}
+	    }
+	}
+	if codes.iter().any(|c| c.is_branch()) {
+	    leaders.insert(pc + 1);
+	}
+    }
+    leaders.retain(|pc| matches!(insns.get(*pc),Some(Some(_))));
+    leaders
+}
+
+/// Translate the microcode of a single instruction at a given pc,
+/// indented to a given level.  Skips are translated by duplicating
+/// the remaining microcode for each outcome.
+fn block(out: &mut String, pc: usize, codes: &[MicroCode], level: usize) {
+    let indent = format!("{}{}","\t".repeat(level / 2),"    ".repeat(level % 2));
+    let mut i = 0;
+    while i < codes.len() {
+	let line = match codes[i] {
+	    MicroCode::Add(x,y,w) => {
+		let r = format!("{}.wrapping_add({})",read(x,w),read(y,w));
+		write(x,w,&r)
+	    }
+	    MicroCode::Copy(x,y,w) => write(x,w,&read(y,w)),
+	    MicroCode::Load(x,v,w) => write(x,w,&format!("{:#x}{}",v & w.mask(),ty(w))),
+	    MicroCode::Goto(t) => format!("next = {};",t),
+	    MicroCode::Jump(o) => format!("next = {};",pc.wrapping_add_signed(o)),
+	    MicroCode::Skip(n) => {
+		i += 1 + n;
+		continue;
+	    }
+	    MicroCode::SkipIfZero(x,w,n) => {
+		out.push_str(&format!("{}if {} != 0 {{\n",indent,read(x,w)));
+		block(out,pc,&codes[i+1..],level+1);
+		let mut other = String::new();
+		block(&mut other,pc,&codes[(i+1+n).min(codes.len())..],level+1);
+		if !other.is_empty() {
+		    out.push_str(&format!("{}}} else {{\n",indent));
+		    out.push_str(&other);
+		}
+		out.push_str(&format!("{}}}\n",indent));
+		return;
+	    }
+	    _ => unreachable!()
+	};
+	out.push_str(&format!("{}{}\n",indent,line));
+	i += 1;
+    }
+}
+
+/// Check whether a given microcode can be translated.
+fn supported(code: &MicroCode) -> bool {
+    matches!(code,MicroCode::Add(..)|MicroCode::Copy(..)|MicroCode::Load(..)|MicroCode::Goto(_)
+	     |MicroCode::Jump(_)|MicroCode::Skip(_)|MicroCode::SkipIfZero(..))
+}
+
+fn ty(w: Width) -> &'static str {
+    match w {
+	Width::Byte => "u8",
+	Width::Word => "u16",
+	Width::DoubleWord => "u32",
+	Width::QuadWord => "u64"
+    }
+}
+
+/// Generate an expression reading a (little endian) value from
+/// memory.
+fn read(x: usize, w: Width) -> String {
+    match w {
+	Width::Byte => format!("memory[{}]",x),
+	_ => format!("{}::from_le_bytes(memory[{}..{}].try_into().unwrap())",ty(w),x,x + w.bytes())
+    }
+}
+
+/// Generate a statement writing a (little endian) value to memory.
+fn write(x: usize, w: Width, value: &str) -> String {
+    match w {
+	Width::Byte => format!("memory[{}] = {};",x,value),
+	_ => format!("{{ let v = {}; memory[{}..{}].copy_from_slice(&v.to_le_bytes()); }}",value,x,x + w.bytes())
+    }
+}
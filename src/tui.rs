@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+use std::io;
+use ratatui::{DefaultTerminal,Frame};
+use ratatui::crossterm::event::{self,Event,KeyCode,KeyEventKind};
+use ratatui::layout::{Constraint,Layout,Rect};
+use ratatui::style::{Modifier,Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block,Paragraph,Wrap};
+use crate::debug::{self,Debugger};
+use crate::machine::State;
+
+/// Number of executed instructions remembered for the trace pane.
+const TRACE : usize = 64;
+
+/// Maximum number of instructions executed when continuing, such that
+/// a program which never reaches a breakpoint does not hang the
+/// interface.
+const BUDGET : usize = 100_000_000;
+
+const KEYS : &str = "s: step  c: continue  b: toggle breakpoint  :: command  up/down: scroll memory  q: quit";
+
+// =====================================================
+// Terminal User Interface
+// =====================================================
+
+/// A terminal front-end for a debugger, showing the disassembly
+/// around the pc, the registers, a hexdump of memory and a trace of
+/// the instructions most recently executed.  Single keys step,
+/// continue and toggle breakpoints, whilst any debugger command (see
+/// `Debugger::command()`) can be entered after `:`.  For example:
+///
+/// ```text
+/// Tui::new(Debugger::new(&program)).run(&mut state)?;
+/// ```
+pub struct Tui<'a> {
+    debugger: Debugger<'a>,
+    /// Pcs of the instructions most recently executed (oldest first).
+    trace: VecDeque<usize>,
+    /// Address of the first byte shown in the memory pane.
+    address: usize,
+    /// The command being entered (if any).
+    input: Option<String>,
+    /// Output of the last action.
+    message: String,
+    /// Indicates the user asked to quit.
+    quit: bool
+}
+
+impl<'a> Tui<'a> {
+    pub fn new(debugger: Debugger<'a>) -> Self {
+	Tui{debugger,trace:VecDeque::new(),address:0,input:None,message:KEYS.to_string(),quit:false}
+    }
+    /// Take over the terminal, handling key presses until the user
+    /// quits.  The terminal is restored afterwards.
+    pub fn run(mut self, state: &mut State) -> io::Result<()> {
+	let mut terminal = ratatui::init();
+	let result = self.event_loop(&mut terminal,state);
+	ratatui::restore();
+	result
+    }
+    /// Check whether the user asked to quit.
+    pub fn is_done(&self) -> bool {
+	self.quit
+    }
+    /// Respond to a given key press.
+    pub fn key(&mut self, key: KeyCode, state: &mut State) {
+	if let Some(input) = &mut self.input {
+	    match key {
+		KeyCode::Char(c) => input.push(c),
+		KeyCode::Backspace => { input.pop(); }
+		KeyCode::Esc => self.input = None,
+		KeyCode::Enter => {
+		    let line = self.input.take().unwrap_or_default();
+		    self.message = match self.debugger.command(state,&line) {
+			Ok(out) => out,
+			Err(e) => format!("error: {}",e)
+		    };
+		}
+		_ => {}
+	    }
+	    return;
+	}
+	match key {
+	    KeyCode::Char('q') => self.quit = true,
+	    KeyCode::Char('s') => {
+		self.message = match self.step(state) {
+		    Ok(()) => String::new(),
+		    Err(e) => e
+		};
+	    }
+	    KeyCode::Char('c') => self.resume(state),
+	    KeyCode::Char('b') => {
+		let pc = state.pc;
+		if !self.debugger.remove_breakpoint(pc) {
+		    self.debugger.add_breakpoint(pc);
+		}
+	    }
+	    KeyCode::Char(':') => self.input = Some(String::new()),
+	    KeyCode::Up => self.address = self.address.saturating_sub(16),
+	    KeyCode::Down => self.address = (self.address + 16).min(state.data.len().saturating_sub(1) & !15),
+	    _ => {}
+	}
+    }
+    /// Draw the interface for a given machine state.
+    pub fn draw(&self, frame: &mut Frame, state: &State) {
+	let [main,trace,status] = Layout::vertical([Constraint::Min(6),Constraint::Length(8),Constraint::Length(4)]).areas(frame.area());
+	let [code,right] = Layout::horizontal([Constraint::Percentage(50),Constraint::Percentage(50)]).areas(main);
+	let registers = debug::registers(state);
+	let [regs,memory] = Layout::vertical([Constraint::Length(registers.len() as u16 + 2),Constraint::Min(3)]).areas(right);
+	frame.render_widget(self.code(code,state),code);
+	let lines : Vec<Line> = registers.iter().map(|(n,v)| Line::from(format!("{:<6}{:#x}",n,v))).collect();
+	frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Registers")),regs);
+	let rows = (memory.height.saturating_sub(2) as usize) * 16;
+	let end = self.address.saturating_add(rows).min(state.data.len());
+	let bytes = state.data.bytes().get(self.address..end).unwrap_or(&[]);
+	frame.render_widget(Paragraph::new(debug::dump(bytes,self.address)).block(Block::bordered().title("Memory")),memory);
+	let skip = self.trace.len().saturating_sub(trace.height.saturating_sub(2) as usize);
+	let lines : Vec<Line> = self.trace.iter().skip(skip).map(|pc| Line::from(self.debugger.render(*pc))).collect();
+	frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Trace")),trace);
+	let text = match &self.input {
+	    Some(input) => format!(":{}",input),
+	    None => self.message.clone()
+	};
+	frame.render_widget(Paragraph::new(text).wrap(Wrap{trim:false}).block(Block::bordered()),status);
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal, state: &mut State) -> io::Result<()> {
+	while !self.quit {
+	    terminal.draw(|frame| self.draw(frame,state))?;
+	    if let Event::Key(key) = event::read()? {
+		if key.kind == KeyEventKind::Press {
+		    self.key(key.code,state);
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    /// Render the disassembly pane, which keeps the pc in the middle.
+    fn code(&self, area: Rect, state: &State) -> Paragraph<'static> {
+	let program = self.debugger.program();
+	let height = area.height.saturating_sub(2) as usize;
+	let start = state.pc.saturating_sub(height / 2);
+	let end = start.saturating_add(height).min(program.len());
+	let lines : Vec<Line> = (start..end).map(|pc| {
+	    let marker = if self.debugger.is_breakpoint(pc) { "*" } else { " " };
+	    let line = Line::from(format!("{} {:>4}  {}",marker,pc,self.debugger.render(pc)));
+	    if pc == state.pc { line.style(Style::default().add_modifier(Modifier::REVERSED)) } else { line }
+	}).collect();
+	Paragraph::new(lines).block(Block::bordered().title("Disassembly"))
+    }
+
+    /// Execute the instruction at the pc, recording it in the trace.
+    fn step(&mut self, state: &mut State) -> Result<(),String> {
+	let program = self.debugger.program();
+	if state.pc >= program.len() {
+	    return Err(format!("halted at pc {}",state.pc));
+	}
+	if self.trace.len() == TRACE {
+	    self.trace.pop_front();
+	}
+	self.trace.push_back(state.pc);
+	state.step(program).map_err(|e| format!("error: {}",e))
+    }
+
+    /// Execute until a breakpoint is reached (other than at the pc),
+    /// or the pc leaves the program.
+    fn resume(&mut self, state: &mut State) {
+	for i in 0..BUDGET {
+	    if i > 0 && self.debugger.is_breakpoint(state.pc) {
+		self.message = format!("breakpoint at pc {}",state.pc);
+		return;
+	    } else if let Err(e) = self.step(state) {
+		self.message = e;
+		return;
+	    }
+	}
+	self.message = format!("stopped at pc {}",state.pc);
+    }
+}
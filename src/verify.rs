@@ -0,0 +1,384 @@
+use core::fmt;
+use alloc::{boxed::Box,string::{String,ToString},vec,vec::Vec};
+use crate::domain::Countable;
+use crate::insn::{Field,Instruction,InstructionSet};
+use crate::machine::{MicroCode,State,Width};
+
+/// The pc at which instructions are executed when checking them.
+/// This leaves room for (reasonable) backwards jumps.
+pub const START_PC : usize = 0x10000;
+
+// =====================================================
+// Equivalence
+// =====================================================
+
+/// A concrete situation in which two instructions behave differently.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Counterexample {
+    pub operands: Vec<usize>,
+    /// Initial contents of memory.
+    pub memory: Vec<u8>,
+    /// Resulting pc and memory for the first instruction.
+    pub first: (usize,Vec<u8>),
+    /// Resulting pc and memory for the second instruction.
+    pub second: (usize,Vec<u8>)
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"operands {:?} with memory {:02x?} gives pc {:#x}, memory {:02x?} versus pc {:#x}, memory {:02x?}",
+	       self.operands,self.memory,self.first.0,self.first.1,self.second.0,self.second.1)
+    }
+}
+
+/// Indicates why two instructions are not equivalent.
+#[derive(Clone,Debug,PartialEq)]
+pub enum EquivalenceError {
+    /// The instructions have different numbers of operands.
+    Arity{first: usize, second: usize},
+    /// The instructions behave differently in some situation.
+    Counterexample(Counterexample)
+}
+
+impl fmt::Display for EquivalenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match &self {
+	    EquivalenceError::Arity{first,second} => {
+		write!(f,"instructions have {} and {} operand(s)",first,second)
+	    }
+	    EquivalenceError::Counterexample(cex) => cex.fmt(f)
+	}
+    }
+}
+
+impl core::error::Error for EquivalenceError {}
+
+/// Check that two instructions (with the same operands) have the same
+/// semantics, by exhaustively executing both for every combination of
+/// operand values and every initial memory of a given size whose
+/// bytes are drawn from a given set of values.  For example, using
+/// the values `[0x00,0x01,0x7F,0x80,0xFF]` covers the usual boundary
+/// cases.  Operand combinations for which either instruction accesses
+/// memory out of bounds (or access registers, which are not
+/// modelled) are skipped.  Thus, this is only feasible for
+/// small operand domains and memories.  Returns the number of
+/// combinations checked or, otherwise, the first counterexample
+/// found (or `EquivalenceError::Arity` if the instructions do not
+/// have the same number of operands).
+pub fn check_equivalent(first: &Instruction, second: &Instruction, memory: usize, values: &[u8]) -> Result<usize,EquivalenceError> {
+    if first.arity() != second.arity() {
+	return Err(EquivalenceError::Arity{first:first.arity(),second:second.arity()});
+    }
+    let mut count = 0;
+    let (mut mc1,mut mc2) = (Vec::new(),Vec::new());
+    for_each_operands(first.format().operands(),|operands| {
+	mc1.clear();
+	mc2.clear();
+	first.to_microcode_into(START_PC,operands,&mut mc1);
+	second.to_microcode_into(START_PC,operands,&mut mc2);
+	if !in_bounds(&mc1,memory) || !in_bounds(&mc2,memory) {
+	    return Ok(());
+	}
+	for_each_memory(memory,values,|initial| {
+	    let a = execute(&mc1,initial);
+	    let b = execute(&mc2,initial);
+	    count += 1;
+	    if a != b {
+		let cex = Counterexample{operands:operands.to_vec(),memory:initial.to_vec(),first:a,second:b};
+		return Err(EquivalenceError::Counterexample(cex));
+	    }
+	    Ok(())
+	})
+    })?;
+    Ok(count)
+}
+
+/// Check that two sequences of microcode have the same semantics, by
+/// exhaustively executing both for every initial memory (as for
+/// `check_equivalent()`).  For example, this can confirm that an
+/// optimized sequence is equivalent to the original (see
+/// `Optimizer`).  Sequences which access memory out of bounds (or
+/// registers) are not checked.  Returns the number of memories
+/// checked or, otherwise, the first counterexample found (with no
+/// operands).
+pub fn check_microcode_equivalent(first: &[MicroCode], second: &[MicroCode], memory: usize, values: &[u8]) -> Result<usize,Counterexample> {
+    if !in_bounds(first,memory) || !in_bounds(second,memory) {
+	return Ok(0);
+    }
+    let mut count = 0;
+    for_each_memory(memory,values,|initial| {
+	let a = execute(first,initial);
+	let b = execute(second,initial);
+	count += 1;
+	if a != b {
+	    return Err(Counterexample{operands:Vec::new(),memory:initial.to_vec(),first:a,second:b});
+	}
+	Ok(())
+    })?;
+    Ok(count)
+}
+
+// =====================================================
+// Properties
+// =====================================================
+
+/// An expression over the state of a machine before and after an
+/// instruction executes.  Arithmetic is on 64 bit unsigned integers
+/// with wrap around.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Expr {
+    Const(u64),
+    /// The value of a given operand.
+    Operand(usize),
+    /// The pc before the instruction executes.
+    OldPc,
+    /// The pc after the instruction executes.
+    NewPc,
+    /// The value at a given address (w bits) before the instruction
+    /// executes.
+    Old(Box<Expr>,Width),
+    /// The value at a given address (w bits) after the instruction
+    /// executes.
+    New(Box<Expr>,Width),
+    Add(Box<Expr>,Box<Expr>),
+    Sub(Box<Expr>,Box<Expr>),
+    Mul(Box<Expr>,Box<Expr>),
+    /// Truncate a value to w bits (i.e. modulo 2^w).
+    Trunc(Box<Expr>,Width)
+}
+
+/// A condition over the state of a machine before and after an
+/// instruction executes.
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub enum Condition {
+    True,
+    Eq(Expr,Expr),
+    Ne(Expr,Expr),
+    /// Unsigned less than.
+    Lt(Expr,Expr),
+    Not(Box<Condition>),
+    And(Box<Condition>,Box<Condition>)
+}
+
+/// A named property of an instruction, consisting of a precondition
+/// (over the state before it executes) and a postcondition (over the
+/// states before and after).  For example, that `add rd, rs` computes
+/// `rd := rd + rs mod 2^8` and advances the pc by one is:
+///
+/// ```text
+/// let rd = || Box::new(Expr::Operand(0));
+/// let rs = || Box::new(Expr::Operand(1));
+/// let sum = Expr::Trunc(Box::new(Expr::Add(Box::new(Expr::Old(rd(),Byte)),Box::new(Expr::Old(rs(),Byte)))),Byte);
+/// let result = Property::new("result",Condition::Eq(Expr::New(rd(),Byte),sum));
+/// let pc = Expr::Add(Box::new(Expr::OldPc),Box::new(Expr::Const(1)));
+/// let advance = Property::new("advance",Condition::Eq(Expr::NewPc,pc));
+/// ```
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize,serde::Deserialize))]
+pub struct Property {
+    pub name: String,
+    pub pre: Condition,
+    pub post: Condition
+}
+
+impl Property {
+    /// Construct a property which holds unconditionally.
+    pub fn new(name: &str, post: Condition) -> Self {
+	Property{name:name.to_string(),pre:Condition::True,post}
+    }
+    /// Restrict this property to states satisfying a given
+    /// precondition.
+    pub fn requires(mut self, pre: Condition) -> Self {
+	self.pre = pre;
+	self
+    }
+}
+
+/// A concrete situation in which an instruction fails to satisfy one
+/// of its properties.
+#[derive(Clone,Debug,PartialEq)]
+pub struct Violation {
+    pub mnemonic: String,
+    /// Name of the property violated.
+    pub property: String,
+    pub operands: Vec<usize>,
+    /// Initial contents of memory.
+    pub memory: Vec<u8>,
+    /// Resulting pc and memory.
+    pub result: (usize,Vec<u8>)
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	write!(f,"{} violates \"{}\" for operands {:?} with memory {:02x?} (giving pc {:#x}, memory {:02x?})",
+	       self.mnemonic,self.property,self.operands,self.memory,self.result.0,self.result.1)
+    }
+}
+
+/// Check that an instruction satisfies each of its properties, by
+/// exhaustively executing it for every combination of operand values
+/// and every initial memory of a given size whose bytes are drawn
+/// from a given set of values (as for `check_equivalent()`).  A
+/// property whose precondition does not hold (or refers to memory
+/// out of bounds) is not checked for that state, whilst one whose
+/// postcondition refers to memory out of bounds is violated.  Returns
+/// the number of states checked or, otherwise, the first violation
+/// found.
+pub fn check_properties(insn: &Instruction, memory: usize, values: &[u8]) -> Result<usize,Box<Violation>> {
+    let mut count = 0;
+    let mut microcode = Vec::new();
+    for_each_operands(insn.format().operands(),|operands| {
+	microcode.clear();
+	insn.to_microcode_into(START_PC,operands,&mut microcode);
+	if !in_bounds(&microcode,memory) {
+	    return Ok(());
+	}
+	for_each_memory(memory,values,|initial| {
+	    let result = execute(&microcode,initial);
+	    let mut env = Env{operands,before:(START_PC,initial),after:None};
+	    count += 1;
+	    for p in insn.properties() {
+		env.after = None;
+		if env.test(&p.pre) != Some(true) { continue; }
+		env.after = Some((result.0,&result.1));
+		if env.test(&p.post) != Some(true) {
+		    return Err(Box::new(Violation{mnemonic:insn.mnemonic().to_string(),property:p.name.clone(),
+					 operands:operands.to_vec(),memory:initial.to_vec(),result:result.clone()}));
+		}
+	    }
+	    Ok(())
+	})
+    })?;
+    Ok(count)
+}
+
+/// Check the properties of every instruction in a given set (see
+/// `check_properties()`).
+pub fn check_all_properties(isa: &InstructionSet, memory: usize, values: &[u8]) -> Result<usize,Box<Violation>> {
+    let mut count = 0;
+    for insn in isa {
+	if !insn.properties().is_empty() {
+	    count += check_properties(insn,memory,values)?;
+	}
+    }
+    Ok(count)
+}
+
+/// The states against which properties are evaluated.
+struct Env<'a> {
+    operands: &'a [usize],
+    /// The pc and memory before the instruction executes.
+    before: (usize,&'a [u8]),
+    /// The pc and memory after the instruction executes (if known).
+    after: Option<(usize,&'a [u8])>
+}
+
+impl Env<'_> {
+    fn test(&self, c: &Condition) -> Option<bool> {
+	match c {
+	    Condition::True => Some(true),
+	    Condition::Eq(l,r) => Some(self.eval(l)? == self.eval(r)?),
+	    Condition::Ne(l,r) => Some(self.eval(l)? != self.eval(r)?),
+	    Condition::Lt(l,r) => Some(self.eval(l)? < self.eval(r)?),
+	    Condition::Not(c) => Some(!self.test(c)?),
+	    Condition::And(l,r) => Some(self.test(l)? && self.test(r)?)
+	}
+    }
+    fn eval(&self, e: &Expr) -> Option<u64> {
+	match e {
+	    Expr::Const(c) => Some(*c),
+	    Expr::Operand(i) => self.operands.get(*i).map(|v| *v as u64),
+	    Expr::OldPc => Some(self.before.0 as u64),
+	    Expr::NewPc => self.after.map(|(pc,_)| pc as u64),
+	    Expr::Old(a,w) => read(self.before.1,self.eval(a)?,*w),
+	    Expr::New(a,w) => read(self.after?.1,self.eval(a)?,*w),
+	    Expr::Add(l,r) => Some(self.eval(l)?.wrapping_add(self.eval(r)?)),
+	    Expr::Sub(l,r) => Some(self.eval(l)?.wrapping_sub(self.eval(r)?)),
+	    Expr::Mul(l,r) => Some(self.eval(l)?.wrapping_mul(self.eval(r)?)),
+	    Expr::Trunc(v,w) => {
+		let bits = 8 * w.bytes() as u32;
+		Some(self.eval(v)? & u64::MAX.checked_shr(64 - bits).unwrap_or(0))
+	    }
+	}
+    }
+}
+
+/// Read a (little endian) value of a given width from memory, or
+/// `None` if it lies out of bounds.
+fn read(memory: &[u8], address: u64, width: Width) -> Option<u64> {
+    let start = usize::try_from(address).ok()?;
+    let bytes = memory.get(start..start.checked_add(width.bytes())?)?;
+    Some(bytes.iter().rev().fold(0,|v,b| (v << 8) | *b as u64))
+}
+
+// =====================================================
+// Helpers
+// =====================================================
+
+/// Execute a sequence of microcode on a given memory (at `START_PC`),
+/// returning the resulting pc and memory.
+fn execute(microcode: &[MicroCode], memory: &[u8]) -> (usize,Vec<u8>) {
+    let mut bytes = memory.to_vec();
+    let mut state = State::new(START_PC,&mut bytes);
+    state.execute_all(microcode);
+    let pc = state.pc;
+    (pc,bytes)
+}
+
+/// Check that every memory access of a sequence of microcode lies
+/// within a memory of a given size.
+fn in_bounds(microcode: &[MicroCode], memory: usize) -> bool {
+    microcode.iter().all(|c| match c {
+	MicroCode::Add(x,y,w)|MicroCode::Alu(_,x,y,w)|MicroCode::Copy(x,y,w) => {
+	    x.checked_add(w.bytes()).is_some_and(|e| e <= memory) && y.checked_add(w.bytes()).is_some_and(|e| e <= memory)
+	}
+	MicroCode::Load(x,_,w) => x.checked_add(w.bytes()).is_some_and(|e| e <= memory),
+	MicroCode::SkipIfZero(x,w,_) => x.checked_add(w.bytes()).is_some_and(|e| e <= memory),
+	MicroCode::Goto(_)|MicroCode::Jump(_)|MicroCode::Skip(_) => true,
+	// Registers are not modelled
+	_ => false
+    })
+}
+
+/// Apply a given function to every combination of values for a given
+/// set of operand fields, stopping at the first error.
+fn for_each_operands<E,F>(fields: &[Field], mut f: F) -> Result<(),E>
+where F: FnMut(&[usize]) -> Result<(),E> {
+    let limits : Vec<u64> = fields.iter().map(|f| f.checked_count_u64().unwrap_or(u64::MAX)).collect();
+    let mut raw = vec![0u64; fields.len()];
+    let mut operands = Vec::with_capacity(fields.len());
+    loop {
+	operands.clear();
+	operands.extend(fields.iter().zip(&raw).map(|(f,r)| f.extend(*r as usize)));
+	f(&operands)?;
+	if !advance(&mut raw,|i| limits[i]) { return Ok(()); }
+    }
+}
+
+/// Apply a given function to every memory of a given size whose bytes
+/// are drawn from a given set of values, stopping at the first error.
+fn for_each_memory<E,F>(size: usize, values: &[u8], mut f: F) -> Result<(),E>
+where F: FnMut(&[u8]) -> Result<(),E> {
+    if values.is_empty() { return Ok(()); }
+    let mut raw = vec![0u64; size];
+    let mut memory = Vec::with_capacity(size);
+    loop {
+	memory.clear();
+	memory.extend(raw.iter().map(|r| values[*r as usize]));
+	f(&memory)?;
+	if !advance(&mut raw,|_| values.len() as u64) { return Ok(()); }
+    }
+}
+
+/// Advance to the next combination of raw values, returning `false`
+/// once all combinations have been seen.
+fn advance(raw: &mut [u64], limit: impl Fn(usize) -> u64) -> bool {
+    for (i,r) in raw.iter_mut().enumerate() {
+	*r += 1;
+	if *r < limit(i) { return true; }
+	*r = 0;
+    }
+    false
+}
@@ -0,0 +1,224 @@
+use crate::machine::{MicroCode,Width};
+use crate::program::DecodedProgram;
+use crate::transpile::{leaders,microcode,TranspileError};
+
+/// The WebAssembly magic number and version.
+const HEADER : [u8;8] = [0x00,0x61,0x73,0x6D,0x01,0x00,0x00,0x00];
+/// Size (in bytes) of a WebAssembly page.
+const PAGE_SIZE : usize = 65536;
+/// Section identifiers.
+const SECTION_TYPE : u8 = 1;
+const SECTION_FUNCTION : u8 = 3;
+const SECTION_MEMORY : u8 = 5;
+const SECTION_EXPORT : u8 = 7;
+const SECTION_CODE : u8 = 10;
+/// Value types.
+const I32 : u8 = 0x7F;
+/// Instruction opcodes.
+const BLOCK : u8 = 0x02;
+const LOOP : u8 = 0x03;
+const IF : u8 = 0x04;
+const ELSE : u8 = 0x05;
+const END : u8 = 0x0B;
+const BR : u8 = 0x0C;
+const BR_TABLE : u8 = 0x0E;
+const LOCAL_GET : u8 = 0x20;
+const LOCAL_SET : u8 = 0x21;
+const I32_CONST : u8 = 0x41;
+const I64_CONST : u8 = 0x42;
+const I64_EQZ : u8 = 0x50;
+const I32_EQZ : u8 = 0x45;
+const I32_ADD : u8 = 0x6A;
+const I64_ADD : u8 = 0x7C;
+/// The empty block type.
+const EMPTY : u8 = 0x40;
+/// Local holding the pc (i.e. the function's parameter).
+const PC : u8 = 0;
+/// Local holding the pc of the next instruction.
+const NEXT : u8 = 1;
+
+// =====================================================
+// WebAssembly
+// =====================================================
+
+/// Lower a decoded program into a (binary) WebAssembly module, such
+/// that it can be run sandboxed (e.g. in a browser or `wasmtime`)
+/// without the interpreter.  The module exports a linear memory
+/// `memory` of (at least) a given size holding the machine's memory,
+/// and a function `run` of type `[i32] -> [i32]`.  This executes the
+/// program from a given pc until the pc leaves it (or reaches an
+/// instruction which could not be decoded), then returns the pc.
+/// Accesses beyond the memory trap.
+///
+/// As for `transpile()`, the program is divided into basic blocks
+/// which execution must start from, and only microcode operating on
+/// memory is supported.  Blocks are dispatched using a `br_table`
+/// indexed by the pc.
+pub fn generate(program: &DecodedProgram, memory: usize) -> Result<Vec<u8>,TranspileError> {
+    let insns = microcode(program)?;
+    let leaders : Vec<usize> = leaders(&insns).into_iter().collect();
+    let k = leaders.len() as u32;
+    // Construct the body of run (with one additional local)
+    let mut body = vec![1,1,I32];
+    body.extend([LOOP,EMPTY,BLOCK,EMPTY]);
+    for _ in 0..k { body.extend([BLOCK,EMPTY]); }
+    body.extend([LOCAL_GET,PC,BR_TABLE]);
+    uleb(&mut body,insns.len() as u64);
+    for pc in 0..insns.len() {
+	let label = leaders.iter().position(|l| *l == pc).map_or(k,|i| i as u32);
+	uleb(&mut body,label as u64);
+    }
+    uleb(&mut body,k as u64);
+    body.push(END);
+    for (i,&start) in leaders.iter().enumerate() {
+	let mut pc = start;
+	while let Some(Some(codes)) = insns.get(pc) {
+	    pc += 1;
+	    if codes.iter().any(|c| c.is_branch()) {
+		i32_const(&mut body,pc as i32);
+		body.extend([LOCAL_SET,NEXT]);
+		block(&mut body,pc - 1,codes);
+		body.extend([LOCAL_GET,NEXT,LOCAL_SET,PC]);
+		break;
+	    }
+	    block(&mut body,pc - 1,codes);
+	    if leaders.contains(&pc) || !matches!(insns.get(pc),Some(Some(_))) {
+		i32_const(&mut body,pc as i32);
+		body.extend([LOCAL_SET,PC]);
+		break;
+	    }
+	}
+	// Branch back to the loop
+	body.push(BR);
+	uleb(&mut body,k as u64 - i as u64);
+	body.push(END);
+    }
+    body.extend([END,LOCAL_GET,PC,END]);
+    // Construct the module
+    let mut bytes = HEADER.to_vec();
+    section(&mut bytes,SECTION_TYPE,&[1,0x60,1,I32,1,I32]);
+    section(&mut bytes,SECTION_FUNCTION,&[1,0]);
+    let mut memories = vec![1,0x00];
+    uleb(&mut memories,memory.div_ceil(PAGE_SIZE) as u64);
+    section(&mut bytes,SECTION_MEMORY,&memories);
+    let mut exports = vec![2];
+    exports.extend([3,b'r',b'u',b'n',0x00,0]);
+    exports.extend([6,b'm',b'e',b'm',b'o',b'r',b'y',0x02,0]);
+    section(&mut bytes,SECTION_EXPORT,&exports);
+    let mut code = vec![1];
+    uleb(&mut code,body.len() as u64);
+    code.extend(body);
+    section(&mut bytes,SECTION_CODE,&code);
+    Ok(bytes)
+}
+
+/// Lower the microcode of a single instruction at a given pc.  As for
+/// `transpile()`, skips are lowered by duplicating the remaining
+/// microcode for each outcome.
+fn block(out: &mut Vec<u8>, pc: usize, codes: &[MicroCode]) {
+    let mut i = 0;
+    while i < codes.len() {
+	match codes[i] {
+	    MicroCode::Add(x,y,w) => {
+		i32_const(out,x as i32);
+		load(out,x,w);
+		load(out,y,w);
+		out.push(if w == Width::QuadWord { I64_ADD } else { I32_ADD });
+		store(out,w);
+	    }
+	    MicroCode::Copy(x,y,w) => {
+		i32_const(out,x as i32);
+		load(out,y,w);
+		store(out,w);
+	    }
+	    MicroCode::Load(x,v,w) => {
+		i32_const(out,x as i32);
+		if w == Width::QuadWord {
+		    out.push(I64_CONST);
+		    sleb(out,v as i64);
+		} else {
+		    i32_const(out,v as i32);
+		}
+		store(out,w);
+	    }
+	    MicroCode::Goto(t) => {
+		i32_const(out,t as i32);
+		out.extend([LOCAL_SET,NEXT]);
+	    }
+	    MicroCode::Jump(o) => {
+		i32_const(out,pc.wrapping_add_signed(o) as i32);
+		out.extend([LOCAL_SET,NEXT]);
+	    }
+	    MicroCode::Skip(n) => {
+		i += 1 + n;
+		continue;
+	    }
+	    MicroCode::SkipIfZero(x,w,n) => {
+		load(out,x,w);
+		if w == Width::QuadWord {
+		    out.extend([I64_EQZ,I32_EQZ]);
+		}
+		out.extend([IF,EMPTY]);
+		block(out,pc,&codes[i+1..]);
+		out.push(ELSE);
+		block(out,pc,&codes[(i+1+n).min(codes.len())..]);
+		out.push(END);
+		return;
+	    }
+	    _ => unreachable!()
+	}
+	i += 1;
+    }
+}
+
+/// Load a value of a given width from a constant address.
+fn load(out: &mut Vec<u8>, x: usize, w: Width) {
+    i32_const(out,x as i32);
+    // i32.load8_u, i32.load16_u, i32.load, i64.load
+    out.extend([match w { Width::Byte => 0x2D, Width::Word => 0x2F, Width::DoubleWord => 0x28, Width::QuadWord => 0x29 },0,0]);
+}
+
+/// Store a value of a given width (the address and value are on the
+/// stack).
+fn store(out: &mut Vec<u8>, w: Width) {
+    // i32.store8, i32.store16, i32.store, i64.store
+    out.extend([match w { Width::Byte => 0x3A, Width::Word => 0x3B, Width::DoubleWord => 0x36, Width::QuadWord => 0x37 },0,0]);
+}
+
+fn i32_const(out: &mut Vec<u8>, v: i32) {
+    out.push(I32_CONST);
+    sleb(out,v as i64);
+}
+
+/// Append a section with a given identifier and contents.
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    uleb(out,contents.len() as u64);
+    out.extend(contents);
+}
+
+/// Append an unsigned LEB128 value.
+fn uleb(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+	let byte = (v & 0x7F) as u8;
+	v >>= 7;
+	if v == 0 {
+	    out.push(byte);
+	    return;
+	}
+	out.push(byte | 0x80);
+    }
+}
+
+/// Append a signed LEB128 value.
+fn sleb(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+	let byte = (v & 0x7F) as u8;
+	v >>= 7;
+	if (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0) {
+	    out.push(byte);
+	    return;
+	}
+	out.push(byte | 0x80);
+    }
+}
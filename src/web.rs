@@ -0,0 +1,160 @@
+use wasm_bindgen::prelude::*;
+use crate::asm::Assembler;
+use crate::debug;
+use crate::disasm::Disassembler;
+use crate::insn;
+use crate::machine::OwnedState;
+use crate::program::DecodedProgram;
+use crate::spec;
+
+// =====================================================
+// Instruction Sets
+// =====================================================
+
+/// An instruction set, as seen from JavaScript.  Instruction sets are
+/// constructed from specifications (see `spec`) and live for the
+/// remainder of the page, since decoded programs borrow them.  The
+/// module is built with, for example, `cargo rustc --lib --release
+/// --target wasm32-unknown-unknown --features web --crate-type
+/// cdylib` followed by `wasm-bindgen --target web`.  Then:
+///
+/// ```text
+/// import init, { InstructionSet, Machine } from "./virmin.js";
+/// await init();
+/// const isa = InstructionSet.fromToml(text);
+/// const m = new Machine(isa, isa.assemble("add r1, r2\n"), 256);
+/// m.step();
+/// console.log(m.current(), m.read(0, 4));
+/// ```
+#[wasm_bindgen(js_name=InstructionSet)]
+pub struct WebInstructionSet {
+    isa: &'static insn::InstructionSet<'static>
+}
+
+#[wasm_bindgen(js_class=InstructionSet)]
+impl WebInstructionSet {
+    /// Construct an instruction set from a TOML specification.
+    #[wasm_bindgen(js_name=fromToml)]
+    pub fn from_toml(text: &str) -> Result<WebInstructionSet,JsError> {
+	spec::from_toml(text).map(Self::new).map_err(|e| JsError::new(&e.to_string()))
+    }
+    /// Construct an instruction set from a JSON specification.
+    #[wasm_bindgen(js_name=fromJson)]
+    pub fn from_json(text: &str) -> Result<WebInstructionSet,JsError> {
+	spec::from_json(text).map(Self::new).map_err(|e| JsError::new(&e.to_string()))
+    }
+    /// Get the mnemonics of all instructions (in order).
+    pub fn mnemonics(&self) -> Vec<String> {
+	(0..self.isa.len()).map(|i| self.isa.instruction(i).mnemonic().to_string()).collect()
+    }
+    /// Assemble some source into a binary image.
+    pub fn assemble(&self, source: &str) -> Result<Vec<u8>,JsError> {
+	match Assembler::new(self.isa).assemble(source) {
+	    Ok(program) => Ok(program.bytes().to_vec()),
+	    Err(e) => Err(JsError::new(&e.to_string()))
+	}
+    }
+    /// Produce a listing of a binary image.
+    pub fn disassemble(&self, image: &[u8], hex: bool) -> String {
+	Disassembler::new(self.isa).hex(hex).listing(image,0)
+    }
+}
+
+impl WebInstructionSet {
+    fn new(isa: insn::InstructionSet<'static>) -> Self {
+	WebInstructionSet{isa:Box::leak(Box::new(isa))}
+    }
+}
+
+// =====================================================
+// Machines
+// =====================================================
+
+/// A machine executing a binary image, as seen from JavaScript.  The
+/// machine owns its memory, and its state can be inspected and
+/// modified between steps (e.g. to animate a playground).
+#[wasm_bindgen(js_name=Machine)]
+pub struct WebMachine {
+    program: DecodedProgram<'static>,
+    disasm: Disassembler<'static>,
+    state: OwnedState
+}
+
+#[wasm_bindgen(js_class=Machine)]
+impl WebMachine {
+    #[wasm_bindgen(constructor)]
+    pub fn new(isa: &WebInstructionSet, image: &[u8], memory: usize) -> WebMachine {
+	let disasm = Disassembler::new(isa.isa).pseudos(false);
+	WebMachine{program:DecodedProgram::new(isa.isa,image),disasm,state:OwnedState::new(memory)}
+    }
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> usize {
+	self.state.pc
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_pc(&mut self, pc: usize) {
+	self.state.pc = pc;
+    }
+    /// Check whether the pc has left the program.
+    #[wasm_bindgen(getter)]
+    pub fn halted(&self) -> bool {
+	self.state.pc >= self.program.len()
+    }
+    /// Get the number of instructions in the program.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+	self.program.len()
+    }
+    /// Render the instruction at a given pc (see `Debugger::render()`).
+    pub fn render(&self, pc: usize) -> String {
+	match self.program.get(pc) {
+	    Ok(e) => format!("{:04x}: {}",e.offset,self.disasm.render(e.insn,e.operands)),
+	    Err(e) => e.to_string()
+	}
+    }
+    /// Render the instruction at the pc.
+    pub fn current(&self) -> String {
+	self.render(self.state.pc)
+    }
+    /// Execute the instruction identified by the pc.
+    pub fn step(&mut self) -> Result<(),JsError> {
+	if self.halted() {
+	    return Err(JsError::new(&format!("halted at pc {}",self.state.pc)));
+	}
+	let program = &self.program;
+	self.state.with_state(|s| s.try_step(program)).map_err(|e| JsError::new(&e.to_string()))
+    }
+    /// Execute until the pc leaves the program (or a given number of
+    /// steps have executed), returning the number of steps executed.
+    pub fn run(&mut self, limit: usize) -> Result<usize,JsError> {
+	let program = &self.program;
+	self.state.with_state(|s| s.run(program,limit)).map_err(|e| JsError::new(&e.to_string()))
+    }
+    /// Get the names of all registers, starting with the special
+    /// registers.
+    #[wasm_bindgen(js_name=registerNames)]
+    pub fn register_names(&mut self) -> Vec<String> {
+	self.state.with_state(|s| debug::registers(s)).into_iter().map(|(n,_)| n).collect()
+    }
+    /// Get the values of all registers (in the same order as their
+    /// names).
+    #[wasm_bindgen(js_name=registerValues)]
+    pub fn register_values(&mut self) -> Vec<u64> {
+	self.state.with_state(|s| debug::registers(s)).into_iter().map(|(_,v)| v).collect()
+    }
+    /// Read some bytes of memory, which are truncated at the end of
+    /// memory.
+    pub fn read(&self, address: usize, length: usize) -> Vec<u8> {
+	let memory = &self.state.memory;
+	let start = address.min(memory.len());
+	memory[start..start.saturating_add(length).min(memory.len())].to_vec()
+    }
+    /// Write some bytes to memory, which are truncated at the end of
+    /// memory.
+    pub fn write(&mut self, address: usize, bytes: &[u8]) {
+	let memory = &mut self.state.memory;
+	let start = address.min(memory.len());
+	let end = start.saturating_add(bytes.len()).min(memory.len());
+	memory[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+}
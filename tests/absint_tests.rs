@@ -0,0 +1,139 @@
+#![cfg(feature="std")]
+use virmin::absint::*;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::{Pc,Var};
+use virmin::machine::AluOp;
+use virmin::machine::Width::{Byte,Word};
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Domains
+// =====================================================
+
+#[test]
+fn test_domain_01() {
+    let i = |lo,hi| Interval{lo,hi};
+    assert_eq!(i(1,3).join(&i(5,6)),i(1,6));
+    assert_eq!(i(1,3).widen(&i(0,2)),i(0,3));
+    assert_eq!(i(1,3).widen(&i(2,4)),i(1,u64::MAX));
+    assert_eq!(i(1,3).add(&i(2,2),Byte),i(3,5));
+    assert_eq!(i(1,254).add(&i(2,2),Byte),i(0,255));
+    assert_eq!(i(0,3).assume(true),Some(i(0,0)));
+    assert_eq!(i(0,3).assume(false),Some(i(1,3)));
+    assert_eq!(i(1,3).assume(true),None);
+    assert_eq!(i(0,0).assume(false),None);
+    assert_eq!((i(2,2).to_string(),i(0,255).to_string()),("2".to_string(),"[0..255]".to_string()));
+}
+
+#[test]
+fn test_domain_02() {
+    use Constant::*;
+    assert_eq!(Value(1).join(&Value(1)),Value(1));
+    assert_eq!(Value(1).join(&Value(2)),Any);
+    assert_eq!(Value(255).add(&Value(2),Byte),Value(1));
+    assert_eq!(Value(0x1234).truncate(Byte),Value(0x34));
+    assert_eq!(Value(1).apply(AluOp::Sub,&Value(2),Byte),Value(0xFF));
+    assert_eq!(Value(1).apply(AluOp::Sub,&Any,Byte),Any);
+    assert_eq!(Any.assume(true),Some(Value(0)));
+    assert_eq!(Value(1).assume(true),None);
+    assert_eq!(Any.bounds(),None);
+}
+
+// =====================================================
+// Analysis
+// =====================================================
+
+#[test]
+fn test_absint_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(0),Var(1))])
+	.instruction("jr",&fmt,&[RegCopy(Pc,Var(0))])
+	.instruction("ldw",&fmt,&[RegFetch(Var(1),Var(0),Word)])
+	.build().ok().unwrap();
+    // do { mem[0] += 1 } while mem[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("li",&[1,1]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,1]).unwrap();
+    program.push("li",&[2,3]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let results = Analysis::<Interval>::new(&decoded,8).zeroed().run();
+    assert_eq!(results.findings,vec![]);
+    assert_eq!(results.memory(1,0,Byte),Some(Interval{lo:0,hi:255}));
+    assert_eq!(results.memory(3,0,Byte),Some(Interval{lo:0,hi:0}));
+    assert_eq!(results.memory(3,1,Byte),Some(Interval{lo:1,hi:1}));
+    assert_eq!(results.memory(3,1,Word),Some(Interval{lo:0,hi:0xFFFF}));
+    assert_eq!(results.max_write(),Some(2));
+    let results = Analysis::<Constant>::new(&decoded,8).zeroed().run();
+    assert_eq!(results.memory(1,0,Byte),Some(Constant::Any));
+    assert_eq!(results.memory(3,1,Byte),Some(Constant::Value(1)));
+}
+
+#[test]
+fn test_absint_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(0),Var(1))])
+	.instruction("jr",&fmt,&[RegCopy(Pc,Var(0))])
+	.instruction("ldw",&fmt,&[RegFetch(Var(1),Var(0),Word)])
+	.build().ok().unwrap();
+    // mem[0] is 2, so the branch is always taken
+    let mut program = Program::new(&isa);
+    program.push("li",&[0,2]).unwrap();
+    program.push("jnz",&[0,3]).unwrap();
+    program.push("li",&[1,1]).unwrap();
+    program.push("li",&[7,1]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let results = Analysis::<Constant>::new(&decoded,7).zeroed().run();
+    assert!(!results.is_reachable(2));
+    assert_eq!(results.state(2),None);
+    let findings : Vec<String> = results.findings.iter().map(|f| f.to_string()).collect();
+    assert_eq!(findings,vec!["pc 0x1: skip #0 is never taken","pc 0x2: unreachable","pc 0x3: mem8[7] is out of bounds"]);
+    assert_eq!(results.max_write(),Some(7));
+    // Otherwise, memory is unknown
+    let mut program = Program::new(&isa);
+    program.push("jnz",&[0,2]).unwrap();
+    program.push("li",&[1,1]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let results = Analysis::<Interval>::new(&decoded,7).run();
+    assert_eq!(results.findings,vec![]);
+    assert_eq!(results.memory(1,0,Byte),Some(Interval{lo:0,hi:0}));
+}
+
+#[test]
+fn test_absint_03() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(0),Var(1))])
+	.instruction("jr",&fmt,&[RegCopy(Pc,Var(0))])
+	.instruction("ldw",&fmt,&[RegFetch(Var(1),Var(0),Word)])
+	.build().ok().unwrap();
+    // Jumps through a register
+    let mut program = Program::new(&isa);
+    program.push("lr",&[0,3]).unwrap();
+    program.push("jr",&[0,0]).unwrap();
+    program.push("li",&[1,1]).unwrap();
+    program.push("li",&[2,1]).unwrap();
+    program.push("ldw",&[4,1]).unwrap();
+    program.push("jr",&[1,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let results = Analysis::<Interval>::new(&decoded,8).registers(2,Word).run();
+    assert!(!results.is_reachable(2));
+    assert_eq!(results.register(3,0),Some(Interval{lo:3,hi:3}));
+    assert_eq!(results.register(5,1),Some(Interval{lo:0,hi:0xFFFF}));
+    assert_eq!(results.findings,vec![Finding::Unreachable{pc:2},Finding::UnknownTarget{pc:5}]);
+    // Registers which do not exist
+    let results = Analysis::<Interval>::new(&decoded,8).registers(1,Word).run();
+    assert_eq!(results.findings,vec![Finding::Unreachable{pc:2},Finding::InvalidRegister{pc:4,register:1},Finding::Unreachable{pc:5}]);
+}
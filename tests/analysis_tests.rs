@@ -0,0 +1,53 @@
+#![cfg(feature="std")]
+use virmin::analysis::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::{Byte,Word};
+
+// =====================================================
+// Microcode Analysis
+// =====================================================
+
+#[test]
+fn test_analysis_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let mc = [Load(Var(0),Const(1),Byte), Load(Var(0),Const(2),Word), Copy(Const(8),Var(1),Byte), Load(Const(8),Const(0),Byte)];
+    let insn = Instruction::new("bad", &fmt, &mc);
+    assert_eq!(analyze_instruction(&insn),vec![
+	Warning::DeadWrite{mnemonic:"bad".to_string(),index:0,overwritten_by:1},
+	Warning::DeadWrite{mnemonic:"bad".to_string(),index:2,overwritten_by:3}]);
+    // Reading a possibly aliased location prevents a dead write
+    let mc = [Load(Var(0),Const(1),Byte), Copy(Const(8),Var(1),Byte), Load(Var(0),Const(2),Byte), Goto(Var(1)), Load(Const(9),Const(0),Byte)];
+    let insn = Instruction::new("ok", &fmt, &mc);
+    assert_eq!(analyze_instruction(&insn),vec![Warning::Unreachable{mnemonic:"ok".to_string(),index:4}]);
+}
+
+#[test]
+fn test_analysis_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Const(7),Byte)];
+    let mc2 = [Copy(Var(0),Const(8),Byte)];
+    let mc3 = [Copy(Var(0),Const(9),Byte), Load(Const(8),Const(0),Byte)];
+    let insns = [Instruction::new("a", &fmt, &mc1),
+		 Instruction::new("b", &fmt, &mc2),
+		 Instruction::new("c", &fmt, &mc3)];
+    let isa = InstructionSet::new(&insns);
+    let warnings = analyze(&isa);
+    assert_eq!(warnings,vec![Warning::UnwrittenRead{mnemonic:"c".to_string(),index:0,address:9}]);
+    assert_eq!(warnings[0].to_string(),"c: read at #0 of location 9 which is never written");
+    let warnings = analyze(&InstructionSet::new(&insns[..2]));
+    assert_eq!(warnings,vec![Warning::UnwrittenRead{mnemonic:"b".to_string(),index:0,address:8}]);
+}
+
+#[test]
+fn test_analysis_03() {
+    use virmin::insn::Predicate;
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).build().ok().unwrap();
+    // Writes within (or before) a conditional are not definitely dead
+    let mc = [Load(Const(8),Const(1),Byte), If(Predicate::Zero(Const(20),Byte),vec![Load(Const(8),Const(2),Byte)],vec![]), Load(Const(8),Const(3),Byte)];
+    let insns = [Instruction::new("cond", &fmt, &mc)];
+    assert_eq!(analyze_instruction(&insns[0]),vec![]);
+    let warnings = analyze(&InstructionSet::new(&insns));
+    assert_eq!(warnings,vec![Warning::UnwrittenRead{mnemonic:"cond".to_string(),index:1,address:20}]);
+}
@@ -0,0 +1,420 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::asm::{Assembler,AsmError,AsmErrorKind,ListingLine};
+use virmin::insn::{EncodeError,Format,Instruction,InstructionSet,PseudoInstruction};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::insn::Operand::Add;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Location,Relocation,Symbol,SymbolKind};
+
+fn formats() -> (Format,Format,Format) {
+    let rr = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ri = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    (rr,ri,n)
+}
+
+// =====================================================
+// Assembler
+// =====================================================
+
+#[test]
+fn test_asm_01() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = "; a simple program\n\
+	       start:  mov r1, r2   ; copy\n\
+	       \n\
+	       ldi r3, -20 # load\n\
+	       end: nop\n\
+	       ldi r0, end\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.len(),4);
+    assert_eq!(program.bytes(),&[0x44,0x0d,0xfb,0x02,0x81,0x00]);
+}
+
+#[test]
+fn test_asm_02() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let asm = Assembler::new(&isa);
+    let err = |line,kind| Some(AsmError{line,kind});
+    assert_eq!(asm.assemble("nop\nadd r1, r2").err(),err(2,AsmErrorKind::UnknownMnemonic("add".to_string())));
+    assert_eq!(asm.assemble("mov r1").err(),err(1,AsmErrorKind::WrongArity{expected:2,actual:1}));
+    assert_eq!(asm.assemble("mov r1, 2").err(),err(1,AsmErrorKind::ExpectedRegister("2".to_string())));
+    assert_eq!(asm.assemble("ldi r1, r2").err(),err(1,AsmErrorKind::ExpectedImmediate("r2".to_string())));
+    assert_eq!(asm.assemble("ldi r1, 1x").err(),err(1,AsmErrorKind::InvalidOperand("1x".to_string())));
+    assert_eq!(asm.assemble("ldi r1, foo").err(),err(1,AsmErrorKind::UnknownLabel("foo".to_string())));
+    assert_eq!(asm.assemble("a: nop\na: nop").err(),err(2,AsmErrorKind::DuplicateLabel("a".to_string())));
+    assert_eq!(asm.assemble("mov r8, r1").err(),err(1,AsmErrorKind::Encode(EncodeError::OutOfRange{operand:0,value:8})));
+}
+
+// =====================================================
+// Branches
+// =====================================================
+
+#[test]
+fn test_asm_branch_01() {
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",6).build().ok().unwrap();
+    let g = Format::builder().label("g").width_bytes(1).opcode_bits(2).immediate("target",6).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc1 = [Jump(Var(0))];
+    let mc2 = [Goto(Var(0))];
+    let insns = [Instruction::new("jmp", &j, &mc1),
+		 Instruction::new("goto", &g, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = "start: jmp fwd\n\
+	       back:  nop\n\
+	       fwd:   jmp back\n\
+	       goto start\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.bytes(),&[0x08,0x02,0xFC,0x01]);
+    // Check execution follows the branches
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [0u8;1];
+    let mut state = State::new(0,&mut data);
+    let mut trace = Vec::new();
+    for _ in 0..5 {
+	state.step(&decoded).unwrap();
+	trace.push(state.pc);
+    }
+    assert_eq!(trace,vec![2,1,2,1,2]);
+}
+
+#[test]
+fn test_asm_branch_02() {
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",3).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc = [Jump(Var(0))];
+    let insns = [Instruction::new("jmp", &j, &mc),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let asm = Assembler::new(&isa);
+    // Offset of +3 fits, but +4 does not
+    assert!(asm.assemble("jmp l\nnop\nnop\nl: nop").is_ok());
+    let e = asm.assemble("jmp l\nnop\nnop\nnop\nl: nop").err().unwrap();
+    assert_eq!(e,AsmError{line:1,kind:AsmErrorKind::OutOfRange{label:"l".to_string(),value:4}});
+    // Offset of -4 fits, but -5 does not
+    assert!(asm.assemble("l: nop\nnop\nnop\nnop\njmp l").is_ok());
+    let e = asm.assemble("l: nop\nnop\nnop\nnop\nnop\njmp l").err().unwrap();
+    assert_eq!(e,AsmError{line:6,kind:AsmErrorKind::OutOfRange{label:"l".to_string(),value:-5}});
+}
+
+#[test]
+fn test_asm_branch_03() {
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).immediate("c",3).simmediate("off",3).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc = [Jump(SExt(Box::new(Var(1)),3))];
+    let insns = [Instruction::new("js", &j, &mc),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    // Sign extended offset to a backward label
+    let program = Assembler::new(&isa).assemble("nop\nl: nop\nnop\njs 0, l").unwrap();
+    assert_eq!(program.bytes()[3],isa.encode("js",&[0,(-2isize) as usize]).unwrap()[0]);
+    // Check execution follows the branch
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [0u8;1];
+    let mut state = State::new(3,&mut data);
+    state.step(&decoded).unwrap();
+    assert_eq!(state.pc,1);
+}
+
+// =====================================================
+// Pseudo Instructions
+// =====================================================
+
+#[test]
+fn test_asm_pseudo_01() {
+    let (rr,ri,_) = formats();
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",6).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let mc3 = [Jump(Var(0))];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("jmp", &j, &mc3)];
+    let pseudos = [PseudoInstruction::new("nop", &[("mov", &[Const(0),Const(0)])]),
+		   PseudoInstruction::new("clr", &[("ldi", &[Var(0),Const(0)])]),
+		   PseudoInstruction::new("dup", &[("mov", &[Var(0),Var(1)]), ("mov", &[Var(0),Var(1)])]),
+		   PseudoInstruction::new("b", &[("jmp", &[Var(0)])])];
+    let isa = InstructionSet::new(&insns).with_pseudos(&pseudos);
+    let src = "nop\n\
+	       clr r3\n\
+	       dup r1, r2\n\
+	       l: b l\n\
+	       b l\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.len(),6);
+    assert_eq!(program.bytes(),&[0x00,0x0D,0x00,0x44,0x44,0x02,0xFE]);
+    let asm = Assembler::new(&isa);
+    let e = asm.assemble("dup r1").err().unwrap();
+    assert_eq!(e,AsmError{line:1,kind:AsmErrorKind::WrongArity{expected:2,actual:1}});
+    let e = asm.assemble("clr 1").err().unwrap();
+    assert_eq!(e,AsmError{line:1,kind:AsmErrorKind::ExpectedRegister("1".to_string())});
+}
+
+#[test]
+fn test_asm_pseudo_03() {
+    let (_,ri,_) = formats();
+    // Load a scaled index, or the address after a label
+    let isa = virmin::insn::InstructionSetBuilder::new()
+	.instruction("ldi",&ri,&[Load(Var(0),Const(0),Byte)])
+	.pseudo("ldx",&[("ldi",&[Var(0),Shl(Box::new(Var(1)),Box::new(Const(2)))])])
+	.pseudo("lda",&[("ldi",&[Var(0),Add(Box::new(Var(1)),Box::new(Const(1)))])])
+	.build().ok().unwrap();
+    let program = Assembler::new(&isa).assemble("l: ldx r1, 3\nlda r2, l\nldx r3, -1\n").unwrap();
+    assert_eq!(program.bytes(),&[0x04,0x03,0x48,0x00,0x0C,0xFF]);
+}
+
+#[test]
+#[should_panic]
+fn test_asm_pseudo_02() {
+    let (rr,_,_) = formats();
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc)];
+    // Pseudo instruction refers to unknown instruction
+    let pseudos = [PseudoInstruction::new("nop", &[("add", &[Const(0),Const(0)])])];
+    let _isa = InstructionSet::new(&insns).with_pseudos(&pseudos);
+}
+
+// =====================================================
+// Macros & Constants
+// =====================================================
+
+#[test]
+fn test_asm_macro_01() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = "  .equ BASE, 0x10\n\
+	       .equ SIZE, (BASE + 2) * 2 - 1\n\
+	       .macro set reg, val\n\
+	       ldi \\reg, \\val\n\
+	       .endm\n\
+	       .macro set2 rd, rs, val\n\
+	       set \\rd, \\val\n\
+	       mov \\rs, \\rd\n\
+	       .endm\n\
+	       start: set2 r1, r2, SIZE\n\
+	       ldi r3, end - start\n\
+	       ldi r3, -BASE >> 2\n\
+	       end: nop\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.len(),5);
+    // ldi r1, 35; mov r2, r1; ldi r3, 4; ldi r3, -4; nop
+    assert_eq!(program.bytes(),&[0xC5,0x08,0x28,0x0D,0x01,0x0D,0xFF,0x02]);
+}
+
+#[test]
+fn test_asm_macro_02() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let asm = Assembler::new(&isa);
+    let err = |line,kind| Some(AsmError{line,kind});
+    assert_eq!(asm.assemble("nop\n.macro m\nnop\n").err(),err(2,AsmErrorKind::UnterminatedMacro("m".to_string())));
+    assert_eq!(asm.assemble(".macro m\nm\n.endm\nm").err(),err(4,AsmErrorKind::MacroRecursion("m".to_string())));
+    assert_eq!(asm.assemble(".macro m a\nnop\n.endm\nm").err(),err(4,AsmErrorKind::WrongArity{expected:1,actual:0}));
+    assert_eq!(asm.assemble(".word 1").err(),err(1,AsmErrorKind::UnknownDirective(".word".to_string())));
+    assert_eq!(asm.assemble(".equ A, 1\n.equ A, 2").err(),err(2,AsmErrorKind::DuplicateLabel("A".to_string())));
+    assert_eq!(asm.assemble(".equ A, B").err(),err(1,AsmErrorKind::UnknownLabel("B".to_string())));
+    assert_eq!(asm.assemble("ldi r1, 1/0").err(),err(1,AsmErrorKind::InvalidOperand("1/0".to_string())));
+    assert_eq!(asm.assemble("ldi r1, (1+2").err(),err(1,AsmErrorKind::InvalidOperand("(1+2".to_string())));
+}
+
+#[test]
+fn test_asm_macro_03() {
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",6).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc = [Jump(Var(0))];
+    let insns = [Instruction::new("jmp", &j, &mc),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    // Expressions involving labels are relative, whilst constants are not.
+    let src = ".equ N, 3\n\
+	       l: jmp l + 2\n\
+	       jmp N\n\
+	       nop\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.bytes(),&[0x08,0x0C,0x01]);
+}
+
+// =====================================================
+// Symbols & Relocations
+// =====================================================
+
+#[test]
+fn test_asm_symbols_01() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = ".global main, SIZE\n\
+	       .equ SIZE, 4\n\
+	       main: nop\n\
+	       ldi r1, SIZE\n\
+	       loop: mov r1, r2\n\
+	       nop\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.symbols(),&[Symbol{name:"main".to_string(),value:0,kind:SymbolKind::Label,global:true},
+				   Symbol{name:"loop".to_string(),value:2,kind:SymbolKind::Label,global:false},
+				   Symbol{name:"SIZE".to_string(),value:4,kind:SymbolKind::Constant,global:true}]);
+    assert_eq!(program.symbol("loop").map(|s| s.value),Some(2));
+    assert_eq!(program.symbol("nothing"),None);
+    assert_eq!(program.symbolize(1).map(|(s,d)| (s.name.as_str(),d)),Some(("main",1)));
+    assert_eq!(program.symbolize(3).map(|(s,d)| (s.name.as_str(),d)),Some(("loop",1)));
+    assert!(program.relocations().is_empty());
+}
+
+#[test]
+fn test_asm_symbols_02() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = ".extern buf, count\n\
+	       nop\n\
+	       ldi r1, buf\n\
+	       ldi r2, (count + 2) * 1 - 1\n";
+    let program = Assembler::new(&isa).assemble(src).unwrap();
+    assert_eq!(program.bytes(),&[0x02,0x05,0x00,0x09,0x00]);
+    assert_eq!(program.relocations(),&[Relocation{pc:1,operand:1,symbol:Some("buf".to_string()),addend:0,relative:false},
+				       Relocation{pc:2,operand:1,symbol:Some("count".to_string()),addend:1,relative:false}]);
+    // Errors
+    let asm = Assembler::new(&isa);
+    let err = |line,kind| Some(AsmError{line,kind});
+    let inv = |s: &str| AsmErrorKind::InvalidRelocation(s.to_string());
+    assert_eq!(asm.assemble(".extern x\nldi r1, 2*x").err(),err(2,inv("2*x")));
+    assert_eq!(asm.assemble(".extern x, y\nldi r1, x+y").err(),err(2,inv("y")));
+    assert_eq!(asm.assemble(".extern x\n.equ A, x").err(),err(2,inv("x")));
+    assert_eq!(asm.assemble(".extern x\nx: nop").err(),err(1,AsmErrorKind::DuplicateLabel("x".to_string())));
+    assert_eq!(asm.assemble(".global y\nnop").err(),err(1,AsmErrorKind::UnknownLabel("y".to_string())));
+}
+
+#[test]
+fn test_asm_symbols_03() {
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",6).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc = [Jump(Var(0))];
+    let insns = [Instruction::new("jmp", &j, &mc),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let program = Assembler::new(&isa).assemble(".extern f\nnop\njmp f - 1").unwrap();
+    assert_eq!(program.bytes(),&[0x01,0x00]);
+    assert_eq!(program.relocations(),&[Relocation{pc:1,operand:0,symbol:Some("f".to_string()),addend:-1,relative:true}]);
+}
+
+// =====================================================
+// Listings
+// =====================================================
+
+#[test]
+fn test_asm_listing_01() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = ".global start\n\
+	       .macro twice\n\
+	       nop\n\
+	       nop\n\
+	       .endm\n\
+	       start: ldi r1, 1\n\
+	       ; comment\n\
+	       twice";
+    let (program,listing) = Assembler::new(&isa).assemble_with_listing(src).unwrap();
+    assert_eq!(program.len(),3);
+    assert_eq!(listing.lines.len(),9);
+    assert_eq!(listing.lines[5],ListingLine{line:6,source:"start: ldi r1, 1".to_string(),pc:Some(0),address:Some(0),bytes:vec![0x45,0x00]});
+    assert_eq!(listing.lines[8],ListingLine{line:8,source:String::new(),pc:Some(2),address:Some(3),bytes:vec![0x02]});
+    let expected = ["   1              .global start",
+		    "   2              .macro twice",
+		    "   3              nop",
+		    "   4              nop",
+		    "   5              .endm",
+		    "   6 0000  45 00  start: ldi r1, 1",
+		    "   7              ; comment",
+		    "   8 0002  02     twice",
+		    "   8 0003  02",
+		    "",
+		    "start = 0 (label, global)",
+		    ""];
+    assert_eq!(listing.to_string(),expected.join("\n"));
+}
+
+#[test]
+fn test_asm_listing_02() {
+    let (rr,ri,n) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[])];
+    let isa = InstructionSet::new(&insns);
+    let src = ".macro twice\nnop\nnop\n.endm\nldi r1, 1\n\ntwice";
+    let program = Assembler::new(&isa).file("main.s").assemble(src).unwrap();
+    let loc = |line| Some(Location{file:"main.s".to_string(),line});
+    assert_eq!(program.location(0).cloned(),loc(5));
+    assert_eq!(program.location(1).cloned(),loc(7));
+    assert_eq!(program.location(2).cloned(),loc(7));
+    assert_eq!(program.location(3),None);
+    assert_eq!(program.location(0).unwrap().to_string(),"main.s:5");
+    assert_eq!(program.find("main.s",7),Some(1));
+    assert_eq!(program.find("main.s",6),None);
+    assert_eq!(program.find("other.s",5),None);
+}
+
+#[test]
+fn test_asm_relative_01() {
+    let (_,ri,n) = formats();
+    // Labels used as pc-relative operands encode their offset
+    let isa = virmin::insn::InstructionSetBuilder::new()
+	.instruction("nop",&n,&[])
+	.instruction("lpc",&ri,&[Copy(Var(0),PcRel(Box::new(Var(1))),Byte)])
+	.build().ok().unwrap();
+    let program = Assembler::new(&isa).assemble("l: nop\nlpc r1, l\nlpc r2, m\nm: nop\n").unwrap();
+    assert_eq!(program.bytes(),&[0x00,0xC5,0xFF,0x49,0x00,0x00]);
+}
+
+#[test]
+fn test_asm_pseudo_04() {
+    let (_,ri,_) = formats();
+    // Split an operand into its upper and lower parts
+    let isa = virmin::insn::InstructionSetBuilder::new()
+	.instruction("ldi",&ri,&[Load(Var(0),Const(0),Byte)])
+	.pseudo("ldw",&[("ldi",&[Var(0),Bits(Box::new(Var(1)),15,8)]),("ldi",&[Var(0),SExt(Box::new(Var(1)),4)])])
+	.build().ok().unwrap();
+    let program = Assembler::new(&isa).assemble("ldw r1, 0x12F\n").unwrap();
+    // ldi r1, 1; ldi r1, -1
+    assert_eq!(program.bytes(),&[0x44,0x00,0xC4,0xFF]);
+}
@@ -0,0 +1,53 @@
+#![cfg(feature="std")]
+use virmin::bench::{Primitives,Workload};
+use virmin::insn::{EncodeError,Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::DecodedProgram;
+
+// =====================================================
+// Workloads
+// =====================================================
+
+#[test]
+fn test_bench_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let primitives = Primitives{add:"add",mov:"mov",jnz:"jnz"};
+    let workloads = Workload::standard(&isa,&primitives).unwrap();
+    let names : Vec<&str> = workloads.iter().map(|w| w.name.as_str()).collect();
+    assert_eq!(names,vec!["arithmetic","copy","branches"]);
+    for workload in &workloads {
+	let measurement = workload.run(&isa,10_000).unwrap();
+	assert_eq!(measurement.instructions,10_000);
+	assert!(measurement.per_second() > 0.0);
+	assert!(measurement.to_string().starts_with(&format!("{}: ",workload.name)));
+    }
+    // Each workload runs 256 iterations before leaving the program
+    let copy = &workloads[1];
+    let program = DecodedProgram::new(&isa,&copy.program);
+    let mut memory = copy.memory.clone();
+    let mut state = State::new(0,&mut memory);
+    let mut count = 0;
+    while state.pc < program.len() {
+	state.step(&program).unwrap();
+	count += 1;
+    }
+    assert_eq!(count,256 * 7);
+    assert_eq!(memory,[0,0xFF,4,0,1,2,3,4]);
+}
+
+#[test]
+fn test_bench_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new().instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)]).build().ok().unwrap();
+    let primitives = Primitives{add:"add",mov:"mov",jnz:"jnz"};
+    assert_eq!(Workload::arithmetic(&isa,&primitives),Err(EncodeError::UnknownMnemonic("jnz".to_string())));
+}
@@ -0,0 +1,74 @@
+#![cfg(feature="capi")]
+use std::ffi::{c_int,c_void,CStr};
+use virmin::capi::*;
+use virmin::machine::SP;
+
+// =====================================================
+// C Interface
+// =====================================================
+
+const SPEC : &CStr = c"
+[[formats]]
+name = \"rr\"
+width = 1
+opcode = 2
+fields = [ { name = \"rd\", bits = 3 }, { name = \"rs\", bits = 3 } ]
+
+[[instructions]]
+mnemonic = \"add\"
+format = \"rr\"
+semantics = [ { Add = [ { Var = 0 }, { Var = 1 }, \"Byte\" ] } ]
+";
+
+/// Counts how often it is called, setting M[0] to 0xFF and stopping
+/// on the second call.
+extern "C" fn hook(machine: *mut Machine, _pc: usize, user: *mut c_void) -> c_int {
+    let count = unsafe { &mut *(user as *mut usize) };
+    *count += 1;
+    unsafe { virmin_machine_write(machine,0,[0xFF].as_ptr(),1); }
+    (*count == 2) as c_int
+}
+
+#[test]
+fn test_capi_01() {
+    unsafe {
+	let isa = virmin_isa_from_toml(SPEC.as_ptr());
+	assert!(!isa.is_null());
+	let m = virmin_machine_new(isa,4);
+	// add 0, 1; add 2, 3; add 0, 1
+	let image = [0x20,0x68,0x20];
+	assert_eq!(virmin_machine_load_image(m,image.as_ptr(),image.len()),VIRMIN_OK);
+	assert_eq!(virmin_machine_write(m,0,[1,2,3,4].as_ptr(),4),VIRMIN_OK);
+	let mut executed = 0;
+	assert_eq!(virmin_machine_run(m,1,&mut executed),VIRMIN_OK);
+	assert_eq!((executed,virmin_machine_pc(m)),(1,1));
+	assert_eq!(virmin_machine_run(m,10,&mut executed),VIRMIN_HALTED);
+	assert_eq!((executed,virmin_machine_pc(m)),(2,3));
+	let mut bytes = [0u8;4];
+	assert_eq!(virmin_machine_read(m,0,bytes.as_mut_ptr(),4),VIRMIN_OK);
+	assert_eq!(bytes,[5,2,7,4]);
+	assert_eq!(virmin_machine_read(m,2,bytes.as_mut_ptr(),4),VIRMIN_ERROR);
+	assert_eq!(CStr::from_ptr(virmin_last_error()).to_str().unwrap(),"memory 0x2..0x6 out of bounds");
+	// Hooks
+	let mut count = 0usize;
+	virmin_machine_set_pc(m,0);
+	virmin_machine_add_hook(m,2,hook,&mut count as *mut usize as *mut c_void);
+	assert_eq!(virmin_machine_run(m,10,&mut executed),VIRMIN_HALTED);
+	assert_eq!((executed,count),(3,1));
+	assert_eq!(virmin_machine_read(m,0,bytes.as_mut_ptr(),1),VIRMIN_OK);
+	assert_eq!(bytes[0],0xFFu8.wrapping_add(2));
+	virmin_machine_set_pc(m,0);
+	assert_eq!(virmin_machine_run(m,10,&mut executed),VIRMIN_HOOK);
+	assert_eq!((executed,count,virmin_machine_pc(m)),(2,2,2));
+	assert!(virmin_machine_remove_hook(m,2));
+	// Registers
+	let mut sp = 0;
+	assert_eq!(virmin_machine_write_register(m,SP,12),VIRMIN_OK);
+	assert_eq!(virmin_machine_read_register(m,SP,&mut sp),VIRMIN_OK);
+	assert_eq!(sp,12);
+	assert_eq!(virmin_machine_read_register(m,0,&mut sp),VIRMIN_ERROR);
+	virmin_machine_free(m);
+	virmin_isa_free(isa);
+	assert!(virmin_isa_from_toml(c"[[formats]]".as_ptr()).is_null());
+    }
+}
@@ -0,0 +1,91 @@
+#![cfg(feature="std")]
+use virmin::cfg::*;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::{Lr,Pc,Var};
+use virmin::machine::MicroCode;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Flow
+// =====================================================
+
+#[test]
+fn test_flow_01() {
+    use MicroCode::*;
+    let flow = Flow::of(4,&[SkipIfZero(0,Byte,1),Jump(-2)]);
+    assert_eq!(flow,Flow{fallthrough:true,targets:vec![2],call:false,returns:false,indirect:false});
+    let flow = Flow::of(4,&[Goto(1),Add(0,1,Byte)]);
+    assert!(!flow.fallthrough);
+    let flow = Flow::of(4,&[RegLoad(virmin::machine::LR,5),Goto(7)]);
+    assert_eq!(flow,Flow{fallthrough:false,targets:vec![7],call:true,returns:false,indirect:false});
+    let flow = Flow::of(4,&[RegFetch(virmin::machine::PC,0,Byte)]);
+    assert!(flow.indirect && !flow.fallthrough);
+}
+
+// =====================================================
+// Graph
+// =====================================================
+
+#[test]
+fn test_cfg_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("j",&fmt,&[Goto(Var(0))])
+	.instruction("call",&fmt,&[RegLoad(Lr,Var(1)),Goto(Var(0))])
+	.instruction("ret",&fmt,&[RegCopy(Pc,Lr)])
+	.instruction("jr",&fmt,&[RegCopy(Pc,Var(0))])
+	.build().ok().unwrap();
+    // 0: loop until mem[0] is zero, then call 4 and exit
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    program.push("call",&[4,3]).unwrap();
+    program.push("j",&[7,0]).unwrap();
+    program.push("add",&[1,1]).unwrap();
+    program.push("ret",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let cfg = Cfg::new(&decoded);
+    let pcs : Vec<_> = cfg.blocks.iter().map(|b| b.pcs.clone()).collect();
+    assert_eq!(pcs,vec![0..2,2..3,3..4,4..6]);
+    assert_eq!(cfg.edges,vec![Edge{from:0,to:Some(0),kind:EdgeKind::Branch},
+			      Edge{from:0,to:Some(1),kind:EdgeKind::Fallthrough},
+			      Edge{from:1,to:Some(3),kind:EdgeKind::Call},
+			      Edge{from:1,to:Some(2),kind:EdgeKind::Fallthrough},
+			      Edge{from:2,to:None,kind:EdgeKind::Branch}]);
+    assert!(cfg.blocks[3].returns);
+    assert_eq!((cfg.block_of(5),cfg.block_of(6)),(Some(3),None));
+    assert_eq!(cfg.predecessors(0).count(),1);
+    assert_eq!(cfg.successors(1).count(),2);
+    assert_eq!(cfg.reachable(),vec![true;4]);
+}
+
+#[test]
+fn test_cfg_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("j",&fmt,&[Goto(Var(0))])
+	.instruction("call",&fmt,&[RegLoad(Lr,Var(1)),Goto(Var(0))])
+	.instruction("ret",&fmt,&[RegCopy(Pc,Lr)])
+	.instruction("jr",&fmt,&[RegCopy(Pc,Var(0))])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("jr",&[0,0]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("j",&[1,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let cfg = Cfg::new(&decoded);
+    assert!(cfg.blocks[0].indirect);
+    assert_eq!(cfg.reachable(),vec![true,false]);
+    assert_eq!(cfg.to_dot(&decoded),"digraph cfg {\n  node [shape=box,fontname=monospace];\n  \
+b0 [label=\"0000: jr 0, 0\\l(indirect)\\l\"];\n  b1 [label=\"0001: add 0, 1\\l0002: j 1, 0\\l\"];\n  \
+b1 -> b1 [color=blue];\n}\n");
+    // Empty programs
+    let decoded = DecodedProgram::new(&isa,&[]);
+    assert_eq!(Cfg::new(&decoded),Cfg{blocks:vec![],edges:vec![]});
+}
@@ -0,0 +1,97 @@
+#![cfg(feature="spec")]
+use std::fs;
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{Command,Output,Stdio};
+
+// =====================================================
+// Command Line
+// =====================================================
+
+const SPEC : &str = r#"
+[[formats]]
+name = "rr"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "rs", bits = 3 } ]
+
+[[formats]]
+name = "ri"
+width = 2
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "imm", bits = 11, kind = "Immediate" } ]
+
+[[instructions]]
+mnemonic = "add"
+format = "rr"
+semantics = [ { Add = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+
+[[instructions]]
+mnemonic = "ldi"
+format = "ri"
+semantics = [ { Load = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+"#;
+
+/// Create an empty directory (for a given test) holding the
+/// specification above.
+fn setup(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("virmin-{}-{}",name,std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("isa.toml"),SPEC).unwrap();
+    dir
+}
+
+fn virmin(dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_virmin")).current_dir(dir).args(args).output().unwrap()
+}
+
+#[test]
+fn test_cli_01() {
+    let dir = setup("01");
+    fs::write(dir.join("test.s"),"ldi r1, 5\nldi r2, 7\nadd r1, r2\n").unwrap();
+    let out = virmin(&dir,&["asm","test.s","--isa","isa.toml"]);
+    assert!(out.status.success());
+    assert_eq!(fs::read(dir.join("test.bin")).unwrap().len(),5);
+    let out = virmin(&dir,&["disasm","test.bin","--isa","isa.toml"]);
+    assert!(out.status.success());
+    let listing = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(listing.lines().map(|l| l.split("  ").last().unwrap().trim()).collect::<Vec<_>>(),vec!["ldi r1, 5","ldi r2, 7","add r1, r2"]);
+    let out = virmin(&dir,&["run","test.bin","--isa","isa.toml","--memory","4","--trace","--dump"]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(),
+	       "0000: ldi r1, 5\n0002: ldi r2, 7\n0004: add r1, r2\nhalted at pc 3 after 3 steps\n0000: 00 0c 07 00\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cli_02() {
+    let dir = setup("02");
+    fs::write(dir.join("test.s"),"ldi r1, 5\nbad r1\n").unwrap();
+    let out = virmin(&dir,&["asm","test.s","--isa","isa.toml","-o","out.bin"]);
+    assert!(!out.status.success());
+    assert!(!dir.join("out.bin").exists());
+    let out = virmin(&dir,&["run","test.s"]);
+    assert!(String::from_utf8(out.stderr).unwrap().contains("missing --isa"));
+    let out = virmin(&dir,&["frobnicate"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr).unwrap().contains("usage:"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_cli_03() {
+    let dir = setup("03");
+    fs::write(dir.join("test.s"),"ldi r1, 5\nldi r2, 7\nadd r1, r2\n").unwrap();
+    assert!(virmin(&dir,&["asm","test.s","--isa","isa.toml"]).status.success());
+    let mut child = Command::new(env!("CARGO_BIN_EXE_virmin")).current_dir(&dir)
+	.args(["debug","test.bin","--isa","isa.toml","--memory","4"])
+	.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"break 2\ncontinue\nmem 0 4\nfrob\nquit\nstep\n").unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(),
+	       "0000: ldi r1, 5\n(virmin) breakpoint at pc 2\n(virmin) breakpoint at pc 2\n=> 0004: add r1, r2\n\
+		(virmin) 0000: 00 05 07 00\n(virmin) error: unknown command \"frob\" (try \"help\")\n(virmin) ");
+    fs::remove_dir_all(&dir).unwrap();
+}
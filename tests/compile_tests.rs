@@ -0,0 +1,64 @@
+#![cfg(feature="std")]
+use virmin::compile::{compile,CompiledSet};
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::{Bits,Const,PcRel,SExt,Temp,Var};
+use virmin::machine::{RegisterFile,State};
+use virmin::machine::Width::{Byte,QuadWord};
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Compiled Instructions
+// =====================================================
+
+#[test]
+fn test_compile_01() {
+    let fmt = Format::builder().width_bytes(2).opcode_bits(4).immediate("a",3).immediate("b",3).simmediate("c",6).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("ldi",&fmt,&[Load(Var(0),SExt(Box::new(Var(2)),4),Byte)])
+	.instruction("bz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![Goto(PcRel(Box::new(Var(2))))],vec![])])
+	.instruction("rot",&fmt,&[RegFetch(Temp(0),Var(0),Byte),RegAdd(Temp(0),Var(1)),RegStore(Bits(Box::new(Var(2)),2,0),Temp(0),Byte)])
+	.instruction("skip",&fmt,&[If(Predicate::Ne(Var(0),Const(0)),vec![Jump(Const(2))],vec![Copy(Var(0),Var(1),Byte)])])
+	.build().ok().unwrap();
+    // Compare against the (uncompiled) microcode
+    for (index,insn) in isa.iter().enumerate() {
+	let compiled = compile(insn);
+	for operands in [[0,1,0],[1,2,9],[3,1,-3isize as usize],[2,0,15],[4,4,-8isize as usize]] {
+	    for initial in [[0u8,0,0,0,0,0,0,0],[1,2,3,4,5,6,7,8]] {
+		let (mut expected,mut actual) = (initial,initial);
+		let mut s1 = State::new(5,&mut expected).with_registers(RegisterFile::new(8,QuadWord));
+		let mut s2 = State::new(5,&mut actual).with_registers(RegisterFile::new(8,QuadWord));
+		s1.execute_all(&insn.to_microcode_at(5,&operands));
+		compiled(&mut s2,&operands);
+		assert_eq!((s1.pc,s1.registers.read(1)),(s2.pc,s2.registers.read(1)),"{} {:?}",index,operands);
+		drop((s1,s2));
+		assert_eq!(expected,actual,"{} {:?}",index,operands);
+	    }
+	}
+    }
+}
+
+#[test]
+fn test_compile_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // M[0] := 3; do { M[2] := M[2] + M[3]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let compiled = CompiledSet::new(&isa);
+    let mut bytes = [3,0xFF,0,2];
+    let mut state = State::new(0,&mut bytes);
+    while state.pc < decoded.len() {
+	compiled.step(&mut state,&decoded).unwrap();
+    }
+    assert_eq!(state.pc,3);
+    assert_eq!(bytes,[0,0xFF,6,2]);
+}
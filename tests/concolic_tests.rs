@@ -0,0 +1,131 @@
+#![cfg(feature="std")]
+use virmin::concolic::*;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::Var;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Execution
+// =====================================================
+
+#[test]
+fn test_execute_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("li",&[4,3]).unwrap();
+    program.push("add",&[0,4]).unwrap();
+    program.push("jnz",&[0,4]).unwrap();
+    program.push("li",&[5,1]).unwrap();
+    program.push("jnz",&[4,6]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let concolic = Concolic::new(&decoded,0..2).memory(8);
+    let execution = concolic.execute(&[0,0]);
+    // Only the first branch depends on the input
+    assert_eq!(execution.steps,4);
+    assert_eq!(execution.fault,None);
+    assert_eq!(execution.branches.len(),1);
+    assert_eq!(execution.branches[0].inputs,vec![0]);
+    assert_eq!((execution.branches[0].outcome.pc,execution.branches[0].outcome.taken),(2,false));
+    let execution = concolic.execute(&[253]);
+    assert_eq!(execution.steps,5);
+    assert!(execution.branches[0].outcome.taken);
+}
+
+#[test]
+fn test_execute_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("jnz",&[3,2]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    // Memory always covers the input
+    let concolic = Concolic::new(&decoded,0..4).memory(2);
+    let execution = concolic.execute(&[0,0,0,1]);
+    assert_eq!(execution.fault,None);
+    assert_eq!(execution.branches[0].inputs,vec![3]);
+    assert!(!execution.branches[0].outcome.taken);
+}
+
+// =====================================================
+// Exploration
+// =====================================================
+
+#[test]
+fn test_explore_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // if in[0] + 3 != 0 goto 4; mem[5] := 1; if in[1] != 0 goto 6; mem[6] := 2
+    let mut program = Program::new(&isa);
+    program.push("li",&[4,3]).unwrap();
+    program.push("add",&[0,4]).unwrap();
+    program.push("jnz",&[0,4]).unwrap();
+    program.push("li",&[5,1]).unwrap();
+    program.push("jnz",&[1,6]).unwrap();
+    program.push("li",&[6,2]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let exploration = Concolic::new(&decoded,0..2).memory(8).explore(&[]);
+    let inputs : Vec<&[u8]> = exploration.paths.iter().map(|p| &p.input[..]).collect();
+    assert_eq!(inputs,vec![&[0,0][..],&[253,0],&[0,1]]);
+    assert_eq!(exploration.covered.len(),4);
+    assert!(exploration.unsolved.is_empty());
+    let outcomes : Vec<(usize,bool)> = exploration.paths[1].outcomes.iter().map(|o| (o.pc,o.taken)).collect();
+    assert_eq!(outcomes,vec![(2,true),(4,true)]);
+    assert!(exploration.to_string().starts_with("path 0: input [00 00] (2 branches)\npath 1: input [fd 00] (2 branches)\n"));
+    assert!(exploration.to_string().ends_with("3 paths, 4 outcomes covered\n"));
+    // Limiting the number of paths
+    let exploration = Concolic::new(&decoded,0..2).memory(8).paths(2).explore(&[]);
+    assert_eq!(exploration.paths.len(),2);
+}
+
+#[test]
+fn test_explore_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // 2 * in[0] + 1 is never zero
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,0]).unwrap();
+    program.push("li",&[4,1]).unwrap();
+    program.push("add",&[0,4]).unwrap();
+    program.push("jnz",&[0,4]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let exploration = Concolic::new(&decoded,0..1).memory(8).explore(&[7]);
+    assert_eq!(exploration.paths.len(),1);
+    let unsolved : Vec<(usize,bool)> = exploration.unsolved.iter().map(|o| (o.pc,o.taken)).collect();
+    assert_eq!(unsolved,vec![(3,true)]);
+}
+
+#[test]
+fn test_explore_03() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // Input is out of bounds for the add when in[0] == 0
+    let mut program = Program::new(&isa);
+    program.push("jnz",&[0,2]).unwrap();
+    program.push("add",&[7,7]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let exploration = Concolic::new(&decoded,0..1).memory(7).explore(&[1]);
+    assert_eq!(exploration.paths.len(),2);
+    assert_eq!(exploration.paths[0].fault,None);
+    assert!(exploration.paths[1].fault.is_some());
+    assert_eq!(exploration.paths[1].input,vec![0]);
+}
@@ -0,0 +1,5 @@
+; r1 := 5 + 7, then store it
+	li r1, 5
+	li r2, 7
+	add r1, r2
+	st r1, 16
@@ -0,0 +1,2 @@
+	ld r0, 0x10
+	ld r1, 0x11
@@ -0,0 +1 @@
+	st r0, 300
@@ -0,0 +1,110 @@
+#![cfg(feature="std")]
+use std::path::Path;
+use virmin::conformance::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::{Byte,DoubleWord,Word};
+
+fn isa_with<F:FnOnce(&InstructionSet)>(f: F) {
+    let rr = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ri = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let li = [RegLoad(Var(0),Var(1))];
+    let add = [RegAdd(Var(0),Var(1))];
+    let ld = [RegFetch(Var(0),Var(1),Byte)];
+    let st = [RegStore(Var(1),Var(0),DoubleWord)];
+    let insns = [Instruction::new("li", &ri, &li),
+		 Instruction::new("add", &rr, &add),
+		 Instruction::new("ld", &ri, &ld),
+		 Instruction::new("st", &ri, &st)];
+    f(&InstructionSet::new(&insns))
+}
+
+fn suite() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"),"/tests/conformance"))
+}
+
+// =====================================================
+// Cases
+// =====================================================
+
+#[test]
+fn test_case_01() {
+    let case = Case::parse("c","\tli r1, 1\n","memory = 16\nregisters = 2 # two\n\nr0 = -1\nmem16[2] = 1 0x203\n","fault\nsteps = 0\n").unwrap();
+    assert_eq!(case.memory,16);
+    assert_eq!(case.registers,2);
+    assert_eq!(case.fuel,FUEL);
+    assert_eq!(case.initial,vec![Assignment{location:Location::Register(0),value:u64::MAX},
+				 Assignment{location:Location::Memory(2,Word),value:1},
+				 Assignment{location:Location::Memory(4,Word),value:0x203}]);
+    assert_eq!(case.steps,Some(0));
+    assert!(case.fault);
+    assert_eq!(case.expected,vec![]);
+}
+
+#[test]
+fn test_case_02() {
+    let error = |message: &str, file: &str, line: usize| Err(ConformanceError::Parse{file:file.to_string(),line,message:message.to_string()});
+    assert_eq!(Case::parse("c","","\nr0 5",""),error("expected \"=\"","c.init",2));
+    assert_eq!(Case::parse("c","","r0 = 0xZ",""),error("invalid number \"0xZ\"","c.init",1));
+    assert_eq!(Case::parse("c","","r0 = 1 2",""),error("expected one value for \"r0\"","c.init",1));
+    assert_eq!(Case::parse("c","","mem128[0] = 1",""),error("invalid location \"mem128[0]\"","c.init",1));
+    assert_eq!(Case::parse("c","","width = 12",""),error("width must be 8, 16, 32 or 64","c.init",1));
+    assert_eq!(Case::parse("c","","steps = 1",""),error("unknown setting \"steps\"","c.init",1));
+    assert_eq!(Case::parse("c","","fault",""),error("fault can only be expected","c.init",1));
+    assert_eq!(Case::parse("c","","","pc =\n"),error("expected value","c.expected",1));
+    assert_eq!(Case::parse("c","","","memory = 1\n"),error("unknown setting \"memory\"","c.expected",1));
+}
+
+// =====================================================
+// Runner
+// =====================================================
+
+#[test]
+fn test_runner_01() {
+    isa_with(|isa| {
+	let report = Runner::new(isa).run_dir(suite()).unwrap();
+	assert!(report.passed(),"{}",report);
+	assert_eq!(report.to_string(),"PASS add\nPASS load\nPASS store_out_of_bounds\n3 passed, 0 failed\n");
+    });
+}
+
+#[test]
+fn test_runner_02() {
+    isa_with(|isa| {
+	let runner = Runner::new(isa);
+	let case = Case::parse("c","\tli r1, 3\n\tst r1, 8\n","registers = 2\nr0 = 4","steps = 1\nr0 = 4\nr1 = 2\nmem[8] = 3 1\nr7 = 0\n").unwrap();
+	let mismatches = runner.run(&case).unwrap();
+	assert_eq!(mismatches,vec![Mismatch::Steps{expected:1,actual:2},
+				   Mismatch::Value{location:Location::Register(1),expected:2,actual:3},
+				   Mismatch::Value{location:Location::Memory(9,Byte),expected:1,actual:0},
+				   Mismatch::Missing(Location::Register(7))]);
+	let report = Report{results:vec![("c".to_string(),mismatches)]};
+	assert!(!report.passed());
+	assert_eq!(report.failures(),vec!["c"]);
+	assert_eq!(report.to_string(),"FAIL c\n  expected 1 steps, found 2\n  r1: expected 0x2, found 0x3\n  mem[0x9]: expected 0x1, found 0x0\n  r7: no such location\n0 passed, 1 failed\n");
+    });
+}
+
+#[test]
+fn test_runner_03() {
+    isa_with(|isa| {
+	let runner = Runner::new(isa);
+	// Unexpected fault
+	let case = Case::parse("c","\tst r0, 300\n","registers = 1","").unwrap();
+	let mismatches = runner.run(&case).unwrap();
+	assert_eq!(mismatches.len(),1);
+	assert!(matches!(&mismatches[0],Mismatch::Fault(Some(_))));
+	// Missing fault
+	let case = Case::parse("c","\tli r0, 1\n","registers = 1","fault").unwrap();
+	assert_eq!(runner.run(&case),Ok(vec![Mismatch::Fault(None)]));
+	// Initial location does not exist
+	let case = Case::parse("c","","memory = 4\nmem32[2] = 0","").unwrap();
+	assert_eq!(runner.run(&case),Ok(vec![Mismatch::Missing(Location::Memory(2,DoubleWord))]));
+	// Program does not assemble
+	let case = Case::parse("c","\tnop\n","","").unwrap();
+	assert!(matches!(runner.run(&case),Err(ConformanceError::Asm{..})));
+	// Missing directory
+	assert!(matches!(runner.run_dir(Path::new("/nonexistent")),Err(ConformanceError::Io{..})));
+    });
+}
@@ -0,0 +1,63 @@
+#![cfg(feature="std")]
+use virmin::coverage::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Coverage
+// =====================================================
+
+#[test]
+fn test_coverage_01() {
+    let rr = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ri = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &rr, &[])];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,2]).unwrap();
+    program.push("mov",&[7,0]).unwrap();
+    program.push("ldi",&[3,-5isize as usize]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [0u8;16];
+    let mut state = State::new(0,&mut bytes);
+    let mut coverage = Coverage::new(&isa);
+    while state.pc < decoded.len() {
+	coverage.step(&mut state,&decoded).unwrap();
+    }
+    assert_eq!((coverage.hits(0),coverage.hits(1),coverage.hits(2)),(2,1,0));
+    assert_eq!(coverage.covered(),2);
+    assert_eq!(coverage.uncovered(),vec!["nop"]);
+    assert_eq!(coverage.buckets(0,0),vec![0,3]);
+    assert_eq!(coverage.buckets(0,1),vec![0,1]);
+    assert_eq!(coverage.buckets(1,1),vec![3]);
+    assert_eq!(coverage.pairs(),vec![((0,0),1),((0,1),1)]);
+    let report = coverage.to_string();
+    assert!(report.starts_with("2/3 instructions covered\n"));
+    assert!(report.contains("  mov rd: missing bucket(s) 1,2\n"));
+    assert!(report.contains("  ldi imm: missing bucket(s) 0,1,2\n"));
+    assert!(report.contains("  nop: never executed\n"));
+}
+
+#[test]
+fn test_coverage_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",1).build().ok().unwrap();
+    let insns = [Instruction::new("a", &fmt, &[]), Instruction::new("b", &fmt, &[])];
+    let isa = InstructionSet::new(&insns);
+    let mut first = Coverage::new(&isa);
+    first.record(0,&[0]);
+    let mut second = Coverage::new(&isa);
+    second.record(0,&[1]);
+    second.record(1,&[1]);
+    first.merge(&second);
+    assert_eq!(first.uncovered(),Vec::<&str>::new());
+    assert_eq!(first.buckets(0,0),vec![0,1]);
+    assert_eq!(first.to_string(),"2/2 instructions covered\n  b rd: missing bucket(s) 0\n");
+}
@@ -0,0 +1,116 @@
+#![cfg(feature="std")]
+use std::collections::BTreeSet;
+use virmin::dataflow::*;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::{Const,Var};
+use virmin::machine::{temp,MicroCode,SP};
+use virmin::machine::Width::{Byte,Word};
+use virmin::program::{DecodedProgram,Program};
+
+fn p(pc: usize, index: usize) -> Point {
+    Point{pc,index}
+}
+
+// =====================================================
+// Locations
+// =====================================================
+
+#[test]
+fn test_accesses_01() {
+    use Location::*;
+    assert_eq!(accesses(MicroCode::Add(4,6,Word)),(vec![Memory(4),Memory(5)],vec![Memory(4),Memory(5),Memory(6),Memory(7)]));
+    assert_eq!(accesses(MicroCode::RegCopy(virmin::machine::PC,2)),(vec![],vec![Register(2)]));
+    assert_eq!(accesses(MicroCode::Skip(1)),(vec![],vec![]));
+    assert_eq!(accesses(MicroCode::RegStoreIndirect(SP,2,Word)),(vec![],vec![Register(SP),Register(2)]));
+    assert_eq!((Register(SP).to_string(),Register(temp(1)).to_string(),Register(3).to_string(),Memory(16).to_string()),
+	       ("sp".to_string(),"t1".to_string(),"reg[3]".to_string(),"mem[0x10]".to_string()));
+}
+
+// =====================================================
+// Liveness
+// =====================================================
+
+#[test]
+fn test_liveness_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(1),Var(0))])
+	.instruction("st",&fmt,&[RegFetch(Const(temp(0)),Var(0),Word),RegStore(Var(1),Const(temp(0)),Word)])
+	.build().ok().unwrap();
+    // mem[1] := 1 is overwritten before being read
+    let mut program = Program::new(&isa);
+    program.push("li",&[1,1]).unwrap();
+    program.push("li",&[1,2]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("li",&[2,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let dataflow = Dataflow::new(&decoded);
+    assert_eq!(dataflow.points().len(),8);
+    assert_eq!(dataflow.dead_stores(),vec![p(0,0)]);
+    let live : BTreeSet<Location> = [Location::Memory(0)].into();
+    assert_eq!(dataflow.live_after(p(1,0)),Some(&[Location::Memory(0),Location::Memory(1)].into()));
+    assert_eq!(dataflow.live_before(p(1,0)),Some(live));
+    assert_eq!(dataflow.live_after(p(9,0)),None);
+}
+
+#[test]
+fn test_liveness_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(1),Var(0))])
+	.instruction("st",&fmt,&[RegFetch(Const(temp(0)),Var(0),Word),RegStore(Var(1),Const(temp(0)),Word)])
+	.build().ok().unwrap();
+    // Values read within a loop are live around it, whilst scratch
+    // registers never live beyond an instruction
+    let mut program = Program::new(&isa);
+    program.push("li",&[1,1]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,1]).unwrap();
+    program.push("st",&[0,2]).unwrap();
+    program.push("lr",&[3,1]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let dataflow = Dataflow::new(&decoded);
+    assert_eq!(dataflow.dead_stores(),vec![]);
+    assert!(dataflow.live_after(p(2,2)).unwrap().contains(&Location::Memory(1)));
+    assert!(dataflow.live_after(p(3,0)).unwrap().contains(&Location::Register(temp(0))));
+    assert!(!dataflow.live_after(p(3,1)).unwrap().contains(&Location::Register(temp(0))));
+}
+
+// =====================================================
+// Def-Use
+// =====================================================
+
+#[test]
+fn test_defuse_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(3).immediate("a",3).immediate("b",2).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("li",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.instruction("lr",&fmt,&[RegLoad(Var(1),Var(0))])
+	.instruction("st",&fmt,&[RegFetch(Const(temp(0)),Var(0),Word),RegStore(Var(1),Const(temp(0)),Word)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("li",&[1,1]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,1]).unwrap();
+    program.push("st",&[0,2]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let dataflow = Dataflow::new(&decoded);
+    // mem[0] is written by the add (in the loop) or is initial
+    assert_eq!(dataflow.definitions(p(1,0),Location::Memory(0)),vec![Definition::Initial,Definition::At(p(1,0))]);
+    assert_eq!(dataflow.definitions(p(3,0),Location::Memory(0)),vec![Definition::At(p(1,0))]);
+    assert_eq!(dataflow.definitions(p(3,0),Location::Memory(1)),vec![Definition::At(p(0,0))]);
+    assert_eq!(dataflow.uses(p(0,0)),vec![p(1,0),p(3,0)]);
+    assert_eq!(dataflow.uses(p(1,0)),vec![p(1,0),p(2,0),p(3,0)]);
+    // The scratch register is cleared at the end of the instruction
+    assert_eq!(dataflow.definitions(p(3,1),Location::Register(temp(0))),vec![Definition::At(p(3,0))]);
+    assert_eq!(dataflow.uses(p(3,0)),vec![p(3,1)]);
+}
@@ -0,0 +1,93 @@
+#![cfg(feature="std")]
+use virmin::debug::{DebugError,Debugger,Stop};
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Debugger
+// =====================================================
+
+#[test]
+fn test_debug_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // do { M[2] := M[2] + M[3]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut debugger = Debugger::new(&decoded);
+    let mut bytes = [3,0xFF,0,1];
+    let mut state = State::new(0,&mut bytes);
+    assert_eq!(debugger.command(&mut state,"break 2"),Ok("breakpoint at pc 2".to_string()));
+    assert_eq!(debugger.command(&mut state,"continue"),Ok("breakpoint at pc 2\n=> 0002: jnz 0, 0".to_string()));
+    assert_eq!(debugger.command(&mut state,"mem 0 4"),Ok("0000: 02 ff 01 01".to_string()));
+    assert_eq!(debugger.command(&mut state,"disasm 1"),Ok("   0001: add 0, 1\n=> 0002: jnz 0, 0".to_string()));
+    assert_eq!(debugger.command(&mut state,"step 2"),Ok("=> 0001: add 0, 1".to_string()));
+    assert_eq!(debugger.command(&mut state,"disasm 1"),Ok("   0000: add 2, 3\n=> 0001: add 0, 1\n * 0002: jnz 0, 0".to_string()));
+    assert!(debugger.command(&mut state,"regs").unwrap().starts_with("pc    0x1\n"));
+    assert_eq!(debugger.command(&mut state,"mem 2 3"),Err(DebugError::Memory{address:2,length:3}));
+    assert_eq!(debugger.command(&mut state,"break"),Err(DebugError::Argument("missing number".to_string())));
+    assert_eq!(debugger.command(&mut state,"frob"),Err(DebugError::Unknown("frob".to_string())));
+    assert_eq!(debugger.command(&mut state,"delete 0x2"),Ok("deleted breakpoint at pc 2".to_string()));
+    assert!(debugger.command(&mut state,"delete 2").is_err());
+    assert_eq!(debugger.command(&mut state,"continue"),Ok("halted at pc 3\nhalted at pc 3".to_string()));
+    assert_eq!(debugger.command(&mut state,"step"),Ok("halted at pc 3".to_string()));
+    assert_eq!(bytes,[0,0xFF,3,1]);
+}
+
+#[test]
+fn test_debug_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut debugger = Debugger::new(&decoded);
+    let mut bytes = [0,1];
+    let mut state = State::new(0,&mut bytes);
+    // A breakpoint at the current pc does not stop execution
+    debugger.add_breakpoint(0);
+    assert_eq!(debugger.resume(&mut state,100),Ok(Stop::Breakpoint(0)));
+    assert_eq!(debugger.resume(&mut state,1),Ok(Stop::Limit));
+    assert_eq!(state.pc,1);
+    assert!(debugger.remove_breakpoint(0));
+    assert_eq!(debugger.breakpoints().count(),0);
+    assert_eq!(debugger.resume(&mut state,usize::MAX),Ok(Stop::Halted(2)));
+}
+
+#[test]
+fn test_debug_03() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut debugger = Debugger::new(&decoded);
+    let mut bytes = [0,1];
+    let mut state = State::new(0,&mut bytes);
+    // Who wrote the values read at each pc?
+    assert_eq!(debugger.command(&mut state,"writers mem 0"),Ok("initial value\n   0  0000: add 0, 1".to_string()));
+    assert_eq!(debugger.command(&mut state,"writers mem 1"),Ok("initial value".to_string()));
+    assert_eq!(debugger.command(&mut state,"step"),Ok("=> 0001: jnz 0, 0".to_string()));
+    assert_eq!(debugger.command(&mut state,"writers mem 0"),Ok("   0  0000: add 0, 1".to_string()));
+    assert_eq!(debugger.command(&mut state,"writers reg 9"),Ok(String::new()));
+    assert!(debugger.command(&mut state,"writers pc").is_err());
+}
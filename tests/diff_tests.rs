@@ -0,0 +1,50 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::diff::{diff,Change};
+use virmin::insn::{Category,Format,Instruction,InstructionSet,Metadata};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::{Byte,Word};
+
+// =====================================================
+// Diff
+// =====================================================
+
+#[test]
+fn test_diff_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc), Instruction::new("nop", &fmt, &[])];
+    let isa = InstructionSet::new(&insns);
+    let d = diff(&isa,&isa);
+    assert!(d.is_empty());
+    assert!(!d.is_breaking());
+    assert_eq!(d.to_string(),"");
+}
+
+#[test]
+fn test_diff_02() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",TWO_BITS, &[SIX_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Copy(Var(0),Var(1),Word)];
+    let v1 = [Instruction::new("mov", &fmt1, &mc1),
+	      Instruction::new("clr", &fmt1, &[]),
+	      Instruction::new("ldi", &fmt1, &[]),
+	      Instruction::new("nop", &fmt1, &[])];
+    let v2 = [Instruction::new("mov", &fmt1, &mc2),
+	      Instruction::new("ldi", &fmt2, &[]),
+	      Instruction::new("nop", &fmt1, &[]).with_metadata(Metadata::new().category(Category::System)),
+	      Instruction::new("add", &fmt1, &[])];
+    let d = diff(&InstructionSet::new(&v1),&InstructionSet::new(&v2));
+    assert_eq!(d.changes,vec![Change::Removed("clr".to_string()),
+			      Change::Semantics("mov".to_string()),
+			      Change::Opcode{mnemonic:"ldi".to_string(),before:2,after:1},
+			      Change::Format{mnemonic:"ldi".to_string(),before:"fmt1".to_string(),after:"fmt2".to_string()},
+			      Change::Opcode{mnemonic:"nop".to_string(),before:3,after:2},
+			      Change::Metadata("nop".to_string()),
+			      Change::Added("add".to_string())]);
+    assert!(d.is_breaking());
+    assert!(d.to_string().starts_with("- clr\n~ mov: semantics changed\n~ ldi: opcode 2 -> 1\n~ ldi: format fmt1 -> fmt2\n"));
+    assert!(!Change::Added("add".to_string()).is_breaking());
+}
@@ -0,0 +1,178 @@
+#![cfg(feature="std")]
+use virmin::compile::CompiledSet;
+use virmin::differential::*;
+use virmin::insn::{Format,InstructionSet,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::{Byte,Word};
+use virmin::program::{DecodedProgram,Program};
+use virmin::tiered::Tiered;
+
+// do { M[2..4] := M[2..4] + M[4..6]; M[0] := M[0] + M[1] } while M[0] != 0
+fn program_with<F:FnOnce(&InstructionSet,&[u8])>(f: F) {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("addw",&fmt,&[Add(Var(0),Var(1),Word)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("addw",&[2,4]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    f(&isa,program.bytes())
+}
+
+const MEMORY : [u8;6] = [100,0xFF,0,0,3,1];
+
+// =====================================================
+// Differences
+// =====================================================
+
+#[test]
+fn test_compare_01() {
+    let (mut l,mut r) = (MEMORY,MEMORY);
+    let mut left = State::new(0,&mut l);
+    let mut right = State::new(0,&mut r);
+    assert_eq!(compare(&left,&right),vec![]);
+    right.pc = 2;
+    right.flags = 1;
+    right.data.write_u8(3,7);
+    left.sp = 4;
+    assert_eq!(compare(&left,&right),vec![Difference::Pc(0,2),Difference::Sp(4,0),Difference::Flags(0,1),
+					   Difference::Memory{address:3,left:0,right:7}]);
+}
+
+// =====================================================
+// Harness
+// =====================================================
+
+#[test]
+fn test_harness_01() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	let set = CompiledSet::new(isa);
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let result = Harness::new(&program).run(&mut left,&mut interpreter(&program),&mut right,&mut compiled(&set,&program));
+	assert_eq!(result,Ok(Agreement{steps:300,faults:None}));
+	assert_eq!(l,[0,0xFF,0x2C,0x65,3,1]);
+    });
+}
+
+#[test]
+fn test_harness_02() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	let quick = DecodedProgram::new(isa,image);
+	let mut fast = tiered(Tiered::new(&quick).threshold(0),quick);
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let result = Harness::new(&program).fuel(10).run(&mut left,&mut interpreter(&program),&mut right,&mut fast);
+	assert_eq!(result,Ok(Agreement{steps:10,faults:None}));
+    });
+}
+
+#[test]
+fn test_harness_03() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	// An engine which corrupts memory on its fifth instruction
+	let mut count = 0;
+	let mut buggy = |state: &mut State| {
+	    state.try_step(&program).map_err(|e| e.to_string())?;
+	    count += 1;
+	    if count == 5 { state.data.write_u8(5,2); }
+	    Ok(1)
+	};
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let divergence = Harness::new(&program).run(&mut left,&mut interpreter(&program),&mut right,&mut buggy).unwrap_err();
+	assert_eq!(divergence.steps,4);
+	assert_eq!(divergence.pc,1);
+	assert_eq!(divergence.instruction.as_deref(),Some("add 0, 1"));
+	assert_eq!(divergence.trace,vec![0,1,2,0]);
+	assert_eq!(divergence.differences,vec![Difference::Memory{address:5,left:1,right:2}]);
+	assert_eq!(divergence.to_string(),"divergence after 4 instructions at pc 0x1 (add 0, 1)\n  trace: 0x0 0x1 0x2 0x0\n  mem[0x5]: 0x01 != 0x02\n");
+    });
+}
+
+#[test]
+fn test_harness_04() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	// An engine which executes two instructions at a time
+	let mut blocks = |state: &mut State| {
+	    state.try_step(&program).map_err(|e| e.to_string())?;
+	    if state.pc >= program.len() { return Ok(1); }
+	    state.try_step(&program).map_err(|e| e.to_string())?;
+	    Ok(2)
+	};
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let result = Harness::new(&program).run(&mut left,&mut blocks,&mut right,&mut interpreter(&program));
+	assert_eq!(result,Ok(Agreement{steps:300,faults:None}));
+    });
+}
+
+#[test]
+fn test_harness_05() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	let mut faulty = |_: &mut State| Err("boom".to_string());
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let divergence = Harness::new(&program).run(&mut left,&mut interpreter(&program),&mut right,&mut faulty).unwrap_err();
+	assert_eq!(divergence.steps,0);
+	assert_eq!(divergence.executed,(1,0));
+	assert_eq!(divergence.differences,vec![Difference::Pc(1,0),Difference::Memory{address:2,left:3,right:0},
+					       Difference::Memory{address:3,left:1,right:0},
+					       Difference::Fault(None,Some("boom".to_string()))]);
+	// Both engines fault
+	let mut faulty2 = |_: &mut State| Err("bang".to_string());
+	let result = Harness::new(&program).run(&mut left,&mut faulty2,&mut right,&mut faulty);
+	assert!(result.is_err());
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let result = Harness::new(&program).run(&mut left,&mut faulty2,&mut right,&mut faulty);
+	assert_eq!(result,Ok(Agreement{steps:0,faults:Some(("bang".to_string(),"boom".to_string()))}));
+    });
+}
+
+#[test]
+fn test_harness_06() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	r[0] = 99;
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let divergence = Harness::new(&program).run(&mut left,&mut interpreter(&program),&mut right,&mut interpreter(&program)).unwrap_err();
+	assert_eq!(divergence.steps,0);
+	assert_eq!(divergence.instruction.as_deref(),Some("addw 2, 4"));
+	assert!(divergence.trace.is_empty());
+	assert_eq!(divergence.differences,vec![Difference::Memory{address:0,left:100,right:99}]);
+    });
+}
+
+#[cfg(feature="jit")]
+#[test]
+fn test_harness_07() {
+    program_with(|isa,image| {
+	let program = DecodedProgram::new(isa,image);
+	let mut native = jit(virmin::jit::Jit::new(&program).unwrap().threshold(0));
+	let (mut l,mut r) = (MEMORY,MEMORY);
+	let mut left = State::new(0,&mut l);
+	let mut right = State::new(0,&mut r);
+	let result = Harness::new(&program).run(&mut left,&mut interpreter(&program),&mut right,&mut native);
+	assert_eq!(result,Ok(Agreement{steps:300,faults:None}));
+    });
+}
@@ -0,0 +1,93 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::disasm::{Case,Disassembler,DisasmStyle,Radix};
+use virmin::insn::{Format,Instruction,InstructionSet,PseudoInstruction};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+use virmin::program::Program;
+
+// =====================================================
+// Disassembler
+// =====================================================
+
+#[test]
+fn test_disasm_01() {
+    let fmt1 = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let fmt2 = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let fmt3 = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("ldi", &fmt2, &mc2),
+		 Instruction::new("nop", &fmt3, &[])];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,2]).unwrap();
+    program.push("ldi",&[3,-20isize as usize]).unwrap();
+    program.push("nop",&[]).unwrap();
+    let disasm = Disassembler::new(&isa);
+    let lines = disasm.disassemble(program.bytes(),0x100);
+    assert_eq!(lines.len(),3);
+    assert_eq!(lines[0].to_string(),"0100: 44  mov r1, r2");
+    assert_eq!(lines[1].to_string(),"0101: 0d fb  ldi r3, -20");
+    assert_eq!(lines[2].to_string(),"0103: 02  nop");
+    let disasm = Disassembler::new(&isa).hex(true);
+    assert_eq!(disasm.render(1,&[3,20]),"ldi r3, 0x14");
+    assert_eq!(disasm.render(1,&[3,-20isize as usize]),"ldi r3, -0x14");
+}
+
+#[test]
+fn test_disasm_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).immediate("imm",3).build().ok().unwrap();
+    let mc = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("ldi", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let disasm = Disassembler::new(&isa);
+    let listing = disasm.listing(&[0x44,0x03],0);
+    assert_eq!(listing,"0000: 44  ldi r1, 2\n0001: 03  .byte 0x03\n");
+}
+
+#[test]
+fn test_disasm_03() {
+    let rr = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ri = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2)];
+    let pseudos = [PseudoInstruction::new("nop", &[("mov", &[Const(0),Const(0)])]),
+		   PseudoInstruction::new("clr", &[("ldi", &[Var(0),Const(0)])]),
+		   PseudoInstruction::new("dup", &[("mov", &[Var(0),Var(1)]), ("mov", &[Var(0),Var(1)])])];
+    let isa = InstructionSet::new(&insns).with_pseudos(&pseudos);
+    let image = [0x00,0x0D,0x00,0x44,0x44,0x44,0x4D,0x00];
+    let lines = Disassembler::new(&isa).disassemble(&image,0);
+    let texts : Vec<(&str,bool)> = lines.iter().map(|l| (l.text.as_str(),l.pseudo)).collect();
+    assert_eq!(texts,vec![("nop",true),("clr r3",true),("dup r1, r2",true),("mov r1, r2",false),("ldi r3, 1",false)]);
+    assert_eq!(lines[2].bytes,vec![0x44,0x44]);
+    let lines = Disassembler::new(&isa).pseudos(false).disassemble(&image,0);
+    assert_eq!(lines.len(),6);
+    assert!(lines.iter().all(|l| !l.pseudo));
+}
+
+#[test]
+fn test_disasm_style_01() {
+    let fmt1 = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let fmt2 = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("LDI", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let style = DisasmStyle::default().registers(&["A","B"]).prefix("x").case(Case::Upper);
+    let disasm = Disassembler::new(&isa).style(style.clone());
+    assert_eq!(disasm.render(0,&[1,2]),"MOV B, x2");
+    assert_eq!(disasm.render(1,&[0,-5isize as usize]),"LDI A, -5");
+    let disasm = Disassembler::new(&isa).style(style.case(Case::Lower).reversed(true).radix(Radix::Binary));
+    assert_eq!(disasm.render(0,&[1,2]),"mov x2, B");
+    assert_eq!(disasm.render(1,&[0,5]),"ldi 0b101, A");
+    let style = DisasmStyle::default();
+    assert_eq!(style.clone().radix(Radix::Octal).immediate(-8),"-0o10");
+    assert_eq!(style.clone().radix(Radix::Hexadecimal).immediate(255),"0xff");
+    assert_eq!(style.mnemonic("Mov"),"Mov");
+}
@@ -0,0 +1,69 @@
+#![cfg(feature="std")]
+use virmin::dump::StateDump;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::{RegisterFile,State};
+use virmin::machine::Width::{Byte,Word};
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Dump
+// =====================================================
+
+#[test]
+fn test_dump_01() {
+    let mut bytes = [1,2,3,4];
+    let mut state = State::new(0,&mut bytes);
+    state.flags = 0x5;
+    let text = StateDump::new().text(&state);
+    assert_eq!(text,"== Registers ==\nspecial  pc=0x0  sp=0x0  flags=0x5  lr=0x0\n== Memory ==\nmemory (0x0..0x4):\n0000: 01 02 03 04\n");
+    let text = StateDump::new().flags(&[("C",0),("Z",1),("N",2)]).region("none",8..12).text(&state);
+    assert_eq!(text,"== Registers ==\nspecial  pc=0x0  sp=0x0  flags=0x5  lr=0x0\n== Flags ==\nflags  0x5  C=1  Z=0  N=1\n== Memory ==\nnone (0x8..0xc):\n(out of bounds)\n");
+}
+
+#[test]
+fn test_dump_02() {
+    let mut bytes = [0u8;4];
+    let registers = RegisterFile::new(6,Word).with_names(&["a0","a1"]);
+    let mut state = State::new(0,&mut bytes).with_registers(registers);
+    state.registers.write(1,0x1234);
+    state.registers.write(5,7);
+    let text = StateDump::new().group("args",0..2).region("low",0..2).text(&state);
+    assert_eq!(text,"\
+== Registers ==
+special  pc=0x0     sp=0x0     flags=0x0  lr=0x0
+args     a0=0x0000  a1=0x1234
+general  r2=0x0000  r3=0x0000  r4=0x0000  r5=0x0007
+== Memory ==
+low (0x0..0x2):
+0000: 00 00
+");
+}
+
+#[test]
+fn test_dump_03() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [3,0xFF,0,1];
+    let mut state = State::new(1,&mut bytes);
+    let dump = StateDump::new().program(&decoded).context(1).region("data",0..4);
+    assert!(dump.text(&state).ends_with("== Disassembly ==\n   0000: add 2, 3\n=> 0001: add 0, 1\n   0002: jnz 0, 0\n"));
+    let html = dump.html(&state);
+    assert!(html.starts_with("<!DOCTYPE html>\n"));
+    assert!(html.contains("<h2>Registers</h2>\n<table>\n<tr><th>special</th><td>pc=0x1</td><td>sp=0x0</td><td>flags=0x0</td><td>lr=0x0</td></tr>\n</table>\n"));
+    assert!(html.contains("<h2>Memory</h2>\n<pre>\ndata (0x0..0x4):\n0000: 03 ff 00 01\n</pre>\n"));
+    assert!(html.contains("<b>=&gt; 0001: add 0, 1</b>\n"));
+    assert!(html.ends_with("</body>\n</html>\n"));
+    state.pc = 3;
+    assert!(dump.text(&state).ends_with("== Disassembly ==\n   0002: jnz 0, 0\nhalted at pc 3\n"));
+}
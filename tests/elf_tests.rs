@@ -0,0 +1,42 @@
+#![cfg(feature="elf")]
+use virmin::elf::{Elf,ElfError,Segment};
+use virmin::machine::Memory;
+
+// =====================================================
+// ELF
+// =====================================================
+
+#[test]
+fn test_elf_01() {
+    let elf = Elf::new(0xBEEF,0x10).segment(0x10,&[1,2,3]).segment(0x20,&[4]);
+    let bytes = elf.to_bytes();
+    assert_eq!(bytes.len(),52 + 64 + 4);
+    assert_eq!(&bytes[0..4],&[0x7F,b'E',b'L',b'F']);
+    assert_eq!(&bytes[18..20],&[0xEF,0xBE]);
+    assert_eq!(Elf::from_bytes(&bytes),Ok(elf));
+}
+
+#[test]
+fn test_elf_02() {
+    let mut elf = Elf::new(1,4).segment(2,&[0xAA,0xBB]);
+    elf.segments.push(Segment{address:6,bytes:vec![0xCC],size:2});
+    let elf = Elf::from_bytes(&elf.to_bytes()).unwrap();
+    let mut bytes = [0xFF;8];
+    let mut memory = Memory::new(&mut bytes);
+    assert_eq!(elf.load(&mut memory),Ok(4));
+    assert_eq!(bytes,[0xFF,0xFF,0xAA,0xBB,0xFF,0xFF,0xCC,0x00]);
+}
+
+#[test]
+fn test_elf_03() {
+    let bytes = Elf::new(1,0).segment(6,&[1,2,3]).to_bytes();
+    assert_eq!(Elf::from_bytes(&bytes[..40]),Err(ElfError::Truncated));
+    assert_eq!(Elf::from_bytes(&bytes[..bytes.len()-1]),Err(ElfError::Truncated));
+    assert_eq!(Elf::from_bytes(&[0;64]),Err(ElfError::BadMagic));
+    let mut wide = bytes.clone();
+    wide[4] = 2;
+    assert_eq!(Elf::from_bytes(&wide),Err(ElfError::Unsupported));
+    let mut small = [0;8];
+    let elf = Elf::from_bytes(&bytes).unwrap();
+    assert_eq!(elf.load(&mut Memory::new(&mut small)),Err(ElfError::OutOfBounds{address:6,length:3}));
+}
@@ -0,0 +1,53 @@
+use virmin::domain::*;
+use virmin::fuzzing::{decode_arbitrary_bytes,execute_arbitrary_program_with_fuel};
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::MachineError;
+use virmin::machine::Width::QuadWord;
+
+// =====================================================
+// Fuzzing
+// =====================================================
+
+fn isa_with<F:FnOnce(&InstructionSet)>(f: F) {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(TWO_BYTES,"fmt2",TWO_BITS, &[FOUR_BITS,TEN_BITS]);
+    let mov = [Copy(Var(0),Var(1),QuadWord)];
+    let jmp = [Jump(SExt(Box::new(Var(1)),10))];
+    let reg = [RegLoad(Var(0),Var(1))];
+    let insns = [Instruction::new("mov", &fmt1, &mov),
+		 Instruction::new("jmp", &fmt2, &jmp),
+		 Instruction::new("reg", &fmt1, &reg)];
+    f(&InstructionSet::new(&insns))
+}
+
+#[test]
+fn test_fuzzing_01() {
+    isa_with(|isa| {
+	assert_eq!(decode_arbitrary_bytes(isa,&[]),0);
+	assert_eq!(decode_arbitrary_bytes(isa,&[0x00,0x06,0x03,0x01]),2);
+	// Every pair of bytes
+	for i in 0..=0xFFFFu32 {
+	    let bytes = i.to_le_bytes();
+	    decode_arbitrary_bytes(isa,&bytes[..2]);
+	    let _ = execute_arbitrary_program_with_fuel(isa,&bytes[..2],16);
+	}
+    });
+}
+
+#[test]
+fn test_fuzzing_02() {
+    isa_with(|isa| {
+	// mov 1, 1 (then fall off the end)
+	assert_eq!(execute_arbitrary_program_with_fuel(isa,&[0x24],10),Ok(1));
+	// jmp -512 wraps out of the program
+	assert_eq!(execute_arbitrary_program_with_fuel(isa,&[0x01,0x80],10),Ok(1));
+	// jmp 0 loops until the fuel runs out
+	assert_eq!(execute_arbitrary_program_with_fuel(isa,&[0x01,0x00],10),Ok(10));
+	// reg 1, 0 accesses a register which does not exist
+	assert_eq!(execute_arbitrary_program_with_fuel(isa,&[0x06],10),Err(MachineError::Register{pc:0,register:1}));
+	// Unknown instruction
+	assert!(matches!(execute_arbitrary_program_with_fuel(isa,&[0x03],10),Err(MachineError::Decode{pc:0,..})));
+    });
+}
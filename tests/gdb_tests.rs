@@ -0,0 +1,89 @@
+#![cfg(feature="std")]
+use std::io::{self,Cursor,Read,Write};
+use virmin::gdb::GdbStub;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// GDB Stub
+// =====================================================
+
+#[test]
+fn test_gdb_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // do { M[2] := M[2] + M[3]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut stub = GdbStub::new(&decoded);
+    let mut bytes = [3,0xFF,0,1];
+    let mut state = State::new(0,&mut bytes);
+    assert_eq!(stub.handle("?",&mut state),"S05");
+    assert_eq!(stub.handle("Z0,2,1",&mut state),"OK");
+    assert_eq!(stub.handle("c",&mut state),"S05");
+    assert_eq!(stub.handle("p0",&mut state),"0200000000000000");
+    assert_eq!(stub.handle("m0,4",&mut state),"02ff0101");
+    assert_eq!(stub.handle("m2,3",&mut state),"E01");
+    assert_eq!(stub.handle("s",&mut state),"S05");
+    assert_eq!(stub.handle("p0",&mut state),"0000000000000000");
+    assert_eq!(stub.handle("M0,1:01",&mut state),"OK");
+    assert_eq!(stub.handle("z0,2,1",&mut state),"OK");
+    assert_eq!(stub.handle("c",&mut state),"W00");
+    assert_eq!(stub.handle("m0,4",&mut state),"00ff0201");
+    assert_eq!(stub.handle("g",&mut state),"0300000000000000".to_string() + &"0".repeat(48));
+    assert_eq!(stub.handle("P0=0100000000000000",&mut state),"OK");
+    assert_eq!(state.pc,1);
+    assert!(stub.handle("qXfer:features:read:target.xml:0,1000",&mut state).contains("<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/>"));
+    assert_eq!(stub.handle("vMustReplyEmpty",&mut state),"");
+}
+
+/// A stream which reads from a given input, and records the output.
+struct Stream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	self.input.read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+	self.output.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+	Ok(())
+    }
+}
+
+#[test]
+fn test_gdb_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [1,2];
+    let mut state = State::new(0,&mut bytes);
+    // A bad checksum is rejected, and the session ends on detach
+    let input = b"+$?#00$?#3f$m0,2#fb$D#44$s#73".to_vec();
+    let mut stream = Stream{input:Cursor::new(input),output:Vec::new()};
+    GdbStub::new(&decoded).serve(&mut stream,&mut state).unwrap();
+    assert_eq!(String::from_utf8(stream.output).unwrap(),"-+$S05#b8+$0102#c3+$OK#9a");
+    assert_eq!(state.pc,0);
+}
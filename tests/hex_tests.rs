@@ -0,0 +1,61 @@
+#![cfg(feature="std")]
+use virmin::hex::{intel_hex,srec,AddressOverflow};
+
+// =====================================================
+// Intel HEX
+// =====================================================
+
+#[test]
+fn test_ihex_01() {
+    assert_eq!(intel_hex(&[],0).unwrap(),":00000001FF\n");
+    assert_eq!(intel_hex(&[1,2,3],0x100).unwrap(),":03010000010203F6\n:00000001FF\n");
+}
+
+#[test]
+fn test_ihex_02() {
+    let image : Vec<u8> = (0..20).collect();
+    assert_eq!(intel_hex(&image,0).unwrap(),
+	       ":10000000000102030405060708090A0B0C0D0E0F78\n\
+		:0400100010111213A6\n\
+		:00000001FF\n");
+}
+
+#[test]
+fn test_ihex_04() {
+    // Does not fit in 32 bits
+    assert_eq!(intel_hex(&[1,2],0xFFFF_FFFF),Err(AddressOverflow{address:0xFFFF_FFFF,length:2}));
+    assert_eq!(intel_hex(&[1],usize::MAX),Err(AddressOverflow{address:usize::MAX,length:1}));
+}
+
+#[test]
+fn test_ihex_03() {
+    // Straddles a 64KB boundary
+    assert_eq!(intel_hex(&[0xAA,0xBB],0xFFFF).unwrap(),
+	       ":01FFFF00AA57\n\
+		:020000040001F9\n\
+		:01000000BB44\n\
+		:00000001FF\n");
+}
+
+// =====================================================
+// S-Record
+// =====================================================
+
+#[test]
+fn test_srec_01() {
+    assert_eq!(srec(&[1,2,3],0x100).unwrap(),"S0030000FC\nS1060100010203F2\nS5030001FB\nS9030000FC\n");
+}
+
+#[test]
+fn test_srec_02() {
+    assert_eq!(srec(&[1,2,3],0x10000).unwrap(),"S0030000FC\nS207010000010203F1\nS5030001FB\nS804000000FB\n");
+    assert_eq!(srec(&[0xFF],0x1000000).unwrap(),"S0030000FC\nS30601000000FFF9\nS5030001FB\nS70500000000FA\n");
+}
+
+#[test]
+fn test_srec_03() {
+    assert_eq!(srec(&[1],0xFFFF_FFFF).unwrap(),"S0030000FC\nS306FFFFFFFF01FC\nS5030001FB\nS70500000000FA\n");
+    // Does not fit in 32 bits
+    assert_eq!(srec(&[1,2],0xFFFF_FFFF),Err(AddressOverflow{address:0xFFFF_FFFF,length:2}));
+    assert_eq!(srec(&[1],usize::MAX),Err(AddressOverflow{address:usize::MAX,length:1}));
+}
@@ -1,11 +1,13 @@
 use num::BigUint;
 use virmin::domain::*;
-use virmin::insn::Format;
-use virmin::insn::Instruction;
+use virmin::domain::Bits;
+use virmin::insn::{Format,FormatCache,FieldKind,FormatError,ByteOrder,BitOrder,EncodeError,DecodeError};
+use virmin::insn::{Category,Extension,Instruction,InstructionSet,InstructionSetBuilder,IsaError,Metadata};
 use virmin::insn::AbstractMicroCode::*;
 use virmin::insn::Operand::*;
+use virmin::insn::Operand::Add;
 use virmin::machine::MicroCode;
-use virmin::machine::Width::{Byte,Word};
+use virmin::machine::Width::Byte;
 
 // =====================================================
 // Bits
@@ -35,6 +37,17 @@ fn test_bits_03() {
     assert_eq!(b.count(),BigUint::from(8u32));
 }
 
+#[test]
+fn test_bits_04() {
+    const B : Bits = Bits::new(127);
+    assert_eq!(B.checked_count(),Some(1u128 << 127));
+    assert_eq!(Countable::checked_count(&B),Some(1u128 << 127));
+    assert_eq!(B.checked_count_u64(),None);
+    let b = Bits::from(200);
+    assert_eq!(b.checked_count(),None);
+    assert_eq!(b.count(),BigUint::from(2u32).pow(200));
+}
+
 // =====================================================
 // Bytes
 // =====================================================   
@@ -63,6 +76,15 @@ fn test_bytes_03() {
     assert_eq!(b.count(),BigUint::from(16777216u32));
 }
 
+#[test]
+fn test_bytes_04() {
+    const B : Bytes = Bytes::new(8);
+    const N : Option<u128> = B.checked_count();
+    assert_eq!((B.bits(),N),(64,Some(1u128 << 64)));
+    assert_eq!(Bytes::from(16).checked_count(),None);
+    assert_eq!(Bytes::from(16).count(),BigUint::from(2u32).pow(128));
+}
+
 // =====================================================
 // Formats
 // =====================================================   
@@ -86,6 +108,7 @@ fn test_format_03() {
     // Check that 8 bits fits into one byte
     let fmt = Format::new(ONE_BYTE,"fmt", FOUR_BITS, &[FOUR_BITS]);
     assert_eq!(fmt.count(),BigUint::from(256u32));
+    assert_eq!(fmt.checked_count(),Some(256));
 }
 
 #[test]
@@ -116,7 +139,7 @@ fn test_format_06() {
 #[test]
 fn test_insn_01() {
     let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
-    let microcode = [Load(Var(0),0,Byte)];
+    let microcode = [Load(Var(0),Const(0),Byte)];
     let insn = Instruction::new("insn", &fmt, &microcode);
     //
     assert!(insn.to_microcode(&[1]) == vec![MicroCode::Load(1,0,Byte)])
@@ -125,26 +148,899 @@ fn test_insn_01() {
 #[test]
 fn test_insn_02() {
     let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
-    let microcode = [Load(Const(123),0,Byte)];
+    let microcode = [Load(Const(123),Const(0),Byte)];
     let insn = Instruction::new("insn", &fmt, &microcode);
     //
     assert!(insn.to_microcode(&[1]) == vec![MicroCode::Load(123,0,Byte)])
 }
 
+#[test]
+fn test_insn_05() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // M[rd*4 + 0x100] := M[rs << 1]
+    let addr = Add(Box::new(Mul(Box::new(Var(0)),Box::new(Const(4)))),Box::new(Const(0x100)));
+    let microcode = [Copy(addr.clone(),Shl(Box::new(Var(1)),Box::new(Const(1))),Byte)];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(addr.arity(),1);
+    assert_eq!(Shl(Box::new(Const(1)),Box::new(Var(1))).arity(),2);
+    assert!(insn.to_microcode(&[3,5]) == vec![MicroCode::Copy(0x10C,10,Byte)]);
+    assert_eq!(Shl(Box::new(Const(1)),Box::new(Const(200))).as_usize(&[]),0);
+    assert_eq!(Add(Box::new(Const(usize::MAX)),Box::new(Const(2))).as_usize(&[]),1);
+}
+
+#[test]
+fn test_insn_06() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // R[rd] := M[rs]; M[rs] := R[rd]; R[rs] := 7
+    let microcode = [RegFetch(Var(0),Var(1),Byte),RegStore(Var(1),Var(0),Byte),RegLoad(Var(1),Const(7))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(insn.arity(),2);
+    assert!(insn.to_microcode(&[3,5]) == vec![MicroCode::RegFetch(3,5,Byte),MicroCode::RegStore(5,3,Byte),MicroCode::RegLoad(5,7)]);
+}
+
+#[test]
+fn test_insn_07() {
+    use virmin::machine::{LR,PC,SP};
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS]);
+    // lr := pc; pc := R[rd]; M[rd] := sp
+    let microcode = [RegCopy(Lr,Pc),RegCopy(Pc,Var(0)),RegStore(Var(0),Sp,Byte)];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(insn.arity(),1);
+    assert!(insn.to_microcode(&[3]) == vec![MicroCode::RegCopy(LR,PC),MicroCode::RegCopy(PC,3),MicroCode::RegStore(3,SP,Byte)]);
+}
+
+#[test]
+fn test_insn_08() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).simmediate("imm",3).build().ok().unwrap();
+    // M[rd] := M[pc + imm]; pc := pc + imm
+    let microcode = [Copy(Var(0),PcRel(Box::new(Var(1))),Byte),Goto(PcRel(Box::new(Var(1))))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert!(!insn.is_relative(0) && insn.is_relative(1));
+    assert!(!PcRel(Box::new(Const(1))).is_constant());
+    let offset = -2isize as usize;
+    assert!(insn.to_microcode_at(10,&[1,offset]) == vec![MicroCode::Copy(1,8,Byte),MicroCode::Goto(8)]);
+    assert!(insn.to_microcode(&[1,3]) == vec![MicroCode::Copy(1,3,Byte),MicroCode::Goto(3)]);
+}
+
+#[test]
+fn test_insn_09() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[SIX_BITS]);
+    // pc := pc + sext4(imm)
+    let microcode = [Goto(PcRel(Box::new(SExt(Box::new(Var(0)),4))))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert!(insn.is_relative(0));
+    assert!(insn.to_microcode_at(10,&[0x0E]) == vec![MicroCode::Goto(8)]);
+    assert!(insn.to_microcode_at(10,&[0x37]) == vec![MicroCode::Goto(17)]);
+    assert_eq!(SExt(Box::new(Const(0xFF)),0).as_usize(&[]),0);
+    assert_eq!(SExt(Box::new(Const(0x80)),8).as_usize(&[]),-128isize as usize);
+    assert_eq!(SExt(Box::new(Const(0x80)),64).as_usize(&[]),0x80);
+}
+
+#[test]
+fn test_insn_10() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[SIX_BITS]);
+    // M[op[5:3]] := M[op[2:0]]
+    let microcode = [Copy(Bits(Box::new(Var(0)),5,3),Bits(Box::new(Var(0)),2,0),Byte)];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert!(insn.to_microcode(&[0b101_011]) == vec![MicroCode::Copy(5,3,Byte)]);
+    assert_eq!(Bits(Box::new(Const(0xAB)),3,4).as_usize(&[]),0);
+    assert_eq!(Bits(Box::new(Const(usize::MAX)),63,0).as_usize(&[]),usize::MAX);
+    assert_eq!(Bits(Box::new(Const(usize::MAX)),70,64).as_usize(&[]),0);
+}
+
+#[test]
+fn test_insn_11() {
+    use virmin::insn::Predicate;
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // if rd != 0 then M[rd] := M[rs]
+    let microcode = [If(Predicate::Ne(Var(0),Const(0)),vec![Copy(Var(0),Var(1),Byte)],vec![])];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(insn.arity(),2);
+    assert!(insn.to_microcode(&[0,2]).is_empty());
+    assert!(insn.to_microcode(&[1,2]) == vec![MicroCode::Copy(1,2,Byte)]);
+}
+
+#[test]
+fn test_insn_12() {
+    use virmin::insn::Predicate;
+    use virmin::machine::State;
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // if M[rs] == 0 then M[rd] := 1 else M[rd] := 2
+    let microcode = [If(Predicate::Zero(Var(1),Byte),vec![Load(Var(0),Const(1),Byte)],vec![Load(Var(0),Const(2),Byte)])];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    let mc = insn.to_microcode(&[0,1]);
+    assert!(mc == vec![MicroCode::SkipIfZero(1,Byte,2),MicroCode::Load(0,2,Byte),MicroCode::Skip(1),MicroCode::Load(0,1,Byte)]);
+    for (initial,expected) in [([0,0],[1,0]),([0,5],[2,5])] {
+	let mut bytes = initial;
+	let mut state = State::new(0,&mut bytes);
+	state.execute_all(&mc);
+	assert_eq!(state.pc,1);
+	assert_eq!(bytes,expected);
+    }
+    // Negating the predicate swaps the branches
+    let microcode = [If(Predicate::Not(Box::new(Predicate::Zero(Var(1),Byte))),vec![Load(Var(0),Const(2),Byte)],vec![Load(Var(0),Const(1),Byte)])];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert!(insn.to_microcode(&[0,1]) == mc);
+}
+
+#[test]
+fn test_insn_13() {
+    use virmin::insn::AbstractMicroCode::Add;
+    use virmin::machine::{RegisterFile,State};
+    use virmin::machine::Width::QuadWord;
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // M[rd] := M[rd] + M[rs]; R[rd] := R[rd] + R[rs]; pc := pc + rs
+    let microcode = [Add(Var(0),Var(1),Byte),RegAdd(Var(0),Var(1)),Jump(Var(1))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(insn.arity(),2);
+    let mc = insn.to_microcode(&[0,1]);
+    assert!(mc == vec![MicroCode::Add(0,1,Byte),MicroCode::RegAdd(0,1),MicroCode::Jump(1)]);
+    let mut registers = RegisterFile::new(2,QuadWord);
+    registers.write(0,4);
+    registers.write(1,6);
+    let mut bytes = [2,3];
+    let mut state = State::new(0,&mut bytes).with_registers(registers);
+    state.execute_all(&mc);
+    assert_eq!(state.pc,1);
+    assert_eq!(state.registers.read(0),10);
+    assert_eq!(bytes,[5,3]);
+    // pc := rd
+    let microcode = [Goto(Var(0))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(microcode[0].arity(),1);
+    assert!(insn.to_microcode(&[4,0]) == vec![MicroCode::Goto(4)]);
+}
+
+#[test]
+fn test_insn_14() {
+    let fmt = Format::builder().width_bytes(2).opcode_bits(2).register("rd",3).simmediate("imm",8).build().ok().unwrap();
+    // M[rd] := imm; R[rd] := imm << 2
+    let microcode = [Load(Var(0),Var(1),Byte),RegLoad(Var(0),Shl(Box::new(Var(1)),Box::new(Const(2))))];
+    let insn = Instruction::owned("ldi",fmt,microcode.to_vec());
+    assert_eq!(microcode[0].arity(),2);
+    assert!(insn.to_microcode(&[3,5]) == vec![MicroCode::Load(3,5,Byte),MicroCode::RegLoad(3,20)]);
+    // Signed immediates are given in two's complement form
+    assert!(insn.to_microcode(&[3,-1isize as usize])[0] == MicroCode::Load(3,u64::MAX,Byte));
+    let mut bytes = [0;4];
+    let mut state = virmin::machine::State::new(0,&mut bytes);
+    state.execute_all(&insn.to_microcode(&[3,-1isize as usize])[..1]);
+    assert_eq!(bytes,[0,0,0,0xFF]);
+}
+
+#[test]
+fn test_insn_15() {
+    use virmin::insn::Predicate;
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // if M[rs] == 0 then M[rd] := 1 else M[rd] := M[rs]
+    let microcode = [If(Predicate::Zero(Var(1),Byte),vec![Load(Var(0),Const(1),Byte)],vec![Copy(Var(0),Var(1),Byte)])];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    let mut buffer = Vec::new();
+    insn.to_microcode_into(3,&[0,1],&mut buffer);
+    assert_eq!(buffer,insn.to_microcode_at(3,&[0,1]));
+    // Appends to the buffer, which can then be reused without allocating
+    microcode[0].to_microcode_into(3,&[2,3],&mut buffer);
+    assert_eq!(buffer.len(),8);
+    assert_eq!(buffer[4..],insn.to_microcode_at(3,&[2,3]));
+    let (ptr,capacity) = (buffer.as_ptr(),buffer.capacity());
+    for rd in 0..8 {
+	buffer.clear();
+	insn.to_microcode_into(3,&[rd,1],&mut buffer);
+	assert_eq!(buffer[1],MicroCode::Copy(rd,1,Byte));
+    }
+    assert_eq!((buffer.as_ptr(),buffer.capacity()),(ptr,capacity));
+}
+
+#[test]
+fn test_insn_16() {
+    use virmin::machine::{AluOp,RegisterFile,State};
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    // M[rd] := M[rd] - M[rs]; R[rd] := R[rd] < R[rs]
+    let microcode = [Alu(AluOp::Sub,Var(0),Var(1),Byte),RegAlu(AluOp::Ltu,Var(0),Var(1))];
+    let insn = Instruction::new("insn", &fmt, &microcode);
+    assert_eq!(insn.arity(),2);
+    let mc = insn.to_microcode(&[0,1]);
+    assert!(mc == vec![MicroCode::Alu(AluOp::Sub,0,1,Byte),MicroCode::RegAlu(AluOp::Ltu,0,1)]);
+    let mut registers = RegisterFile::new(2,Byte);
+    registers.write(1,6);
+    let mut bytes = [2,3];
+    let mut state = State::new(0,&mut bytes).with_registers(registers);
+    state.execute_all(&mc);
+    assert_eq!(state.registers.read(0),1);
+    assert_eq!(bytes,[0xFF,3]);
+}
+
 #[test]
 #[should_panic]
 fn test_insn_03() {
     let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[]);
-    let microcode = [Load(Var(0),0,Byte)];
+    let microcode = [Load(Var(0),Const(0),Byte)];
     // Microcode expects operand, but format has none.
-    let insn = Instruction::new("insn", &fmt, &microcode);
+    let _insn = Instruction::new("insn", &fmt, &microcode);
 }
 
 #[test]
 #[should_panic]
 fn test_insn_04() {
     let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
-    let microcode = [Load(Var(1),0,Byte)];
+    let microcode = [Load(Var(1),Const(0),Byte)];
     // Microcode expects two operands, but format has one.
-    let insn = Instruction::new("insn", &fmt, &microcode);
+    let _insn = Instruction::new("insn", &fmt, &microcode);
+}
+
+// =====================================================
+// Format Builder
+// =====================================================
+
+#[test]
+fn test_builder_01() {
+    let fmt = Format::builder().width_bytes(2).opcode_bits(6).register("rd",3).simmediate("off",7).build().ok().unwrap();
+    assert_eq!(fmt.count(),BigUint::from(65536u32));
+    assert_eq!(fmt.operands().len(),2);
+    assert_eq!(fmt.operands()[0].name(),"rd");
+    assert_eq!(fmt.operands()[0].kind(),FieldKind::Register);
+    assert_eq!(fmt.operands()[1].kind(),FieldKind::SignedImmediate);
+}
+
+#[test]
+fn test_builder_02() {
+    // Check that 17 bits does not fit into two bytes
+    let r = Format::builder().width_bytes(2).opcode_bits(6).register("rd",4).simmediate("off",7).build();
+    assert!(r.err() == Some(FormatError::DoesNotFit{required:17,available:16}));
+}
+
+#[test]
+fn test_builder_03() {
+    let r = Format::builder().opcode_bits(6).build();
+    assert!(r.err() == Some(FormatError::MissingWidth));
+    let r = Format::builder().width_bytes(1).build();
+    assert!(r.err() == Some(FormatError::MissingOpcode));
+}
+
+#[test]
+fn test_builder_04() {
+    let r = Format::builder().width_bytes(1).opcode_bits(2).register("rd",0).build();
+    assert!(r.err() == Some(FormatError::ZeroSized("rd".to_string())));
+}
+
+#[test]
+fn test_builder_05() {
+    let r = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rd",3).build();
+    assert!(r.err() == Some(FormatError::DuplicateField("rd".to_string())));
+}
+
+#[test]
+fn test_builder_06() {
+    let r = Format::builder().width_bytes(1).opcode_bits(4).register("rd",3).register("rs",3).build();
+    let err = r.err().unwrap();
+    assert_eq!(err,FormatError::DoesNotFit{required:10,available:8});
+    assert_eq!(err.to_string(),"fields require 10 bit(s) but only 8 are available");
+}
+
+// =====================================================
+// Utilization
+// =====================================================
+
+#[test]
+fn test_utilization_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",SIX_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("clr", &fmt2, &mc2),
+		 Instruction::new("add", &fmt1, &mc1)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.opcode(1),1);
+    assert_eq!(isa.opcode(2),2);
+    let util = isa.utilization();
+    assert_eq!(util.formats.len(),2);
+    assert_eq!(util.formats[0].defined,2);
+    assert_eq!(util.formats[0].free,vec![1..2,3..4]);
+    assert_eq!(util.formats[0].used,BigUint::from(128u32));
+    assert_eq!(util.formats[1].free,vec![0..1,2..64]);
+    assert_eq!(util.formats[1].free_opcodes(),63);
+    assert_eq!(util.formats[1].used,BigUint::from(1u32));
+    assert_eq!(util.used(),BigUint::from(129u32));
+    assert_eq!(util.available(),BigUint::from(512u32));
+}
+
+#[test]
+fn test_utilization_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",ONE_BITS, &[]);
+    let mc = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("a", &fmt, &mc), Instruction::new("b", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let util = isa.utilization();
+    assert!(util.formats[0].free.is_empty());
+    assert_eq!(util.to_string(),"fmt: 2/2 opcodes (none free), 2/256 encodings (0.8%)\ntotal: 2/256 encodings (0.8%)\n");
+}
+
+#[test]
+fn test_utilization_03() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",TWO_BITS, &[SIX_BITS]);
+    let mc = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("a", &fmt1, &mc),
+		 Instruction::new("b", &fmt2, &mc),
+		 Instruction::new("c", &fmt2, &mc),
+		 Instruction::new("d", &fmt1, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let util = isa.utilization();
+    assert_eq!(util.formats[0].free,vec![1..3]);
+    assert_eq!(util.formats[1].free,vec![0..1,3..4]);
+    for u in &util.formats {
+	assert_eq!(BigUint::from(u.defined as u64 + u.free_opcodes()),u.opcodes);
+    }
+}
+
+// =====================================================
+// Encoding
+// =====================================================
+
+fn encoding_format(byte_order: ByteOrder, bit_order: BitOrder) -> Format {
+    Format::builder().width_bytes(2).opcode_bits(6).register("rd",3).simmediate("off",7)
+	.byte_order(byte_order).bit_order(bit_order).build().ok().unwrap()
+}
+
+#[test]
+fn test_encoding_01() {
+    let fmt = encoding_format(ByteOrder::LittleEndian,BitOrder::LsbFirst);
+    let bytes = fmt.encode(5,&[3,-2isize as usize]).unwrap();
+    assert_eq!(bytes,vec![0xC5,0xFC]);
+    assert_eq!(fmt.decode(&bytes).unwrap(),(5,vec![3,-2isize as usize]));
+}
+
+#[test]
+fn test_encoding_02() {
+    let fmt = encoding_format(ByteOrder::BigEndian,BitOrder::LsbFirst);
+    let bytes = fmt.encode(5,&[3,-2isize as usize]).unwrap();
+    assert_eq!(bytes,vec![0xFC,0xC5]);
+    assert_eq!(fmt.decode(&bytes).unwrap(),(5,vec![3,-2isize as usize]));
+}
+
+#[test]
+fn test_encoding_03() {
+    let fmt = encoding_format(ByteOrder::LittleEndian,BitOrder::MsbFirst);
+    let bytes = fmt.encode(5,&[3,-2isize as usize]).unwrap();
+    assert_eq!(bytes,vec![0xFE,0x15]);
+    assert_eq!(fmt.decode(&bytes).unwrap(),(5,vec![3,-2isize as usize]));
+}
+
+#[test]
+fn test_encoding_04() {
+    let fmt = encoding_format(ByteOrder::BigEndian,BitOrder::MsbFirst);
+    let bytes = fmt.encode(5,&[3,-2isize as usize]).unwrap();
+    assert_eq!(bytes,vec![0x15,0xFE]);
+    assert_eq!(fmt.decode(&bytes).unwrap(),(5,vec![3,-2isize as usize]));
+}
+
+#[test]
+fn test_encoding_05() {
+    let fmt = encoding_format(ByteOrder::LittleEndian,BitOrder::LsbFirst);
+    assert_eq!(fmt.encode(64,&[0,0]),Err(EncodeError::InvalidOpcode(64)));
+    assert_eq!(fmt.encode(0,&[8,0]),Err(EncodeError::OutOfRange{operand:0,value:8}));
+    assert_eq!(fmt.encode(0,&[0,64]),Err(EncodeError::OutOfRange{operand:1,value:64}));
+    assert_eq!(fmt.encode(0,&[0]),Err(EncodeError::WrongArity{expected:2,actual:1}));
+    assert_eq!(fmt.decode(&[0]),Err(DecodeError::Truncated{required:2,available:1}));
+}
+
+#[test]
+fn test_encoding_06() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("clr", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.encode("mov",&[1,2]).unwrap(),vec![0b0100_0100]);
+    assert_eq!(isa.encode("clr",&[]).unwrap(),vec![1]);
+    assert_eq!(isa.decode(&[0b0100_0100]).unwrap(),(0,vec![1,2]));
+    assert_eq!(isa.decode(&[1]).unwrap(),(1,vec![]));
+    assert_eq!(isa.decode(&[0b0000_0011]),Err(DecodeError::Unknown));
+    assert_eq!(isa.encode("add",&[]),Err(EncodeError::UnknownMnemonic("add".to_string())));
+}
+
+#[test]
+fn test_lookup_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("Swap", &fmt1, &mc1),
+		 Instruction::new("mov", &fmt1, &[])];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.index_of("mov"),Some(0));
+    assert_eq!(isa.index_of("Swap"),Some(1));
+    assert_eq!(isa.index_of("swap"),None);
+    assert_eq!(isa.get("Swap").map(|i| i.mnemonic()),Some("Swap"));
+    assert!(isa.get("MOV").is_none());
+    assert_eq!(isa.get_ignore_case("MOV").map(|i| i.semantic().len()),Some(1));
+    assert_eq!(isa.get_ignore_case("sWaP").map(|i| i.mnemonic()),Some("Swap"));
+    assert!(isa.get_ignore_case("add").is_none());
+}
+
+#[test]
+fn test_dispatch_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("nop", &fmt2, &[]),
+		 Instruction::new("swp", &fmt1, &mc1)];
+    let isa = InstructionSet::new(&insns);
+    let table = isa.dispatch_table();
+    assert_eq!(table.len(),2);
+    assert_eq!(table.formats().map(|f| f.label()).collect::<Vec<_>>(),vec!["fmt1","fmt2"]);
+    assert_eq!(table.lookup(0,0),Some(0));
+    assert_eq!(table.lookup(0,1),None);
+    assert_eq!(table.lookup(0,2),Some(2));
+    assert_eq!(table.lookup(0,3),None);
+    assert_eq!(table.lookup(1,1),Some(1));
+    assert_eq!(table.lookup(1,200),None);
+    assert_eq!(isa.decode(&[0b0100_0110]),Ok((2,vec![1,2])));
+    assert_eq!(isa.decode(&[0b0000_0001]),Ok((1,vec![])));
+}
+
+#[test]
+fn test_dispatch_02() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",TWO_BITS, &[SIX_BITS]);
+    let fmt3 = Format::new(ONE_BYTE,"fmt3",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("jmp", &fmt2, &[]),
+		 Instruction::new("nop", &fmt3, &[])];
+    let isa = InstructionSet::new(&insns);
+    let table = isa.dispatch_table();
+    // Opcode 2 of fmt3 overlaps with fmt1 and fmt2
+    assert_eq!((table.is_exclusive(0),table.is_exclusive(1),table.is_exclusive(2)),(true,true,false));
+    let mut cache = FormatCache::default();
+    for byte in [0b0100_0101,0b0100_0100,0b0000_0010,0b0100_0101,0b1111_1111] {
+	assert_eq!(isa.decode_cached(&[byte],&mut cache),isa.decode(&[byte]));
+    }
+    assert_eq!(isa.decode_cached(&[0b0000_0010],&mut cache),Ok((2,vec![])));
+}
+
+#[test]
+fn test_query_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let fmt3 = Format::new(ONE_BYTE,"fmt3",TWO_BITS, &[THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("nop", &fmt2, &[]),
+		 Instruction::new("swp", &fmt1, &mc1),
+		 Instruction::new("inc", &fmt3, &[])];
+    let isa = InstructionSet::new(&insns);
+    let names = |it: &mut dyn Iterator<Item=&Instruction>| it.map(|i| i.mnemonic().to_string()).collect::<Vec<_>>();
+    assert_eq!(names(&mut isa.iter()),vec!["mov","nop","swp","inc"]);
+    assert_eq!(names(&mut isa.by_format(&fmt1)),vec!["mov","swp"]);
+    assert_eq!(names(&mut isa.by_arity(0)),vec!["nop"]);
+    assert_eq!(names(&mut isa.by_arity(1)),vec!["inc"]);
+    assert_eq!(isa.instructions().len(),4);
+    assert_eq!(insns[3].arity(),1);
+    let mut count = 0;
+    for insn in &isa {
+	assert!(isa.get(insn.mnemonic()).is_some());
+	count += 1;
+    }
+    assert_eq!(count,4);
+}
+
+#[test]
+fn test_validate_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("nop", &fmt2, &[]),
+		 Instruction::new("swp", &fmt1, &mc1)];
+    assert_eq!(InstructionSet::new(&insns).validate(),Ok(()));
+    assert_eq!(InstructionSet::new(&[]).validate(),Ok(()));
+}
+
+#[test]
+fn test_validate_02() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("mov", &fmt2, &[]),
+		 Instruction::new("swp", &fmt1, &mc1),
+		 Instruction::new("mov", &fmt2, &[]),
+		 Instruction::new("add", &fmt1, &mc1)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.validate(),Err(vec![IsaError::DuplicateMnemonic("mov".to_string()),
+				       IsaError::OpcodeExhausted{mnemonic:"add".to_string(),opcode:4,bits:2}]));
+    assert_eq!(IsaError::OpcodeExhausted{mnemonic:"add".to_string(),opcode:4,bits:2}.to_string(),
+	       "opcode 4 of \"add\" does not fit in 2 bit(s)");
+}
+
+#[test]
+fn test_encoding_07() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(4).register("rd",4).extension("imm",2,FieldKind::SignedImmediate).build().ok().unwrap();
+    assert_eq!(fmt.width().value(),1);
+    assert_eq!(fmt.length(),3);
+    assert_eq!(fmt.extensions(),1);
+    assert_eq!(fmt.layout(),vec![(0,4),(4,4)]);
+    assert_eq!(fmt.encode(3,&[2,0x1234]),Ok(vec![0x23,0x34,0x12]));
+    assert_eq!(fmt.encode(3,&[2,-2isize as usize]),Ok(vec![0x23,0xFE,0xFF]));
+    assert_eq!(fmt.encode(3,&[2,0x8000]),Err(EncodeError::OutOfRange{operand:1,value:0x8000}));
+    assert_eq!(fmt.decode(&[0x23,0x34,0x12,0xFF]),Ok((3,vec![2,0x1234])));
+    assert_eq!(fmt.decode(&[0x23,0xFE,0xFF]),Ok((3,vec![2,-2isize as usize])));
+    assert_eq!(fmt.decode(&[0x23,0x34]),Err(DecodeError::Truncated{required:3,available:2}));
+}
+
+#[test]
+fn test_encoding_08() {
+    let fmt = Format::builder().width_bytes(2).opcode_bits(8).immediate("a",8)
+	.extension("b",1,FieldKind::Immediate).extension("c",4,FieldKind::Immediate)
+	.byte_order(ByteOrder::BigEndian).build().ok().unwrap();
+    assert_eq!(fmt.length(),7);
+    assert_eq!(fmt.encode(1,&[2,3,0x04050607]),Ok(vec![0x02,0x01,0x03,0x04,0x05,0x06,0x07]));
+    assert_eq!(fmt.decode(&[0x02,0x01,0x03,0x04,0x05,0x06,0x07]),Ok((1,vec![2,3,0x04050607])));
+    let mc = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("ld", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.decode(&[0x02,0x00,0x03,0x04,0x05,0x06,0x07]),Ok((0,vec![2,3,0x04050607])));
+    // Errors
+    let ext = |bytes| Format::builder().width_bytes(1).opcode_bits(8).extension("x",bytes,FieldKind::Immediate).build().err();
+    assert_eq!(ext(0),Some(FormatError::ZeroSized("x".to_string())));
+    assert_eq!(ext(9),Some(FormatError::DoesNotFit{required:72,available:64}));
+}
+
+fn build_isa() -> Result<InstructionSet<'static>,Vec<IsaError>> {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    InstructionSetBuilder::new()
+	.instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)])
+	.instruction("swp",&fmt,&[])
+	.pseudo("nop",&[("mov",&[Const(0),Const(0)])])
+	.build()
+}
+
+#[test]
+fn test_isa_builder_01() {
+    let isa = build_isa().ok().unwrap();
+    assert_eq!(isa.len(),2);
+    assert_eq!(isa.pseudos().len(),1);
+    assert_eq!(isa.get("swp").map(|i| i.mnemonic()),Some("swp"));
+    let bytes = isa.encode("swp",&[1,2]).ok().unwrap();
+    assert_eq!(isa.decode(&bytes),Ok((1,vec![1,2])));
+}
+
+#[test]
+fn test_isa_builder_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",ONE_BITS, &[THREE_BITS]);
+    let errs = InstructionSetBuilder::new()
+	.instruction("inc",&fmt,&[Copy(Var(0),Var(1),Byte)])
+	.instruction("dec",&fmt,&[])
+	.instruction("neg",&fmt,&[])
+	.pseudo("nop",&[("inc",&[])])
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::InvalidSemantic("inc".to_string()),
+				    IsaError::OpcodeExhausted{mnemonic:"neg".to_string(),opcode:2,bits:1},
+				    IsaError::InvalidPseudo("nop".to_string())]));
+    let errs = InstructionSetBuilder::new()
+	.requires(2)
+	.instruction("inc",&fmt,&[])
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::NoInstruction("feature".to_string())]));
+    assert_eq!(IsaError::NoInstruction("feature".to_string()).to_string(),"feature given before any instruction");
+}
+
+#[test]
+fn test_isa_try_01() {
+    use virmin::insn::PseudoInstruction;
+    let fmt = Format::new(ONE_BYTE,"fmt",ONE_BITS, &[THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    assert_eq!(Instruction::try_new("inc",&fmt,&mc).err(),Some(IsaError::InvalidSemantic("inc".to_string())));
+    assert_eq!(Instruction::try_owned("inc",fmt.clone(),mc.to_vec()).err(),Some(IsaError::InvalidSemantic("inc".to_string())));
+    assert_eq!(PseudoInstruction::try_new("nop",&[]).err(),Some(IsaError::InvalidPseudo("nop".to_string())));
+    assert_eq!(PseudoInstruction::try_owned("nop",vec![]).err(),Some(IsaError::InvalidPseudo("nop".to_string())));
+    let mc = [Copy(Var(0),Var(0),Byte)];
+    let insns = [Instruction::try_new("inc",&fmt,&mc).unwrap()];
+    let pseudos = [PseudoInstruction::try_new("nop",&[("dec",&[Const(0)])]).unwrap()];
+    assert_eq!(InstructionSet::new(&insns).try_with_pseudos(&pseudos).err(),Some(IsaError::InvalidPseudo("nop".to_string())));
+    let pseudos = [PseudoInstruction::try_new("nop",&[("inc",&[Const(0)])]).unwrap()];
+    assert_eq!(InstructionSet::new(&insns).try_with_pseudos(&pseudos).map(|isa| isa.pseudos().len()),Ok(1));
+}
+
+#[test]
+fn test_domain_try_01() {
+    assert_eq!(Bits::try_new(0).err(),Some(ZeroSized));
+    assert_eq!(Bytes::try_new(0).err(),Some(ZeroSized));
+    assert_eq!(Bits::try_new(3).ok().map(|b| b.value()),Some(3));
+    assert_eq!(Bytes::try_new(2).ok().map(|b| b.bits()),Some(16));
+    assert_eq!(ZeroSized.to_string(),"domain has zero size");
+}
+
+#[test]
+fn test_isa_builder_03() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS]);
+    let isa = InstructionSetBuilder::new()
+	.opcode(2).instruction("inc",&fmt,&[])
+	.instruction("dec",&fmt,&[])
+	.opcode(0).instruction("neg",&fmt,&[])
+	.build().ok().unwrap();
+    assert_eq!((isa.opcode(0),isa.opcode(1),isa.opcode(2)),(2,3,0));
+    let bytes = isa.encode("neg",&[1]).ok().unwrap();
+    assert_eq!(isa.decode(&bytes),Ok((2,vec![1])));
+    let errs = InstructionSetBuilder::new()
+	.opcode(1).instruction("inc",&fmt,&[])
+	.opcode(1).instruction("dec",&fmt,&[])
+	.build();
+    assert!(errs.is_err());
+}
+
+#[test]
+fn test_isa_builder_04() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS]);
+    let errs = InstructionSetBuilder::new()
+	.opcode(1 << 34).instruction("inc",&fmt,&[])
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::OpcodeExhausted{mnemonic:"inc".to_string(),opcode:1 << 34,bits:2}]));
+    let errs = InstructionSetBuilder::new()
+	.opcode(usize::MAX).instruction("inc",&fmt,&[])
+	.instruction("dec",&fmt,&[])
+	.build().err().unwrap();
+    assert_eq!(errs[0],IsaError::OpcodeOverflow("dec".to_string()));
+}
+
+#[test]
+fn test_isa_macro_01() {
+    let isa = virmin::isa! {
+	formats {
+	    rr(1,2) { rd: register(3), rs: register(3) }
+	    r(1,2) { rd: register(6), }
+	}
+	instructions {
+	    mov: rr => [Copy(Var(0),Var(1),Byte)];
+	    clr: r => [Load(Var(0),Const(0),Word)];
+	}
+	pseudos {
+	    nop => [mov(Const(0),Const(0))];
+	    clr2 => [clr(Var(0)), clr(Var(1))];
+	}
+    }.ok().unwrap();
+    assert_eq!(isa.len(),2);
+    assert_eq!(isa.formats().len(),2);
+    assert_eq!(isa.get("clr").unwrap().format().label(),"r");
+    assert_eq!(isa.pseudos()[1].arity(),2);
+    assert_eq!(isa.encode("clr",&[5]),Ok(vec![0x15]));
+}
+
+#[test]
+fn test_isa_macro_02() {
+    let errs = virmin::isa! {
+	formats {
+	    r(1,1) { rd: register(3) }
+	}
+	instructions {
+	    inc: r => [Copy(Var(0),Var(1),Byte)];
+	    dec: r => [];
+	    dec: r => [];
+	}
+    }.err();
+    assert_eq!(errs,Some(vec![IsaError::InvalidSemantic("inc".to_string()),
+			      IsaError::DuplicateMnemonic("dec".to_string()),
+			      IsaError::OpcodeExhausted{mnemonic:"dec".to_string(),opcode:2,bits:1}]));
+}
+
+#[test]
+fn test_metadata_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let add = Metadata::new().category(Category::Alu).description("Add registers").writes(&["Z","C"]);
+    let insns = [Instruction::new("add", &fmt, &[]).with_metadata(add.clone()),
+		 Instruction::new("mov", &fmt, &mc).with_metadata(Metadata::new().category(Category::Move)),
+		 Instruction::new("adc", &fmt, &[]).with_metadata(Metadata::new().category(Category::Alu).reads(&["C"])),
+		 Instruction::new("nop", &fmt, &[])];
+    let isa = InstructionSet::new(&insns);
+    assert!(isa.instruction(0).metadata() == &add);
+    assert_eq!(isa.instruction(3).metadata(),&Metadata::default());
+    let alu : Vec<&str> = isa.by_category(&Category::Alu).map(|i| i.mnemonic()).collect();
+    assert_eq!(alu,vec!["add","adc"]);
+    assert_eq!(isa.by_category(&Category::Other("io".to_string())).count(),0);
+}
+
+#[test]
+fn test_metadata_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[]);
+    let isa = InstructionSetBuilder::new()
+	.instruction("hlt",&fmt,&[]).metadata(Metadata::new().category(Category::System))
+	.instruction("nop",&fmt,&[])
+	.build().ok().unwrap();
+    assert_eq!(isa.get("hlt").unwrap().metadata().category,Some(Category::System));
+    assert_eq!(isa.get("nop").unwrap().metadata().category,None);
+}
+
+#[test]
+fn test_extension_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
+    let base = [Instruction::new("inc", &fmt, &[]), Instruction::new("dec", &fmt, &[])];
+    let m = [Instruction::new("mul", &fmt, &[]), Instruction::new("div", &fmt, &[])];
+    let c = [Instruction::new("neg", &fmt, &[])];
+    let isa = InstructionSet::new(&base)
+	.extension(Extension::new("M",8,&m))
+	.extension(Extension::new("C",12,&c));
+    assert_eq!(isa.extensions().len(),2);
+    let mc = isa.with_extensions(&["C","M"]).ok().unwrap();
+    assert_eq!(mc.len(),5);
+    assert_eq!(mc.enabled(),&["C".to_string(),"M".to_string()]);
+    assert_eq!(mc.encode("div",&[1]),Ok(vec![0x19]));
+    assert_eq!(mc.encode("neg",&[1]),Ok(vec![0x1C]));
+    assert_eq!(mc.decode(&[0x19]),Ok((4,vec![1])));
+    // Encodings do not depend on other extensions
+    let m = isa.with_extensions(&["M"]).ok().unwrap();
+    assert_eq!(m.encode("div",&[1]),Ok(vec![0x19]));
+    assert!(m.decode(&[0x1C]).is_err());
+}
+
+#[test]
+fn test_extension_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
+    let base = [Instruction::new("inc", &fmt, &[]), Instruction::new("dec", &fmt, &[])];
+    let x = [Instruction::new("mul", &fmt, &[])];
+    let y = [Instruction::new("neg", &fmt, &[]), Instruction::new("not", &fmt, &[])];
+    let isa = InstructionSet::new(&base)
+	.extension(Extension::new("X",1,&x))
+	.extension(Extension::new("Y",2,&y));
+    assert_eq!(isa.with_extensions(&["Z"]).err(),Some(vec![IsaError::UnknownExtension("Z".to_string())]));
+    assert_eq!(isa.with_extensions(&["X","Y"]).err(),
+	       Some(vec![IsaError::OpcodeConflict{first:"dec".to_string(),second:"mul".to_string(),opcode:1}]));
+    assert!(isa.with_extensions(&["Y"]).is_ok());
+}
+
+#[test]
+fn test_extension_03() {
+    let fmt = Format::new(ONE_BYTE,"fmt",FOUR_BITS, &[FOUR_BITS]);
+    let base = [Instruction::new("inc", &fmt, &[])];
+    let x = [Instruction::new("mul", &fmt, &[])];
+    let isa = InstructionSet::new(&base).extension(Extension::new("X",1 << 34,&x));
+    assert_eq!(isa.with_extensions(&["X"]).err(),
+	       Some(vec![IsaError::OpcodeExhausted{mnemonic:"mul".to_string(),opcode:1 << 34,bits:4}]));
+}
+
+#[test]
+fn test_profile_01() {
+    use virmin::machine::{MachineProfile,Width::*};
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).immediate("imm",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("mov",&fmt,&[Copy(Var(0),Const(0x10),Word)])
+	.instruction("clr",&fmt,&[Load(Var(0),Const(0),QuadWord)])
+	.instruction("st",&fmt,&[Copy(Const(0xFF),Var(1),Byte)])
+	.build().ok().unwrap();
+    assert_eq!(isa.check_profile(&MachineProfile::new(256)),Ok(()));
+    let profile = MachineProfile::new(0x100).registers(0..8).widths(&[Byte,Word]);
+    assert_eq!(isa.check_profile(&profile),Err(vec![
+	IsaError::IllegalRegister{mnemonic:"mov".to_string(),operand:0},
+	IsaError::IllegalWidth{mnemonic:"clr".to_string(),width:QuadWord},
+	IsaError::IllegalRegister{mnemonic:"clr".to_string(),operand:0}]));
+    let profile = MachineProfile::new(0x11).registers(0..9);
+    assert_eq!(isa.check_profile(&profile),Err(vec![
+	IsaError::IllegalAddress{mnemonic:"mov".to_string(),address:0x10},
+	IsaError::IllegalRegister{mnemonic:"clr".to_string(),operand:0},
+	IsaError::IllegalAddress{mnemonic:"st".to_string(),address:0xFF}]));
+}
+
+#[test]
+fn test_profile_02() {
+    use virmin::machine::{MachineProfile,Width::*};
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).build().ok().unwrap();
+    let errs = InstructionSetBuilder::new()
+	.profile(MachineProfile::new(8).widths(&[Byte]))
+	.instruction("clr",&fmt,&[Load(Var(0),Const(0),Word)])
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::IllegalWidth{mnemonic:"clr".to_string(),width:Word},
+				    IsaError::IllegalRegister{mnemonic:"clr".to_string(),operand:0}]));
+    let isa = InstructionSetBuilder::new()
+	.profile(MachineProfile::new(8))
+	.instruction("clr",&fmt,&[Load(Var(0),Const(0),Byte)])
+	.build();
+    assert!(isa.is_ok());
+}
+
+#[test]
+fn test_profile_03() {
+    use virmin::machine::{MachineProfile,Width::QuadWord};
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let slot = |v| RegSlot(Box::new(Var(v)),QuadWord);
+    let mc = [Copy(slot(0),slot(1),QuadWord)];
+    let insn = Instruction::new("mov", &fmt, &mc);
+    assert_eq!(slot(1).arity(),2);
+    assert!(insn.to_microcode(&[3,5]) == vec![MicroCode::Copy(24,40,QuadWord)]);
+    // Eight registers of eight bytes each
+    let isa = InstructionSetBuilder::new()
+	.profile(MachineProfile::new(64).registers(0..64))
+	.instruction("mov",&fmt,&mc)
+	.build();
+    assert!(isa.is_ok());
+    let errs = InstructionSetBuilder::new()
+	.profile(MachineProfile::new(64).registers(0..63))
+	.instruction("mov",&fmt,&mc)
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::IllegalRegister{mnemonic:"mov".to_string(),operand:0},
+				    IsaError::IllegalRegister{mnemonic:"mov".to_string(),operand:1}]));
+}
+
+#[test]
+fn test_temps_01() {
+    use virmin::insn::Predicate;
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ok = InstructionSetBuilder::new()
+	.instruction("swap",&fmt,&[RegFetch(Temp(0),Var(0),Byte),Copy(Var(0),Var(1),Byte),RegStore(Var(1),Temp(0),Byte)])
+	.build();
+    assert!(ok.is_ok());
+    // Reading a scratch register not (definitely) written
+    let errs = InstructionSetBuilder::new()
+	.instruction("a",&fmt,&[RegStore(Var(1),Temp(1),Byte)])
+	.instruction("b",&fmt,&[If(Predicate::Eq(Var(0),Const(0)),vec![RegLoad(Temp(2),Const(0))],vec![]),RegStore(Var(1),Temp(2),Byte)])
+	.instruction("c",&fmt,&[RegLoad(Temp(99),Const(0))])
+	.build();
+    assert_eq!(errs.err(),Some(vec![IsaError::UndefinedTemp{mnemonic:"a".to_string(),temp:1},
+				    IsaError::UndefinedTemp{mnemonic:"b".to_string(),temp:2},
+				    IsaError::UndefinedTemp{mnemonic:"c".to_string(),temp:99}]));
+}
+
+#[test]
+fn test_constraint_01() {
+    use virmin::insn::Constraint;
+    let fmt = Format::builder().width_bytes(2).opcode_bits(2).register("rd",3).simmediate("off",8)
+	.constraint("rd",Constraint::NonZero)
+	.constraint("off",Constraint::Aligned(2))
+	.constraint("off",Constraint::Range(-64,63))
+	.build().ok().unwrap();
+    assert_eq!(fmt.encode(1,&[2,-4isize as usize]).unwrap(),vec![0x89,0x1F]);
+    assert_eq!(fmt.encode(1,&[0,2]),Err(EncodeError::Reserved{operand:0,value:0}));
+    assert_eq!(fmt.encode(1,&[2,3]),Err(EncodeError::Reserved{operand:1,value:3}));
+    assert_eq!(fmt.encode(1,&[2,-66isize as usize]),Err(EncodeError::Reserved{operand:1,value:-66isize as usize}));
+    assert_eq!(fmt.decode(&[0x89,0x1F]),Ok((1,vec![2,-4isize as usize])));
+    assert_eq!(fmt.decode(&[0x01,0x08]),Err(DecodeError::Reserved{operand:0,value:0}));
+    // Constraints must refer to a named field
+    let r = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).constraint("rs",Constraint::NonZero).build();
+    assert!(r.err() == Some(FormatError::UnknownField("rs".to_string())));
+}
+
+#[test]
+fn test_constraint_02() {
+    use virmin::insn::Constraint;
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3)
+	.constraint("rd",Constraint::NonZero)
+	.build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)])
+	.build().ok().unwrap();
+    assert_eq!(isa.decode(&[0b0100_0100]),Ok((0,vec![1,2])));
+    assert_eq!(isa.decode(&[0b0100_0000]),Err(DecodeError::Reserved{operand:0,value:0}));
+    // Unassigned opcodes are still unknown, rather than reserved
+    assert_eq!(isa.decode(&[0b0100_0001]),Err(DecodeError::Unknown));
+}
+
+// =====================================================
+// Pseudo-code
+// =====================================================
+
+#[test]
+fn test_pseudocode_01() {
+    use virmin::insn::Predicate;
+    use virmin::machine::Width::DoubleWord;
+    assert_eq!(Add(Box::new(Mul(Box::new(Var(0)),Box::new(Const(4)))),Box::new(Const(256))).to_string(),"(op0 * 4) + 256");
+    assert_eq!(virmin::insn::Operand::Bits(Box::new(PcRel(Box::new(Var(1)))),7,4).to_string(),"(pc + op1)[7:4]");
+    assert_eq!(Copy(Var(0),Var(1),DoubleWord).to_string(),"mem32[op0] := mem32[op1]");
+    assert_eq!(RegAdd(Var(0),Sp).to_string(),"reg[op0] := reg[op0] + sp");
+    assert_eq!(RegAlu(virmin::machine::AluOp::Sar,Var(0),Var(1)).to_string(),"reg[op0] := reg[op0] >>s reg[op1]");
+    let code = If(Predicate::Not(Box::new(Predicate::RegZero(Temp(0)))),vec![Goto(Const(0))],vec![Jump(SExt(Box::new(Var(0)),4))]);
+    assert_eq!(code.to_string(),"if !(t0 == 0) then { pc := 0 } else { pc := pc + sext4(op0) }");
+}
+
+#[test]
+fn test_pseudocode_02() {
+    let fmt = Format::builder().label("fmt").width_bytes(2).opcode_bits(6).register("rd",3).simmediate("off",7).build().ok().unwrap();
+    assert_eq!(fmt.to_string(),"fmt (2 bytes): opcode:6 rd:r3 off:s7");
+    let microcode = [RegFetch(Var(0),PcRel(Box::new(Var(1))),Byte),virmin::insn::AbstractMicroCode::Add(Var(0),Var(0),Byte)];
+    let insn = Instruction::new("ld", &fmt, &microcode);
+    assert_eq!(insn.to_string(),"ld rd, off\n    reg[rd] := mem8[pc + off]\n    mem8[rd] := mem8[rd] + mem8[rd]");
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    assert_eq!(fmt.to_string(),"fmt (1 byte): opcode:2 op0:u3 op1:u3");
+    assert!(format!("{:?}",fmt).starts_with("Format { width: Bytes { value: 1 }, label: \"fmt\""));
 }
@@ -0,0 +1,201 @@
+#![cfg(feature="std")]
+#[cfg(feature="chip8")]
+use virmin::isa::chip8;
+
+// =====================================================
+// CHIP-8
+// =====================================================
+
+#[test]
+#[cfg(feature="chip8")]
+fn test_chip8_01() {
+    use virmin::asm::Assembler;
+    use virmin::disasm::Disassembler;
+    let isa = chip8::isa();
+    assert_eq!(isa.len(),16);
+    assert_eq!(isa.validate(),Ok(()));
+    let src = "cls\nld r1, 0x42\nor r1, r2\ndrw r1, r2, 5\nld_b_v r3\njp 0x200\nret\n";
+    let program = Assembler::new(&isa).assemble(src).ok().unwrap();
+    assert_eq!(program.bytes(),&[0x00,0xE0,0x61,0x42,0x81,0x21,0xD1,0x25,0xF3,0x33,0x12,0x00,0x00,0xEE]);
+    let lines = Disassembler::new(&isa).disassemble(program.bytes(),0);
+    let texts : Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(texts,vec!["cls","ld r1, 66","or r1, r2","drw r1, r2, 5","ld_b_v r3","jp 512","ret"]);
+}
+
+#[test]
+#[cfg(feature="chip8")]
+fn test_chip8_02() {
+    use virmin::machine::Memory;
+    let mut bytes = [0u8;chip8::MEMORY_SIZE];
+    chip8::load_font(&mut Memory::new(&mut bytes));
+    let zero = chip8::FONT_ADDRESS;
+    let mut display = chip8::Display::new();
+    assert!(!display.draw(62,0,&bytes[zero..zero+5]));
+    assert!(display.get(62,0) && display.get(1,0) && !display.get(63,1));
+    assert!(display.draw(62,0,&bytes[zero..zero+1]));
+    assert!(!display.get(62,0));
+    assert!(display.to_string().starts_with(&format!("{}\n.#............................................................#.\n",".".repeat(64))));
+    display.clear();
+    assert_eq!(display,chip8::Display::default());
+}
+
+// =====================================================
+// LC-3
+// =====================================================
+
+#[test]
+#[cfg(feature="lc3")]
+fn test_lc3_01() {
+    use virmin::asm::Assembler;
+    use virmin::disasm::Disassembler;
+    use virmin::isa::lc3;
+    let isa = lc3::isa();
+    assert_eq!(isa.len(),16);
+    assert_eq!(isa.opcode(isa.index_of("op_trap").unwrap()),0xF);
+    let src = "add r1, r2, 3\naddi r1, r1, -1\nbrz -3\nnot r0, r1\nret\nhalt\nldr r2, r6, -2\n";
+    let program = Assembler::new(&isa).assemble(src).ok().unwrap();
+    assert_eq!(program.bytes(),&[0x12,0x83,0x12,0x7F,0x05,0xFD,0x90,0x7F,0xC1,0xC0,0xF0,0x25,0x65,0xBE]);
+    let lines = Disassembler::new(&isa).disassemble(program.bytes(),0);
+    let texts : Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+    assert_eq!(texts,vec!["add r1, r2, 3","addi r1, r1, -1","brz -3","not r0, r1","ret","halt","ldr r2, r6, -2"]);
+}
+
+// =====================================================
+// RV32I
+// =====================================================
+
+#[test]
+#[cfg(feature="rv32i")]
+fn test_rv32i_01() {
+    use virmin::asm::Assembler;
+    use virmin::disasm::{Disassembler,DisasmStyle};
+    use virmin::isa::rv32i;
+    let isa = rv32i::isa();
+    assert_eq!(isa.validate(),Ok(()));
+    assert_eq!(isa.opcode(isa.index_of("op_reg").unwrap()),0x33);
+    let src = "addi r1, r0, -5\nadd r3, r1, r2\nsub r3, r1, r2\nlw r4, r2, 8\nsw r4, r2, 8, 0\nlui r5, 0x12345\necall\nret\n";
+    let program = Assembler::new(&isa).assemble(src).ok().unwrap();
+    assert_eq!(program.bytes(),&[0x93,0x00,0xB0,0xFF, 0xB3,0x81,0x20,0x00, 0xB3,0x81,0x20,0x40, 0x03,0x22,0x81,0x00,
+				 0x23,0x24,0x41,0x00, 0xB7,0x52,0x34,0x12, 0x73,0x00,0x00,0x00, 0x67,0x80,0x00,0x00]);
+    let disasm = Disassembler::new(&isa).style(DisasmStyle::default().prefix("x"));
+    let texts : Vec<String> = disasm.disassemble(program.bytes(),0).into_iter().map(|l| l.text).collect();
+    assert_eq!(texts,vec!["addi x1, x0, -5","add x3, x1, x2","sub x3, x1, x2","lw x4, x2, 8","sw x4, x2, 8, 0",
+			  "lui x5, 74565","ecall","ret"]);
+}
+
+#[test]
+#[cfg(feature="rv32i")]
+fn test_rv32i_02() {
+    use virmin::asm::Assembler;
+    use virmin::disasm::{Disassembler,DisasmStyle};
+    use virmin::isa::rv32i;
+    let isa = rv32i::isa();
+    let profile = rv32i::profile();
+    assert_eq!(profile.register_index("fp"),Some(8));
+    assert_eq!(profile.register_name(2),Some("sp"));
+    let src = "addi sp, sp, -16\nsw ra, x2, 12, 0\nadd a0, fp, t6\nret\n";
+    let program = Assembler::new(&isa).profile(&profile).assemble(src).ok().unwrap();
+    let disasm = Disassembler::new(&isa).style(DisasmStyle::default().profile(&profile));
+    let texts : Vec<String> = disasm.disassemble(program.bytes(),0).into_iter().map(|l| l.text).collect();
+    assert_eq!(texts,vec!["addi sp, sp, -16","sw ra, sp, 12, 0","add a0, s0, t6","ret"]);
+}
+
+// =====================================================
+// SUBLEQ
+// =====================================================
+
+#[test]
+#[cfg(feature="subleq")]
+fn test_subleq_01() {
+    use virmin::asm::Assembler;
+    use virmin::isa::subleq;
+    let isa = subleq::isa();
+    let program = Assembler::new(&isa).assemble(subleq::ADD.source).ok().unwrap();
+    assert_eq!(program.bytes(),&[0,0,2,1, 0,2,1,2, 0,2,2,0xFF]);
+}
+
+#[test]
+#[cfg(feature="subleq")]
+fn test_subleq_02() {
+    use virmin::asm::Assembler;
+    use virmin::isa::subleq;
+    use virmin::machine::State;
+    use virmin::program::DecodedProgram;
+    let isa = subleq::isa();
+    for (sample,expected) in subleq::SAMPLES.iter().zip([42,42]) {
+	let program = Assembler::new(&isa).assemble(sample.source).ok().unwrap();
+	let decoded = DecodedProgram::new(&isa,program.bytes());
+	let mut bytes = [0u8;subleq::MEMORY_SIZE];
+	bytes[..sample.data.len()].copy_from_slice(sample.data);
+	let mut state = State::new(0,&mut bytes);
+	let steps = state.run(&decoded,1000).ok().unwrap();
+	assert!(steps < 1000,"{} did not halt",sample.name);
+	assert_eq!(state.pc,subleq::HALT);
+	assert_eq!(state.data.read_u8(sample.result),expected,"{}",sample.name);
+    }
+}
+
+// =====================================================
+// 6502
+// =====================================================
+
+#[test]
+#[cfg(feature="mos6502")]
+fn test_mos6502_01() {
+    use virmin::asm::Assembler;
+    use virmin::disasm::Disassembler;
+    use virmin::isa::mos6502;
+    let isa = mos6502::isa();
+    assert_eq!(isa.len(),151);
+    assert_eq!(isa.validate(),Ok(()));
+    assert_eq!(isa.get("adc_imm").unwrap().metadata().writes,vec!["N","V","Z","C"]);
+    let src = "lda_imm 0x10\nsta_abs 0x1234\nclc\nadc_indy 0x20\nbne -4\nasl_a\nrts\n";
+    let program = Assembler::new(&isa).assemble(src).ok().unwrap();
+    assert_eq!(program.bytes(),&[0xA9,0x10, 0x8D,0x34,0x12, 0x18, 0x71,0x20, 0xD0,0xFC, 0x0A, 0x60]);
+    let texts : Vec<String> = Disassembler::new(&isa).hex(true).disassemble(program.bytes(),0).into_iter().map(|l| l.text).collect();
+    assert_eq!(texts,vec!["lda_imm 0x10","sta_abs 0x1234","clc","adc_indy 0x20","bne -0x4","asl_a","rts"]);
+}
+
+// =====================================================
+// Stack Machine
+// =====================================================
+
+#[test]
+#[cfg(feature="stack")]
+fn test_stack_01() {
+    use virmin::asm::Assembler;
+    use virmin::isa::stack;
+    use virmin::machine::State;
+    use virmin::program::DecodedProgram;
+    let isa = stack::isa();
+    let src = "push 5\ncall double\ninc\nhalt\ndouble: swap\ndup\nadd\nswap\nret\n";
+    let program = Assembler::new(&isa).assemble(src).ok().unwrap();
+    assert_eq!(&program.bytes()[..8],&[1,5,0,0,0,11,5,0]);
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [0u8;64];
+    let mut state = State::new(0,&mut bytes);
+    stack::reset(&mut state);
+    assert_eq!(stack::run(&mut state,&decoded,100),Ok(10));
+    assert_eq!(stack::stack(&state),vec![11]);
+}
+
+#[test]
+#[cfg(feature="stack")]
+fn test_stack_02() {
+    use virmin::asm::Assembler;
+    use virmin::isa::stack;
+    use virmin::machine::State;
+    use virmin::program::DecodedProgram;
+    let isa = stack::isa();
+    let program = Assembler::new(&isa).assemble("push 1\nadd\n").ok().unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut bytes = [0u8;12];
+    let mut state = State::new(0,&mut bytes);
+    stack::reset(&mut state);
+    assert_eq!(stack::run(&mut state,&decoded,100),Err(stack::Fault::Underflow{pc:1}));
+    let program = Assembler::new(&isa).assemble("push 1\npush 2\npush 3\n").ok().unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    stack::reset(&mut state);
+    state.pc = 0;
+    assert_eq!(stack::run(&mut state,&decoded,100),Err(stack::Fault::Overflow{pc:2}));
+}
@@ -0,0 +1,124 @@
+#![cfg(feature="jit")]
+use virmin::insn::{DecodeError,Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::{Const,Var};
+use virmin::jit::{Jit,JitError};
+use virmin::machine::{MachineError,RegisterFile,State};
+use virmin::machine::Width::{Byte,QuadWord,Word};
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// JIT
+// =====================================================
+
+#[test]
+fn test_jit_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("addw",&fmt,&[Add(Var(0),Var(1),Word)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // do { M[2..4] := M[2..4] + M[4..6]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("addw",&[2,4]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut jit = Jit::new(&decoded).unwrap().threshold(2).differential(true);
+    let mut bytes = [100,0xFF,0,0,3,1];
+    let mut state = State::new(0,&mut bytes);
+    let mut count = 0;
+    while state.pc < decoded.len() {
+	count += jit.step(&mut state).unwrap();
+    }
+    assert_eq!(jit.compiled(),1);
+    assert_eq!(count,300);
+    assert_eq!(state.pc,3);
+    assert_eq!(bytes,[0,0xFF,0x2C,0x65,3,1]);
+}
+
+#[test]
+fn test_jit_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("ldi",&fmt,&[Load(Var(0),Var(1),QuadWord)])
+	.instruction("reg",&fmt,&[RegLoad(Var(0),Var(1))])
+	.instruction("out",&fmt,&[Copy(Const(12),Var(0),Byte)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("ldi",&[0,5]).unwrap();
+    program.push("reg",&[0,7]).unwrap();
+    program.push("out",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut jit = Jit::new(&decoded).unwrap().threshold(0);
+    let mut bytes = [0xFF;12];
+    let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(1,QuadWord));
+    // Register microcode is interpreted, whilst the out of bounds
+    // copy faults in the interpreter.
+    assert_eq!(jit.step(&mut state),Ok(1));
+    assert_eq!(jit.step(&mut state),Ok(1));
+    assert_eq!(state.registers.read(0),7);
+    assert_eq!(jit.step(&mut state),Err(JitError::Machine(MachineError::Memory{pc:2,address:12,length:1})));
+    assert_eq!(jit.compiled(),1);
+    let mut state = State::new(3,&mut bytes);
+    let error = MachineError::Decode{pc:3,error:DecodeError::OutOfBounds(3)};
+    assert_eq!(jit.step(&mut state),Err(JitError::Machine(error)));
+}
+
+#[test]
+fn test_jit_03() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // Branches between three blocks (at pcs 0, 3 and 2)
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,3]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    program.push("jnz",&[0,2]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut jit = Jit::new(&decoded).unwrap().threshold(1).differential(true);
+    let mut bytes = [0,0xFF];
+    let mut state = State::new(0,&mut bytes);
+    let mut count = 0;
+    while state.pc < decoded.len() {
+	count += jit.step(&mut state).unwrap();
+    }
+    assert_eq!(jit.compiled(),3);
+    assert_eq!(count,1024);
+    // Changing the size of memory discards compiled blocks
+    let mut bytes = [0,0xFF,0];
+    let mut state = State::new(0,&mut bytes);
+    assert_eq!(jit.step(&mut state),Ok(1));
+    assert_eq!(jit.compiled(),0);
+}
+
+#[test]
+fn test_jit_04() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let mut decoded = DecodedProgram::new(&isa,program.bytes());
+    assert_eq!(decoded.fuse(&[(0,0)]),1);
+    // A compiled block is replayed one instruction at a time, rather
+    // than executing the fused pair in one step
+    let mut jit = Jit::new(&decoded).unwrap().threshold(1).differential(true);
+    let mut bytes = [0,1];
+    let mut state = State::new(0,&mut bytes);
+    let mut count = 0;
+    while state.pc < decoded.len() {
+	count += jit.step(&mut state).unwrap();
+    }
+    assert_eq!(jit.compiled(),1);
+    assert_eq!(count,384);
+}
@@ -0,0 +1,88 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::asm::Assembler;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::link::{Linker,LinkError,Object};
+use virmin::machine::Width::Byte;
+use virmin::program::{Location,SymbolKind};
+
+fn formats() -> (Format,Format,Format,Format) {
+    let rr = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let ri = Format::builder().label("ri").width_bytes(2).opcode_bits(2).register("rd",4).simmediate("imm",10).build().ok().unwrap();
+    let n = Format::new(ONE_BYTE,"n",TWO_BITS,&[]);
+    let j = Format::builder().label("j").width_bytes(1).opcode_bits(2).simmediate("off",6).build().ok().unwrap();
+    (rr,ri,n,j)
+}
+
+// =====================================================
+// Linker
+// =====================================================
+
+#[test]
+fn test_link_01() {
+    let (rr,ri,n,j) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let mc3 = [Jump(Var(0))];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[]),
+		 Instruction::new("jmp", &j, &mc3)];
+    let isa = InstructionSet::new(&insns);
+    let asm = Assembler::new(&isa);
+    let main = Assembler::new(&isa).file("main.s").assemble(".extern f\n.global start\nstart: ldi r1, f + 1\njmp f\nnop").unwrap();
+    let lib = asm.assemble(".global f\nf: nop\nloop: jmp loop\nldi r2, loop\njmp start").err();
+    // Labels not declared external cannot be used
+    assert!(lib.is_some());
+    let lib = Assembler::new(&isa).file("lib.s").assemble(".global f\n.extern start\nf: nop\nloop: jmp loop\nldi r2, loop\njmp start").unwrap();
+    let program = Linker::new(&isa)
+	.object(Object::new().section("text",main))
+	.object(Object::new().section("text",lib))
+	.link().ok().unwrap();
+    let expected = asm.assemble("start: ldi r1, f + 1\njmp f\nnop\nf: nop\nloop: jmp loop\nldi r2, loop\njmp start").unwrap();
+    assert_eq!(program.bytes(),expected.bytes());
+    assert_eq!(program.symbol("f").map(|s| (s.value,s.global)),Some((3,true)));
+    assert_eq!(program.symbol("loop").map(|s| (s.value,s.global)),Some((4,false)));
+    assert_eq!(program.symbol("start").map(|s| s.kind),Some(SymbolKind::Label));
+    assert!(program.relocations().is_empty());
+    assert_eq!(program.location(4).cloned(),Some(Location{file:"lib.s".to_string(),line:4}));
+}
+
+#[test]
+fn test_link_02() {
+    let (rr,ri,n,j) = formats();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let mc3 = [Jump(Var(0))];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("nop", &n, &[]),
+		 Instruction::new("jmp", &j, &mc3)];
+    let isa = InstructionSet::new(&insns);
+    let asm = Assembler::new(&isa);
+    let object = || {
+	let text = asm.assemble(".extern table\nldi r1, table\njmp table").unwrap();
+	let data = asm.assemble(".global table\ntable: mov r1, r2").unwrap();
+	Object::new().section("data",data).section("text",text)
+    };
+    let program = Linker::new(&isa).object(object()).place("data",4).place("text",0).fill("nop").link().ok().unwrap();
+    let expected = asm.assemble("ldi r1, table\njmp table\nnop\nnop\ntable: mov r1, r2").unwrap();
+    assert_eq!(program.bytes(),expected.bytes());
+    // Errors
+    let err = |l: Linker| l.link().err();
+    assert_eq!(err(Linker::new(&isa).object(object()).place("data",4).place("text",0)),Some(LinkError::Gap("data".to_string())));
+    assert_eq!(err(Linker::new(&isa).object(object()).place("data",1).place("text",0)),
+	       Some(LinkError::Overlap{first:"text".to_string(),second:"data".to_string()}));
+    // An empty section does not hide an overlap
+    let sections = Object::new().section("a",asm.assemble("nop\nnop\nnop").unwrap())
+	.section("b",asm.assemble("").unwrap()).section("c",asm.assemble("nop").unwrap());
+    assert_eq!(err(Linker::new(&isa).object(sections).place("a",0).place("b",1).place("c",2)),
+	       Some(LinkError::Overlap{first:"a".to_string(),second:"c".to_string()}));
+    assert_eq!(err(Linker::new(&isa).object(object()).object(object())),Some(LinkError::DuplicateSymbol("table".to_string())));
+    assert_eq!(err(Linker::new(&isa).object(object()).place("data",40).place("text",0).fill("nop")),
+	       Some(LinkError::OutOfRange{symbol:"table".to_string(),value:39}));
+    let text = asm.assemble(".extern missing\nldi r1, missing").unwrap();
+    assert_eq!(err(Linker::new(&isa).object(Object::new().section("text",text))),Some(LinkError::UndefinedSymbol("missing".to_string())));
+}
@@ -0,0 +1,49 @@
+#![cfg(feature="log")]
+use std::sync::Mutex;
+use virmin::domain::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+/// Records every event as its target and message.
+struct Recorder(Mutex<Vec<(String,String)>>);
+
+impl log::Log for Recorder {
+    fn enabled(&self, _: &log::Metadata) -> bool { true }
+    fn log(&self, record: &log::Record) {
+	self.0.lock().unwrap().push((record.target().to_string(),record.args().to_string()));
+    }
+    fn flush(&self) {}
+}
+
+static RECORDER : Recorder = Recorder(Mutex::new(Vec::new()));
+
+// =====================================================
+// Logging
+// =====================================================
+
+#[test]
+fn test_log_01() {
+    log::set_logger(&RECORDER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,0]).unwrap();
+    program.push("mov",&[6,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [3u8,0,0,0];
+    let mut state = State::new(0,&mut data);
+    assert!(state.run(&decoded,10).is_err());
+    let events = RECORDER.0.lock().unwrap().clone();
+    let find = |target: &str| events.iter().filter(|(t,_)| t == target).map(|(_,m)| m.as_str()).collect::<Vec<_>>();
+    assert_eq!(find("virmin::decode"),vec!["0x0000: mov [1, 0]","0x0001: mov [6, 0]"]);
+    assert_eq!(find("virmin::fetch"),vec!["pc 0: mov","pc 1: mov"]);
+    assert_eq!(find("virmin::execute"),vec!["pc 0: mem8[1] := mem8[0]"]);
+    assert_eq!(find("virmin::fault"),vec!["instruction at pc 1 accesses memory 0x6..0x7 out of bounds"]);
+}
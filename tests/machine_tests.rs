@@ -1,8 +1,6 @@
-use virmin::machine::MicroCode;
+use virmin::machine::{AluOp,MicroCode,RegisterFile};
 use virmin::machine::State;
-use virmin::machine::Memory;
 use virmin::machine::Width::{Byte,Word,DoubleWord,QuadWord};
-use virmin::machine::Sign::*;
 
 // =====================================================
 // MicroCode (Add)
@@ -41,6 +39,57 @@ fn test_add_03() {
     assert_eq!(bytes,[3,4,2,2]);
 }    
 
+// =====================================================
+// MicroCode (Alu)
+// =====================================================
+
+#[test]
+fn test_alu_01() {
+    let mut bytes : [u8;2] = [1,2];
+    let mut state = State::new(0,&mut bytes);
+    state.execute(MicroCode::Alu(AluOp::Sub,0,1,Byte));
+    assert_eq!(state.pc,1);
+    assert_eq!(bytes,[0xFF,2]);
+}
+
+#[test]
+fn test_alu_02() {
+    // Check each operation against expected results at a given width
+    let cases = [(AluOp::Sub,0x0001,0x0002,Word,0xFFFF),(AluOp::And,0xF0,0x3C,Byte,0x30),
+		 (AluOp::Or,0xF0,0x3C,Byte,0xFC),(AluOp::Xor,0xF0,0x3C,Byte,0xCC),
+		 (AluOp::Shl,0x81,1,Byte,0x02),(AluOp::Shl,0x81,8,Byte,0),
+		 (AluOp::Shr,0x80,7,Byte,1),(AluOp::Shr,0x80,9,Byte,0),
+		 (AluOp::Sar,0x80,1,Byte,0xC0),(AluOp::Sar,0x80,200,Byte,0xFF),(AluOp::Sar,0x40,1,Byte,0x20),
+		 (AluOp::Eq,3,3,Byte,1),(AluOp::Eq,3,4,Byte,0),
+		 (AluOp::Lt,0xFF,1,Byte,1),(AluOp::Lt,1,0xFF,Byte,0),
+		 (AluOp::Ltu,0xFF,1,Byte,0),(AluOp::Ltu,1,0xFF,Byte,1),
+		 (AluOp::Lt,0x8000_0000,0,DoubleWord,1),(AluOp::Sar,1 << 63,63,QuadWord,u64::MAX)];
+    for (op,x,y,w,r) in cases {
+	assert_eq!(op.apply(x,y,w),r,"{:?}",(op,x,y,w));
+	let mut bytes = [0u8;16];
+	bytes[..8].copy_from_slice(&x.to_le_bytes());
+	bytes[8..].copy_from_slice(&y.to_le_bytes());
+	let mut state = State::new(0,&mut bytes);
+	state.execute(MicroCode::Alu(op,0,8,w));
+	assert_eq!(u64::from_le_bytes(bytes[..8].try_into().unwrap()) & w.mask(),r,"{:?}",(op,x,y,w));
+    }
+}
+
+#[test]
+fn test_alu_03() {
+    use virmin::machine::PC;
+    let mut bytes : [u8;0] = [];
+    let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(2,Byte));
+    // Operations on registers are at the width of the register file
+    state.execute_all(&[MicroCode::RegLoad(0,1),MicroCode::RegLoad(1,2),MicroCode::RegAlu(AluOp::Sub,0,1)]);
+    assert_eq!(state.registers.read(0),0xFF);
+    state.execute_all(&[MicroCode::RegLoad(1,0x80),MicroCode::RegAlu(AluOp::Lt,1,0)]);
+    assert_eq!(state.registers.read(1),1);
+    // Writing the pc branches
+    state.execute_all(&[MicroCode::RegLoad(0,3),MicroCode::RegAlu(AluOp::Xor,PC,0)]);
+    assert_eq!(state.pc,2 ^ 3);
+}
+
 // =====================================================
 // MicroCode (Copy)
 // =====================================================   
@@ -188,3 +237,319 @@ fn test_jump_02() {
     assert_eq!(state.pc,1);
     assert_eq!(bytes,[1,2]);
 }
+
+// =====================================================
+// MicroCode (Sequences)
+// =====================================================
+
+#[test]
+fn test_execute_all_01() {
+    let mut bytes : [u8;2] = [1,2];
+    let mut state = State::new(0,&mut bytes);
+    // Execute a single instruction
+    state.execute_all(&[MicroCode::Copy(0,1,Byte),MicroCode::Add(0,1,Byte)]);
+    // Check what happened
+    assert_eq!(state.pc,1);
+    assert_eq!(bytes,[4,2]);
+}
+
+#[test]
+fn test_execute_all_02() {
+    let mut bytes : [u8;2] = [1,2];
+    let mut state = State::new(3,&mut bytes);
+    // Execute a single instruction
+    state.execute_all(&[MicroCode::Add(0,1,Byte),MicroCode::Jump(-2)]);
+    // Check what happened
+    assert_eq!(state.pc,1);
+    assert_eq!(bytes,[3,2]);
+}
+
+// =====================================================
+// MicroCode (Registers)
+// =====================================================
+
+#[test]
+fn test_registers_01() {
+    let mut bytes : [u8;2] = [0,0];
+    let registers = RegisterFile::new(4,Byte).with_names(&["a","b"]);
+    let mut state = State::new(0,&mut bytes).with_registers(registers);
+    // Execute some instructions
+    state.execute(MicroCode::RegLoad(0,0x1FF));
+    state.execute(MicroCode::RegLoad(1,2));
+    state.execute(MicroCode::RegAdd(0,1));
+    state.execute(MicroCode::RegCopy(3,0));
+    // Check what happened
+    assert_eq!(state.pc,4);
+    assert_eq!(state.registers.get("a"),Some(1));
+    assert_eq!(state.registers.get("b"),Some(2));
+    assert_eq!(state.registers.read(3),1);
+    assert_eq!(state.registers.get("c"),None);
+    assert_eq!(state.registers.index_of("b"),Some(1));
+    assert_eq!(bytes,[0,0]);
+}
+
+#[test]
+fn test_registers_02() {
+    let mut bytes : [u8;4] = [1,2,3,4];
+    let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(2,QuadWord));
+    // Execute some instructions
+    state.execute(MicroCode::RegFetch(0,0,DoubleWord));
+    state.execute(MicroCode::RegFetch(1,1,Byte));
+    state.execute(MicroCode::RegStore(2,0,Word));
+    // Check what happened
+    assert_eq!(state.pc,3);
+    assert_eq!(state.registers.read(0),0x04030201);
+    assert_eq!(state.registers.read(1),2);
+    assert_eq!(bytes,[1,2,1,2]);
+}
+
+#[test]
+fn test_registers_03() {
+    use virmin::machine::{LR,PC,SP};
+    let mut bytes : [u8;2] = [0,0];
+    let mut state = State::new(5,&mut bytes).with_registers(RegisterFile::new(1,QuadWord));
+    // Call: lr := pc; pc := 9
+    state.execute_all(&[MicroCode::RegCopy(LR,PC),MicroCode::RegLoad(PC,9)]);
+    assert_eq!((state.pc,state.lr),(9,5));
+    // Adjust stack: sp := sp + r0
+    state.execute_all(&[MicroCode::RegLoad(0,16),MicroCode::RegAdd(SP,0)]);
+    assert_eq!((state.pc,state.sp),(10,16));
+    // Return: pc := lr + 1
+    state.execute_all(&[MicroCode::RegLoad(0,1),MicroCode::RegAdd(0,LR),MicroCode::RegCopy(PC,0)]);
+    assert_eq!(state.pc,6);
+    assert_eq!(state.read_register(SP),16);
+}
+
+#[test]
+fn test_registers_04() {
+    let mut bytes : [u8;6] = [1,2,3,4,5,6];
+    let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(2,QuadWord));
+    // r1 := mem16[r0]; mem8[r0] := r1
+    state.execute_all(&[MicroCode::RegLoad(0,2),MicroCode::RegFetchIndirect(1,0,Word)]);
+    assert_eq!(state.registers.read(1),0x0403);
+    state.execute_all(&[MicroCode::RegLoad(0,5),MicroCode::RegStoreIndirect(0,1,Byte)]);
+    // Addresses wrap around memory, as do values beyond its end
+    state.execute_all(&[MicroCode::RegLoad(0,11),MicroCode::RegFetchIndirect(1,0,DoubleWord)]);
+    assert_eq!(state.registers.read(1),0x03020103);
+    state.execute_all(&[MicroCode::RegLoad(1,0xAABB),MicroCode::RegStoreIndirect(0,1,Word)]);
+    assert_eq!(bytes,[0xAA,2,3,4,5,0xBB]);
+}
+
+#[test]
+fn test_temps_01() {
+    use virmin::machine::temp;
+    let mut bytes : [u8;2] = [3,4];
+    let mut state = State::new(0,&mut bytes);
+    // Swap bytes via scratch registers
+    state.execute_all(&[MicroCode::RegFetch(temp(0),0,Byte),MicroCode::RegFetch(temp(1),1,Byte),
+			MicroCode::RegStore(0,temp(1),Byte),MicroCode::RegStore(1,temp(0),Byte)]);
+    assert_eq!(state.pc,1);
+    // Scratch registers do not survive the instruction
+    assert_eq!(state.read_register(temp(0)),0);
+    assert_eq!(state.read_register(temp(1)),0);
+    assert_eq!(bytes,[4,3]);
+}
+
+// =====================================================
+// Memory
+// =====================================================
+
+#[test]
+fn test_memory_01() {
+    use virmin::machine::Memory;
+    let mut bytes = [0u8;8];
+    let mut memory = Memory::new(&mut bytes);
+    memory.write_bytes(1,&[1,2,3]);
+    memory.fill(5,3,9);
+    let mut block = [0u8;4];
+    memory.read_bytes(0,&mut block);
+    assert_eq!(block,[0,1,2,3]);
+    // Overlapping copies (in both directions)
+    memory.copy(2,1,3);
+    assert_eq!(memory.bytes(),&[0,1,1,2,3,9,9,9]);
+    memory.copy(0,3,4);
+    assert_eq!(bytes,[2,3,9,9,3,9,9,9]);
+}
+
+#[test]
+#[should_panic]
+fn test_memory_02() {
+    use virmin::machine::Memory;
+    let mut bytes = [0u8;4];
+    Memory::new(&mut bytes).copy(2,0,3);
+}
+
+#[test]
+fn test_memory_03() {
+    use virmin::machine::{Memory,Scalar};
+    let mut bytes = [0u8;8];
+    let mut memory = Memory::new(&mut bytes);
+    memory.write::<u32>(2,0x12345678);
+    assert_eq!(memory.read::<u16>(2),0x5678);
+    assert_eq!(memory.read::<u16>(4),0x1234);
+    assert_eq!(memory.read::<u64>(0),0x0000123456780000);
+    assert_eq!((u8::WIDTH,u64::WIDTH),(Byte,QuadWord));
+    assert_eq!(u16::truncate(0x12345).wrapping_add(0xE000).extend(),0x0345);
+    assert_eq!(bytes,[0,0,0x78,0x56,0x34,0x12,0,0]);
+}
+
+#[test]
+fn test_memory_04() {
+    use virmin::insn::ByteOrder;
+    use virmin::machine::Memory;
+    let mut bytes = [0u8;8];
+    let mut memory = Memory::new(&mut bytes).with_byte_order(ByteOrder::BigEndian);
+    assert_eq!(memory.byte_order(),ByteOrder::BigEndian);
+    memory.write::<u32>(2,0x12345678);
+    assert_eq!(memory.read::<u16>(2),0x1234);
+    assert_eq!(memory.read::<u16>(4),0x5678);
+    assert_eq!(memory.read::<u64>(0),0x0000123456780000);
+    assert_eq!(bytes,[0,0,0x12,0x34,0x56,0x78,0,0]);
+}
+
+// =====================================================
+// Pseudo-code
+// =====================================================
+
+#[test]
+fn test_pseudocode_01() {
+    use virmin::machine::{temp,PC,SP};
+    assert_eq!(MicroCode::Add(16,20,DoubleWord).to_string(),"mem32[16] := mem32[16] + mem32[20]");
+    assert_eq!(MicroCode::Jump(-3).to_string(),"pc := pc - 3");
+    assert_eq!(MicroCode::Alu(AluOp::Shl,0,1,Byte).to_string(),"mem8[0] := mem8[0] << mem8[1]");
+    assert_eq!(MicroCode::RegAlu(AluOp::Lt,1,2).to_string(),"reg[1] := reg[1] <s reg[2]");
+    assert_eq!(MicroCode::RegFetchIndirect(1,SP,Word).to_string(),"reg[1] := mem16[sp]");
+    assert_eq!(MicroCode::RegStoreIndirect(temp(0),2,Byte).to_string(),"mem8[t0] := reg[2]");
+    assert_eq!(MicroCode::RegFetch(PC,4,Word).to_string(),"pc := mem16[4]");
+    assert_eq!(MicroCode::RegCopy(temp(2),3).to_string(),"t2 := reg[3]");
+    assert_eq!(MicroCode::SkipIfZero(1,QuadWord,2).to_string(),"if mem64[1] == 0 then skip 2");
+}
+
+// =====================================================
+// Machine Configuration
+// =====================================================
+
+fn config_with<F:FnOnce(&virmin::program::DecodedProgram)>(f: F) {
+    use virmin::insn::{Format,InstructionSetBuilder};
+    use virmin::insn::AbstractMicroCode::*;
+    use virmin::insn::Operand::Var;
+    use virmin::program::{DecodedProgram,Program};
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).immediate("imm",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("li",&fmt,&[RegLoad(Var(0),Var(1))])
+	.instruction("ld",&fmt,&[RegFetch(Var(0),Var(1),Word)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("li",&[0,5]).unwrap();
+    program.push("ld",&[1,7]).unwrap();
+    program.push("li",&[2,6]).unwrap();
+    f(&DecodedProgram::new(&isa,program.bytes()))
+}
+
+#[test]
+fn test_config_01() {
+    use virmin::insn::ByteOrder;
+    use virmin::machine::{MachineConfig,OwnedState,StackDirection};
+    let machine = OwnedState::new(16);
+    assert_eq!(machine.config,MachineConfig{memory:16,..MachineConfig::default()});
+    assert_eq!((machine.sp,machine.features,machine.registers.len()),(0,u64::MAX,0));
+    let mut machine = OwnedState::builder().memory(8).byte_order(ByteOrder::BigEndian)
+	.registers(2,Word).register_names(&["a","b"]).stack(StackDirection::Descending)
+	.features(1).build().unwrap();
+    assert_eq!((machine.sp,machine.features,machine.memory.len()),(8,1,8));
+    assert_eq!(machine.registers.len(),2);
+    assert_eq!(machine.registers.width(),Word);
+    machine.with_state(|s| s.data.write::<u16>(0,0x1234));
+    assert_eq!(machine.memory[..2],[0x12,0x34]);
+}
+
+#[test]
+fn test_config_02() {
+    use virmin::machine::{ConfigError,OwnedState};
+    assert_eq!(OwnedState::builder().pc_bits(0).build().err(),Some(ConfigError::PcBits(0)));
+    assert_eq!(OwnedState::builder().pc_bits(usize::BITS+1).build().err(),Some(ConfigError::PcBits(usize::BITS+1)));
+    let error = OwnedState::builder().register_names(&["a"]).build().err().unwrap();
+    assert_eq!(error,ConfigError::RegisterNames{registers:0,names:1});
+    assert_eq!(error.to_string(),"1 register names given for 0 registers");
+}
+
+#[test]
+fn test_config_03() {
+    use virmin::machine::{OwnedState,Trap};
+    config_with(|program| {
+	let build = |trap| OwnedState::builder().memory(8).registers(3,Word).trap(trap).build().unwrap();
+	// Out of bounds load faults
+	let mut machine = build(Trap::Error);
+	assert!(machine.run(program,10).is_err());
+	assert_eq!((machine.pc,machine.registers.read(0)),(1,5));
+	// Out of bounds load is skipped
+	let mut machine = build(Trap::Skip);
+	assert_eq!(machine.run(program,10),Ok(3));
+	assert_eq!((machine.pc,machine.registers.read(1),machine.registers.read(2)),(3,0,6));
+	// Out of bounds load halts
+	let mut machine = build(Trap::Halt);
+	assert_eq!(machine.run(program,10),Ok(1));
+	assert_eq!(machine.pc,1);
+    });
+}
+
+#[test]
+fn test_config_04() {
+    use virmin::machine::OwnedState;
+    config_with(|program| {
+	// The pc wraps around to the start of the program
+	let mut machine = OwnedState::builder().memory(16).registers(3,Word).pc_bits(1).build().unwrap();
+	assert_eq!(machine.config.pc_mask(),1);
+	machine.pc = 1;
+	machine.memory[7] = 1;
+	machine.step(program).unwrap();
+	assert_eq!((machine.pc,machine.registers.read(1)),(0,1));
+	machine.step(program).unwrap();
+	assert_eq!((machine.pc,machine.registers.read(0)),(1,5));
+    });
+}
+
+// =====================================================
+// Forking
+// =====================================================
+
+#[test]
+fn test_fork_01() {
+    use virmin::machine::{Fork,OwnedState};
+    let mut machine = OwnedState::builder().memory(4).registers(2,Word).build().unwrap();
+    machine.memory[0] = 1;
+    let copy = machine.clone();
+    let root = Fork::from(machine);
+    assert!(!root.is_shared());
+    let mut left = root.fork();
+    let right = root.fork();
+    assert!(root.is_shared() && left.is_shared());
+    // Modifying a branch copies its memory
+    left.memory_mut()[0] = 2;
+    left.registers.write(1,3);
+    assert!(!left.is_shared());
+    assert_eq!((left.memory(),root.memory()),(&[2,0,0,0][..],&[1,0,0,0][..]));
+    assert_eq!(root.registers.read(1),0);
+    drop(root);
+    assert_eq!(right.into_owned(),copy);
+}
+
+#[test]
+fn test_fork_02() {
+    use virmin::machine::{Fork,State};
+    config_with(|program| {
+	let mut bytes = [0u8;16];
+	bytes[7] = 9;
+	let mut state = State::new(0,&mut bytes).with_registers(RegisterFile::new(3,Word));
+	state.try_step(program).unwrap();
+	let root = Fork::of(&state);
+	let mut left = root.fork();
+	assert_eq!(left.run(program,10),Ok(2));
+	assert_eq!((left.pc,left.registers.read(0),left.registers.read(1),left.registers.read(2)),(3,5,9,6));
+	let mut right = root.fork();
+	right.pc = 2;
+	assert_eq!(right.run(program,10),Ok(1));
+	assert_eq!(right.registers.read(1),0);
+	assert_eq!(root.pc,1);
+    });
+}
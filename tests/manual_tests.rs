@@ -0,0 +1,90 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::insn::{Category,Format,FieldKind,Instruction,InstructionSet,Metadata};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+use virmin::manual::{BitField,Manual,OpcodeMap,OperandInfo};
+
+// =====================================================
+// Manual
+// =====================================================
+
+fn field(msb: usize, lsb: usize, label: &str) -> BitField {
+    BitField{msb,lsb,label:label.to_string()}
+}
+
+#[test]
+fn test_manual_01() {
+    let fmt = Format::builder().label("rr").width_bytes(1).opcode_bits(2).register("rd",3).register("rs",2).build().ok().unwrap();
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let meta = Metadata::new().category(Category::Move).description("Copy <register>");
+    let insns = [Instruction::new("nop", &fmt, &[]),
+		 Instruction::new("mov", &fmt, &mc).with_metadata(meta)];
+    let isa = InstructionSet::new(&insns);
+    let manual = Manual::new(&isa);
+    assert_eq!(manual.entries.len(),2);
+    let mov = &manual.entries[1];
+    assert_eq!(mov.opcode,1);
+    assert_eq!(mov.encoding,vec![field(7,7,"-"),field(6,5,"rs"),field(4,2,"rd"),field(1,0,"01")]);
+    assert_eq!(mov.operands[0],OperandInfo{name:"rd".to_string(),kind:FieldKind::Register,bits:3,extension:false});
+    assert_eq!(mov.semantics,vec!["M[rd] := M[rs] (8 bits)".to_string()]);
+    let md = manual.markdown();
+    assert!(md.contains("## mov\n\nCopy <register>\n\nFormat `rr`, opcode 1, category Move.\n\n"));
+    assert!(md.contains("| 7 | 6:5 | 4:2 | 1:0 |\n|---|---|---|---|\n| - | rs | rd | 01 |\n"));
+    assert!(md.contains("- `rs`: register (2 bits)\n"));
+    assert!(md.contains("```text\nM[rd] := M[rs] (8 bits)\n```\n"));
+    let html = manual.html();
+    assert!(html.contains("<h2 id=\"mov\">mov</h2>\n<p>Copy &lt;register&gt;</p>\n"));
+    assert!(html.contains("<tr><td>-</td><td>rs</td><td>rd</td><td>01</td></tr>"));
+}
+
+#[test]
+fn test_manual_02() {
+    let fmt = Format::builder().label("ri").width_bytes(1).opcode_bits(4).immediate("",4).extension("imm",2,FieldKind::SignedImmediate).build().ok().unwrap();
+    let mc = [Load(Var(0),Const(3),Byte),Jump(Var(1))];
+    let insns = [Instruction::new("jmp", &fmt, &mc)];
+    let manual = Manual::new(&InstructionSet::new(&insns));
+    let jmp = &manual.entries[0];
+    assert_eq!(jmp.encoding,vec![field(7,4,"op0"),field(3,0,"0000")]);
+    assert!(jmp.operands[1].extension);
+    assert_eq!(jmp.semantics,vec!["M[op0] := 3 (8 bits)".to_string(),"pc := pc + imm".to_string()]);
+    assert!(manual.markdown().contains("- `imm`: signed immediate (16 bits, extension word)\n"));
+}
+
+#[test]
+fn test_opcode_map_01() {
+    let fmt1 = Format::new(ONE_BYTE,"rr",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"i",TWO_BITS, &[SIX_BITS]);
+    let insns = [Instruction::new("mov", &fmt1, &[]),
+		 Instruction::new("ldi", &fmt2, &[]),
+		 Instruction::new("add", &fmt1, &[])];
+    let map = OpcodeMap::new(&InstructionSet::new(&insns));
+    assert_eq!(map.pages.len(),2);
+    assert_eq!(map.pages[0].columns,2);
+    assert_eq!(map.pages[0].get(2),Some("add"));
+    assert_eq!(map.pages[0].get(1),None);
+    assert_eq!(map.pages[1].get(1),Some("ldi"));
+    assert_eq!(map.to_string(),["rr (2 bit opcode)",
+				"   0   1",
+				"0  mov -",
+				"2  add -",
+				"",
+				"i (2 bit opcode)",
+				"   0   1",
+				"0  -   ldi",
+				"2  -   -",
+				""].join("\n"));
+}
+
+#[test]
+fn test_opcode_map_02() {
+    let fmt = Format::new(TWO_BYTES,"fmt",EIGHT_BITS, &[]);
+    let insns = [Instruction::new("nop", &fmt, &[])];
+    let map = OpcodeMap::new(&InstructionSet::new(&insns));
+    let page = &map.pages[0];
+    assert_eq!((page.columns,page.rows.len()),(16,16));
+    let text = map.to_string();
+    assert!(text.starts_with("fmt (8 bit opcode)\n    0   1   2   3"));
+    assert!(text.contains("\nF0  -   -   -"));
+}
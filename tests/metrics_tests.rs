@@ -0,0 +1,47 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::{MachineError,State};
+use virmin::machine::Width::Byte;
+use virmin::metrics::{self,Counters,Metrics};
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Metrics
+// =====================================================
+
+#[test]
+fn test_metrics_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,0]).unwrap();
+    program.push("mov",&[2,1]).unwrap();
+    program.push("mov",&[6,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [3u8,0,0,0];
+    let mut state = State::new(0,&mut data);
+    let mut metrics = Metrics::new();
+    assert_eq!(metrics.run(&mut state,&decoded,1),Ok(1));
+    assert!(matches!(metrics.run(&mut state,&decoded,10),Err(MachineError::Memory{pc:2,..})));
+    let counters = metrics.collect(&decoded);
+    assert_eq!(counters,Counters{instructions:2,faults:1,fuel:3,cache_hits:2,cache_misses:1});
+    let mut total = counters;
+    total += counters;
+    assert_eq!(total.fuel,6);
+    metrics.reset();
+    assert_eq!(metrics.counters(),&Counters::default());
+}
+
+#[test]
+fn test_metrics_02() {
+    let a = Counters{instructions:5,..Counters::default()};
+    let text = metrics::prometheus(&[(&[("vm","a")],a),(&[("vm","b\"")],Counters::default())]);
+    assert!(text.starts_with("# HELP virmin_instructions_total Instructions executed.\n# TYPE virmin_instructions_total counter\n"));
+    assert!(text.contains("virmin_instructions_total{vm=\"a\"} 5\nvirmin_instructions_total{vm=\"b\\\"\"} 0\n"));
+    assert_eq!(text.lines().count(),5 * 4);
+}
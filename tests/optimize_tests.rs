@@ -0,0 +1,156 @@
+use virmin::domain::{ONE_BYTE,THREE_BITS,TWO_BITS};
+use virmin::insn::{ByteOrder,Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode;
+use virmin::insn::Operand::{Const,Var};
+use virmin::machine::{AluOp,MicroCode,MicroCode::*,State};
+use virmin::machine::Width::{Byte,DoubleWord,Word};
+use virmin::optimize::Optimizer;
+use virmin::program::{DecodedProgram,Program};
+use virmin::verify::check_microcode_equivalent;
+
+const VALUES : [u8;3] = [0x00,0x01,0xFF];
+
+/// Optimize some microcode, checking the result is equivalent.
+fn optimize(microcode: &[MicroCode]) -> Vec<MicroCode> {
+    let optimized = Optimizer::new(4).optimize(microcode);
+    assert!(check_microcode_equivalent(microcode,&optimized,4,&VALUES).unwrap() > 0);
+    optimized
+}
+
+// =====================================================
+// Copies
+// =====================================================
+
+#[test]
+fn test_optimize_01() {
+    assert_eq!(optimize(&[Copy(1,1,Byte),Load(0,1,Byte)]),vec![Load(0,1,Byte)]);
+    assert_eq!(optimize(&[Copy(0,2,Word),Copy(2,0,Word)]),vec![Copy(0,2,Word)]);
+    assert_eq!(optimize(&[Copy(0,1,Byte),Add(2,3,Byte),Copy(0,1,Byte)]),vec![Copy(0,1,Byte),Add(2,3,Byte)]);
+    // Overwritten in between
+    let mc = [Copy(0,1,Byte),Add(1,0,Byte),Copy(0,1,Byte)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+    // Overlapping locations
+    let mc = [Copy(0,1,Word),Copy(0,1,Word)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+}
+
+#[test]
+fn test_optimize_02() {
+    // Copies which could fault are retained
+    let mc = [Copy(8,8,Byte)];
+    assert_eq!(Optimizer::new(4).optimize(&mc),mc.to_vec());
+    let mc = [RegCopy(1,1)];
+    assert_eq!(Optimizer::new(4).optimize(&mc),mc.to_vec());
+}
+
+// =====================================================
+// Constants
+// =====================================================
+
+#[test]
+fn test_optimize_03() {
+    assert_eq!(optimize(&[Load(0,1,Byte),Load(1,0xFF,Byte),Add(0,1,Byte)]),vec![Load(0,0xFF00,Word)]);
+    assert_eq!(optimize(&[Load(0,3,Byte),Add(0,0,Byte),Add(2,0,Byte)]),vec![Load(0,6,Byte),Add(2,0,Byte)]);
+    assert_eq!(optimize(&[Load(2,0x1234,Word),Copy(0,2,Word)]),vec![Load(0,0x12341234,DoubleWord)]);
+    assert_eq!(optimize(&[Load(0,3,Byte),Load(1,5,Byte),Alu(AluOp::Sub,0,1,Byte)]),vec![Load(0,0x05FE,Word)]);
+    // Unknown operand
+    let mc = [Load(0,1,Byte),Add(0,1,Byte)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+    // Partially overwritten constant
+    let mc = [Load(0,1,Word),Copy(1,3,Byte),Add(2,0,Word)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+}
+
+// =====================================================
+// Dead writes
+// =====================================================
+
+#[test]
+fn test_optimize_04() {
+    assert_eq!(optimize(&[Load(1,5,Byte),Copy(0,2,Word)]),vec![Copy(0,2,Word)]);
+    assert_eq!(optimize(&[Copy(0,3,Byte),Load(0,7,Word)]),vec![Load(0,7,Word)]);
+    // Read before being overwritten
+    let mc = [Copy(1,3,Byte),Copy(0,1,Byte),Load(1,6,Byte)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+    let mc = [Copy(1,3,Byte),Alu(AluOp::Xor,0,1,Byte),Load(1,6,Byte)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+    // Not completely overwritten
+    let mc = [Copy(1,2,Word),Load(0,1,Word)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+}
+
+// =====================================================
+// Merging
+// =====================================================
+
+#[test]
+fn test_optimize_05() {
+    assert_eq!(optimize(&[Load(0,0x12,Byte),Load(1,0x34,Byte)]),vec![Load(0,0x3412,Word)]);
+    assert_eq!(optimize(&[Load(1,0x34,Byte),Load(0,0x12,Byte)]),vec![Load(0,0x3412,Word)]);
+    assert_eq!(optimize(&[Load(0,1,Byte),Load(1,2,Byte),Load(2,3,Byte),Load(3,0x104,Byte)]),vec![Load(0,0x04030201,DoubleWord)]);
+    assert_eq!(optimize(&[Copy(0,2,Byte),Copy(1,3,Byte)]),vec![Copy(0,2,Word)]);
+    // Second copy reads what first writes
+    let mc = [Copy(1,0,Byte),Copy(2,1,Byte)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+    // Different widths
+    let mc = [Load(0,1,Byte),Load(1,2,Word)];
+    assert_eq!(optimize(&mc),mc.to_vec());
+}
+
+#[test]
+fn test_optimize_06() {
+    let optimizer = Optimizer::new(4).byte_order(ByteOrder::BigEndian);
+    assert_eq!(optimizer.optimize(&[Load(0,0x12,Byte),Load(1,0x34,Byte)]),vec![Load(0,0x1234,Word)]);
+    assert_eq!(optimizer.optimize(&[Load(1,0x34,Byte),Load(0,0x12,Byte)]),vec![Load(0,0x1234,Word)]);
+    let mut bytes = [0u8;4];
+    let mut state = State::new(0,&mut bytes).with_byte_order(ByteOrder::BigEndian);
+    state.execute_all(&optimizer.optimize(&[Load(0,1,Byte),Load(1,2,Byte),Load(2,3,Byte),Load(3,4,Byte)]));
+    assert_eq!(bytes,[1,2,3,4]);
+}
+
+// =====================================================
+// Skips
+// =====================================================
+
+#[test]
+fn test_optimize_07() {
+    // Rewrites do not cross skips, which are adjusted
+    let mc = [Copy(1,1,Byte),SkipIfZero(0,Byte,3),Load(2,1,Byte),Load(3,2,Byte),Skip(1),Load(2,3,Byte),Load(3,4,Byte)];
+    assert_eq!(optimize(&mc),vec![SkipIfZero(0,Byte,2),Load(2,0x201,Word),Skip(1),Load(2,3,Byte),Load(3,4,Byte)]);
+    // Nor do they cross indirect accesses
+    let mc = [Load(0,1,Byte),RegFetchIndirect(0,1,Byte),Load(0,2,Byte)];
+    assert_eq!(Optimizer::new(4).optimize(&mc),mc.to_vec());
+    // The microcode skipped to can be removed
+    let mc = [SkipIfZero(0,Byte,1),Load(1,1,Byte),Copy(2,2,Byte),Load(3,1,Byte)];
+    assert_eq!(optimize(&mc),vec![SkipIfZero(0,Byte,1),Load(1,1,Byte),Load(3,1,Byte)]);
+    // Beyond the end
+    let mc = [SkipIfZero(0,Byte,7),Copy(1,1,Byte)];
+    assert_eq!(optimize(&mc),vec![SkipIfZero(0,Byte,0)]);
+}
+
+// =====================================================
+// Programs
+// =====================================================
+
+#[test]
+fn test_optimize_08() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS,&[THREE_BITS,THREE_BITS]);
+    let mc1 = [AbstractMicroCode::Load(Var(0),Const(1),Byte),AbstractMicroCode::Load(Var(1),Const(2),Byte),AbstractMicroCode::Add(Var(0),Var(1),Byte)];
+    let mc2 = [AbstractMicroCode::Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("ld3",&fmt,&mc1),Instruction::new("mov",&fmt,&mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("ld3",&[0,1]).unwrap();
+    program.push("mov",&[2,2]).unwrap();
+    program.push("mov",&[3,0]).unwrap();
+    let mut decoded = DecodedProgram::new(&isa,program.bytes());
+    assert!(decoded.quicken(1));
+    assert_eq!(decoded.optimize(&Optimizer::new(4)),3);
+    assert_eq!(decoded.get(0).unwrap().microcode.iter().map(|t| t.code()).collect::<Vec<_>>(),vec![Load(0,0x0203,Word)]);
+    assert!(decoded.get(1).unwrap().microcode.is_empty());
+    assert!(!decoded.get(1).unwrap().quick);
+    let mut bytes = [0u8;4];
+    let mut state = State::new(0,&mut bytes);
+    assert_eq!(state.run(&decoded,10),Ok(3));
+    assert_eq!(bytes,[3,2,0,3]);
+}
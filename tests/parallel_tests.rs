@@ -0,0 +1,45 @@
+#![cfg(feature="parallel")]
+use virmin::disasm::Disassembler;
+use virmin::domain::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Parallel Decoding
+// =====================================================
+
+#[test]
+fn test_parallel_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(TWO_BYTES,"fmt2",TWO_BITS, &[FOUR_BITS,TEN_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("ldi", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    for i in 0..50 {
+	if i % 3 == 0 {
+	    program.push("ldi",&[i % 16,i]).unwrap();
+	} else {
+	    program.push("mov",&[i % 8,(i + 1) % 8]).unwrap();
+	}
+    }
+    let mut image = program.bytes().to_vec();
+    image.extend([0xFF,0xFF]);
+    let expected = DecodedProgram::new(&isa,&image);
+    let disasm = Disassembler::new(&isa);
+    let listing = disasm.disassemble(&image,0x100);
+    // Boundaries both within and between instructions
+    for boundaries in [vec![],vec![1,2,3],vec![40,10,100],(0..image.len()).step_by(7).collect()] {
+	let decoded = DecodedProgram::new_parallel(&isa,&image,&boundaries);
+	assert_eq!(decoded.len(),expected.len());
+	for pc in 0..expected.len() {
+	    assert_eq!(decoded.get(pc),expected.get(pc));
+	}
+	assert_eq!(disasm.disassemble_parallel(&image,0x100,&boundaries),listing);
+    }
+}
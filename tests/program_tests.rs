@@ -0,0 +1,247 @@
+use virmin::domain::*;
+use virmin::insn::{DecodeError,EncodeError,Format,Instruction,InstructionSet,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::*;
+use virmin::machine::{MachineError,Memory,MicroCode,State,Threaded};
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+
+// =====================================================
+// Program
+// =====================================================
+
+#[test]
+fn test_program_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(TWO_BYTES,"fmt2",TWO_BITS, &[FOUR_BITS,TEN_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("ldi", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    assert_eq!(program.push("ldi",&[1,2]),Ok(0));
+    assert_eq!(program.push("mov",&[1,2]),Ok(1));
+    assert_eq!(program.push("ldi",&[3,4]),Ok(2));
+    assert_eq!(program.len(),3);
+    assert_eq!(program.offsets(),&[0,2,3]);
+    assert_eq!(program.pc(3),Some(2));
+    assert_eq!(program.pc(1),None);
+    assert_eq!(program.bytes(),&[0x85,0x00,0x44,0x0D,0x01]);
+}
+
+#[test]
+fn test_program_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    assert_eq!(program.push("add",&[1,2]),Err(EncodeError::UnknownMnemonic("add".to_string())));
+    assert_eq!(program.push("mov",&[1,8]),Err(EncodeError::OutOfRange{operand:1,value:8}));
+    assert!(program.is_empty());
+}
+
+#[test]
+fn test_program_03() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,2]).unwrap();
+    program.push("mov",&[3,4]).unwrap();
+    let mut bytes = [0u8;4];
+    let mut memory = Memory::new(&mut bytes);
+    program.load(&mut memory,1);
+    assert_eq!(bytes,[0,0x44,0x8C,0]);
+}
+
+// =====================================================
+// Decoded Program
+// =====================================================
+
+#[test]
+fn test_decoded_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(7),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc1),
+		 Instruction::new("ldi", &fmt, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("ldi",&[0,0]).unwrap();
+    program.push("mov",&[1,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    assert_eq!(decoded.len(),2);
+    assert_eq!(decoded.get(1).unwrap().operands,vec![1,0]);
+    assert_eq!(decoded.get(1).unwrap().microcode,vec![Threaded::new(MicroCode::Copy(1,0,Byte))]);
+    let mut data = [0u8;4];
+    let mut state = State::new(0,&mut data);
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(state.step(&decoded),Err(MachineError::Decode{pc:2,error:DecodeError::OutOfBounds(2)}));
+    assert_eq!(state.pc,2);
+    assert_eq!(data,[7,7,0,0]);
+}
+
+#[test]
+fn test_decoded_04() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(7),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc1),
+		 Instruction::new("ldi", &fmt, &mc2).requires(3)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(isa.instruction(1).feature(),Some(3));
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,0]).unwrap();
+    program.push("ldi",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [0u8,1,0,0];
+    let mut state = State::new(0,&mut data).with_features(0b0111);
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(state.step(&decoded),Err(MachineError::Decode{pc:1,error:DecodeError::Illegal{pc:1,feature:3}}));
+    assert_eq!(state.pc,1);
+    state.features |= 1 << 3;
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(data,[7,0,0,0]);
+}
+
+#[test]
+fn test_decoded_02() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(TWO_BYTES,"fmt2",TWO_BITS, &[FOUR_BITS,TEN_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("ldi", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,2]).unwrap();
+    program.push("mov",&[3,4]).unwrap();
+    program.push("mov",&[5,6]).unwrap();
+    let mut image = program.bytes().to_vec();
+    let mut decoded = DecodedProgram::new(&isa,&image);
+    assert_eq!(decoded.pc(2),Some(2));
+    // Overwrite second instruction with a wider one
+    let bytes = isa.encode("ldi",&[1,0]).unwrap();
+    image[1] = bytes[0];
+    image[2] = bytes[1];
+    decoded.invalidate(1,2);
+    assert!(decoded.is_stale());
+    assert!(decoded.get(0).is_ok());
+    assert_eq!(decoded.get(1),Err(DecodeError::Stale(1)));
+    assert_eq!(decoded.get(2),Err(DecodeError::Stale(2)));
+    decoded.refresh(&image);
+    assert_eq!(decoded.len(),2);
+    assert_eq!(decoded.get(1).unwrap().insn,1);
+    assert_eq!(decoded.get(1).unwrap().length,2);
+    assert_eq!(decoded.get(1).unwrap().operands,vec![1,0]);
+    assert_eq!(decoded.get(1).unwrap().microcode,vec![Threaded::new(MicroCode::Load(1,0,Byte))]);
+    assert_eq!(decoded.get(0).unwrap().operands,vec![1,2]);
+}
+
+#[test]
+fn test_decoded_03() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let decoded = DecodedProgram::new(&isa,&[0x00,0x01,0x04]);
+    assert_eq!(decoded.len(),3);
+    assert_eq!(decoded.get(1),Err(DecodeError::Unknown));
+    assert_eq!(decoded.get(2).unwrap().operands,vec![1,0]);
+}
+
+#[test]
+fn test_decoded_05() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(7),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc1),
+		 Instruction::new("ldi", &fmt, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("ldi",&[0,0]).unwrap();
+    program.push("mov",&[1,0]).unwrap();
+    program.push("mov",&[2,1]).unwrap();
+    let mut decoded = DecodedProgram::new(&isa,program.bytes());
+    // Fuse ldi followed by mov
+    assert_eq!(decoded.fuse(&[(1,0)]),1);
+    assert!(decoded.get(0).unwrap().fused);
+    assert!(!decoded.get(1).unwrap().fused);
+    let mut data = [0u8;4];
+    let mut state = State::new(0,&mut data);
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(state.pc,2);
+    assert_eq!(state.step(&decoded),Ok(()));
+    assert_eq!(state.pc,3);
+    assert_eq!(data,[7,7,7,0]);
+    // Invalidating the program undoes fusion
+    decoded.invalidate(2,1);
+    assert!(!decoded.get(0).unwrap().fused);
+}
+
+#[test]
+fn test_decoded_06() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Add(Var(0),Var(1),Byte)];
+    let mc2 = [Copy(Var(0),Var(1),Byte)];
+    let mc3 = [If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])];
+    let insns = [Instruction::new("add", &fmt, &mc1),
+		 Instruction::new("mov", &fmt, &mc2),
+		 Instruction::new("jnz", &fmt, &mc3)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("add",&[0,1]).unwrap();
+    program.push("mov",&[2,0]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let mut image = program.bytes().to_vec();
+    image.push(0xFF);
+    let decoded = DecodedProgram::new(&isa,&image);
+    let mut data = [0u8,0xFF,0,0];
+    let mut state = State::new(0,&mut data);
+    // Each block ends at the taken branch
+    assert_eq!(state.run_block(&decoded,10),Ok(3));
+    assert_eq!(state.pc,0);
+    assert_eq!(state.run_block(&decoded,2),Ok(2));
+    assert_eq!(state.pc,2);
+    assert_eq!(state.run_block(&decoded,10),Ok(1));
+    // Stops before the undecodable byte
+    state.data.write_u8(0,1);
+    assert_eq!(state.run_block(&decoded,10),Ok(3));
+    assert_eq!(state.pc,3);
+    assert_eq!(state.run_block(&decoded,10),Err(MachineError::Decode{pc:3,error:DecodeError::Unknown}));
+    assert_eq!(state.run_block(&decoded,0),Ok(0));
+    // Accesses out of bounds fault, rather than panic
+    let mut data = [0u8;2];
+    let mut state = State::new(1,&mut data);
+    assert_eq!(state.step(&decoded),Err(MachineError::Memory{pc:1,address:2,length:1}));
+    assert_eq!(state.run_block(&decoded,10),Err(MachineError::Memory{pc:1,address:2,length:1}));
+    state.pc = 0;
+    assert_eq!(state.run_block(&decoded,10),Ok(1));
+}
+
+#[test]
+fn test_decoded_07() {
+    use virmin::machine::MachineError;
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc1)];
+    let isa = InstructionSet::new(&insns);
+    let mut program = Program::new(&isa);
+    program.push("mov",&[1,0]).unwrap();
+    program.push("mov",&[6,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut data = [3u8,0,0,0];
+    let mut state = State::new(0,&mut data);
+    assert_eq!(state.run(&decoded,10),Err(MachineError::Memory{pc:1,address:6,length:1}));
+    assert_eq!(state.pc,1);
+    let err = state.try_step(&decoded).unwrap_err();
+    assert_eq!(err.to_string(),"instruction at pc 1 accesses memory 0x6..0x7 out of bounds");
+    state.pc = 2;
+    assert_eq!(state.try_step(&decoded),Err(MachineError::Decode{pc:2,error:DecodeError::OutOfBounds(2)}));
+    assert_eq!(data,[3,3,0,0]);
+}
@@ -0,0 +1,63 @@
+#![cfg(feature="python")]
+use pyo3::prelude::*;
+use pyo3::ffi::c_str;
+
+// =====================================================
+// Python Bindings
+// =====================================================
+
+#[test]
+fn test_python_01() {
+    virmin::python::append_to_inittab();
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+	py.run(c_str!(r#"
+import virmin
+isa = virmin.InstructionSet.from_toml('''
+[[formats]]
+name = "rr"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "rs", bits = 3 } ]
+
+[[formats]]
+name = "ri"
+width = 2
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "imm", bits = 11, kind = "Immediate" } ]
+
+[[instructions]]
+mnemonic = "add"
+format = "rr"
+semantics = [ { Add = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+
+[[instructions]]
+mnemonic = "ldi"
+format = "ri"
+semantics = [ { Load = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+''')
+assert isa.mnemonics() == ["add", "ldi"]
+image = isa.assemble("ldi r1, 5\nldi r2, 7\nadd r1, r2\n")
+assert len(image) == 5
+m = virmin.Machine(isa, image, memory=4)
+m.step()
+assert m.pc == 1 and m.read(1, 1) == b"\x05"
+assert m.run() == 2 and m.halted
+assert m.memory() == b"\x00\x0c\x07\x00"
+m.write(0, b"\x2a")
+assert m.read(0, 2) == b"\x2a\x0c"
+m.write_register(virmin.SP, 3)
+assert m.read_register(virmin.SP) == 3
+try:
+    m.read(3, 2)
+    assert False
+except IndexError:
+    pass
+try:
+    virmin.InstructionSet.from_toml("[[formats]]")
+    assert False
+except ValueError:
+    pass
+"#),None,None).unwrap();
+    });
+}
@@ -0,0 +1,46 @@
+#![cfg(feature="serde")]
+use virmin::domain::*;
+use virmin::insn::{Format,Instruction,InstructionSet,PseudoInstruction};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+
+// =====================================================
+// Serialization
+// =====================================================
+
+#[test]
+fn test_serde_01() {
+    let fmt = Format::new(ONE_BYTE,"fmt",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    let insns = [Instruction::new("mov", &fmt, &mc), Instruction::new("swp", &fmt, &[])];
+    let pseudos = [PseudoInstruction::new("nop", &[("mov",&[Const(0),Const(0)])])];
+    let isa = InstructionSet::new(&insns).with_pseudos(&pseudos);
+    let json = serde_json::to_string(&isa).unwrap();
+    let copy : InstructionSet = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy.len(),2);
+    assert_eq!(copy.pseudos().len(),1);
+    assert!(copy.instruction(0).semantic() == mc);
+    assert!(copy.instruction(1).format() == &fmt);
+    assert_eq!(copy.encode("swp",&[1,2]),isa.encode("swp",&[1,2]));
+    assert_eq!(serde_json::to_string(&copy).unwrap(),json);
+}
+
+#[test]
+fn test_serde_02() {
+    let fmt = Format::new(ONE_BYTE,"fmt",ONE_BITS, &[]);
+    let insns = [Instruction::new("a", &fmt, &[]), Instruction::new("a", &fmt, &[])];
+    let json = serde_json::to_string(&InstructionSet::new(&insns)).unwrap();
+    let err = serde_json::from_str::<InstructionSet>(&json).err().unwrap();
+    assert_eq!(err.to_string(),"duplicate mnemonic \"a\"");
+}
+
+#[test]
+fn test_serde_03() {
+    let fmt = Format::new(ONE_BYTE,"fmt",ONE_BITS, &[]);
+    let insns = [Instruction::new("a", &fmt, &[]), Instruction::new("b", &fmt, &[])];
+    let json = serde_json::to_string(&InstructionSet::new(&insns)).unwrap();
+    let json = json.replace("\"opcodes\":[0,1]","\"opcodes\":[0,17179869184]");
+    let err = serde_json::from_str::<InstructionSet>(&json).err().unwrap();
+    assert_eq!(err.to_string(),"opcode 17179869184 of \"b\" does not fit in 1 bit(s)");
+}
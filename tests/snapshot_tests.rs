@@ -0,0 +1,57 @@
+#![cfg(feature="serde")]
+use virmin::machine::{OwnedState,RegisterFile,State};
+use virmin::machine::Width::Word;
+use virmin::snapshot::{Snapshot,SnapshotError,VERSION};
+
+// =====================================================
+// Snapshots
+// =====================================================
+
+#[test]
+fn test_snapshot_01() {
+    let mut bytes = [1u8,2,3,4];
+    let registers = RegisterFile::new(2,Word).with_names(&["a","b"]);
+    let mut state = State::new(3,&mut bytes).with_registers(registers);
+    state.registers.write(1,0x1234);
+    state.flags = 5;
+    let snapshot = Snapshot::of(&state).with_fuel(100);
+    let copy = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+    assert_eq!(copy,snapshot);
+    // Resume in a fresh machine
+    let mut data = [0u8;4];
+    let mut other = State::new(0,&mut data);
+    copy.restore(&mut other).unwrap();
+    assert_eq!((other.pc,other.flags,other.registers.get("b")),(3,5,Some(0x1234)));
+    assert_eq!(data,[1,2,3,4]);
+    let owned = copy.to_owned_state();
+    assert_eq!(Snapshot::of_owned(&owned).with_fuel(100),snapshot);
+}
+
+#[test]
+fn test_snapshot_02() {
+    let mut state = OwnedState::new(2);
+    state.memory[1] = 7;
+    state.sp = 1;
+    let snapshot = Snapshot::of_owned(&state);
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let copy : Snapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy,snapshot);
+    assert_eq!(copy.fuel,None);
+    let json = json.replacen(&format!("\"version\":{}",VERSION),"\"version\":99",1);
+    let err = serde_json::from_str::<Snapshot>(&json).err().unwrap();
+    assert!(err.to_string().starts_with("unsupported snapshot version 99"));
+}
+
+#[test]
+fn test_snapshot_03() {
+    let bytes = Snapshot::of_owned(&OwnedState::new(4)).to_bytes();
+    assert_eq!(Snapshot::from_bytes(&bytes[..bytes.len()-1]),Err(SnapshotError::Truncated));
+    assert_eq!(Snapshot::from_bytes(b"ELF!"),Err(SnapshotError::BadMagic));
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(Snapshot::from_bytes(&newer),Err(SnapshotError::Version(2)));
+    let mut data = [0u8;2];
+    let mut state = State::new(0,&mut data);
+    let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(snapshot.restore(&mut state),Err(SnapshotError::MemorySize{expected:2,actual:4}));
+}
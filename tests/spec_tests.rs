@@ -0,0 +1,91 @@
+#![cfg(feature="spec")]
+use virmin::insn::{Category,FieldKind,FormatError,IsaError};
+use virmin::spec::{self,SpecError};
+
+// =====================================================
+// Specifications
+// =====================================================
+
+const SPEC_01 : &str = r#"
+[[formats]]
+name = "rr"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "rs", bits = 3 } ]
+
+[[formats]]
+name = "ri"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 } ]
+extensions = [ { name = "imm", bytes = 2, kind = "SignedImmediate" } ]
+
+[[instructions]]
+mnemonic = "mov"
+format = "rr"
+semantics = [ { Copy = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+metadata = { category = "Move", description = "Copy register" }
+
+[[instructions]]
+mnemonic = "ldi"
+format = "ri"
+
+[[pseudos]]
+mnemonic = "nop"
+expansion = [ [ "mov", [ { Const = 0 }, { Const = 0 } ] ] ]
+"#;
+
+#[test]
+fn test_spec_01() {
+    let isa = spec::from_toml(SPEC_01).ok().unwrap();
+    assert_eq!(isa.len(),2);
+    assert_eq!(isa.pseudos().len(),1);
+    let ldi = isa.get("ldi").unwrap();
+    assert_eq!(ldi.format().label(),"ri");
+    assert_eq!(ldi.format().operands()[1].kind(),FieldKind::SignedImmediate);
+    assert_eq!(isa.get("mov").unwrap().metadata().category,Some(Category::Move));
+    assert_eq!(isa.get("ldi").unwrap().metadata().description,None);
+    assert_eq!(isa.encode("mov",&[1,2]),Ok(vec![0x44]));
+    assert_eq!(isa.encode("ldi",&[1,0x1234]),Ok(vec![0x05,0x34,0x12]));
+}
+
+#[test]
+fn test_spec_02() {
+    let json = r#"{
+      "formats": [ { "name": "r", "width": 1, "opcode": 4, "fields": [ { "name": "rd", "bits": 4 } ] } ],
+      "instructions": [ { "mnemonic": "inc", "format": "r" }, { "mnemonic": "dec", "format": "r" } ]
+    }"#;
+    let isa = spec::from_json(json).ok().unwrap();
+    assert_eq!(isa.encode("dec",&[3]),Ok(vec![0x31]));
+}
+
+#[test]
+fn test_spec_03() {
+    let unknown = "[[instructions]]\nmnemonic = \"inc\"\nformat = \"r\"\n";
+    assert_eq!(spec::from_toml(unknown).err(),Some(SpecError::UnknownFormat{mnemonic:"inc".to_string(),format:"r".to_string()}));
+    let malformed = "[[formats]]\nname = \"r\"\nwidth = 1\nopcode = 6\nfields = [ { bits = 3 } ]\n";
+    assert_eq!(spec::from_toml(malformed).err(),Some(SpecError::Format{name:"r".to_string(),error:FormatError::DoesNotFit{required:9,available:8}}));
+    let duplicate = "[[formats]]\nname = \"r\"\nwidth = 1\nopcode = 1\n[[instructions]]\nmnemonic = \"a\"\nformat = \"r\"\n[[instructions]]\nmnemonic = \"a\"\nformat = \"r\"\n";
+    assert_eq!(spec::from_toml(duplicate).err(),Some(SpecError::Isa(vec![IsaError::DuplicateMnemonic("a".to_string())])));
+    assert!(matches!(spec::from_toml("formats = 1"),Err(SpecError::Parse(_))));
+}
+
+#[test]
+fn test_spec_properties_01() {
+    let text = r#"
+[[formats]]
+name = "rr"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "rs", bits = 3 } ]
+
+[[instructions]]
+mnemonic = "mov"
+format = "rr"
+semantics = [ { Copy = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+properties = [ { name = "copies", pre = "True", post = { Eq = [ { New = [ { Operand = 0 }, "Byte" ] }, { Old = [ { Operand = 1 }, "Byte" ] } ] } } ]
+"#;
+    let isa = spec::from_toml(text).ok().unwrap();
+    assert_eq!(isa.instruction(0).properties().len(),1);
+    assert_eq!(virmin::verify::check_all_properties(&isa,2,&[0,1]),Ok(16));
+}
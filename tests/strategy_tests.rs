@@ -0,0 +1,149 @@
+#![cfg(any(feature="arbitrary",feature="proptest"))]
+use virmin::insn::{Constraint,Format,FieldKind,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::QuadWord;
+use virmin::program::DecodedProgram;
+use virmin::strategy::*;
+
+fn isa_with<F:FnOnce(&InstructionSet)>(f: F) {
+    let fmt1 = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3)
+	.constraint("rd",Constraint::NonZero).build().unwrap();
+    let fmt2 = Format::builder().width_bytes(2).opcode_bits(2).register("rd",4).simmediate("off",10)
+	.constraint("off",Constraint::Aligned(4)).constraint("off",Constraint::Range(-64,63)).build().unwrap();
+    let fmt3 = Format::builder().width_bytes(1).opcode_bits(2).extension("imm",4,FieldKind::Immediate).build().unwrap();
+    let mov = [Copy(Var(0),Var(1),QuadWord)];
+    let jmp = [Jump(SExt(Box::new(Var(1)),10))];
+    let imm = [Copy(Const(0),Var(0),QuadWord)];
+    let insns = [Instruction::new("mov", &fmt1, &mov),
+		 Instruction::new("jmp", &fmt2, &jmp),
+		 Instruction::new("imm", &fmt3, &imm)];
+    f(&InstructionSet::new(&insns))
+}
+
+// =====================================================
+// Encoded
+// =====================================================
+
+#[test]
+fn test_encoded_01() {
+    isa_with(|isa| {
+	let e = Encoded::new(isa,0,&[0,0xFF]).unwrap();
+	assert_eq!(e.operands,vec![1,7]);
+	assert_eq!(isa.decode(&e.bytes),Ok((0,e.operands.clone())));
+	let e = Encoded::new(isa,1,&[3,0x3FF]).unwrap();
+	assert_eq!(e.operands,vec![3,-4isize as usize]);
+	let e = Encoded::new(isa,1,&[3,0x1FF]).unwrap();
+	assert_eq!(e.operands,vec![3,60]);
+	assert_eq!(isa.decode(&e.bytes),Ok((1,e.operands.clone())));
+	assert_eq!(Encoded::new(isa,1,&[3]),None);
+    });
+}
+
+#[test]
+fn test_encoded_02() {
+    isa_with(|isa| {
+	let insns = [Encoded::new(isa,2,&[usize::MAX]).unwrap(),Encoded::new(isa,0,&[2,3]).unwrap()];
+	assert_eq!(insns[0].operands,vec![0xFFFF_FFFF]);
+	let program = assemble(isa,&insns);
+	let bytes : Vec<u8> = insns.iter().flat_map(|e| e.bytes.clone()).collect();
+	assert_eq!(program.bytes(),&bytes[..]);
+    });
+}
+
+// =====================================================
+// Arbitrary
+// =====================================================
+
+#[cfg(feature="arbitrary")]
+mod arbitrary_tests {
+    use arbitrary::{Arbitrary,Unstructured};
+    use super::*;
+
+    fn seeds() -> impl Iterator<Item=Vec<u8>> {
+	(0..256u32).map(|i| (0..64).map(|j| (i.wrapping_mul(2654435761).rotate_left(j) ^ j) as u8).collect())
+    }
+
+    #[test]
+    fn test_arbitrary_01() {
+	for seed in seeds() {
+	    let format = Format::arbitrary(&mut Unstructured::new(&seed)).unwrap();
+	    let operands = vec![0;format.operands().len()];
+	    let bytes = format.encode(1,&operands).unwrap();
+	    assert_eq!(format.decode(&bytes),Ok((1,operands)));
+	}
+    }
+
+    #[test]
+    fn test_arbitrary_02() {
+	for seed in seeds() {
+	    let operand = virmin::insn::Operand::arbitrary(&mut Unstructured::new(&seed)).unwrap();
+	    assert!(operand.arity() <= MAX_VAR + 1);
+	    operand.evaluate(0,&[1,2,3,4]);
+	}
+    }
+
+    #[test]
+    fn test_arbitrary_03() {
+	isa_with(|isa| {
+	    for seed in seeds() {
+		let insns = arbitrary_program(&mut Unstructured::new(&seed),isa,8).unwrap();
+		let program = assemble(isa,&insns);
+		let decoded = DecodedProgram::new(isa,program.bytes());
+		assert_eq!(decoded.len(),insns.len());
+		for (pc,e) in insns.iter().enumerate() {
+		    assert_eq!(decoded.get(pc).unwrap().operands,&e.operands[..]);
+		}
+	    }
+	});
+    }
+}
+
+// =====================================================
+// Proptest
+// =====================================================
+
+#[cfg(feature="proptest")]
+mod proptest_tests {
+    use proptest::prelude::*;
+    use proptest::test_runner::TestRunner;
+    use super::*;
+
+    proptest! {
+	#[test]
+	fn test_proptest_01(format in formats(), opcode in 0usize..2) {
+	    let operands = vec![0;format.operands().len()];
+	    let bytes = format.encode(opcode,&operands).unwrap();
+	    prop_assert_eq!(bytes.len(),format.length());
+	    prop_assert_eq!(format.decode(&bytes),Ok((opcode,operands)));
+	}
+
+	#[test]
+	fn test_proptest_02(operand in operands()) {
+	    prop_assert!(operand.arity() <= MAX_VAR + 1);
+	    operand.evaluate(0,&[1,2,3,4]);
+	}
+    }
+
+    #[test]
+    fn test_proptest_03() {
+	isa_with(|isa| {
+	    TestRunner::default().run(&instructions(isa),|e| {
+		prop_assert_eq!(isa.decode(&e.bytes),Ok((e.index,e.operands)));
+		Ok(())
+	    }).unwrap();
+	});
+    }
+
+    #[test]
+    fn test_proptest_04() {
+	isa_with(|isa| {
+	    TestRunner::default().run(&programs(isa,8),|insns| {
+		prop_assert!(insns.len() <= 8);
+		let program = assemble(isa,&insns);
+		prop_assert_eq!(DecodedProgram::new(isa,program.bytes()).len(),insns.len());
+		Ok(())
+	    }).unwrap();
+	});
+    }
+}
@@ -0,0 +1,119 @@
+#![cfg(feature="std")]
+use virmin::domain::*;
+use virmin::insn::{Format,Instruction,InstructionSet};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+use virmin::testing::*;
+
+// =====================================================
+// Round Tripping
+// =====================================================
+
+#[test]
+fn test_roundtrip_01() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[THREE_BITS,THREE_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("mov", &fmt1, &mc1),
+		 Instruction::new("clr", &fmt2, &mc2)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(check_exhaustive(&isa),Ok(65));
+    assert_eq!(check_random(&isa,10,1),Ok(20));
+    assert_roundtrip(&isa);
+}
+
+#[test]
+fn test_roundtrip_02() {
+    let fmt = Format::builder().width_bytes(4).opcode_bits(8).register("rd",4).simmediate("imm",20).build().ok().unwrap();
+    let mc = [Load(Var(0),Const(0),Byte)];
+    let insns = [Instruction::new("li", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    assert_eq!(roundtrip(&isa,0,&[3,-5isize as usize]),Ok(()));
+    assert_eq!(check_random(&isa,100,42),Ok(100));
+    assert_roundtrip(&isa);
+}
+
+#[test]
+fn test_roundtrip_03() {
+    // Instruction "e" is encoded as [4] which is indistinguishable from
+    // "a" with operand 1.
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[SIX_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("a", &fmt1, &mc),
+		 Instruction::new("b", &fmt2, &mc),
+		 Instruction::new("c", &fmt2, &mc),
+		 Instruction::new("d", &fmt2, &mc),
+		 Instruction::new("e", &fmt2, &mc)];
+    let isa = InstructionSet::new(&insns);
+    let expected = RoundTripError::Mismatch{mnemonic:"e".to_string(),operands:vec![],bytes:vec![4],decoded:("a".to_string(),vec![1])};
+    assert_eq!(check_exhaustive(&isa),Err(expected));
+}
+
+#[test]
+#[should_panic]
+fn test_roundtrip_04() {
+    let fmt1 = Format::new(ONE_BYTE,"fmt1",TWO_BITS, &[SIX_BITS]);
+    let fmt2 = Format::new(ONE_BYTE,"fmt2",EIGHT_BITS, &[]);
+    let mc = [Load(Const(0),Const(0),Byte)];
+    let insns = [Instruction::new("a", &fmt1, &mc),
+		 Instruction::new("b", &fmt2, &mc),
+		 Instruction::new("c", &fmt2, &mc),
+		 Instruction::new("d", &fmt2, &mc),
+		 Instruction::new("e", &fmt2, &mc)];
+    assert_roundtrip(&InstructionSet::new(&insns));
+}
+
+// =====================================================
+// Program Generation
+// =====================================================
+
+#[test]
+fn test_generator_01() {
+    use virmin::machine::State;
+    use virmin::program::DecodedProgram;
+    let rr = Format::builder().width_bytes(2).opcode_bits(2).register("rd",7).register("rs",7).build().ok().unwrap();
+    let ri = Format::builder().width_bytes(2).opcode_bits(2).register("rd",6).simmediate("imm",8).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),virmin::machine::Width::Word)];
+    let mc2 = [Load(Var(0),Const(7),Byte)];
+    let mc3 = [Jump(Var(1))];
+    let mc4 = [Goto(Var(1))];
+    let insns = [Instruction::new("mov", &rr, &mc1),
+		 Instruction::new("ldi", &ri, &mc2),
+		 Instruction::new("jmp", &ri, &mc3),
+		 Instruction::new("goto", &ri, &mc4)];
+    let isa = InstructionSet::new(&insns);
+    let program = Generator::new(&isa,7).memory(16).generate(50).unwrap();
+    assert_eq!(program.len(),50);
+    // Generation is deterministic
+    assert_eq!(Generator::new(&isa,7).memory(16).generate(50).unwrap().bytes(),program.bytes());
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    for pc in 0..decoded.len() {
+	let entry = decoded.get(pc).unwrap();
+	let ops = &entry.operands;
+	match entry.insn {
+	    0 => assert!(ops[0] <= 14 && ops[1] <= 14),
+	    1 => assert!(ops[0] < 16),
+	    2 => assert!((pc as isize + ops[1] as isize) < 50 && (pc as isize + ops[1] as isize) >= 0),
+	    _ => assert!(ops[1] < 50)
+	}
+    }
+    let mut bytes = [0u8;16];
+    let mut state = State::new(0,&mut bytes);
+    for _ in 0..1000 {
+	state.step(&decoded).unwrap();
+    }
+}
+
+#[test]
+fn test_generator_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",6).build().ok().unwrap();
+    let mc = [Load(Var(0),Const(0),virmin::machine::Width::QuadWord)];
+    let insns = [Instruction::new("clr", &fmt, &mc)];
+    let isa = InstructionSet::new(&insns);
+    assert!(Generator::new(&isa,1).memory(8).generate(10).is_some());
+    // No address permits an eight byte access
+    assert!(Generator::new(&isa,1).memory(7).generate(10).is_none());
+}
@@ -0,0 +1,50 @@
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::{Byte,Word};
+use virmin::program::{DecodedProgram,Program};
+use virmin::tiered::Tiered;
+
+// =====================================================
+// Tiered Execution
+// =====================================================
+
+#[test]
+fn test_tiered_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("addw",&fmt,&[Add(Var(0),Var(1),Word)]).requires(0)
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // do { M[2..4] := M[2..4] + M[4..6]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("addw",&[2,4]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let mut decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut tiered = Tiered::new(&decoded).threshold(3);
+    let mut bytes = [100,0xFF,0,0,3,1];
+    let mut state = State::new(0,&mut bytes);
+    for _ in 0..9 {
+	tiered.step(&mut state,&mut decoded).unwrap();
+    }
+    // Only the add has the form of a quickened instruction
+    let quick : Vec<bool> = (0..3).map(|pc| decoded.get(pc).unwrap().quick).collect();
+    assert_eq!(quick,vec![false,false,false]);
+    tiered.step(&mut state,&mut decoded).unwrap();
+    tiered.step(&mut state,&mut decoded).unwrap();
+    let quick : Vec<bool> = (0..3).map(|pc| decoded.get(pc).unwrap().quick).collect();
+    assert_eq!(quick,vec![false,true,false]);
+    while state.pc < decoded.len() {
+	tiered.step(&mut state,&mut decoded).unwrap();
+    }
+    assert_eq!(bytes,[0,0xFF,0x2C,0x65,3,1]);
+    // Fused instructions cannot be quickened
+    assert!(decoded.quicken(1));
+    assert_eq!(decoded.fuse(&[(1,0),(0,2)]),2);
+    assert!(!decoded.get(1).unwrap().quick);
+    assert!(!decoded.quicken(1));
+}
@@ -0,0 +1,61 @@
+#![cfg(feature="std")]
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::{Temp,Var};
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+use virmin::transpile::{transpile,TranspileError};
+
+// =====================================================
+// Transpiler
+// =====================================================
+
+#[test]
+fn test_transpile_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let source = transpile(&decoded,"run").unwrap();
+    assert_eq!(source,"pub fn run(memory: &mut [u8], mut pc: usize) -> usize {
+    loop {
+	match pc {
+	    0 => {
+		// add 2, 3
+		memory[2] = memory[2].wrapping_add(memory[3]);
+		// add 0, 1
+		memory[0] = memory[0].wrapping_add(memory[1]);
+		// jnz 0, 0
+		let mut next = 3;
+		if memory[0] != 0 {
+		    next = 0;
+		}
+		pc = next;
+	    }
+	    _ => return pc
+	}
+    }
+}
+");
+}
+
+#[test]
+fn test_transpile_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",3).register("rs",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("inc",&fmt,&[RegFetch(Temp(0),Var(0),Byte)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("inc",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    assert_eq!(transpile(&decoded,"run"),Err(TranspileError::Unsupported{pc:1}));
+}
@@ -0,0 +1,77 @@
+#![cfg(feature="tui")]
+use ratatui::Terminal;
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::KeyCode;
+use virmin::debug::Debugger;
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::Var;
+use virmin::machine::State;
+use virmin::machine::Width::Byte;
+use virmin::program::{DecodedProgram,Program};
+use virmin::tui::Tui;
+
+/// Draw a given interface, returning the rows of the screen.
+fn screen(tui: &Tui, state: &State) -> Vec<String> {
+    let mut terminal = Terminal::new(TestBackend::new(80,30)).unwrap();
+    terminal.draw(|frame| tui.draw(frame,state)).unwrap();
+    let buffer = terminal.backend().buffer();
+    (0..30).map(|y| (0..80).map(|x| buffer[(x,y)].symbol()).collect::<String>()).collect()
+}
+
+fn contains(screen: &[String], text: &str) -> bool {
+    screen.iter().any(|row| row.contains(text))
+}
+
+// =====================================================
+// Terminal User Interface
+// =====================================================
+
+#[test]
+fn test_tui_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // do { M[2] := M[2] + M[3]; M[0] := M[0] + M[1] } while M[0] != 0
+    let mut program = Program::new(&isa);
+    program.push("add",&[2,3]).unwrap();
+    program.push("add",&[0,1]).unwrap();
+    program.push("jnz",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut tui = Tui::new(Debugger::new(&decoded));
+    let mut bytes = [3,0xFF,0,1];
+    let mut state = State::new(0,&mut bytes);
+    let s = screen(&tui,&state);
+    assert!(contains(&s,"Disassembly") && contains(&s,"Registers") && contains(&s,"Memory") && contains(&s,"Trace"));
+    assert!(contains(&s,"0000: add 2, 3") && contains(&s,"0002: jnz 0, 0"));
+    assert!(contains(&s,"0000: 03 ff 00 01"));
+    // Step twice, then set a breakpoint on the jnz
+    tui.key(KeyCode::Char('s'),&mut state);
+    tui.key(KeyCode::Char('s'),&mut state);
+    tui.key(KeyCode::Char('b'),&mut state);
+    let s = screen(&tui,&state);
+    assert!(contains(&s,"*    2  0002: jnz 0, 0"));
+    assert!(contains(&s,"pc    0x2"));
+    assert!(contains(&s,"0000: 02 ff 01 01"));
+    // Continue until the breakpoint is reached again
+    tui.key(KeyCode::Char('c'),&mut state);
+    let s = screen(&tui,&state);
+    assert!(contains(&s,"breakpoint at pc 2"));
+    assert!(contains(&s,"0000: 01 ff 02 01"));
+    // Commands are passed to the debugger
+    for c in ":mem 2 1".chars() {
+	tui.key(KeyCode::Char(c),&mut state);
+    }
+    assert!(contains(&screen(&tui,&state),":mem 2 1"));
+    tui.key(KeyCode::Enter,&mut state);
+    assert!(contains(&screen(&tui,&state),"0002: 02"));
+    tui.key(KeyCode::Char('b'),&mut state);
+    tui.key(KeyCode::Char('c'),&mut state);
+    assert!(contains(&screen(&tui,&state),"halted at pc 3"));
+    assert!(!tui.is_done());
+    tui.key(KeyCode::Char('q'),&mut state);
+    assert!(tui.is_done());
+}
@@ -0,0 +1,104 @@
+use virmin::insn::{Format,Instruction};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::Operand::*;
+use virmin::machine::Width::Byte;
+use virmin::verify::*;
+
+// =====================================================
+// Equivalence
+// =====================================================
+
+#[test]
+fn test_equivalent_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).register("rs",2).build().ok().unwrap();
+    let mc1 = [Load(Var(0),Const(5),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte), Load(Var(0),Const(5),Byte)];
+    let (a,b) = (Instruction::new("a", &fmt, &mc1),Instruction::new("b", &fmt, &mc2));
+    // Two valid addresses for rd, four for rs and four memories
+    assert_eq!(check_equivalent(&a,&b,2,&[0,1]),Ok(32));
+    let mc1 = [Copy(Var(0),Var(1),Byte), Goto(Const(3))];
+    let mc2 = [Goto(Const(3)), Copy(Var(0),Var(1),Byte)];
+    let (a,b) = (Instruction::new("a", &fmt, &mc1),Instruction::new("b", &fmt, &mc2));
+    assert_eq!(check_equivalent(&a,&b,4,&[0,0xFF]),Ok(256));
+}
+
+#[test]
+fn test_equivalent_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).register("rs",2).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Copy(Var(1),Var(0),Byte)];
+    let (a,b) = (Instruction::new("a", &fmt, &mc1),Instruction::new("b", &fmt, &mc2));
+    let Some(EquivalenceError::Counterexample(cex)) = check_equivalent(&a,&b,2,&[0,1]).err() else { panic!() };
+    assert_eq!(cex,Counterexample{operands:vec![1,0],memory:vec![1,0],first:(START_PC+1,vec![1,1]),second:(START_PC+1,vec![0,0])});
+    assert_eq!(cex.to_string(),"operands [1, 0] with memory [01, 00] gives pc 0x10001, memory [01, 01] versus pc 0x10001, memory [00, 00]");
+}
+
+#[test]
+fn test_equivalent_03() {
+    let fmt1 = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).register("rs",2).build().ok().unwrap();
+    let fmt2 = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).build().ok().unwrap();
+    let mc1 = [Copy(Var(0),Var(1),Byte)];
+    let mc2 = [Load(Var(0),Const(0),Byte)];
+    let (a,b) = (Instruction::new("a", &fmt1, &mc1),Instruction::new("b", &fmt2, &mc2));
+    let err = check_equivalent(&a,&b,2,&[0,1]).err().unwrap();
+    assert_eq!(err,EquivalenceError::Arity{first:2,second:1});
+    assert_eq!(err.to_string(),"instructions have 2 and 1 operand(s)");
+}
+
+#[test]
+fn test_equivalent_04() {
+    use virmin::machine::MicroCode;
+    let mc1 = [MicroCode::Load(0,1,Byte),MicroCode::Load(1,2,Byte)];
+    let mc2 = [MicroCode::Load(0,0x0201,virmin::machine::Width::Word)];
+    assert_eq!(check_microcode_equivalent(&mc1,&mc2,2,&[0,1]),Ok(4));
+    let mc2 = [MicroCode::Load(0,0x0102,virmin::machine::Width::Word)];
+    let cex = check_microcode_equivalent(&mc1,&mc2,2,&[0]).err().unwrap();
+    assert_eq!(cex,Counterexample{operands:vec![],memory:vec![0,0],first:(START_PC+1,vec![1,2]),second:(START_PC+1,vec![2,1])});
+    // Out of bounds
+    assert_eq!(check_microcode_equivalent(&mc1,&mc2,1,&[0]),Ok(0));
+}
+
+// =====================================================
+// Properties
+// =====================================================
+
+fn operand(i: usize) -> Box<Expr> {
+    Box::new(Expr::Operand(i))
+}
+
+#[test]
+fn test_properties_01() {
+    use virmin::insn::InstructionSetBuilder;
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).register("rs",2).build().ok().unwrap();
+    let copies = Property::new("copies",Condition::Eq(Expr::New(operand(0),Byte),Expr::Old(operand(1),Byte)));
+    let advance = Property::new("advance",Condition::Eq(Expr::NewPc,Expr::Add(Box::new(Expr::OldPc),Box::new(Expr::Const(1)))));
+    let isa = InstructionSetBuilder::new()
+	.instruction("mov",&fmt,&[Copy(Var(0),Var(1),Byte)]).property(copies.clone()).property(advance.clone())
+	.instruction("jmp",&fmt,&[Goto(Var(0))])
+	.build().ok().unwrap();
+    assert_eq!(check_properties(isa.instruction(0),4,&[0,1,0xFF]),Ok(16 * 81));
+    assert_eq!(check_all_properties(&isa,4,&[0,1,0xFF]),Ok(16 * 81));
+    // Jumps do not advance the pc (except when jumping to the next instruction)
+    let never = Condition::Eq(Expr::Operand(0),Expr::Add(Box::new(Expr::OldPc),Box::new(Expr::Const(1))));
+    let insn = isa.instruction(1).clone().with_property(advance.clone().requires(Condition::Not(Box::new(never))));
+    let violation = check_properties(&insn,1,&[0]).err().unwrap();
+    assert_eq!(violation.property,"advance");
+    assert_eq!(violation.operands,vec![0,0]);
+    assert_eq!(violation.result,(0,vec![0]));
+}
+
+#[test]
+fn test_properties_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).register("rd",2).register("rs",2).build().ok().unwrap();
+    let mc = [Copy(Var(0),Var(1),Byte)];
+    // Claim that mov adds its operands, which holds only for some states
+    let sum = Expr::Trunc(Box::new(Expr::Add(Box::new(Expr::Old(operand(0),Byte)),Box::new(Expr::Old(operand(1),Byte)))),Byte);
+    let adds = Property::new("adds",Condition::Eq(Expr::New(operand(0),Byte),sum));
+    let insn = Instruction::new("mov", &fmt, &mc).with_property(adds.clone());
+    let violation = check_properties(&insn,2,&[0,1]).err().unwrap();
+    assert_eq!(violation.to_string(),"mov violates \"adds\" for operands [0, 0] with memory [01, 00] (giving pc 0x10001, memory [01, 00])");
+    // Holds whenever the destination is initially zero
+    let zero = Condition::Eq(Expr::Old(operand(0),Byte),Expr::Const(0));
+    let insn = Instruction::new("mov", &fmt, &mc).with_property(adds.requires(zero));
+    assert_eq!(check_properties(&insn,2,&[0,1]),Ok(16));
+}
@@ -0,0 +1,74 @@
+#![cfg(feature="wasm")]
+use virmin::insn::{Format,InstructionSetBuilder,Predicate};
+use virmin::insn::AbstractMicroCode::*;
+use virmin::insn::AbstractMicroCode::Add;
+use virmin::insn::Operand::{Const,Var};
+use virmin::machine::State;
+use virmin::machine::Width::{Byte,QuadWord,Word};
+use virmin::program::{DecodedProgram,Program};
+use virmin::wasm::generate;
+use wasmi::{Engine,Linker,Module,Store};
+
+/// Run a WebAssembly module generated by `generate()` on a given
+/// memory, returning the final pc.
+fn run(module: &[u8], pc: usize, memory: &mut [u8]) -> usize {
+    let engine = Engine::default();
+    let module = Module::new(&engine,module).unwrap();
+    let mut store = Store::new(&engine,());
+    let instance = Linker::<()>::new(&engine).instantiate(&mut store,&module).unwrap().start(&mut store).unwrap();
+    let mem = instance.get_memory(&store,"memory").unwrap();
+    mem.write(&mut store,0,memory).unwrap();
+    let run = instance.get_typed_func::<i32,i32>(&store,"run").unwrap();
+    let pc = run.call(&mut store,pc as i32).unwrap();
+    mem.read(&store,0,memory).unwrap();
+    pc as usize
+}
+
+// =====================================================
+// WebAssembly
+// =====================================================
+
+#[test]
+fn test_wasm_01() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),Word)])
+	.instruction("ldi",&fmt,&[Load(Var(0),Var(1),Byte)])
+	.instruction("jnz",&fmt,&[If(Predicate::Zero(Var(0),Byte),vec![Copy(Var(0),Const(7),Byte)],vec![Goto(Var(1))])])
+	.build().ok().unwrap();
+    // M[6] := 5; do { M[2..4] += M[6..8]; M[0..2] += M[4..6] } while M[0] != 0; M[0] := M[7]
+    let mut program = Program::new(&isa);
+    program.push("ldi",&[6,5]).unwrap();
+    program.push("add",&[2,6]).unwrap();
+    program.push("add",&[0,4]).unwrap();
+    program.push("jnz",&[0,1]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let module = generate(&decoded,8).unwrap();
+    let mut expected = [3u8,0,0,1,0xFF,0xFF,0,9];
+    let mut actual = expected;
+    let mut state = State::new(0,&mut expected);
+    while state.pc < decoded.len() {
+	state.step(&decoded).unwrap();
+    }
+    assert_eq!(run(&module,0,&mut actual),4);
+    drop(state);
+    assert_eq!(expected,actual);
+}
+
+#[test]
+fn test_wasm_02() {
+    let fmt = Format::builder().width_bytes(1).opcode_bits(2).immediate("a",3).immediate("b",3).build().ok().unwrap();
+    let isa = InstructionSetBuilder::new()
+	.instruction("add",&fmt,&[Add(Var(0),Var(1),QuadWord)])
+	.instruction("ldi",&fmt,&[Load(Var(0),Const(usize::MAX),QuadWord)])
+	.build().ok().unwrap();
+    let mut program = Program::new(&isa);
+    program.push("ldi",&[0,0]).unwrap();
+    program.push("add",&[0,0]).unwrap();
+    let decoded = DecodedProgram::new(&isa,program.bytes());
+    let mut memory = [0u8;8];
+    // Starting part way through a block stops immediately
+    assert_eq!(run(&generate(&decoded,8).unwrap(),1,&mut memory),1);
+    assert_eq!(run(&generate(&decoded,8).unwrap(),0,&mut memory),2);
+    assert_eq!(memory,[0xFE,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF]);
+}
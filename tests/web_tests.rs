@@ -0,0 +1,53 @@
+#![cfg(feature="web")]
+use virmin::web::{WebInstructionSet,WebMachine};
+
+// =====================================================
+// Browser Bindings
+// =====================================================
+
+const SPEC : &str = r#"
+[[formats]]
+name = "rr"
+width = 1
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "rs", bits = 3 } ]
+
+[[formats]]
+name = "ri"
+width = 2
+opcode = 2
+fields = [ { name = "rd", bits = 3 }, { name = "imm", bits = 11, kind = "Immediate" } ]
+
+[[instructions]]
+mnemonic = "add"
+format = "rr"
+semantics = [ { Add = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+
+[[instructions]]
+mnemonic = "ldi"
+format = "ri"
+semantics = [ { Load = [ { Var = 0 }, { Var = 1 }, "Byte" ] } ]
+"#;
+
+// NOTE: errors cannot be constructed outside of a browser, hence only
+// successful calls are tested here.
+#[test]
+fn test_web_01() {
+    let isa = WebInstructionSet::from_toml(SPEC).ok().unwrap();
+    assert_eq!(isa.mnemonics(),vec!["add","ldi"]);
+    let image = isa.assemble("ldi r1, 5\nldi r2, 7\nadd r1, r2\n").ok().unwrap();
+    assert_eq!(image.len(),5);
+    let mut m = WebMachine::new(&isa,&image,4);
+    assert_eq!(m.length(),3);
+    assert_eq!(m.current(),"0000: ldi r1, 5");
+    m.step().ok().unwrap();
+    assert_eq!((m.pc(),m.read(0,4)),(1,vec![0,5,0,0]));
+    assert_eq!(m.run(10).ok(),Some(2));
+    assert!(m.halted());
+    assert_eq!(m.read(0,8),vec![0,12,7,0]);
+    m.write(3,&[1,2]);
+    assert_eq!(m.read(2,2),vec![7,1]);
+    assert_eq!(m.register_names(),vec!["pc","sp","flags","lr"]);
+    assert_eq!(m.register_values(),vec![3,0,0,0]);
+    assert!(isa.disassemble(&image,false).contains("add r1, r2"));
+}